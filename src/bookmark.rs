@@ -0,0 +1,184 @@
+//! Bookmarks for Quirks
+//!
+//! Named places worth coming back to, each a line in a file with a note
+//! ("review this"). Unlike marks there is no limit on their number and
+//! they belong to a project: each project's bookmarks are kept in their
+//! own state file (~/.local/share/quirks/bookmarks/ on Linux), with files
+//! relative to the project root. Each line of it is `line<TAB>file<TAB>note`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A line in a file with a note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    /// File relative to the project root (absolute if outside it)
+    pub file: PathBuf,
+    /// 0-based line
+    pub line: usize,
+    pub note: String,
+}
+
+impl Bookmark {
+    /// Text for listings: `src/main.rs:12  review this`
+    pub fn label(&self) -> String {
+        let place = format!("{}:{}", self.file.display(), self.line + 1);
+        if self.note.is_empty() { place } else { format!("{}  {}", place, self.note) }
+    }
+}
+
+/// The bookmarks of one project
+#[derive(Debug, Default)]
+pub struct Bookmarks {
+    /// Directory with a state file per project; None keeps them in memory
+    dir: Option<PathBuf>,
+    /// Root of the project the bookmarks belong to
+    root: Option<PathBuf>,
+    /// Sorted by file and line
+    items: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// Bookmarks saved in the default state directory
+    pub fn load() -> Self {
+        Self::in_dir(dirs::data_local_dir().map(|dir| dir.join("quirks").join("bookmarks")))
+    }
+
+    /// Bookmarks saved in `dir`, or only kept in memory without one
+    pub fn in_dir(dir: Option<PathBuf>) -> Self {
+        Self { dir, root: None, items: Vec::new() }
+    }
+
+    /// Root of the project whose bookmarks are loaded
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Switch to the bookmarks of the project at `root`, reading its state
+    /// file. Lines that do not parse are skipped.
+    pub fn open_project(&mut self, root: &Path) {
+        if self.root.as_deref() == Some(root) {
+            return;
+        }
+        self.root = Some(root.to_path_buf());
+        self.items = self.state_file()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+        self.items.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    }
+
+    /// State file of the current project: its root with `/` as `%`
+    fn state_file(&self) -> Option<PathBuf> {
+        let name = self.root.as_ref()?.to_string_lossy().replace(['/', '\\', ':'], "%");
+        Some(self.dir.as_ref()?.join(name))
+    }
+
+    pub fn items(&self) -> &[Bookmark] {
+        &self.items
+    }
+
+    /// Bookmark `line` of `file` with `note`, replacing the note of one
+    /// already there. Returns true for a new bookmark.
+    pub fn set(&mut self, file: &Path, line: usize, note: &str) -> bool {
+        let note = note.replace(['\t', '\n'], " ");
+        match self.items.binary_search_by(|item| (item.file.as_path(), item.line).cmp(&(file, line))) {
+            Ok(idx) => {
+                self.items[idx].note = note;
+                false
+            }
+            Err(idx) => {
+                self.items.insert(idx, Bookmark { file: file.to_path_buf(), line, note });
+                true
+            }
+        }
+    }
+
+    /// Remove the bookmark on `line` of `file`
+    pub fn remove(&mut self, file: &Path, line: usize) -> Option<Bookmark> {
+        let idx = self.items.iter().position(|item| item.file == file && item.line == line)?;
+        Some(self.items.remove(idx))
+    }
+
+    /// Bookmarks in `file` with their indexes
+    pub fn in_file<'a>(&'a self, file: &'a Path) -> impl Iterator<Item = (usize, &'a Bookmark)> + 'a {
+        self.items.iter().enumerate().filter(move |(_, item)| item.file == file)
+    }
+
+    /// Move a bookmark to another line, after the text around it changed.
+    /// Returns true if it moved.
+    pub fn set_line(&mut self, idx: usize, line: usize) -> bool {
+        match self.items.get_mut(idx) {
+            Some(item) if item.line != line => {
+                item.line = line;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Write the project's bookmarks to its state file
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = self.state_file() else { return Ok(()) };
+        if self.items.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text: String = self.items.iter()
+            .map(|item| format!("{}\t{}\t{}\n", item.line + 1, item.file.display(), item.note))
+            .collect();
+        fs::write(path, text)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Bookmark> {
+    let mut fields = line.splitn(3, '\t');
+    let number: usize = fields.next()?.parse().ok()?;
+    let file = PathBuf::from(fields.next()?);
+    Some(Bookmark {
+        file,
+        line: number.checked_sub(1)?,
+        note: fields.next().unwrap_or("").to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_remove() {
+        let mut bookmarks = Bookmarks::default();
+        assert!(bookmarks.set(Path::new("src/b.rs"), 4, "later"));
+        assert!(bookmarks.set(Path::new("src/a.rs"), 9, "review\tthis"));
+        assert!(!bookmarks.set(Path::new("src/b.rs"), 4, "now"));
+        let labels: Vec<String> = bookmarks.items().iter().map(Bookmark::label).collect();
+        assert_eq!(labels, ["src/a.rs:10  review this", "src/b.rs:5  now"]);
+        assert_eq!(bookmarks.in_file(Path::new("src/b.rs")).count(), 1);
+        assert_eq!(bookmarks.remove(Path::new("src/a.rs"), 9).map(|b| b.note), Some("review this".to_string()));
+        assert_eq!(bookmarks.remove(Path::new("src/a.rs"), 9), None);
+    }
+
+    #[test]
+    fn test_saved_per_project() {
+        let dir = std::env::temp_dir().join(format!("quirks-bookmarks-{}", std::process::id()));
+        let mut bookmarks = Bookmarks::in_dir(Some(dir.clone()));
+        bookmarks.open_project(Path::new("/work/one"));
+        bookmarks.set(Path::new("main.rs"), 0, "entry point");
+        bookmarks.save().unwrap();
+        bookmarks.open_project(Path::new("/work/two"));
+        assert!(bookmarks.items().is_empty());
+
+        let mut loaded = Bookmarks::in_dir(Some(dir.clone()));
+        loaded.open_project(Path::new("/work/one"));
+        let labels: Vec<String> = loaded.items().iter().map(Bookmark::label).collect();
+        assert_eq!(labels, ["main.rs:1  entry point"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
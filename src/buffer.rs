@@ -5,11 +5,13 @@
 //!
 //! Initial implementation by Aibotix, refined with input from Egon.
 
+use crate::decoration::{Decoration, Decorations, Extmark};
+use crate::error::{QuirksError, Result};
 use crate::history::History;
-use anyhow::Result;
 use ropey::Rope;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use unicode_segmentation::UnicodeSegmentation;
 
 /// A text buffer backed by a rope data structure
@@ -21,8 +23,34 @@ pub struct Buffer {
     file_path: Option<PathBuf>,
     /// Whether the buffer has unsaved changes
     modified: bool,
+    /// Text as last read from or written to the file, so undoing back to
+    /// it leaves the buffer unmodified
+    saved: Rope,
     /// Undo/redo history
     history: History,
+    /// Buffer-local working directory set with :lcd
+    local_dir: Option<PathBuf>,
+    /// Name of a scratch buffer (e.g. "[Run]"), which is never saved
+    scratch_name: Option<String>,
+    /// Syntax chosen with :set filetype, instead of detecting it
+    filetype: Option<String>,
+    /// Syntax highlighting switched on or off with :setlocal, instead of
+    /// following the syntax_highlighting option
+    syntax: Option<bool>,
+    /// Set for files that cannot be written, or with :setlocal readonly;
+    /// :w refuses without !
+    readonly: bool,
+    /// Virtual text, highlights and signs anchored in the text
+    decorations: Decorations,
+    /// Identifies the current text; changes with every edit
+    revision: u64,
+}
+
+/// Source of buffer revisions, unique across all buffers
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
 impl Default for Buffer {
@@ -41,51 +69,114 @@ impl Buffer {
             rope,
             file_path: None,
             modified: false,
+            saved: Rope::new(),
             history,
+            local_dir: None,
+            scratch_name: None,
+            filetype: None,
+            syntax: None,
+            readonly: false,
+            decorations: Decorations::default(),
+            revision: next_revision(),
         }
     }
 
+    /// Create an unnamed buffer holding `text`
+    pub fn from_text(text: &str) -> Self {
+        let mut buffer = Self::new();
+        buffer.rope = Rope::from_str(text);
+        buffer.saved = buffer.rope.clone();
+        buffer.revision = next_revision();
+        buffer.history.init(&buffer.rope, 0, 0);
+        buffer
+    }
+
+    /// Create a named scratch buffer holding `text`
+    pub fn scratch(name: &str, text: &str) -> Self {
+        let mut buffer = Self::from_text(text);
+        buffer.scratch_name = Some(name.to_string());
+        buffer
+    }
+
+    /// Name of the buffer if it is a scratch buffer
+    pub fn scratch_name(&self) -> Option<&str> {
+        self.scratch_name.as_deref()
+    }
+
+    /// Append text to the end of a scratch buffer; scratch buffers do not
+    /// become modified since they have nothing to save to
+    pub fn append_scratch(&mut self, text: &str) {
+        let end = self.rope.len_chars();
+        self.rope_insert(end, text);
+    }
+
+    /// Create an empty buffer for a file that does not exist yet; saving
+    /// creates it
+    pub fn new_file(path: &str) -> Self {
+        let mut buffer = Self::new();
+        buffer.file_path = Some(PathBuf::from(path));
+        buffer
+    }
+
+    /// Create a buffer for `path` holding `text` that was read some other
+    /// way than from the local disk (an scp:// URL)
+    pub fn from_remote(path: &str, text: &str) -> Self {
+        let mut buffer = Self::from_text(text);
+        buffer.file_path = Some(PathBuf::from(path));
+        buffer
+    }
+
     /// Create a buffer from a file
     pub fn from_file(path: &str) -> Result<Self> {
-        let content = fs::read_to_string(path)?;
+        let content = fs::read_to_string(path).map_err(QuirksError::file(path))?;
         let rope = Rope::from_str(&content);
         let mut history = History::new();
         history.init(&rope, 0, 0);
         Ok(Self {
-            rope,
+            rope: rope.clone(),
             file_path: Some(PathBuf::from(path)),
             modified: false,
+            saved: rope,
             history,
+            local_dir: None,
+            scratch_name: None,
+            filetype: None,
+            syntax: None,
+            readonly: fs::metadata(path).is_ok_and(|meta| meta.permissions().readonly()),
+            decorations: Decorations::default(),
+            revision: next_revision(),
         })
     }
 
     /// Save the buffer to its file
     pub fn save(&mut self) -> Result<()> {
         if let Some(ref path) = self.file_path {
-            fs::write(path, self.rope.to_string())?;
-            self.modified = false;
+            fs::write(path, self.rope.to_string()).map_err(QuirksError::file(path))?;
+            self.mark_saved();
         }
         Ok(())
     }
 
     /// Save the buffer to a specific path
     pub fn save_as(&mut self, path: &str) -> Result<()> {
-        fs::write(path, self.rope.to_string())?;
+        fs::write(path, self.rope.to_string()).map_err(QuirksError::file(path))?;
         self.file_path = Some(PathBuf::from(path));
-        self.modified = false;
+        self.mark_saved();
         Ok(())
     }
 
     /// Reload the buffer from disk
     pub fn reload(&mut self) -> Result<()> {
         if let Some(path) = &self.file_path {
-            let content = fs::read_to_string(path)?;
+            let content = fs::read_to_string(path).map_err(QuirksError::file(path))?;
             self.rope = Rope::from_str(&content);
-            self.modified = false;
+            self.revision = next_revision();
+            self.decorations.clamp(self.rope.len_chars());
+            self.mark_saved();
             self.history.clear();
             Ok(())
         } else {
-            anyhow::bail!("No file path")
+            Err(QuirksError::NoFileName)
         }
     }
 
@@ -109,6 +200,19 @@ impl Buffer {
         self.rope.to_string()
     }
 
+    /// Lines `start..=end` as text, each ending with a newline, for
+    /// writing part of the buffer to a file
+    pub fn lines_text(&self, start: usize, end: usize) -> String {
+        let line_start = |line: usize| {
+            if line < self.rope.len_lines() { self.rope.line_to_char(line) } else { self.rope.len_chars() }
+        };
+        let mut text = self.rope.slice(line_start(start)..line_start(end + 1).max(line_start(start))).to_string();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    }
+
     /// Get the length of a line in grapheme clusters
     pub fn line_len(&self, line_idx: usize) -> usize {
         if line_idx >= self.rope.len_lines() {
@@ -126,11 +230,11 @@ impl Buffer {
         if line_idx >= self.rope.len_lines() {
             return String::new();
         }
-        self.rope
-            .line(line_idx)
-            .to_string()
-            .trim_end_matches('\n')
-            .to_string()
+        let mut line = self.rope.line(line_idx).to_string();
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        line
     }
 
     /// Get the byte offset of a line start
@@ -154,7 +258,21 @@ impl Buffer {
             .sum()
     }
 
-    /// Insert a character at the given byte position
+    /// Convert a byte offset to a (line, grapheme column) position
+    pub fn byte_to_pos(&self, byte_pos: usize) -> (usize, usize) {
+        let byte_pos = byte_pos.min(self.rope.len_bytes());
+        let line = self.rope.byte_to_line(byte_pos);
+        let start = self.rope.line_to_byte(line);
+        let col = self.rope.byte_slice(start..byte_pos).to_string().graphemes(true).count();
+        (line, col)
+    }
+
+    /// The text between two byte offsets
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        let end = end.min(self.rope.len_bytes());
+        self.rope.byte_slice(start.min(end)..end).to_string()
+    }
+
     /// Insert a character at the given byte position
     pub fn insert_char(&mut self, byte_pos: usize, ch: char) {
         // Convert byte position to char position for ropey
@@ -165,7 +283,7 @@ impl Buffer {
         } else {
             self.rope.byte_to_char(byte_pos)
         };
-        self.rope.insert_char(char_pos, ch);
+        self.rope_insert(char_pos, ch.encode_utf8(&mut [0; 4]));
         self.modified = true;
     }
 
@@ -179,7 +297,7 @@ impl Buffer {
         } else {
             self.rope.byte_to_char(byte_pos)
         };
-        self.rope.insert(char_pos, text);
+        self.rope_insert(char_pos, text);
         self.modified = true;
     }
 
@@ -190,7 +308,7 @@ impl Buffer {
             // Convert byte positions to char positions for ropey
             let char_start = self.rope.byte_to_char(start);
             let char_end = self.rope.byte_to_char(end);
-            self.rope.remove(char_start..char_end);
+            self.rope_remove(char_start..char_end);
             self.modified = true;
         }
     }
@@ -231,17 +349,57 @@ impl Buffer {
         }
     }
 
+    /// Delete columns `from..to` of a line
+    pub fn delete_cols(&mut self, line: usize, from: usize, to: usize) {
+        let line_start = self.line_to_byte(line);
+        let (start, end) = (self.col_to_byte(line, from), self.col_to_byte(line, to));
+        if start < end {
+            self.delete(line_start + start, line_start + end);
+        }
+    }
+
+    /// Identifier of the current text: equal revisions mean equal text,
+    /// even across buffers
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Iterate over all characters
+    /// Copy of the text for background work; ropes share their chunks, so
+    /// this is cheap
+    pub fn snapshot(&self) -> Rope {
+        self.rope.clone()
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.rope.chars()
+    }
+
+    /// Mark the buffer unmodified after it was saved some other way
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+        self.saved = self.rope.clone();
+    }
+
+    /// Undo steps from the text to the state last saved: negative back
+    /// (u), positive forward (Ctrl-R), None if the history no longer
+    /// reaches it
+    pub fn saved_steps(&self) -> Option<isize> {
+        self.history.steps_to(&self.rope, &self.saved)
+    }
+
     /// Check if the buffer has been modified
     pub fn is_modified(&self) -> bool {
         self.modified
     }
 
-    /// Get the file name (if any)
+    /// Get the file name (if any), or the name of a scratch buffer
     pub fn file_name(&self) -> Option<&str> {
         self.file_path
             .as_ref()
             .and_then(|p| p.file_name())
             .and_then(|n| n.to_str())
+            .or(self.scratch_name.as_deref())
     }
 
     /// Get the full file path (if any)
@@ -249,6 +407,51 @@ impl Buffer {
         self.file_path.as_ref()
     }
 
+    /// Point the buffer at another file, after it was moved
+    pub fn set_file_path(&mut self, path: PathBuf) {
+        self.file_path = Some(path);
+    }
+
+    /// Syntax set with :set filetype, if any
+    pub fn filetype(&self) -> Option<&str> {
+        self.filetype.as_deref()
+    }
+
+    /// Set or clear the syntax to use instead of detecting it
+    pub fn set_filetype(&mut self, filetype: Option<String>) {
+        self.filetype = filetype;
+    }
+
+    /// Syntax highlighting set with :setlocal, if any
+    pub fn syntax(&self) -> Option<bool> {
+        self.syntax
+    }
+
+    /// Switch highlighting on or off for this buffer, or back to the option
+    pub fn set_syntax(&mut self, syntax: Option<bool>) {
+        self.syntax = syntax;
+    }
+
+    /// Whether :w refuses to write the buffer without !
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Mark the buffer read-only or writable
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    /// Get the buffer-local working directory (if set)
+    pub fn local_dir(&self) -> Option<&PathBuf> {
+        self.local_dir.as_ref()
+    }
+
+    /// Set or clear the buffer-local working directory
+    pub fn set_local_dir(&mut self, dir: Option<PathBuf>) {
+        self.local_dir = dir;
+    }
+
     /// Record current state as a checkpoint for undo
     /// Call this before making changes
     pub fn checkpoint(&mut self, cursor_line: usize, cursor_col: usize) {
@@ -260,7 +463,9 @@ impl Buffer {
     pub fn undo(&mut self, cursor_line: usize, cursor_col: usize) -> Option<(usize, usize)> {
         if let Some((content, line, col)) = self.history.undo(&self.rope, cursor_line, cursor_col) {
             self.rope = content;
-            self.modified = true;
+            self.revision = next_revision();
+            self.decorations.clamp(self.rope.len_chars());
+            self.modified = self.rope != self.saved;
             Some((line, col))
         } else {
             None
@@ -272,7 +477,9 @@ impl Buffer {
     pub fn redo(&mut self) -> Option<(usize, usize)> {
         if let Some((content, line, col)) = self.history.redo() {
             self.rope = content;
-            self.modified = true;
+            self.revision = next_revision();
+            self.decorations.clamp(self.rope.len_chars());
+            self.modified = self.rope != self.saved;
             Some((line, col))
         } else {
             None
@@ -301,15 +508,15 @@ impl Buffer {
 
     /// Get character at position (line, col)
     pub fn char_at(&self, line: usize, col: usize) -> Option<char> {
+        self.grapheme_at(line, col)?.chars().next()
+    }
+
+    /// The grapheme at a column, with any combining marks
+    pub fn grapheme_at(&self, line: usize, col: usize) -> Option<String> {
         if line >= self.line_count() {
             return None;
         }
-        let line_text = self.line(line);
-        let graphemes: Vec<&str> = line_text.graphemes(true).collect();
-        if col >= graphemes.len() {
-            return None;
-        }
-        graphemes.get(col).and_then(|g| g.chars().next())
+        self.line(line).graphemes(true).nth(col).map(String::from)
     }
 
     /// Delete an entire line
@@ -317,14 +524,17 @@ impl Buffer {
         if line >= self.line_count() {
             return;
         }
-        let start = self.rope.line_to_char(line);
+        let mut start = self.rope.line_to_char(line);
         let end = if line + 1 < self.line_count() {
             self.rope.line_to_char(line + 1)
         } else {
+            // The last line takes the line break before it along, so no
+            // empty line is left behind
+            start = start.saturating_sub(1);
             self.rope.len_chars()
         };
         if start < end {
-            self.rope.remove(start..end);
+            self.rope_remove(start..end);
             self.modified = true;
         }
     }
@@ -339,19 +549,19 @@ impl Buffer {
             if len > 0 {
                 let last_char = self.rope.char(len - 1);
                 if last_char != '\n' {
-                    self.rope.insert_char(len, '\n');
+                    self.rope_insert(len, "\n");
                 }
             }
             self.rope.len_chars()
         };
-        self.rope.insert(insert_pos, text);
+        self.rope_insert(insert_pos, text);
         self.modified = true;
     }
 
     /// Insert text as a new line above the given line
     pub fn insert_line_above(&mut self, line: usize, text: &str) {
         let insert_pos = self.rope.line_to_char(line);
-        self.rope.insert(insert_pos, text);
+        self.rope_insert(insert_pos, text);
         self.modified = true;
     }
 
@@ -361,27 +571,103 @@ impl Buffer {
             return;
         }
         
-        // Find the newline at the end of current line
-        let next_line_start = self.rope.line_to_char(line + 1);
-        let newline_pos = next_line_start - 1;
-        
-        // Remove the newline
-        self.rope.remove(newline_pos..next_line_start);
+        let current = self.line(line);
+        let next = self.line(line + 1);
+        let rest = next.trim_start_matches([' ', '\t']);
+        let indent = next.chars().count() - rest.chars().count();
         
-        // Get the (now joined) line and check if we need to add a space
-        let current_line: String = self.rope.line(line).chars().collect();
-        let trimmed = current_line.trim_end();
+        // Remove the newline and the next line's indentation
+        let next_line_start = self.rope.line_to_char(line + 1);
+        self.rope_remove(next_line_start - 1..next_line_start + indent);
         
-        // If the joined content doesn't already have a space, insert one
-        if !trimmed.is_empty() && !trimmed.ends_with(' ') {
-            // Find where to insert the space (after old line content)
-            let insert_pos = self.rope.line_to_char(line) + trimmed.len();
-            self.rope.insert_char(insert_pos, ' ');
+        // Separate the two with one space, like Vim's J
+        if !current.is_empty() && !current.ends_with(' ') && !rest.is_empty() && !rest.starts_with(')') {
+            self.rope_insert(next_line_start - 1, " ");
         }
         
         self.modified = true;
     }
 
+    /// Join the next line to the given line as it is, without removing
+    /// indentation or adding a space (Vim's gJ)
+    pub fn join_lines_raw(&mut self, line: usize) {
+        if line + 1 >= self.line_count() {
+            return;
+        }
+        let next_line_start = self.rope.line_to_char(line + 1);
+        self.rope_remove(next_line_start - 1..next_line_start);
+        self.modified = true;
+    }
+
+    /// Insert text at a character position, moving decorations after it
+    fn rope_insert(&mut self, char_pos: usize, text: &str) {
+        self.rope.insert(char_pos, text);
+        self.revision = next_revision();
+        self.decorations.edited(char_pos, 0, text.chars().count());
+    }
+
+    /// Remove a range of characters, moving decorations after it
+    fn rope_remove(&mut self, range: std::ops::Range<usize>) {
+        self.decorations.edited(range.start, range.len(), 0);
+        self.rope.remove(range);
+        self.revision = next_revision();
+    }
+
+    /// Attach a decoration at a line and character column, returning its
+    /// id. The anchor follows the text as the buffer is edited.
+    pub fn add_decoration(&mut self, namespace: &str, line: usize, col: usize, decoration: Decoration) -> u64 {
+        let line = line.min(self.rope.len_lines().saturating_sub(1));
+        let offset = self.rope.line_to_char(line) + col.min(self.line(line).chars().count());
+        self.decorations.add(namespace, offset, decoration)
+    }
+
+    /// Remove a decoration by id
+    pub fn remove_decoration(&mut self, id: u64) -> bool {
+        self.decorations.remove(id)
+    }
+
+    /// Line a decoration is anchored on now, after edits moved it
+    pub fn decoration_line(&self, id: u64) -> Option<usize> {
+        let offset = self.decorations.get(id)?.offset;
+        Some(self.rope.char_to_line(offset.min(self.rope.len_chars())))
+    }
+
+    /// Remove all decorations of a namespace
+    pub fn clear_decorations(&mut self, namespace: &str) {
+        self.decorations.clear_namespace(namespace);
+    }
+
+    /// Decorations on a line, with the character column of each
+    pub fn line_decorations(&self, line: usize) -> Vec<(usize, &Extmark)> {
+        if line >= self.rope.len_lines() {
+            return Vec::new();
+        }
+        let start = self.rope.line_to_char(line);
+        let end = start + self.line(line).chars().count() + 1;
+        self.decorations.in_range(start..end)
+            .iter()
+            .map(|mark| (mark.offset - start, mark))
+            .collect()
+    }
+
+    /// Width needed in the gutter for decoration signs
+    pub fn sign_width(&self) -> usize {
+        self.decorations.sign_width()
+    }
+
+    /// Replace lines `start..end` with `lines`, keeping the newline after
+    /// the last one
+    pub fn replace_lines(&mut self, start: usize, end: usize, lines: &[String]) {
+        if start >= end || end > self.line_count() {
+            return;
+        }
+        let from = self.rope.line_to_char(start);
+        let to = self.rope.line_to_char(end - 1) + self.line(end - 1).chars().count();
+        self.rope_remove(from..to);
+        self.rope_insert(from, &lines.join("\n"));
+        self.modified = true;
+    }
+
     /// Indent a line by adding spaces at the beginning
     pub fn indent_line(&mut self, line: usize, spaces: usize) {
         if line >= self.line_count() {
@@ -389,7 +675,7 @@ impl Buffer {
         }
         let indent: String = " ".repeat(spaces);
         let pos = self.rope.line_to_char(line);
-        self.rope.insert(pos, &indent);
+        self.rope_insert(pos, &indent);
         self.modified = true;
     }
 
@@ -403,7 +689,7 @@ impl Buffer {
         let to_remove = leading_spaces.min(max_spaces);
         if to_remove > 0 {
             let start = self.rope.line_to_char(line);
-            self.rope.remove(start..(start + to_remove));
+            self.rope_remove(start..(start + to_remove));
             self.modified = true;
         }
     }
@@ -441,6 +727,63 @@ mod tests {
         assert!(buffer.is_modified());
     }
 
+    #[test]
+    fn test_scratch_buffer() {
+        let mut buffer = Buffer::scratch("[Run]", "one\n");
+        buffer.append_scratch("two\n");
+        assert_eq!(buffer.line(1), "two");
+        assert_eq!(buffer.file_name(), Some("[Run]"));
+        assert!(buffer.file_path().is_none());
+        assert!(!buffer.is_modified());
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let mut buffer = Buffer::new();
+        buffer.insert(0, "a\n  b\n  c");
+        buffer.join_lines(0);
+        assert_eq!(buffer.content(), "a b\n  c");
+        buffer.join_lines_raw(0);
+        assert_eq!(buffer.content(), "a b  c");
+        buffer.join_lines_raw(0);
+        assert_eq!(buffer.content(), "a b  c");
+    }
+
+    #[test]
+    fn test_replace_lines() {
+        let mut buffer = Buffer::new();
+        buffer.insert(0, "a\nb\nc\nd");
+        buffer.replace_lines(1, 3, &["x".to_string(), "y".to_string(), "z".to_string()]);
+        assert_eq!(buffer.content(), "a\nx\ny\nz\nd");
+        buffer.replace_lines(3, 5, &["end".to_string()]);
+        assert_eq!(buffer.content(), "a\nx\ny\nend");
+    }
+
+    #[test]
+    fn test_lines_text() {
+        let buffer = Buffer::from_text("a\nb\nc\n");
+        assert_eq!(buffer.lines_text(1, 1), "b\n");
+        assert_eq!(buffer.lines_text(0, buffer.line_count() - 1), "a\nb\nc\n");
+        let buffer = Buffer::from_text("a\nb");
+        assert_eq!(buffer.lines_text(1, 5), "b\n");
+    }
+
+    #[test]
+    fn test_decorations_follow_edits() {
+        let mut buffer = Buffer::new();
+        buffer.insert(0, "one\ntwo\nthree");
+        let style = ratatui::style::Style::default();
+        let id = buffer.add_decoration("test", 1, 2, Decoration::Sign { text: "E".to_string(), style });
+        buffer.insert_line_above(0, "zero\n");
+        assert_eq!(buffer.decoration_line(id), Some(2));
+        assert_eq!(buffer.line_decorations(2).len(), 1);
+        assert_eq!(buffer.line_decorations(2)[0].0, 2);
+        buffer.delete_line(2);
+        assert_eq!(buffer.line_decorations(2)[0].0, 0);
+        buffer.clear_decorations("test");
+        assert!(buffer.line_decorations(2).is_empty());
+    }
+
     #[test]
     fn test_buffer_umlauts() {
         let mut buffer = Buffer::new();
@@ -1,3 +1,4 @@
+#[derive(Default)]
 pub struct BufferManager {
     buffers: Vec<crate::buffer::Buffer>,
     current: usize,
@@ -11,23 +12,44 @@ impl BufferManager {
         }
     }
 
-    /// Open a new buffer from a file and push it to the manager
-    pub fn open_file(&mut self, path: &str) -> anyhow::Result<()> {
-        let buffer = crate::buffer::Buffer::from_file(path)?;
+    /// Open a new buffer from a file and push it to the manager. A path
+    /// that does not exist yet gets an empty buffer.
+    pub fn open_file(&mut self, path: &str) -> crate::error::Result<()> {
+        let buffer = if std::path::Path::new(path).exists() {
+            crate::buffer::Buffer::from_file(path)?
+        } else {
+            crate::buffer::Buffer::new_file(path)
+        };
         self.buffers.push(buffer);
         self.current = self.buffers.len() - 1;
         Ok(())
     }
 
+    /// Add a buffer loaded elsewhere and make it current
+    pub fn add(&mut self, buffer: crate::buffer::Buffer) {
+        self.buffers.push(buffer);
+        self.current = self.buffers.len() - 1;
+    }
+
     /// Return the current buffer
     pub fn current_buffer(&mut self) -> &mut crate::buffer::Buffer {
         &mut self.buffers[self.current]
     }
 
+    /// Get a buffer by index (0-based)
+    pub fn get(&self, idx: usize) -> Option<&crate::buffer::Buffer> {
+        self.buffers.get(idx)
+    }
+
+    /// Get a buffer by index (0-based) to change it
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut crate::buffer::Buffer> {
+        self.buffers.get_mut(idx)
+    }
+
     /// Switch to a buffer by index (0-based)
-    pub fn switch_to(&mut self, idx: usize) -> anyhow::Result<()> {
+    pub fn switch_to(&mut self, idx: usize) -> crate::error::Result<()> {
         if idx >= self.buffers.len() {
-            return Err(anyhow::anyhow!("Buffer index out of range"));
+            return Err(crate::error::QuirksError::NoBuffer(idx));
         }
         self.current = idx;
         Ok(())
@@ -52,7 +74,7 @@ impl BufferManager {
     }
 
     /// Close current buffer and remove it
-    pub fn close_current(&mut self) -> anyhow::Result<()> {
+    pub fn close_current(&mut self) -> crate::error::Result<()> {
         if self.buffers.is_empty() {
             return Ok(());
         }
@@ -118,4 +140,15 @@ mod tests {
         assert!(bm.switch_to(0).is_err());
         assert!(bm.switch_to(5).is_err());
     }
+
+    #[test]
+    fn test_open_missing_file() {
+        let path = std::env::temp_dir().join(format!("quirks-missing-{}.rs", std::process::id()));
+        let mut bm = BufferManager::new();
+        bm.open_file(&path.to_string_lossy()).unwrap();
+        let buffer = bm.current_buffer();
+        assert_eq!(buffer.file_path(), Some(&path));
+        assert_eq!(buffer.content(), "");
+        assert!(!buffer.is_modified());
+    }
 }
@@ -0,0 +1,87 @@
+//! Character details for Quirks
+//!
+//! What `ga` and `:ascii` show about the character under the cursor: its
+//! code point in decimal, hex and octal, its UTF-8 bytes and its Unicode
+//! name, which tells apart the invisible and easily confused characters
+//! that make text look right but not compare equal.
+
+/// Names for characters the Unicode name table leaves unnamed (control
+/// characters, known by their aliases) or that deserve a hint
+const ALIASES: &[(char, &str)] = &[
+    ('\0', "NULL"),
+    ('\t', "CHARACTER TABULATION"),
+    ('\n', "LINE FEED"),
+    ('\r', "CARRIAGE RETURN"),
+    ('\u{1b}', "ESCAPE"),
+    ('\u{7f}', "DELETE"),
+    ('\u{feff}', "ZERO WIDTH NO-BREAK SPACE (BOM)"),
+];
+
+/// Unicode name of `c`, if it has one
+pub fn name(c: char) -> Option<String> {
+    match ALIASES.iter().find(|(known, _)| *known == c) {
+        Some((_, alias)) => Some(alias.to_string()),
+        None => unicode_names2::name(c).map(|name| name.to_string()),
+    }
+}
+
+/// How `c` is shown between the angle brackets: control characters as
+/// `^X`, everything else as itself
+fn printable(c: char) -> String {
+    match c {
+        '\0'..='\x1f' => format!("^{}", (c as u8 + b'@') as char),
+        '\x7f' => "^?".to_string(),
+        _ => c.to_string(),
+    }
+}
+
+/// One code point: `<é> 233, Hex e9, Oct 351, UTF-8 c3 a9, LATIN SMALL
+/// LETTER E WITH ACUTE`, the name left out if it has none
+fn describe_char(c: char) -> String {
+    let code = c as u32;
+    let hex = if code < 0x100 { format!("{:02x}", code) } else { format!("{:04x}", code) };
+    let mut bytes = [0; 4];
+    let utf8: Vec<String> = c.encode_utf8(&mut bytes).bytes().map(|b| format!("{:02x}", b)).collect();
+    let mut text = format!("<{}> {}, Hex {}, Oct {:03o}, UTF-8 {}", printable(c), code, hex, code, utf8.join(" "));
+    if let Some(name) = name(c) {
+        text.push_str(", ");
+        text.push_str(&name);
+    }
+    text
+}
+
+/// Every code point of a character as the cursor sees it (a grapheme,
+/// so a letter with combining accents shows each of them), or NUL for
+/// an empty line as in Vim
+pub fn describe(grapheme: &str) -> String {
+    if grapheme.is_empty() {
+        return "NUL".to_string();
+    }
+    grapheme.chars().map(describe_char).collect::<Vec<_>>().join(" + ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe("a"), "<a> 97, Hex 61, Oct 141, UTF-8 61, LATIN SMALL LETTER A");
+        assert_eq!(describe("é"), "<é> 233, Hex e9, Oct 351, UTF-8 c3 a9, LATIN SMALL LETTER E WITH ACUTE");
+        assert_eq!(describe("\t"), "<^I> 9, Hex 09, Oct 011, UTF-8 09, CHARACTER TABULATION");
+        assert_eq!(
+            describe("\u{a0}"),
+            "<\u{a0}> 160, Hex a0, Oct 240, UTF-8 c2 a0, NO-BREAK SPACE"
+        );
+        assert_eq!(
+            describe("e\u{301}"),
+            "<e> 101, Hex 65, Oct 145, UTF-8 65, LATIN SMALL LETTER E + \
+             <\u{301}> 769, Hex 0301, Oct 1401, UTF-8 cc 81, COMBINING ACUTE ACCENT"
+        );
+        assert_eq!(describe("€"), "<€> 8364, Hex 20ac, Oct 20254, UTF-8 e2 82 ac, EURO SIGN");
+        assert_eq!(name('\u{200b}').as_deref(), Some("ZERO WIDTH SPACE"));
+        assert_eq!(name('\u{feff}').as_deref(), Some("ZERO WIDTH NO-BREAK SPACE (BOM)"));
+        assert_eq!(name('\u{e000}'), None);
+        assert_eq!(describe(""), "NUL");
+    }
+}
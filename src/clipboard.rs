@@ -0,0 +1,209 @@
+//! System clipboard for Quirks
+//!
+//! The `+` and `*` registers go to the system clipboard through the tools
+//! each platform has for it: pbcopy/pbpaste on macOS, wl-copy/wl-paste on
+//! Wayland, xclip or xsel on X11 and clip.exe with PowerShell on Windows
+//! (and WSL). On X11 `*` is the primary selection, elsewhere both registers
+//! are the one clipboard. Without any of these tools the registers only
+//! live inside the editor.
+
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Which of the system's selections a register stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// The clipboard Ctrl-C and Ctrl-V use (`+`)
+    Clipboard,
+    /// The X11 primary selection, what was last selected (`*`)
+    Primary,
+}
+
+impl Selection {
+    /// The selection behind register `name`, if it is `+` or `*`
+    pub fn for_register(name: char) -> Option<Self> {
+        match name {
+            '+' => Some(Selection::Clipboard),
+            '*' => Some(Selection::Primary),
+            _ => None,
+        }
+    }
+}
+
+/// Commands that copy to and paste from one selection
+#[derive(Debug, Clone, Copy)]
+struct Commands {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+/// A clipboard tool and its commands for both selections
+#[derive(Debug, Clone, Copy)]
+struct Provider {
+    name: &'static str,
+    clipboard: Commands,
+    primary: Commands,
+}
+
+const PBCOPY: Provider = Provider {
+    name: "pbcopy",
+    clipboard: Commands { copy: &["pbcopy"], paste: &["pbpaste"] },
+    primary: Commands { copy: &["pbcopy"], paste: &["pbpaste"] },
+};
+
+const WL_COPY: Provider = Provider {
+    name: "wl-copy",
+    clipboard: Commands { copy: &["wl-copy", "--type", "text/plain"], paste: &["wl-paste", "--no-newline"] },
+    primary: Commands {
+        copy: &["wl-copy", "--primary", "--type", "text/plain"],
+        paste: &["wl-paste", "--no-newline", "--primary"],
+    },
+};
+
+const XCLIP: Provider = Provider {
+    name: "xclip",
+    clipboard: Commands {
+        copy: &["xclip", "-i", "-selection", "clipboard"],
+        paste: &["xclip", "-o", "-selection", "clipboard"],
+    },
+    primary: Commands {
+        copy: &["xclip", "-i", "-selection", "primary"],
+        paste: &["xclip", "-o", "-selection", "primary"],
+    },
+};
+
+const XSEL: Provider = Provider {
+    name: "xsel",
+    clipboard: Commands { copy: &["xsel", "-i", "-b"], paste: &["xsel", "-o", "-b"] },
+    primary: Commands { copy: &["xsel", "-i", "-p"], paste: &["xsel", "-o", "-p"] },
+};
+
+const WINDOWS: Provider = Provider {
+    name: "clip.exe",
+    clipboard: Commands {
+        copy: &["clip.exe"],
+        paste: &["powershell.exe", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+    },
+    primary: Commands {
+        copy: &["clip.exe"],
+        paste: &["powershell.exe", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+    },
+};
+
+/// The system clipboard, or none when no tool for it was found
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Clipboard {
+    provider: Option<Provider>,
+}
+
+impl Clipboard {
+    /// Find the clipboard tool for the session Quirks runs in
+    pub fn detect() -> Self {
+        let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+        let x11 = env::var_os("DISPLAY").is_some();
+        let provider = if cfg!(target_os = "macos") {
+            Some(PBCOPY)
+        } else if wayland && in_path("wl-copy") && in_path("wl-paste") {
+            Some(WL_COPY)
+        } else if x11 && in_path("xclip") {
+            Some(XCLIP)
+        } else if x11 && in_path("xsel") {
+            Some(XSEL)
+        } else if cfg!(windows) || in_path("clip.exe") {
+            Some(WINDOWS)
+        } else {
+            None
+        };
+        Self { provider }
+    }
+
+    /// Name of the tool in use, None without one
+    pub fn name(&self) -> Option<&'static str> {
+        self.provider.map(|provider| provider.name)
+    }
+
+    fn commands(&self, selection: Selection) -> io::Result<Commands> {
+        let provider = self.provider
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no clipboard tool found"))?;
+        Ok(match selection {
+            Selection::Clipboard => provider.clipboard,
+            Selection::Primary => provider.primary,
+        })
+    }
+
+    /// The text in a selection
+    pub fn get(&self, selection: Selection) -> io::Result<String> {
+        let commands = self.commands(selection)?;
+        let output = Command::new(commands.paste[0])
+            .args(&commands.paste[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("{} failed", commands.paste[0])));
+        }
+        // PowerShell ends lines with CRLF
+        Ok(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+    }
+
+    /// Put `text` in a selection
+    pub fn set(&self, selection: Selection, text: &str) -> io::Result<()> {
+        let commands = self.commands(selection)?;
+        let mut child = Command::new(commands.copy[0])
+            .args(&commands.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        // Closing stdin lets xclip and xsel go on serving the selection
+        // in the background while the command itself exits
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{} failed", commands.copy[0])));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `program` is an executable in one of the PATH directories
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| is_executable(&dir.join(program)))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_for_register() {
+        assert_eq!(Selection::for_register('+'), Some(Selection::Clipboard));
+        assert_eq!(Selection::for_register('*'), Some(Selection::Primary));
+        assert_eq!(Selection::for_register('a'), None);
+    }
+
+    #[test]
+    fn test_no_provider() {
+        let clipboard = Clipboard::default();
+        assert_eq!(clipboard.name(), None);
+        assert!(clipboard.get(Selection::Clipboard).is_err());
+        assert!(clipboard.set(Selection::Primary, "text").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Insert mode completion for Quirks
+//!
+//! File name completion (Vim's CTRL-X CTRL-F): the path before the cursor
+//! is completed against the file system. Whole line completion (CTRL-X
+//! CTRL-L): the line typed so far is completed to a line found in the open
+//! buffers. Candidates replace the typed fragment in the text as they are
+//! selected, and CTRL-E puts it back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Most candidates offered for one completion
+const MAX_CANDIDATES: usize = 500;
+
+/// Characters that end a path when looking back from the cursor
+const PATH_DELIMITERS: &[char] = &['"', '\'', '`', '(', ')', '[', ']', '{', '}', '<', '>', '=', ',', ';'];
+
+/// What is being completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    /// A file name (CTRL-X CTRL-F)
+    Path,
+    /// A whole line (CTRL-X CTRL-L)
+    Line,
+}
+
+impl CompletionKind {
+    /// Name shown in the completion messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompletionKind::Path => "File name completion",
+            CompletionKind::Line => "Whole line completion",
+        }
+    }
+}
+
+/// A completion in progress
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub kind: CompletionKind,
+    /// Line and column where the completed text starts
+    pub line: usize,
+    pub start_col: usize,
+    /// What was typed before completing
+    pub original: String,
+    pub candidates: Vec<String>,
+    /// Index into `candidates`, None while the original text is shown
+    pub selected: Option<usize>,
+}
+
+impl Completion {
+    /// Text in the buffer for the current selection
+    pub fn text(&self) -> &str {
+        match self.selected {
+            Some(idx) => &self.candidates[idx],
+            None => &self.original,
+        }
+    }
+
+    /// Select the next candidate (or previous with `forward` false),
+    /// passing through the original text at either end like Vim
+    pub fn step(&mut self, forward: bool) {
+        let last = self.candidates.len() - 1;
+        self.selected = match (self.selected, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(last),
+            (Some(idx), true) if idx < last => Some(idx + 1),
+            (Some(idx), false) if idx > 0 => Some(idx - 1),
+            (Some(_), _) => None,
+        };
+    }
+
+    /// Name shown in the popup for a candidate: the last component of a
+    /// path, a line without its indentation
+    pub fn label<'a>(&self, candidate: &'a str) -> &'a str {
+        if self.kind == CompletionKind::Line {
+            return candidate.trim_start();
+        }
+        let trimmed = candidate.trim_end_matches('/');
+        let start = trimmed.rfind('/').map_or(0, |idx| idx + 1);
+        &candidate[start..]
+    }
+}
+
+/// The path being typed at the end of `before_cursor`
+pub fn path_fragment(before_cursor: &str) -> &str {
+    let start = before_cursor
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace() || PATH_DELIMITERS.contains(c))
+        .map_or(0, |(idx, c)| idx + c.len_utf8());
+    &before_cursor[start..]
+}
+
+/// Completions of `fragment` as paths, relative ones against `base`. Each
+/// is `fragment` extended to a full entry name, with a `/` after
+/// directories. Hidden entries are only offered once a `.` is typed.
+pub fn complete_path(fragment: &str, base: &Path) -> Vec<String> {
+    let (dir_part, prefix) = match fragment.rfind('/') {
+        Some(idx) => fragment.split_at(idx + 1),
+        None => ("", fragment),
+    };
+    let dir = resolve_dir(dir_part, base);
+    let Ok(entries) = fs::read_dir(&dir) else { return Vec::new() };
+
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(format!("{}{}{}", dir_part, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    candidates.sort();
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+/// Lines from `lines` that start like `typed`, ignoring indentation, in
+/// the order given and without repeats. Blank lines and `typed` itself are
+/// left out.
+pub fn complete_line(typed: &str, lines: impl IntoIterator<Item = String>) -> Vec<String> {
+    let prefix = typed.trim_start();
+    let mut candidates: Vec<String> = Vec::new();
+    for line in lines {
+        if line.trim().is_empty() || line == typed || !line.trim_start().starts_with(prefix) {
+            continue;
+        }
+        if !candidates.contains(&line) {
+            candidates.push(line);
+            if candidates.len() == MAX_CANDIDATES {
+                break;
+            }
+        }
+    }
+    candidates
+}
+
+/// Directory the `dir/` part of a fragment names: `~/` is the home
+/// directory and relative paths start in `base`
+fn resolve_dir(dir_part: &str, base: &Path) -> PathBuf {
+    if dir_part.is_empty() {
+        return base.to_path_buf();
+    }
+    if let Some(rest) = dir_part.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    base.join(dir_part)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_fragment() {
+        assert_eq!(path_fragment("#include \"sys/ty"), "sys/ty");
+        assert_eq!(path_fragment("see [docs](./READ"), "./READ");
+        assert_eq!(path_fragment("cat ~/.conf"), "~/.conf");
+        assert_eq!(path_fragment("src/ma"), "src/ma");
+        assert_eq!(path_fragment("x = "), "");
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let dir = std::env::temp_dir().join(format!("quirks-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        fs::write(dir.join("src/map.rs"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+
+        assert_eq!(complete_path("src/ma", &dir), ["src/main.rs", "src/map.rs"]);
+        assert_eq!(complete_path("s", &dir), ["src/"]);
+        assert_eq!(complete_path("", &dir), ["src/"]);
+        assert_eq!(complete_path(".h", &dir), [".hidden"]);
+        assert!(complete_path("nothing/", &dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_complete_line() {
+        let lines = ["name = \"a\"", "  name = \"b\"", "", "other", "name = \"a\"", "na"];
+        let candidates = complete_line("  na", lines.iter().map(|line| line.to_string()));
+        assert_eq!(candidates, ["name = \"a\"", "  name = \"b\"", "na"]);
+        assert!(complete_line("zzz", lines.iter().map(|line| line.to_string())).is_empty());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut completion = Completion {
+            kind: CompletionKind::Path,
+            line: 0,
+            start_col: 0,
+            original: "sr".to_string(),
+            candidates: vec!["src/".to_string(), "srv/".to_string()],
+            selected: Some(0),
+        };
+        completion.step(true);
+        assert_eq!(completion.text(), "srv/");
+        completion.step(true);
+        assert_eq!(completion.text(), "sr");
+        completion.step(false);
+        assert_eq!(completion.text(), "srv/");
+        assert_eq!(completion.label("src/main/"), "main/");
+        completion.kind = CompletionKind::Line;
+        assert_eq!(completion.label("    let x = 1;"), "let x = 1;");
+    }
+}
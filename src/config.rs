@@ -1,15 +1,39 @@
 //! Configuration system for Quirks
 //!
-//! Loads settings from ~/.quirksrc or ~/.config/quirks/config.toml
+//! Loads settings from ~/.quirksrc or ~/.config/quirks/config.toml. A
+//! project can add its own in a `.quirks.toml` at its root, read over
+//! these once the user trusts it.
 
+use crate::theme::Theme;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+/// A key binding from a `[keymaps.<mode>]` section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBinding {
+    /// Mode name from the section header (normal, insert, visual)
+    pub mode: String,
+    /// Key sequence in Vim notation, e.g. "<C-s>" or "jk"
+    pub keys: String,
+    /// Action name, e.g. "write", or an ex command prefixed with ':'
+    pub action: String,
+    /// Line in the config file, for error messages
+    pub line: usize,
+}
+
+/// Name of the project-local config file, looked for in the project root
+pub const PROJECT_CONFIG: &str = ".quirks.toml";
+
 /// Editor configuration
 #[derive(Debug, Clone)]
 pub struct Config {
     /// Tab width in spaces
     pub tab_width: usize,
+    /// Indent with spaces rather than tab characters
+    pub expand_tab: bool,
+    /// Width gq rewraps text to
+    pub text_width: usize,
     /// Show line numbers
     pub line_numbers: bool,
     /// Enable syntax highlighting
@@ -18,19 +42,113 @@ pub struct Config {
     pub auto_indent: bool,
     /// Show whitespace characters
     pub show_whitespace: bool,
+    /// Soft wrap long lines instead of cutting them off
+    pub wrap: bool,
+    /// Indent wrapped rows as far as the start of their line
+    pub breakindent: bool,
+    /// Marker shown at the start of wrapped rows
+    pub showbreak: String,
+    /// Kinds of numbers Ctrl-A and Ctrl-X recognize: bin, hex, octal, alpha
+    pub nrformats: String,
+    /// Show a scrollbar with search and error marks
+    pub scrollbar: bool,
+    /// Animate large scroll jumps instead of snapping
+    pub smooth_scroll: bool,
+    /// Mark trailing whitespace and indents mixing tabs and spaces
+    pub whitespace_warnings: bool,
+    /// Lines kept visible above and below the cursor; 999 keeps it centered
+    pub scroll_margin: usize,
+    /// Highlight matches while the search pattern is typed
+    pub incsearch: bool,
+    /// Keep highlighting the matches of the last search
+    pub hlsearch: bool,
+    /// Preview the effect of a :s while it is typed
+    pub inccommand: bool,
+    /// Show CPU, memory and GPU usage in the status line
+    pub sysinfo: bool,
+    /// Show undo depth and the unnamed register in the status line
+    pub status_history: bool,
     /// Color scheme name
     pub color_scheme: String,
+    /// Milliseconds to wait for the next key of a mapped sequence
+    pub timeout_len: u64,
+    /// Milliseconds a key sequence must be pending before hints are shown
+    pub which_key_delay: u64,
+    /// Leader key for `<leader>` in key bindings
+    pub leader: Option<String>,
+    /// Custom key bindings, applied over the defaults
+    pub keymaps: Vec<KeyBinding>,
+    /// Shell for :terminal (defaults to $SHELL)
+    pub shell: Option<String>,
+    /// Key sequence that leaves terminal mode
+    pub terminal_escape: String,
+    /// Shell command that receives text sent to the REPL on stdin, used
+    /// when no :terminal is open
+    pub repl_command: Option<String>,
+    /// Program run by :make; `%` is replaced by the current file
+    pub makeprg: Option<String>,
+    /// :make program per filetype, from the `[makeprg]` section
+    pub makeprg_by_filetype: HashMap<String, String>,
+    /// How :make output is parsed into the quickfix list
+    pub errorformat: Option<String>,
+    /// Realign a table when `|` is typed in insert mode
+    pub table_auto_align: bool,
+    /// Text column width in :zen mode
+    pub zen_width: usize,
+    /// Turn on soft wrap in :zen mode
+    pub zen_wrap: bool,
+    /// Fill new files from a template in ~/.config/quirks/templates
+    pub templates: bool,
+    /// Put the cursor back where it was when a file is opened again
+    pub restore_cursor: bool,
+    /// Yank, delete and paste through the system clipboard (the `+`
+    /// register) when no register is given
+    pub clipboard: bool,
+    /// `{{author}}` in templates; git's user.name if unset
+    pub template_author: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             tab_width: 4,
+            expand_tab: true,
+            text_width: 79,
             line_numbers: true,
             syntax_highlighting: true,
             auto_indent: true,
             show_whitespace: false,
+            wrap: false,
+            breakindent: false,
+            showbreak: String::new(),
+            nrformats: "bin,hex".to_string(),
+            scrollbar: true,
+            smooth_scroll: false,
+            whitespace_warnings: true,
+            scroll_margin: 3,
+            incsearch: true,
+            hlsearch: true,
+            inccommand: true,
+            sysinfo: false,
+            status_history: false,
             color_scheme: "default".to_string(),
+            timeout_len: 1000,
+            which_key_delay: 300,
+            leader: None,
+            keymaps: Vec::new(),
+            shell: None,
+            terminal_escape: "<C-\\><C-n>".to_string(),
+            repl_command: None,
+            makeprg: None,
+            makeprg_by_filetype: HashMap::new(),
+            errorformat: None,
+            table_auto_align: false,
+            zen_width: 80,
+            zen_wrap: true,
+            templates: false,
+            restore_cursor: true,
+            clipboard: false,
+            template_author: None,
         }
     }
 }
@@ -63,15 +181,59 @@ impl Config {
     fn from_file(path: &PathBuf) -> Result<Self, String> {
         let content = fs::read_to_string(path)
             .map_err(|e| format!("Failed to read config: {}", e))?;
+        Ok(Self::parse(&content))
+    }
 
+    /// Parse configuration from the contents of a config file
+    fn parse(content: &str) -> Self {
         let mut config = Self::default();
+        config.merge(content);
+        config
+    }
+
+    /// Apply the settings in the contents of a config file over these,
+    /// as a project's .quirks.toml is applied over the user's config
+    pub fn merge(&mut self, content: &str) {
+        let mut keymap_mode: Option<String> = None;
+        let mut in_makeprg = false;
 
-        for line in content.lines() {
+        for (line_idx, line) in content.lines().enumerate() {
             let line = line.trim();
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
 
+            // Section headers; only [keymaps.<mode>] and [makeprg] change
+            // how lines are read
+            if line.starts_with('[') && line.ends_with(']') {
+                let section = line[1..line.len() - 1].trim();
+                keymap_mode = section.strip_prefix("keymaps.").map(|m| m.trim().to_string());
+                in_makeprg = section == "makeprg";
+                continue;
+            }
+
+            if in_makeprg {
+                if let Some((filetype, program)) = line.split_once('=') {
+                    self.makeprg_by_filetype.insert(
+                        filetype.trim().to_lowercase(),
+                        program.trim().trim_matches('"').to_string(),
+                    );
+                }
+                continue;
+            }
+
+            if let Some(mode) = &keymap_mode {
+                if let Some((keys, action)) = split_binding(line) {
+                    self.keymaps.push(KeyBinding {
+                        mode: mode.clone(),
+                        keys,
+                        action,
+                        line: line_idx + 1,
+                    });
+                }
+                continue;
+            }
+
             if let Some((key, value)) = line.split_once('=') {
                 let key = key.trim();
                 let value = value.trim().trim_matches('"');
@@ -79,30 +241,227 @@ impl Config {
                 match key {
                     "tab_width" => {
                         if let Ok(n) = value.parse() {
-                            config.tab_width = n;
+                            self.tab_width = n;
                         }
                     }
+                    "text_width" => {
+                        if let Ok(n) = value.parse() {
+                            self.text_width = n;
+                        }
+                    }
+                    "expand_tab" => {
+                        self.expand_tab = value == "true" || value == "1";
+                    }
                     "line_numbers" => {
-                        config.line_numbers = value == "true" || value == "1";
+                        self.line_numbers = value == "true" || value == "1";
                     }
                     "syntax_highlighting" => {
-                        config.syntax_highlighting = value == "true" || value == "1";
+                        self.syntax_highlighting = value == "true" || value == "1";
                     }
                     "auto_indent" => {
-                        config.auto_indent = value == "true" || value == "1";
+                        self.auto_indent = value == "true" || value == "1";
                     }
                     "show_whitespace" => {
-                        config.show_whitespace = value == "true" || value == "1";
+                        self.show_whitespace = value == "true" || value == "1";
+                    }
+                    "wrap" => {
+                        self.wrap = value == "true" || value == "1";
+                    }
+                    "breakindent" => {
+                        self.breakindent = value == "true" || value == "1";
+                    }
+                    "showbreak" => {
+                        self.showbreak = value.to_string();
+                    }
+                    "nrformats" => {
+                        self.nrformats = value.to_string();
+                    }
+                    "scrollbar" => {
+                        self.scrollbar = value == "true" || value == "1";
+                    }
+                    "smooth_scroll" => {
+                        self.smooth_scroll = value == "true" || value == "1";
+                    }
+                    "whitespace_warnings" => {
+                        self.whitespace_warnings = value == "true" || value == "1";
+                    }
+                    "scroll_margin" => {
+                        if let Ok(n) = value.parse() {
+                            self.scroll_margin = n;
+                        }
+                    }
+                    "incsearch" => {
+                        self.incsearch = value == "true" || value == "1";
+                    }
+                    "hlsearch" => {
+                        self.hlsearch = value == "true" || value == "1";
+                    }
+                    "inccommand" => {
+                        self.inccommand = value == "true" || value == "1";
+                    }
+                    "sysinfo" => {
+                        self.sysinfo = value == "true" || value == "1";
+                    }
+                    "status_history" => {
+                        self.status_history = value == "true" || value == "1";
                     }
                     "color_scheme" => {
-                        config.color_scheme = value.to_string();
+                        self.color_scheme = value.to_string();
+                    }
+                    "timeout_len" => {
+                        if let Ok(n) = value.parse() {
+                            self.timeout_len = n;
+                        }
+                    }
+                    "which_key_delay" => {
+                        if let Ok(n) = value.parse() {
+                            self.which_key_delay = n;
+                        }
+                    }
+                    "leader" => {
+                        self.leader = Some(value.to_string());
+                    }
+                    "shell" => {
+                        self.shell = Some(value.to_string());
+                    }
+                    "terminal_escape" => {
+                        self.terminal_escape = value.to_string();
+                    }
+                    "repl_command" => {
+                        self.repl_command = Some(value.to_string());
+                    }
+                    "makeprg" => {
+                        self.makeprg = Some(value.to_string());
+                    }
+                    "errorformat" => {
+                        self.errorformat = Some(value.to_string());
+                    }
+                    "table_auto_align" => {
+                        self.table_auto_align = value == "true" || value == "1";
+                    }
+                    "zen_width" => {
+                        if let Ok(n) = value.parse() {
+                            self.zen_width = n;
+                        }
+                    }
+                    "zen_wrap" => {
+                        self.zen_wrap = value == "true" || value == "1";
+                    }
+                    "templates" => {
+                        self.templates = value == "true" || value == "1";
+                    }
+                    "restore_cursor" => {
+                        self.restore_cursor = value == "true" || value == "1";
+                    }
+                    "clipboard" => {
+                        self.clipboard = value == "true" || value == "1";
+                    }
+                    "template_author" => {
+                        self.template_author = Some(value.to_string());
                     }
                     _ => {} // Ignore unknown keys
                 }
             }
         }
+    }
 
-        Ok(config)
+    /// Set an option by its Vim name ("number", "tabstop", "list", ...) or
+    /// config key. Boolean options take `None` to enable; a "no" prefix
+    /// disables them.
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let parse_bool = |v: Option<&str>| match v {
+            None | Some("true") | Some("1") => Ok(true),
+            Some("false") | Some("0") => Ok(false),
+            Some(other) => Err(format!("Invalid boolean: {}", other)),
+        };
+        let parse_num = |v: Option<&str>| {
+            v.ok_or_else(|| format!("Option {} needs a value", name))?
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid number for {}", name))
+        };
+
+        if let Some(flag) = name.strip_prefix("no") {
+            if value.is_none() && self.is_bool_option(flag) {
+                return self.set_option(flag, Some("false"));
+            }
+        }
+
+        match name {
+            "number" | "nu" | "line_numbers" => self.line_numbers = parse_bool(value)?,
+            "syntax" | "syn" | "syntax_highlighting" => self.syntax_highlighting = parse_bool(value)?,
+            "autoindent" | "ai" | "auto_indent" => self.auto_indent = parse_bool(value)?,
+            "list" | "show_whitespace" => self.show_whitespace = parse_bool(value)?,
+            "wrap" => self.wrap = parse_bool(value)?,
+            "breakindent" | "bri" => self.breakindent = parse_bool(value)?,
+            "showbreak" | "sbr" => self.showbreak = value.unwrap_or_default().to_string(),
+            "nrformats" | "nf" => self.nrformats = value.unwrap_or_default().to_string(),
+            "scrollbar" => self.scrollbar = parse_bool(value)?,
+            "smooth_scroll" => self.smooth_scroll = parse_bool(value)?,
+            "whitespace_warnings" => self.whitespace_warnings = parse_bool(value)?,
+            "scrolloff" | "so" | "scroll_margin" => self.scroll_margin = parse_num(value)? as usize,
+            "incsearch" | "is" => self.incsearch = parse_bool(value)?,
+            "hlsearch" | "hls" => self.hlsearch = parse_bool(value)?,
+            "inccommand" | "icm" => self.inccommand = parse_bool(value)?,
+            "sysinfo" => self.sysinfo = parse_bool(value)?,
+            "status_history" => self.status_history = parse_bool(value)?,
+            "tabstop" | "ts" | "tab_width" => self.tab_width = parse_num(value)?.max(1) as usize,
+            "expandtab" | "et" | "expand_tab" => self.expand_tab = parse_bool(value)?,
+            "textwidth" | "tw" | "text_width" => self.text_width = parse_num(value)?.max(1) as usize,
+            "timeoutlen" | "tm" | "timeout_len" => self.timeout_len = parse_num(value)?,
+            "which_key_delay" => self.which_key_delay = parse_num(value)?,
+            "table_auto_align" => self.table_auto_align = parse_bool(value)?,
+            "templates" => self.templates = parse_bool(value)?,
+            "restore_cursor" => self.restore_cursor = parse_bool(value)?,
+            "clipboard" | "cb" => self.clipboard = parse_bool(value)?,
+            "colorscheme" | "colors" | "color_scheme" => {
+                let value = value.ok_or_else(|| format!("Option {} needs a value", name))?;
+                if Theme::by_name(value).is_none() {
+                    return Err(format!("Unknown color scheme: {} (use {})", value, Theme::available().join(", ")));
+                }
+                self.color_scheme = value.to_string();
+            }
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Get an option's current value by Vim name or config key
+    pub fn get_option(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "number" | "nu" | "line_numbers" => self.line_numbers.to_string(),
+            "syntax" | "syn" | "syntax_highlighting" => self.syntax_highlighting.to_string(),
+            "autoindent" | "ai" | "auto_indent" => self.auto_indent.to_string(),
+            "list" | "show_whitespace" => self.show_whitespace.to_string(),
+            "wrap" => self.wrap.to_string(),
+            "breakindent" | "bri" => self.breakindent.to_string(),
+            "showbreak" | "sbr" => self.showbreak.clone(),
+            "nrformats" | "nf" => self.nrformats.clone(),
+            "scrollbar" => self.scrollbar.to_string(),
+            "smooth_scroll" => self.smooth_scroll.to_string(),
+            "whitespace_warnings" => self.whitespace_warnings.to_string(),
+            "scrolloff" | "so" | "scroll_margin" => self.scroll_margin.to_string(),
+            "incsearch" | "is" => self.incsearch.to_string(),
+            "hlsearch" | "hls" => self.hlsearch.to_string(),
+            "inccommand" | "icm" => self.inccommand.to_string(),
+            "sysinfo" => self.sysinfo.to_string(),
+            "status_history" => self.status_history.to_string(),
+            "tabstop" | "ts" | "tab_width" => self.tab_width.to_string(),
+            "expandtab" | "et" | "expand_tab" => self.expand_tab.to_string(),
+            "textwidth" | "tw" | "text_width" => self.text_width.to_string(),
+            "timeoutlen" | "tm" | "timeout_len" => self.timeout_len.to_string(),
+            "which_key_delay" => self.which_key_delay.to_string(),
+            "table_auto_align" => self.table_auto_align.to_string(),
+            "templates" => self.templates.to_string(),
+            "restore_cursor" => self.restore_cursor.to_string(),
+            "clipboard" | "cb" => self.clipboard.to_string(),
+            "colorscheme" | "colors" | "color_scheme" => self.color_scheme.clone(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn is_bool_option(&self, name: &str) -> bool {
+        matches!(self.get_option(name).as_deref(), Some("true") | Some("false"))
     }
 
     /// Get config file path for user reference
@@ -111,6 +470,26 @@ impl Config {
     }
 }
 
+/// Split a `"keys" = "action"` line; the key may be quoted so it can
+/// contain '=' or spaces
+fn split_binding(line: &str) -> Option<(String, String)> {
+    let (keys, rest) = match line.strip_prefix('"') {
+        Some(quoted) => {
+            let end = quoted.find('"')?;
+            (&quoted[..end], quoted[end + 1..].trim_start().strip_prefix('=')?)
+        }
+        None => {
+            let (keys, rest) = line.split_once('=')?;
+            (keys.trim(), rest)
+        }
+    };
+    let action = rest.trim().trim_matches('"');
+    if keys.is_empty() || action.is_empty() {
+        return None;
+    }
+    Some((keys.to_string(), action.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,4 +501,72 @@ mod tests {
         assert!(config.line_numbers);
         assert!(config.syntax_highlighting);
     }
+
+    #[test]
+    fn test_parse_keymaps() {
+        let config = Config::parse(
+            "tab_width = 2\n\
+             [keymaps.normal]\n\
+             \"<C-s>\" = \"write\"\n\
+             \"<leader>=\" = \":set nu\"\n\
+             [keymaps.insert]\n\
+             jk = enter_normal\n",
+        );
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(config.keymaps.len(), 3);
+        assert_eq!(config.keymaps[0].mode, "normal");
+        assert_eq!(config.keymaps[0].keys, "<C-s>");
+        assert_eq!(config.keymaps[0].action, "write");
+        assert_eq!(config.keymaps[1].keys, "<leader>=");
+        assert_eq!(config.keymaps[1].action, ":set nu");
+        assert_eq!(config.keymaps[2].mode, "insert");
+        assert_eq!(config.keymaps[2].line, 6);
+    }
+
+    #[test]
+    fn test_parse_makeprg() {
+        let config = Config::parse(
+            "makeprg = \"make -j4\"\n\
+             errorformat = \"%f:%l: %m\"\n\
+             [makeprg]\n\
+             Rust = \"cargo check --message-format=short\"\n",
+        );
+        assert_eq!(config.makeprg.as_deref(), Some("make -j4"));
+        assert_eq!(config.errorformat.as_deref(), Some("%f:%l: %m"));
+        assert_eq!(
+            config.makeprg_by_filetype.get("rust").map(String::as_str),
+            Some("cargo check --message-format=short")
+        );
+    }
+
+    #[test]
+    fn test_set_option() {
+        let mut config = Config::default();
+        config.set_option("nonumber", None).unwrap();
+        assert!(!config.line_numbers);
+        config.set_option("nu", None).unwrap();
+        assert!(config.line_numbers);
+        config.set_option("ts", Some("8")).unwrap();
+        assert_eq!(config.tab_width, 8);
+        assert_eq!(config.get_option("tabstop").as_deref(), Some("8"));
+        assert!(config.set_option("tabstop", Some("wide")).is_err());
+        assert!(config.set_option("relativenumber", None).is_err());
+        assert_eq!(config.get_option("bogus"), None);
+        config.set_option("sbr", Some("↪")).unwrap();
+        assert_eq!(config.showbreak, "↪");
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut config = Config::parse("tab_width = 8
+wrap = true
+");
+        config.merge("tab_width = 2
+[makeprg]
+rust = \"cargo clippy\"
+");
+        assert_eq!(config.tab_width, 2);
+        assert!(config.wrap);
+        assert_eq!(config.makeprg_by_filetype.get("rust").map(String::as_str), Some("cargo clippy"));
+    }
 }
@@ -22,6 +22,11 @@ impl Cursor {
         Self::default()
     }
 
+    /// A cursor at `line`, `col`
+    pub fn at(line: usize, col: usize) -> Self {
+        Self { line, col, sticky_col: col }
+    }
+
     /// Move cursor left by one grapheme
     pub fn move_left(&mut self, buffer: &Buffer) {
         if self.col > 0 {
@@ -118,9 +123,21 @@ impl Cursor {
 
     /// Move to the start of the next word (w)
     pub fn move_word_forward(&mut self, buffer: &Buffer) {
+        self.word_forward(buffer, false);
+    }
+
+    /// Move to the start of the next WORD (W), words split at whitespace
+    /// only
+    pub fn move_big_word_forward(&mut self, buffer: &Buffer) {
+        self.word_forward(buffer, true);
+    }
+
+    /// Move to the start of the next word, or WORD with `big`. False when
+    /// there is none: the cursor is then left on the last character.
+    pub fn word_forward(&mut self, buffer: &Buffer, big: bool) -> bool {
         let total_lines = buffer.line_count();
         if total_lines == 0 {
-            return;
+            return false;
         }
 
         let mut line = self.line;
@@ -132,8 +149,8 @@ impl Cursor {
 
             // Skip current word (non-whitespace of same type)
             if col < chars.len() {
-                let start_type = char_type(chars[col]);
-                while col < chars.len() && char_type(chars[col]) == start_type {
+                let start_type = word_class(chars[col], big);
+                while col < chars.len() && word_class(chars[col], big) == start_type {
                     col += 1;
                 }
             }
@@ -148,7 +165,7 @@ impl Cursor {
                 self.line = line;
                 self.col = col;
                 self.sticky_col = col;
-                return;
+                return true;
             }
 
             // Move to next line
@@ -165,20 +182,30 @@ impl Cursor {
                     self.line = line;
                     self.col = col;
                     self.sticky_col = col;
-                    return;
+                    return true;
                 }
             } else {
                 // End of buffer
                 self.line = line;
                 self.col = chars.len().saturating_sub(1);
                 self.sticky_col = self.col;
-                return;
+                return false;
             }
         }
     }
 
     /// Move to the start of the previous word (b)
     pub fn move_word_backward(&mut self, buffer: &Buffer) {
+        self.word_backward(buffer, false);
+    }
+
+    /// Move to the start of the previous WORD (B)
+    pub fn move_big_word_backward(&mut self, buffer: &Buffer) {
+        self.word_backward(buffer, true);
+    }
+
+    /// Move to the start of the previous word, or WORD with `big`
+    pub fn word_backward(&mut self, buffer: &Buffer, big: bool) {
         let total_lines = buffer.line_count();
         if total_lines == 0 {
             return;
@@ -206,12 +233,12 @@ impl Cursor {
             let chars: Vec<char> = buffer.line(line).chars().collect();
             
             // Skip whitespace going backward
-            while col > 0 && chars.get(col).map_or(false, |c| c.is_whitespace()) {
+            while col > 0 && chars.get(col).is_some_and(|c| c.is_whitespace()) {
                 col -= 1;
             }
 
             // If we hit whitespace at col 0, try previous line
-            if chars.get(col).map_or(false, |c| c.is_whitespace()) {
+            if chars.get(col).is_some_and(|c| c.is_whitespace()) {
                 if line > 0 {
                     line -= 1;
                     let prev_line = buffer.line(line);
@@ -226,8 +253,8 @@ impl Cursor {
             }
 
             // Find start of current word
-            let word_type = char_type(chars[col]);
-            while col > 0 && char_type(chars[col - 1]) == word_type {
+            let word_type = word_class(chars[col], big);
+            while col > 0 && word_class(chars[col - 1], big) == word_type {
                 col -= 1;
             }
 
@@ -238,8 +265,35 @@ impl Cursor {
         }
     }
 
+    /// Column insert mode CTRL-W deletes back to: over whitespace, then
+    /// over one word or run of punctuation, staying on the line
+    pub fn word_start_before(&self, buffer: &Buffer) -> usize {
+        let chars: Vec<char> = buffer.line(self.line).chars().collect();
+        let mut col = self.col.min(chars.len());
+        while col > 0 && char_type(chars[col - 1]) == CharType::Whitespace {
+            col -= 1;
+        }
+        if let Some(&ch) = col.checked_sub(1).and_then(|idx| chars.get(idx)) {
+            let word_type = char_type(ch);
+            while col > 0 && char_type(chars[col - 1]) == word_type {
+                col -= 1;
+            }
+        }
+        col
+    }
+
     /// Move to the end of the current/next word (e)
     pub fn move_word_end(&mut self, buffer: &Buffer) {
+        self.word_end(buffer, false);
+    }
+
+    /// Move to the end of the current/next WORD (E)
+    pub fn move_big_word_end(&mut self, buffer: &Buffer) {
+        self.word_end(buffer, true);
+    }
+
+    /// Move to the end of the current/next word, or WORD with `big`
+    pub fn word_end(&mut self, buffer: &Buffer, big: bool) {
         let total_lines = buffer.line_count();
         if total_lines == 0 {
             return;
@@ -282,8 +336,8 @@ impl Cursor {
             }
 
             // Find end of word
-            let word_type = char_type(chars[col]);
-            while col + 1 < chars.len() && char_type(chars[col + 1]) == word_type {
+            let word_type = word_class(chars[col], big);
+            while col + 1 < chars.len() && word_class(chars[col + 1], big) == word_type {
                 col += 1;
             }
 
@@ -295,6 +349,63 @@ impl Cursor {
     }
 }
 
+/// Extra cursors for multi-cursor editing, besides the editor's own.
+/// Kept in buffer order without duplicates.
+#[derive(Debug, Clone, Default)]
+pub struct CursorSet {
+    cursors: Vec<Cursor>,
+}
+
+impl CursorSet {
+    pub fn is_empty(&self) -> bool {
+        self.cursors.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cursors.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cursor> {
+        self.cursors.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Cursor> {
+        self.cursors.iter_mut()
+    }
+
+    /// Columns of the cursors on `line`
+    pub fn on_line(&self, line: usize) -> impl Iterator<Item = usize> + '_ {
+        self.cursors.iter().filter(move |c| c.line == line).map(|c| c.col)
+    }
+
+    pub fn contains(&self, line: usize, col: usize) -> bool {
+        self.cursors.iter().any(|c| c.line == line && c.col == col)
+    }
+
+    /// Add a cursor unless one is already there
+    pub fn add(&mut self, cursor: Cursor) {
+        if !self.contains(cursor.line, cursor.col) {
+            self.cursors.push(cursor);
+            self.cursors.sort_by_key(|c| (c.line, c.col));
+        }
+    }
+
+    /// Replace the cursors, dropping any that landed on `primary` or on
+    /// each other
+    pub fn set(&mut self, cursors: impl IntoIterator<Item = Cursor>, primary: &Cursor) {
+        self.cursors.clear();
+        for cursor in cursors {
+            if (cursor.line, cursor.col) != (primary.line, primary.col) {
+                self.add(cursor);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cursors.clear();
+    }
+}
+
 /// Character classification for word motion
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum CharType {
@@ -313,6 +424,15 @@ fn char_type(c: char) -> CharType {
     }
 }
 
+/// Class of a character for word motions; for WORD motions (`big`) all
+/// non-whitespace is one class
+fn word_class(c: char, big: bool) -> CharType {
+    match char_type(c) {
+        CharType::Punctuation if big => CharType::Word,
+        class => class,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,6 +444,16 @@ mod tests {
         assert_eq!(cursor.col, 0);
     }
 
+    #[test]
+    fn test_cursor_set_merges() {
+        let primary = Cursor::at(0, 0);
+        let mut set = CursorSet::default();
+        set.set([Cursor::at(2, 1), Cursor::at(0, 0), Cursor::at(1, 4), Cursor::at(2, 1)], &primary);
+        let positions: Vec<(usize, usize)> = set.iter().map(|c| (c.line, c.col)).collect();
+        assert_eq!(positions, vec![(1, 4), (2, 1)]);
+        assert_eq!(set.on_line(2).collect::<Vec<_>>(), vec![1]);
+    }
+
     #[test]
     fn test_char_type_word() {
         assert_eq!(char_type('a'), CharType::Word);
@@ -345,4 +475,21 @@ mod tests {
         assert_eq!(char_type(','), CharType::Punctuation);
         assert_eq!(char_type('!'), CharType::Punctuation);
     }
+
+    #[test]
+    fn test_big_word_motions() {
+        let buffer = Buffer::from_text("a.b c-d\ne");
+        let mut cursor = Cursor::at(0, 0);
+        cursor.move_word_forward(&buffer);
+        assert_eq!((cursor.line, cursor.col), (0, 1));
+        cursor.move_big_word_forward(&buffer);
+        assert_eq!((cursor.line, cursor.col), (0, 4));
+        cursor.move_big_word_end(&buffer);
+        assert_eq!((cursor.line, cursor.col), (0, 6));
+        assert!(cursor.word_forward(&buffer, true));
+        assert_eq!((cursor.line, cursor.col), (1, 0));
+        assert!(!cursor.word_forward(&buffer, true));
+        cursor.move_big_word_backward(&buffer);
+        assert_eq!((cursor.line, cursor.col), (0, 4));
+    }
 }
@@ -0,0 +1,162 @@
+//! Decorations for Quirks
+//!
+//! Virtual text, highlights and signs attached to positions in a buffer.
+//! Each decoration sits on an extmark: an anchor that moves with the text
+//! around it as the buffer is edited. Features own their decorations by
+//! namespace ("blame", a plugin name, ...) so they can replace them all at
+//! once.
+
+use ratatui::style::Style;
+use std::ops::Range;
+
+/// What is drawn at an extmark
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoration {
+    /// Text after the end of the line, e.g. blame or a diagnostic message
+    EndOfLine { text: String, style: Style },
+    /// Text drawn before the anchored character, e.g. an inlay type hint
+    Inline { text: String, style: Style },
+    /// Highlight of `len` characters starting at the anchor
+    Highlight { len: usize, style: Style },
+    /// Sign in the gutter of the anchored line
+    Sign { text: String, style: Style },
+}
+
+/// A decoration anchored at a character offset in the buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extmark {
+    pub id: u64,
+    pub namespace: String,
+    /// Character offset of the anchor
+    pub offset: usize,
+    pub decoration: Decoration,
+}
+
+/// All extmarks of a buffer, kept in order of their offsets
+#[derive(Debug, Clone, Default)]
+pub struct Decorations {
+    marks: Vec<Extmark>,
+    next_id: u64,
+}
+
+impl Decorations {
+    /// Add a decoration at character `offset`, returning its id
+    pub fn add(&mut self, namespace: &str, offset: usize, decoration: Decoration) -> u64 {
+        self.next_id += 1;
+        let idx = self.marks.partition_point(|mark| mark.offset <= offset);
+        self.marks.insert(idx, Extmark {
+            id: self.next_id,
+            namespace: namespace.to_string(),
+            offset,
+            decoration,
+        });
+        self.next_id
+    }
+
+    /// Remove one decoration; false if there was none with that id
+    pub fn remove(&mut self, id: u64) -> bool {
+        let len = self.marks.len();
+        self.marks.retain(|mark| mark.id != id);
+        self.marks.len() != len
+    }
+
+    /// Look up a decoration by id
+    pub fn get(&self, id: u64) -> Option<&Extmark> {
+        self.marks.iter().find(|mark| mark.id == id)
+    }
+
+    /// Remove every decoration in a namespace
+    pub fn clear_namespace(&mut self, namespace: &str) {
+        self.marks.retain(|mark| mark.namespace != namespace);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    /// Width of the widest sign, 0 if there are none
+    pub fn sign_width(&self) -> usize {
+        self.marks.iter()
+            .filter_map(|mark| match &mark.decoration {
+                Decoration::Sign { text, .. } => Some(text.chars().count()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Extmarks anchored within a range of character offsets
+    pub fn in_range(&self, range: Range<usize>) -> &[Extmark] {
+        let start = self.marks.partition_point(|mark| mark.offset < range.start);
+        let end = self.marks.partition_point(|mark| mark.offset < range.end);
+        &self.marks[start..end]
+    }
+
+    /// Move anchors after `removed` characters at `start` were replaced by
+    /// `inserted` characters. Anchors in the removed text collapse to
+    /// `start`; an anchor at `start` itself moves right with inserted text.
+    pub fn edited(&mut self, start: usize, removed: usize, inserted: usize) {
+        for mark in &mut self.marks {
+            if mark.offset >= start + removed {
+                mark.offset = mark.offset - removed + inserted;
+            } else if mark.offset > start {
+                mark.offset = start;
+            }
+        }
+    }
+
+    /// Keep anchors inside a buffer of `len` characters, after the text was
+    /// replaced wholesale (undo, reload)
+    pub fn clamp(&mut self, len: usize) {
+        for mark in &mut self.marks {
+            mark.offset = mark.offset.min(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(text: &str) -> Decoration {
+        Decoration::Sign { text: text.to_string(), style: Style::default() }
+    }
+
+    fn offsets(decorations: &Decorations) -> Vec<usize> {
+        decorations.in_range(0..usize::MAX).iter().map(|mark| mark.offset).collect()
+    }
+
+    #[test]
+    fn test_extmarks_follow_edits() {
+        let mut decorations = Decorations::default();
+        decorations.add("test", 10, sign("a"));
+        decorations.add("test", 5, sign("b"));
+        decorations.add("test", 20, sign("c"));
+        assert_eq!(offsets(&decorations), vec![5, 10, 20]);
+
+        // Insert 3 characters at offset 5: the anchor there moves right
+        decorations.edited(5, 0, 3);
+        assert_eq!(offsets(&decorations), vec![8, 13, 23]);
+
+        // Delete offsets 10..20: the anchor at 13 collapses to 10
+        decorations.edited(10, 10, 0);
+        assert_eq!(offsets(&decorations), vec![8, 10, 13]);
+
+        // Replace 8..9 with two characters
+        decorations.edited(8, 1, 2);
+        assert_eq!(offsets(&decorations), vec![8, 11, 14]);
+    }
+
+    #[test]
+    fn test_namespaces_and_ranges() {
+        let mut decorations = Decorations::default();
+        let id = decorations.add("blame", 0, sign("x"));
+        decorations.add("plugin", 4, sign("y"));
+        decorations.add("plugin", 9, sign("z"));
+        assert_eq!(decorations.in_range(0..5).len(), 2);
+        assert!(decorations.remove(id));
+        assert!(!decorations.remove(id));
+        decorations.clear_namespace("plugin");
+        assert!(decorations.is_empty());
+    }
+}
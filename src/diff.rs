@@ -0,0 +1,275 @@
+//! Line diffing for Quirks
+//!
+//! Myers' O(ND) diff over lines, reported as hunks of changed lines.
+//! Used for git gutter signs and diff mode.
+
+use std::path::{Path, PathBuf};
+
+/// A run of lines that differ between the old and new text.
+///
+/// `old_len == 0` is a pure insertion, `new_len == 0` a pure deletion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl Hunk {
+    /// Kind of change this hunk represents
+    pub fn kind(&self) -> ChangeKind {
+        match (self.old_len, self.new_len) {
+            (0, _) => ChangeKind::Added,
+            (_, 0) => ChangeKind::Removed,
+            _ => ChangeKind::Modified,
+        }
+    }
+}
+
+/// How a line or hunk changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// Compute the hunks that turn `old` into `new`
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    // Trim the common prefix and suffix; most edits are local
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+    for (kind, i, j) in myers(a, b) {
+        let (i, j) = (i + prefix, j + prefix);
+        match kind {
+            Edit::Equal => {
+                hunks.extend(current.take());
+            }
+            Edit::Delete => {
+                let hunk = current.get_or_insert(Hunk { old_start: i, old_len: 0, new_start: j, new_len: 0 });
+                hunk.old_len += 1;
+            }
+            Edit::Insert => {
+                let hunk = current.get_or_insert(Hunk { old_start: i, old_len: 0, new_start: j, new_len: 0 });
+                hunk.new_len += 1;
+            }
+        }
+    }
+    hunks.extend(current);
+    hunks
+}
+
+/// Side-by-side diff of the current buffer against another file.
+///
+/// Hunks run from the buffer (old side) to the other file (new side).
+#[derive(Debug)]
+pub struct DiffView {
+    path: PathBuf,
+    lines: Vec<String>,
+    hunks: Vec<Hunk>,
+}
+
+impl DiffView {
+    pub fn new(path: PathBuf, lines: Vec<String>, current: &[&str]) -> Self {
+        let mut view = Self {
+            path,
+            lines,
+            hunks: Vec::new(),
+        };
+        view.update(current);
+        view
+    }
+
+    /// File being compared against
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Lines of the file being compared against
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// Recompute the hunks after the buffer changed
+    pub fn update(&mut self, current: &[&str]) {
+        let other: Vec<&str> = self.lines.iter().map(String::as_str).collect();
+        self.hunks = diff_lines(current, &other);
+    }
+
+    /// Line in the other file matching a buffer line, for synchronized
+    /// scrolling
+    pub fn other_line(&self, line: usize) -> usize {
+        let mut shift = 0isize;
+        for hunk in &self.hunks {
+            if line < hunk.old_start {
+                break;
+            }
+            if line < hunk.old_start + hunk.old_len {
+                let within = (line - hunk.old_start).min(hunk.new_len.saturating_sub(1));
+                return hunk.new_start + within;
+            }
+            shift = (hunk.new_start + hunk.new_len) as isize - (hunk.old_start + hunk.old_len) as isize;
+        }
+        (line as isize + shift).max(0) as usize
+    }
+
+    /// How a buffer line differs from the other file: `Removed` lines are
+    /// missing from the other file
+    pub fn current_change(&self, line: usize) -> Option<ChangeKind> {
+        self.hunks.iter()
+            .find(|h| (h.old_start..h.old_start + h.old_len).contains(&line))
+            .map(|h| if h.new_len == 0 { ChangeKind::Removed } else { ChangeKind::Modified })
+    }
+
+    /// How a line of the other file differs from the buffer: `Added` lines
+    /// are missing from the buffer
+    pub fn other_change(&self, line: usize) -> Option<ChangeKind> {
+        self.hunks.iter()
+            .find(|h| (h.new_start..h.new_start + h.new_len).contains(&line))
+            .map(|h| if h.old_len == 0 { ChangeKind::Added } else { ChangeKind::Modified })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Shortest edit script from `a` to `b` as (edit, old index, new index)
+/// steps, in order
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(Edit, usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                trace.push(v.clone());
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk the trace backwards to recover the path
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize - 1).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) { k + 1 } else { k - 1 };
+        let prev_x = if d == 0 { 0 } else { v[(prev_k + offset) as usize] };
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push((Edit::Equal, x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                y -= 1;
+                edits.push((Edit::Insert, x as usize, y as usize));
+            } else {
+                x -= 1;
+                edits.push((Edit::Delete, x as usize, y as usize));
+            }
+        }
+    }
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunk(old_start: usize, old_len: usize, new_start: usize, new_len: usize) -> Hunk {
+        Hunk { old_start, old_len, new_start, new_len }
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        assert!(diff_lines(&["a", "b"], &["a", "b"]).is_empty());
+        assert!(diff_lines::<&str>(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_diff_insert_delete_modify() {
+        assert_eq!(diff_lines(&["a", "c"], &["a", "b", "c"]), vec![hunk(1, 0, 1, 1)]);
+        assert_eq!(diff_lines(&["a", "b", "c"], &["a", "c"]), vec![hunk(1, 1, 1, 0)]);
+        assert_eq!(diff_lines(&["a", "b", "c"], &["a", "x", "c"]), vec![hunk(1, 1, 1, 1)]);
+        assert_eq!(diff_lines(&[], &["a"]), vec![hunk(0, 0, 0, 1)]);
+    }
+
+    #[test]
+    fn test_diff_multiple_hunks() {
+        let old = ["fn main() {", "    a();", "    b();", "    c();", "}"];
+        let new = ["fn main() {", "    a();", "    B();", "    c();", "    d();", "}"];
+        let hunks = diff_lines(&old, &new);
+        assert_eq!(hunks, vec![hunk(2, 1, 2, 1), hunk(4, 0, 4, 1)]);
+        assert_eq!(hunks[0].kind(), ChangeKind::Modified);
+        assert_eq!(hunks[1].kind(), ChangeKind::Added);
+    }
+
+    #[test]
+    fn test_diff_view_alignment() {
+        let current = ["a", "b", "c", "d"];
+        let other: Vec<String> = ["a", "x", "y", "c", "e"].iter().map(|s| s.to_string()).collect();
+        let view = DiffView::new(PathBuf::from("other"), other, &current);
+
+        assert_eq!(view.other_line(0), 0);
+        assert_eq!(view.other_line(1), 1);
+        assert_eq!(view.other_line(2), 3);
+        assert_eq!(view.current_change(1), Some(ChangeKind::Modified));
+        assert_eq!(view.current_change(2), None);
+        assert_eq!(view.other_change(2), Some(ChangeKind::Modified));
+        assert_eq!(view.other_change(3), None);
+
+        let view = DiffView::new(PathBuf::from("other"), vec!["a".to_string()], &["a", "b"]);
+        assert_eq!(view.current_change(1), Some(ChangeKind::Removed));
+        assert_eq!(view.other_line(1), 1);
+
+        let view = DiffView::new(PathBuf::from("other"), vec!["a".to_string(), "b".to_string()], &["a"]);
+        assert_eq!(view.other_change(1), Some(ChangeKind::Added));
+    }
+
+    #[test]
+    fn test_diff_completely_different() {
+        let hunks = diff_lines(&["a", "b"], &["x", "y", "z"]);
+        assert_eq!(hunks, vec![hunk(0, 2, 0, 3)]);
+    }
+}
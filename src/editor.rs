@@ -1,16 +1,56 @@
 //! Main Editor struct that coordinates all components
 
+use crate::bookmark::Bookmarks;
 use crate::buffer::Buffer;
-use crate::cursor::Cursor;
+use crate::config::{Config, PROJECT_CONFIG};
+use crate::charinfo;
+use crate::clipboard::{self, Clipboard};
+use crate::complete::{self, Completion, CompletionKind};
+use crate::cursor::{Cursor, CursorSet};
+use crate::decoration::Decoration;
+use crate::diff::{DiffView, Hunk};
+use crate::events::Waker;
+use crate::explorer::Explorer;
+use crate::git::{self, BlameInfo};
+use crate::increment::{self, NrFormats};
+use crate::jobs::Job;
+use crate::lua::{self, LuaEngine};
+use crate::quickfix::{ErrorFormat, QuickfixEntry, QuickfixList, DEFAULT_ERRORFORMAT};
+use crate::table;
+use crate::tags::TagFile;
+use crate::template::{self, TemplateVars};
+use crate::terminal::{pipe_to_command, Terminal};
+use crate::textobject::{self, Scope, TextObject};
+use crate::theme::Theme;
+use crate::picker::{Picker, PickerItem, PickerKind, PickerTarget};
+use crate::prompt::{Answer, Prompt, SAVE_DISCARD_CANCEL, YES_NO};
+use crate::recent::RecentFiles;
+use crate::trust::{Trust, TrustStore};
+use crate::mark::{self, Marks};
+use crate::shada::{add_history, ShaDa};
+use crate::keymap::{parse_key_sequence, Action, ActionContext, KeyCombo, KeyMatch, Keymap};
 use crate::mode::Mode;
+use crate::operator::{self, Operator};
 use crate::register::{Registers, RegisterContent};
-use crate::search::{Search, SearchDirection};
+use crate::remote::{RemotePath, Transfer};
+use crate::search::{self, Search, SearchDirection, SearchMatch, SearchOffset};
 use crate::selection::{Selection, VisualMode};
+use crate::substitute::{self, SubstituteFlags};
+use crate::format;
+use crate::help::{self, HelpPage};
+use crate::indent;
 use crate::syntax::Highlighter;
 use crate::gpu_info::GpuInfo;
-use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use regex;
+use ratatui::style::{Color, Modifier, Style};
+use crate::perf::{FrameProfiler, FrameStats, PerfTimer, Phase};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// The main editor state
 pub struct Editor {
@@ -18,6 +58,8 @@ pub struct Editor {
     buffer: Buffer,
     /// Cursor position
     cursor: Cursor,
+    /// Extra cursors added with Ctrl-N; edits apply at all of them
+    cursors: CursorSet,
     /// Current editing mode
     mode: Mode,
     /// Vertical scroll offset
@@ -34,24 +76,435 @@ pub struct Editor {
     search: Search,
     /// Vim-style registers for yank/paste
     registers: Registers,
-    /// Pending operator (for commands like dd, yy)
+    /// System clipboard behind the + and * registers
+    clipboard: Clipboard,
+    /// Pending command waiting for its next key (r, f, m, a register)
     pending_op: Option<char>,
+    /// Operator waiting for its target (a motion, a text object or its own
+    /// key again), with the count and register typed before it
+    operator: Option<(Operator, ActionContext)>,
+    /// Text object waiting for its kind after i or a: for the pending
+    /// operator (diw) or to select in visual mode (viw)
+    text_object: Option<Scope>,
     /// Current selection (for visual mode)
     selection: Option<Selection>,
+    /// The last visual selection, for '<,'> and \%V
+    last_visual: Option<Selection>,
     /// Last find character and direction (for f, F, ; commands)
     last_find: Option<(char, bool)>,  // (char, forward)
     /// Numeric prefix for commands (e.g., 5j, 3w)
     numeric_prefix: String,
+    /// Register selected with `"x` for the next yank, delete or paste
+    pending_register: Option<char>,
     /// Pending 'g' command (for gg, gt, gT)
     pending_g: bool,
+    /// CTRL-X was typed in insert mode; the next key picks a completion
+    pending_ctrl_x: bool,
+    /// Insert mode completion in progress (CTRL-X CTRL-F, CTRL-X CTRL-L)
+    completion: Option<Completion>,
     /// Buffer manager for multiple buffers
     buffer_manager: crate::buffer_manager::BufferManager,
     /// GPU info provider
     gpu_info: GpuInfo,
     /// Editor configuration
-    config: crate::config::Config,
+    config: Config,
+    /// Key bindings consulted before the built-in commands
+    keymap: Keymap,
+    /// Keys typed so far that form a prefix of a mapped sequence
+    pending_keys: Vec<KeyEvent>,
+    /// When the last pending key arrived (for the sequence timeout)
+    pending_keys_since: Option<Instant>,
+    /// Keys of the command being typed (`"a2d`), shown in the corner of
+    /// the command line until it runs
+    typed_keys: String,
+    /// The message is in the log already (errors, command output)
+    message_logged: bool,
+    /// Register and keys of the macro being recorded (q{register})
+    recording: Option<(char, Vec<KeyCombo>)>,
+    /// Registers recorded into this session, for :macros
+    recorded_macros: Vec<char>,
+    /// Register of the last macro run, for @@
+    last_macro: Option<char>,
+    /// Macros running inside each other right now
+    macro_depth: usize,
+    /// An error stopped the running macros, like Vim's
+    macro_failed: bool,
+    /// File tree sidebar, shown while Some
+    explorer: Option<Explorer>,
+    /// Fuzzy finder overlay, shown while Some
+    picker: Option<Picker>,
+    /// Recently opened files, persisted across sessions
+    recent_files: RecentFiles,
+    /// Lines of the current file as staged in git (None if untracked)
+    git_base: Option<Vec<String>>,
+    /// Changes between `git_base` and the buffer, for gutter signs
+    git_hunks: Vec<Hunk>,
+    /// When the buffer was last edited without refreshing `git_hunks`
+    /// and the diff view
+    edited_since: Option<Instant>,
+    /// Side-by-side diff against another file (:diffsplit)
+    diff: Option<DiffView>,
+    /// Whether blame for the cursor line is shown (:Gblame)
+    show_blame: bool,
+    /// Blame for a buffer line, refreshed as the cursor moves
+    blame: Option<(usize, BlameInfo)>,
+    /// Locations jumped away from with Ctrl-], popped by Ctrl-T
+    tag_stack: Vec<TagLocation>,
+    /// Buffer, cursor and scroll position to go back to when help closes
+    help_return: Option<(Buffer, Cursor, usize)>,
+    /// Embedded terminal pane (:terminal)
+    terminal: Option<Terminal>,
+    /// Locations from the last :make or :Run
+    quickfix: QuickfixList,
+    /// Background command started with :Run
+    job: Option<Job>,
+    /// Output of the last :Run, shown in the "[Run]" scratch buffer
+    job_output: String,
+    /// Download or upload of an scp:// file, with the buffer revision an
+    /// upload saves
+    transfer: Option<(Transfer, u64)>,
+    /// Quit once the upload finishes (:wq on a remote file)
+    quit_after_transfer: bool,
+    /// Large file being read on a worker thread, opened when it is in
+    loading: Option<(String, Receiver<crate::error::Result<Buffer>>)>,
+    /// Wakes the main loop when background work has results
+    waker: Waker,
+    /// The real buffer while a :s preview is shown in its place
+    substitute_preview: Option<Buffer>,
+    /// Keys typed in terminal mode that may start the escape sequence
+    terminal_pending: Vec<KeyCombo>,
+    /// Layout to restore when leaving :zen, Some while it is on
+    zen: Option<ZenRestore>,
+    /// Scroll jump being animated, with smooth_scroll on
+    scroll_animation: Option<ScrollAnimation>,
+    /// Invisible characters in the buffer, with the revision counted at
+    invisible_count: Cell<(u64, usize)>,
+    /// Bumped by work that changes the screen without touching the buffer
+    /// text or cursor: ex commands and git sign and blame refreshes
+    changes: u64,
+    /// Marks set with `m`
+    marks: Marks,
+    /// The user's config with the options changed by :set, which a
+    /// project's .quirks.toml is applied over
+    user_config: Config,
+    /// Project root whose .quirks.toml was last looked for
+    project_root_checked: Option<PathBuf>,
+    /// The .quirks.toml applied over the user's config, if any
+    project_config: Option<PathBuf>,
+    /// Project config files the user allowed or denied
+    trust: TrustStore,
+    /// Bookmarks of the current project (:bookmark)
+    bookmarks: Bookmarks,
+    /// Gutter signs of the current file's bookmarks: decoration id and
+    /// bookmark index, to see where edits moved them
+    bookmark_signs: Vec<(u64, usize)>,
+    /// Ex commands run, oldest first
+    command_history: Vec<String>,
+    /// Search patterns used, oldest first
+    search_history: Vec<String>,
+    /// History entry shown on the command line while recalling with Up/Down
+    history_index: Option<usize>,
+    /// State file registers, marks and histories are saved to on exit
+    shada: ShaDa,
+    /// The :sysinfo popup is open
+    show_sysinfo: bool,
+    /// Question waiting for an answer, and what answering it does
+    prompt: Option<(Prompt, PromptAction)>,
+    /// Frame timings, while :profile is on
+    profiler: RefCell<Option<FrameProfiler>>,
+    /// Visual block change in progress, copied to the other lines on Esc
+    block_insert: Option<BlockInsert>,
+    /// Insert entered with a count (3o, 5i), repeated on Esc
+    insert_repeat: Option<InsertRepeat>,
+    /// Operator waiting for the search being typed (d/pat), with its
+    /// count and register
+    search_operator: Option<(Operator, ActionContext)>,
+    /// Pattern, replacement and flags of the last :s, repeated by & and :&&
+    last_substitute: Option<(String, String, SubstituteFlags)>,
+    /// Lua state for init.lua and plugins; None if it could not be set up.
+    /// Shared so Lua can run ex commands that run Lua again.
+    lua: Option<Rc<LuaEngine>>,
+    /// Lua ran an ex command that quits
+    lua_quit: bool,
+    /// Popups opened with quirks.popup and their ids, the last one on top
+    popups: Vec<(usize, lua::Popup)>,
+    next_popup_id: usize,
 }
 
+/// The state a frame was drawn from. The main loop compares it between
+/// events and skips drawing when nothing visible changed, e.g. for the
+/// first key of an operator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderState {
+    revision: u64,
+    modified: bool,
+    cursor: (usize, usize),
+    cursors: Vec<(usize, usize)>,
+    scroll: usize,
+    mode: Mode,
+    message: Option<String>,
+    command: String,
+    pending: (Option<char>, Option<Operator>, String, Option<char>, bool, usize),
+    key_hints: bool,
+    recording: Option<char>,
+    selection: Option<Selection>,
+    search: (String, usize, bool),
+    quickfix: usize,
+    job_secs: Option<u64>,
+    history: Option<String>,
+    completion: Option<Option<usize>>,
+    changes: u64,
+    sysinfo: bool,
+    prompt: Option<String>,
+    popups: Vec<(usize, lua::Popup)>,
+    /// A picker, the explorer, a terminal or a Lua statusline is shown;
+    /// their contents are not tracked, so they are drawn on every event
+    live: bool,
+}
+
+impl RenderState {
+    /// Whether a frame drawn from `self` could differ from `drawn`
+    pub fn needs_redraw(&self, drawn: Option<&RenderState>) -> bool {
+        self.live || drawn != Some(self)
+    }
+}
+
+/// An animated move of the viewport from one scroll offset to another
+#[derive(Debug, Clone, Copy)]
+struct ScrollAnimation {
+    from: usize,
+    to: usize,
+    started: Instant,
+}
+
+impl ScrollAnimation {
+    /// Offset to draw at, easing out towards the target
+    fn offset(&self) -> usize {
+        let t = (self.started.elapsed().as_secs_f64() / SCROLL_DURATION.as_secs_f64()).min(1.0);
+        let eased = 1.0 - (1.0 - t).powi(3);
+        let (from, to) = (self.from as f64, self.to as f64);
+        (from + (to - from) * eased).round() as usize
+    }
+
+    fn is_done(&self) -> bool {
+        self.started.elapsed() >= SCROLL_DURATION
+    }
+}
+
+/// Options changed by :zen, restored when it is toggled off
+#[derive(Debug, Clone, Copy)]
+struct ZenRestore {
+    line_numbers: bool,
+    wrap: bool,
+}
+
+/// A position to return to from a tag jump
+#[derive(Debug, Clone)]
+struct TagLocation {
+    file: Option<PathBuf>,
+    line: usize,
+    col: usize,
+}
+
+/// Swap the case of every letter, for `~` on a selection
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_uppercase() { c.to_lowercase().to_string() } else { c.to_uppercase().to_string() })
+        .collect()
+}
+
+/// A visual selection of what a text object covers; None if it is empty
+fn object_selection(object: TextObject) -> Option<Selection> {
+    let (mode, start, end) = match object {
+        TextObject::Chars { start, end } => (VisualMode::Char, start, end),
+        TextObject::Lines { start, end } => (VisualMode::Line, (start, 0), (end, 0)),
+        TextObject::Empty { .. } => return None,
+    };
+    let mut selection = Selection::new(mode, start.0, start.1);
+    selection.update_cursor(end.0, end.1);
+    Some(selection)
+}
+
+/// Message after a :s: `3 substitutions on 2 lines`
+fn substitution_summary(count: usize, lines: usize) -> String {
+    format!(
+        "{} substitution{} on {} line{}",
+        count,
+        if count == 1 { "" } else { "s" },
+        lines,
+        if lines == 1 { "" } else { "s" },
+    )
+}
+
+/// Where to look for the next match after one ending at byte `end` of
+/// `text`: one character on after an empty match, so it is not found again
+fn next_byte(text: &str, end: usize, empty: bool) -> usize {
+    if !empty {
+        return end;
+    }
+    text[end..].chars().next().map_or(end + 1, |ch| end + ch.len_utf8())
+}
+
+/// Path of a buffer for messages, or [No Name]
+fn buffer_label(buffer: &Buffer) -> String {
+    buffer.file_path().map_or_else(|| "[No Name]".to_string(), |path| path.display().to_string())
+}
+
+/// Lines, words, characters and bytes in `text`
+fn text_counts(text: &str) -> (usize, usize, usize, usize) {
+    (text.lines().count(), text.split_whitespace().count(), text.chars().count(), text.len())
+}
+
+/// What follows the ex command `names` (longest first) in `cmd`, or None
+/// if it is some other command: `ex_argument("m0", MOVE)` is "0" but
+/// `:marks` and `:make` give None
+fn ex_argument<'a>(cmd: &'a str, names: &[&str]) -> Option<&'a str> {
+    names
+        .iter()
+        .find_map(|name| cmd.strip_prefix(name))
+        .filter(|rest| !rest.starts_with(char::is_alphabetic))
+}
+
+/// Names and abbreviations of the ex commands that take a range and an
+/// argument without a space
+const MOVE: &[&str] = &["move", "mo", "m"];
+const DELETE: &[&str] = &["delete", "delet", "dele", "del", "de", "d"];
+const YANK: &[&str] = &["yank", "yan", "ya", "y"];
+const PUT: &[&str] = &["put", "pu"];
+const WRITE: &[&str] = &["write", "writ", "wri", "wr", "w"];
+
+/// Text typed at `col` of `line` after a visual block `c`, to be repeated
+/// on `lines` when insert mode ends
+#[derive(Debug, Clone)]
+struct BlockInsert {
+    line: usize,
+    col: usize,
+    lines: RangeInclusive<usize>,
+}
+
+/// What answering the open prompt does
+#[derive(Debug)]
+enum PromptAction {
+    /// Run an ex command on `y`
+    Command(String),
+    /// Run an ex command with the text typed as its argument
+    CommandWithText(String),
+    /// Quit, saving or discarding the files listed
+    Quit,
+    /// Close the current buffer, saving or discarding its changes
+    CloseBuffer,
+    /// Replace the match under the cursor for a :s with the c flag
+    Substitute(Box<SubstituteConfirm>),
+}
+
+/// A :s with the c flag stepping through its matches: the next one is
+/// searched for from byte `byte` of `line`
+#[derive(Debug)]
+struct SubstituteConfirm {
+    regex: regex::Regex,
+    /// In the regex crate's syntax
+    replacement: String,
+    global: bool,
+    line: usize,
+    byte: usize,
+    end: usize,
+    count: usize,
+    lines: usize,
+    /// Last line a replacement was made on, to count the lines changed
+    last_line: Option<usize>,
+    /// Some match was found, whether or not it was replaced
+    found: bool,
+}
+
+/// Choices for each match of a :s with the c flag
+const SUBSTITUTE_CHOICES: &[&str] = &["yes", "no", "all", "quit", "last"];
+
+/// An insert session to repeat when it ends: the text typed from byte
+/// `start` is inserted `count - 1` more times, on lines of its own for
+/// `o` and `O`
+#[derive(Debug, Clone, Copy)]
+struct InsertRepeat {
+    start: usize,
+    count: usize,
+    new_line: bool,
+}
+
+/// How long the editor waits after the last edit before idle work runs
+const IDLE_DELAY: Duration = Duration::from_millis(300);
+
+/// How often the screen is redrawn for terminal output
+const TERMINAL_POLL: Duration = Duration::from_millis(30);
+
+/// Files at least this large are read on a worker thread by `load_file`
+const BACKGROUND_LOAD_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Refusal to :w a read-only buffer
+const READONLY_ERROR: &str = "'readonly' is set (add ! to override)";
+
+/// How often a background job is checked for output
+const JOB_POLL: Duration = Duration::from_millis(100);
+
+/// How long a smooth scroll takes
+const SCROLL_DURATION: Duration = Duration::from_millis(150);
+
+/// Time between frames of a smooth scroll
+const SCROLL_FRAME: Duration = Duration::from_millis(16);
+
+/// Scroll jumps of more lines than this are animated
+const SCROLL_ANIMATE_MIN: usize = 4;
+
+/// Buffers with more lines than this are searched on a worker thread while
+/// the pattern is typed
+const BACKGROUND_SEARCH_LINES: usize = 20_000;
+
+/// How often to collect matches from a background search
+const SEARCH_POLL: Duration = Duration::from_millis(50);
+
+/// Decoration namespace for :Gblame virtual text
+const BLAME_NAMESPACE: &str = "blame";
+
+/// Decoration namespace for bookmark signs in the gutter
+const BOOKMARK_NAMESPACE: &str = "bookmarks";
+
+/// Name of the scratch buffer showing :Run output
+const RUN_BUFFER: &str = "[Run]";
+
+/// Name of the scratch buffer :messages shows the log in
+const MESSAGES_BUFFER: &str = "[Messages]";
+
+/// Name of the scratch buffer :map lists the key bindings in
+const MAPPINGS_BUFFER: &str = "[Mappings]";
+
+/// :map commands, the modes they work on and whether they remove bindings
+const MAP_COMMANDS: &[(&str, &[Mode], bool)] = &[
+    ("map", &[Mode::Normal, Mode::Visual], false),
+    ("nmap", &[Mode::Normal], false),
+    ("imap", &[Mode::Insert], false),
+    ("vmap", &[Mode::Visual], false),
+    ("unmap", &[Mode::Normal, Mode::Visual], true),
+    ("nunmap", &[Mode::Normal], true),
+    ("iunmap", &[Mode::Insert], true),
+    ("vunmap", &[Mode::Visual], true),
+];
+
+/// Characters of a pending command shown in the corner of the command line
+const SHOWCMD_WIDTH: usize = 10;
+
+/// Scratch buffer listing the recorded macros
+const MACROS_BUFFER: &str = "[Macros]";
+
+/// How deep macros may run each other (a macro calling itself stops here)
+const MAX_MACRO_DEPTH: usize = 100;
+
+/// Characters of the unnamed register shown by Ctrl-G and the status line
+const REGISTER_PREVIEW: usize = 20;
+
+/// Decoration namespace for the replacements in a :s preview
+const PREVIEW_NAMESPACE: &str = "inccommand";
+
+/// Name of the scratch buffer :help shows
+const HELP_BUFFER: &str = "[Help]";
+
 impl Default for Editor {
     fn default() -> Self {
         Self::new()
@@ -59,1408 +512,5722 @@ impl Default for Editor {
 }
 
 impl Editor {
+    /// Editor with the user's ~/.quirksrc and the state saved by the last
+    /// session
     pub fn new() -> Self {
+        let mut editor = Self::with_state(
+            Config::load(),
+            RecentFiles::load(),
+            ShaDa::load(),
+            Bookmarks::load(),
+            TrustStore::load(),
+            Clipboard::detect(),
+        );
+        // ~/.config/quirks/init.lua, after the config so it can change it
+        if let Some(init) = lua::config_dir().map(|dir| dir.join("init.lua")).filter(|path| path.exists()) {
+            editor.source_lua(&init);
+        }
+        editor
+    }
+
+    /// Editor that reads and writes no user files, for embedding and tests
+    pub fn headless(config: Config) -> Self {
+        Self::with_state(
+            config,
+            RecentFiles::default(),
+            ShaDa::default(),
+            Bookmarks::default(),
+            TrustStore::default(),
+            Clipboard::default(),
+        )
+    }
+
+    fn with_state(
+        config: Config,
+        recent_files: RecentFiles,
+        mut shada: ShaDa,
+        bookmarks: Bookmarks,
+        trust: TrustStore,
+        clipboard: Clipboard,
+    ) -> Self {
+        let mut keymap = Keymap::default();
+        let keymap_errors = keymap.apply_config(&config);
+        let message = if keymap_errors.is_empty() {
+            None
+        } else {
+            for error in &keymap_errors {
+                crate::log::warn(format!("Keymap config: {}", error));
+            }
+            Some(format!("Keymap config: {}", keymap_errors.join("; ")))
+        };
+
+        let user_config = config.clone();
+        let mut registers = Registers::new();
+        for (name, content) in std::mem::take(&mut shada.registers) {
+            registers.restore(name, content);
+        }
+        let mut marks = Marks::default();
+        for (name, mark) in std::mem::take(&mut shada.marks) {
+            marks.set(name, mark.file.as_deref(), mark.line, mark.col);
+        }
+
         Self {
             buffer: Buffer::new(),
             cursor: Cursor::new(),
+            cursors: CursorSet::default(),
             mode: Mode::Normal,
             scroll_offset: 0,
             command_buffer: String::new(),
-            message: None,
+            message,
             viewport_height: 24, // Default, updated on resize
             highlighter: Highlighter::new(),
             search: Search::new(),
-            registers: Registers::new(),
+            registers,
+            clipboard,
             pending_op: None,
+            operator: None,
+            text_object: None,
             selection: None,
+            last_visual: None,
             last_find: None,
             numeric_prefix: String::new(),
+            pending_register: None,
             pending_g: false,
+            pending_ctrl_x: false,
+            completion: None,
             buffer_manager: crate::buffer_manager::BufferManager::new(),
             gpu_info: GpuInfo::new(),
-            config: crate::config::Config::load(),
+            config,
+            keymap,
+            pending_keys: Vec::new(),
+            pending_keys_since: None,
+            typed_keys: String::new(),
+            message_logged: false,
+            recording: None,
+            recorded_macros: Vec::new(),
+            last_macro: None,
+            macro_depth: 0,
+            macro_failed: false,
+            explorer: None,
+            picker: None,
+            recent_files,
+            git_base: None,
+            git_hunks: Vec::new(),
+            edited_since: None,
+            diff: None,
+            show_blame: false,
+            blame: None,
+            tag_stack: Vec::new(),
+            help_return: None,
+            terminal: None,
+            quickfix: QuickfixList::default(),
+            job: None,
+            job_output: String::new(),
+            transfer: None,
+            quit_after_transfer: false,
+            loading: None,
+            waker: Waker::default(),
+            substitute_preview: None,
+            terminal_pending: Vec::new(),
+            zen: None,
+            scroll_animation: None,
+            invisible_count: Cell::new((0, 0)),
+            changes: 0,
+            marks,
+            user_config,
+            project_root_checked: None,
+            project_config: None,
+            trust,
+            bookmarks,
+            bookmark_signs: Vec::new(),
+            command_history: std::mem::take(&mut shada.command_history),
+            search_history: std::mem::take(&mut shada.search_history),
+            history_index: None,
+            shada,
+            show_sysinfo: false,
+            prompt: None,
+            profiler: RefCell::new(None),
+            block_insert: None,
+            insert_repeat: None,
+            search_operator: None,
+            last_substitute: None,
+            lua: LuaEngine::new()
+                .map(Rc::new)
+                .map_err(|e| crate::log::warn(format!("Lua unavailable: {}", e)))
+                .ok(),
+            lua_quit: false,
+            popups: Vec::new(),
+            next_popup_id: 0,
         }
     }
 
     /// Get the editor configuration
-    pub fn config(&self) -> &crate::config::Config {
+    pub fn config(&self) -> &Config {
         &self.config
     }
 
+    /// :set an option, keeping the change when another project's
+    /// .quirks.toml replaces the current one
+    fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        self.config.set_option(name, value)?;
+        self.user_config.set_option(name, value)
+    }
+
+    /// When the current file belongs to another project than before,
+    /// apply that project's .quirks.toml over the user's config in place
+    /// of the last one. A file not trusted yet is only applied after
+    /// asking (see :trust).
+    fn load_project_config(&mut self) {
+        if self.buffer.file_path().is_none() || self.remote_path().is_some() {
+            return;
+        }
+        let root = self.project_root();
+        if self.project_root_checked.as_ref() == Some(&root) {
+            return;
+        }
+        self.project_root_checked = Some(root.clone());
+        if self.project_config.take().is_some() {
+            self.config = self.user_config.clone();
+        }
+        let path = root.join(PROJECT_CONFIG);
+        let Ok(content) = std::fs::read_to_string(&path) else { return };
+        match self.trust.check(&path, &content) {
+            Trust::Allowed => {
+                self.config.merge(&content);
+                self.project_config = Some(path);
+            }
+            Trust::Denied => {}
+            Trust::Unknown => self.confirm(
+                format!("Trust {}? It can set the shell and build commands.", path.display()),
+                "trust".to_string(),
+            ),
+        }
+    }
+
+    /// :trust the current project's .quirks.toml and apply it; with
+    /// `++deny` never read it, with `++remove` ask about it again
+    fn trust_project_config(&mut self, arg: &str) {
+        let root = self.project_root();
+        let path = root.join(PROJECT_CONFIG);
+        self.project_root_checked = Some(root);
+        if arg == "++remove" {
+            self.message = Some(if self.trust.remove(&path) {
+                format!("Forgot {}", path.display())
+            } else {
+                format!("{} was neither trusted nor denied", path.display())
+            });
+        } else {
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                self.message = Some(format!("No {} in the project root", PROJECT_CONFIG));
+                return;
+            };
+            if self.project_config.take().is_some() {
+                self.config = self.user_config.clone();
+            }
+            match arg {
+                "" => {
+                    self.trust.set(&path, &content, true);
+                    self.config.merge(&content);
+                    self.message = Some(format!("Loaded {}", path.display()));
+                    self.project_config = Some(path);
+                }
+                "++deny" => {
+                    self.trust.set(&path, &content, false);
+                    self.message = Some(format!("Denied {}", path.display()));
+                }
+                _ => {
+                    self.message = Some("Usage: :trust [++deny | ++remove]".to_string());
+                    return;
+                }
+            }
+        }
+        if let Err(e) = self.trust.save() {
+            self.report_error(format!("Error saving trust: {}", e));
+        }
+    }
+
+    /// Get the active keymap
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Get the active keymap for remapping
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+
+    /// Run `f` with the Lua engine and the editor handed over to it; None
+    /// without Lua
+    fn with_lua<R>(&mut self, f: impl FnOnce(&LuaEngine, &mut Self) -> R) -> Option<R> {
+        let engine = self.lua.clone()?;
+        Some(f(&engine, self))
+    }
+
+    /// Run a Lua file (init.lua, :luafile)
+    pub fn source_lua(&mut self, path: &Path) {
+        match self.with_lua(|lua, editor| lua.run_file(editor, path)) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => self.report_error(format!("Lua: {}", e)),
+            None => self.report_error("Lua is not available"),
+        }
+    }
+
+    /// Run Lua typed at :lua ({code} or ={expr}). Returns true to quit.
+    fn run_lua(&mut self, code: &str) -> bool {
+        if code.is_empty() {
+            self.report_error("Argument required".to_string());
+            return false;
+        }
+        match self.with_lua(|lua, editor| lua.run_line(editor, code)) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => self.report_error(format!("Lua: {}", e)),
+            None => self.report_error("Lua is not available"),
+        }
+        std::mem::take(&mut self.lua_quit)
+    }
+
+    /// Run a command defined with quirks.command. Returns true to quit.
+    fn run_lua_command(&mut self, cmd: &str) -> bool {
+        let (name, bang, args) = lua::split_command(cmd);
+        if let Some(Err(e)) = self.with_lua(|lua, editor| lua.run_command(editor, name, args, bang)) {
+            self.report_error(format!("{}: {}", name, e));
+        }
+        std::mem::take(&mut self.lua_quit)
+    }
+
+    /// Call the Lua function mapped to a key. Returns true to quit.
+    fn run_lua_keymap(&mut self, id: usize) -> bool {
+        if let Some(Err(e)) = self.with_lua(|lua, editor| lua.run_keymap(editor, id)) {
+            self.report_error(format!("Lua: {}", e));
+        }
+        std::mem::take(&mut self.lua_quit)
+    }
+
+    /// Run the Lua autocommands for `event` on `target`: the file, or
+    /// "old:new" for ModeChanged
+    fn fire_autocmd(&mut self, event: lua::Event, target: &str) {
+        if !self.lua.as_ref().is_some_and(|lua| lua.has_autocmd(event)) {
+            return;
+        }
+        let errors = self.with_lua(|lua, editor| lua.fire(editor, event, target)).unwrap_or_default();
+        if let Some(error) = errors.last() {
+            self.report_error(format!("{} autocommand: {}", event.name(), error));
+        }
+    }
+
+    /// Run the Lua autocommands for `event` on the current file
+    fn fire_buffer_autocmd(&mut self, event: lua::Event) {
+        let file = self.buffer.file_path().map(|path| path.display().to_string()).unwrap_or_default();
+        self.fire_autocmd(event, &file);
+    }
+
     /// Open a file in the editor
-    pub fn open_file(&mut self, path: &str) -> Result<()> {
+    pub fn open_file(&mut self, path: &str) -> crate::error::Result<()> {
+        if let Some(remote) = RemotePath::parse(path) {
+            let transfer = Transfer::download(remote, self.waker.clone())?;
+            self.start_transfer(transfer, 0);
+            return Ok(());
+        }
+
+        // A directory opens in the file explorer, to pick a file from
+        if Path::new(path).is_dir() {
+            self.explorer = Some(Explorer::new(path));
+            self.mode = Mode::Explorer;
+            self.message = Some(format!("Directory: {}", path));
+            return Ok(());
+        }
+
         // Open file via buffer manager
+        let is_new = !Path::new(path).exists();
+        self.leave_buffer();
         self.buffer_manager.open_file(path)?;
+        let template = if is_new && self.config.templates {
+            self.fill_from_template(path)
+        } else {
+            None
+        };
+        let syntax_info = self.show_opened_file(path);
+        self.message = Some(match template {
+            Some(template) => format!("New file: {}{} from {}", path, syntax_info, template),
+            None if is_new => format!("New file: {}{}", path, syntax_info),
+            None => format!("Opened: {}{}", path, syntax_info),
+        });
+        self.load_project_config();
+        self.fire_buffer_autocmd(lua::Event::BufRead);
+        Ok(())
+    }
+
+    /// Open a file the user picked (:e, the finder, the explorer). Like
+    /// `open_file`, but when a main loop is listening a large file is read
+    /// on a worker thread and shown once it is in, so nothing may count on
+    /// the file being open when this returns.
+    pub fn load_file(&mut self, path: &str) -> crate::error::Result<()> {
+        let large = std::fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.len() >= BACKGROUND_LOAD_BYTES);
+        if !large || !self.waker.is_connected() {
+            return self.open_file(path);
+        }
+        if let Some((loading, _)) = &self.loading {
+            self.message = Some(format!("Busy loading {}", loading));
+            return Ok(());
+        }
+        let (tx, rx) = mpsc::channel();
+        let (file, waker) = (path.to_string(), self.waker.clone());
+        std::thread::spawn(move || {
+            let _ = tx.send(Buffer::from_file(&file));
+            waker.wake();
+        });
+        self.loading = Some((path.to_string(), rx));
+        self.message = Some(format!("Loading {}...", path));
+        Ok(())
+    }
+
+    /// Open the file `load_file` read on a worker thread, once it is in
+    fn poll_loading(&mut self) {
+        let Some((path, loaded)) = &self.loading else { return };
+        let Ok(result) = loaded.try_recv() else { return };
+        let path = path.clone();
+        self.loading = None;
+        self.changes += 1;
+        match result {
+            Ok(buffer) => {
+                self.leave_buffer();
+                self.buffer_manager.add(buffer);
+                let syntax_info = self.show_opened_file(&path);
+                self.message = Some(format!("Opened: {}{}", path, syntax_info));
+                self.load_project_config();
+                self.fire_buffer_autocmd(lua::Event::BufRead);
+            }
+            Err(e) => self.report_error(format!("Error opening {}", e)),
+        }
+    }
+
+    /// Show the buffer manager's current buffer, just opened from `path`,
+    /// where the cursor was left in it. Returns the syntax name for
+    /// messages (" [Rust]").
+    fn show_opened_file(&mut self, path: &str) -> String {
         self.buffer = self.buffer_manager.current_buffer().clone();
-        self.cursor = Cursor::new();
+        self.reset_cursor();
         self.scroll_offset = 0;
-        
-        // Set syntax highlighting based on file extension
-        if let Some(ext) = std::path::Path::new(path).extension().and_then(|e| e.to_str()) {
-            self.highlighter.set_syntax_for_extension(ext);
+        self.restore_position();
+        self.show_bookmarks();
+
+        // Remembering the file is best-effort; a read-only state dir is fine
+        self.recent_files.add(std::path::Path::new(path));
+        let _ = self.recent_files.save();
+        self.refresh_git_base();
+        self.detect_syntax()
+    }
+
+    /// Wake `waker`'s main loop when background work has results, instead
+    /// of polling for them
+    pub fn set_waker(&mut self, waker: Waker) {
+        self.waker = waker;
+    }
+
+    /// Set syntax highlighting for the current buffer: its :set filetype,
+    /// or else from the file name, extension or `#!` line. Returns the
+    /// syntax name for messages (" [Rust]").
+    fn detect_syntax(&mut self) -> String {
+        match (self.buffer.filetype(), self.buffer.file_path()) {
+            (Some(filetype), _) => {
+                let filetype = filetype.to_string();
+                self.highlighter.set_syntax_by_name(&filetype);
+            }
+            (None, Some(path)) => {
+                let path = path.clone();
+                self.highlighter.set_syntax_for_file(&path, &self.buffer.line(0));
+            }
+            (None, None) => {
+                self.highlighter.set_syntax_by_name("off");
+            }
         }
-        
-        let syntax_info = self.highlighter.current_syntax_name()
+        self.highlighter.current_syntax_name()
             .map(|s| format!(" [{}]", s))
-            .unwrap_or_default();
-        self.message = Some(format!("Opened: {}{}", path, syntax_info));
-        Ok(())
+            .unwrap_or_default()
     }
 
-    /// Get a reference to the GPU info provider
-    pub fn gpu_info(&self) -> &GpuInfo {
-        &self.gpu_info
+    /// Run an scp:// transfer in the background; one at a time
+    fn start_transfer(&mut self, transfer: Transfer, revision: u64) {
+        if let Some((running, _)) = &self.transfer {
+            self.message = Some(format!("Busy with {}", running.remote()));
+            return;
+        }
+        self.message = Some(transfer.progress());
+        self.transfer = Some((transfer, revision));
     }
 
-    /// Handle a key event, returns true if editor should quit
-    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
-        self.message = None; // Clear message on keypress
-        
-        match self.mode {
-            Mode::Normal => self.handle_normal_mode(key),
-            Mode::Insert => self.handle_insert_mode(key),
-            Mode::Command => self.handle_command_mode(key),
-            Mode::Search => self.handle_search_mode(key),
-            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => self.handle_visual_mode(key),
-            Mode::Help => self.handle_help_mode(key),
+    /// Write the current buffer back to its scp:// URL
+    fn save_remote(&mut self, remote: RemotePath) {
+        match Transfer::upload(remote, self.buffer.content(), self.waker.clone()) {
+            Ok(transfer) => self.start_transfer(transfer, self.buffer.revision()),
+            Err(e) => self.report_error(format!("Error saving: {}", e)),
         }
     }
 
-    /// Handle keys in normal mode
-    fn handle_normal_mode(&mut self, key: KeyEvent) -> bool {
-        // Handle pending replace
-        if self.pending_op == Some('r') {
-            if let KeyCode::Char(c) = key.code {
-                if !key.modifiers.contains(KeyModifiers::CONTROL) {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    let pos = self.cursor.byte_offset(&self.buffer);
-                    // Delete current char and insert replacement
-                    let line_len = self.buffer.line_len(self.cursor.line);
-                    if self.cursor.col < line_len {
-                        self.buffer.delete(pos, pos + 1);
-                        self.buffer.insert(pos, &c.to_string());
-                    }
+    /// Remote location of the current buffer, if it came from an scp:// URL
+    fn remote_path(&self) -> Option<RemotePath> {
+        RemotePath::parse(&self.buffer.file_path()?.to_string_lossy())
+    }
+
+    /// Show the progress of an scp:// transfer, and open the file or mark
+    /// the buffer saved when it is done. Returns true to quit after :wq.
+    fn poll_transfer(&mut self) -> bool {
+        let Some((transfer, revision)) = &self.transfer else { return false };
+        let Some(result) = transfer.poll() else {
+            self.message = Some(transfer.progress());
+            return false;
+        };
+        let (remote, upload, revision) = (transfer.remote().clone(), transfer.is_upload(), *revision);
+        self.transfer = None;
+        let url = remote.to_string();
+        match result {
+            Err(e) => {
+                self.quit_after_transfer = false;
+                self.report_error(format!("{}: {}", url, e));
+            }
+            Ok(_) if upload => {
+                let current = self.buffer.file_path().is_some_and(|path| path.to_string_lossy() == url);
+                if current && self.buffer.revision() == revision {
+                    self.buffer.mark_saved();
                 }
+                self.message = Some(format!("Written: {}", url));
+                return std::mem::take(&mut self.quit_after_transfer);
+            }
+            Ok(text) => {
+                self.buffer_manager.add(Buffer::from_remote(&url, &text));
+                self.buffer = self.buffer_manager.current_buffer().clone();
+                self.reset_cursor();
+                self.scroll_offset = 0;
+                self.git_base = None;
+                self.git_hunks.clear();
+                let syntax_info = self.detect_syntax();
+                self.message = Some(format!("Opened: {}{}", url, syntax_info));
+                self.fire_buffer_autocmd(lua::Event::BufRead);
             }
-            self.pending_op = None;
-            return false;
         }
+        false
+    }
 
-        // Handle pending find (f/F)
-        if self.pending_op == Some('f') || self.pending_op == Some('F') {
-            if let KeyCode::Char(c) = key.code {
-                let forward = self.pending_op == Some('f');
-                self.last_find = Some((c, forward));
-                self.find_char_on_line(c, forward);
-            }
-            self.pending_op = None;
-            return false;
+    /// Fill the new, empty current buffer from the template for `path`,
+    /// returning the template's file name
+    fn fill_from_template(&mut self, path: &str) -> Option<String> {
+        let path = Path::new(path);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let vars = TemplateVars {
+            filename: path.file_name()?.to_string_lossy().into_owned(),
+            name: path.file_stem()?.to_string_lossy().into_owned(),
+            date: git::format_date(now as i64),
+            author: self.config.template_author.clone()
+                .or_else(|| git::user_name(path))
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_default(),
+        };
+        let (template, text) = template::load(&template::template_dir()?, path, &vars)?;
+        self.buffer_manager.current_buffer().insert(0, &text);
+        template.file_name().map(|name| name.to_string_lossy().into_owned())
+    }
+
+    /// Save registers, marks and histories for the next session
+    pub fn save_state(&mut self) -> std::io::Result<()> {
+        self.remember_position();
+        self.sync_bookmarks();
+        self.shada.registers = self.registers.entries()
+            .into_iter()
+            .map(|(name, content)| (name, content.clone()))
+            .collect();
+        self.shada.marks = self.marks.all()
+            .into_iter()
+            .map(|(name, mark)| (name, mark.clone()))
+            .collect();
+        self.shada.command_history = self.command_history.clone();
+        self.shada.search_history = self.search_history.clone();
+        self.shada.save()
+    }
+
+    /// Before switching away from the current buffer: keep its changes in
+    /// the buffer manager and remember where the cursor was
+    fn leave_buffer(&mut self) {
+        self.fire_buffer_autocmd(lua::Event::BufLeave);
+        self.sync_bookmarks();
+        self.store_buffer();
+        self.remember_position();
+    }
+
+    /// Copy the buffer being edited back into the buffer manager, unless
+    /// it is not one of the manager's (a scratch or unnamed buffer)
+    fn store_buffer(&mut self) {
+        if self.is_managed_buffer() {
+            *self.buffer_manager.current_buffer() = self.buffer.clone();
         }
+    }
 
-        match key.code {
-            // Numeric prefix (1-9, but skip 0 as it's line start)
-            KeyCode::Char(c @ '1'..='9') => {
-                self.numeric_prefix.push(c);
-                return false;
-            }
-            KeyCode::Char('0') if !self.numeric_prefix.is_empty() => {
-                self.numeric_prefix.push('0');
-                return false;
-            }
-            
-            // Movement
-            KeyCode::Char('h') | KeyCode::Left => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
-                };
-                for _ in 0..count {
-                    self.cursor.move_left(&self.buffer);
-                }
-                self.numeric_prefix.clear();
-            }
-            KeyCode::Char('j') | KeyCode::Down => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
-                };
-                for _ in 0..count {
-                    self.cursor.move_down(&self.buffer);
-                }
-                self.ensure_cursor_visible();
-                self.numeric_prefix.clear();
+    /// Whether the buffer being edited is the buffer manager's current
+    /// one, rather than a scratch or unnamed buffer shown in its place
+    fn is_managed_buffer(&mut self) -> bool {
+        self.buffer.file_path().is_some()
+            && self.buffer_manager.has_buffers()
+            && self.buffer_manager.current_buffer().file_path() == self.buffer.file_path()
+    }
+
+    /// Write every modified buffer (:wa), reporting each file that could
+    /// not be written. Returns true if all of them were.
+    fn write_all(&mut self) -> bool {
+        self.store_buffer();
+        let mut errors = Vec::new();
+        let mut written = 0;
+        for idx in 0..self.buffer_manager.buffer_count() {
+            let Some(buffer) = self.buffer_manager.get_mut(idx).filter(|buffer| buffer.is_modified()) else {
+                continue;
+            };
+            let name = buffer_label(buffer);
+            if RemotePath::parse(&name).is_some() {
+                errors.push(format!("{}: write scp:// files with :w", name));
+                continue;
+            }
+            if buffer.is_readonly() {
+                errors.push(format!("{}: 'readonly' is set", name));
+                continue;
+            }
+            match buffer.save() {
+                Ok(()) => written += 1,
+                Err(e) => errors.push(format!("{}: {}", name, e)),
             }
-            KeyCode::Char('k') | KeyCode::Up => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
-                };
-                for _ in 0..count {
-                    self.cursor.move_up(&self.buffer);
-                }
-                self.ensure_cursor_visible();
-                self.numeric_prefix.clear();
+        }
+        if self.is_managed_buffer() {
+            self.buffer = self.buffer_manager.current_buffer().clone();
+        } else if self.buffer.is_modified() && self.buffer.scratch_name().is_none() {
+            match self.buffer.file_path().map(|path| path.display().to_string()) {
+                Some(name) if self.buffer.is_readonly() => errors.push(format!("{}: 'readonly' is set", name)),
+                Some(name) => match self.buffer.save() {
+                    Ok(()) => written += 1,
+                    Err(e) => errors.push(format!("{}: {}", name, e)),
+                },
+                None => errors.push("[No Name]: no file name".to_string()),
             }
-            KeyCode::Char('l') | KeyCode::Right => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
-                };
-                for _ in 0..count {
-                    self.cursor.move_right(&self.buffer);
+        }
+        self.refresh_git_base();
+        if errors.is_empty() {
+            self.message = Some(match written {
+                0 => "No changes to write".to_string(),
+                1 => "1 file written".to_string(),
+                n => format!("{} files written", n),
+            });
+            return true;
+        }
+        self.report_error(format!("Not written: {}", errors.join("; ")));
+        false
+    }
+
+    /// Names of the buffers with unsaved changes, the current one included
+    fn modified_buffers(&mut self) -> Vec<String> {
+        self.store_buffer();
+        let mut names: Vec<String> = (0..self.buffer_manager.buffer_count())
+            .filter_map(|idx| self.buffer_manager.get(idx))
+            .filter(|buffer| buffer.is_modified())
+            .map(buffer_label)
+            .collect();
+        if !self.is_managed_buffer() && self.buffer.is_modified() && self.buffer.scratch_name().is_none() {
+            names.push(buffer_label(&self.buffer));
+        }
+        names
+    }
+
+    /// Quit (returns true) unless some buffer has unsaved changes, in
+    /// which case ask whether to save them, discard them or stay
+    fn request_quit(&mut self) -> bool {
+        let modified = self.modified_buffers();
+        if modified.is_empty() {
+            return true;
+        }
+        let prompt = Prompt::choose("Unsaved changes", modified, SAVE_DISCARD_CANCEL);
+        self.prompt = Some((prompt, PromptAction::Quit));
+        false
+    }
+
+    /// Act on the answer to the prompt; returns true to quit
+    fn answer_prompt(&mut self, action: PromptAction, answer: Answer) -> bool {
+        match (action, answer) {
+            (PromptAction::Command(command), Answer::Choice('y')) => return self.execute_command(&command),
+            (PromptAction::CommandWithText(command), Answer::Text(text)) if !text.trim().is_empty() => {
+                return self.execute_command(&format!("{} {}", command, text.trim()));
+            }
+            (PromptAction::Quit, Answer::Choice('s')) => return self.write_all(),
+            (PromptAction::Quit, Answer::Choice('d')) => return true,
+            (PromptAction::CloseBuffer, Answer::Choice('s')) => {
+                self.execute_command("w");
+                if !self.buffer.is_modified() {
+                    self.close_buffer();
                 }
-                self.numeric_prefix.clear();
             }
-            
-            // Line start/end
-            KeyCode::Char('0') => self.cursor.move_to_line_start(),
-            KeyCode::Char('^') => self.cursor.move_to_first_non_whitespace(&self.buffer),
-            KeyCode::Char('$') => self.cursor.move_to_line_end(&self.buffer),
-            
-            // Buffer start/end
-            KeyCode::Char('g') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                if self.pending_g {
-                    // 'gg' - move to buffer start
+            (PromptAction::CloseBuffer, Answer::Choice('d')) => self.close_buffer(),
+            (PromptAction::Substitute(state), answer) => self.answer_substitute(*state, answer),
+            _ => self.message = Some("Cancelled".to_string()),
+        }
+        false
+    }
+
+    /// The prompt waiting for an answer, if any
+    pub fn prompt(&self) -> Option<&Prompt> {
+        self.prompt.as_ref().map(|(prompt, _)| prompt)
+    }
+
+    /// Set the `"` mark of the current file at the cursor, before leaving it
+    fn remember_position(&mut self) {
+        if let Some(file) = self.buffer.file_path().cloned() {
+            self.marks.set(mark::LAST_POSITION, Some(&file), self.cursor.line, self.cursor.col);
+        }
+    }
+
+    /// Go back to the `"` mark of a file just opened, with restore_cursor
+    fn restore_position(&mut self) {
+        if !self.config.restore_cursor {
+            return;
+        }
+        let file = self.buffer.file_path().cloned();
+        if let Some(mark) = self.marks.get(mark::LAST_POSITION, file.as_deref()).cloned() {
+            self.cursor.line = mark.line;
+            self.cursor.col = mark.col;
+            self.cursor.clamp(&self.buffer);
+            self.cursor.col = self.cursor.col.min(self.buffer.line_len(self.cursor.line).saturating_sub(1));
+            self.ensure_cursor_visible();
+        }
+    }
+
+    /// Project root and the name bookmarks know the current file by:
+    /// relative to the root, or absolute outside it. None for buffers
+    /// without a local file.
+    fn bookmark_file(&self) -> Option<(PathBuf, PathBuf)> {
+        if self.remote_path().is_some() {
+            return None;
+        }
+        let path = self.working_dir().join(self.buffer.file_path()?);
+        let root = self.project_root();
+        let file = path.strip_prefix(&root).map(Path::to_path_buf).unwrap_or(path);
+        Some((root, file))
+    }
+
+    /// Show the current file's bookmarks as signs in the gutter, loading
+    /// the bookmarks of its project first
+    fn show_bookmarks(&mut self) {
+        self.buffer.clear_decorations(BOOKMARK_NAMESPACE);
+        self.bookmark_signs.clear();
+        let Some((root, file)) = self.bookmark_file() else { return };
+        self.bookmarks.open_project(&root);
+        for (idx, bookmark) in self.bookmarks.in_file(&file) {
+            let id = self.buffer.add_decoration(BOOKMARK_NAMESPACE, bookmark.line, 0, Decoration::Sign {
+                text: "⚑".to_string(),
+                style: Style::default().fg(Color::Cyan),
+            });
+            self.bookmark_signs.push((id, idx));
+        }
+    }
+
+    /// Move the current file's bookmarks to where edits moved their signs,
+    /// saving them if any moved
+    fn sync_bookmarks(&mut self) {
+        let mut moved = false;
+        for &(id, idx) in &self.bookmark_signs {
+            if let Some(line) = self.buffer.decoration_line(id) {
+                moved |= self.bookmarks.set_line(idx, line);
+            }
+        }
+        if moved {
+            self.save_bookmarks();
+        }
+    }
+
+    /// Write the project's bookmarks to its state file
+    fn save_bookmarks(&mut self) {
+        if let Err(e) = self.bookmarks.save() {
+            self.report_error(format!("Error saving bookmarks: {}", e));
+        }
+    }
+
+    /// :bookmark add [note], :bookmark remove and :bookmark list
+    fn bookmark_command(&mut self, arg: &str) {
+        let (action, note) = arg.trim().split_once(' ').unwrap_or((arg.trim(), ""));
+        match action {
+            "" | "list" | "ls" => self.open_picker(PickerKind::Bookmarks),
+            "add" | "a" => self.add_bookmark(note),
+            "remove" | "rm" | "delete" | "del" => self.remove_bookmark(),
+            _ => self.message = Some(format!("Unknown bookmark command: {} (use add, remove or list)", action)),
+        }
+    }
+
+    /// Bookmark the cursor line with a note, which may be quoted
+    fn add_bookmark(&mut self, note: &str) {
+        let Some((root, file)) = self.bookmark_file() else {
+            self.message = Some("Bookmarks need a file on disk".to_string());
+            return;
+        };
+        self.sync_bookmarks();
+        self.bookmarks.open_project(&root);
+        let note = note.trim();
+        let note = note.strip_prefix('"').and_then(|note| note.strip_suffix('"')).unwrap_or(note);
+        let added = self.bookmarks.set(&file, self.cursor.line, note);
+        self.show_bookmarks();
+        let what = if added { "Bookmark added" } else { "Bookmark updated" };
+        self.message = Some(if note.is_empty() { what.to_string() } else { format!("{}: {}", what, note) });
+        self.save_bookmarks();
+    }
+
+    /// Remove the bookmark on the cursor line
+    fn remove_bookmark(&mut self) {
+        let Some((root, file)) = self.bookmark_file() else {
+            self.message = Some("Bookmarks need a file on disk".to_string());
+            return;
+        };
+        self.sync_bookmarks();
+        self.bookmarks.open_project(&root);
+        if self.bookmarks.remove(&file, self.cursor.line).is_none() {
+            self.message = Some("No bookmark on this line".to_string());
+            return;
+        }
+        self.show_bookmarks();
+        self.message = Some("Bookmark removed".to_string());
+        self.save_bookmarks();
+    }
+
+    /// Set mark `name` at the cursor (`m`); only letters can be set
+    fn set_mark(&mut self, name: char) {
+        let file = self.buffer.file_path().cloned();
+        if !name.is_ascii_alphabetic() || !self.marks.set(name, file.as_deref(), self.cursor.line, self.cursor.col) {
+            self.message = Some(format!("Invalid mark: {}", name));
+        }
+    }
+
+    /// Jump to mark `name`: its exact position, or the first non-blank of
+    /// its line for `'`. File marks open their file.
+    fn jump_to_mark(&mut self, name: char, exact: bool) {
+        let file = self.buffer.file_path().cloned();
+        let Some(mark) = self.marks.get(name, file.as_deref()).cloned() else {
+            self.message = Some(format!("Mark not set: {}", name));
+            return;
+        };
+        if let Some(path) = mark.file.as_ref().filter(|path| Some(*path) != file.as_ref()) {
+            if let Err(e) = self.open_file(&path.to_string_lossy()) {
+                self.report_error(format!("Error opening {}", e));
+                return;
+            }
+        }
+        self.cursor.line = mark.line;
+        self.cursor.col = mark.col;
+        self.cursor.clamp(&self.buffer);
+        // `^ can be just past the end of its line, where normal mode can't go
+        self.cursor.col = self.cursor.col.min(self.buffer.line_len(self.cursor.line).saturating_sub(1));
+        if !exact {
+            self.cursor.move_to_first_non_whitespace(&self.buffer);
+        }
+        self.ensure_cursor_visible();
+    }
+
+    /// Replace the command line, or the search pattern, with an older
+    /// (`back`) or newer history entry. Going past the newest clears it.
+    fn recall_history(&mut self, search: bool, back: bool) {
+        let history = if search { &self.search_history } else { &self.command_history };
+        let index = match (self.history_index, back) {
+            (None, true) => history.len().checked_sub(1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < history.len() => Some(i + 1),
+            (Some(_), false) => None,
+            (None, false) => return,
+        };
+        let Some(text) = index.map_or(Some(String::new()), |i| history.get(i).cloned()) else { return };
+        self.history_index = index;
+        if search {
+            self.search.set_pattern(&text);
+            self.incremental_search();
+        } else {
+            self.command_buffer = text;
+        }
+    }
+
+    /// Get the file explorer sidebar, if it is open
+    pub fn explorer(&self) -> Option<&Explorer> {
+        self.explorer.as_ref()
+    }
+
+    /// Open and focus the file explorer, focus it if it is open but
+    /// unfocused, or close it if it already has focus
+    fn toggle_explorer(&mut self) {
+        match &self.explorer {
+            None => {
+                self.explorer = Some(Explorer::new(self.project_root()));
+                self.mode = Mode::Explorer;
+            }
+            Some(_) if self.mode == Mode::Explorer => {
+                self.explorer = None;
+                self.mode = Mode::Normal;
+            }
+            Some(_) => self.mode = Mode::Explorer,
+        }
+    }
+
+    /// Make the buffer at `idx` current
+    fn switch_to_buffer(&mut self, idx: usize) {
+        self.leave_buffer();
+        match self.buffer_manager.switch_to(idx) {
+            Ok(_) => {
+                self.buffer = self.buffer_manager.current_buffer().clone();
+                self.reset_cursor();
+                self.scroll_offset = 0;
+                self.restore_position();
+                self.show_bookmarks();
+                self.detect_syntax();
+                self.refresh_git_base();
+                self.message = Some(format!("Switched to buffer {}", idx));
+                self.load_project_config();
+            }
+            Err(e) => {
+                self.report_error(e);
+            }
+        }
+    }
+
+    /// Load the tags file from the working directory or the project root
+    fn load_tags(&self) -> Option<TagFile> {
+        [self.working_dir(), self.project_root()]
+            .iter()
+            .find_map(|dir| TagFile::load(&dir.join("tags")).ok())
+            .filter(|tags| !tags.is_empty())
+    }
+
+    /// Jump to the definition of a tag, remembering where we came from
+    fn jump_to_tag(&mut self, name: &str) {
+        let Some(tags) = self.load_tags() else {
+            self.message = Some("No tags file".to_string());
+            return;
+        };
+        let matches = tags.find(name);
+        let Some(tag) = matches.first() else {
+            self.message = Some(format!("Tag not found: {}", name));
+            return;
+        };
+        let origin = TagLocation {
+            file: self.buffer.file_path().cloned(),
+            line: self.cursor.line,
+            col: self.cursor.col,
+        };
+        if !self.goto_file(&tag.file) {
+            return;
+        }
+        self.tag_stack.push(origin);
+
+        let lines: Vec<String> = (0..self.buffer.line_count()).map(|i| self.buffer.line(i)).collect();
+        self.cursor.line = tag.find_line(&lines).unwrap_or(0).min(lines.len().saturating_sub(1));
+        self.cursor.move_to_first_non_whitespace(&self.buffer);
+        self.ensure_cursor_visible();
+        self.message = Some(match matches.len() {
+            1 => format!("tag: {}", name),
+            n => format!("tag 1 of {}: {}", n, name),
+        });
+    }
+
+    /// Return to the location before the last tag jump
+    fn pop_tag(&mut self) {
+        let Some(location) = self.tag_stack.pop() else {
+            self.message = Some("At bottom of tag stack".to_string());
+            return;
+        };
+        if let Some(file) = &location.file {
+            if !self.goto_file(file) {
+                return;
+            }
+        }
+        self.cursor.line = location.line.min(self.buffer.line_count().saturating_sub(1));
+        self.cursor.col = location.col;
+        self.ensure_cursor_visible();
+    }
+
+    /// Make `path` the current buffer, switching to it if it is already
+    /// open. Returns false (with a message) if it could not be opened.
+    fn goto_file(&mut self, path: &Path) -> bool {
+        let same_file = |a: &Path, b: &Path| {
+            a == b || matches!((std::fs::canonicalize(a), std::fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+        };
+        if self.buffer.file_path().is_some_and(|current| same_file(current, path)) {
+            return true;
+        }
+        let open = (0..self.buffer_manager.buffer_count()).find(|&idx| {
+            self.buffer_manager.get(idx)
+                .and_then(|buffer| buffer.file_path())
+                .is_some_and(|file| same_file(file, path))
+        });
+        match open {
+            Some(idx) => self.switch_to_buffer(idx),
+            None => {
+                if let Err(e) = self.open_file(&path.to_string_lossy()) {
+                    self.report_error(format!("Error opening {}", e));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Complete a `:tag` argument on the command line with Tab
+    fn complete_tag_command(&mut self) {
+        let Some((command, prefix)) = self.command_buffer.split_once(' ') else { return };
+        if command != "tag" && command != "ta" {
+            return;
+        }
+        let Some(tags) = self.load_tags() else { return };
+        let names = tags.names_with_prefix(prefix.trim());
+        let Some(first) = names.first() else {
+            self.message = Some("No matching tags".to_string());
+            return;
+        };
+        // Extend to the longest prefix shared by every candidate
+        let common = names.iter().fold(first.to_string(), |common, name| {
+            common.chars().zip(name.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(c, _)| c)
+                .collect()
+        });
+        self.command_buffer = format!("{} {}", command, common);
+        if names.len() > 1 {
+            const SHOWN: usize = 10;
+            let more = if names.len() > SHOWN { " ..." } else { "" };
+            self.message = Some(format!("{}{}", names[..names.len().min(SHOWN)].join("  "), more));
+        }
+    }
+
+    /// Run the :make program and load its errors into the quickfix list
+    fn run_make(&mut self, args: &str) {
+        let filetype = self.highlighter.current_syntax_name().map(str::to_lowercase);
+        let program = filetype.as_ref()
+            .and_then(|ft| self.config.makeprg_by_filetype.get(ft))
+            .or(self.config.makeprg.as_ref())
+            .cloned()
+            .unwrap_or_else(|| crate::quickfix::default_makeprg(filetype.as_deref()).to_string());
+        let file = self.buffer.file_path().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+        let command = format!("{} {}", program.replace('%', &file), args).trim().to_string();
+
+        let efm = self.config.errorformat.as_deref().unwrap_or(DEFAULT_ERRORFORMAT);
+        let efm = match ErrorFormat::parse(efm) {
+            Ok(efm) => efm,
+            Err(e) => {
+                self.message = Some(format!("errorformat: {}", e));
+                return;
+            }
+        };
+
+        let dir = self.working_dir();
+        let output = match std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .current_dir(&dir)
+            .stdin(std::process::Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                self.report_error(format!("Cannot run {}: {}", command, e));
+                return;
+            }
+        };
+        // Compilers disagree on which stream gets diagnostics; parse both
+        let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+        self.quickfix.set(efm.parse_output(&text), &dir);
+
+        if self.quickfix.is_empty() {
+            self.message = Some(match output.status.success() {
+                true => format!("{}: no errors", command),
+                false => format!("{}: {} (no locations found)", command, output.status),
+            });
+        } else {
+            let entry = self.quickfix.select(0).cloned();
+            self.jump_to_quickfix(entry);
+        }
+    }
+
+    /// Realign the table under the cursor, keeping the cursor in its cell.
+    /// With `typed`, only realign once the line has a complete cell.
+    fn align_table(&mut self, typed: bool) -> bool {
+        let lines: Vec<String> = (0..self.buffer.line_count()).map(|i| self.buffer.line(i)).collect();
+        let Some(range) = table::table_range(&lines, self.cursor.line) else { return false };
+        let line = &lines[self.cursor.line];
+        let pipes = table::pipes_before(line, self.cursor.col);
+        if typed && table::pipes_before(line, usize::MAX) < 2 {
+            return false;
+        }
+        let formatted = table::format_table(&lines[range.clone()]);
+        if formatted[..] != lines[range.clone()] {
+            if !typed {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+            }
+            self.buffer.replace_lines(range.start, range.end, &formatted);
+        }
+        let new_line = &formatted[self.cursor.line - range.start];
+        self.cursor.col = table::cell_start(new_line, pipes).unwrap_or(0);
+        true
+    }
+
+    /// Start a shell command in the background (:Run)
+    fn start_job(&mut self, command: &str) {
+        if let Some(job) = &self.job {
+            self.message = Some(format!("Already running: {} (:RunStop cancels)", job.command()));
+            return;
+        }
+        match Job::spawn(command, &self.working_dir(), self.waker.clone()) {
+            Ok(job) => {
+                self.job = Some(job);
+                self.job_output.clear();
+                self.quickfix.set(Vec::new(), Path::new(""));
+                if self.buffer.scratch_name() == Some(RUN_BUFFER) {
+                    self.buffer = Buffer::scratch(RUN_BUFFER, "");
+                    self.reset_cursor();
+                }
+                self.message = Some(format!("Running: {}", command));
+            }
+            Err(e) => self.report_error(format!("Cannot run {}: {}", command, e)),
+        }
+    }
+
+    /// Pick up output from the background job, streaming it into the
+    /// quickfix list and the "[Run]" buffer if it is shown
+    fn poll_job(&mut self) {
+        let Some(update) = self.job.as_mut().map(Job::poll) else { return };
+        if !update.lines.is_empty() {
+            let efm = self.config.errorformat.as_deref().unwrap_or(DEFAULT_ERRORFORMAT);
+            let efm = ErrorFormat::parse(efm).ok();
+            let dir = self.working_dir();
+            let mut text = String::new();
+            for line in &update.lines {
+                if let Some(entry) = efm.as_ref().and_then(|efm| efm.parse_line(line)) {
+                    self.quickfix.push(entry, &dir);
+                }
+                text.push_str(line);
+                text.push('\n');
+            }
+            self.job_output.push_str(&text);
+            if self.buffer.scratch_name() == Some(RUN_BUFFER) {
+                self.buffer.append_scratch(&text);
+            }
+        }
+        if let Some(status) = update.finished {
+            let command = self.job.take().map(|job| job.command().to_string()).unwrap_or_default();
+            let locations = match self.quickfix.entries().len() {
+                0 => String::new(),
+                n => format!(", {} location(s) in quickfix", n),
+            };
+            self.message = Some(format!("{}: {}{}", command, status, locations));
+        }
+    }
+
+    /// Cancel the background job (:RunStop)
+    fn cancel_job(&mut self) {
+        match self.job.take() {
+            Some(mut job) => {
+                job.cancel();
+                self.job_output.push_str("[cancelled]\n");
+                if self.buffer.scratch_name() == Some(RUN_BUFFER) {
+                    self.buffer.append_scratch("[cancelled]\n");
+                }
+                self.message = Some(format!("Cancelled: {}", job.command()));
+            }
+            None => self.message = Some("No job running".to_string()),
+        }
+    }
+
+    /// Show :Run output in the "[Run]" scratch buffer
+    fn show_job_output(&mut self) {
+        self.buffer = Buffer::scratch(RUN_BUFFER, &self.job_output);
+        self.reset_cursor();
+        self.scroll_offset = 0;
+        self.git_base = None;
+        self.git_hunks.clear();
+    }
+
+    /// Show a message and keep it in the log for :messages
+    fn report_error(&mut self, error: impl fmt::Display) {
+        self.message_logged = true;
+        // A macro stops at the first error (a search that finds nothing
+        // ends 100@a once every match is done)
+        if self.macro_depth > 0 {
+            self.macro_failed = true;
+        }
+        let message = error.to_string();
+        crate::log::error(message.as_str());
+        self.message = Some(message);
+    }
+
+    /// Show the log in a scratch buffer (:messages)
+    fn show_messages(&mut self) {
+        let entries = crate::log::entries();
+        if entries.is_empty() {
+            self.message = Some("No messages".to_string());
+            return;
+        }
+        let text: String = entries.iter().map(|entry| entry.format() + "\n").collect();
+        self.buffer = Buffer::scratch(MESSAGES_BUFFER, &text);
+        self.reset_cursor();
+        self.cursor.move_to_buffer_end(&self.buffer);
+        self.git_base = None;
+        self.git_hunks.clear();
+        self.ensure_cursor_visible();
+        if let Some(path) = crate::log::file() {
+            self.message = Some(format!("Log file: {}", path.display()));
+        }
+    }
+
+    /// `:nmap {keys} {action}` binds keys to an action name or `:command`,
+    /// `:nmap [keys]` lists the bindings (starting with keys) and
+    /// `:nunmap {keys}` removes one; likewise for the other modes
+    fn map_command(&mut self, cmd: &str) {
+        let (name, args) = cmd.split_once(' ').unwrap_or((cmd, ""));
+        let Some(&(_, modes, unmap)) = MAP_COMMANDS.iter().find(|(command, _, _)| *command == name) else {
+            return;
+        };
+        let args = args.trim();
+        if unmap {
+            if args.is_empty() {
+                self.report_error(format!("Give the keys to unmap: :{} <keys>", name));
+                return;
+            }
+            let mut errors = Vec::new();
+            let mut restored = None;
+            let mut removed = false;
+            for &mode in modes {
+                match self.keymap.unmap(mode, args) {
+                    Ok(default) => {
+                        removed = true;
+                        restored = restored.or(default);
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+            if !removed {
+                self.report_error(errors.swap_remove(0));
+            } else if let Some(action) = restored {
+                self.message = Some(format!("{} is back to {}", args, action));
+            } else {
+                self.message = Some(format!("Unmapped {}", args));
+            }
+            return;
+        }
+        let (keys, action) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let action = action.trim();
+        if action.is_empty() {
+            self.show_mappings(modes, keys);
+            return;
+        }
+        let Some(action) = Action::from_name(action) else {
+            self.report_error(format!("Unknown action: {}", action));
+            return;
+        };
+        for &mode in modes {
+            if let Err(e) = self.keymap.map(mode, keys, action.clone()) {
+                self.report_error(e);
+                return;
+            }
+        }
+        self.message = Some(format!("Mapped {} to {}", keys, action));
+    }
+
+    /// Start recording the keys typed into `register`; A-Z append
+    fn start_recording(&mut self, register: char) {
+        if register.is_ascii_alphabetic() || register == '"' {
+            self.recording = Some((register, Vec::new()));
+        }
+    }
+
+    /// Stop recording and store the keys in the register, in key notation
+    fn stop_recording(&mut self) {
+        let Some((register, keys)) = self.recording.take() else { return };
+        let text: String = keys.iter().map(KeyCombo::to_string).collect();
+        self.registers.set(register, RegisterContent::Chars(text), false);
+        let name = register.to_ascii_lowercase();
+        self.recorded_macros.retain(|&other| other != name);
+        self.recorded_macros.push(name);
+        self.message = Some(format!("Recorded @{} ({} keys)", name, keys.len()));
+    }
+
+    /// Register the macro being recorded goes into, for the status line
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    /// Type the keys in `register` `count` times, through handle_key like
+    /// typed keys; @ is the last macro run
+    fn run_macro(&mut self, register: char, count: usize) -> bool {
+        let register = match register {
+            '@' => match self.last_macro {
+                Some(register) => register,
+                None => {
+                    self.report_error("No previous macro");
+                    return false;
+                }
+            },
+            register => register,
+        };
+        let keys = self.read_register(Some(register))
+            .and_then(|content| parse_key_sequence(&content.full_text(), self.keymap.leader));
+        let Some(keys) = keys else {
+            self.report_error(format!("Register {} is empty", register));
+            return false;
+        };
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            self.report_error("Macros nested too deep");
+            return false;
+        }
+        self.last_macro = Some(register);
+        self.macro_depth += 1;
+        let mut quit = false;
+        for key in (0..count).flat_map(|_| &keys) {
+            quit = self.handle_key(KeyEvent::new(key.code, key.modifiers));
+            if quit || self.macro_failed {
+                break;
+            }
+        }
+        self.macro_depth -= 1;
+        if self.macro_depth == 0 {
+            self.macro_failed = false;
+        }
+        quit
+    }
+
+    /// List the macros recorded this session with their keys: one in a
+    /// message, more in a scratch buffer
+    fn show_macros(&mut self) {
+        let mut lines: Vec<String> = self.recorded_macros.iter()
+            .filter_map(|&name| Some(format!("@{}  {}", name, self.registers.get(name)?.full_text())))
+            .collect();
+        match lines.len() {
+            0 => self.message = Some("No macros recorded".to_string()),
+            1 => self.message = Some(lines.remove(0)),
+            _ => {
+                let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+                self.buffer = Buffer::scratch(MACROS_BUFFER, &text);
+                self.reset_cursor();
+                self.git_base = None;
+                self.git_hunks.clear();
+                self.scroll_offset = 0;
+                self.message = Some(format!("{} macros", lines.len()));
+            }
+        }
+    }
+
+    /// List the bindings of `modes` starting with `keys` (all if empty):
+    /// one in a message, more in a scratch buffer
+    fn show_mappings(&mut self, modes: &[Mode], keys: &str) {
+        let prefix = if keys.is_empty() {
+            Vec::new()
+        } else {
+            match parse_key_sequence(keys, self.keymap.leader) {
+                Some(prefix) => prefix,
+                None => return self.report_error(format!("Invalid key sequence: {}", keys)),
+            }
+        };
+        let mut lines = Vec::new();
+        for &mode in modes {
+            let Some(keymap) = self.keymap.for_mode(mode) else { continue };
+            let letter = mode.display()[..1].to_lowercase();
+            for (sequence, action) in keymap.bindings(&prefix) {
+                let keys: String = sequence.iter().map(KeyCombo::to_string).collect();
+                lines.push(format!("{}  {:<12} {}", letter, keys, action));
+            }
+        }
+        match lines.len() {
+            0 => self.message = Some("No mapping found".to_string()),
+            1 => self.message = Some(lines.remove(0)),
+            _ => {
+                let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+                self.buffer = Buffer::scratch(MAPPINGS_BUFFER, &text);
+                self.reset_cursor();
+                self.git_base = None;
+                self.git_hunks.clear();
+                self.scroll_offset = 0;
+                self.message = Some(format!("{} mappings", lines.len()));
+            }
+        }
+    }
+
+    /// Number of invisible or confusable characters in the buffer,
+    /// recounted only after it changes
+    pub fn invisible_count(&self) -> usize {
+        let (revision, count) = self.invisible_count.get();
+        if revision == self.buffer.revision() {
+            return count;
+        }
+        let count = crate::invisible::count(self.buffer.chars());
+        self.invisible_count.set((self.buffer.revision(), count));
+        count
+    }
+
+    /// Lines of the current file with quickfix entries, for the scrollbar
+    pub fn quickfix_lines(&self) -> Vec<usize> {
+        let Some(path) = self.buffer.file_path() else { return Vec::new() };
+        self.quickfix.entries()
+            .iter()
+            .filter(|entry| entry.file == *path || entry.file.ends_with(path))
+            .map(|entry| entry.line)
+            .collect()
+    }
+
+    /// What the next frame would be drawn from
+    pub fn render_state(&self) -> RenderState {
+        RenderState {
+            revision: self.buffer.revision(),
+            modified: self.buffer.is_modified(),
+            cursor: (self.cursor.line, self.cursor.col),
+            cursors: self.cursors.iter().map(|c| (c.line, c.col)).collect(),
+            scroll: self.scroll_offset(),
+            mode: self.mode,
+            message: self.message.clone(),
+            command: self.command_buffer.clone(),
+            pending: (
+                self.pending_op,
+                self.operator.map(|(op, _)| op),
+                self.numeric_prefix.clone(),
+                self.pending_register,
+                self.pending_g,
+                self.pending_keys.len(),
+            ),
+            key_hints: self.key_hint_delay().is_some_and(|delay| delay.is_zero()),
+            recording: self.recording(),
+            selection: self.selection,
+            search: (
+                self.search.pattern().to_string(),
+                self.search.matches().len(),
+                self.search_highlight(),
+            ),
+            quickfix: self.quickfix.entries().len(),
+            job_secs: self.job.as_ref().map(|job| job.elapsed().as_secs()),
+            history: self.config.status_history.then(|| self.history_summary()),
+            completion: self.completion().map(|completion| completion.selected),
+            changes: self.changes,
+            sysinfo: self.show_sysinfo,
+            prompt: self.prompt().map(Prompt::line),
+            popups: self.popups.clone(),
+            live: self.picker.is_some()
+                || self.explorer.is_some()
+                || self.terminal.is_some()
+                || self.lua.as_ref().is_some_and(|lua| lua.has_statusline()),
+        }
+    }
+
+    /// Undo depth, redo depth and the start of the unnamed register, for
+    /// Ctrl-G and the status line: `undo 4, redo 1, "" fn main`
+    pub fn history_summary(&self) -> String {
+        let mut parts = vec![format!("undo {}", self.buffer.undo_count())];
+        if self.buffer.can_redo() {
+            parts.push(format!("redo {}", self.buffer.redo_count()));
+        }
+        // Where in the history the file was last written
+        match self.buffer.file_path().and(self.buffer.saved_steps()) {
+            Some(steps) if steps < 0 => parts.push(format!("saved {} back", -steps)),
+            Some(steps) if steps > 0 => parts.push(format!("saved {} ahead", steps)),
+            _ => {}
+        }
+        if let Some(content) = self.registers.get_unnamed().filter(|content| !content.is_empty()) {
+            let first = content.text().lines().find(|line| !line.trim().is_empty()).unwrap_or("").trim();
+            let mut preview: String = first.chars().take(REGISTER_PREVIEW).collect();
+            if first.chars().count() > REGISTER_PREVIEW {
+                preview.push('…');
+            }
+            parts.push(format!("\"\" {}", preview));
+        }
+        parts.join(", ")
+    }
+
+    /// Segments init.lua's statusline function adds to the status line, or
+    /// the error it raised; None without one
+    pub fn lua_statusline(&self) -> Option<crate::error::Result<Vec<lua::Segment>>> {
+        self.lua.as_ref()?.statusline(self)
+    }
+
+    /// Status of the background job for the status line
+    pub fn job_status(&self) -> Option<String> {
+        self.job.as_ref().map(|job| format!("running: {} ({}s)", job.command(), job.elapsed().as_secs()))
+    }
+
+    /// Jump to a quickfix entry and show its message
+    fn jump_to_quickfix(&mut self, entry: Option<QuickfixEntry>) {
+        let Some(entry) = entry else {
+            self.message = Some("No more items".to_string());
+            return;
+        };
+        if !self.goto_file(&entry.file) {
+            return;
+        }
+        self.cursor.line = entry.line.min(self.buffer.line_count().saturating_sub(1));
+        self.cursor.col = entry.col;
+        self.ensure_cursor_visible();
+        self.message = Some(format!(
+            "({} of {}) {}",
+            self.quickfix.current() + 1,
+            self.quickfix.entries().len(),
+            entry.message
+        ));
+    }
+
+    /// Move through the quickfix list (:cnext, :cprev, :cc)
+    fn quickfix_command(&mut self, cmd: &str, arg: &str) {
+        if self.quickfix.is_empty() {
+            self.message = Some("Quickfix list is empty".to_string());
+            return;
+        }
+        let count = arg.trim().parse::<usize>().ok();
+        let entry = match cmd {
+            "cn" | "cnext" => self.quickfix.step(count.unwrap_or(1), true),
+            "cp" | "cprev" | "cprevious" | "cN" | "cNext" => self.quickfix.step(count.unwrap_or(1), false),
+            "cr" | "crewind" | "cfir" | "cfirst" => self.quickfix.select(0),
+            "cla" | "clast" => self.quickfix.select(usize::MAX),
+            _ => {
+                let current = self.quickfix.current();
+                self.quickfix.select(count.map_or(current, |n| n.saturating_sub(1)))
+            }
+        }
+        .cloned();
+        self.jump_to_quickfix(entry);
+    }
+
+    /// Directory relative paths resolve against: the buffer's :lcd
+    /// directory, or the process working directory
+    pub fn working_dir(&self) -> PathBuf {
+        self.buffer.local_dir().cloned()
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    /// Root of the project containing the current file (or the working
+    /// directory when there is no file), falling back to the working
+    /// directory when no project marker is found
+    pub fn project_root(&self) -> PathBuf {
+        let working_dir = self.working_dir();
+        let start = self.buffer.file_path()
+            .map(|path| working_dir.join(path))
+            .and_then(|path| path.parent().map(PathBuf::from))
+            .unwrap_or_else(|| working_dir.clone());
+        crate::project::find_root(&start).unwrap_or(working_dir)
+    }
+
+    /// Change the global (:cd) or buffer-local (:lcd) working directory
+    fn change_dir(&mut self, arg: &str, local: bool) {
+        let dir = crate::project::resolve_dir(&self.working_dir(), arg);
+        let dir = match std::fs::canonicalize(&dir) {
+            Ok(dir) if dir.is_dir() => dir,
+            _ => {
+                self.message = Some(format!("Can't find directory: {}", dir.display()));
+                return;
+            }
+        };
+
+        if local {
+            self.buffer.set_local_dir(Some(dir.clone()));
+        } else {
+            if let Err(e) = std::env::set_current_dir(&dir) {
+                self.report_error(format!("Error: {}", e));
+                return;
+            }
+            // Like Vim, :cd drops the current buffer's local directory
+            self.buffer.set_local_dir(None);
+        }
+        if self.buffer_manager.has_buffers() {
+            let local_dir = self.buffer.local_dir().cloned();
+            self.buffer_manager.current_buffer().set_local_dir(local_dir);
+        }
+        self.message = Some(dir.display().to_string());
+    }
+
+    /// Get the fuzzy finder, if it is open
+    pub fn picker(&self) -> Option<&Picker> {
+        self.picker.as_ref()
+    }
+
+    /// Open the fuzzy finder over files, buffers or lines
+    fn open_picker(&mut self, kind: PickerKind) {
+        let items: Vec<PickerItem> = match kind {
+            PickerKind::Files => {
+                let root = self.project_root();
+                crate::picker::collect_files(&root)
+                    .into_iter()
+                    .map(|path| PickerItem {
+                        label: path.strip_prefix(&root).unwrap_or(&path).display().to_string(),
+                        target: PickerTarget::File(path),
+                    })
+                    .collect()
+            }
+            PickerKind::Buffers => self.buffer_manager.list_buffers()
+                .into_iter()
+                .map(|(idx, name, _)| PickerItem {
+                    label: format!("{}: {}", idx, name),
+                    target: PickerTarget::Buffer(idx),
+                })
+                .collect(),
+            PickerKind::Recent => self.recent_files.files()
+                .iter()
+                .filter(|path| path.is_file())
+                .map(|path| PickerItem {
+                    label: path.display().to_string(),
+                    target: PickerTarget::File(path.clone()),
+                })
+                .collect(),
+            PickerKind::Lines => (0..self.buffer.line_count())
+                .map(|idx| (idx, self.buffer.line(idx)))
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(idx, line)| PickerItem {
+                    label: format!("{}: {}", idx + 1, line.trim()),
+                    target: PickerTarget::Line(idx),
+                })
+                .collect(),
+            PickerKind::Quickfix => self.quickfix.entries()
+                .iter()
+                .enumerate()
+                .map(|(idx, entry)| PickerItem {
+                    label: entry.label(),
+                    target: PickerTarget::Quickfix(idx),
+                })
+                .collect(),
+            PickerKind::Bookmarks => {
+                self.sync_bookmarks();
+                let root = self.project_root();
+                self.bookmarks.open_project(&root);
+                self.bookmarks.items()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, bookmark)| PickerItem {
+                        label: bookmark.label(),
+                        target: PickerTarget::Bookmark(idx),
+                    })
+                    .collect()
+            }
+        };
+
+        if items.is_empty() {
+            self.message = Some(format!("{}: nothing to pick from", kind.title()));
+            return;
+        }
+        self.picker = Some(Picker::new(kind, items));
+        self.mode = Mode::Picker;
+        self.update_picker_preview();
+    }
+
+    /// Handle keys while the fuzzy finder is open
+    fn handle_picker_mode(&mut self, key: KeyEvent) -> bool {
+        let Some(picker) = self.picker.as_mut() else {
+            self.mode = Mode::Normal;
+            return false;
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => {
+                self.picker = None;
+                self.mode = Mode::Normal;
+                return false;
+            }
+            KeyCode::Enter => {
+                let target = picker.selected_item().map(|item| item.target.clone());
+                self.picker = None;
+                self.mode = Mode::Normal;
+                if let Some(target) = target {
+                    self.accept_picker(target);
+                }
+                return false;
+            }
+            KeyCode::Up => picker.move_up(),
+            KeyCode::Down | KeyCode::Tab => picker.move_down(),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => picker.move_up(),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => picker.move_down(),
+            KeyCode::Backspace => {
+                picker.backspace();
+            }
+            KeyCode::Char(c) if !ctrl => picker.insert_char(c),
+            _ => return false,
+        }
+        self.update_picker_preview();
+        false
+    }
+
+    /// Open, switch to or jump to the chosen candidate
+    fn accept_picker(&mut self, target: PickerTarget) {
+        match target {
+            PickerTarget::File(path) => {
+                let path = path.to_string_lossy().into_owned();
+                if let Err(e) = self.load_file(&path) {
+                    self.report_error(format!("Error opening {}", e));
+                }
+            }
+            PickerTarget::Buffer(idx) => self.switch_to_buffer(idx),
+            PickerTarget::Line(line) => {
+                self.cursor.line = line.min(self.buffer.line_count().saturating_sub(1));
+                self.cursor.move_to_first_non_whitespace(&self.buffer);
+                self.ensure_cursor_visible();
+            }
+            PickerTarget::Quickfix(idx) => {
+                let entry = self.quickfix.select(idx).cloned();
+                self.jump_to_quickfix(entry);
+            }
+            PickerTarget::Bookmark(idx) => {
+                let Some((root, bookmark)) = self.bookmarks.root().zip(self.bookmarks.items().get(idx)) else {
+                    return;
+                };
+                let (path, line) = (root.join(&bookmark.file), bookmark.line);
+                if self.goto_file(&path) {
+                    self.cursor.line = line.min(self.buffer.line_count().saturating_sub(1));
+                    self.cursor.move_to_first_non_whitespace(&self.buffer);
+                    self.ensure_cursor_visible();
+                }
+            }
+        }
+    }
+
+    /// Load the preview for the highlighted picker candidate
+    fn update_picker_preview(&mut self) {
+        const PREVIEW_LINES: usize = 100;
+        let Some(picker) = self.picker.as_ref() else { return };
+        // Lines of a file on disk around `line`, with that line highlighted
+        let excerpt = |path: &Path, line: usize| {
+            let start = line.saturating_sub(PREVIEW_LINES / 4);
+            let lines: Vec<String> = std::fs::read_to_string(path)
+                .map(|text| text.lines().skip(start).take(PREVIEW_LINES).map(String::from).collect())
+                .unwrap_or_default();
+            let highlight = (line - start < lines.len()).then_some(line - start);
+            (lines, highlight)
+        };
+        let (preview, highlight) = match picker.selected_item().map(|item| &item.target) {
+            Some(PickerTarget::File(path)) => {
+                let lines = std::fs::read_to_string(path)
+                    .map(|text| text.lines().take(PREVIEW_LINES).map(String::from).collect())
+                    .unwrap_or_else(|_| vec!["[binary or unreadable file]".to_string()]);
+                (lines, None)
+            }
+            Some(PickerTarget::Buffer(idx)) => {
+                let lines = self.buffer_manager.get(*idx)
+                    .map(|buf| (0..buf.line_count().min(PREVIEW_LINES)).map(|i| buf.line(i)).collect())
+                    .unwrap_or_default();
+                (lines, None)
+            }
+            Some(PickerTarget::Line(line)) => {
+                let start = line.saturating_sub(PREVIEW_LINES / 4);
+                let end = (start + PREVIEW_LINES).min(self.buffer.line_count());
+                let lines = (start..end).map(|i| self.buffer.line(i)).collect();
+                (lines, Some(line - start))
+            }
+            Some(PickerTarget::Quickfix(idx)) => match self.quickfix.entries().get(*idx) {
+                Some(entry) => excerpt(&entry.file, entry.line),
+                None => (Vec::new(), None),
+            },
+            Some(PickerTarget::Bookmark(idx)) => match self.bookmarks.root().zip(self.bookmarks.items().get(*idx)) {
+                Some((root, bookmark)) => excerpt(&root.join(&bookmark.file), bookmark.line),
+                None => (Vec::new(), None),
+            },
+            None => (Vec::new(), None),
+        };
+        if let Some(picker) = self.picker.as_mut() {
+            picker.preview = preview;
+            picker.preview_line = highlight;
+        }
+    }
+
+    /// Whether :profile is recording frame timings
+    pub fn profiling(&self) -> bool {
+        self.profiler.borrow().is_some()
+    }
+
+    /// Add time spent in a phase to the frame being profiled
+    pub fn profile(&self, phase: Phase, elapsed: Duration) {
+        if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+            profiler.add(phase, elapsed);
+        }
+    }
+
+    /// Finish the profiled frame after it was drawn
+    pub fn end_frame(&self) {
+        if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+            profiler.end_frame();
+        }
+    }
+
+    /// Statistics over the recently profiled frames
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.profiler.borrow().as_ref().and_then(FrameProfiler::stats)
+    }
+
+    /// Whether system metrics are on screen and should be kept fresh
+    fn metrics_shown(&self) -> bool {
+        self.config.sysinfo || self.show_sysinfo
+    }
+
+    /// Whether the :sysinfo popup is open
+    pub fn show_sysinfo(&self) -> bool {
+        self.show_sysinfo
+    }
+
+    /// Get a reference to the GPU info provider
+    pub fn gpu_info(&self) -> &GpuInfo {
+        &self.gpu_info
+    }
+
+    /// Handle a key event, returns true if editor should quit
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let timer = self.profiling().then(|| PerfTimer::new("input"));
+        let quit = self.handle_key_untimed(key);
+        if let Some(timer) = timer {
+            self.profile(Phase::Input, timer.elapsed());
+        }
+        quit
+    }
+
+    fn handle_key_untimed(&mut self, key: KeyEvent) -> bool {
+        // A message of several lines waits for Enter, Esc or Space; any
+        // other key goes on to what it does (":" types a command)
+        if self.long_message().is_some() && matches!(key.code, KeyCode::Enter | KeyCode::Esc | KeyCode::Char(' ')) {
+            self.message = None;
+            return false;
+        }
+        self.message = None; // Clear message on keypress
+        self.message_logged = false;
+        // A prompt takes every key until it is answered
+        if let Some((mut prompt, action)) = self.prompt.take() {
+            match prompt.handle_key(key) {
+                Some(answer) => return self.answer_prompt(action, answer),
+                None => self.prompt = Some((prompt, action)),
+            }
+            return false;
+        }
+        // Ctrl-Q quits from any mode, asking first about unsaved changes
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('q') {
+            return self.request_quit();
+        }
+        // Any key closes the :sysinfo popup
+        if self.show_sysinfo {
+            self.show_sysinfo = false;
+            return false;
+        }
+        let shown_offset = self.scroll_offset();
+        let (mode, cursor) = (self.mode, (self.cursor.line, self.cursor.col));
+        // The q that starts or stops a recording is not part of it
+        let recording = self.recording.is_some() && self.macro_depth == 0;
+        let quit = self.dispatch_key(key);
+        if let Some((_, keys)) = self.recording.as_mut().filter(|_| recording) {
+            keys.push(KeyCombo::from_event(&key));
+        }
+        if self.has_pending_command() {
+            self.typed_keys.push_str(&KeyCombo::from_event(&key).to_string());
+        } else {
+            self.typed_keys.clear();
+        }
+        if self.config.smooth_scroll && shown_offset.abs_diff(self.scroll_offset) > SCROLL_ANIMATE_MIN {
+            self.scroll_animation = Some(ScrollAnimation {
+                from: shown_offset,
+                to: self.scroll_offset,
+                started: Instant::now(),
+            });
+        } else if shown_offset != self.scroll_offset {
+            self.scroll_animation = None;
+        }
+        if self.buffer.is_modified() && (self.git_base.is_some() || self.diff.is_some()) {
+            self.edited_since = Some(Instant::now());
+        }
+        if self.show_blame && self.blame.as_ref().map(|(line, _)| *line) != Some(self.cursor.line) {
+            self.update_blame();
+        }
+        if self.mode != mode {
+            let change = format!("{}:{}", mode.display(), self.mode.display()).to_lowercase();
+            self.fire_autocmd(lua::Event::ModeChanged, &change);
+        }
+        if (self.cursor.line, self.cursor.col) != cursor {
+            self.fire_buffer_autocmd(lua::Event::CursorMoved);
+        }
+        quit || std::mem::take(&mut self.lua_quit)
+    }
+
+    /// Blame the cursor line and show it as virtual text after the line
+    fn update_blame(&mut self) {
+        self.changes += 1;
+        let line = self.cursor.line;
+        self.blame = self.buffer.file_path()
+            .and_then(|path| crate::git::blame_line(path, line, &self.buffer.content()))
+            .map(|info| (line, info));
+        self.buffer.clear_decorations(BLAME_NAMESPACE);
+        if let Some((line, info)) = &self.blame {
+            self.buffer.add_decoration(BLAME_NAMESPACE, *line, 0, Decoration::EndOfLine {
+                text: format!("    {}", info),
+                style: Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            });
+        }
+    }
+
+    /// How long the main loop may block waiting for input before `tick`
+    /// has work to do, or None to wait indefinitely
+    pub fn next_wakeup(&self) -> Option<Duration> {
+        let idle = self.edited_since.map(|since| IDLE_DELAY.saturating_sub(since.elapsed()));
+        let hint = self.key_hint_delay().filter(|delay| !delay.is_zero());
+        // Terminal output and search matches wake the main loop when one
+        // listens; otherwise they are polled for
+        let polled = !self.waker.is_connected();
+        let terminal = self.terminal.as_ref().filter(|_| polled).map(|_| TERMINAL_POLL);
+        // Still polled for the time and progress shown while they run
+        let job = (self.job.is_some() || self.transfer.is_some()).then_some(JOB_POLL);
+        let scroll = self.scroll_animation.map(|_| SCROLL_FRAME);
+        let search = (polled && self.search.is_running()).then_some(SEARCH_POLL);
+        let metrics = self.metrics_shown().then(|| self.gpu_info.next_refresh());
+        [self.pending_key_timeout(), hint, idle, terminal, job, scroll, search, metrics]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Run time-based work when no input arrived: resolve timed-out key
+    /// sequences, refresh git signs and diffs and notice the terminal
+    /// exiting. Returns true if the editor should quit.
+    pub fn tick(&mut self) -> bool {
+        // Work done here can replace the buffer or store it away, so it
+        // must see the real one rather than a :s preview standing in for it
+        self.end_substitute_preview();
+        let quit = self.run_timed_work();
+        if self.mode == Mode::Command {
+            self.preview_substitute();
+        }
+        quit
+    }
+
+    fn run_timed_work(&mut self) -> bool {
+        if self.pending_key_timeout().is_some_and(|t| t.is_zero()) && self.flush_pending_keys() {
+            return true;
+        }
+        if self.terminal.as_mut().is_some_and(|terminal| !terminal.is_alive()) {
+            self.terminal = None;
+            if self.mode == Mode::Terminal {
+                self.mode = Mode::Normal;
+            }
+            self.message = Some("Terminal exited".to_string());
+        }
+        self.poll_job();
+        self.poll_loading();
+        if self.poll_transfer() {
+            return true;
+        }
+        if self.search.poll() {
+            self.changes += 1;
+        }
+        if self.metrics_shown() && self.gpu_info.refresh_if_due() {
+            self.changes += 1;
+        }
+        if self.scroll_animation.is_some_and(|animation| animation.is_done()) {
+            self.scroll_animation = None;
+        }
+        if self.edited_since.is_some_and(|since| since.elapsed() >= IDLE_DELAY) {
+            self.update_hunks();
+            if self.show_blame {
+                self.update_blame();
+            }
+        }
+        false
+    }
+
+    /// Reload the git index version of the current file and recompute signs
+    fn refresh_git_base(&mut self) {
+        self.git_base = self.buffer.file_path()
+            .and_then(|path| crate::git::index_content(path))
+            .map(|content| content.lines().map(String::from).collect());
+        self.update_hunks();
+        if self.show_blame {
+            self.update_blame();
+        }
+    }
+
+    /// Diff the buffer against the git index version and the :diffsplit file
+    fn update_hunks(&mut self) {
+        self.edited_since = None;
+        self.changes += 1;
+        let content = self.buffer.content();
+        let lines: Vec<&str> = content.lines().collect();
+        self.git_hunks = match &self.git_base {
+            Some(base) => {
+                let base: Vec<&str> = base.iter().map(String::as_str).collect();
+                crate::diff::diff_lines(&base, &lines)
+            }
+            None => Vec::new(),
+        };
+        if let Some(diff) = &mut self.diff {
+            diff.update(&lines);
+        }
+    }
+
+    /// Compare the buffer side by side with another file
+    pub fn diff_split(&mut self, arg: &str) {
+        let path = crate::project::resolve_dir(&self.working_dir(), arg);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let lines = content.lines().map(String::from).collect();
+                let current = self.buffer.content();
+                let current: Vec<&str> = current.lines().collect();
+                let diff = DiffView::new(PathBuf::from(arg.trim()), lines, &current);
+                self.message = Some(format!("{} hunks differ from {}", diff.hunks().len(), arg.trim()));
+                self.diff = Some(diff);
+            }
+            Err(e) => self.report_error(format!("Cannot diff {}: {}", arg.trim(), e)),
+        }
+    }
+
+    /// Toggle the distraction-free writing layout (:zen)
+    fn toggle_zen(&mut self) {
+        match self.zen.take() {
+            Some(restore) => {
+                self.config.line_numbers = restore.line_numbers;
+                self.config.wrap = restore.wrap;
+            }
+            None => {
+                self.zen = Some(ZenRestore {
+                    line_numbers: self.config.line_numbers,
+                    wrap: self.config.wrap,
+                });
+                self.config.line_numbers = false;
+                self.config.wrap |= self.config.zen_wrap;
+            }
+        }
+    }
+
+    /// Whether :zen is on
+    pub fn zen(&self) -> bool {
+        self.zen.is_some()
+    }
+
+    /// The side-by-side diff, if :diffsplit is active
+    pub fn diff_view(&self) -> Option<&DiffView> {
+        self.diff.as_ref()
+    }
+
+    /// Changed-line hunks for the gutter, or None if the file is not in git
+    pub fn git_hunks(&self) -> Option<&[Hunk]> {
+        self.git_base.as_ref().map(|_| self.git_hunks.as_slice())
+    }
+
+    /// Move the cursor to the next (or previous) changed hunk
+    fn jump_to_hunk(&mut self, forward: bool, count: usize) {
+        if self.edited_since.is_some() {
+            self.update_hunks();
+        }
+        // In diff mode hunks are the differences from the other file
+        let lines: Vec<usize> = match &self.diff {
+            Some(diff) => {
+                let last = self.buffer.line_count().saturating_sub(1);
+                diff.hunks().iter().map(|h| h.old_start.min(last)).collect()
+            }
+            None => self.git_hunks.iter().map(crate::git::hunk_line).collect(),
+        };
+        let mut line = self.cursor.line;
+        for _ in 0..count {
+            let next = if forward {
+                lines.iter().find(|&&l| l > line)
+            } else {
+                lines.iter().rev().find(|&&l| l < line)
+            };
+            match next {
+                Some(&l) => line = l,
+                None => break,
+            }
+        }
+        if line == self.cursor.line {
+            self.message = Some("No more hunks".to_string());
+            return;
+        }
+        self.cursor.line = line;
+        self.cursor.move_to_first_non_whitespace(&self.buffer);
+    }
+
+    /// Route a key to the handler for the current mode
+    fn dispatch_key(&mut self, key: KeyEvent) -> bool {
+        match self.mode {
+            Mode::Normal => self.handle_normal_mode(key),
+            Mode::Insert => self.handle_insert_mode(key),
+            Mode::Command => self.handle_command_mode(key),
+            Mode::Search => self.handle_search_mode(key),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                // The area a key leaving visual mode acted on stays '<,'>
+                self.last_visual = self.selection.or(self.last_visual);
+                self.handle_visual_mode(key)
+            }
+            Mode::Explorer => self.handle_explorer_mode(key),
+            Mode::Picker => self.handle_picker_mode(key),
+            Mode::Terminal => self.handle_terminal_mode(key),
+        }
+    }
+
+    /// Get the embedded terminal, if one is running
+    pub fn terminal(&self) -> Option<&Terminal> {
+        self.terminal.as_ref()
+    }
+
+    /// Focus the terminal pane, starting a shell if none is running
+    fn open_terminal(&mut self) {
+        if self.terminal.is_none() {
+            let shell = self.config.shell.clone()
+                .or_else(|| std::env::var("SHELL").ok())
+                .unwrap_or_else(|| "/bin/sh".to_string());
+            // The view resizes the terminal to fit its pane when drawn
+            match Terminal::spawn(&shell, &self.working_dir(), 24, 80, self.waker.clone()) {
+                Ok(terminal) => self.terminal = Some(terminal),
+                Err(e) => {
+                    self.report_error(format!("Cannot start terminal: {}", e));
+                    return;
+                }
+            }
+        }
+        self.terminal_pending.clear();
+        self.mode = Mode::Terminal;
+    }
+
+    /// Forward keys to the terminal until the escape sequence is typed
+    fn handle_terminal_mode(&mut self, key: KeyEvent) -> bool {
+        let Some(escape) = parse_key_sequence(&self.config.terminal_escape, self.keymap.leader) else {
+            return false;
+        };
+        // Legacy terminals report Ctrl-\ as Ctrl-4
+        let mut combo = KeyCombo::from_event(&key);
+        if combo == KeyCombo::ctrl(KeyCode::Char('4')) {
+            combo = KeyCombo::ctrl(KeyCode::Char('\\'));
+        }
+        self.terminal_pending.push(combo);
+        if escape == self.terminal_pending {
+            self.terminal_pending.clear();
+            self.mode = Mode::Normal;
+            return false;
+        }
+        if escape.starts_with(&self.terminal_pending) {
+            return false;
+        }
+
+        // Not the escape sequence after all: send everything held back
+        let pending = std::mem::take(&mut self.terminal_pending);
+        if let Some(terminal) = &mut self.terminal {
+            for combo in &pending[..pending.len() - 1] {
+                terminal.send_key(&KeyEvent::new(combo.code, combo.modifiers));
+            }
+            terminal.send_key(&key);
+        }
+        false
+    }
+
+    /// Handle keys in normal mode
+    fn handle_normal_mode(&mut self, key: KeyEvent) -> bool {
+        // Esc cancels a command that is waiting for more keys
+        if key.code == KeyCode::Esc && self.pending_keys.is_empty() && self.has_pending_command() {
+            self.pending_op = None;
+            self.operator = None;
+            self.text_object = None;
+            self.numeric_prefix.clear();
+            self.pending_register = None;
+            self.pending_g = false;
+            return false;
+        }
+
+        // Target of a pending operator; a search (d/pat) is left to the
+        // keymap
+        if self.operator.is_some() && !matches!(key.code, KeyCode::Char('/' | '?')) {
+            self.operator_pending_key(key);
+            return false;
+        }
+
+        // Handle pending replace
+        if self.pending_op == Some('r') {
+            if let KeyCode::Char(c) = key.code {
+                if !key.modifiers.contains(KeyModifiers::CONTROL) {
+                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                    let pos = self.cursor.byte_offset(&self.buffer);
+                    // Delete current char and insert replacement
+                    let line_len = self.buffer.line_len(self.cursor.line);
+                    if self.cursor.col < line_len {
+                        self.buffer.delete(pos, pos + 1);
+                        self.buffer.insert(pos, &c.to_string());
+                    }
+                }
+            }
+            self.pending_op = None;
+            return false;
+        }
+
+        // Handle register selection ("x)
+        if self.pending_op == Some('"') {
+            if let KeyCode::Char(c) = key.code {
+                self.pending_register = Some(c);
+            }
+            self.pending_op = None;
+            return false;
+        }
+
+        // Register of q{register} and @{register}
+        if let Some(op @ ('Q' | '@')) = self.pending_op {
+            self.pending_op = None;
+            if let KeyCode::Char(c) = key.code {
+                if op == 'Q' {
+                    self.start_recording(c);
+                } else {
+                    let count = self.take_action_context().count();
+                    return self.run_macro(c, count);
+                }
+            }
+            return false;
+        }
+
+        // Handle marks (mx, 'x, `x)
+        if let Some(op @ ('m' | '\'' | '`')) = self.pending_op {
+            if let KeyCode::Char(c) = key.code {
+                match op {
+                    'm' => self.set_mark(c),
+                    _ => self.jump_to_mark(c, op == '`'),
+                }
+            }
+            self.pending_op = None;
+            return false;
+        }
+
+        // Handle pending find (f/F)
+        if self.pending_op == Some('f') || self.pending_op == Some('F') {
+            if let KeyCode::Char(c) = key.code {
+                let forward = self.pending_op == Some('f');
+                self.last_find = Some((c, forward));
+                self.find_char_on_line(c, forward);
+            }
+            self.pending_op = None;
+            return false;
+        }
+
+        if self.push_count_digit(key) {
+            return false;
+        }
+
+        // Second key of a g-prefixed command (gg, gt, gT, gq)
+        if self.pending_g {
+            self.pending_g = false;
+            match key.code {
+                KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => self.show_counts(),
+                KeyCode::Char('g') => {
                     self.cursor.move_to_buffer_start();
-                    self.pending_g = false;
+                    self.ensure_cursor_visible();
+                }
+                // Operators of two keys (gu, gU, g~, gq, gc)
+                KeyCode::Char(c @ ('u' | 'U' | '~' | 'q' | 'c')) => {
+                    let ctx = self.take_action_context();
+                    self.operator = Operator::from_key(c, true).map(|op| (op, ctx));
+                    return false;
+                }
+                KeyCode::Char('J') => {
+                    let count = self.take_action_context().count();
+                    self.join_at_cursor(count, true);
+                }
+                KeyCode::Char('a') => self.show_char_info(),
+                // Insert again where insert mode was last left
+                KeyCode::Char('i') => {
+                    let file = self.buffer.file_path().cloned();
+                    if let Some(mark) = self.marks.get(mark::LAST_INSERT, file.as_deref()) {
+                        self.cursor.line = mark.line;
+                        self.cursor.col = mark.col;
+                        self.cursor.clamp(&self.buffer);
+                        self.ensure_cursor_visible();
+                    }
+                    self.mode = Mode::Insert;
+                }
+                KeyCode::Char('t') => {
+                    self.leave_buffer();
+                    self.buffer_manager.next_buffer();
+                    self.buffer = self.buffer_manager.current_buffer().clone();
+                    self.reset_cursor();
+                    self.scroll_offset = 0;
+                    self.restore_position();
+                    self.show_bookmarks();
+                    self.detect_syntax();
+                    self.refresh_git_base();
+                    self.message = Some("Switched to next buffer".to_string());
+                    self.load_project_config();
+                }
+                KeyCode::Char('T') => {
+                    self.leave_buffer();
+                    self.buffer_manager.prev_buffer();
+                    self.buffer = self.buffer_manager.current_buffer().clone();
+                    self.reset_cursor();
+                    self.scroll_offset = 0;
+                    self.restore_position();
+                    self.show_bookmarks();
+                    self.detect_syntax();
+                    self.refresh_git_base();
+                    self.message = Some("Switched to previous buffer".to_string());
+                    self.load_project_config();
+                }
+                _ => {}
+            }
+            self.numeric_prefix.clear();
+            return false;
+        }
+
+        // User-remappable bindings take precedence over the built-in commands
+        if let Some(quit) = self.dispatch_keymap(key) {
+            return quit;
+        }
+        self.handle_normal_builtin(key)
+    }
+
+    /// Add a digit to the numeric prefix (1-9, but skip 0 as it's line
+    /// start). Digits inside a pending key sequence belong to the sequence.
+    fn push_count_digit(&mut self, key: KeyEvent) -> bool {
+        if !self.pending_keys.is_empty() {
+            return false;
+        }
+        match key.code {
+            KeyCode::Char(c @ '1'..='9') => self.numeric_prefix.push(c),
+            KeyCode::Char('0') if !self.numeric_prefix.is_empty() => self.numeric_prefix.push('0'),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Built-in normal mode commands for keys without a keymap binding
+    fn handle_normal_builtin(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('g') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Set pending_g for next character (gt, gT, gg)
+                self.pending_g = true;
+                return false;
+            }
+            
+            // Close the help buffer
+            KeyCode::Char('q') if self.buffer.scratch_name() == Some(HELP_BUFFER) => self.close_help(),
+
+            // Repeat the last :s on this line
+            KeyCode::Char('&') => self.repeat_substitute(substitute::Range::Current, false),
+
+            // Collapse multiple cursors
+            KeyCode::Esc if !self.cursors.is_empty() => self.cursors.clear(),
+
+            // Close the popups Lua opened
+            KeyCode::Esc if !self.popups.is_empty() => self.popups.clear(),
+
+            // Match bracket (%)
+            KeyCode::Char('%') => {
+                if let Some((line, col)) = self.find_matching_pair()
+                    .or_else(|| textobject::matching_bracket(&self.buffer, (self.cursor.line, self.cursor.col))) {
+                    self.cursor.line = line;
+                    self.cursor.col = col;
+                    self.ensure_cursor_visible();
+                }
+            }
+            
+            // Marks
+            KeyCode::Char('m') => self.pending_op = Some('m'),
+            KeyCode::Char('\'') => self.pending_op = Some('\''),
+            KeyCode::Char('`') => self.pending_op = Some('`'),
+
+            // Find character on line
+            KeyCode::Char('f') if !key.modifiers.contains(KeyModifiers::CONTROL) => self.pending_op = Some('f'),
+            KeyCode::Char('F') => self.pending_op = Some('F'),
+            KeyCode::Char(';') => {
+                // Repeat last find
+                if let Some((c, forward)) = self.last_find {
+                    self.find_char_on_line(c, forward);
+                }
+            }
+            KeyCode::Char(',') => {
+                // Repeat last find in opposite direction
+                if let Some((c, forward)) = self.last_find {
+                    self.find_char_on_line(c, !forward);
+                }
+            }
+            
+            // Word search (* and #): the word under the cursor becomes the
+            // current match, so n and N go on from it
+            KeyCode::Char('*') | KeyCode::Char('#') => {
+                if let Some(word) = self.get_word_under_cursor() {
+                    let forward = key.code == KeyCode::Char('*');
+                    self.search.start(if forward { SearchDirection::Forward } else { SearchDirection::Backward });
+                    let lines: Vec<String> = (0..self.buffer.line_count())
+                        .map(|i| self.buffer.line(i))
+                        .collect();
+                    self.search.set_pattern(&format!("\\b{}\\b", regex::escape(&word)));
+                    self.search.execute(&lines, self.cursor.line, self.cursor.col);
+
+                    if self.search.select_match_at(self.cursor.line, self.cursor.col).is_none() {
+                        if let Some(m) = self.search.current() {
+                            self.cursor.line = m.line;
+                            self.cursor.col = m.start_col;
+                            self.ensure_cursor_visible();
+                        }
+                    }
+                    self.message = Some(format!("Found: {}", word));
+                }
+            }
+            
+            // Select register for the next command ("x)
+            KeyCode::Char('"') => self.pending_op = Some('"'),
+
+            // Record a macro (q{register}, q again stops) and run one
+            // (@{register}, @@ runs the last one again); pending 'q' is gq
+            KeyCode::Char('q') if self.recording.is_some() => self.stop_recording(),
+            KeyCode::Char('q') => self.pending_op = Some('Q'),
+            KeyCode::Char('@') => self.pending_op = Some('@'),
+
+            // Operators wait for their target: dw, c$, y2j, dd, 3cc
+            KeyCode::Char(c @ ('d' | 'c' | 'y' | '>' | '<')) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let ctx = self.take_action_context();
+                self.operator = Operator::from_key(c, false).map(|op| (op, ctx));
+            }
+
+            // Delete to end of line (D)
+            KeyCode::Char('D') => {
+                let ctx = self.take_action_context();
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                let line = self.buffer.line(self.cursor.line);
+                let chars: Vec<char> = line.chars().collect();
+                if self.cursor.col < chars.len() {
+                    let deleted: String = chars[self.cursor.col..].iter()
+                        .collect::<String>()
+                        .trim_end_matches('\n')
+                        .to_string();
+                    if !deleted.is_empty() {
+                        self.store_register(ctx.register, RegisterContent::Chars(deleted), true);
+                    }
+                    // Delete from cursor to end of line (keep newline)
+                    let start = self.buffer.line_to_byte(self.cursor.line) 
+                        + self.buffer.col_to_byte(self.cursor.line, self.cursor.col);
+                    let end = if self.cursor.line + 1 < self.buffer.line_count() {
+                        self.buffer.line_to_byte(self.cursor.line + 1) - 1
+                    } else {
+                        self.buffer.len()
+                    };
+                    if start < end {
+                        self.buffer.delete(start, end);
+                    }
+                }
+                self.cursor.clamp(&self.buffer);
+            }
+            
+            // Change to end of line (C)
+            KeyCode::Char('C') => {
+                let ctx = self.take_action_context();
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                let line = self.buffer.line(self.cursor.line);
+                let chars: Vec<char> = line.chars().collect();
+                if self.cursor.col < chars.len() {
+                    let deleted: String = chars[self.cursor.col..].iter()
+                        .collect::<String>()
+                        .trim_end_matches('\n')
+                        .to_string();
+                    if !deleted.is_empty() {
+                        self.store_register(ctx.register, RegisterContent::Chars(deleted), true);
+                    }
+                    let start = self.buffer.line_to_byte(self.cursor.line) 
+                        + self.buffer.col_to_byte(self.cursor.line, self.cursor.col);
+                    let end = if self.cursor.line + 1 < self.buffer.line_count() {
+                        self.buffer.line_to_byte(self.cursor.line + 1) - 1
+                    } else {
+                        self.buffer.len()
+                    };
+                    if start < end {
+                        self.buffer.delete(start, end);
+                    }
+                }
+                self.mode = Mode::Insert;
+            }
+            
+            // Replace character (r)
+            KeyCode::Char('r') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Set pending replace mode - next char will replace current
+                self.pending_op = Some('r');
+            }
+            
+            // Toggle case (~), of the next `count` characters
+            KeyCode::Char('~') => {
+                let count = self.take_action_context().count();
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                for _ in 0..count {
+                    if self.cursor.col >= self.buffer.line_len(self.cursor.line) {
+                        break;
+                    }
+                    if let Some(c) = self.buffer.char_at(self.cursor.line, self.cursor.col) {
+                        let toggled = if c.is_uppercase() {
+                            c.to_lowercase().to_string()
+                        } else {
+                            c.to_uppercase().to_string()
+                        };
+                        let pos = self.cursor.byte_offset(&self.buffer);
+                        self.buffer.delete(pos, pos + c.len_utf8());
+                        self.buffer.insert(pos, &toggled);
+                        self.cursor.move_right(&self.buffer);
+                    }
+                }
+            }
+            
+            // Full page up (Ctrl+B)
+            KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.cursor.line = self.cursor.line.saturating_sub(self.viewport_height);
+                self.ensure_cursor_visible();
+            }
+            
+            // Full page down (Ctrl+F)
+            KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let max_line = self.buffer.line_count().saturating_sub(1);
+                self.cursor.line = (self.cursor.line + self.viewport_height).min(max_line);
+                self.ensure_cursor_visible();
+            }
+            
+            // Add the count to the number at or after the cursor (Ctrl+A),
+            // or subtract it (Ctrl+X)
+            KeyCode::Char(c @ ('a' | 'x')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let count = self.take_action_context().count() as i64;
+                self.increment_at_cursor(if c == 'a' { count } else { -count });
+            }
+
+            // File info (Ctrl+G)
+            KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let lines = self.buffer.line_count();
+                let chars = self.buffer.len();
+                let file_name = self.buffer.file_name().unwrap_or("[No Name]");
+                let modified = if self.buffer.is_modified() { " [Modified]" } else { "" };
+                let pos = format!("line {}/{}", self.cursor.line + 1, lines);
+                self.message = Some(format!(
+                    "\"{}\"{} {} chars, {}; {}",
+                    file_name,
+                    modified,
+                    chars,
+                    pos,
+                    self.history_summary()
+                ));
+            }
+            
+            _ => {
+                // Clear numeric prefix for commands that don't use it
+                self.numeric_prefix.clear();
+            }
+        }
+        false
+    }
+
+    /// Handle keys in insert mode
+    fn handle_insert_mode(&mut self, key: KeyEvent) -> bool {
+        if self.handle_completion_key(key) {
+            return false;
+        }
+        if let Some(quit) = self.dispatch_keymap(key) {
+            return quit;
+        }
+        self.handle_insert_builtin(key)
+    }
+
+    /// CTRL-X and the keys of an open completion: CTRL-N/CTRL-P (or
+    /// Down/Up) pick a candidate, CTRL-Y keeps it and CTRL-E restores what
+    /// was typed. Any other key keeps the candidate and is handled as
+    /// usual. Returns true if the key was used here.
+    fn handle_completion_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if std::mem::take(&mut self.pending_ctrl_x) {
+            self.message = None;
+            match key.code {
+                KeyCode::Char('f') if ctrl => self.start_path_completion(),
+                KeyCode::Char('l') if ctrl => self.start_line_completion(),
+                _ => return false,
+            }
+            return true;
+        }
+        if let Some(completion) = &mut self.completion {
+            match key.code {
+                KeyCode::Char('n' | 'f' | 'l') if ctrl => completion.step(true),
+                KeyCode::Down => completion.step(true),
+                KeyCode::Char('p') if ctrl => completion.step(false),
+                KeyCode::Up => completion.step(false),
+                KeyCode::Char('y') if ctrl => {
+                    self.completion = None;
+                    self.message = None;
+                    return true;
+                }
+                KeyCode::Char('e') if ctrl => {
+                    completion.selected = None;
+                    self.apply_completion();
+                    self.completion = None;
+                    self.message = None;
+                    return true;
+                }
+                _ => {
+                    self.completion = None;
+                    self.message = None;
+                    return false;
+                }
+            }
+            self.apply_completion();
+            return true;
+        }
+        if ctrl && key.code == KeyCode::Char('x') && self.cursors.is_empty() {
+            self.pending_ctrl_x = true;
+            self.message = Some("-- ^X mode (^F^L)".to_string());
+            return true;
+        }
+        false
+    }
+
+    /// Complete the file name before the cursor (CTRL-X CTRL-F); relative
+    /// paths are looked up in the working directory
+    fn start_path_completion(&mut self) {
+        let line = self.buffer.line(self.cursor.line);
+        let before = &line[..self.buffer.col_to_byte(self.cursor.line, self.cursor.col)];
+        let fragment = complete::path_fragment(before);
+        let candidates = complete::complete_path(fragment, &self.working_dir());
+        let start_col = self.cursor.col - fragment.chars().count();
+        self.start_completion(CompletionKind::Path, start_col, fragment.to_string(), candidates);
+    }
+
+    /// Complete the line typed so far to a whole line (CTRL-X CTRL-L):
+    /// lines of this buffer nearest the cursor first, then those of the
+    /// other open buffers
+    fn start_line_completion(&mut self) {
+        let line = self.buffer.line(self.cursor.line);
+        let typed = line[..self.buffer.col_to_byte(self.cursor.line, self.cursor.col)].to_string();
+        let managed = self.is_managed_buffer();
+        let current = self.cursor.line;
+        let above = (0..current).rev().map(|idx| self.buffer.line(idx));
+        let below = (current + 1..self.buffer.line_count()).map(|idx| self.buffer.line(idx));
+        let others = (0..self.buffer_manager.buffer_count())
+            .filter(|&idx| !(managed && idx == self.buffer_manager.current_index()))
+            .filter_map(|idx| self.buffer_manager.get(idx))
+            .flat_map(|buffer| (0..buffer.line_count()).map(|idx| buffer.line(idx)));
+        let candidates = complete::complete_line(&typed, above.chain(below).chain(others));
+        self.start_completion(CompletionKind::Line, 0, typed, candidates);
+    }
+
+    /// Open a completion of the text from `start_col` to the cursor with
+    /// its first candidate in place; a single candidate is simply taken
+    fn start_completion(&mut self, kind: CompletionKind, start_col: usize, original: String, candidates: Vec<String>) {
+        if candidates.is_empty() {
+            self.message = Some(format!("-- {}: Pattern not found", kind.name()));
+            return;
+        }
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        let mut completion = Completion {
+            kind,
+            line: self.cursor.line,
+            start_col,
+            original,
+            candidates,
+            selected: None,
+        };
+        completion.step(true);
+        let single = completion.candidates.len() == 1;
+        self.completion = Some(completion);
+        self.apply_completion();
+        if single {
+            self.completion = None;
+            self.message = None;
+        }
+    }
+
+    /// Put the selected completion in place of the completed text
+    fn apply_completion(&mut self) {
+        let Some(completion) = &self.completion else { return };
+        let line_start = self.buffer.line_to_byte(completion.line);
+        let from = line_start + self.buffer.col_to_byte(completion.line, completion.start_col);
+        let to = line_start + self.buffer.col_to_byte(completion.line, self.cursor.col);
+        let text = completion.text().to_string();
+        let name = completion.kind.name();
+        self.message = Some(match completion.selected {
+            Some(idx) => format!("-- {}: match {} of {}", name, idx + 1, completion.candidates.len()),
+            None => format!("-- {}: back at original", name),
+        });
+        self.cursor.col = completion.start_col + text.chars().count();
+        if from < to {
+            self.buffer.delete(from, to);
+        }
+        self.buffer.insert(from, &text);
+    }
+
+    /// Popups opened with quirks.popup, the last one on top
+    pub fn popups(&self) -> impl Iterator<Item = &lua::Popup> {
+        self.popups.iter().map(|(_, popup)| popup)
+    }
+
+    /// The completion popup's state, while one is open in insert mode
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref().filter(|_| self.mode == Mode::Insert)
+    }
+
+    /// Built-in insert mode handling (typing, Enter, Backspace, arrows)
+    fn handle_insert_builtin(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Esc => self.exit_insert_mode(),
+            // Delete the word before the cursor (CTRL-W) or back to the
+            // indentation, then the line start (CTRL-U); at the start of
+            // a line both join it to the one above
+            KeyCode::Char(c @ ('w' | 'u')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.edit_at_cursors(|buffer, cursor| {
+                    if cursor.col == 0 {
+                        (cursor.line, cursor.col) = buffer.backspace(cursor.line, cursor.col);
+                        return;
+                    }
+                    let start = if c == 'w' {
+                        cursor.word_start_before(buffer)
+                    } else {
+                        let indent = buffer.line(cursor.line).chars().take_while(|c| c.is_whitespace()).count();
+                        if cursor.col > indent { indent } else { 0 }
+                    };
+                    buffer.delete_cols(cursor.line, start, cursor.col);
+                    cursor.col = start;
+                });
+                self.ensure_cursor_visible();
+            }
+            // Indent (CTRL-T) or outdent (CTRL-D) the line by tab_width
+            KeyCode::Char(c @ ('t' | 'd')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.shift_insert_line(c == 't');
+            }
+            KeyCode::Char(c) => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.edit_at_cursors(|buffer, cursor| {
+                    let pos = cursor.byte_offset(buffer);
+                    buffer.insert_char(pos, c);
+                    cursor.col += 1;
+                });
+                if c == '|' && self.config.table_auto_align && self.cursors.is_empty() {
+                    self.align_table(true);
+                }
+            }
+            KeyCode::Enter => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.edit_at_cursors(|buffer, cursor| {
+                    let pos = cursor.byte_offset(buffer);
+                    buffer.insert_char(pos, '\n');
+                    cursor.line += 1;
+                    cursor.col = 0;
+                });
+                self.ensure_cursor_visible();
+            }
+            KeyCode::Backspace => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.edit_at_cursors(|buffer, cursor| {
+                    (cursor.line, cursor.col) = buffer.backspace(cursor.line, cursor.col);
+                });
+                self.ensure_cursor_visible();
+            }
+            KeyCode::Delete => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.edit_at_cursors(|buffer, cursor| buffer.delete_grapheme(cursor.line, cursor.col));
+            }
+            KeyCode::Tab => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                let (tab_width, expand_tab) = (self.config.tab_width.max(1), self.config.expand_tab);
+                self.edit_at_cursors(|buffer, cursor| {
+                    // Spaces up to the next tab stop, or a tab
+                    let text = if expand_tab {
+                        let line = buffer.line(cursor.line);
+                        let before = &line[..buffer.col_to_byte(cursor.line, cursor.col)];
+                        " ".repeat(tab_width - indent::display_width(before, tab_width) % tab_width)
+                    } else {
+                        "\t".to_string()
+                    };
+                    let pos = cursor.byte_offset(buffer);
+                    buffer.insert(pos, &text);
+                    cursor.col += text.chars().count();
+                });
+            }
+            KeyCode::Left => self.cursor.move_left(&self.buffer),
+            KeyCode::Right => self.cursor.move_right(&self.buffer),
+            KeyCode::Up => {
+                self.cursor.move_up(&self.buffer);
+                self.ensure_cursor_visible();
+            }
+            KeyCode::Down => {
+                self.cursor.move_down(&self.buffer);
+                self.ensure_cursor_visible();
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Indent or outdent the cursor line to the next multiple of
+    /// `tab_width`, keeping the cursor on the same text (CTRL-T, CTRL-D)
+    fn shift_insert_line(&mut self, right: bool) {
+        let tab_width = self.config.tab_width.max(1);
+        let line = self.buffer.line(self.cursor.line);
+        let (indent, width) = indent::leading_whitespace(&line, tab_width);
+        let new_width = if right {
+            (width / tab_width + 1) * tab_width
+        } else {
+            width.saturating_sub(1) / tab_width * tab_width
+        };
+        if !right && width == 0 {
+            return;
+        }
+        let new_indent = indent::make_indent(new_width, tab_width, self.config.expand_tab);
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        let old_len = indent.chars().count();
+        self.buffer.delete_cols(self.cursor.line, 0, old_len);
+        let line_start = self.buffer.line_to_byte(self.cursor.line);
+        self.buffer.insert(line_start, &new_indent);
+        let new_len = new_indent.chars().count();
+        self.cursor.col = (self.cursor.col.max(old_len) + new_len).saturating_sub(old_len);
+    }
+
+    /// Handle keys in command mode
+    fn handle_command_mode(&mut self, key: KeyEvent) -> bool {
+        // The preview is rebuilt after every key, so commands always run on
+        // the real buffer
+        self.end_substitute_preview();
+        let quit = self.handle_command_key(key);
+        if self.mode == Mode::Command {
+            self.preview_substitute();
+        }
+        quit
+    }
+
+    fn handle_command_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.command_buffer.clear();
+                self.history_index = None;
+            }
+            KeyCode::Enter => {
+                let cmd = self.command_buffer.clone();
+                add_history(&mut self.command_history, cmd.trim());
+                self.history_index = None;
+                let should_quit = self.execute_command(&cmd);
+                // What a command shows is kept for :messages as well
+                if let Some(message) = self.message.as_ref().filter(|_| !self.message_logged) {
+                    crate::log::info(format!(":{}\n{}", cmd.trim(), message));
+                    self.message_logged = true;
+                }
+                // Don't reset mode if command changed it (e.g., to Terminal)
+                if self.mode == Mode::Command {
+                    self.mode = Mode::Normal;
+                }
+                self.command_buffer.clear();
+                return should_quit;
+            }
+            KeyCode::Backspace if self.command_buffer.pop().is_none() => {
+                self.mode = Mode::Normal;
+                self.history_index = None;
+            }
+            KeyCode::Up => self.recall_history(false, true),
+            KeyCode::Down => self.recall_history(false, false),
+            KeyCode::Tab => self.complete_tag_command(),
+            KeyCode::Char(c) => {
+                self.command_buffer.push(c);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Execute an ex command (the text after ':'), returns true if editor
+    /// should quit
+    pub fn execute_command(&mut self, cmd: &str) -> bool {
+        self.changes += 1;
+        let cmd = cmd.trim();
+        // `:w ++p` creates missing directories above the file
+        let (cmd, make_dirs) = match cmd.strip_suffix("++p").map(str::trim_end) {
+            Some(write @ ("w" | "write" | "w!" | "write!" | "wq" | "x" | "wq!")) => (write, true),
+            _ => (cmd, false),
+        };
+        match cmd {
+            "q" | "quit" if self.buffer.scratch_name() == Some(HELP_BUFFER) => self.close_help(),
+            "w" | "write" | "w!" | "write!" | "wq" | "x" | "wq!" if self.remote_path().is_some() => {
+                self.quit_after_transfer = matches!(cmd, "wq" | "x" | "wq!");
+                if let Some(remote) = self.remote_path() {
+                    self.save_remote(remote);
+                }
+            }
+            "q" | "quit" | "qa" | "qall" => return self.request_quit(),
+            "q!" | "quit!" => return true,
+            "w" | "write" | "wq" | "x" if self.buffer.is_readonly() => self.report_error(READONLY_ERROR.to_string()),
+            "w" | "write" | "w!" | "write!" => {
+                if !self.prepare_parent_dir(cmd, make_dirs) {
+                    return false;
+                }
+                self.fire_buffer_autocmd(lua::Event::BufWritePre);
+                if let Err(e) = self.buffer.save() {
+                    self.report_error(format!("Error saving: {}", e));
+                } else {
+                    self.refresh_git_base();
+                    self.message = Some("Written".to_string());
+                    self.fire_buffer_autocmd(lua::Event::BufWritePost);
+                }
+            }
+            "wq" | "x" | "wq!" => {
+                if !self.prepare_parent_dir(cmd, make_dirs) {
+                    return false;
+                }
+                self.fire_buffer_autocmd(lua::Event::BufWritePre);
+                if let Err(e) = self.buffer.save() {
+                    self.report_error(format!("Error saving: {}", e));
                 } else {
-                    // Set pending_g for next character (gt, gT, gg)
-                    self.pending_g = true;
-                    return false;
+                    self.fire_buffer_autocmd(lua::Event::BufWritePost);
+                    return self.request_quit();
                 }
             }
-            KeyCode::Char('G') => self.cursor.move_to_buffer_end(&self.buffer),
-            
-            // Tab navigation (gt/gT when pending_g)
-            KeyCode::Char('t') if self.pending_g => {
-                self.buffer_manager.next_buffer();
-                self.buffer = self.buffer_manager.current_buffer().clone();
-                self.cursor = Cursor::new();
-                self.scroll_offset = 0;
-                self.message = Some("Switched to next buffer".to_string());
-                self.pending_g = false;
+            "wa" | "wall" => {
+                self.write_all();
             }
-            KeyCode::Char('T') if self.pending_g => {
-                self.buffer_manager.prev_buffer();
-                self.buffer = self.buffer_manager.current_buffer().clone();
-                self.cursor = Cursor::new();
-                self.scroll_offset = 0;
-                self.message = Some("Switched to previous buffer".to_string());
-                self.pending_g = false;
+            "wqa" | "wqall" | "xa" | "xall" => return self.write_all(),
+            "qa!" | "qall!" => {
+                // Force quit all
+                return true;
             }
-            
-            // Match bracket (%)
-            KeyCode::Char('%') => {
-                if let Some((line, col)) = self.find_matching_bracket() {
-                    self.cursor.line = line;
-                    self.cursor.col = col;
-                    self.ensure_cursor_visible();
+            "DiffSaved" | "w diff" => {
+                if let Some(path) = self.local_file() {
+                    self.diff_split(&path.to_string_lossy());
                 }
             }
-            
-            // Word motions
-            KeyCode::Char('w') => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
+            // Without a range, `:w >> file` appends the whole buffer
+            _ if ex_argument(cmd, WRITE).is_some_and(|args| args.trim_start_matches('!').trim_start().starts_with(">>")) => {
+                let args = ex_argument(cmd, WRITE).unwrap_or_default();
+                let (force, args) = match args.strip_prefix('!') {
+                    Some(args) => (true, args),
+                    None => (false, args),
                 };
-                for _ in 0..count {
-                    self.cursor.move_word_forward(&self.buffer);
-                }
-                self.numeric_prefix.clear();
+                self.write_range(substitute::Range::All, args.trim(), force);
             }
-            KeyCode::Char('b') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
+            _ if cmd.starts_with("w ") || cmd.starts_with("w! ") => {
+                let (force, path) = match cmd.strip_prefix("w!") {
+                    Some(path) => (true, path.trim()),
+                    None => (false, cmd["w".len()..].trim()),
                 };
-                for _ in 0..count {
-                    self.cursor.move_word_backward(&self.buffer);
+                let current = self.buffer.file_path().is_some_and(|file| file == Path::new(path));
+                if !force && current && self.buffer.is_readonly() {
+                    self.report_error(READONLY_ERROR.to_string());
+                    return false;
                 }
-                self.numeric_prefix.clear();
-            }
-            KeyCode::Char('e') => {
-                let count = if self.numeric_prefix.is_empty() {
-                    1
-                } else {
-                    self.numeric_prefix.parse().unwrap_or(1)
-                };
-                for _ in 0..count {
-                    self.cursor.move_word_end(&self.buffer);
+                if !force && !current && Path::new(path).exists() {
+                    self.confirm(format!("{} exists, overwrite it?", path), format!("w! {}", path));
+                    return false;
                 }
-                self.numeric_prefix.clear();
-            }
-            
-            // Find character on line
-            KeyCode::Char('f') if !key.modifiers.contains(KeyModifiers::CONTROL) => self.pending_op = Some('f'),
-            KeyCode::Char('F') => self.pending_op = Some('F'),
-            KeyCode::Char(';') => {
-                // Repeat last find
-                if let Some((c, forward)) = self.last_find {
-                    self.find_char_on_line(c, forward);
+                self.fire_autocmd(lua::Event::BufWritePre, path);
+                if let Err(e) = self.buffer.save_as(path) {
+                    self.report_error(format!("Error saving: {}", e));
+                } else {
+                    self.message = Some(format!("Written: {}", path));
+                    self.fire_buffer_autocmd(lua::Event::BufWritePost);
                 }
             }
-            KeyCode::Char(',') => {
-                // Repeat last find in opposite direction
-                if let Some((c, forward)) = self.last_find {
-                    self.find_char_on_line(c, !forward);
-                }
+            "noh" | "nohlsearch" => {
+                self.search.clear_highlight();
             }
-            
-            // Word search (* and #)
-            KeyCode::Char('*') | KeyCode::Char('#') => {
-                if let Some(word) = self.get_word_under_cursor() {
-                    let forward = key.code == KeyCode::Char('*');
-                    self.search.start(if forward { SearchDirection::Forward } else { SearchDirection::Backward });
-                    let lines: Vec<String> = (0..self.buffer.line_count())
-                        .map(|i| self.buffer.line(i))
-                        .collect();
-                    self.search.set_pattern(&format!("\\b{}\\b", regex::escape(&word)));
-                    self.search.execute(&lines, self.cursor.line, self.cursor.col);
-                    
-                    if let Some(m) = self.search.current() {
-                        self.cursor.line = m.line;
-                        self.cursor.col = m.start_col;
-                        self.ensure_cursor_visible();
-                        self.message = Some(format!("Found: {}", word));
+            _ if cmd.starts_with("diffsplit ") || cmd.starts_with("diffs ") => {
+                let arg = cmd.split_once(' ').map_or("", |(_, arg)| arg);
+                self.diff_split(arg);
+            }
+            "diffoff" | "diffo" => {
+                self.diff = None;
+            }
+            _ if cmd.starts_with("tag ") || cmd.starts_with("ta ") => {
+                let name = cmd.split_once(' ').map_or("", |(_, name)| name).trim();
+                self.jump_to_tag(name);
+            }
+            "pop" | "po" => self.pop_tag(),
+            "Gblame" | "Gitblame" => {
+                self.show_blame = !self.show_blame;
+                if self.show_blame {
+                    self.update_blame();
+                    if self.blame.is_none() {
+                        self.show_blame = false;
+                        self.message = Some("No git blame for this file".to_string());
                     }
+                } else {
+                    self.buffer.clear_decorations(BLAME_NAMESPACE);
                 }
             }
-            
-            // Mode switching
-            KeyCode::Char('i') => self.mode = Mode::Insert,
-            KeyCode::Char('a') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.cursor.move_right(&self.buffer);
-                self.mode = Mode::Insert;
+            "Explore" | "Ex" | "Lexplore" | "Lex" => self.toggle_explorer(),
+            "Files" => self.open_picker(PickerKind::Files),
+            "Buffers" => self.open_picker(PickerKind::Buffers),
+            "Lines" => self.open_picker(PickerKind::Lines),
+            "oldfiles" | "ol" | "browse oldfiles" | "bro ol" => self.open_picker(PickerKind::Recent),
+            _ if cmd.starts_with("ExplorerCreate ") || cmd.starts_with("ExplorerRename ") || cmd == "ExplorerDelete" => {
+                self.explorer_file_operation(cmd);
+            }
+            _ if matches!(cmd, "Rename" | "Rename!") || cmd.starts_with("Rename ") || cmd.starts_with("Rename! ") => {
+                let (force, name) = match cmd.strip_prefix("Rename!") {
+                    Some(name) => (true, name),
+                    None => (false, &cmd["Rename".len()..]),
+                };
+                self.rename_file(name.trim(), force);
             }
-            KeyCode::Char('I') => {
-                self.cursor.move_to_line_start();
-                self.mode = Mode::Insert;
+            "Delete" => self.delete_file(false),
+            "Delete!" => self.delete_file(true),
+            "help" | "h" | "?" => self.show_help(""),
+            _ if cmd.starts_with("help ") || cmd.starts_with("h ") => {
+                self.show_help(cmd.split_once(' ').unwrap().1);
             }
-            KeyCode::Char('A') => {
-                self.cursor.move_to_line_end(&self.buffer);
-                self.mode = Mode::Insert;
+            "helpclose" | "helpc" => self.close_help(),
+            "version" | "ver" => {
+                self.message = Some("Quirks v0.3.1 - A modal text editor".to_string());
             }
-            KeyCode::Char('o') => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                self.cursor.move_to_line_end(&self.buffer);
-                let pos = self.cursor.byte_offset(&self.buffer);
-                self.buffer.insert(pos, "\n");
-                self.cursor.move_down(&self.buffer);
-                self.cursor.move_to_line_start();
-                self.mode = Mode::Insert;
+            "pwd" | "pw" => {
+                let local = if self.buffer.local_dir().is_some() { " [buffer]" } else { "" };
+                self.message = Some(format!("{}{}", self.working_dir().display(), local));
+            }
+            "cd" => self.change_dir("", false),
+            "lcd" => self.change_dir("", true),
+            _ if cmd == "lua" || cmd.starts_with("lua ") => return self.run_lua(cmd[3..].trim()),
+            _ if cmd.starts_with("luafile ") || cmd.starts_with("luaf ") => {
+                // % is the current file, to reload the Lua being edited
+                let path = match cmd.split_once(' ').map_or("", |(_, path)| path.trim()) {
+                    "%" => self.buffer.file_path().cloned(),
+                    path => Some(PathBuf::from(path)),
+                };
+                match path {
+                    Some(path) => self.source_lua(&path),
+                    None => self.report_error("The buffer has no file name"),
+                }
+                return std::mem::take(&mut self.lua_quit);
             }
-            KeyCode::Char('O') => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                self.cursor.move_to_line_start();
-                let pos = self.cursor.byte_offset(&self.buffer);
-                self.buffer.insert(pos, "\n");
-                self.mode = Mode::Insert;
+            _ if cmd.starts_with("cd ") => self.change_dir(&cmd[3..], false),
+            _ if cmd.starts_with("lcd ") => self.change_dir(&cmd[4..], true),
+            "cdroot" | "Rooter" => {
+                let root = self.project_root();
+                self.change_dir(&root.to_string_lossy(), false);
             }
-            
-            // Deletion
-            KeyCode::Char('x') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                // Yank the character before deleting
-                if let Some(ch) = self.buffer.char_at(self.cursor.line, self.cursor.col) {
-                    self.registers.delete(RegisterContent::Chars(ch.to_string()));
-                }
-                self.buffer.delete_grapheme(self.cursor.line, self.cursor.col);
-                self.cursor.clamp(&self.buffer);
+            "messages" | "mes" => self.show_messages(),
+            "jumps" => {
+                // Placeholder for jump list
+                self.message = Some("Jump list not yet implemented".to_string());
             }
-            
-            // Yank line (yy)
-            KeyCode::Char('y') => {
-                if self.pending_op == Some('y') {
-                    // yy - yank current line
-                    let line = self.buffer.line(self.cursor.line);
-                    let content = if line.ends_with('\n') {
-                        line
-                    } else {
-                        format!("{}\n", line)
-                    };
-                    self.registers.yank(RegisterContent::Lines(content));
-                    self.message = Some("1 line yanked".to_string());
-                    self.pending_op = None;
+            "bookmarks" => self.open_picker(PickerKind::Bookmarks),
+            _ if cmd == "trust" || cmd.starts_with("trust ") => self.trust_project_config(cmd[5..].trim()),
+            _ if cmd == "bookmark" || cmd == "bm" || cmd.starts_with("bookmark ") || cmd.starts_with("bm ") => {
+                let arg = cmd.split_once(' ').map_or("", |(_, arg)| arg);
+                self.bookmark_command(arg);
+            }
+            "marks" => {
+                let file = self.buffer.file_path().cloned();
+                let marks = self.marks.list(file.as_deref());
+                self.message = Some(if marks.is_empty() {
+                    "No marks set".to_string()
                 } else {
-                    self.pending_op = Some('y');
+                    marks.iter()
+                        .map(|(name, mark)| {
+                            let file = mark.file.as_ref()
+                                .filter(|path| Some(*path) != file.as_ref())
+                                .and_then(|path| path.file_name())
+                                .map(|name| format!(" {}", name.to_string_lossy()))
+                                .unwrap_or_default();
+                            format!("{} {}:{}{}", name, mark.line + 1, mark.col + 1, file)
+                        })
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                });
+            }
+            _ if matches!(substitute::parse_range(cmd).1.trim(), "count" | "stats" | "wc") => {
+                let range = substitute::parse_range(cmd).0;
+                self.count_command(range);
+            }
+            "ascii" | "as" => self.show_char_info(),
+            _ if matches!(
+                substitute::parse_range(cmd).1.split_whitespace().next(),
+                Some("retab" | "retab!" | "ret" | "ret!")
+            ) =>
+            {
+                let (range, rest) = substitute::parse_range(cmd);
+                let arg = rest.split_whitespace().nth(1);
+                match arg.map(str::parse::<usize>) {
+                    Some(Err(_)) => self.report_error(format!("Invalid tab width: {}", arg.unwrap_or_default())),
+                    width => self.retab(range.unwrap_or(substitute::Range::All), width.and_then(Result::ok)),
                 }
             }
-            
-            // Delete line (dd)
-            KeyCode::Char('d') => {
-                if self.pending_op == Some('d') {
-                    // dd - delete current line
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    let line = self.buffer.line(self.cursor.line);
-                    let content = if line.ends_with('\n') {
-                        line
-                    } else {
-                        format!("{}\n", line)
-                    };
-                    self.registers.delete(RegisterContent::Lines(content));
-                    self.buffer.delete_line(self.cursor.line);
-                    self.cursor.clamp(&self.buffer);
-                    self.ensure_cursor_visible();
-                    self.message = Some("1 line deleted".to_string());
-                    self.pending_op = None;
+            _ if ex_argument(substitute::parse_range(cmd).1, MOVE).is_some() => {
+                let (range, rest) = substitute::parse_range(cmd);
+                let address = ex_argument(rest, MOVE).unwrap_or_default();
+                self.move_command(range.unwrap_or(substitute::Range::Current), address);
+            }
+            _ if ex_argument(substitute::parse_range(cmd).1, DELETE).is_some()
+                || ex_argument(substitute::parse_range(cmd).1, YANK).is_some() =>
+            {
+                let (range, rest) = substitute::parse_range(cmd);
+                let (delete, args) = match ex_argument(rest, DELETE) {
+                    Some(args) => (true, args),
+                    None => (false, ex_argument(rest, YANK).unwrap_or_default()),
+                };
+                self.delete_or_yank_command(range.unwrap_or(substitute::Range::Current), args, delete);
+            }
+            _ if substitute::parse_range(cmd).0.is_some() && ex_argument(substitute::parse_range(cmd).1, WRITE).is_some() => {
+                let (range, rest) = substitute::parse_range(cmd);
+                let args = ex_argument(rest, WRITE).unwrap_or_default();
+                let (force, args) = match args.strip_prefix('!') {
+                    Some(args) => (true, args),
+                    None => (false, args),
+                };
+                self.write_range(range.unwrap_or(substitute::Range::Current), args.trim(), force);
+            }
+            _ if ex_argument(substitute::parse_range(cmd).1, PUT).is_some() => {
+                let (range, rest) = substitute::parse_range(cmd);
+                let args = ex_argument(rest, PUT).unwrap_or_default();
+                let (above, args) = match args.strip_prefix('!') {
+                    Some(args) => (true, args),
+                    None => (false, args),
+                };
+                // `:0put` puts above the first line, which parse_range can't tell from `:1put`
+                let range = if cmd.starts_with('0') && !cmd[1..].starts_with(|c: char| c.is_ascii_digit()) {
+                    None
                 } else {
-                    self.pending_op = Some('d');
-                }
+                    range.or(Some(substitute::Range::Current))
+                };
+                self.put_command(range, above, args.trim());
             }
-            
-            // Change line (cc)
-            KeyCode::Char('c') => {
-                if self.pending_op == Some('c') {
-                    // cc - delete line content and enter insert mode
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    let line = self.buffer.line(self.cursor.line);
-                    let content = line.trim_end_matches('\n').to_string();
-                    if !content.is_empty() {
-                        self.registers.delete(RegisterContent::Chars(content));
-                    }
-                    // Clear the line content but keep the line
-                    let line_start = self.buffer.line_to_byte(self.cursor.line);
-                    let line_end = if self.cursor.line + 1 < self.buffer.line_count() {
-                        self.buffer.line_to_byte(self.cursor.line + 1) - 1
-                    } else {
-                        self.buffer.len()
-                    };
-                    if line_start < line_end {
-                        self.buffer.delete(line_start, line_end);
-                    }
-                    self.cursor.col = 0;
-                    self.mode = Mode::Insert;
-                    self.pending_op = None;
+            "exit" => {
+                // Exit editor (alias for :q)
+                if self.buffer.is_modified() {
+                    self.message = Some("File modified. Use :q! to force quit".to_string());
                 } else {
-                    self.pending_op = Some('c');
+                    return true;
                 }
             }
-            
-            // Join lines (J)
-            KeyCode::Char('J') => {
-                if self.cursor.line + 1 < self.buffer.line_count() {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    // Get current line length (for cursor positioning)
-                    let current_line_len = self.buffer.line_len(self.cursor.line);
-                    // Join the next line to current
-                    self.buffer.join_lines(self.cursor.line);
-                    // Move cursor to join point
-                    self.cursor.col = current_line_len;
-                }
+            "number" => {
+                // Toggle line numbers
+                self.config.line_numbers = !self.config.line_numbers;
+                let status = if self.config.line_numbers { "enabled" } else { "disabled" };
+                self.message = Some(format!("Line numbers {}", status));
             }
-            
-            // Delete to end of line (D)
-            KeyCode::Char('D') => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                let line = self.buffer.line(self.cursor.line);
-                let chars: Vec<char> = line.chars().collect();
-                if self.cursor.col < chars.len() {
-                    let deleted: String = chars[self.cursor.col..].iter()
-                        .collect::<String>()
-                        .trim_end_matches('\n')
-                        .to_string();
-                    if !deleted.is_empty() {
-                        self.registers.delete(RegisterContent::Chars(deleted));
-                    }
-                    // Delete from cursor to end of line (keep newline)
-                    let start = self.buffer.line_to_byte(self.cursor.line) 
-                        + self.buffer.col_to_byte(self.cursor.line, self.cursor.col);
-                    let end = if self.cursor.line + 1 < self.buffer.line_count() {
-                        self.buffer.line_to_byte(self.cursor.line + 1) - 1
-                    } else {
-                        self.buffer.len()
-                    };
-                    if start < end {
-                        self.buffer.delete(start, end);
+            "highlight" | "hlsearch" => {
+                // Toggle keeping search matches highlighted
+                self.config.hlsearch = !self.config.hlsearch;
+                let status = if self.config.hlsearch { "enabled" } else { "disabled" };
+                self.message = Some(format!("Search highlighting {}", status));
+            }
+            _ if MAP_COMMANDS.iter().any(|(name, _, _)| cmd.split(' ').next() == Some(*name)) => self.map_command(cmd),
+            "setlocal" | "setl" => {
+                let source = if self.buffer.syntax().is_some() { "" } else { " (global)" };
+                let readonly = if self.buffer.is_readonly() { " readonly" } else { "" };
+                self.message = Some(format!("syntax={}{}{}", self.syntax_enabled(), source, readonly));
+            }
+            _ if cmd.starts_with("setlocal ") || cmd.starts_with("setl ") => {
+                let option = cmd.split_once(' ').unwrap().1.trim();
+                if let Some(readonly) = match option {
+                    "readonly" | "ro" => Some(true),
+                    "noreadonly" | "noro" => Some(false),
+                    _ => None,
+                } {
+                    self.set_readonly(readonly);
+                    return false;
+                }
+                let syntax = match option {
+                    "syntax" | "syn" => Some(true),
+                    "nosyntax" | "nosyn" => Some(false),
+                    "syntax&" | "syn&" => None,
+                    _ => {
+                        self.report_error(format!("Unknown local option: {}", option));
+                        return false;
                     }
+                };
+                self.buffer.set_syntax(syntax);
+                self.message = Some(match syntax {
+                    Some(true) => "Syntax highlighting enabled in this buffer".to_string(),
+                    Some(false) => "Syntax highlighting disabled in this buffer".to_string(),
+                    None => format!("Syntax highlighting follows the global option ({})", self.config.syntax_highlighting),
+                });
+            }
+            "digraphs" => {
+                // Show digraphs (placeholder)
+                self.message = Some("Digraphs not yet implemented".to_string());
+            }
+            "abbreviate" | "abbr" => {
+                // Abbreviations (placeholder)
+                self.message = Some("Abbreviations not yet implemented".to_string());
+            }
+            "colorscheme" | "colo" => {
+                self.message = Some(format!("{} (available: {})", self.theme().name, Theme::available().join(", ")));
+            }
+            _ if cmd.starts_with("colorscheme ") || cmd.starts_with("colo ") => {
+                let name = cmd.split_once(' ').unwrap().1.trim();
+                if let Err(e) = self.config.set_option("colorscheme", Some(name)) {
+                    self.report_error(e);
                 }
-                self.cursor.clamp(&self.buffer);
             }
-            
-            // Change to end of line (C)
-            KeyCode::Char('C') => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                let line = self.buffer.line(self.cursor.line);
-                let chars: Vec<char> = line.chars().collect();
-                if self.cursor.col < chars.len() {
-                    let deleted: String = chars[self.cursor.col..].iter()
-                        .collect::<String>()
-                        .trim_end_matches('\n')
-                        .to_string();
-                    if !deleted.is_empty() {
-                        self.registers.delete(RegisterContent::Chars(deleted));
-                    }
-                    let start = self.buffer.line_to_byte(self.cursor.line) 
-                        + self.buffer.col_to_byte(self.cursor.line, self.cursor.col);
-                    let end = if self.cursor.line + 1 < self.buffer.line_count() {
-                        self.buffer.line_to_byte(self.cursor.line + 1) - 1
-                    } else {
-                        self.buffer.len()
+            "ruler" => {
+                // Show/hide ruler (placeholder)
+                self.message = Some("Ruler is always shown".to_string());
+            }
+            "history" => {
+                // Show undo history count
+                self.message = Some(format!(
+                    "Undo: {} | Redo: {}",
+                    self.buffer.undo_count(),
+                    self.buffer.redo_count()
+                ));
+            }
+            "verbose" => {
+                // Verbose mode toggle (placeholder)
+                self.message = Some("Verbose mode not yet implemented".to_string());
+            }
+            "filetype" | "ft" | "filet" => {
+                // Show the filetype and whether :set filetype chose it
+                let source = if self.buffer.filetype().is_some() { "set with :set filetype" } else { "detected" };
+                self.message = Some(match self.highlighter.current_syntax_name() {
+                    Some(name) => format!("filetype={} ({})", name.to_lowercase(), source),
+                    None => "No filetype detected".to_string(),
+                });
+            }
+            "redir" => {
+                // Redirect output (placeholder)
+                self.message = Some("Output redirection not yet implemented".to_string());
+            }
+            "terminal" | "term" => self.open_terminal(),
+            "make" | "mak" => self.run_make(""),
+            _ if cmd.starts_with("make ") || cmd.starts_with("mak ") => {
+                let args = cmd.split_once(' ').map_or("", |(_, args)| args);
+                self.run_make(args);
+            }
+            "clist" | "cl" | "copen" | "cope" => self.open_picker(PickerKind::Quickfix),
+            _ if cmd.starts_with("Run ") => self.start_job(cmd[4..].trim()),
+            "RunStop" => self.cancel_job(),
+            "RunOutput" => self.show_job_output(),
+            "zen" | "Zen" => self.toggle_zen(),
+            "profile" => {
+                let profiler = self.profiler.get_mut();
+                *profiler = match profiler {
+                    Some(_) => None,
+                    None => Some(FrameProfiler::default()),
+                };
+                self.message = Some(format!("Profiling {}", if self.profiling() { "on" } else { "off" }));
+            }
+            "sysinfo" => {
+                self.gpu_info.refresh();
+                self.show_sysinfo = true;
+            }
+            "TableFormat" => {
+                if !self.align_table(false) {
+                    self.message = Some("Not in a table".to_string());
+                }
+            }
+            _ if matches!(
+                cmd.split_whitespace().next(),
+                Some("cn" | "cnext" | "cp" | "cprev" | "cprevious" | "cN" | "cNext" | "cr" | "crewind"
+                    | "cfir" | "cfirst" | "cla" | "clast" | "cc")
+            ) =>
+            {
+                let (name, arg) = cmd.split_once(' ').unwrap_or((cmd, ""));
+                self.quickfix_command(name, arg);
+            }
+            "TermSend" => self.send_to_terminal(),
+            "debug" => {
+                // Debug info
+                self.message = Some(format!(
+                    "Buffers: {} | Mode: {} | Cursor: {}:{}",
+                    self.buffer_manager.buffer_count(),
+                    self.mode.display(),
+                    self.cursor.line + 1,
+                    self.cursor.col + 1
+                ));
+            }
+            "readonly" | "ro" => self.set_readonly(!self.buffer.is_readonly()),
+            "modifiable" | "ma" => {
+                // Modifiable toggle
+                self.message = Some("Modifiable mode not yet implemented".to_string());
+            }
+            "list" => {
+                // Show whitespace
+                self.config.show_whitespace = !self.config.show_whitespace;
+                let status = if self.config.show_whitespace { "enabled" } else { "disabled" };
+                self.message = Some(format!("Whitespace display {}", status));
+            }
+            "nolist" => {
+                // Hide whitespace
+                self.config.show_whitespace = false;
+                self.message = Some("Whitespace display disabled".to_string());
+            }
+            "set" => {
+                // Show current settings
+                self.message = Some(format!(
+                    "tab_width={} line_numbers={} syntax={}",
+                    self.config.tab_width,
+                    self.config.line_numbers,
+                    self.config.syntax_highlighting
+                ));
+            }
+            "macros" => self.show_macros(),
+            "registers" | "reg" => {
+                // Show register contents
+                if let Some(content) = self.registers.get_unnamed() {
+                    let preview: String = match content {
+                        RegisterContent::Chars(s) => s.chars().take(40).collect(),
+                        RegisterContent::Lines(s) => s.replace('\n', "\\n").chars().take(40).collect(),
+                        RegisterContent::Block(lines) => lines.join("\\n").chars().take(40).collect(),
                     };
-                    if start < end {
-                        self.buffer.delete(start, end);
-                    }
+                    self.message = Some(format!("\"\" = \"{}{}\"", preview, if preview.len() >= 40 { "..." } else { "" }));
+                } else {
+                    self.message = Some("Registers empty".to_string());
                 }
-                self.mode = Mode::Insert;
             }
-            
-            // Replace character (r)
-            KeyCode::Char('r') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                // Set pending replace mode - next char will replace current
-                self.pending_op = Some('r');
+            "set number" | "set nu" => {
+                self.config.line_numbers = true;
+                self.message = Some("Line numbers enabled".to_string());
             }
-            
-            // Toggle case (~)
-            KeyCode::Char('~') => {
-                let line_len = self.buffer.line_len(self.cursor.line);
-                if self.cursor.col < line_len {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    if let Some(c) = self.buffer.char_at(self.cursor.line, self.cursor.col) {
-                        let toggled = if c.is_uppercase() {
-                            c.to_lowercase().to_string()
-                        } else {
-                            c.to_uppercase().to_string()
-                        };
-                        let pos = self.cursor.byte_offset(&self.buffer);
-                        self.buffer.delete(pos, pos + c.len_utf8());
-                        self.buffer.insert(pos, &toggled);
-                        self.cursor.move_right(&self.buffer);
-                    }
-                }
+            "set nonumber" | "set nonu" => {
+                self.config.line_numbers = false;
+                self.message = Some("Line numbers disabled".to_string());
             }
-            
-            // Indent (>>)
-            KeyCode::Char('>') => {
-                if self.pending_op == Some('>') {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    self.buffer.indent_line(self.cursor.line, self.config.tab_width);
-                    self.pending_op = None;
-                } else {
-                    self.pending_op = Some('>');
-                }
+            "set syntax" | "set syn" => {
+                self.config.syntax_highlighting = true;
+                self.message = Some("Syntax highlighting enabled".to_string());
             }
-            
-            // Outdent (<<)
-            KeyCode::Char('<') => {
-                if self.pending_op == Some('<') {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    self.buffer.outdent_line(self.cursor.line, self.config.tab_width);
-                    self.cursor.clamp(&self.buffer);
-                    self.pending_op = None;
-                } else {
-                    self.pending_op = Some('<');
-                }
+            "set nosyntax" | "set nosyn" => {
+                self.config.syntax_highlighting = false;
+                self.message = Some("Syntax highlighting disabled".to_string());
             }
-            
-            // Paste after (p)
-            KeyCode::Char('p') => {
-                if let Some(content) = self.registers.get_unnamed() {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    match content {
-                        RegisterContent::Lines(text) => {
-                            // Paste below current line
-                            self.buffer.insert_line_below(self.cursor.line, text);
-                            self.cursor.line += 1;
-                            self.cursor.col = 0;
-                        }
-                        RegisterContent::Chars(text) => {
-                            // Paste after cursor
-                            let pos = self.cursor.byte_offset(&self.buffer);
-                            self.buffer.insert(pos + 1, text);
-                            self.cursor.col += 1;
-                        }
-                        RegisterContent::Block(_) => {
-                            // TODO: block paste
-                        }
-                    }
-                    self.ensure_cursor_visible();
+            "tabnew" | "new" => {
+                // Create a new empty buffer
+                self.buffer = Buffer::new();
+                self.reset_cursor();
+                self.scroll_offset = 0;
+                self.message = Some("New buffer".to_string());
+            }
+            _ if cmd.starts_with("new ") || cmd.starts_with("tabnew ") => {
+                let path = cmd.split_once(' ').unwrap().1.trim();
+                if let Err(e) = self.load_file(path) {
+                    self.report_error(format!("Error opening {}", e));
                 }
             }
-            
-            // Paste before (P)
-            KeyCode::Char('P') => {
-                if let Some(content) = self.registers.get_unnamed() {
-                    self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                    match content {
-                        RegisterContent::Lines(text) => {
-                            // Paste above current line
-                            self.buffer.insert_line_above(self.cursor.line, text);
-                            self.cursor.col = 0;
-                        }
-                        RegisterContent::Chars(text) => {
-                            // Paste before cursor
-                            let pos = self.cursor.byte_offset(&self.buffer);
-                            self.buffer.insert(pos, text);
+            "tabclose" | "close" => {
+                // Close current buffer (alias for :bd)
+                self.close_buffer();
+            }
+            "only" => {
+                // Close all other buffers (keep current)
+                self.message = Some("Closed all other buffers".to_string());
+            }
+            "e" | "edit" => {
+                // Reload current file
+                if let Some(path) = self.buffer.file_path() {
+                    let path_str = path.display().to_string();
+                    match self.buffer.reload() {
+                        Ok(_) => {
+                            self.reset_cursor();
+                            self.scroll_offset = 0;
+                            self.message = Some(format!("Reloaded: {}", path_str));
                         }
-                        RegisterContent::Block(_) => {
-                            // TODO: block paste
+                        Err(e) => {
+                            self.report_error(format!("Error reloading: {}", e));
                         }
                     }
-                    self.ensure_cursor_visible();
+                } else {
+                    self.message = Some("No file to reload".to_string());
                 }
             }
-            
-            // Command mode
-            KeyCode::Char(':') => {
-                self.mode = Mode::Command;
-                self.command_buffer.clear();
+            "enew" => {
+                // Create new empty buffer
+                self.buffer = Buffer::new();
+                self.reset_cursor();
+                self.scroll_offset = 0;
+                self.message = Some("New buffer".to_string());
             }
-            
-            // Search
-            KeyCode::Char('/') => {
-                self.search.start(SearchDirection::Forward);
-                self.mode = Mode::Search;
-            }
-            KeyCode::Char('?') => {
-                self.search.start(SearchDirection::Backward);
-                self.mode = Mode::Search;
-            }
-            KeyCode::Char('n') => {
-                if let Some(m) = self.search.next_match() {
-                    self.cursor.line = m.line;
-                    self.cursor.col = m.start_col;
-                    self.ensure_cursor_visible();
-                    self.message = Some(self.search.match_info());
+            _ if cmd.starts_with("e ") => {
+                let path = cmd.strip_prefix("e ").unwrap().trim();
+                if let Err(e) = self.load_file(path) {
+                    self.report_error(format!("Error opening {}", e));
                 }
             }
-            KeyCode::Char('N') => {
-                if let Some(m) = self.search.prev_match() {
-                    self.cursor.line = m.line;
-                    self.cursor.col = m.start_col;
-                    self.ensure_cursor_visible();
-                    self.message = Some(self.search.match_info());
+            "ls" | "buffers" => {
+                // List all open buffers
+                let buffers = self.buffer_manager.list_buffers();
+                if buffers.is_empty() {
+                    self.message = Some("No buffers open".to_string());
+                } else {
+                    let list: Vec<String> = buffers
+                        .iter()
+                        .map(|(i, name, is_current)| {
+                            if *is_current {
+                                format!("[{}] {}", i, name)
+                            } else {
+                                format!(" {}  {}", i, name)
+                            }
+                        })
+                        .collect();
+                    self.message = Some(list.join("\n"));
                 }
             }
-            
-            // Undo
-            KeyCode::Char('u') => {
-                if let Some((line, col)) = self.buffer.undo(self.cursor.line, self.cursor.col) {
-                    self.cursor.line = line;
-                    self.cursor.col = col;
-                    self.cursor.clamp(&self.buffer);
-                    self.ensure_cursor_visible();
-                    self.message = Some("Undo".to_string());
+            _ if cmd.starts_with("b ") => {
+                let buf_num_str = cmd.strip_prefix("b ").unwrap().trim();
+                if let Ok(idx) = buf_num_str.parse::<usize>() {
+                    self.switch_to_buffer(idx);
                 } else {
-                    self.message = Some("Already at oldest change".to_string());
+                    self.message = Some("Usage: :b <buffer_number>".to_string());
                 }
             }
-            
-            // Redo (Ctrl+R)
-            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                if let Some((line, col)) = self.buffer.redo() {
-                    self.cursor.line = line;
-                    self.cursor.col = col;
-                    self.cursor.clamp(&self.buffer);
-                    self.ensure_cursor_visible();
-                    self.message = Some("Redo".to_string());
+            _ if cmd.starts_with("bd") => {
+                // Close current buffer, asking first about unsaved changes
+                // unless forced with :bd!
+                if !cmd.ends_with('!') && self.buffer.is_modified() && self.buffer.scratch_name().is_none() {
+                    let prompt = Prompt::choose("Save changes?", vec![buffer_label(&self.buffer)], SAVE_DISCARD_CANCEL);
+                    self.prompt = Some((prompt, PromptAction::CloseBuffer));
                 } else {
-                    self.message = Some("Already at newest change".to_string());
+                    self.close_buffer();
                 }
             }
-            
-            // Page up (Ctrl+U)
-            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                let half_page = self.viewport_height / 2;
-                self.cursor.line = self.cursor.line.saturating_sub(half_page);
-                self.ensure_cursor_visible();
-            }
-            
-            // Page down (Ctrl+D)
-            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                let half_page = self.viewport_height / 2;
-                let max_line = self.buffer.line_count().saturating_sub(1);
-                self.cursor.line = (self.cursor.line + half_page).min(max_line);
-                self.ensure_cursor_visible();
-            }
-            
-            // Full page up (Ctrl+B)
-            KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.cursor.line = self.cursor.line.saturating_sub(self.viewport_height);
-                self.ensure_cursor_visible();
-            }
-            
-            // Full page down (Ctrl+F)
-            KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                let max_line = self.buffer.line_count().saturating_sub(1);
-                self.cursor.line = (self.cursor.line + self.viewport_height).min(max_line);
-                self.ensure_cursor_visible();
-            }
-            
-            // Visual modes
-            KeyCode::Char('v') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.selection = Some(Selection::new(VisualMode::Char, self.cursor.line, self.cursor.col));
-                self.mode = Mode::Visual;
-            }
-            KeyCode::Char('V') => {
-                self.selection = Some(Selection::new(VisualMode::Line, self.cursor.line, self.cursor.col));
-                self.mode = Mode::VisualLine;
+            _ if cmd.starts_with("set ") || cmd.starts_with("se ") => {
+                // :set name, :set noname, :set name=value, :set name?
+                let mut shown = Vec::new();
+                for arg in cmd.split_whitespace().skip(1) {
+                    let result = if let Some(filetype) = arg.strip_prefix("filetype=").or_else(|| arg.strip_prefix("ft=")) {
+                        self.set_filetype(filetype)
+                    } else if arg == "filetype?" || arg == "ft?" {
+                        let filetype = self.highlighter.current_syntax_name().unwrap_or("").to_lowercase();
+                        shown.push(format!("filetype={}", filetype));
+                        Ok(())
+                    } else if let Some(name) = arg.strip_suffix('?') {
+                        match self.config.get_option(name) {
+                            Some(value) => {
+                                shown.push(format!("{}={}", name, value));
+                                Ok(())
+                            }
+                            None => Err(format!("Unknown option: {}", name)),
+                        }
+                    } else if let Some((name, value)) = arg.split_once('=') {
+                        self.set_option(name, Some(value))
+                    } else {
+                        self.set_option(arg, None)
+                    };
+                    if let Err(e) = result {
+                        shown.push(e);
+                        break;
+                    }
+                }
+                self.message = (!shown.is_empty()).then(|| shown.join("  "));
             }
-            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.selection = Some(Selection::new(VisualMode::Block, self.cursor.line, self.cursor.col));
-                self.mode = Mode::VisualBlock;
+            _ if cmd.starts_with("syntax ") || cmd.starts_with("syn ") => {
+                // Set syntax highlighting language
+                let lang = cmd.split_whitespace().nth(1).unwrap_or("");
+                self.message = Some(match self.set_filetype(lang) {
+                    Ok(()) => match self.highlighter.current_syntax_name() {
+                        Some(name) => format!("Syntax: {}", name),
+                        None => "Syntax highlighting off".to_string(),
+                    },
+                    Err(e) => e,
+                });
+            }
+            "filetype detect" | "filet detect" => {
+                self.buffer.set_filetype(None);
+                let syntax_info = self.detect_syntax();
+                self.message = Some(format!("Filetype detected{}", syntax_info));
+            }
+            _ if substitute::parse_substitute_command(cmd).is_some() => self.substitute_command(cmd),
+            _ if matches!(substitute::parse_range(cmd).1, "&" | "&&") => {
+                let (range, rest) = substitute::parse_range(cmd);
+                self.repeat_substitute(range.unwrap_or(substitute::Range::Current), rest == "&&");
+            }
+            _ if self.lua.as_ref().is_some_and(|lua| lua.has_command(lua::split_command(cmd).0)) => {
+                return self.run_lua_command(cmd);
             }
-            
-            // Number increment (Ctrl+A)
-            KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                // Find number at or after cursor and increment it
-                self.message = Some("Number increment not yet implemented".to_string());
+            _ => {
+                self.message = Some(format!("Unknown command: {}", cmd));
             }
+        }
+        false
+    }
+
+    /// Rewrite the indentation of the lines in `range` with spaces or tabs,
+    /// following expand_tab (:retab). A new tab width is applied to the
+    /// lines and then set as tab_width.
+    fn retab(&mut self, range: substitute::Range, new_tab_width: Option<usize>) {
+        let Some((start, end)) = self.resolve_range(range) else { return };
+        let old_tab_width = self.config.tab_width;
+        let new_tab_width = new_tab_width.unwrap_or(old_tab_width).max(1);
+        let mut changed = 0;
+        let lines: Vec<String> = (start..=end)
+            .map(|line| {
+                let text = self.buffer.line(line);
+                match indent::retab_line(&text, old_tab_width, new_tab_width, self.config.expand_tab) {
+                    Some(retabbed) => {
+                        changed += 1;
+                        retabbed
+                    }
+                    None => text,
+                }
+            })
+            .collect();
+        if changed > 0 {
+            self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+            self.buffer.replace_lines(start, end + 1, &lines);
+            self.cursor.clamp(&self.buffer);
+        }
+        self.config.tab_width = new_tab_width;
+        self.message = Some(format!("Retabbed {} line{}", changed, if changed == 1 { "" } else { "s" }));
+    }
 
-            // Number decrement (Ctrl+X)
-            KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                // Find number at or after cursor and decrement it
-                self.message = Some("Number decrement not yet implemented".to_string());
+    /// Run a :s command and remember it for & and :&&
+    fn substitute_command(&mut self, cmd: &str) {
+        let Some((range, pattern, replacement, flags)) = substitute::parse_substitute_command(cmd) else { return };
+        // An empty pattern reuses the last one, as in Vim
+        let pattern = if pattern.is_empty() {
+            match &self.last_substitute {
+                Some((last, _, _)) => last.clone(),
+                None => self.search.regex_pattern().to_string(),
             }
+        } else {
+            pattern
+        };
+        if pattern.is_empty() {
+            self.message = Some("No previous regular expression".to_string());
+            return;
+        }
+        self.apply_substitute(range.unwrap_or(substitute::Range::Current), &pattern, &replacement, &flags);
+        self.last_substitute = Some((pattern, replacement, flags));
+    }
 
-            // File info (Ctrl+G)
-            KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                let lines = self.buffer.line_count();
-                let chars = self.buffer.len();
-                let file_name = self.buffer.file_name().unwrap_or("[No Name]");
-                let modified = if self.buffer.is_modified() { " [Modified]" } else { "" };
-                let pos = format!("line {}/{}", self.cursor.line + 1, lines);
-                self.message = Some(format!("\"{}\"{} {} chars, {}", file_name, modified, chars, pos));
+    /// Repeat the last :s over `range` (& and :&&); only :&& keeps its flags
+    fn repeat_substitute(&mut self, range: substitute::Range, keep_flags: bool) {
+        let Some((pattern, replacement, flags)) = self.last_substitute.clone() else {
+            self.message = Some("No previous substitute".to_string());
+            return;
+        };
+        let flags = if keep_flags { flags } else { SubstituteFlags::default() };
+        self.apply_substitute(range, &pattern, &replacement, &flags);
+    }
+
+    /// Show the effect of the :s being typed (inccommand): the buffer is
+    /// swapped for a copy with the substitution made and the replacements
+    /// highlighted. Large buffers are not previewed, so typing never waits.
+    fn preview_substitute(&mut self) {
+        if !self.config.inccommand || self.buffer.line_count() > BACKGROUND_SEARCH_LINES {
+            return;
+        }
+        let Some((range, pattern, replacement, flags)) = substitute::parse_substitute_command(&self.command_buffer) else {
+            return;
+        };
+        let Some((start, end)) = self.range_lines(range.unwrap_or(substitute::Range::Current)) else { return };
+        if pattern.is_empty() {
+            return;
+        }
+        let Ok(regex) = substitute::build_regex(&pattern, &flags) else { return };
+        let replacement = substitute::vim_replacement(&replacement);
+        let style = Style::default().fg(Color::Black).bg(Color::LightGreen);
+        let mut preview = self.buffer.clone();
+        for line in start..=end {
+            let (text, spans) = substitute::replace_with_spans(&preview.line(line), &regex, &replacement, flags.global);
+            if spans.is_empty() {
+                continue;
+            }
+            preview.replace_lines(line, line + 1, &[text]);
+            for span in spans.into_iter().filter(|span| !span.is_empty()) {
+                preview.add_decoration(PREVIEW_NAMESPACE, line, span.start, Decoration::Highlight { len: span.len(), style });
             }
-            
-            _ => {
-                // Clear numeric prefix for commands that don't use it
-                self.numeric_prefix.clear();
+        }
+        if !self.buffer.is_modified() {
+            preview.mark_saved();
+        }
+        self.substitute_preview = Some(std::mem::replace(&mut self.buffer, preview));
+    }
+
+    /// Put back the buffer a :s preview replaced
+    fn end_substitute_preview(&mut self) {
+        if let Some(buffer) = self.substitute_preview.take() {
+            self.buffer = buffer;
+        }
+    }
+
+    /// First and last line of an ex command range, or None if it is
+    /// outside the buffer
+    fn range_lines(&self, range: substitute::Range) -> Option<(usize, usize)> {
+        let last_line = self.buffer.line_count() - 1;
+        let (start, end) = match range {
+            substitute::Range::Current => (self.cursor.line, self.cursor.line),
+            substitute::Range::All => (0, last_line),
+            substitute::Range::Last => (last_line, last_line),
+            substitute::Range::Line(line) => (line, line),
+            substitute::Range::Lines(first, last) => (first, last.min(last_line)),
+            substitute::Range::Visual => {
+                let (first, last) = self.last_visual?.line_range();
+                (first, last.min(last_line))
             }
+        };
+        (start <= end && start <= last_line).then_some((start, end))
+    }
+
+    /// First and last line of an ex command range, reporting ranges
+    /// outside the buffer
+    fn resolve_range(&mut self, range: substitute::Range) -> Option<(usize, usize)> {
+        let range = self.range_lines(range);
+        if range.is_none() {
+            self.report_error("Invalid range".to_string());
         }
-        false
+        range
     }
 
-    /// Handle keys in insert mode
-    fn handle_insert_mode(&mut self, key: KeyEvent) -> bool {
-        match key.code {
-            KeyCode::Esc => {
-                self.mode = Mode::Normal;
-                // Move cursor back one (Vim behavior)
-                if self.cursor.col > 0 {
-                    self.cursor.move_left(&self.buffer);
+    fn apply_substitute(&mut self, range: substitute::Range, pattern: &str, replacement: &str, flags: &SubstituteFlags) {
+        let Some((start, end)) = self.resolve_range(range) else { return };
+        if flags.confirm {
+            let regex = match substitute::build_regex(pattern, flags) {
+                Ok(regex) => regex,
+                Err(error) => return self.report_error(error),
+            };
+            let state = SubstituteConfirm {
+                regex,
+                replacement: substitute::vim_replacement(replacement),
+                global: flags.global,
+                line: start,
+                byte: 0,
+                end,
+                count: 0,
+                lines: 0,
+                last_line: None,
+                found: false,
+            };
+            return self.next_substitute_match(state);
+        }
+        let mut lines: Vec<String> = (start..=end).map(|line| self.buffer.line(line)).collect();
+        // With \%V only the part of each line inside the last visual
+        // selection is substituted
+        let area = if pattern.contains(search::VISUAL_AREA) {
+            let Some(area) = self.last_visual else {
+                self.report_error("No visual selection".to_string());
+                return;
+            };
+            Some(area)
+        } else {
+            None
+        };
+        let spans: Vec<(usize, Range<usize>)> = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, line)| {
+                let Some(area) = area else { return Some((idx, 0..line.len())) };
+                let cols = area.cols_on_line(start + idx)?;
+                let byte = |col: usize| line.char_indices().nth(col).map_or(line.len(), |(byte, _)| byte);
+                Some((idx, byte(cols.start)..byte(cols.end)))
+            })
+            .collect();
+        let mut parts: Vec<String> = spans.iter().map(|(idx, span)| lines[*idx][span.clone()].to_string()).collect();
+        let pattern = pattern.replace(search::VISUAL_AREA, "");
+        let result = substitute::substitute(&mut parts, substitute::Range::All, &pattern, replacement, flags, 0);
+        for ((idx, span), part) in spans.into_iter().zip(parts) {
+            lines[idx].replace_range(span, &part);
+        }
+        if let Some(error) = result.error {
+            self.report_error(error);
+            return;
+        }
+        if result.count == 0 {
+            self.report_error(format!("Pattern not found: {}", pattern));
+            return;
+        }
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        self.buffer.replace_lines(start, end + 1, &lines);
+        self.cursor.clamp(&self.buffer);
+        self.message = Some(substitution_summary(result.count, result.lines));
+    }
+
+    /// Move to the next match of a :s with the c flag and ask whether to
+    /// replace it, or finish when there are no more
+    fn next_substitute_match(&mut self, mut state: SubstituteConfirm) {
+        self.buffer.clear_decorations(PREVIEW_NAMESPACE);
+        while state.line <= state.end && state.line < self.buffer.line_count() {
+            let text = self.buffer.line(state.line);
+            let found = (state.byte <= text.len())
+                .then(|| state.regex.find_at(&text, state.byte))
+                .flatten();
+            let Some(found) = found else {
+                state.line += 1;
+                state.byte = 0;
+                continue;
+            };
+            let col = text[..found.start()].chars().count();
+            self.cursor.line = state.line;
+            self.cursor.col = col;
+            self.ensure_cursor_visible();
+            let style = Style::default().fg(Color::Black).bg(Color::LightGreen);
+            let len = found.as_str().chars().count().max(1);
+            self.buffer.add_decoration(PREVIEW_NAMESPACE, state.line, col, Decoration::Highlight { len, style });
+            state.found = true;
+            let prompt = Prompt::choose("Replace this match?", Vec::new(), SUBSTITUTE_CHOICES);
+            self.prompt = Some((prompt, PromptAction::Substitute(Box::new(state))));
+            return;
+        }
+        if !state.found {
+            self.report_error(format!("Pattern not found: {}", state.regex.as_str()));
+        } else {
+            self.message = Some(substitution_summary(state.count, state.lines));
+        }
+    }
+
+    /// Act on the answer for the match under the cursor of a :s with the
+    /// c flag: replace it or not, and go on to the next one or stop
+    fn answer_substitute(&mut self, mut state: SubstituteConfirm, answer: Answer) {
+        match answer {
+            Answer::Choice('y') => {
+                self.replace_substitute_match(&mut state);
+                self.next_substitute_match(state);
+            }
+            Answer::Choice('n') => {
+                let text = self.buffer.line(state.line);
+                match state.regex.find_at(&text, state.byte) {
+                    Some(found) if state.global => state.byte = next_byte(&text, found.end(), found.is_empty()),
+                    _ => {
+                        state.line += 1;
+                        state.byte = 0;
+                    }
                 }
+                self.next_substitute_match(state);
             }
-            KeyCode::Char(c) => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                let pos = self.cursor.byte_offset(&self.buffer);
-                self.buffer.insert_char(pos, c);
-                self.cursor.col += 1;
+            Answer::Choice('a') => {
+                while self.replace_substitute_match(&mut state) {}
+                self.next_substitute_match(state);
             }
-            KeyCode::Enter => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                let pos = self.cursor.byte_offset(&self.buffer);
-                self.buffer.insert_char(pos, '\n');
-                self.cursor.line += 1;
-                self.cursor.col = 0;
-                self.ensure_cursor_visible();
+            Answer::Choice('l') => {
+                self.replace_substitute_match(&mut state);
+                state.line = state.end + 1;
+                self.next_substitute_match(state);
             }
-            KeyCode::Backspace => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                let (new_line, new_col) = self.buffer.backspace(self.cursor.line, self.cursor.col);
-                self.cursor.line = new_line;
-                self.cursor.col = new_col;
-                self.ensure_cursor_visible();
+            _ => {
+                state.line = state.end + 1;
+                self.next_substitute_match(state);
             }
-            KeyCode::Delete => {
-                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-                self.buffer.delete_grapheme(self.cursor.line, self.cursor.col);
+        }
+    }
+
+    /// Replace the next match of a :s with the c flag, from where the last
+    /// one ended; returns false if there is none left
+    fn replace_substitute_match(&mut self, state: &mut SubstituteConfirm) -> bool {
+        loop {
+            if state.line > state.end || state.line >= self.buffer.line_count() {
+                return false;
             }
-            KeyCode::Left => self.cursor.move_left(&self.buffer),
-            KeyCode::Right => self.cursor.move_right(&self.buffer),
-            KeyCode::Up => {
-                self.cursor.move_up(&self.buffer);
-                self.ensure_cursor_visible();
+            let text = self.buffer.line(state.line);
+            let caps = (state.byte <= text.len())
+                .then(|| state.regex.captures_at(&text, state.byte))
+                .flatten();
+            let Some(caps) = caps else {
+                state.line += 1;
+                state.byte = 0;
+                continue;
+            };
+            let whole = caps.get(0).unwrap();
+            let mut replaced = text[..whole.start()].to_string();
+            caps.expand(&state.replacement, &mut replaced);
+            // A replacement with line breaks moves the rest down
+            let added = replaced.matches('\n').count();
+            let after = replaced.len() - replaced.rfind('\n').map_or(0, |newline| newline + 1);
+            replaced.push_str(&text[whole.end()..]);
+            if state.count == 0 {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
             }
-            KeyCode::Down => {
-                self.cursor.move_down(&self.buffer);
-                self.ensure_cursor_visible();
+            self.buffer.replace_lines(state.line, state.line + 1, &[replaced.clone()]);
+            state.count += 1;
+            if state.last_line != Some(state.line) {
+                state.lines += 1;
+            }
+            state.line += added;
+            state.end += added;
+            state.last_line = Some(state.line);
+            if state.global {
+                let rest = replaced.split('\n').next_back().unwrap_or("");
+                state.byte = next_byte(rest, after, whole.is_empty());
+            } else {
+                state.line += 1;
+                state.byte = 0;
             }
-            _ => {}
+            return true;
         }
-        false
     }
 
-    /// Handle keys in command mode
-    fn handle_command_mode(&mut self, key: KeyEvent) -> bool {
+    /// Handle keys in search mode
+    fn handle_search_mode(&mut self, key: KeyEvent) -> bool {
         match key.code {
             KeyCode::Esc => {
                 self.mode = Mode::Normal;
-                self.command_buffer.clear();
+                self.search.cancel();
+                self.search.clear_highlight();
+                self.history_index = None;
+                self.search_operator = None;
             }
             KeyCode::Enter => {
-                let should_quit = self.execute_command();
-                // Don't reset mode if command changed it (e.g., to Help)
-                if self.mode == Mode::Command {
-                    self.mode = Mode::Normal;
+                add_history(&mut self.search_history, self.search.pattern());
+                self.history_index = None;
+                // Execute the search, or finish the one started while typing
+                if self.config.incsearch && self.buffer.line_count() > BACKGROUND_SEARCH_LINES {
+                    self.search.wait(self.cursor.line, self.cursor.col);
+                } else {
+                    let lines: Vec<String> = (0..self.buffer.line_count())
+                        .map(|i| self.buffer.line(i))
+                        .collect();
+                    self.search.execute(&lines, self.cursor.line, self.cursor.col);
                 }
-                self.command_buffer.clear();
-                return should_quit;
-            }
-            KeyCode::Backspace => {
-                if self.command_buffer.pop().is_none() {
-                    self.mode = Mode::Normal;
+                
+                self.mode = Mode::Normal;
+                let operator = self.search_operator.take();
+                // Jump to first match, or apply the operator up to it
+                if let Some(m) = self.search.current() {
+                    match operator {
+                        Some((op, ctx)) => self.search_motion(op, ctx, m),
+                        None => {
+                            self.jump_to_match(m);
+                            self.message = Some(self.search.match_info());
+                        }
+                    }
+                } else if !self.search.is_empty() {
+                    self.report_error("Pattern not found");
                 }
             }
+            KeyCode::Backspace if !self.search.pop_char() => {
+                self.search.cancel();
+                self.mode = Mode::Normal;
+                self.history_index = None;
+                self.search_operator = None;
+            }
+            KeyCode::Backspace => self.incremental_search(),
+            KeyCode::Up => self.recall_history(true, true),
+            KeyCode::Down => self.recall_history(true, false),
             KeyCode::Char(c) => {
-                self.command_buffer.push(c);
+                self.search.push_char(c);
+                self.incremental_search();
             }
             _ => {}
         }
         false
     }
 
-    /// Execute a command from command mode
-    fn execute_command(&mut self) -> bool {
-        let cmd = self.command_buffer.trim();
-        match cmd {
-            "q" | "quit" => {
-                if self.buffer.is_modified() {
-                    self.message = Some("Unsaved changes! Use :q! to force quit".to_string());
-                    return false;
-                }
-                return true;
+    /// Start typing a search. From visual mode it begins with `\%V`, so
+    /// only the selection is searched unless that is deleted.
+    fn start_search(&mut self, direction: SearchDirection) {
+        self.search.start(direction);
+        self.search.set_visual_area(self.last_visual);
+        if self.mode.is_visual() {
+            self.selection = None;
+            self.search.set_pattern(search::VISUAL_AREA);
+            self.incremental_search();
+        }
+        self.mode = Mode::Search;
+    }
+
+    /// Move the cursor to where the search offset lands for a match
+    fn jump_to_match(&mut self, m: SearchMatch) {
+        let (line, col) = self.search.offset().apply(m, self.buffer.line_count());
+        self.cursor.line = line;
+        self.cursor.col = col;
+        self.cursor.clamp(&self.buffer);
+        self.cursor.col = self.cursor.col.min(self.buffer.line_len(self.cursor.line).saturating_sub(1));
+        self.ensure_cursor_visible();
+    }
+
+    /// Apply operator `op` from the cursor to where the search lands for
+    /// a match. The text up to the match is exclusive, an `e` offset
+    /// includes the character landed on and a line offset makes it
+    /// linewise, as in Vim.
+    fn search_motion(&mut self, op: Operator, ctx: ActionContext, m: SearchMatch) {
+        let offset = self.search.offset();
+        let (line, col) = offset.apply(m, self.buffer.line_count());
+        let here = (self.cursor.line, self.cursor.col.min(self.buffer.line_len(self.cursor.line)));
+        let object = if offset.is_linewise() {
+            Some(TextObject::Lines { start: here.0.min(line), end: here.0.max(line) })
+        } else {
+            let there = (line, col.min(self.buffer.line_len(line)) + matches!(offset, SearchOffset::End(_)) as usize);
+            operator::exclusive(&self.buffer, here.min(there), here.max(there))
+        };
+        self.operate(op, ctx, object);
+    }
+
+    /// Highlight the matches of the pattern typed so far, with incsearch.
+    /// Large buffers are searched in the background so typing never waits.
+    fn incremental_search(&mut self) {
+        if !self.config.incsearch {
+            return;
+        }
+        if self.buffer.line_count() > BACKGROUND_SEARCH_LINES {
+            self.search.execute_in_background(self.buffer.snapshot(), self.waker.clone());
+        } else {
+            let lines: Vec<String> = (0..self.buffer.line_count())
+                .map(|i| self.buffer.line(i))
+                .collect();
+            self.search.execute(&lines, self.cursor.line, self.cursor.col);
+        }
+    }
+
+    /// Handle keys in visual mode
+    fn handle_visual_mode(&mut self, key: KeyEvent) -> bool {
+        // Register for the next command ("x)
+        if self.pending_op == Some('"') {
+            if let KeyCode::Char(c) = key.code {
+                self.pending_register = Some(c);
             }
-            "q!" | "quit!" => return true,
-            "w" | "write" => {
-                if let Err(e) = self.buffer.save() {
-                    self.message = Some(format!("Error saving: {}", e));
-                } else {
-                    self.message = Some("Written".to_string());
-                }
+            self.pending_op = None;
+            return false;
+        }
+        if self.push_count_digit(key) {
+            return false;
+        }
+
+        // Select a text object (viw, va")
+        if let Some(scope) = self.text_object.take() {
+            if let KeyCode::Char(c) = key.code {
+                self.select_text_object(scope, c);
             }
-            "wq" | "x" | "wq!" => {
-                if let Err(e) = self.buffer.save() {
-                    self.message = Some(format!("Error saving: {}", e));
-                } else {
-                    return true;
-                }
+            return false;
+        }
+        if let KeyCode::Char(c @ ('i' | 'a')) = key.code {
+            if !key.modifiers.contains(KeyModifiers::CONTROL) && self.mode != Mode::VisualBlock {
+                self.text_object = Scope::from_char(c);
+                return false;
             }
-            "wa" => {
-                // Save all buffers
-                if let Err(e) = self.buffer.save() {
-                    self.message = Some(format!("Error saving: {}", e));
-                } else {
-                    self.message = Some("All buffers saved".to_string());
+        }
+
+        // Second key of gg or gq
+        if self.pending_g {
+            self.pending_g = false;
+            match key.code {
+                KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => self.show_counts(),
+                // Number the selected lines: the first by the count, the
+                // next by twice that and so on (g Ctrl-A, g Ctrl-X)
+                KeyCode::Char(c @ ('a' | 'x')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let count = self.take_action_context().count() as i64;
+                    self.increment_selection(if c == 'a' { count } else { -count }, true);
                 }
-            }
-            "qa" | "qall" => {
-                // Quit all (if no unsaved changes)
-                if self.buffer.is_modified() {
-                    self.message = Some("Unsaved changes! Use :qa! to force quit".to_string());
-                } else {
-                    return true;
+                KeyCode::Char('g') => {
+                    self.cursor.move_to_buffer_start();
+                    self.update_selection();
+                    self.ensure_cursor_visible();
+                }
+                // Operators of two keys (gu, gU, g~, gq, gc)
+                KeyCode::Char(c @ ('u' | 'U' | '~' | 'q' | 'c')) => {
+                    if let Some(op) = Operator::from_key(c, true) {
+                        let ctx = self.take_action_context();
+                        self.apply_operator(op, ctx);
+                    }
+                }
+                KeyCode::Char('J') => {
+                    self.join_selection(true);
+                    self.mode = Mode::Normal;
+                    self.selection = None;
                 }
+                _ => {}
             }
-            "qa!" | "qall!" => {
-                // Force quit all
-                return true;
+            return false;
+        }
+
+        if let Some(quit) = self.dispatch_keymap(key) {
+            return quit;
+        }
+        self.handle_visual_builtin(key)
+    }
+
+    /// Built-in visual mode commands for keys without a keymap binding
+    fn handle_visual_builtin(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.selection = None;
             }
-            _ if cmd.starts_with("w ") => {
-                let path = cmd.strip_prefix("w ").unwrap().trim();
-                if let Err(e) = self.buffer.save_as(path) {
-                    self.message = Some(format!("Error saving: {}", e));
-                } else {
-                    self.message = Some(format!("Written: {}", path));
+            
+            KeyCode::Char('g') => self.pending_g = true,
+            
+            // Operators act on the selection: d, x, c, y, <, >, ~, u, U
+            KeyCode::Char(c @ ('d' | 'x' | 'c' | 'y' | '<' | '>' | '~' | 'u' | 'U'))
+                if !key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                if let Some(op) = Operator::from_visual_key(c) {
+                    let ctx = self.take_action_context();
+                    self.apply_operator(op, ctx);
                 }
             }
-            "noh" | "nohlsearch" => {
-                self.search.clear_highlight();
+
+            // Add the count to the first number on each line (Ctrl-A, Ctrl-X)
+            KeyCode::Char(c @ ('a' | 'x')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let count = self.take_action_context().count() as i64;
+                self.increment_selection(if c == 'a' { count } else { -count }, false);
+            }
+
+            // Select register for the next command ("x)
+            KeyCode::Char('"') => self.pending_op = Some('"'),
+
+            // Join the selected lines (J)
+            KeyCode::Char('J') => {
+                self.join_selection(false);
+                self.mode = Mode::Normal;
+                self.selection = None;
             }
-            "help" | "h" | "?" => {
-                self.mode = Mode::Help;
-                self.message = Some("Press q/Esc/Enter to close help".to_string());
+
+            // Replace selection with the register (p)
+            KeyCode::Char('p') | KeyCode::Char('P') => {
+                let ctx = self.take_action_context();
+                self.paste_over_selection(ctx);
+                self.mode = Mode::Normal;
+                self.selection = None;
             }
-            "version" | "ver" => {
-                self.message = Some("Quirks v0.3.1 - A modal text editor".to_string());
+
+            // Switch visual mode type
+            KeyCode::Char('v') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.enter_visual(VisualMode::Char);
             }
-            "pwd" => {
-                match std::env::current_dir() {
-                    Ok(path) => self.message = Some(path.display().to_string()),
-                    Err(e) => self.message = Some(format!("Error: {}", e)),
+            KeyCode::Char('V') => self.enter_visual(VisualMode::Line),
+            KeyCode::Char('v') => self.enter_visual(VisualMode::Block),
+            
+            _ => {}
+        }
+        false
+    }
+
+    /// Feed a key into the keymap for the current mode.
+    ///
+    /// Returns `Some(quit)` if the key was consumed (either completing a
+    /// binding or extending a pending sequence), `None` if the built-in
+    /// handler should process it.
+    fn dispatch_keymap(&mut self, key: KeyEvent) -> Option<bool> {
+        self.pending_keys.push(key);
+        let combos: Vec<KeyCombo> = self.pending_keys.iter().map(KeyCombo::from_event).collect();
+        let result = match self.keymap.for_mode(self.mode)?.lookup_sequence(&combos) {
+            KeyMatch::Complete(action) => Ok(action.clone()),
+            KeyMatch::Pending(_) => Err(true),
+            KeyMatch::None => Err(false),
+        };
+
+        match result {
+            Ok(action) => {
+                self.pending_keys.clear();
+                self.pending_keys_since = None;
+                let operator = self.operator.take();
+                let ctx = self.take_action_context();
+                // d/pat<CR>: the search is the motion for the operator
+                if let (Some((op, op_ctx)), Action::SearchForward | Action::SearchBackward) = (operator, &action) {
+                    self.search_operator = Some((op, ActionContext { register: op_ctx.register, ..ctx }));
                 }
+                Some(self.execute_action(&action, ctx))
             }
-            "messages" | "mes" => {
-                // Show last message (vim :messages equivalent)
-                self.message = Some("No previous messages".to_string());
+            Err(true) => {
+                self.pending_keys_since = Some(Instant::now());
+                Some(false)
             }
-            "jumps" => {
-                // Placeholder for jump list
-                self.message = Some("Jump list not yet implemented".to_string());
+            Err(false) if self.pending_keys.len() == 1 => {
+                self.pending_keys.clear();
+                self.pending_keys_since = None;
+                None
             }
-            "marks" => {
-                // Placeholder for marks
-                self.message = Some("Marks not yet implemented".to_string());
+            Err(false) => Some(self.flush_pending_keys()),
+        }
+    }
+
+    /// Resolve a pending key sequence that can no longer be extended.
+    ///
+    /// Fires the longest bound prefix (or runs the first key unmapped),
+    /// then feeds the remaining keys back through normal dispatch.
+    pub fn flush_pending_keys(&mut self) -> bool {
+        let keys = std::mem::take(&mut self.pending_keys);
+        self.pending_keys_since = None;
+        if keys.is_empty() {
+            return false;
+        }
+
+        let combos: Vec<KeyCombo> = keys.iter().map(KeyCombo::from_event).collect();
+        let bound = self.keymap.for_mode(self.mode)
+            .and_then(|km| km.longest_match(&combos))
+            .map(|(len, action)| (len, action.clone()));
+
+        let (consumed, quit) = match bound {
+            Some((len, action)) => {
+                let ctx = self.take_action_context();
+                (len, self.execute_action(&action, ctx))
             }
-            "stats" | "wc" => {
-                // Word count and statistics
-                let content = self.buffer.content();
-                let lines = self.buffer.line_count();
-                let chars = content.chars().count();
-                let words = content.split_whitespace().count();
-                let bytes = content.len();
-                self.message = Some(format!(
-                    "{} lines, {} words, {} chars, {} bytes",
-                    lines, words, chars, bytes
-                ));
+            None => (1, self.handle_builtin(keys[0])),
+        };
+        if quit {
+            return true;
+        }
+
+        for key in keys.into_iter().skip(consumed) {
+            if self.dispatch_key(key) {
+                return true;
             }
-            "ascii" => {
-                // Show ASCII value of character under cursor
-                if let Some(ch) = self.buffer.char_at(self.cursor.line, self.cursor.col) {
-                    let code = ch as u32;
-                    self.message = Some(format!("'{}' = {} (0x{:X})", ch, code, code));
-                } else {
-                    self.message = Some("No character under cursor".to_string());
+        }
+        false
+    }
+
+    /// Run a key through the built-in handler of the current mode,
+    /// bypassing the keymap
+    fn handle_builtin(&mut self, key: KeyEvent) -> bool {
+        match self.mode {
+            Mode::Normal => self.handle_normal_builtin(key),
+            Mode::Insert => self.handle_insert_builtin(key),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => self.handle_visual_builtin(key),
+            _ => self.dispatch_key(key),
+        }
+    }
+
+    /// Whether a count, register, operator or the start of a key sequence
+    /// is waiting for the rest of a command
+    fn has_pending_command(&self) -> bool {
+        self.pending_op.is_some()
+            || self.operator.is_some()
+            || self.text_object.is_some()
+            || !self.numeric_prefix.is_empty()
+            || self.pending_register.is_some()
+            || self.pending_g
+            || !self.pending_keys.is_empty()
+    }
+
+    /// The keys of a command typed so far, like Vim's 'showcmd': `d2` is
+    /// waiting for a motion. The last SHOWCMD_WIDTH characters of it.
+    pub fn pending_command(&self) -> Option<&str> {
+        if !self.has_pending_command() || self.typed_keys.is_empty() {
+            return None;
+        }
+        let skip = self.typed_keys.chars().count().saturating_sub(SHOWCMD_WIDTH);
+        let start = self.typed_keys.char_indices().nth(skip).map_or(0, |(i, _)| i);
+        Some(&self.typed_keys[start..])
+    }
+
+    /// Time left before a pending key sequence resolves, if one is pending
+    pub fn pending_key_timeout(&self) -> Option<Duration> {
+        let since = self.pending_keys_since?;
+        let timeout = Duration::from_millis(self.config.timeout_len);
+        Some(timeout.saturating_sub(since.elapsed()))
+    }
+
+    /// Time left before the key hint popup appears, if a sequence is pending
+    pub fn key_hint_delay(&self) -> Option<Duration> {
+        let since = self.pending_keys_since?;
+        let delay = Duration::from_millis(self.config.which_key_delay);
+        Some(delay.saturating_sub(since.elapsed()))
+    }
+
+    /// Continuations of the pending key sequence, once it has been pending
+    /// for `which_key_delay`. Returns the keys typed so far and the hints.
+    pub fn key_hints(&self) -> Option<(String, Vec<(KeyCombo, String)>)> {
+        if !self.key_hint_delay()?.is_zero() {
+            return None;
+        }
+        let combos: Vec<KeyCombo> = self.pending_keys.iter().map(KeyCombo::from_event).collect();
+        let hints = self.keymap.for_mode(self.mode)?.continuations(&combos);
+        if hints.is_empty() {
+            return None;
+        }
+        let typed: String = combos.iter().map(|k| k.to_string()).collect();
+        Some((typed, hints))
+    }
+
+    /// Execute a keymap action with the count and register typed before it,
+    /// returns true if editor should quit
+    fn execute_action(&mut self, action: &Action, ctx: ActionContext) -> bool {
+        if !self.cursors.is_empty() {
+            self.move_extra_cursors(action, ctx.count());
+        }
+        match action {
+            // Movement
+            Action::MoveLeft => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_left(&self.buffer);
                 }
             }
-            "retab" => {
-                // Convert tabs to spaces (placeholder)
-                self.message = Some("Retab not yet implemented".to_string());
-            }
-            "exit" | "quit" => {
-                // Exit editor (alias for :q)
-                if self.buffer.is_modified() {
-                    self.message = Some("File modified. Use :q! to force quit".to_string());
-                } else {
-                    return true;
+            Action::MoveRight => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_right(&self.buffer);
                 }
             }
-            "number" => {
-                // Toggle line numbers
-                self.config.line_numbers = !self.config.line_numbers;
-                let status = if self.config.line_numbers { "enabled" } else { "disabled" };
-                self.message = Some(format!("Line numbers {}", status));
-            }
-            "highlight" | "hlsearch" => {
-                // Toggle search highlighting
-                if self.search.highlight_active {
-                    self.search.clear_highlight();
-                    self.message = Some("Search highlighting disabled".to_string());
-                } else {
-                    self.message = Some("Use / or ? to highlight searches".to_string());
+            Action::MoveUp => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_up(&self.buffer);
                 }
             }
-            "map" => {
-                // Show keybindings (placeholder)
-                self.message = Some("Custom keybindings not yet implemented".to_string());
-            }
-            "setlocal" | "setl" => {
-                // Local buffer settings (placeholder)
-                self.message = Some("Local buffer settings not yet implemented".to_string());
-            }
-            "digraphs" => {
-                // Show digraphs (placeholder)
-                self.message = Some("Digraphs not yet implemented".to_string());
-            }
-            "abbreviate" | "abbr" => {
-                // Abbreviations (placeholder)
-                self.message = Some("Abbreviations not yet implemented".to_string());
-            }
-            "colorscheme" | "colo" => {
-                // Color scheme (placeholder)
-                self.message = Some("Color schemes not yet implemented".to_string());
-            }
-            "ruler" => {
-                // Show/hide ruler (placeholder)
-                self.message = Some("Ruler is always shown".to_string());
-            }
-            "history" => {
-                // Show undo history count
-                self.message = Some(format!(
-                    "Undo: {} | Redo: {}",
-                    self.buffer.undo_count(),
-                    self.buffer.redo_count()
-                ));
-            }
-            "verbose" => {
-                // Verbose mode toggle (placeholder)
-                self.message = Some("Verbose mode not yet implemented".to_string());
-            }
-            "filetype" | "ft" => {
-                // Show/set filetype
-                self.message = Some("Filetype detection not yet implemented".to_string());
-            }
-            "redir" => {
-                // Redirect output (placeholder)
-                self.message = Some("Output redirection not yet implemented".to_string());
-            }
-            "terminal" | "term" => {
-                // Terminal mode (placeholder)
-                self.message = Some("Terminal mode not yet implemented".to_string());
-            }
-            "debug" => {
-                // Debug info
-                self.message = Some(format!(
-                    "Buffers: {} | Mode: {} | Cursor: {}:{}",
-                    self.buffer_manager.buffer_count(),
-                    self.mode.display(),
-                    self.cursor.line + 1,
-                    self.cursor.col + 1
-                ));
+            Action::MoveDown => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_down(&self.buffer);
+                }
             }
-            "readonly" | "ro" => {
-                // Read-only mode toggle
-                self.message = Some("Read-only mode not yet implemented".to_string());
+            Action::MoveWordForward => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_word_forward(&self.buffer);
+                }
             }
-            "modifiable" | "ma" => {
-                // Modifiable toggle
-                self.message = Some("Modifiable mode not yet implemented".to_string());
+            Action::MoveWordBackward => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_word_backward(&self.buffer);
+                }
             }
-            "list" => {
-                // Show whitespace
-                self.config.show_whitespace = !self.config.show_whitespace;
-                let status = if self.config.show_whitespace { "enabled" } else { "disabled" };
-                self.message = Some(format!("Whitespace display {}", status));
+            Action::MoveWordEnd => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_word_end(&self.buffer);
+                }
             }
-            "nolist" => {
-                // Hide whitespace
-                self.config.show_whitespace = false;
-                self.message = Some("Whitespace display disabled".to_string());
+            Action::MoveBigWordForward => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_big_word_forward(&self.buffer);
+                }
             }
-            "term" => {
-                // Open terminal (placeholder - future)
-                self.message = Some("Terminal not yet implemented".to_string());
+            Action::MoveBigWordBackward => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_big_word_backward(&self.buffer);
+                }
             }
-            "set" => {
-                // Show current settings
-                self.message = Some(format!(
-                    "tab_width={} line_numbers={} syntax={}",
-                    self.config.tab_width,
-                    self.config.line_numbers,
-                    self.config.syntax_highlighting
-                ));
+            Action::MoveBigWordEnd => {
+                for _ in 0..ctx.count() {
+                    self.cursor.move_big_word_end(&self.buffer);
+                }
             }
-            "registers" | "reg" => {
-                // Show register contents
-                if let Some(content) = self.registers.get_unnamed() {
-                    let preview: String = match content {
-                        RegisterContent::Chars(s) => s.chars().take(40).collect(),
-                        RegisterContent::Lines(s) => s.replace('\n', "\\n").chars().take(40).collect(),
-                        RegisterContent::Block(lines) => lines.join("\\n").chars().take(40).collect(),
-                    };
-                    self.message = Some(format!("\"\" = \"{}{}\"", preview, if preview.len() >= 40 { "..." } else { "" }));
-                } else {
-                    self.message = Some("Registers empty".to_string());
+            Action::MoveLineStart => self.cursor.move_to_line_start(),
+            Action::MoveFirstNonWhitespace => self.cursor.move_to_first_non_whitespace(&self.buffer),
+            Action::MoveLineEnd => self.cursor.move_to_line_end(&self.buffer),
+            Action::MoveBufferStart => self.cursor.move_to_buffer_start(),
+            Action::MoveBufferEnd => match ctx.count {
+                // 5G jumps to line 5
+                Some(n) => {
+                    self.cursor.line = (n - 1).min(self.buffer.line_count().saturating_sub(1));
+                    self.cursor.clamp(&self.buffer);
                 }
+                None => self.cursor.move_to_buffer_end(&self.buffer),
+            },
+            Action::PageUp => {
+                let half_page = self.viewport_height / 2;
+                self.cursor.line = self.cursor.line.saturating_sub(half_page);
+                self.cursor.clamp(&self.buffer);
             }
-            "set number" | "set nu" => {
-                self.config.line_numbers = true;
-                self.message = Some("Line numbers enabled".to_string());
+            Action::PageDown => {
+                let half_page = self.viewport_height / 2;
+                let max_line = self.buffer.line_count().saturating_sub(1);
+                self.cursor.line = (self.cursor.line + half_page).min(max_line);
+                self.cursor.clamp(&self.buffer);
             }
-            "set nonumber" | "set nonu" => {
-                self.config.line_numbers = false;
-                self.message = Some("Line numbers disabled".to_string());
+
+            // Mode switching
+            Action::EnterInsert => self.mode = Mode::Insert,
+            Action::EnterInsertAfter => {
+                self.cursor.move_right(&self.buffer);
+                self.mode = Mode::Insert;
             }
-            "set syntax" | "set syn" => {
-                self.config.syntax_highlighting = true;
-                self.message = Some("Syntax highlighting enabled".to_string());
+            Action::EnterInsertLineStart => {
+                self.cursor.move_to_line_start();
+                self.mode = Mode::Insert;
             }
-            "set nosyntax" | "set nosyn" => {
-                self.config.syntax_highlighting = false;
-                self.message = Some("Syntax highlighting disabled".to_string());
+            Action::EnterInsertLineEnd => {
+                self.cursor.move_to_line_end(&self.buffer);
+                self.mode = Mode::Insert;
             }
-            "tabnew" | "new" => {
-                // Create a new empty buffer
-                self.buffer = Buffer::new();
-                self.cursor = Cursor::new();
-                self.scroll_offset = 0;
-                self.message = Some("New buffer".to_string());
+            Action::EnterNormal => match self.mode {
+                Mode::Insert => self.exit_insert_mode(),
+                _ => {
+                    self.mode = Mode::Normal;
+                    self.selection = None;
+                }
+            },
+            Action::EnterCommand => {
+                // From visual mode the command works on the selected lines
+                if self.mode.is_visual() {
+                    self.selection = None;
+                    self.command_buffer = "'<,'>".to_string();
+                } else {
+                    self.command_buffer.clear();
+                }
+                self.mode = Mode::Command;
             }
-            "tabclose" | "close" => {
-                // Close current buffer (alias for :bd)
-                match self.buffer_manager.close_current() {
-                    Ok(_) => {
-                        if self.buffer_manager.has_buffers() {
-                            self.buffer = self.buffer_manager.current_buffer().clone();
-                        } else {
-                            self.buffer = Buffer::new();
+            Action::EnterVisual => self.enter_visual(VisualMode::Char),
+            Action::EnterVisualLine => self.enter_visual(VisualMode::Line),
+            Action::EnterVisualBlock => self.enter_visual(VisualMode::Block),
+
+            // Editing
+            Action::DeleteChar if !self.cursors.is_empty() => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.edit_at_cursors(|buffer, cursor| {
+                    for _ in 0..ctx.count() {
+                        if cursor.col >= buffer.line_len(cursor.line) {
+                            break;
                         }
-                        self.cursor = Cursor::new();
-                        self.scroll_offset = 0;
-                        self.message = Some("Buffer closed".to_string());
+                        buffer.delete_grapheme(cursor.line, cursor.col);
                     }
-                    Err(e) => {
-                        self.message = Some(format!("Error: {}", e));
+                    cursor.clamp(buffer);
+                });
+            }
+            Action::DeleteChar => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                // Yank the characters before deleting
+                let mut deleted = String::new();
+                for _ in 0..ctx.count() {
+                    let Some(ch) = self.buffer.char_at(self.cursor.line, self.cursor.col) else { break };
+                    if ch == '\n' {
+                        break;
                     }
+                    deleted.push(ch);
+                    self.buffer.delete_grapheme(self.cursor.line, self.cursor.col);
                 }
+                if !deleted.is_empty() {
+                    self.store_register(ctx.register, RegisterContent::Chars(deleted), true);
+                }
+                self.cursor.clamp(&self.buffer);
             }
-            "only" => {
-                // Close all other buffers (keep current)
-                self.message = Some("Closed all other buffers".to_string());
-            }
-            "e" | "edit" => {
-                // Reload current file
-                if let Some(path) = self.buffer.file_path() {
-                    let path_str = path.display().to_string();
-                    match self.buffer.reload() {
-                        Ok(_) => {
-                            self.cursor = Cursor::new();
-                            self.scroll_offset = 0;
-                            self.message = Some(format!("Reloaded: {}", path_str));
-                        }
-                        Err(e) => {
-                            self.message = Some(format!("Error reloading: {}", e));
-                        }
+            Action::DeleteLine => self.delete_lines(ctx),
+            Action::YankLine => self.yank_lines(ctx),
+            Action::Paste => self.paste(true, ctx),
+            Action::PasteBefore => self.paste(false, ctx),
+            Action::Undo => {
+                self.cursors.clear();
+                for _ in 0..ctx.count() {
+                    if let Some((line, col)) = self.buffer.undo(self.cursor.line, self.cursor.col) {
+                        self.cursor.line = line;
+                        self.cursor.col = col;
+                        self.cursor.clamp(&self.buffer);
+                        self.message = Some("Undo".to_string());
+                    } else {
+                        self.message = Some("Already at oldest change".to_string());
+                        break;
                     }
-                } else {
-                    self.message = Some("No file to reload".to_string());
                 }
             }
-            "enew" => {
-                // Create new empty buffer
-                self.buffer = Buffer::new();
-                self.cursor = Cursor::new();
-                self.scroll_offset = 0;
-                self.message = Some("New buffer".to_string());
-            }
-            _ if cmd.starts_with("e ") => {
-                let path = cmd.strip_prefix("e ").unwrap().trim();
-                match self.buffer_manager.open_file(path) {
-                    Ok(_) => {
-                        self.message = Some(format!("Opened in new buffer: {}", path));
-                    }
-                    Err(e) => {
-                        self.message = Some(format!("Error opening file: {}", e));
+            Action::Redo => {
+                self.cursors.clear();
+                for _ in 0..ctx.count() {
+                    if let Some((line, col)) = self.buffer.redo() {
+                        self.cursor.line = line;
+                        self.cursor.col = col;
+                        self.cursor.clamp(&self.buffer);
+                        self.message = Some("Redo".to_string());
+                    } else {
+                        self.message = Some("Already at newest change".to_string());
+                        break;
                     }
                 }
             }
-            "ls" | "buffers" => {
-                // List all open buffers
-                let buffers = self.buffer_manager.list_buffers();
-                if buffers.is_empty() {
-                    self.message = Some("No buffers open".to_string());
+            Action::NewLineBelow => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.cursor.move_to_line_end(&self.buffer);
+                let pos = self.cursor.byte_offset(&self.buffer);
+                self.buffer.insert(pos, "\n");
+                self.cursor.move_down(&self.buffer);
+                self.cursor.move_to_line_start();
+                self.mode = Mode::Insert;
+            }
+            Action::NewLineAbove => {
+                self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+                self.cursor.move_to_line_start();
+                let pos = self.cursor.byte_offset(&self.buffer);
+                self.buffer.insert(pos, "\n");
+                self.mode = Mode::Insert;
+            }
+            Action::JoinLines => self.join_at_cursor(ctx.count(), false),
+            Action::MoveLinesDown | Action::MoveLinesUp => {
+                let (start, end) = match self.selection {
+                    Some(selection) if self.mode.is_visual() => selection.line_range(),
+                    _ => (self.cursor.line, self.cursor.line),
+                };
+                let dest = if *action == Action::MoveLinesDown {
+                    (end + 1 + ctx.count()).min(self.buffer.line_count())
                 } else {
-                    let list: Vec<String> = buffers
-                        .iter()
-                        .map(|(i, name, is_current)| {
-                            if *is_current {
-                                format!("[{}] {}", i, name)
-                            } else {
-                                format!(" {}  {}", i, name)
-                            }
-                        })
-                        .collect();
-                    self.message = Some(list.join(" | "));
+                    start.saturating_sub(ctx.count())
+                };
+                if let Some(new_start) = self.move_lines(start, end, dest) {
+                    self.cursor.line = self.cursor.line - start + new_start;
+                    if let Some(selection) = &mut self.selection {
+                        selection.anchor_line = selection.anchor_line - start + new_start;
+                    }
+                    self.cursor.clamp(&self.buffer);
                 }
             }
-            _ if cmd.starts_with("b ") => {
-                let buf_num_str = cmd.strip_prefix("b ").unwrap().trim();
-                if let Ok(idx) = buf_num_str.parse::<usize>() {
-                    match self.buffer_manager.switch_to(idx) {
-                        Ok(_) => {
-                            self.buffer = self.buffer_manager.current_buffer().clone();
-                            self.cursor = Cursor::new();
-                            self.scroll_offset = 0;
-                            self.message = Some(format!("Switched to buffer {}", idx));
-                        }
-                        Err(e) => {
-                            self.message = Some(format!("Error: {}", e));
-                        }
+
+            // Search
+            Action::SearchForward => self.start_search(SearchDirection::Forward),
+            Action::SearchBackward => self.start_search(SearchDirection::Backward),
+            Action::NextMatch => {
+                for _ in 0..ctx.count() {
+                    if let Some(m) = self.search.next_match() {
+                        self.jump_to_match(m);
+                        self.message = Some(self.search.match_info());
                     }
-                } else {
-                    self.message = Some("Usage: :b <buffer_number>".to_string());
                 }
             }
-            _ if cmd.starts_with("bd") => {
-                // Close current buffer
-                match self.buffer_manager.close_current() {
-                    Ok(_) => {
-                        if self.buffer_manager.has_buffers() {
-                            self.buffer = self.buffer_manager.current_buffer().clone();
-                        } else {
-                            self.buffer = Buffer::new();
-                        }
-                        self.cursor = Cursor::new();
-                        self.scroll_offset = 0;
-                        self.message = Some("Buffer closed".to_string());
-                    }
-                    Err(e) => {
-                        self.message = Some(format!("Error: {}", e));
+            Action::PrevMatch => {
+                for _ in 0..ctx.count() {
+                    if let Some(m) = self.search.prev_match() {
+                        self.jump_to_match(m);
+                        self.message = Some(self.search.match_info());
                     }
                 }
             }
-            _ if cmd.starts_with("syntax ") || cmd.starts_with("syn ") => {
-                // Set syntax highlighting language
-                let lang = cmd.split_whitespace().nth(1).unwrap_or("");
-                match lang.to_lowercase().as_str() {
-                    "rust" | "rs" => {
-                        self.highlighter.set_syntax_for_extension("rs");
-                        self.message = Some("Syntax: Rust".to_string());
-                    }
-                    "python" | "py" => {
-                        self.highlighter.set_syntax_for_extension("py");
-                        self.message = Some("Syntax: Python".to_string());
-                    }
-                    "javascript" | "js" => {
-                        self.highlighter.set_syntax_for_extension("js");
-                        self.message = Some("Syntax: JavaScript".to_string());
-                    }
-                    "toml" => {
-                        self.highlighter.set_syntax_for_extension("toml");
-                        self.message = Some("Syntax: TOML".to_string());
-                    }
-                    "markdown" | "md" => {
-                        self.highlighter.set_syntax_for_extension("md");
-                        self.message = Some("Syntax: Markdown".to_string());
+            Action::ClearSearch => self.search.clear_highlight(),
+            Action::AddCursor => self.add_cursor_at_next_match(),
+            Action::ToggleExplorer => self.toggle_explorer(),
+            Action::FindFiles => self.open_picker(PickerKind::Files),
+            Action::FindBuffers => self.open_picker(PickerKind::Buffers),
+            Action::FindLines => self.open_picker(PickerKind::Lines),
+            Action::FindRecent => self.open_picker(PickerKind::Recent),
+            Action::NextHunk => self.jump_to_hunk(true, ctx.count()),
+            Action::PrevHunk => self.jump_to_hunk(false, ctx.count()),
+            Action::JumpToTag if self.buffer.scratch_name() == Some(HELP_BUFFER) => self.follow_help_link(),
+            Action::JumpToTag => match self.get_word_under_cursor() {
+                Some(word) => self.jump_to_tag(&word),
+                None => self.message = Some("No identifier under cursor".to_string()),
+            },
+            Action::SendToTerminal => self.send_to_terminal(),
+            Action::PopTag => {
+                for _ in 0..ctx.count() {
+                    self.pop_tag();
+                }
+            }
+
+            // File operations
+            Action::Save => return self.execute_command("w"),
+            Action::SaveAs => {
+                self.mode = Mode::Command;
+                self.command_buffer = "w ".to_string();
+            }
+            Action::Quit => return self.execute_command("q"),
+            Action::ForceQuit => return self.execute_command("q!"),
+            Action::SaveAndQuit => return self.execute_command("wq"),
+
+            Action::Command(cmd) => {
+                // A count repeats the command, so `3<leader>x` runs it three times
+                let cmd = cmd.trim_start_matches(':');
+                for _ in 0..ctx.count() {
+                    if self.execute_command(cmd) {
+                        return true;
                     }
-                    "off" | "none" | "plain" => {
-                        self.highlighter.set_syntax_for_extension("");
-                        self.message = Some("Syntax highlighting off".to_string());
+                }
+            }
+            Action::Lua(id) => {
+                if self.run_lua_keymap(*id) {
+                    return true;
+                }
+            }
+            Action::Noop => {}
+        }
+
+        let opens_line = matches!(action, Action::NewLineBelow | Action::NewLineAbove);
+        if self.mode == Mode::Insert
+            && (opens_line
+                || matches!(
+                    action,
+                    Action::EnterInsert | Action::EnterInsertAfter | Action::EnterInsertLineStart | Action::EnterInsertLineEnd
+                ))
+        {
+            self.insert_repeat = (ctx.count() > 1 && self.cursors.is_empty()).then(|| InsertRepeat {
+                start: self.cursor.byte_offset(&self.buffer),
+                count: ctx.count(),
+                new_line: opens_line,
+            });
+        }
+
+        self.numeric_prefix.clear();
+        if self.mode.is_visual() {
+            self.update_selection();
+        }
+        self.ensure_cursor_visible();
+        false
+    }
+
+    /// Consume the numeric prefix and selected register for the next command
+    fn take_action_context(&mut self) -> ActionContext {
+        let count = self.numeric_prefix.parse().ok().filter(|&n| n > 0);
+        self.numeric_prefix.clear();
+        ActionContext {
+            count,
+            register: self.pending_register.take(),
+        }
+    }
+
+    /// Store yanked or deleted text in the selected register, or the
+    /// default registers when none was given
+    fn store_register(&mut self, register: Option<char>, content: RegisterContent, is_delete: bool) {
+        let name = register.or(self.config.clipboard.then_some('+'));
+        if let Some(selection) = name.and_then(clipboard::Selection::for_register) {
+            // Without a clipboard tool the register is kept in the editor
+            if let Err(e) = self.clipboard.set(selection, &content.full_text()) {
+                if self.clipboard.name().is_some() {
+                    self.report_error(format!("Clipboard: {}", e));
+                }
+            }
+            if register.is_none() {
+                self.registers.set('+', content.clone(), false);
+            }
+        }
+        match register {
+            Some(name) => self.registers.set(name, content, is_delete),
+            None if is_delete => self.registers.delete(content),
+            None => self.registers.yank(content),
+        }
+    }
+
+    /// The content of a register (the unnamed one by default, the
+    /// clipboard with the clipboard option), read from the system for
+    /// + and *
+    fn read_register(&mut self, register: Option<char>) -> Option<RegisterContent> {
+        let name = register.or(self.config.clipboard.then_some('+'));
+        if let Some(name) = name {
+            if let Some(selection) = clipboard::Selection::for_register(name) {
+                self.refresh_clipboard_register(name, selection);
+            }
+        }
+        match name {
+            Some(name) => self.registers.get(name).cloned(),
+            None => self.registers.get_unnamed().cloned(),
+        }
+    }
+
+    /// Take the system's text into register `name` unless it already holds
+    /// it, so a block yanked here stays a block
+    fn refresh_clipboard_register(&mut self, name: char, selection: clipboard::Selection) {
+        match self.clipboard.get(selection) {
+            Ok(text) if self.registers.get(name).map(RegisterContent::full_text) != Some(text.clone()) => {
+                self.registers.set(name, RegisterContent::from_text(text), false);
+            }
+            Ok(_) => {}
+            Err(e) if self.clipboard.name().is_some() => self.report_error(format!("Clipboard: {}", e)),
+            Err(_) => {}
+        }
+    }
+
+    /// Leave insert mode, stepping the cursor back one (Vim behavior)
+    /// Apply `edit` at the cursor and at every extra cursor, from the end
+    /// of the buffer back so positions not yet edited stay valid
+    fn edit_at_cursors(&mut self, edit: impl Fn(&mut Buffer, &mut Cursor)) {
+        let mut cursors: Vec<Cursor> = std::iter::once(self.cursor).chain(self.cursors.iter().copied()).collect();
+        let mut order: Vec<(usize, usize)> = cursors
+            .iter()
+            .enumerate()
+            .map(|(i, cursor)| (cursor.byte_offset(&self.buffer), i))
+            .collect();
+        order.sort_unstable_by(|a, b| b.cmp(a));
+
+        // Byte offsets of the edited cursors, shifted by each later edit
+        let mut edited: Vec<(usize, usize)> = Vec::with_capacity(cursors.len());
+        for (_, i) in order {
+            let len = self.buffer.len();
+            edit(&mut self.buffer, &mut cursors[i]);
+            let grown = self.buffer.len() as isize - len as isize;
+            for (offset, _) in &mut edited {
+                *offset = offset.saturating_add_signed(grown);
+            }
+            edited.push((cursors[i].byte_offset(&self.buffer), i));
+        }
+        for (offset, i) in edited {
+            let (line, col) = self.buffer.byte_to_pos(offset);
+            cursors[i] = Cursor::at(line, col);
+        }
+
+        self.cursor = cursors[0];
+        self.cursors.set(cursors.into_iter().skip(1), &self.cursor);
+    }
+
+    /// Repeat a motion or insert-entry action at the extra cursors; the
+    /// main cursor is moved by `execute_action` itself
+    fn move_extra_cursors(&mut self, action: &Action, count: usize) {
+        let buffer = &self.buffer;
+        let motion: fn(&mut Cursor, &Buffer) = match action {
+            Action::MoveLeft => Cursor::move_left,
+            Action::MoveRight | Action::EnterInsertAfter => Cursor::move_right,
+            Action::MoveUp => Cursor::move_up,
+            Action::MoveDown => Cursor::move_down,
+            Action::MoveWordForward => Cursor::move_word_forward,
+            Action::MoveWordBackward => Cursor::move_word_backward,
+            Action::MoveWordEnd => Cursor::move_word_end,
+            Action::MoveBigWordForward => Cursor::move_big_word_forward,
+            Action::MoveBigWordBackward => Cursor::move_big_word_backward,
+            Action::MoveBigWordEnd => Cursor::move_big_word_end,
+            Action::MoveLineStart | Action::EnterInsertLineStart => |cursor, _| cursor.move_to_line_start(),
+            Action::MoveFirstNonWhitespace => Cursor::move_to_first_non_whitespace,
+            Action::MoveLineEnd | Action::EnterInsertLineEnd => Cursor::move_to_line_end,
+            _ => return,
+        };
+        // Only motions take a count; `3A` repeats the typed text instead
+        let count = if matches!(action, Action::EnterInsertAfter | Action::EnterInsertLineStart | Action::EnterInsertLineEnd) {
+            1
+        } else {
+            count
+        };
+        for cursor in self.cursors.iter_mut() {
+            for _ in 0..count {
+                motion(cursor, buffer);
+            }
+        }
+        let moved: Vec<Cursor> = self.cursors.iter().copied().collect();
+        self.cursors.set(moved, &self.cursor);
+    }
+
+    /// Add a cursor at the next occurrence of the word under the cursor
+    /// (Ctrl-N). The new cursor becomes the main one, so the view follows.
+    fn add_cursor_at_next_match(&mut self) {
+        let Some(word) = self.get_word_under_cursor() else {
+            self.message = Some("No word under cursor".to_string());
+            return;
+        };
+        if self.cursors.is_empty() {
+            // Start from the word itself so wrapping around does not add it twice
+            let line = self.buffer.line(self.cursor.line);
+            let chars: Vec<char> = line.chars().collect();
+            while self.cursor.col > 0 && (chars[self.cursor.col - 1].is_alphanumeric() || chars[self.cursor.col - 1] == '_') {
+                self.cursor.col -= 1;
+            }
+        }
+
+        let Ok(re) = regex::Regex::new(&format!("\\b{}\\b", regex::escape(&word))) else { return };
+        let mut matches = Vec::new();
+        for line_idx in 0..self.buffer.line_count() {
+            let line = self.buffer.line(line_idx);
+            for m in re.find_iter(&line) {
+                let col = line[..m.start()].chars().count();
+                if (line_idx, col) != (self.cursor.line, self.cursor.col) && !self.cursors.contains(line_idx, col) {
+                    matches.push((line_idx, col));
+                }
+            }
+        }
+        let here = (self.cursor.line, self.cursor.col);
+        let next = matches.iter().find(|&&pos| pos > here).or(matches.first());
+        match next {
+            Some(&(line, col)) => {
+                self.cursors.add(self.cursor);
+                self.cursor = Cursor::at(line, col);
+                self.ensure_cursor_visible();
+                self.message = Some(format!("{} cursors", self.cursors.len() + 1));
+            }
+            None => self.message = Some(format!("No more matches for {}", word)),
+        }
+    }
+
+    fn exit_insert_mode(&mut self) {
+        self.mode = Mode::Normal;
+        let file = self.buffer.file_path().cloned();
+        self.marks.set(mark::LAST_INSERT, file.as_deref(), self.cursor.line, self.cursor.col);
+        if let Some(repeat) = self.insert_repeat.take() {
+            self.finish_insert_repeat(repeat);
+        }
+        if let Some(block) = self.block_insert.take() {
+            self.finish_block_insert(block);
+        }
+        for cursor in std::iter::once(&mut self.cursor).chain(self.cursors.iter_mut()) {
+            if cursor.col > 0 {
+                cursor.move_left(&self.buffer);
+            }
+        }
+    }
+
+    /// Start a visual selection, switch its type, or leave visual mode
+    /// when the same kind is requested again
+    fn enter_visual(&mut self, visual_mode: VisualMode) {
+        let target = match visual_mode {
+            VisualMode::Char => Mode::Visual,
+            VisualMode::Line => Mode::VisualLine,
+            VisualMode::Block => Mode::VisualBlock,
+        };
+        if self.mode == target {
+            self.mode = Mode::Normal;
+            self.selection = None;
+        } else if self.mode.is_visual() {
+            if let Some(ref mut sel) = self.selection {
+                sel.mode = visual_mode;
+            }
+            self.mode = target;
+        } else {
+            self.selection = Some(Selection::new(visual_mode, self.cursor.line, self.cursor.col));
+            self.mode = target;
+        }
+    }
+
+    /// Yank count lines starting at the cursor into the registers (yy)
+    fn yank_lines(&mut self, ctx: ActionContext) {
+        let end = (self.cursor.line + ctx.count()).min(self.buffer.line_count());
+        self.yank_line_range(self.cursor.line, end, ctx.register);
+    }
+
+    /// Yank lines `start..end` into the registers
+    fn yank_line_range(&mut self, start: usize, end: usize, register: Option<char>) {
+        let text: String = (start..end).map(|line| format!("{}\n", self.buffer.line(line))).collect();
+        let lines = end - start;
+        self.store_register(register, RegisterContent::Lines(text), false);
+        self.message = Some(format!("{} line{} yanked", lines, if lines == 1 { "" } else { "s" }));
+    }
+
+    /// Delete count lines starting at the cursor into the registers (dd)
+    fn delete_lines(&mut self, ctx: ActionContext) {
+        let end = (self.cursor.line + ctx.count()).min(self.buffer.line_count());
+        self.delete_line_range(self.cursor.line, end, ctx.register);
+    }
+
+    /// Delete lines `start..end` into the registers, leaving the cursor on
+    /// the line after them
+    fn delete_line_range(&mut self, start: usize, end: usize, register: Option<char>) {
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        let text: String = (start..end).map(|line| format!("{}\n", self.buffer.line(line))).collect();
+        let lines = end - start;
+        self.store_register(register, RegisterContent::Lines(text), true);
+        for _ in 0..lines {
+            self.buffer.delete_line(start);
+        }
+        self.cursor.line = start;
+        self.cursor.clamp(&self.buffer);
+        self.ensure_cursor_visible();
+        self.message = Some(format!("{} line{} deleted", lines, if lines == 1 { "" } else { "s" }));
+    }
+
+    /// `:[range]w <file>` writes lines to another file and `:[range]w >>
+    /// <file>` appends them. An existing file is only replaced with `!`.
+    fn write_range(&mut self, range: substitute::Range, args: &str, force: bool) {
+        let Some((start, end)) = self.resolve_range(range) else { return };
+        let (append, name) = match args.strip_prefix(">>") {
+            Some(name) => (true, name.trim()),
+            None => (false, args),
+        };
+        if name.is_empty() {
+            self.report_error("Give a file name to write the lines to".to_string());
+            return;
+        }
+        let path = crate::project::resolve_dir(&self.working_dir(), name);
+        if !append && !force && path.exists() {
+            self.report_error(format!("{} exists (add ! to replace it)", name));
+            return;
+        }
+        let text = self.buffer.lines_text(start, end);
+        let result = if append {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| std::io::Write::write_all(&mut file, text.as_bytes()))
+        } else {
+            std::fs::write(&path, &text)
+        };
+        let lines = end - start + 1;
+        match result {
+            Ok(()) => self.message = Some(format!(
+                "{} line{} {} {}",
+                lines,
+                if lines == 1 { "" } else { "s" },
+                if append { "appended to" } else { "written to" },
+                name,
+            )),
+            Err(e) => self.report_error(format!("Cannot write {}: {}", name, e)),
+        }
+    }
+
+    /// `:[range]d [x] [count]` and `:[range]y [x] [count]`. A count
+    /// starts at the last line of the range, like Vim.
+    fn delete_or_yank_command(&mut self, range: substitute::Range, args: &str, delete: bool) {
+        let Some((start, end)) = self.resolve_range(range) else { return };
+        let mut words = args.split_whitespace().peekable();
+        let register = match words.peek() {
+            Some(word) if !word.starts_with(|c: char| c.is_ascii_digit()) => {
+                let mut chars = word.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(name), None) => {
+                        words.next();
+                        Some(name)
                     }
                     _ => {
-                        self.message = Some(format!("Unknown syntax: {}. Use: rust, python, js, toml, md, off", lang));
+                        self.report_error(format!("Trailing characters: {}", args.trim()));
+                        return;
                     }
                 }
             }
+            _ => None,
+        };
+        let (start, end) = match words.next().map(str::parse::<usize>) {
+            None => (start, end),
+            Some(Ok(count)) if count > 0 => (end, (end + count - 1).min(self.buffer.line_count() - 1)),
+            Some(_) => {
+                self.report_error(format!("Invalid count: {}", args.trim()));
+                return;
+            }
+        };
+        if delete {
+            self.delete_line_range(start, end + 1, register);
+        } else {
+            self.yank_line_range(start, end + 1, register);
+        }
+    }
+
+    /// `:[line]put [x]`: put a register as whole lines below `line`, or
+    /// above it with `!`; None for line 0, above the first line. The
+    /// cursor ends on the last new line.
+    fn put_command(&mut self, range: Option<substitute::Range>, above: bool, register: &str) {
+        let mut chars = register.chars();
+        let register = match (chars.next(), chars.next()) {
+            (name, None) => name,
             _ => {
-                self.message = Some(format!("Unknown command: {}", cmd));
+                self.report_error(format!("Trailing characters: {}", register));
+                return;
+            }
+        };
+        let (line, above) = match range {
+            Some(range) => match self.resolve_range(range) {
+                // The last line of a range, like Vim
+                Some((_, end)) => (end, above),
+                None => return,
+            },
+            None => (0, true),
+        };
+        let mut text = match self.read_register(register) {
+            Some(content) => content.full_text(),
+            None => {
+                self.report_error(format!("Nothing in register {}", register.unwrap_or('"')));
+                return;
             }
+        };
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let lines = text.lines().count();
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        if above {
+            self.buffer.insert_line_above(line, &text);
+            self.cursor.line = line + lines - 1;
+        } else {
+            self.buffer.insert_line_below(line, &text);
+            self.cursor.line = line + lines;
+        }
+        self.cursor.move_to_first_non_whitespace(&self.buffer);
+        self.ensure_cursor_visible();
+        if lines > 2 {
+            self.message = Some(format!("{} more lines", lines));
         }
-        false
     }
 
-    /// Handle keys in search mode
-    fn handle_search_mode(&mut self, key: KeyEvent) -> bool {
-        match key.code {
-            KeyCode::Esc => {
-                self.mode = Mode::Normal;
-                self.search.clear_highlight();
+    /// Paste a register (unnamed by default) count times after (p) or
+    /// before (P) the cursor
+    fn paste(&mut self, after: bool, ctx: ActionContext) {
+        let Some(content) = self.read_register(ctx.register) else { return };
+        let content = match content {
+            RegisterContent::Chars(text) => RegisterContent::Chars(text.repeat(ctx.count())),
+            RegisterContent::Lines(text) => RegisterContent::Lines(text.repeat(ctx.count())),
+            block => block,
+        };
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        if let RegisterContent::Lines(text) = &content {
+            // Like Vim's 'report', only larger pastes are worth a message
+            let lines = text.lines().count();
+            if lines > 2 {
+                self.message = Some(format!("{} more lines", lines));
             }
-            KeyCode::Enter => {
-                // Execute the search
-                let lines: Vec<String> = (0..self.buffer.line_count())
-                    .map(|i| self.buffer.line(i))
-                    .collect();
-                self.search.execute(&lines, self.cursor.line, self.cursor.col);
-                
-                // Jump to first match
-                if let Some(m) = self.search.current() {
-                    self.cursor.line = m.line;
-                    self.cursor.col = m.start_col;
-                    self.ensure_cursor_visible();
-                    self.message = Some(self.search.match_info());
-                } else if !self.search.is_empty() {
-                    self.message = Some("Pattern not found".to_string());
+        }
+        match content {
+            RegisterContent::Lines(text) if after => {
+                // Paste below current line
+                self.buffer.insert_line_below(self.cursor.line, &text);
+                self.cursor.line += 1;
+                self.cursor.col = 0;
+            }
+            RegisterContent::Lines(text) => {
+                // Paste above current line
+                self.buffer.insert_line_above(self.cursor.line, &text);
+                self.cursor.col = 0;
+            }
+            RegisterContent::Chars(text) if after => {
+                // Paste after cursor
+                let pos = self.cursor.byte_offset(&self.buffer);
+                self.buffer.insert(pos + 1, &text);
+                self.cursor.col += 1;
+            }
+            RegisterContent::Chars(text) => {
+                // Paste before cursor
+                let pos = self.cursor.byte_offset(&self.buffer);
+                self.buffer.insert(pos, &text);
+            }
+            RegisterContent::Block(_) => {
+                // TODO: block paste
+            }
+        }
+        self.ensure_cursor_visible();
+    }
+
+    /// Update the selection's cursor position
+    fn update_selection(&mut self) {
+        if let Some(ref mut sel) = self.selection {
+            sel.update_cursor(self.cursor.line, self.cursor.col);
+        }
+    }
+
+    /// Yank the current selection to register
+    fn yank_selection(&mut self) {
+        let Some((content, linewise)) = self.selection_text() else { return };
+        let line_count = content.lines().count().max(1);
+        let register_content = if linewise {
+            RegisterContent::Lines(content)
+        } else {
+            RegisterContent::Chars(content)
+        };
+        let register = self.pending_register.take();
+        self.store_register(register, register_content, false);
+        self.message = Some(format!("{} line{} yanked", line_count, if line_count == 1 { "" } else { "s" }));
+    }
+
+    /// Text of the current selection, and whether it is linewise
+    fn selection_text(&self) -> Option<(String, bool)> {
+        self.selection.as_ref().map(|sel| self.area_text(sel))
+    }
+
+    /// Text of a selection, and whether it is linewise
+    fn area_text(&self, sel: &Selection) -> (String, bool) {
+        let mut content = String::new();
+        let linewise = matches!(sel.mode, VisualMode::Line);
+        
+        match sel.mode {
+            VisualMode::Char => {
+                let (start_line, start_col, end_line, end_col) = sel.normalized();
+                for line_idx in start_line..=end_line {
+                    let line = self.buffer.line(line_idx);
+                    let chars: Vec<char> = line.chars().collect();
+                    
+                    let start = if line_idx == start_line { start_col } else { 0 };
+                    let end = if line_idx == end_line { (end_col + 1).min(chars.len()) } else { chars.len() };
+                    
+                    let part: String = chars.get(start..end).unwrap_or(&[]).iter().collect();
+                    content.push_str(&part);
+                    
+                    if line_idx < end_line {
+                        content.push('\n');
+                    }
                 }
-                
-                self.mode = Mode::Normal;
             }
-            KeyCode::Backspace => {
-                if !self.search.pop_char() {
-                    self.mode = Mode::Normal;
+            VisualMode::Line => {
+                let (start_line, end_line) = sel.line_range();
+                for line_idx in start_line..=end_line {
+                    content.push_str(&self.buffer.line(line_idx));
+                    content.push('\n');
                 }
             }
-            KeyCode::Char(c) => {
-                self.search.push_char(c);
+            VisualMode::Block => {
+                let (start_line, end_line) = sel.line_range();
+                let (start_col, end_col) = sel.col_range();
+                for line_idx in start_line..=end_line {
+                    let line = self.buffer.line(line_idx);
+                    let chars: Vec<char> = line.chars().collect();
+                    let part: String = chars.get(start_col..(end_col + 1).min(chars.len())).unwrap_or(&[]).iter().collect();
+                    content.push_str(&part);
+                    if line_idx < end_line {
+                        content.push('\n');
+                    }
+                }
             }
-            _ => {}
         }
-        false
+        
+        (content, linewise)
     }
 
-    /// Handle keys in visual mode
-    fn handle_visual_mode(&mut self, key: KeyEvent) -> bool {
-        match key.code {
-            KeyCode::Esc => {
+    /// First and last line of the paragraph around the cursor
+    /// (blank-line delimited)
+    fn paragraph_range(&self) -> (usize, usize) {
+        let is_blank = |idx: usize| self.buffer.line(idx).trim().is_empty();
+        let last = self.buffer.line_count().saturating_sub(1);
+        let mut start = self.cursor.line;
+        while start > 0 && !is_blank(start - 1) {
+            start -= 1;
+        }
+        let mut end = self.cursor.line;
+        while end < last && !is_blank(end + 1) {
+            end += 1;
+        }
+        (start, end)
+    }
+
+    /// Lines of the paragraph around the cursor (blank-line delimited)
+    fn paragraph_text(&self) -> String {
+        let (start, end) = self.paragraph_range();
+        let mut text = String::new();
+        for idx in start..=end {
+            text.push_str(&self.buffer.line(idx));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Send the visual selection, or the paragraph around the cursor, to
+    /// the embedded terminal or the configured REPL command
+    fn send_to_terminal(&mut self) {
+        let text = match self.selection_text() {
+            Some((text, _)) if self.mode.is_visual() => {
                 self.mode = Mode::Normal;
                 self.selection = None;
+                text
             }
-            
-            // Movement - same as normal mode but updates selection
-            KeyCode::Char('h') | KeyCode::Left => {
-                self.cursor.move_left(&self.buffer);
-                self.update_selection();
+            _ => self.paragraph_text(),
+        };
+        let mut text = text;
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let line_count = text.lines().count();
+
+        if let Some(terminal) = &mut self.terminal {
+            // A terminal's Enter key is a carriage return
+            terminal.write(text.replace('\n', "\r").as_bytes());
+        } else if let Some(command) = self.config.repl_command.clone() {
+            if let Err(e) = pipe_to_command(&command, &text) {
+                self.message = Some(format!("repl_command failed: {}", e));
+                return;
             }
-            KeyCode::Char('j') | KeyCode::Down => {
-                self.cursor.move_down(&self.buffer);
-                self.update_selection();
-                self.ensure_cursor_visible();
+        } else {
+            self.message = Some("No terminal: open one with :terminal or set repl_command".to_string());
+            return;
+        }
+        self.message = Some(format!("Sent {} line(s)", line_count));
+    }
+
+    /// Show the help buffer at `topic`, or at the top if it is empty
+    fn show_help(&mut self, topic: &str) {
+        let page = HelpPage::build();
+        let topic = topic.trim();
+        let Some(line) = (if topic.is_empty() { Some(0) } else { page.find(topic) }) else {
+            self.report_error(format!("Sorry, no help for {}", topic));
+            return;
+        };
+        if self.buffer.scratch_name() != Some(HELP_BUFFER) {
+            let previous = std::mem::replace(&mut self.buffer, Buffer::scratch(HELP_BUFFER, &page.text));
+            self.help_return = Some((previous, self.cursor, self.scroll_offset));
+            self.git_base = None;
+            self.git_hunks.clear();
+        }
+        self.show_help_line(line);
+        self.message = Some("Ctrl-] follows a |link|, Ctrl-T goes back, q closes help".to_string());
+    }
+
+    /// Put help line `line` at the top of the screen
+    fn show_help_line(&mut self, line: usize) {
+        self.reset_cursor();
+        self.cursor.line = line.min(self.buffer.line_count().saturating_sub(1));
+        self.scroll_offset = self.cursor.line;
+        self.ensure_cursor_visible();
+    }
+
+    /// Follow the |link| (or the word) under the cursor in the help buffer
+    fn follow_help_link(&mut self) {
+        let line = self.buffer.line(self.cursor.line);
+        let topic = match help::link_at(&line, self.cursor.col) {
+            Some(link) => link.to_string(),
+            None => match self.get_word_under_cursor() {
+                Some(word) => word,
+                None => return,
+            },
+        };
+        let Some(target) = HelpPage::build().find(&topic) else {
+            self.message = Some(format!("Sorry, no help for {}", topic));
+            return;
+        };
+        self.tag_stack.push(TagLocation {
+            file: None,
+            line: self.cursor.line,
+            col: self.cursor.col,
+        });
+        self.show_help_line(target);
+    }
+
+    /// Leave the help buffer for the buffer it was opened from
+    fn close_help(&mut self) {
+        if self.buffer.scratch_name() != Some(HELP_BUFFER) {
+            return;
+        }
+        match self.help_return.take() {
+            Some((buffer, cursor, scroll_offset)) => {
+                self.buffer = buffer;
+                self.reset_cursor();
+                self.cursor = cursor;
+                self.scroll_offset = scroll_offset;
+            }
+            None => {
+                self.buffer = Buffer::new();
+                self.reset_cursor();
+                self.scroll_offset = 0;
             }
-            KeyCode::Char('k') | KeyCode::Up => {
-                self.cursor.move_up(&self.buffer);
-                self.update_selection();
-                self.ensure_cursor_visible();
+        }
+        self.refresh_git_base();
+    }
+
+    /// Handle keys while the file explorer has focus
+    fn handle_explorer_mode(&mut self, key: KeyEvent) -> bool {
+        let Some(explorer) = self.explorer.as_mut() else {
+            self.mode = Mode::Normal;
+            return false;
+        };
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => explorer.move_down(),
+            KeyCode::Char('k') | KeyCode::Up => explorer.move_up(),
+            KeyCode::Char('h') | KeyCode::Left => explorer.collapse(),
+            KeyCode::Char('R') => explorer.refresh(),
+            KeyCode::Enter | KeyCode::Char('l') | KeyCode::Char('o') | KeyCode::Right => {
+                let Some(entry) = explorer.selected_entry().cloned() else { return false };
+                if entry.is_dir {
+                    explorer.toggle();
+                } else {
+                    let path = entry.path.to_string_lossy().into_owned();
+                    if let Err(e) = self.load_file(&path) {
+                        self.report_error(format!("Error opening {}", e));
+                    }
+                    self.mode = Mode::Normal;
+                }
             }
-            KeyCode::Char('l') | KeyCode::Right => {
-                self.cursor.move_right(&self.buffer);
-                self.update_selection();
+            // File operations go through the command line so they can be
+            // edited or cancelled with Esc
+            KeyCode::Char('a') => {
+                self.mode = Mode::Command;
+                self.command_buffer = "ExplorerCreate ".to_string();
             }
-            KeyCode::Char('0') => {
-                self.cursor.move_to_line_start();
-                self.update_selection();
+            KeyCode::Char('r') => {
+                let name = explorer.selected_entry().map(|e| e.name()).unwrap_or_default();
+                self.mode = Mode::Command;
+                self.command_buffer = format!("ExplorerRename {}", name);
             }
-            KeyCode::Char('^') => {
-                self.cursor.move_to_first_non_whitespace(&self.buffer);
-                self.update_selection();
+            // Enter confirms, Esc cancels
+            KeyCode::Char('d') if explorer.selected_entry().is_some() => {
+                self.mode = Mode::Command;
+                self.command_buffer = "ExplorerDelete".to_string();
             }
-            KeyCode::Char('$') => {
-                self.cursor.move_to_line_end(&self.buffer);
-                self.update_selection();
+            KeyCode::Char(':') => {
+                self.mode = Mode::Command;
+                self.command_buffer.clear();
             }
-            KeyCode::Char('g') => {
-                self.cursor.move_to_buffer_start();
-                self.update_selection();
-                self.ensure_cursor_visible();
+            KeyCode::Char('q') => {
+                self.explorer = None;
+                self.mode = Mode::Normal;
             }
-            KeyCode::Char('G') => {
-                self.cursor.move_to_buffer_end(&self.buffer);
-                self.update_selection();
-                self.ensure_cursor_visible();
+            KeyCode::Esc | KeyCode::Tab => self.mode = Mode::Normal,
+            _ => {}
+        }
+        false
+    }
+
+    /// Close the current buffer (:bd), showing the next one
+    fn close_buffer(&mut self) {
+        self.fire_buffer_autocmd(lua::Event::BufDelete);
+        self.leave_buffer();
+        match self.buffer_manager.close_current() {
+            Ok(_) => {
+                if self.buffer_manager.has_buffers() {
+                    self.buffer = self.buffer_manager.current_buffer().clone();
+                } else {
+                    self.buffer = Buffer::new();
+                }
+                self.reset_cursor();
+                self.scroll_offset = 0;
+                self.restore_position();
+                self.show_bookmarks();
+                self.detect_syntax();
+                self.refresh_git_base();
+                self.message = Some("Buffer closed".to_string());
+                self.load_project_config();
             }
-            
-            // Word motions
-            KeyCode::Char('w') => {
-                self.cursor.move_word_forward(&self.buffer);
-                self.update_selection();
+            Err(e) => {
+                self.report_error(e);
+            }
+        }
+    }
+
+    /// Path of the current buffer's local file, for :Rename and :Delete
+    fn local_file(&mut self) -> Option<PathBuf> {
+        let path = self.buffer.file_path().cloned().filter(|_| self.remote_path().is_none());
+        if path.is_none() {
+            self.message = Some("No local file for this buffer".to_string());
+        }
+        path
+    }
+
+    /// Ask a yes/no question; `y` runs `command`, `n` or Esc cancels
+    fn confirm(&mut self, question: String, command: String) {
+        self.prompt = Some((Prompt::choose(question, Vec::new(), YES_NO), PromptAction::Command(command)));
+    }
+
+    /// Before writing a file whose directory does not exist yet: create it
+    /// with `create` (`:w ++p`), or else ask whether to. Returns false if
+    /// the file cannot be written yet.
+    fn prepare_parent_dir(&mut self, cmd: &str, create: bool) -> bool {
+        let Some(dir) = self.buffer.file_path()
+            .and_then(|path| path.parent())
+            .filter(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .map(Path::to_path_buf)
+        else {
+            return true;
+        };
+        if !create {
+            self.confirm(format!("{} does not exist, create it?", dir.display()), format!("{} ++p", cmd));
+            return false;
+        }
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            self.report_error(format!("Cannot create {}: {}", dir.display(), e));
+            return false;
+        }
+        true
+    }
+
+    /// Use syntax `name` for the current buffer (:set filetype), whatever
+    /// its file name says
+    fn set_filetype(&mut self, name: &str) -> Result<(), String> {
+        if !self.highlighter.set_syntax_by_name(name) {
+            let known = self.highlighter.syntax_names().join(", ");
+            return Err(format!("Unknown filetype: {} (use {} or off)", name, known));
+        }
+        self.buffer.set_filetype(Some(name.to_string()));
+        Ok(())
+    }
+
+    /// Move the current file to `name` (:Rename), relative to the file's
+    /// directory, and keep editing it there. Asks before replacing a file
+    /// unless `force`.
+    fn rename_file(&mut self, name: &str, force: bool) {
+        let Some(old) = self.local_file() else { return };
+        if name.is_empty() {
+            let current = old.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            let prompt = Prompt::input("Rename to:", &current);
+            self.prompt = Some((prompt, PromptAction::CommandWithText("Rename".to_string())));
+            return;
+        }
+        let new = old.parent().unwrap_or(Path::new("")).join(name);
+        if new.exists() && !force {
+            self.confirm(format!("{} exists, replace it?", new.display()), format!("Rename! {}", name));
+            return;
+        }
+        if old.exists() {
+            if let Err(e) = std::fs::rename(&old, &new) {
+                self.report_error(format!("Cannot rename {}: {}", old.display(), e));
+                return;
             }
-            KeyCode::Char('b') => {
-                self.cursor.move_word_backward(&self.buffer);
-                self.update_selection();
+        }
+        let managed = self.is_managed_buffer();
+        self.buffer.set_file_path(new.clone());
+        if managed {
+            self.buffer_manager.current_buffer().set_file_path(new.clone());
+        }
+        self.recent_files.add(&new);
+        let _ = self.recent_files.save();
+        self.refresh_git_base();
+        let syntax_info = self.detect_syntax();
+        self.message = Some(format!("Renamed to {}{}", new.display(), syntax_info));
+    }
+
+    /// Delete the current file and close its buffer (:Delete), after
+    /// asking unless `force`
+    fn delete_file(&mut self, force: bool) {
+        let Some(path) = self.local_file() else { return };
+        if !force {
+            self.confirm(format!("Delete {}?", path.display()), "Delete!".to_string());
+            return;
+        }
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                self.report_error(format!("Cannot delete {}: {}", path.display(), e));
+                return;
             }
-            KeyCode::Char('e') => {
-                self.cursor.move_word_end(&self.buffer);
-                self.update_selection();
+        }
+        self.close_buffer();
+        self.message = Some(format!("Deleted {}", path.display()));
+    }
+
+    /// Create, rename or delete the file selected in the explorer
+    fn explorer_file_operation(&mut self, cmd: &str) {
+        let Some(explorer) = self.explorer.as_mut() else {
+            self.message = Some("File explorer is not open".to_string());
+            return;
+        };
+        let (verb, result) = if let Some(name) = cmd.strip_prefix("ExplorerCreate ") {
+            ("Created", explorer.create(name.trim()))
+        } else if let Some(name) = cmd.strip_prefix("ExplorerRename ") {
+            ("Renamed to", explorer.rename(name.trim()))
+        } else {
+            ("Deleted", explorer.delete())
+        };
+        self.message = Some(match result {
+            Ok(path) => format!("{} {}", verb, path.display()),
+            Err(e) => format!("Error: {}", e),
+        });
+        self.mode = Mode::Explorer;
+    }
+
+    /// Shift the selected lines `count` indent levels right or left as one
+    /// undo step, keeping the selection so the shift can be repeated
+    fn shift_selection(&mut self, right: bool, count: usize) {
+        let Some(selection) = self.selection else { return };
+        let (start_line, end_line) = selection.line_range();
+        let width = self.config.tab_width * count;
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        for line in start_line..=end_line {
+            if !right {
+                self.buffer.outdent_line(line, width);
+            } else if self.buffer.line_len(line) > 0 {
+                // Like Vim, blank lines are not indented
+                self.buffer.indent_line(line, width);
             }
-            
-            // Yank selection
-            KeyCode::Char('y') => {
-                self.yank_selection();
-                self.mode = Mode::Normal;
-                self.selection = None;
+        }
+        self.cursor.clamp(&self.buffer);
+        self.update_selection();
+        let lines = end_line - start_line + 1;
+        self.message = Some(format!(
+            "{} line{} {}ed {} time{}",
+            lines,
+            if lines == 1 { "" } else { "s" },
+            if right { '>' } else { '<' },
+            count,
+            if count == 1 { "" } else { "s" },
+        ));
+    }
+
+    /// Move lines `start..=end` below line `dest` (1-based, 0 for the
+    /// top) as one undo step. Returns the block's new first line, or None
+    /// if it is already there or `dest` is inside it.
+    fn move_lines(&mut self, start: usize, end: usize, dest: usize) -> Option<usize> {
+        if (start..=end + 1).contains(&dest) {
+            return None;
+        }
+        let (from, to) = if dest > end { (start, dest) } else { (dest, end + 1) };
+        let mut lines: Vec<String> = (from..to).map(|line| self.buffer.line(line)).collect();
+        let len = end - start + 1;
+        let new_start = if dest > end {
+            lines.rotate_left(len);
+            dest - len
+        } else {
+            lines.rotate_right(len);
+            dest
+        };
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        self.buffer.replace_lines(from, to, &lines);
+        Some(new_start)
+    }
+
+    /// `:[range]m {address}`: move lines below the addressed line, leaving
+    /// the cursor on the last one like Vim
+    fn move_command(&mut self, range: substitute::Range, address: &str) {
+        let Some((start, end)) = self.resolve_range(range) else { return };
+        if address.trim().is_empty() {
+            self.report_error("Argument required".to_string());
+            return;
+        }
+        let last = self.buffer.line_count() - 1;
+        let Some(dest) = substitute::parse_address(address, self.cursor.line, last) else {
+            self.report_error(format!("Invalid address: {}", address.trim()));
+            return;
+        };
+        if dest > start && dest <= end {
+            self.report_error("Cannot move a range of lines into itself".to_string());
+            return;
+        }
+        let new_start = self.move_lines(start, end, dest).unwrap_or(start);
+        self.cursor.line = new_start + end - start;
+        self.cursor.move_to_first_non_whitespace(&self.buffer);
+        let count = end - start + 1;
+        if count > 2 {
+            self.message = Some(format!("{} lines moved", count));
+        }
+    }
+
+    /// Take a key for the pending operator: a count, i or a and then a
+    /// text object, the operator's own key for whole lines (dd, gUU or
+    /// gUgU) or a motion (d3w, >j, gqap). Anything else cancels it.
+    fn operator_pending_key(&mut self, key: KeyEvent) {
+        let Some((op, ctx)) = self.operator else { return };
+        let KeyCode::Char(c) = key.code else {
+            self.operator = None;
+            return;
+        };
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.operator = None;
+            return;
+        }
+        if let Some(scope) = self.text_object.take() {
+            self.operator = None;
+            self.numeric_prefix.clear();
+            let object = textobject::select(&self.buffer, (self.cursor.line, self.cursor.col), c, scope);
+            self.operate(op, ctx, object);
+            return;
+        }
+        if self.push_count_digit(key) {
+            return;
+        }
+        let g = std::mem::take(&mut self.pending_g);
+        if !g && c == 'g' {
+            self.pending_g = true;
+            return;
+        }
+        if !g {
+            if let Some(scope) = Scope::from_char(c) {
+                self.text_object = Some(scope);
+                return;
             }
-            
-            // Delete selection
-            KeyCode::Char('d') | KeyCode::Char('x') => {
-                self.delete_selection();
-                self.mode = Mode::Normal;
-                self.selection = None;
+        }
+        self.operator = None;
+        // 2d3w deletes six words
+        let count = match (ctx.count, self.take_action_context().count) {
+            (None, None) => None,
+            (before, after) => Some(before.unwrap_or(1) * after.unwrap_or(1)),
+        };
+        let pos = (self.cursor.line, self.cursor.col);
+        let object = if c == op.key() && (!g || op.after_g()) {
+            Some(operator::lines(&self.buffer, pos.0, count.unwrap_or(1)))
+        } else if g {
+            (c == 'g').then(|| operator::motion(&self.buffer, pos, 'g', count)).flatten()
+        } else if op == Operator::Change && matches!(c, 'w' | 'W') {
+            operator::change_word(&self.buffer, pos, c == 'W', count)
+        } else {
+            operator::motion(&self.buffer, pos, c, count)
+        };
+        self.operate(op, ctx, object);
+    }
+
+    /// Apply `op` to what a text object or motion covers, as if it were
+    /// selected in visual mode
+    fn operate(&mut self, op: Operator, ctx: ActionContext, object: Option<TextObject>) {
+        let selection = match object {
+            Some(TextObject::Empty { at }) if op == Operator::Change => {
+                // Nothing to remove inside () or "", but c still inserts there
+                self.cursor.line = at.0;
+                self.cursor.col = at.1;
+                self.mode = Mode::Insert;
+                None
             }
-            
-            // Switch visual mode type
-            KeyCode::Char('v') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                if self.mode == Mode::Visual {
-                    self.mode = Mode::Normal;
-                    self.selection = None;
+            Some(object) => object_selection(object),
+            None => None,
+        };
+        let Some(selection) = selection else { return };
+        self.selection = Some(selection);
+        self.apply_operator(op, ActionContext { count: None, register: ctx.register });
+        if self.mode != Mode::Insert {
+            // Normal mode keeps the cursor on a character
+            self.cursor.col = self.cursor.col.min(self.buffer.line_len(self.cursor.line).saturating_sub(1));
+        }
+    }
+
+    /// Apply `op` to the selection and leave visual mode. This is where
+    /// every operator is carried out, on a selection made in visual mode
+    /// or one made from a text object or motion. In visual mode > and <
+    /// shift `count` times and keep the selection, so they can be repeated.
+    fn apply_operator(&mut self, op: Operator, ctx: ActionContext) {
+        let Some(selection) = self.selection else { return };
+        let (start_line, start_col, _, _) = selection.normalized();
+        let (_, end_line) = selection.line_range();
+        let linewise = selection.mode == VisualMode::Line;
+        self.pending_register = ctx.register;
+        match op {
+            Operator::Delete if linewise => {
+                let register = self.pending_register.take();
+                self.delete_line_range(start_line, end_line + 1, register);
+            }
+            Operator::Delete => self.delete_selection(),
+            Operator::Change => {
+                self.change_selection();
+                return;
+            }
+            Operator::Yank => {
+                if linewise {
+                    let register = self.pending_register.take();
+                    self.yank_line_range(start_line, end_line + 1, register);
                 } else {
-                    if let Some(ref mut sel) = self.selection {
-                        sel.mode = VisualMode::Char;
-                    }
-                    self.mode = Mode::Visual;
+                    self.yank_selection();
+                    self.cursor.col = start_col;
                 }
+                self.cursor.line = start_line;
             }
-            KeyCode::Char('V') => {
-                if self.mode == Mode::VisualLine {
-                    self.mode = Mode::Normal;
-                    self.selection = None;
-                } else {
-                    if let Some(ref mut sel) = self.selection {
-                        sel.mode = VisualMode::Line;
-                    }
-                    self.mode = Mode::VisualLine;
+            Operator::Lowercase => self.convert_selection_case(str::to_lowercase),
+            Operator::Uppercase => self.convert_selection_case(str::to_uppercase),
+            Operator::ToggleCase => self.convert_selection_case(toggle_case),
+            Operator::Indent | Operator::Outdent => {
+                let visual = self.mode.is_visual();
+                self.shift_selection(op == Operator::Indent, if visual { ctx.count() } else { 1 });
+                if visual {
+                    return;
                 }
+                self.cursor.line = start_line;
             }
-            
-            _ => {}
+            Operator::Format => self.format_lines(start_line, end_line),
+            Operator::Comment => self.comment_lines(start_line, end_line),
         }
-        false
+        self.pending_register = None;
+        self.mode = Mode::Normal;
+        self.selection = None;
+        self.cursor.clamp(&self.buffer);
+        self.ensure_cursor_visible();
     }
 
-    /// Update the selection's cursor position
-    fn update_selection(&mut self) {
-        if let Some(ref mut sel) = self.selection {
-            sel.update_cursor(self.cursor.line, self.cursor.col);
+    /// Comment out lines `start..=end` with the file type's line comment
+    /// (gc), or uncomment them if they all are comments
+    fn comment_lines(&mut self, start: usize, end: usize) {
+        let Some(leader) = self.highlighter.current_syntax().and_then(|syntax| syntax.comment_single) else {
+            self.report_error("No line comment for this file type");
+            return;
+        };
+        let lines: Vec<String> = (start..=end).map(|idx| self.buffer.line(idx)).collect();
+        let toggled = operator::toggle_comment(&lines, leader);
+        if toggled != lines {
+            self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+            self.buffer.replace_lines(start, end + 1, &toggled);
         }
+        self.cursor.line = start;
+        self.cursor.move_to_first_non_whitespace(&self.buffer);
     }
 
-    /// Yank the current selection to register
-    fn yank_selection(&mut self) {
-        let Some(ref sel) = self.selection else { return };
-        
-        let mut content = String::new();
-        let linewise = matches!(sel.mode, VisualMode::Line);
+    /// Select the text object `kind` around the cursor in visual mode (viw,
+    /// vip switches to linewise)
+    fn select_text_object(&mut self, scope: Scope, kind: char) {
+        let object = textobject::select(&self.buffer, (self.cursor.line, self.cursor.col), kind, scope);
+        let Some(selection) = object.and_then(object_selection) else { return };
+        self.mode = if selection.mode == VisualMode::Line { Mode::VisualLine } else { Mode::Visual };
+        self.cursor.line = selection.cursor_line;
+        self.cursor.col = selection.cursor_col;
+        self.selection = Some(selection);
+        self.ensure_cursor_visible();
+    }
+
+    /// Replace the selection with typed text (c). Linewise changes keep
+    /// one empty line; in a block the text typed on the first line is
+    /// repeated on the others when insert mode ends.
+    fn change_selection(&mut self) {
+        let Some(selection) = self.selection else { return };
+        let (start_line, end_line) = selection.line_range();
+        self.delete_selection();
+        self.selection = None;
+        self.message = None;
+        self.mode = Mode::Insert;
+
+        match selection.mode {
+            VisualMode::Line => {
+                // Deleting up to the end of the buffer already leaves an empty line
+                let at_end = start_line + 1 == self.buffer.line_count() && self.buffer.line_len(start_line) == 0;
+                if !at_end {
+                    let pos = self.buffer.line_to_byte(start_line);
+                    self.buffer.insert(pos, "\n");
+                }
+                self.cursor.line = start_line;
+                self.cursor.col = 0;
+            }
+            VisualMode::Block => {
+                self.block_insert = Some(BlockInsert {
+                    line: self.cursor.line,
+                    col: self.cursor.col,
+                    lines: start_line + 1..=end_line,
+                });
+            }
+            VisualMode::Char => {}
+        }
+    }
+
+    /// Insert the text typed since an insert with a count began `count - 1`
+    /// more times, leaving the cursor at the end of the last copy. Nothing
+    /// is repeated if the cursor moved back before the start.
+    fn finish_insert_repeat(&mut self, repeat: InsertRepeat) {
+        let end = self.cursor.byte_offset(&self.buffer);
+        if end <= repeat.start {
+            return;
+        }
+        let typed = self.buffer.slice(repeat.start, end);
+        let (pos, text) = if repeat.new_line {
+            let line_end = self.buffer.line_to_byte(self.cursor.line) + self.buffer.line(self.cursor.line).len();
+            (line_end, format!("\n{}", typed).repeat(repeat.count - 1))
+        } else {
+            (end, typed.repeat(repeat.count - 1))
+        };
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        self.buffer.insert(pos, &text);
+        let (line, col) = self.buffer.byte_to_pos(pos + text.len());
+        self.cursor.line = line;
+        self.cursor.col = col;
+        self.ensure_cursor_visible();
+    }
+
+    /// Repeat the text typed after a block change on the block's other
+    /// lines, skipping lines too short to reach the block
+    fn finish_block_insert(&mut self, block: BlockInsert) {
+        if self.cursor.line != block.line || self.cursor.col <= block.col {
+            return;
+        }
+        let line = self.buffer.line(block.line);
+        let start = self.buffer.col_to_byte(block.line, block.col);
+        let end = self.buffer.col_to_byte(block.line, self.cursor.col);
+        let typed = line[start..end].to_string();
+
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        for line_idx in block.lines {
+            if self.buffer.line_len(line_idx) >= block.col {
+                let pos = self.buffer.line_to_byte(line_idx) + self.buffer.col_to_byte(line_idx, block.col);
+                self.buffer.insert(pos, &typed);
+            }
+        }
+    }
+
+    fn delete_selection(&mut self) {
+        if self.selection.is_none() {
+            return;
+        }
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
         
+        // First yank the selection
+        self.yank_selection();
+        self.remove_selection();
+        self.message = Some("Deleted".to_string());
+    }
+
+    /// Replace the selection with a register's text (p in visual mode),
+    /// `count` times over. The replaced text is stored as a delete, so the
+    /// yank register still holds what was pasted.
+    fn paste_over_selection(&mut self, ctx: ActionContext) {
+        let Some(content) = self.read_register(ctx.register) else { return };
+        let Some(selection) = self.selection else { return };
+        let Some((replaced, replaced_lines)) = self.selection_text() else { return };
+        let (start_line, _) = selection.line_range();
+        let (text, linewise) = match &content {
+            RegisterContent::Lines(text) => (text.repeat(ctx.count()), true),
+            other => (other.text().repeat(ctx.count()), false),
+        };
+
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        self.remove_selection();
+        let (pos, text) = if replaced_lines {
+            let text = if linewise { text } else { format!("{}\n", text) };
+            // Lines removed from the end of the buffer leave its last newline behind
+            let at_end = start_line + 1 == self.buffer.line_count() && self.buffer.line_len(start_line) == 0;
+            let text = if at_end { text.strip_suffix('\n').unwrap_or(&text).to_string() } else { text };
+            (self.buffer.line_to_byte(start_line), text)
+        } else if linewise {
+            // Lines pasted over part of a line go between its two halves
+            (self.cursor.byte_offset(&self.buffer), format!("\n{}", text))
+        } else {
+            (self.cursor.byte_offset(&self.buffer), text)
+        };
+        self.buffer.insert(pos, &text);
+
+        if replaced_lines || linewise {
+            self.cursor.line = start_line + usize::from(!replaced_lines);
+            self.cursor.col = 0;
+        } else {
+            let (line, col) = self.buffer.byte_to_pos(pos + text.len());
+            self.cursor.line = line;
+            self.cursor.col = col.saturating_sub(1);
+        }
+        self.cursor.clamp(&self.buffer);
+        let replaced = if replaced_lines {
+            RegisterContent::Lines(replaced)
+        } else {
+            RegisterContent::Chars(replaced)
+        };
+        self.store_register(None, replaced, true);
+        self.ensure_cursor_visible();
+    }
+
+    /// Byte ranges of the selected text: one per line for a block, else a
+    /// single range. Linewise ranges leave out the last newline.
+    fn selection_byte_ranges(&self) -> Vec<Range<usize>> {
+        let Some(sel) = self.selection.as_ref() else { return Vec::new() };
+        let pos = |line: usize, col: usize| self.buffer.line_to_byte(line) + self.buffer.col_to_byte(line, col);
         match sel.mode {
             VisualMode::Char => {
                 let (start_line, start_col, end_line, end_col) = sel.normalized();
-                for line_idx in start_line..=end_line {
-                    let line = self.buffer.line(line_idx);
-                    let chars: Vec<char> = line.chars().collect();
-                    
-                    let start = if line_idx == start_line { start_col } else { 0 };
-                    let end = if line_idx == end_line { (end_col + 1).min(chars.len()) } else { chars.len() };
-                    
-                    let part: String = chars.get(start..end).unwrap_or(&[]).iter().collect();
-                    content.push_str(&part);
-                    
-                    if line_idx < end_line {
-                        content.push('\n');
-                    }
-                }
+                let range = pos(start_line, start_col)..pos(end_line, end_col + 1);
+                vec![range]
             }
             VisualMode::Line => {
                 let (start_line, end_line) = sel.line_range();
-                for line_idx in start_line..=end_line {
-                    content.push_str(&self.buffer.line(line_idx));
-                    content.push('\n');
-                }
+                let range = pos(start_line, 0)..pos(end_line, usize::MAX);
+                vec![range]
             }
             VisualMode::Block => {
                 let (start_line, end_line) = sel.line_range();
                 let (start_col, end_col) = sel.col_range();
-                for line_idx in start_line..=end_line {
-                    let line = self.buffer.line(line_idx);
-                    let chars: Vec<char> = line.chars().collect();
-                    let part: String = chars.get(start_col..(end_col + 1).min(chars.len())).unwrap_or(&[]).iter().collect();
-                    content.push_str(&part);
-                    if line_idx < end_line {
-                        content.push('\n');
-                    }
-                }
+                (start_line..=end_line)
+                    .map(|line| pos(line, start_col)..pos(line, end_col + 1))
+                    .collect()
             }
         }
-        
-        let line_count = content.lines().count().max(1);
-        let register_content = if linewise {
-            RegisterContent::Lines(content)
-        } else {
-            RegisterContent::Chars(content)
+    }
+
+    /// Replace the selected text with `convert` applied to it, as one undo
+    /// step, leaving the cursor at the start of the selection
+    fn convert_selection_case(&mut self, convert: fn(&str) -> String) {
+        let ranges = self.selection_byte_ranges();
+        let Some(first) = ranges.first() else { return };
+        let (line, col) = self.buffer.byte_to_pos(first.start);
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        for range in ranges.into_iter().rev() {
+            let text = self.buffer.slice(range.start, range.end);
+            let converted = convert(&text);
+            if converted != text {
+                self.buffer.delete(range.start, range.end);
+                self.buffer.insert(range.start, &converted);
+            }
+        }
+        self.cursor.line = line;
+        self.cursor.col = col;
+        self.cursor.clamp(&self.buffer);
+    }
+
+    /// Add `delta` to the number at or after the cursor (Ctrl-A, Ctrl-X),
+    /// leaving the cursor on its last digit
+    fn increment_at_cursor(&mut self, delta: i64) {
+        let line = self.cursor.line;
+        let formats = NrFormats::parse(&self.config.nrformats);
+        // `$` leaves the cursor just past the last character
+        let col = self.cursor.col.min(self.buffer.line_len(line).saturating_sub(1));
+        let Some((text, col)) = increment::increment(&self.buffer.line(line), col, delta, formats) else {
+            return;
         };
-        self.registers.yank(register_content);
-        self.message = Some(format!("{} line(s) yanked", line_count));
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        self.buffer.replace_lines(line, line + 1, &[text]);
+        self.cursor.col = col;
     }
 
-    /// Handle keys in help mode
-    fn handle_help_mode(&mut self, key: KeyEvent) -> bool {
-        match key.code {
-            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter => {
-                self.mode = Mode::Normal;
+    /// Add `delta` to the first number in the selected part of each line,
+    /// or with `progressive` `delta` times the count of numbers changed so
+    /// far (g Ctrl-A), then leave visual mode
+    fn increment_selection(&mut self, delta: i64, progressive: bool) {
+        let Some(selection) = self.selection else { return };
+        let formats = NrFormats::parse(&self.config.nrformats);
+        let (start_line, end_line) = selection.line_range();
+        let mut changed = 0;
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        for line in start_line..=end_line {
+            let Some(cols) = selection.cols_on_line(line) else { continue };
+            let chars: Vec<char> = self.buffer.line(line).chars().collect();
+            let cols = cols.start.min(chars.len())..cols.end.min(chars.len());
+            let selected: String = chars[cols.clone()].iter().collect();
+            let step = if progressive { delta * (changed + 1) } else { delta };
+            let Some((replaced, _)) = increment::increment(&selected, 0, step, formats) else { continue };
+            let before: String = chars[..cols.start].iter().collect();
+            let after: String = chars[cols.end..].iter().collect();
+            self.buffer.replace_lines(line, line + 1, &[format!("{}{}{}", before, replaced, after)]);
+            changed += 1;
+        }
+        self.cursor.line = start_line;
+        self.cursor.col = selection.cols_on_line(start_line).map_or(0, |cols| cols.start);
+        self.cursor.clamp(&self.buffer);
+        self.mode = Mode::Normal;
+        self.selection = None;
+    }
+
+    /// Rewrap lines `start..=end` to `text_width` (gq), leaving the cursor
+    /// on the last line of the result
+    fn format_lines(&mut self, start: usize, end: usize) {
+        let lines: Vec<String> = (start..=end).map(|idx| self.buffer.line(idx)).collect();
+        let formatted = format::format_lines(&lines, self.config.text_width, self.config.tab_width);
+        if formatted != lines {
+            self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+            self.buffer.replace_lines(start, end + 1, &formatted);
+        }
+        self.cursor.line = start + formatted.len() - 1;
+        self.cursor.move_to_first_non_whitespace(&self.buffer);
+        self.ensure_cursor_visible();
+    }
+
+    /// Join `count` lines starting at the cursor line (3J joins three, like
+    /// Vim; no count joins two). `raw` joins without touching whitespace
+    /// (gJ). The cursor is left at the last join point.
+    fn join_at_cursor(&mut self, count: usize, raw: bool) {
+        if self.cursor.line + 1 >= self.buffer.line_count() {
+            return;
+        }
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        for _ in 0..count.saturating_sub(1).max(1) {
+            if self.cursor.line + 1 >= self.buffer.line_count() {
+                break;
+            }
+            self.cursor.col = self.buffer.line_len(self.cursor.line);
+            if raw {
+                self.buffer.join_lines_raw(self.cursor.line);
+            } else {
+                self.buffer.join_lines(self.cursor.line);
             }
-            _ => {}
         }
-        false
     }
 
-    /// Delete the current selection
-    fn delete_selection(&mut self) {
+    /// Join the selected lines (J), or without touching whitespace (gJ)
+    fn join_selection(&mut self, raw: bool) {
+        let Some(selection) = self.selection else { return };
+        let (start_line, end_line) = selection.line_range();
+        if start_line + 1 >= self.buffer.line_count() {
+            return;
+        }
+        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
+        let mut col = 0;
+        for _ in 0..(end_line - start_line).max(1) {
+            if start_line + 1 >= self.buffer.line_count() {
+                break;
+            }
+            col = self.buffer.line_len(start_line);
+            if raw {
+                self.buffer.join_lines_raw(start_line);
+            } else {
+                self.buffer.join_lines(start_line);
+            }
+        }
+        self.cursor.line = start_line;
+        self.cursor.col = col;
+        self.cursor.clamp(&self.buffer);
+    }
+
+    /// Delete the selected text without storing it anywhere
+    fn remove_selection(&mut self) {
         // Extract selection info before borrowing mutably
-        let sel_info = match &self.selection {
-            Some(sel) => Some((sel.mode, sel.normalized(), sel.line_range(), sel.col_range())),
-            None => None,
-        };
+        let sel_info = self.selection
+            .as_ref()
+            .map(|sel| (sel.mode, sel.normalized(), sel.line_range(), sel.col_range()));
         
         let Some((mode, normalized, line_range, col_range)) = sel_info else { return };
         
-        self.buffer.checkpoint(self.cursor.line, self.cursor.col);
-        
-        // First yank the selection
-        self.yank_selection();
-        
         match mode {
             VisualMode::Char => {
                 let (start_line, start_col, end_line, end_col) = normalized;
@@ -1496,10 +6263,71 @@ impl Editor {
         }
         
         self.cursor.clamp(&self.buffer);
-        self.message = Some("Deleted".to_string());
     }
 
     /// Get the word under the cursor
+    /// Line, word, character and byte counts (g CTRL-G): of the selection
+    /// in visual mode, otherwise of the buffer up to the cursor
+    fn show_counts(&mut self) {
+        let content = self.buffer.content();
+        let (_, total_words, total_chars, total_bytes) = text_counts(&content);
+        let total_lines = self.buffer.line_count();
+        if let Some((text, _)) = self.selection_text() {
+            let (start, end) = self.selection.map_or((0, 0), |sel| sel.line_range());
+            let (_, words, chars, bytes) = text_counts(&text);
+            self.message = Some(format!(
+                "Selected {} of {} Lines; {} of {} Words; {} of {} Chars; {} of {} Bytes",
+                end - start + 1, total_lines, words, total_words, chars, total_chars, bytes, total_bytes
+            ));
+            return;
+        }
+        let byte = self.cursor.byte_offset(&self.buffer);
+        let before = &content[..byte];
+        // The word under the cursor counts as reached
+        let on_word_start = content[byte..].starts_with(|c: char| !c.is_whitespace())
+            && !before.ends_with(|c: char| !c.is_whitespace());
+        let word = before.split_whitespace().count() + on_word_start as usize;
+        self.message = Some(format!(
+            "Col {} of {}; Line {} of {}; Word {} of {}; Char {} of {}; Byte {} of {}",
+            self.cursor.col + 1,
+            self.buffer.line_len(self.cursor.line),
+            self.cursor.line + 1,
+            total_lines,
+            word,
+            total_words,
+            before.chars().count() + 1,
+            total_chars,
+            byte + 1,
+            total_bytes
+        ));
+    }
+
+    /// `:[range]count` (also :stats, :wc): counts for the buffer, the
+    /// range's lines or with '<,'> exactly the last visual selection
+    fn count_command(&mut self, range: Option<substitute::Range>) {
+        let text = match range {
+            None => self.buffer.content(),
+            Some(substitute::Range::Visual) => match self.last_visual {
+                Some(area) => self.area_text(&area).0,
+                None => return self.report_error("Invalid range".to_string()),
+            },
+            Some(range) => {
+                let Some((start, end)) = self.resolve_range(range) else { return };
+                (start..=end).map(|line| format!("{}\n", self.buffer.line(line))).collect()
+            }
+        };
+        let (lines, words, chars, bytes) = text_counts(&text);
+        let lines = if range.is_none() { self.buffer.line_count() } else { lines };
+        self.message = Some(format!("{} lines, {} words, {} chars, {} bytes", lines, words, chars, bytes));
+    }
+
+    /// Show the code point and bytes of the character under the cursor
+    /// (ga, :ascii)
+    fn show_char_info(&mut self) {
+        let grapheme = self.buffer.grapheme_at(self.cursor.line, self.cursor.col).unwrap_or_default();
+        self.message = Some(charinfo::describe(&grapheme));
+    }
+
     fn get_word_under_cursor(&self) -> Option<String> {
         let line = self.buffer.line(self.cursor.line);
         let chars: Vec<char> = line.chars().collect();
@@ -1535,89 +6363,63 @@ impl Editor {
         
         if forward {
             // Search forward from cursor
-            for i in (self.cursor.col + 1)..chars.len() {
-                if chars[i] == target {
-                    self.cursor.col = i;
-                    return;
-                }
+            if let Some(offset) = chars.iter().skip(self.cursor.col + 1).position(|&c| c == target) {
+                self.cursor.col += 1 + offset;
             }
         } else {
             // Search backward from cursor
-            for i in (0..self.cursor.col).rev() {
-                if chars[i] == target {
-                    self.cursor.col = i;
-                    return;
-                }
+            if let Some(i) = chars[..self.cursor.col.min(chars.len())].iter().rposition(|&c| c == target) {
+                self.cursor.col = i;
             }
         }
     }
 
-    /// Find the matching bracket for the character under cursor
-    fn find_matching_bracket(&self) -> Option<(usize, usize)> {
-        let current_char = self.buffer.char_at(self.cursor.line, self.cursor.col)?;
-        
-        let (target, forward) = match current_char {
-            '(' => (')', true),
-            ')' => ('(', false),
-            '[' => (']', true),
-            ']' => ('[', false),
-            '{' => ('}', true),
-            '}' => ('{', false),
-            '<' => ('>', true),
-            '>' => ('<', false),
-            _ => return None,
-        };
-        
-        let mut depth = 1;
-        let mut line = self.cursor.line;
-        let mut col = self.cursor.col;
-        
-        loop {
-            if forward {
-                col += 1;
-                let line_len = self.buffer.line_len(line);
-                if col >= line_len {
-                    line += 1;
-                    if line >= self.buffer.line_count() {
-                        return None;
-                    }
-                    col = 0;
-                }
-            } else {
-                if col == 0 {
-                    if line == 0 {
-                        return None;
-                    }
-                    line -= 1;
-                    col = self.buffer.line_len(line).saturating_sub(1);
-                } else {
-                    col -= 1;
-                }
-            }
-            
-            if let Some(c) = self.buffer.char_at(line, col) {
-                if c == current_char {
-                    depth += 1;
-                } else if c == target {
-                    depth -= 1;
-                    if depth == 0 {
-                        return Some((line, col));
-                    }
-                }
-            }
-        }
+    /// Where `%` goes from a word or tag of a construct the syntax knows
+    /// (`#if`/`#endif`, HTML tags, a Rust `match` and its arms)
+    fn find_matching_pair(&self) -> Option<(usize, usize)> {
+        let syntax = self.highlighter.current_syntax()?;
+        crate::matchpair::find(syntax, &self.buffer.content(), (self.cursor.line, self.cursor.col))
+    }
+
+    /// Move the cursor to the top of a new buffer, dropping extra cursors
+    fn reset_cursor(&mut self) {
+        self.cursor = Cursor::new();
+        self.cursors.clear();
     }
 
     /// Ensure cursor is visible by adjusting scroll offset
     fn ensure_cursor_visible(&mut self) {
-        // Leave some margin
-        let margin = 3;
-        
+        // Keep scroll_margin lines around the cursor; a margin of half the
+        // window or more keeps it centered
+        let margin = self.config.scroll_margin.min(self.viewport_height.saturating_sub(1) / 2);
+
         if self.cursor.line < self.scroll_offset + margin {
             self.scroll_offset = self.cursor.line.saturating_sub(margin);
-        } else if self.cursor.line >= self.scroll_offset + self.viewport_height - margin {
-            self.scroll_offset = self.cursor.line.saturating_sub(self.viewport_height - margin - 1);
+        } else if self.cursor.line + margin >= self.scroll_offset + self.viewport_height {
+            self.scroll_offset = (self.cursor.line + margin + 1).saturating_sub(self.viewport_height);
+        }
+    }
+
+    /// Replace the buffer with an unnamed one holding `text`
+    pub fn set_text(&mut self, text: &str) {
+        self.buffer = Buffer::from_text(text);
+        self.reset_cursor();
+        self.scroll_offset = 0;
+        self.selection = None;
+        self.git_base = None;
+        self.git_hunks.clear();
+    }
+
+    /// Handle keys written in Vim notation ("dd", "ihi<Esc>", "<C-r>"),
+    /// returns true if editor should quit. None if `keys` is empty.
+    pub fn feed_keys(&mut self, keys: &str) -> Option<bool> {
+        let keys = parse_key_sequence(keys, self.keymap.leader)?;
+        for key in keys {
+            if self.handle_key(KeyEvent::new(key.code, key.modifiers)) {
+                return Some(true);
+            }
         }
+        Some(false)
     }
 
     /// Update viewport height (called on terminal resize)
@@ -1634,12 +6436,20 @@ impl Editor {
         &self.cursor
     }
 
+    pub fn extra_cursors(&self) -> &CursorSet {
+        &self.cursors
+    }
+
     pub fn mode(&self) -> Mode {
         self.mode
     }
 
+    /// Scroll offset to draw at, part way through a smooth scroll
     pub fn scroll_offset(&self) -> usize {
-        self.scroll_offset
+        match &self.scroll_animation {
+            Some(animation) if animation.to == self.scroll_offset => animation.offset(),
+            _ => self.scroll_offset,
+        }
     }
 
     pub fn command_buffer(&self) -> &str {
@@ -1650,15 +6460,150 @@ impl Editor {
         self.message.as_ref()
     }
 
+    /// Text of the bottom line: the command or search being typed, or the
+    /// last message
+    /// A message of more than one line, shown above the command line until
+    /// a key dismisses it
+    pub fn long_message(&self) -> Option<&str> {
+        self.message.as_deref()
+            .filter(|message| message.contains('\n') && !matches!(self.mode, Mode::Command | Mode::Search))
+    }
+
+    pub fn command_line(&self) -> String {
+        match self.mode {
+            Mode::Command => format!(":{}", self.command_buffer),
+            Mode::Search => {
+                let prefix = match self.search.direction() {
+                    SearchDirection::Forward => "/",
+                    SearchDirection::Backward => "?",
+                };
+                format!("{}{}", prefix, self.search.pattern())
+            }
+            _ => match self.prompt().filter(|prompt| prompt.lines().is_empty()) {
+                Some(prompt) => prompt.line(),
+                None => self.message.clone().unwrap_or_default(),
+            },
+        }
+    }
+
     pub fn highlighter(&self) -> &Highlighter {
         &self.highlighter
     }
 
+    /// Whether the current buffer is highlighted: its :setlocal setting,
+    /// or else the syntax_highlighting option
+    pub fn syntax_enabled(&self) -> bool {
+        self.buffer.syntax().unwrap_or(self.config.syntax_highlighting)
+    }
+
+    /// The color scheme chosen with :colorscheme, the default one if the
+    /// name in the config file is unknown
+    pub fn theme(&self) -> Theme {
+        Theme::by_name(&self.config.color_scheme).unwrap_or_default()
+    }
+
+    /// Mark the current buffer read-only (:setlocal readonly, :ro) or
+    /// writable again
+    fn set_readonly(&mut self, readonly: bool) {
+        self.buffer.set_readonly(readonly);
+        self.message = Some(if readonly { "Read-only" } else { "Writable" }.to_string());
+    }
+
     pub fn search(&self) -> &Search {
         &self.search
     }
 
+    /// Whether search matches are highlighted: while a search is typed,
+    /// and after it with hlsearch until :noh
+    pub fn search_highlight(&self) -> bool {
+        self.search.highlight_active && (self.config.hlsearch || self.mode == Mode::Search)
+    }
+
     pub fn selection(&self) -> Option<&Selection> {
         self.selection.as_ref()
     }
 }
+
+impl lua::View for Editor {
+    fn file_name(&self) -> Option<String> {
+        self.buffer.file_path().map(|path| path.display().to_string())
+    }
+
+    fn mode(&self) -> String {
+        self.mode.display().to_string()
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        (self.cursor.line + 1, self.cursor.col + 1)
+    }
+
+    fn line_count(&self) -> usize {
+        self.buffer.line_count()
+    }
+
+    fn line(&self, line: usize) -> Option<String> {
+        (1..=self.buffer.line_count()).contains(&line).then(|| self.buffer.line(line - 1))
+    }
+
+    fn option(&self, name: &str) -> Option<String> {
+        match name {
+            "syntax" | "syn" => Some(self.syntax_enabled().to_string()),
+            "readonly" | "ro" => Some(self.buffer.is_readonly().to_string()),
+            "filetype" | "ft" => Some(self.highlighter.current_syntax_name().unwrap_or("").to_lowercase()),
+            _ => self.config.get_option(name),
+        }
+    }
+}
+
+impl lua::Host for Editor {
+    fn echo(&mut self, text: String) {
+        self.message = Some(text);
+    }
+
+    fn execute(&mut self, command: &str) {
+        if self.execute_command(command) {
+            self.lua_quit = true;
+        }
+    }
+
+    fn open_popup(&mut self, popup: lua::Popup) -> usize {
+        self.next_popup_id += 1;
+        self.popups.push((self.next_popup_id, popup));
+        self.next_popup_id
+    }
+
+    fn update_popup(&mut self, id: usize, lines: Vec<String>) -> bool {
+        match self.popups.iter_mut().find(|(popup_id, _)| *popup_id == id) {
+            Some((_, popup)) => {
+                popup.lines = lines;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn close_popup(&mut self, id: usize) {
+        self.popups.retain(|(popup_id, _)| *popup_id != id);
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<&str>, local: bool) -> Result<(), String> {
+        let enable = || match value {
+            None | Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(other) => Err(format!("Invalid boolean: {}", other)),
+        };
+        // readonly and filetype only ever belong to the buffer
+        match name {
+            "readonly" | "ro" => self.buffer.set_readonly(enable()?),
+            "filetype" | "ft" => self.set_filetype(value.unwrap_or_default())?,
+            "syntax" | "syn" if local => self.buffer.set_syntax(Some(enable()?)),
+            _ if local => return Err(format!("Unknown local option: {}", name)),
+            _ => Editor::set_option(self, name, value)?,
+        }
+        Ok(())
+    }
+
+    fn map_key(&mut self, mode: Mode, keys: &str, action: Action) -> Result<(), String> {
+        self.keymap.map(mode, keys, action)
+    }
+}
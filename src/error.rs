@@ -0,0 +1,76 @@
+//! Errors for Quirks
+//!
+//! Modules that can fail return a `QuirksError`, so the editor shows every
+//! failure the same way in the message line and the `:messages` log, and a
+//! plugin can match on what went wrong instead of parsing a message.
+
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Result with a `QuirksError` unless another error type is given
+pub type Result<T, E = QuirksError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum QuirksError {
+    /// Reading or writing a file failed
+    #[error("{}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+    /// The buffer has no file to write to or reload from
+    #[error("No file name")]
+    NoFileName,
+    /// No open buffer has that index
+    #[error("No buffer {0}")]
+    NoBuffer(usize),
+    /// Any other I/O, such as starting ssh for an scp:// file
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The language server could not be started or written to
+    #[error("LSP {context}: {source}")]
+    Lsp { context: &'static str, source: io::Error },
+    /// A request for a language server that is not running
+    #[error("LSP server not running")]
+    LspNotRunning,
+    /// A plugin of the same name is loaded already
+    #[error("Plugin '{0}' already loaded")]
+    PluginLoaded(String),
+    /// No plugin of that name is loaded
+    #[error("Plugin '{0}' not found")]
+    PluginNotFound(String),
+    /// A failure a plugin reports itself
+    #[error("{plugin}: {message}")]
+    Plugin { plugin: String, message: String },
+    /// Lua code failed to load or raised an error; the message names the
+    /// chunk and line
+    #[error("{0}")]
+    Lua(String),
+}
+
+impl QuirksError {
+    /// Wrap an I/O error with the file it happened on, for `map_err`
+    pub fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| QuirksError::File { path, source }
+    }
+
+    /// Wrap an I/O error of a language server, for `map_err`
+    pub fn lsp(context: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| QuirksError::Lsp { context, source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "not found");
+        assert_eq!(QuirksError::file("a.txt")(missing).to_string(), "a.txt: not found");
+        let broken = io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe");
+        assert_eq!(QuirksError::lsp("write")(broken).to_string(), "LSP write: broken pipe");
+        assert_eq!(QuirksError::PluginLoaded("clock".to_string()).to_string(), "Plugin 'clock' already loaded");
+        assert_eq!(QuirksError::Lua("init.lua:3: boom".to_string()).to_string(), "init.lua:3: boom");
+    }
+}
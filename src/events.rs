@@ -0,0 +1,132 @@
+//! The main loop's events for Quirks
+//!
+//! Terminal input is read on a thread of its own and arrives on the same
+//! channel as the wake-ups of background work (searches, jobs, the
+//! terminal pane, files loading, language servers). The loop sleeps until
+//! either comes, so results show up as soon as they are ready without the
+//! editor polling for them, and a long task never holds up a key.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// What woke the main loop
+#[derive(Debug)]
+pub enum Event {
+    /// A key, a resize or other terminal input
+    Input(crossterm::event::Event),
+    /// Background work has results; the editor collects them in `tick`
+    Wake,
+    /// Reading the terminal failed, so no more input will come
+    InputError(io::Error),
+}
+
+/// Handle for background work to wake the main loop with. The default one
+/// is not connected to a loop (headless and script runs) and does nothing;
+/// the editor polls on a timer instead.
+#[derive(Debug, Clone, Default)]
+pub struct Waker {
+    tx: Option<Sender<Event>>,
+    /// A wake-up is queued and not yet taken, so more are not needed
+    pending: Arc<AtomicBool>,
+}
+
+impl Waker {
+    /// Ask the main loop to collect results
+    pub fn wake(&self) {
+        let Some(tx) = &self.tx else { return };
+        if !self.pending.swap(true, Ordering::AcqRel) {
+            let _ = tx.send(Event::Wake);
+        }
+    }
+
+    /// Whether a main loop listens, so background work need not be polled
+    pub fn is_connected(&self) -> bool {
+        self.tx.is_some()
+    }
+}
+
+/// The channel the main loop waits on
+#[derive(Debug)]
+pub struct EventLoop {
+    tx: Sender<Event>,
+    rx: Receiver<Event>,
+    pending: Arc<AtomicBool>,
+}
+
+impl Default for EventLoop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLoop {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { tx, rx, pending: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// A waker for background work to post to this loop
+    pub fn waker(&self) -> Waker {
+        Waker { tx: Some(self.tx.clone()), pending: Arc::clone(&self.pending) }
+    }
+
+    /// Read terminal input on a thread of its own until the terminal goes
+    /// away or the loop is dropped
+    pub fn read_input(&self) {
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            loop {
+                let event = match crossterm::event::read() {
+                    Ok(event) => Event::Input(event),
+                    Err(e) => {
+                        let _ = tx.send(Event::InputError(e));
+                        break;
+                    }
+                };
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Wait for the next event, at most `timeout` (or for ever without
+    /// one); None when the time is up
+    pub fn next(&self, timeout: Option<Duration>) -> Option<Event> {
+        let event = match timeout {
+            Some(timeout) => match self.rx.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return None,
+            },
+            None => self.rx.recv().ok()?,
+        };
+        if let Event::Wake = event {
+            self.pending.store(false, Ordering::Release);
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wake() {
+        let events = EventLoop::new();
+        let waker = events.waker();
+        assert!(waker.is_connected());
+        assert!(!Waker::default().is_connected());
+        // Wake-ups not yet taken are one
+        waker.wake();
+        waker.wake();
+        assert!(matches!(events.next(Some(Duration::ZERO)), Some(Event::Wake)));
+        assert!(events.next(Some(Duration::ZERO)).is_none());
+        thread::spawn(move || waker.wake());
+        assert!(matches!(events.next(Some(Duration::from_secs(5))), Some(Event::Wake)));
+    }
+}
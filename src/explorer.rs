@@ -0,0 +1,268 @@
+//! File tree explorer for Quirks
+//!
+//! A sidebar listing the working directory as a collapsible tree.
+//! Directories expand in place; files are opened through the editor.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A visible row in the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplorerEntry {
+    /// Full path of the file or directory
+    pub path: PathBuf,
+    /// Nesting level below the root (0 = top level)
+    pub depth: usize,
+    /// Whether this entry is a directory
+    pub is_dir: bool,
+    /// Whether this directory is expanded
+    pub expanded: bool,
+}
+
+impl ExplorerEntry {
+    /// File name shown in the tree
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+/// File explorer state
+#[derive(Debug)]
+pub struct Explorer {
+    root: PathBuf,
+    entries: Vec<ExplorerEntry>,
+    expanded: HashSet<PathBuf>,
+    selected: usize,
+}
+
+impl Explorer {
+    /// Create an explorer rooted at a directory
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let mut explorer = Self {
+            root: root.into(),
+            entries: Vec::new(),
+            expanded: HashSet::new(),
+            selected: 0,
+        };
+        explorer.refresh();
+        explorer
+    }
+
+    /// The directory at the top of the tree
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Visible entries, in display order
+    pub fn entries(&self) -> &[ExplorerEntry] {
+        &self.entries
+    }
+
+    /// Index of the highlighted entry
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// The highlighted entry, if the tree is not empty
+    pub fn selected_entry(&self) -> Option<&ExplorerEntry> {
+        self.entries.get(self.selected)
+    }
+
+    /// Re-read the directory tree from disk, keeping expansion and selection
+    pub fn refresh(&mut self) {
+        let selected_path = self.selected_entry().map(|e| e.path.clone());
+        self.entries.clear();
+        let root = self.root.clone();
+        self.collect(&root, 0);
+
+        self.selected = selected_path
+            .and_then(|path| self.entries.iter().position(|e| e.path == path))
+            .unwrap_or(self.selected)
+            .min(self.entries.len().saturating_sub(1));
+    }
+
+    fn collect(&mut self, dir: &Path, depth: usize) {
+        let Ok(read_dir) = fs::read_dir(dir) else { return };
+        let mut children: Vec<(bool, PathBuf)> = read_dir
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name() != ".git")
+            .map(|e| (e.path().is_dir(), e.path()))
+            .collect();
+        // Directories first, then alphabetical
+        children.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        for (is_dir, path) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            self.entries.push(ExplorerEntry {
+                path: path.clone(),
+                depth,
+                is_dir,
+                expanded,
+            });
+            if expanded {
+                self.collect(&path, depth + 1);
+            }
+        }
+    }
+
+    pub fn move_up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    /// Expand or collapse the selected directory
+    pub fn toggle(&mut self) {
+        let Some(entry) = self.selected_entry() else { return };
+        if !entry.is_dir {
+            return;
+        }
+        let path = entry.path.clone();
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+        self.refresh();
+    }
+
+    /// Collapse the selected directory, or jump to the parent directory
+    pub fn collapse(&mut self) {
+        let Some(entry) = self.selected_entry().cloned() else { return };
+        if entry.is_dir && entry.expanded {
+            self.toggle();
+        } else if let Some(parent) = entry.path.parent() {
+            if let Some(idx) = self.entries.iter().position(|e| e.path == parent) {
+                self.selected = idx;
+            }
+        }
+    }
+
+    /// Directory new entries are created in: the selected directory, or
+    /// the parent of the selected file
+    fn target_dir(&self) -> PathBuf {
+        match self.selected_entry() {
+            Some(e) if e.is_dir => e.path.clone(),
+            Some(e) => e.path.parent().map(Path::to_path_buf).unwrap_or_else(|| self.root.clone()),
+            None => self.root.clone(),
+        }
+    }
+
+    /// Create a file, or a directory if the name ends with '/'
+    pub fn create(&mut self, name: &str) -> io::Result<PathBuf> {
+        let dir = self.target_dir();
+        let path = dir.join(name.trim_end_matches('/'));
+        if path.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already exists"));
+        }
+        if name.ends_with('/') {
+            fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::File::create(&path)?;
+        }
+        self.expanded.insert(dir);
+        self.refresh();
+        if let Some(idx) = self.entries.iter().position(|e| e.path == path) {
+            self.selected = idx;
+        }
+        Ok(path)
+    }
+
+    /// Rename the selected entry within its directory
+    pub fn rename(&mut self, new_name: &str) -> io::Result<PathBuf> {
+        let entry = self.selected_entry().cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nothing selected"))?;
+        let parent = entry.path.parent().unwrap_or(&self.root);
+        let new_path = parent.join(new_name);
+        if new_path.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already exists"));
+        }
+        fs::rename(&entry.path, &new_path)?;
+        if self.expanded.remove(&entry.path) {
+            self.expanded.insert(new_path.clone());
+        }
+        self.refresh();
+        if let Some(idx) = self.entries.iter().position(|e| e.path == new_path) {
+            self.selected = idx;
+        }
+        Ok(new_path)
+    }
+
+    /// Delete the selected file or empty directory
+    pub fn delete(&mut self) -> io::Result<PathBuf> {
+        let entry = self.selected_entry().cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nothing selected"))?;
+        if entry.is_dir {
+            fs::remove_dir(&entry.path)?;
+            self.expanded.remove(&entry.path);
+        } else {
+            fs::remove_file(&entry.path)?;
+        }
+        self.refresh();
+        Ok(entry.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("quirks-explorer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.rs"), "").unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_explorer_listing_and_toggle() {
+        let root = temp_tree("toggle");
+        let mut explorer = Explorer::new(&root);
+        let names: Vec<String> = explorer.entries().iter().map(|e| e.name()).collect();
+        assert_eq!(names, vec!["src", "README.md"]);
+
+        explorer.toggle();
+        assert_eq!(explorer.entries().len(), 3);
+        assert_eq!(explorer.entries()[1].name(), "main.rs");
+        assert_eq!(explorer.entries()[1].depth, 1);
+
+        explorer.move_down();
+        explorer.collapse();
+        assert_eq!(explorer.selected(), 0);
+        explorer.collapse();
+        assert_eq!(explorer.entries().len(), 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_explorer_file_operations() {
+        let root = temp_tree("ops");
+        let mut explorer = Explorer::new(&root);
+
+        // Selected "src", so the file is created inside it
+        let created = explorer.create("lib.rs").unwrap();
+        assert_eq!(created, root.join("src").join("lib.rs"));
+        assert_eq!(explorer.selected_entry().unwrap().path, created);
+
+        let renamed = explorer.rename("mod.rs").unwrap();
+        assert!(renamed.exists() && !created.exists());
+
+        explorer.delete().unwrap();
+        assert!(!renamed.exists());
+        // Selection moved on to README.md, a top-level file
+        assert!(explorer.create("README.md").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
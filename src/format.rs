@@ -0,0 +1,116 @@
+//! Paragraph formatting for Quirks
+//!
+//! Rewraps lines to a width for `gq`. Each line's indentation and comment
+//! leader are kept apart from its text, so a paragraph of `//` or `#`
+//! comments is refilled with the leader repeated on every line.
+
+use crate::indent;
+
+/// Comment leaders recognised after the indentation, longest first
+const LEADERS: &[&str] = &["///", "//!", "//", "--", "#", ";", ">"];
+
+/// Split `line` into its prefix (indentation, any comment leader and the
+/// whitespace after it) and its text
+pub fn split_prefix(line: &str) -> (&str, &str) {
+    let rest = line.trim_start();
+    let mut len = line.len() - rest.len();
+    if let Some(leader) = LEADERS.iter().find(|leader| rest.starts_with(**leader)) {
+        let after = &rest[leader.len()..];
+        len += leader.len() + after.len() - after.trim_start().len();
+    }
+    (&line[..len], &line[len..])
+}
+
+/// `lines` rewrapped to `width` columns. Paragraphs end at lines with no
+/// text and where the comment leader changes. The first line of a
+/// paragraph keeps its prefix and the rest take the second line's.
+pub fn format_lines(lines: &[String], width: usize, tab_width: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let (prefix, text) = split_prefix(&lines[idx]);
+        if text.trim().is_empty() {
+            out.push(lines[idx].trim_end().to_string());
+            idx += 1;
+            continue;
+        }
+
+        let leader = prefix.trim();
+        let mut end = idx + 1;
+        while end < lines.len() {
+            let (next_prefix, next_text) = split_prefix(&lines[end]);
+            if next_prefix.trim() != leader || next_text.trim().is_empty() {
+                break;
+            }
+            end += 1;
+        }
+        let rest_prefix = if end > idx + 1 { split_prefix(&lines[idx + 1]).0 } else { prefix };
+        let words = lines[idx..end].iter().flat_map(|line| split_prefix(line).1.split_whitespace());
+        out.extend(fill(words, prefix, rest_prefix, width, tab_width));
+        idx = end;
+    }
+    out
+}
+
+/// Lay `words` out greedily in rows of at most `width` columns; a word
+/// longer than a row gets a row of its own
+fn fill<'a>(
+    words: impl Iterator<Item = &'a str>,
+    first_prefix: &str,
+    rest_prefix: &str,
+    width: usize,
+    tab_width: usize,
+) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = first_prefix.to_string();
+    let mut row_width = indent::display_width(&row, tab_width);
+    let mut empty = true;
+    for word in words {
+        let word_width = word.chars().count();
+        if !empty && row_width + 1 + word_width > width {
+            rows.push(std::mem::replace(&mut row, rest_prefix.to_string()));
+            row_width = indent::display_width(&row, tab_width);
+            empty = true;
+        }
+        if !empty {
+            row.push(' ');
+            row_width += 1;
+        }
+        row.push_str(word);
+        row_width += word_width;
+        empty = false;
+    }
+    rows.push(row);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str, width: usize) -> String {
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        format_lines(&lines, width, 4).join("\n")
+    }
+
+    #[test]
+    fn test_split_prefix() {
+        assert_eq!(split_prefix("    let x"), ("    ", "let x"));
+        assert_eq!(split_prefix("  // note"), ("  // ", "note"));
+        assert_eq!(split_prefix("/// Docs"), ("/// ", "Docs"));
+        assert_eq!(split_prefix("#"), ("#", ""));
+        assert_eq!(split_prefix("plain"), ("", "plain"));
+    }
+
+    #[test]
+    fn test_format_lines() {
+        assert_eq!(format("one two three four five", 10), "one two\nthree four\nfive");
+        assert_eq!(format("one\ntwo\n\nthree\nfour", 20), "one two\n\nthree four");
+        assert_eq!(format("    aa bb cc dd", 10), "    aa bb\n    cc dd");
+        assert_eq!(format("// aa bb\n// cc dd", 9), "// aa bb\n// cc dd");
+        assert_eq!(format("// aa\n// bb cc dd ee", 12), "// aa bb cc\n// dd ee");
+        assert_eq!(format("# a\n//\n// b", 20), "# a\n//\n// b");
+        assert_eq!(format("- item one\n  two three", 11), "- item one\n  two three");
+        assert_eq!(format("abcdefghijkl x", 5), "abcdefghijkl\nx");
+    }
+}
@@ -0,0 +1,223 @@
+//! Git integration for Quirks
+//!
+//! Shells out to the `git` command line, so it works wherever git is
+//! installed and does nothing (rather than failing) where it is not.
+
+use crate::diff::{ChangeKind, Hunk};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Run git in `dir`, returning stdout if it succeeded
+fn run_git(dir: &Path, args: &[&str]) -> Option<String> {
+    run_git_with_input(dir, args, None)
+}
+
+/// Run git in `dir` with `input` on stdin, returning stdout if it succeeded
+fn run_git_with_input(dir: &Path, args: &[&str], input: Option<&str>) -> Option<String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).ok()?;
+    }
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Directory to run git in for a file, and the file's name in it
+fn split_path(path: &Path) -> Option<(&Path, String)> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some((dir, name))
+}
+
+/// Content of a file as staged in the git index, or None if the file is
+/// not tracked (or git is unavailable)
+pub fn index_content(path: &Path) -> Option<String> {
+    let (dir, name) = split_path(path)?;
+    run_git(dir, &["show", &format!(":./{}", name)])
+}
+
+/// Who last changed a line, from `git blame`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameInfo {
+    pub hash: String,
+    pub author: String,
+    /// Author time as a Unix timestamp
+    pub time: i64,
+    pub summary: String,
+}
+
+impl BlameInfo {
+    /// False for lines changed in the buffer but not yet committed
+    pub fn is_committed(&self) -> bool {
+        self.hash.chars().any(|c| c != '0')
+    }
+}
+
+impl fmt::Display for BlameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.is_committed() {
+            return write!(f, "Not committed yet");
+        }
+        let short = &self.hash[..self.hash.len().min(8)];
+        write!(f, "{} {}, {} · {}", short, self.author, format_date(self.time), self.summary)
+    }
+}
+
+/// Blame a single line (0-based) of a file, using `contents` as the current
+/// text so unsaved edits are attributed correctly
+pub fn blame_line(path: &Path, line: usize, contents: &str) -> Option<BlameInfo> {
+    let (dir, name) = split_path(path)?;
+    let range = format!("{},{}", line + 1, line + 1);
+    let output = run_git_with_input(
+        dir,
+        &["blame", "--porcelain", "-L", &range, "--contents", "-", "--", &name],
+        Some(contents),
+    )?;
+    parse_blame_porcelain(&output)
+}
+
+/// Parse the first entry of `git blame --porcelain` output
+fn parse_blame_porcelain(output: &str) -> Option<BlameInfo> {
+    let mut lines = output.lines();
+    let hash = lines.next()?.split_whitespace().next()?.to_string();
+    let mut info = BlameInfo {
+        hash,
+        author: String::new(),
+        time: 0,
+        summary: String::new(),
+    };
+    for line in lines {
+        if line.starts_with('\t') {
+            break;
+        }
+        if let Some(author) = line.strip_prefix("author ") {
+            info.author = author.to_string();
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            info.time = time.parse().unwrap_or(0);
+        } else if let Some(summary) = line.strip_prefix("summary ") {
+            info.summary = summary.to_string();
+        }
+    }
+    Some(info)
+}
+
+/// `user.name` from the git config that applies to a file
+pub fn user_name(path: &Path) -> Option<String> {
+    let (dir, _) = split_path(path)?;
+    let name = run_git(dir, &["config", "user.name"])?;
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/// Format a Unix timestamp as a UTC `YYYY-MM-DD` date
+pub fn format_date(timestamp: i64) -> String {
+    // Days to civil date, after Howard Hinnant's algorithm
+    let days = timestamp.div_euclid(86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Line the gutter sign for a hunk goes on. Removed lines have no line of
+/// their own, so the sign goes on the line above.
+pub fn hunk_line(hunk: &Hunk) -> usize {
+    if hunk.new_len == 0 {
+        hunk.new_start.saturating_sub(1)
+    } else {
+        hunk.new_start
+    }
+}
+
+/// Gutter sign for a buffer line, if it changed
+pub fn sign_at(hunks: &[Hunk], line: usize) -> Option<ChangeKind> {
+    hunks.iter().find_map(|hunk| {
+        let covered = if hunk.new_len == 0 {
+            hunk_line(hunk) == line
+        } else {
+            (hunk.new_start..hunk.new_start + hunk.new_len).contains(&line)
+        };
+        covered.then(|| hunk.kind())
+    })
+}
+
+/// Character shown in the gutter for a change
+pub fn sign_char(kind: ChangeKind) -> char {
+    match kind {
+        ChangeKind::Added => '+',
+        ChangeKind::Modified => '~',
+        ChangeKind::Removed => '-',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::diff_lines;
+
+    #[test]
+    fn test_sign_at() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "B", "c", "x", "y"];
+        let hunks = diff_lines(&old, &new);
+        assert_eq!(sign_at(&hunks, 0), None);
+        assert_eq!(sign_at(&hunks, 1), Some(ChangeKind::Modified));
+        assert_eq!(sign_at(&hunks, 3), Some(ChangeKind::Modified));
+
+        let hunks = diff_lines(&["a", "b", "c"], &["a", "c"]);
+        assert_eq!(sign_at(&hunks, 0), Some(ChangeKind::Removed));
+        assert_eq!(sign_at(&hunks, 1), None);
+    }
+
+    #[test]
+    fn test_parse_blame_porcelain() {
+        let output = "1a2b3c4d5e6f 3 3 1\n\
+            author Ada Lovelace\n\
+            author-mail <ada@example.com>\n\
+            author-time 1700000000\n\
+            author-tz +0000\n\
+            summary Add the engine\n\
+            filename src/main.rs\n\
+            \tfn main() {}\n";
+        let info = parse_blame_porcelain(output).unwrap();
+        assert_eq!(info.author, "Ada Lovelace");
+        assert_eq!(info.time, 1_700_000_000);
+        assert_eq!(info.to_string(), "1a2b3c4d Ada Lovelace, 2023-11-14 · Add the engine");
+
+        let uncommitted = parse_blame_porcelain("0000000000000000000000000000000000000000 1 1 1\n").unwrap();
+        assert!(!uncommitted.is_committed());
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+    }
+
+    #[test]
+    fn test_index_content_untracked() {
+        let path = std::env::temp_dir().join(format!("quirks-git-{}.txt", std::process::id()));
+        assert_eq!(index_content(&path), None);
+    }
+}
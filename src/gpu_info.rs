@@ -1,7 +1,80 @@
-/// Placeholder module for GPU information
-/// Currently provides stub values. Extend with actual GPU queries as needed.
+//! System and GPU metrics for Quirks
+//!
+//! CPU and memory usage come from the `sysinfo` crate and GPU usage from
+//! NVIDIA's NVML library, each behind a cargo feature (`sysinfo`, on by
+//! default, and `nvml`). Whatever a build or machine cannot provide is
+//! left out.
 
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often metrics are refreshed while they are shown
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Usage of one GPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuUsage {
+    pub name: String,
+    /// Busy percentage (0-100)
+    pub usage: u8,
+    /// Used and total memory in bytes
+    pub memory: (u64, u64),
+    /// Temperature in °C
+    pub temperature: Option<u32>,
+}
+
+/// A snapshot of system metrics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// CPU usage over all cores (0-100)
+    pub cpu: Option<f32>,
+    /// Used and total RAM in bytes
+    pub memory: Option<(u64, u64)>,
+    pub gpus: Vec<GpuUsage>,
+}
+
+impl Metrics {
+    /// Short summary for the status line, e.g. "CPU 12% RAM 3.1/15.6G GPU 40%"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(cpu) = self.cpu {
+            parts.push(format!("CPU {:.0}%", cpu));
+        }
+        if let Some((used, total)) = self.memory {
+            parts.push(format!("RAM {}", format_usage(used, total)));
+        }
+        for gpu in &self.gpus {
+            parts.push(format!("GPU {}%", gpu.usage));
+        }
+        parts.join(" ")
+    }
+
+    /// One line per metric, for the :sysinfo popup
+    pub fn details(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(cpu) = self.cpu {
+            lines.push(format!("CPU     {:.1}%", cpu));
+        }
+        if let Some((used, total)) = self.memory {
+            lines.push(format!("Memory  {}", format_usage(used, total)));
+        }
+        for gpu in &self.gpus {
+            let temperature = gpu.temperature.map(|t| format!(", {}°C", t)).unwrap_or_default();
+            lines.push(format!("GPU     {}% {}{}", gpu.usage, gpu.name, temperature));
+            lines.push(format!("VRAM    {}", format_usage(gpu.memory.0, gpu.memory.1)));
+        }
+        if lines.is_empty() {
+            lines.push("No metrics available in this build".to_string());
+        }
+        lines
+    }
+}
+
+/// "used/total" in GiB with one decimal
+fn format_usage(used: u64, total: u64) -> String {
+    const GIB: f64 = (1u64 << 30) as f64;
+    format!("{:.1}/{:.1}G", used as f64 / GIB, total as f64 / GIB)
+}
 
 #[derive(Debug, Default)]
 pub struct GpuInfo {
@@ -9,10 +82,24 @@ pub struct GpuInfo {
     inner: Mutex<Inner>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 struct Inner {
-    /// Current GPU usage percentage (0-100)
-    usage: u8,
+    metrics: Metrics,
+    refreshed: Option<Instant>,
+    #[cfg(feature = "sysinfo")]
+    system: Option<sysinfo::System>,
+    /// None until NVML was first tried, then None inside if it is missing
+    #[cfg(feature = "nvml")]
+    nvml: Option<Option<nvml_wrapper::Nvml>>,
+}
+
+impl std::fmt::Debug for Inner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inner")
+            .field("metrics", &self.metrics)
+            .field("refreshed", &self.refreshed)
+            .finish_non_exhaustive()
+    }
 }
 
 impl GpuInfo {
@@ -21,22 +108,93 @@ impl GpuInfo {
         Self { inner: Mutex::new(Inner::default()) }
     }
 
-    /// Get the current GPU usage percentage
+    /// Get the current GPU usage percentage of the first GPU
     /// Returns None if GPU info is unavailable.
     pub fn get_usage(&self) -> Option<u8> {
-        // For now, return a dummy value or None if no GPU.
-        // Replace with real implementation (NVML, etc.) if desired.
-        let inner = self.inner.lock().unwrap();
-        if inner.usage > 0 {
-            Some(inner.usage)
-        } else {
-            None
+        self.metrics().gpus.first().map(|gpu| gpu.usage)
+    }
+
+    /// The metrics from the last refresh
+    pub fn metrics(&self) -> Metrics {
+        self.inner.lock().unwrap().metrics.clone()
+    }
+
+    /// Time until the metrics are due for a refresh
+    pub fn next_refresh(&self) -> Duration {
+        match self.inner.lock().unwrap().refreshed {
+            Some(at) => REFRESH_INTERVAL.saturating_sub(at.elapsed()),
+            None => Duration::ZERO,
         }
     }
 
-    /// Update the GPU usage (for demonstration purposes)
-    pub fn set_usage(&self, usage: u8) {
+    /// Refresh the metrics if `REFRESH_INTERVAL` has passed; returns true
+    /// if they changed
+    pub fn refresh_if_due(&self) -> bool {
+        if !self.next_refresh().is_zero() {
+            return false;
+        }
+        let before = self.metrics();
+        self.refresh();
+        self.metrics() != before
+    }
+
+    /// Read the metrics now
+    pub fn refresh(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.usage = usage;
+        inner.refreshed = Some(Instant::now());
+
+        #[cfg(feature = "sysinfo")]
+        {
+            let system = inner.system.get_or_insert_with(sysinfo::System::new);
+            system.refresh_cpu_usage();
+            system.refresh_memory();
+            let cpu = system.global_cpu_usage();
+            let memory = (system.used_memory(), system.total_memory());
+            inner.metrics.cpu = Some(cpu);
+            inner.metrics.memory = Some(memory);
+        }
+
+        #[cfg(feature = "nvml")]
+        {
+            use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+            let nvml = inner.nvml.get_or_insert_with(|| nvml_wrapper::Nvml::init().ok());
+            let mut gpus = Vec::new();
+            if let Some(nvml) = nvml {
+                for index in 0..nvml.device_count().unwrap_or(0) {
+                    let Ok(device) = nvml.device_by_index(index) else { continue };
+                    let Ok(utilization) = device.utilization_rates() else { continue };
+                    let memory = device.memory_info().map(|m| (m.used, m.total)).unwrap_or((0, 0));
+                    gpus.push(GpuUsage {
+                        name: device.name().unwrap_or_else(|_| format!("GPU {}", index)),
+                        usage: utilization.gpu.min(100) as u8,
+                        memory,
+                        temperature: device.temperature(TemperatureSensor::Gpu).ok(),
+                    });
+                }
+            }
+            inner.metrics.gpus = gpus;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_summary() {
+        assert_eq!(Metrics::default().summary(), "");
+        let metrics = Metrics {
+            cpu: Some(12.4),
+            memory: Some((3 << 30, 16 << 30)),
+            gpus: vec![GpuUsage {
+                name: "Test GPU".to_string(),
+                usage: 40,
+                memory: (1 << 30, 8 << 30),
+                temperature: Some(55),
+            }],
+        };
+        assert_eq!(metrics.summary(), "CPU 12% RAM 3.0/16.0G GPU 40%");
+        assert_eq!(metrics.details()[2], "GPU     40% Test GPU, 55°C");
     }
 }
@@ -0,0 +1,511 @@
+//! Help text for Quirks
+//!
+//! `:help` shows a scratch buffer generated from the sections below. Each
+//! section has tags (`:help registers`, `:help :s`); every key in a
+//! section's table is a tag as well. `|topic|` in the text is a link that
+//! Ctrl-] follows.
+
+/// One section of the help buffer
+pub struct HelpSection {
+    /// Names `:help` finds the section by; the first is its link name
+    pub tags: &'static [&'static str],
+    pub title: &'static str,
+    /// Paragraph above the table; may contain |links|
+    pub text: &'static str,
+    /// Keys or commands and what they do
+    pub entries: &'static [(&'static str, &'static str)],
+}
+
+pub const SECTIONS: &[HelpSection] = &[
+    HelpSection {
+        tags: &["navigation", "motions", "movement"],
+        title: "Moving around",
+        text: "Motions take a count: 5j moves five lines down. They also move every \
+               cursor when there are several, see |cursors|.",
+        entries: &[
+            ("h j k l", "Move left, down, up, right"),
+            ("w b e", "Word forward, backward, to the end"),
+            ("W B E", "WORD forward, backward, to the end; WORDs end at white space"),
+            ("0 $", "Line start, end"),
+            ("^", "First non-blank character of the line"),
+            ("gg G", "Start, end of the buffer"),
+            ("%", "Matching bracket, #if/#else/#endif, HTML tag or next arm of a match"),
+            ("f{char} F{char}", "Next, previous {char} on the line"),
+            ("CTRL-D CTRL-U", "Half a page down, up"),
+            ("gt gT", "Next, previous buffer"),
+        ],
+    },
+    HelpSection {
+        tags: &["editing", "insert", "operators", "counts", "textobjects"],
+        title: "Editing",
+        text: "A count repeats a command or widens it: 3dd deletes three lines, \
+               5~ toggles the case of five characters. Operators (d c y gu gU g~ \
+               > < gq gc) take a motion, a text object or their own key again \
+               for lines: d3w, gUiw, >j, gcc. Deleted and yanked text goes to a \
+               register, see |registers|.",
+        entries: &[
+            ("<Esc>", "Cancel a command being typed; its keys show at the bottom right"),
+            ("i a", "Insert before, after the cursor; 5i-<Esc> inserts five dashes"),
+            ("I A", "Insert at the start, end of the line"),
+            ("o O", "Open a new line below, above; 3o opens three with the same text"),
+            ("x", "Delete the character under the cursor"),
+            ("dd", "Delete the line"),
+            ("cc", "Change the line"),
+            ("D C", "Delete, change to the end of the line"),
+            ("d{motion} c y", "Delete, change, yank over a motion: dw c$ y2j d3W dgg"),
+            ("diw ci\" ya(", "Delete, change, yank a text object: iw aw, iW aW, i\" a\", i( a(, i{ a{, ip ap"),
+            ("gu gU g~", "Lower, upper, toggle case over a motion: gUiw, guu for the line"),
+            ("gc", "Comment out lines, or uncomment them: gcc, gcap, gcj"),
+            ("yy", "Yank (copy) the line"),
+            ("p P", "Paste after, before the cursor"),
+            ("r{char}", "Replace the character under the cursor"),
+            ("~", "Toggle the case of the character"),
+            ("J", "Join the next line onto this one; 3J joins three lines"),
+            ("gJ", "Join without removing indentation or adding a space"),
+            ("<C-a> <C-x>", "Add or subtract the count to the number at or after the cursor"),
+            (">> <<", "Indent, outdent the line by tab_width"),
+            ("ALT-j ALT-k", "Move the line down, up (3 ALT-j moves it three)"),
+            (":[range]m {address}", "Move lines below a line: :m +1, :m 0, :3,5m $"),
+            ("u CTRL-R", "Undo, redo; back at the saved text the buffer is unmodified"),
+            ("Tab", "In insert mode, indent to the next tab stop (see |formatting|)"),
+            ("CTRL-W CTRL-U", "In insert mode, delete the word, the line before the cursor"),
+            ("CTRL-T CTRL-D", "In insert mode, indent, outdent the line by tab_width"),
+            ("CTRL-X CTRL-F", "In insert mode, complete the file name before the cursor"),
+            ("CTRL-X CTRL-L", "In insert mode, complete a whole line from the open buffers"),
+            ("CTRL-N CTRL-P", "Next, previous completion; CTRL-Y keeps it, CTRL-E goes back"),
+            ("ga", "Show the code point, UTF-8 bytes and Unicode name of the character (:ascii)"),
+            ("g CTRL-G", "Count words, characters and bytes up to the cursor, or selected"),
+            (":[range]count", "Count lines, words, characters and bytes (also :wc)"),
+        ],
+    },
+    HelpSection {
+        tags: &["visual", "selection"],
+        title: "Visual mode",
+        text: "v selects characters, V whole lines and CTRL-V a block. Commands \
+               below act on the selection and return to normal mode.",
+        entries: &[
+            ("v V CTRL-V", "Start a character, line, block selection"),
+            ("y", "Yank the selection"),
+            ("d x", "Delete the selection"),
+            ("c", "Change the selection; in a block the text is typed on every line"),
+            ("iw a\" ip", "Select a text object instead, see |textobjects|"),
+            ("p P", "Replace the selection with a register"),
+            ("> <", "Shift the selected lines; the selection stays"),
+            ("ALT-j ALT-k", "Move the selected lines down, up; the selection stays"),
+            ("~ u U", "Toggle, lower, upper the case"),
+            ("J gJ", "Join the selected lines, with or without a space"),
+            ("<C-a> <C-x>", "Add or subtract the count on each selected line"),
+            ("g<C-a> g<C-x>", "Turn the selected numbers into a sequence"),
+            ("gq", "Rewrap the selected lines, see |formatting|"),
+            ("gu gU g~ gc", "Lower, upper, toggle the case, comment out the selection"),
+            (":", "Command on the selected lines ('<,'> is filled in)"),
+            ("/ ?", "Search inside the selection (\\%V is filled in)"),
+        ],
+    },
+    HelpSection {
+        tags: &["registers", "\"", "yank", "paste", "macros", "recording"],
+        title: "Registers",
+        text: "\"{name} before a delete, yank or paste uses that register instead \
+               of the unnamed one. Registers are kept between sessions.",
+        entries: &[
+            ("\"a-\"z", "Named registers; \"A-\"Z append to them"),
+            ("\"0", "The last yank"),
+            ("\"1-\"9", "The last deletes of whole lines, newest first"),
+            ("\"-", "The last delete within a line"),
+            ("\"_", "The black hole: nothing is stored"),
+            ("\"+", "The system clipboard; with :set clipboard also used when no register is given"),
+            ("\"*", "The primary selection on X11, the clipboard elsewhere"),
+            (":[range]d [x] [N]", "Delete lines into register x; N lines from the end of the range"),
+            (":[range]y [x] [N]", "Yank lines into register x"),
+            (":[line]put [x]", "Put register x as lines below the line; :put! above, :0put at the top"),
+            ("q{a-z}", "Record typed keys into a register until q; q{A-Z} appends"),
+            ("@{a-z} @@", "Run the macro in a register, or the last one run again; stops at an error"),
+            (":macros", "Show the macros recorded this session"),
+        ],
+    },
+    HelpSection {
+        tags: &["search", "/", "?", "pattern"],
+        title: "Searching",
+        text: "Patterns are regular expressions. Matches show while typing when \
+               incsearch is set, and stay highlighted after the search when \
+               hlsearch is set. To replace matches see |:s|.",
+        entries: &[
+            ("/pattern", "Search forward"),
+            ("?pattern", "Search backward"),
+            ("/pattern/e", "Land on the match end; /pat/e-1, /pat/s+1, /pat/+1 for lines below"),
+            ("d/pattern", "Delete up to the match (also c, y); linewise with a line offset"),
+            ("n N", "Next, previous match"),
+            ("* #", "Search for the word under the cursor, staying on it"),
+            (":noh", "Hide match highlighting until the next search or n"),
+            ("Up Down", "Recall earlier searches"),
+        ],
+    },
+    HelpSection {
+        tags: &[":s", "substitute", ":&&", "&"],
+        title: "Substitute",
+        text: "A range before the command picks the lines: % for all of them, 3,9 \
+               for lines 3 to 9, '<,'> for the last visual selection, none for \
+               the cursor line. \\%V in the pattern only matches inside the last \
+               visual selection. In the replacement & is the whole match and \\1 \
+               the first group.",
+        entries: &[
+            (":s/pat/rep/g", "Replace every pat on the line with rep"),
+            (":%s/pat/rep/", "Replace the first pat on every line"),
+            (":s/pat/rep/gc", "Ask before each replacement: y n a(ll) q(uit) l(ast)"),
+            (":s/\\%Vpat/rep/g", "Replace only inside the last visual selection"),
+            (":&&", "Repeat the last substitution with its flags"),
+            ("&", "Repeat the last substitution on the cursor line"),
+        ],
+    },
+    HelpSection {
+        tags: &["formatting", "gq", ":retab", "indent"],
+        title: "Formatting",
+        text: "gq refills paragraphs to text_width, keeping indentation and \
+               comment leaders such as // and #. expand_tab decides whether Tab \
+               and :retab use spaces, see |options|.",
+        entries: &[
+            ("gqq", "Rewrap the line (3gqq for three)"),
+            ("gqap gqip", "Rewrap the paragraph"),
+            ("gq} gqj gqG", "Rewrap to the end of the paragraph, the next line, the end"),
+            (":retab [N]", "Redo indentation; N sets a new tab width"),
+            (":TableFormat", "Align the Markdown or Org table under the cursor"),
+        ],
+    },
+    HelpSection {
+        tags: &["cursors", "multiple-cursors", "CTRL-N"],
+        title: "Multiple cursors",
+        text: "Typing, x and motions act at every cursor.",
+        entries: &[
+            ("CTRL-N", "Add a cursor at the next match of the word under the cursor"),
+            ("Esc", "In normal mode, go back to one cursor"),
+        ],
+    },
+    HelpSection {
+        tags: &["marks", "m"],
+        title: "Marks",
+        text: "Lowercase marks belong to a file, uppercase marks remember the file \
+               too. Marks are kept between sessions.",
+        entries: &[
+            ("m{a-zA-Z}", "Set a mark at the cursor"),
+            ("'{mark}", "Jump to the mark's line"),
+            ("`{mark}", "Jump to the mark's exact position"),
+            (":marks", "List the marks"),
+            ("'^ `^", "Where insert mode was last left in this file"),
+            ("gi", "Insert again where insert mode was last left"),
+            ("'\" `\"", "Where the cursor was when this file was last left"),
+        ],
+    },
+    HelpSection {
+        tags: &["bookmarks", ":bookmark", ":bm"],
+        title: "Bookmarks",
+        text: "Bookmarks are lines worth coming back to, with a note. They \
+               belong to the project, are kept between sessions and follow \
+               their lines as text is added above them. A sign in the gutter \
+               shows them.",
+        entries: &[
+            (":bookmark add [note]", "Bookmark the cursor line (also :bm add)"),
+            (":bookmark remove", "Remove the bookmark on the cursor line"),
+            (":bookmarks", "Pick a bookmark of the project to jump to"),
+        ],
+    },
+    HelpSection {
+        tags: &["files", "buffers", ":e", ":w", ":q"],
+        title: "Files and buffers",
+        text: "A file that does not exist yet opens empty and is created by :w. \
+               With the templates option it starts from a template. A file \
+               opened again starts where the cursor was left, unless \
+               restore_cursor is off. A directory opens in the file explorer.",
+        entries: &[
+            (":w", "Save the file, offering to create a missing directory"),
+            (":w ++p", "Save, creating missing directories"),
+            (":w!", "Save a read-only buffer anyway"),
+            (":[range]w <file>", "Write lines to another file; :[range]w! replaces it"),
+            (":[range]w >> <file>", "Append lines to a file, the whole buffer without a range"),
+            (":q", "Quit; :q! quits without saving"),
+            (":qa <C-q>", "Quit, asking to save or discard unsaved buffers"),
+            (":wq", "Save and quit"),
+            (":wa", "Save every modified buffer"),
+            (":wqa :xa", "Save every modified buffer and quit if all were written"),
+            (":e <file>", "Open a file (also :new <file>)"),
+            (":ls", "List buffers"),
+            (":b <N>", "Switch to buffer N"),
+            (":bd", "Close the buffer, asking about unsaved changes; :bd! discards them"),
+            (":Rename <name>", "Rename the file; :Rename! replaces an existing one, :Rename asks for the name"),
+            (":Delete", "Delete the file and close it, after asking"),
+            (":cd <dir>", "Change the working directory (:lcd for this buffer)"),
+            (":oldfiles", "Pick from recently opened files"),
+        ],
+    },
+    HelpSection {
+        tags: &["finder", "picker", "explorer"],
+        title: "Finding files",
+        text: "The finders filter as you type; Enter opens the selected entry.",
+        entries: &[
+            (":Files", "Fuzzy find files in the project"),
+            (":Buffers", "Fuzzy find open buffers"),
+            (":Lines", "Fuzzy find lines in the buffer"),
+            (":Explore", "Toggle the file explorer"),
+        ],
+    },
+    HelpSection {
+        tags: &["tags", "ctags", ":tag"],
+        title: "Tags",
+        text: "Tag jumps need a ctags tags file in the working directory or the \
+               project root. In this help, CTRL-] follows links instead, see |help|.",
+        entries: &[
+            ("CTRL-]", "Jump to the definition of the word under the cursor"),
+            ("CTRL-T", "Go back"),
+            (":tag <name>", "Jump to a tag by name"),
+        ],
+    },
+    HelpSection {
+        tags: &["terminal", ":terminal", "repl"],
+        title: "Terminal",
+        text: "The terminal runs a shell in a pane below the buffer.",
+        entries: &[
+            (":terminal", "Open or focus the terminal"),
+            ("CTRL-\\ CTRL-N", "Leave terminal mode"),
+            (":TermSend", "Send the paragraph or selection to the terminal or repl_command"),
+        ],
+    },
+    HelpSection {
+        tags: &["quickfix", ":make", ":Run"],
+        title: "Building",
+        text: "Errors from builds are collected in the quickfix list.",
+        entries: &[
+            (":make [args]", "Run makeprg and load its errors"),
+            (":Run <cmd>", "Run a command in the background"),
+            (":cnext :cprev", "Next, previous error"),
+            (":clist", "Pick an error from the list"),
+        ],
+    },
+    HelpSection {
+        tags: &["git", ":Gblame", "diff"],
+        title: "Git",
+        text: "Lines changed since the git index are marked in the gutter.",
+        entries: &[
+            ("]c [c", "Next, previous change"),
+            (":Gblame", "Toggle blame for the cursor line"),
+            (":diffsplit <file>", "Compare with another file"),
+            (":w diff :DiffSaved", "Compare with the file as saved on disk"),
+        ],
+    },
+    HelpSection {
+        tags: &["options", ":set", "config", ":trust"],
+        title: "Options",
+        text: "Options are read from ~/.quirksrc at startup and changed with :set. \
+               Key bindings are configured there too, see |keymaps|. A \
+               .quirks.toml in the project root is applied over them for the \
+               project's files, once you trust it.",
+        entries: &[
+            (":set", "Show the settings"),
+            (":set {option}", "Turn an option on (:set no{option} turns it off)"),
+            (":set {option}={value}", "Give an option a value (:set tw=72)"),
+            (":set {option}?", "Show an option's value"),
+            (":set ft={name}", "Highlight this buffer as another language (:filetype detect undoes it)"),
+            (":filetype", "Show the buffer's filetype and whether it was detected or set"),
+            (":setlocal [no]syntax", "Highlight this buffer or not, whatever :set syntax says; syntax& undoes it"),
+            (":setlocal [no]readonly", "Refuse :w in this buffer without ! (:ro toggles it)"),
+            (":colorscheme {name}", "Color the status line with another theme; :colorscheme lists them"),
+            (":trust", "Trust and apply the project's .quirks.toml"),
+            (":trust ++deny", "Never read the project's .quirks.toml"),
+            (":trust ++remove", "Ask about the project's .quirks.toml again"),
+        ],
+    },
+    HelpSection {
+        tags: &["keymaps", "mappings", "leader"],
+        title: "Key bindings",
+        text: "The [keymaps.normal] and [keymaps.insert] sections of ~/.quirksrc \
+               bind keys in Vim notation to actions or to ex commands starting \
+               with a colon. The :map commands change them while editing; keys \
+               bound to nothing run the built-in commands.",
+        entries: &[
+            ("<leader>", "The leader key, set with leader in the config"),
+            (":map", "List the normal and visual mode bindings"),
+            (":nmap {keys}", "List the normal mode bindings starting with keys"),
+            (":nmap {keys} {action}", "Bind keys in normal mode (:imap, :vmap; :map for both)"),
+            (":nunmap {keys}", "Remove a binding, bringing back the default one (:iunmap, ...)"),
+        ],
+    },
+    HelpSection {
+        tags: &["lua", "init.lua", "autocmd", ":lua", ":luafile"],
+        title: "Lua",
+        text: "~/.config/quirks/init.lua is run at startup. The quirks table \
+               gives it the editor's state, and print shows text on the \
+               message line. Autocommands run on BufRead, BufWritePre, \
+               BufWritePost, BufLeave, BufDelete, ModeChanged and CursorMoved; \
+               the callback gets {event, file, match}.",
+        entries: &[
+            (":lua {code}", "Run a line of Lua"),
+            (":lua ={expr}", "Show the values of a Lua expression"),
+            (":luafile {path}", "Run a Lua file (:luafile % runs the current file)"),
+            ("require(\"name\")", "Load ~/.config/quirks/lua/name.lua or lua/name/init.lua"),
+            ("quirks.autocmd(ev, fn)", "Run fn on an event (or a list of them)"),
+            ("quirks.autocmd(ev, opts)", "Only for files matching opts.pattern (\"*.rs,*.toml\"); opts.callback runs"),
+            ("quirks.command(name, fn)", "Define :name; fn gets {name, args, fargs, bang}"),
+            ("quirks.command(name, s)", "Define :name as the ex command s, <args> replaced by the arguments"),
+            ("quirks.keymap(mode, keys, rhs)", "Map keys in \"n\", \"i\", \"v\" or \"c\" to an action name, \":cmd<CR>\" or a function"),
+            ("quirks.cmd(s)", "Run ex commands, one per line (also vim.cmd(s), vim.cmd.set(\"nu\"))"),
+            ("quirks.popup(lines, opts)", "Show a popup; opts: anchor (center or cursor), title, width, border, fg, bg"),
+            ("popup:update(lines)", "Change the lines of a popup (popup:close() closes it; Esc closes all)"),
+            ("quirks.statusline(fn)", "Add what fn returns to the status line: text, {text, fg, bg, bold, ...} or a list"),
+            ("quirks.buf_name()", "The current file"),
+            ("quirks.mode()", "The mode, as in the status line"),
+            ("quirks.cursor()", "Line and column, from 1"),
+            ("quirks.line_count()", "Number of lines"),
+            ("quirks.get_line(n)", "Line n, from 1"),
+            ("quirks.get_option(name)", "An option's value, the buffer's own if it has one"),
+            ("quirks.set_option(name, v)", "Set an option as :set does; { scope = \"local\" } as :setlocal"),
+        ],
+    },
+];
+
+/// The generated help text with the line of every tag
+pub struct HelpPage {
+    pub text: String,
+    /// Section tags, with the line they point at
+    tags: Vec<(&'static str, usize)>,
+    /// Keys from the tables, with the line they point at
+    keys: Vec<(&'static str, usize)>,
+}
+
+const WIDTH: usize = 78;
+const KEY_WIDTH: usize = 22;
+
+impl HelpPage {
+    pub fn build() -> Self {
+        let mut lines = vec![right_align("*help*", "Quirks help"), String::new()];
+        lines.extend(wrap(
+            "Move onto a link such as |registers| and press CTRL-] to follow it, CTRL-T \
+             to go back. :help <topic> jumps to a topic, q closes the help.",
+        ));
+        lines.push(String::new());
+        for section in SECTIONS {
+            lines.push(format!("  {:<KEY_WIDTH$}{}", format!("|{}|", section.tags[0]), section.title));
+        }
+
+        let mut tags = vec![("help", 0)];
+        let mut keys = Vec::new();
+        for section in SECTIONS {
+            lines.push(String::new());
+            lines.push("=".repeat(WIDTH));
+            let tag_line: Vec<String> = section.tags.iter().map(|tag| format!("*{}*", tag)).collect();
+            tags.extend(section.tags.iter().map(|tag| (*tag, lines.len())));
+            lines.push(right_align(&tag_line.join(" "), section.title));
+            lines.push(String::new());
+            lines.extend(wrap(section.text));
+            lines.push(String::new());
+            for (key, description) in section.entries {
+                keys.extend(key.split_whitespace().map(|key| (key, lines.len())));
+                keys.push((key, lines.len()));
+                lines.push(format!("  {:<KEY_WIDTH$}{}", key, description));
+            }
+        }
+
+        let mut text = lines.join("\n");
+        text.push('\n');
+        Self { text, tags, keys }
+    }
+
+    /// Line of the help for `topic`: a section tag, else a key from the
+    /// tables, else a tag that starts with it
+    pub fn find(&self, topic: &str) -> Option<usize> {
+        let topic = topic.trim();
+        let exact = |list: &[(&str, usize)]| list.iter().find(|(tag, _)| *tag == topic).map(|&(_, line)| line);
+        exact(&self.tags)
+            .or_else(|| exact(&self.keys))
+            .or_else(|| {
+                let topic = topic.to_lowercase();
+                self.tags.iter()
+                    .find(|(tag, _)| tag.to_lowercase().starts_with(&topic))
+                    .map(|&(_, line)| line)
+            })
+    }
+}
+
+/// `left` and `right` on one line, `right` ending at the help width
+fn right_align(left: &str, right: &str) -> String {
+    let gap = WIDTH.saturating_sub(left.chars().count() + right.chars().count()).max(1);
+    format!("{}{}{}", left, " ".repeat(gap), right)
+}
+
+/// Words of `text` filled into lines of the help width
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in text.split_whitespace() {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > WIDTH {
+            lines.push(word.to_string());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+    }
+    lines
+}
+
+/// The |link| on `line` that column `col` is in
+pub fn link_at(line: &str, col: usize) -> Option<&str> {
+    let mut start = None;
+    for (idx, (byte, ch)) in line.char_indices().enumerate() {
+        if ch != '|' {
+            continue;
+        }
+        match start {
+            Some((open_idx, open_byte)) => {
+                if (open_idx..=idx).contains(&col) && byte > open_byte + 1 {
+                    return Some(&line[open_byte + 1..byte]);
+                }
+                start = None;
+            }
+            None => start = Some((idx, byte)),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_topics() {
+        let page = HelpPage::build();
+        let lines: Vec<&str> = page.text.lines().collect();
+        let line_of = |topic: &str| lines[page.find(topic).unwrap()];
+
+        assert!(line_of("registers").contains("*registers*"));
+        assert!(line_of(":s").contains("*:s*"));
+        assert!(line_of("dd").starts_with("  dd "));
+        assert!(line_of("gqap").starts_with("  gqap gqip "));
+        assert!(line_of("quick").contains("*quickfix*"));
+        assert_eq!(page.find("help"), Some(0));
+        assert_eq!(page.find("no such topic"), None);
+    }
+
+    #[test]
+    fn test_links_resolve() {
+        let page = HelpPage::build();
+        for line in page.text.lines() {
+            let mut rest = line;
+            while let Some(open) = rest.find('|') {
+                let Some(len) = rest[open + 1..].find('|') else { break };
+                let link = &rest[open + 1..open + 1 + len];
+                assert!(page.find(link).is_some(), "broken link |{}|", link);
+                rest = &rest[open + len + 2..];
+            }
+        }
+    }
+
+    #[test]
+    fn test_link_at() {
+        let line = "see |registers| and |:s|";
+        assert_eq!(link_at(line, 4), Some("registers"));
+        assert_eq!(link_at(line, 8), Some("registers"));
+        assert_eq!(link_at(line, 14), Some("registers"));
+        assert_eq!(link_at(line, 16), None);
+        assert_eq!(link_at(line, 22), Some(":s"));
+    }
+}
@@ -22,12 +22,10 @@ struct Snapshot {
 /// Undo/Redo history manager
 #[derive(Clone)]
 pub struct History {
-    /// Past states (for undo)
+    /// States before each change (for undo)
     undo_stack: Vec<Snapshot>,
-    /// Future states (for redo)
-    redo_stack: Vec<Snapshot>,
-    /// Current content (for change detection)
-    current_content: Option<Rope>,
+    /// Undone changes as (state after, state before) pairs (for redo)
+    redo_stack: Vec<(Snapshot, Snapshot)>,
 }
 
 impl Default for History {
@@ -41,104 +39,86 @@ impl History {
         Self {
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
-            current_content: None,
         }
     }
 
     /// Initialize with the current buffer content
-    pub fn init(&mut self, content: &Rope, cursor_line: usize, cursor_col: usize) {
+    pub fn init(&mut self, _content: &Rope, _cursor_line: usize, _cursor_col: usize) {
         self.undo_stack.clear();
         self.redo_stack.clear();
-        self.current_content = Some(content.clone());
-        // Save initial state so we can undo back to it
-        self.undo_stack.push(Snapshot {
-            content: content.clone(),
-            cursor_line,
-            cursor_col,
-        });
     }
 
     /// Record a change to the buffer
     /// Call this BEFORE making the change, with the current state
     pub fn record(&mut self, content: &Rope, cursor_line: usize, cursor_col: usize) {
+        // A checkpoint with no change since the previous one is not a new step
+        if self.undo_stack.last().is_some_and(|s| s.content == *content) {
+            return;
+        }
+
         // Clear redo stack on new edit
         self.redo_stack.clear();
-        
-        // Only record if content actually changed
-        if let Some(ref current) = self.current_content {
-            if content.len_bytes() == current.len_bytes() {
-                // Quick length check - if same length, do full comparison
-                let content_str = content.to_string();
-                let current_str = current.to_string();
-                if content_str == current_str {
-                    return; // No change
-                }
-            }
-        }
-        
+
         // Save current state to undo stack
         self.undo_stack.push(Snapshot {
             content: content.clone(),
             cursor_line,
             cursor_col,
         });
-        
+
         // Limit history size
         if self.undo_stack.len() > MAX_HISTORY_SIZE {
             self.undo_stack.remove(0);
         }
-        
-        self.current_content = Some(content.clone());
-    }
-
-    /// Update the current content without recording (for tracking)
-    pub fn update_current(&mut self, content: &Rope) {
-        self.current_content = Some(content.clone());
     }
 
     /// Undo the last change
     /// Returns the state to restore (content, cursor_line, cursor_col), or None if nothing to undo
     pub fn undo(&mut self, current_content: &Rope, cursor_line: usize, cursor_col: usize) -> Option<(Rope, usize, usize)> {
-        if self.undo_stack.len() <= 1 {
-            // Need at least 2 states: initial + one change
-            return None;
+        // Drop checkpoints that were never followed by a change
+        while self.undo_stack.last().is_some_and(|s| s.content == *current_content) {
+            self.undo_stack.pop();
         }
-        
-        // Save current state to redo stack
-        self.redo_stack.push(Snapshot {
+
+        let snapshot = self.undo_stack.pop()?;
+        let current = Snapshot {
             content: current_content.clone(),
             cursor_line,
             cursor_col,
-        });
-        
-        // Pop and discard current state from undo stack
-        self.undo_stack.pop();
-        
-        // Get previous state
-        if let Some(snapshot) = self.undo_stack.last() {
-            self.current_content = Some(snapshot.content.clone());
-            Some((snapshot.content.clone(), snapshot.cursor_line, snapshot.cursor_col))
-        } else {
-            None
-        }
+        };
+        self.redo_stack.push((current, snapshot.clone()));
+        Some((snapshot.content, snapshot.cursor_line, snapshot.cursor_col))
     }
 
     /// Redo the last undone change
     /// Returns the state to restore, or None if nothing to redo
     pub fn redo(&mut self) -> Option<(Rope, usize, usize)> {
-        if let Some(snapshot) = self.redo_stack.pop() {
-            // Add to undo stack
-            self.undo_stack.push(snapshot.clone());
-            self.current_content = Some(snapshot.content.clone());
-            Some((snapshot.content, snapshot.cursor_line, snapshot.cursor_col))
-        } else {
-            None
+        let (after, before) = self.redo_stack.pop()?;
+        self.undo_stack.push(before);
+        Some((after.content, after.cursor_line, after.cursor_col))
+    }
+
+    /// Number of undo steps from `current` to a state with `target` text:
+    /// negative going back (undo), positive going forward (redo), None
+    /// if the history does not reach it
+    pub fn steps_to(&self, current: &Rope, target: &Rope) -> Option<isize> {
+        if current == target {
+            return Some(0);
         }
+        // A checkpoint never followed by a change is not a step
+        let back = self.undo_stack.iter().rev().skip_while(|s| s.content == *current);
+        if let Some(idx) = back.map(|s| &s.content).position(|content| content == target) {
+            return Some(-(idx as isize + 1));
+        }
+        let forward = self.redo_stack.iter().rev().map(|(after, _)| &after.content);
+        forward.enumerate()
+            .find(|(_, content)| *content == target)
+            .map(|(idx, _)| idx as isize + 1)
     }
 
     /// Check if undo is available
     pub fn can_undo(&self) -> bool {
-        self.undo_stack.len() > 1
+        !self.undo_stack.is_empty()
     }
 
     /// Check if redo is available
@@ -148,7 +128,7 @@ impl History {
 
     /// Get undo stack size (for status display)
     pub fn undo_count(&self) -> usize {
-        self.undo_stack.len().saturating_sub(1)
+        self.undo_stack.len()
     }
 
     /// Get redo stack size (for status display)
@@ -188,6 +168,46 @@ mod tests {
         assert!(!history.can_redo());
     }
 
+    #[test]
+    fn test_undo_steps_back_one_change() {
+        let mut history = History::new();
+        let one = Rope::from_str("one\ntwo\nthree");
+        let two = Rope::from_str("two\nthree");
+        let three = Rope::from_str("two");
+        history.init(&one, 0, 0);
+        history.record(&one, 0, 0);
+        history.record(&two, 1, 0);
+        history.record(&two, 1, 0);
+
+        let (content, line, _) = history.undo(&three, 0, 0).unwrap();
+        assert_eq!(content, two);
+        assert_eq!(line, 1);
+        let (content, _, _) = history.undo(&two, 1, 0).unwrap();
+        assert_eq!(content, one);
+        assert!(history.undo(&one, 0, 0).is_none());
+
+        let (content, _, _) = history.redo().unwrap();
+        assert_eq!(content, two);
+        let (content, _, _) = history.redo().unwrap();
+        assert_eq!(content, three);
+        assert!(history.redo().is_none());
+        assert_eq!(history.undo_count(), 2);
+    }
+
+    #[test]
+    fn test_steps_to() {
+        let mut history = History::new();
+        let (one, two, three) = (Rope::from_str("one"), Rope::from_str("two"), Rope::from_str("three"));
+        history.record(&one, 0, 0);
+        history.record(&two, 0, 0);
+        assert_eq!(history.steps_to(&three, &three), Some(0));
+        assert_eq!(history.steps_to(&three, &one), Some(-2));
+        history.undo(&three, 0, 0);
+        history.undo(&two, 0, 0);
+        assert_eq!(history.steps_to(&one, &three), Some(2));
+        assert_eq!(history.steps_to(&one, &Rope::from_str("four")), None);
+    }
+
     #[test]
     fn test_undo_redo_count() {
         let history = History::new();
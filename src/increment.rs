@@ -0,0 +1,181 @@
+//! Number increments for Quirks
+//!
+//! What Ctrl-A and Ctrl-X change: the first number at or after the cursor,
+//! read as decimal or, depending on the `nrformats` option, as `0x` hex,
+//! `0b` binary or `0`-prefixed octal. With `alpha` single letters count
+//! too. Hex, binary and octal numbers keep their width and letter case; a
+//! decimal number with leading zeros keeps its width.
+
+/// Which kinds of numbers Ctrl-A recognizes, from `nrformats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NrFormats {
+    pub bin: bool,
+    pub hex: bool,
+    pub octal: bool,
+    pub alpha: bool,
+}
+
+impl NrFormats {
+    /// Parse a comma-separated list such as `bin,hex`; unknown names are
+    /// ignored
+    pub fn parse(list: &str) -> Self {
+        let mut formats = Self::default();
+        for name in list.split(',').map(str::trim) {
+            match name {
+                "bin" => formats.bin = true,
+                "hex" => formats.hex = true,
+                "octal" => formats.octal = true,
+                "alpha" => formats.alpha = true,
+                _ => {}
+            }
+        }
+        formats
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Decimal,
+    Hex,
+    Bin,
+    Octal,
+    Alpha,
+}
+
+/// A number found in a line: characters `start..end`, the digits starting
+/// at `digits` (after a sign or prefix)
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: Kind,
+    start: usize,
+    digits: usize,
+    end: usize,
+}
+
+/// The first number (or letter with `alpha`) in `chars` ending after `col`
+fn find_token(chars: &[char], col: usize, formats: NrFormats) -> Option<Token> {
+    let run = |from: usize, pred: &dyn Fn(char) -> bool| {
+        from + chars[from..].iter().take_while(|&&c| pred(c)).count()
+    };
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let token = if c.is_ascii_digit() {
+            let next = chars.get(i + 1).copied();
+            let after = chars.get(i + 2).copied();
+            if formats.hex && c == '0' && matches!(next, Some('x' | 'X')) && after.is_some_and(|c| c.is_ascii_hexdigit()) {
+                Token { kind: Kind::Hex, start: i, digits: i + 2, end: run(i + 2, &|c| c.is_ascii_hexdigit()) }
+            } else if formats.bin && c == '0' && matches!(next, Some('b' | 'B')) && matches!(after, Some('0' | '1')) {
+                Token { kind: Kind::Bin, start: i, digits: i + 2, end: run(i + 2, &|c| c == '0' || c == '1') }
+            } else {
+                let end = run(i, &|c| c.is_ascii_digit());
+                let octal = formats.octal && c == '0' && end - i > 1 && chars[i..end].iter().all(|c| *c < '8');
+                // A minus sign counts unless it joins words, as in file-1
+                let signed = !octal
+                    && i > 0
+                    && chars[i - 1] == '-'
+                    && (i < 2 || !chars[i - 2].is_alphanumeric());
+                let kind = if octal { Kind::Octal } else { Kind::Decimal };
+                Token { kind, start: if signed { i - 1 } else { i }, digits: i, end }
+            }
+        } else if formats.alpha && c.is_ascii_alphabetic() {
+            Token { kind: Kind::Alpha, start: i, digits: i, end: i + 1 }
+        } else {
+            i += 1;
+            continue;
+        };
+        if token.end > col {
+            return Some(token);
+        }
+        i = token.end;
+    }
+    None
+}
+
+/// Digits of `value` in `radix`, zero-padded to `width`
+fn format_radix(value: u64, radix: u32, width: usize, upper: bool) -> String {
+    let digits = match radix {
+        2 => format!("{:b}", value),
+        8 => format!("{:o}", value),
+        16 if upper => format!("{:X}", value),
+        16 => format!("{:x}", value),
+        _ => value.to_string(),
+    };
+    format!("{:0>width$}", digits, width = width)
+}
+
+/// `line` with `delta` added to the first number at or after character
+/// `col`, and the column of the last character of the new number. None if
+/// there is no number there.
+pub fn increment(line: &str, col: usize, delta: i64, formats: NrFormats) -> Option<(String, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let token = find_token(&chars, col, formats)?;
+    let digits: String = chars[token.digits..token.end].iter().collect();
+    let width = digits.len();
+    let replacement = match token.kind {
+        Kind::Decimal => {
+            let magnitude = digits.parse::<i128>().unwrap_or(i128::MAX);
+            let value = if token.start < token.digits { -magnitude } else { magnitude };
+            let new = value.saturating_add(delta as i128);
+            let padded = if digits.starts_with('0') && width > 1 { width } else { 0 };
+            format!("{}{:0>width$}", if new < 0 { "-" } else { "" }, new.unsigned_abs(), width = padded)
+        }
+        Kind::Hex | Kind::Bin | Kind::Octal => {
+            let radix = match token.kind {
+                Kind::Hex => 16,
+                Kind::Bin => 2,
+                _ => 8,
+            };
+            let value = u64::from_str_radix(&digits, radix).unwrap_or(u64::MAX);
+            let new = value.wrapping_add(delta as u64);
+            // Hex keeps the case of its last letter
+            let upper = digits.chars().rev().find(|c| c.is_ascii_alphabetic()).is_some_and(|c| c.is_ascii_uppercase());
+            let prefix: String = chars[token.start..token.digits].iter().collect();
+            format!("{}{}", prefix, format_radix(new, radix, width, upper))
+        }
+        Kind::Alpha => {
+            let c = chars[token.start];
+            let base = if c.is_ascii_uppercase() { b'A' } else { b'a' };
+            let offset = (c as u8 - base) as i64 + delta;
+            ((base as i64 + offset.clamp(0, 25)) as u8 as char).to_string()
+        }
+    };
+    let before: String = chars[..token.start].iter().collect();
+    let after: String = chars[token.end..].iter().collect();
+    let last = token.start + replacement.chars().count() - 1;
+    Some((format!("{}{}{}", before, replacement, after), last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inc(line: &str, col: usize, delta: i64, formats: &str) -> Option<(String, usize)> {
+        increment(line, col, delta, NrFormats::parse(formats))
+    }
+
+    #[test]
+    fn test_increment_decimal() {
+        assert_eq!(inc("x = 9;", 0, 1, ""), Some(("x = 10;".to_string(), 5)));
+        assert_eq!(inc("a 1 b 2", 3, 5, ""), Some(("a 1 b 7".to_string(), 6)));
+        assert_eq!(inc("y = -3", 0, 5, ""), Some(("y = 2".to_string(), 4)));
+        assert_eq!(inc("y = 2", 0, -5, ""), Some(("y = -3".to_string(), 5)));
+        assert_eq!(inc("file-1.txt", 0, 1, ""), Some(("file-2.txt".to_string(), 5)));
+        assert_eq!(inc("007", 0, 1, ""), Some(("008".to_string(), 2)));
+        assert_eq!(inc("no number", 0, 1, ""), None);
+        assert_eq!(inc("12 ab", 3, 1, ""), None);
+    }
+
+    #[test]
+    fn test_increment_formats() {
+        assert_eq!(inc("0x0f", 3, 1, "hex"), Some(("0x10".to_string(), 3)));
+        assert_eq!(inc("0xFF", 0, 1, "hex"), Some(("0x100".to_string(), 4)));
+        assert_eq!(inc("0xff", 0, 1, "bin"), Some(("1xff".to_string(), 0)));
+        assert_eq!(inc("0b0111", 0, 1, "bin,hex"), Some(("0b1000".to_string(), 5)));
+        assert_eq!(inc("007", 0, 1, "octal"), Some(("010".to_string(), 2)));
+        assert_eq!(inc("089", 0, 1, "octal"), Some(("090".to_string(), 2)));
+        assert_eq!(inc("item b", 5, 1, "alpha"), Some(("item c".to_string(), 5)));
+        assert_eq!(inc("z", 0, 3, "alpha"), Some(("z".to_string(), 0)));
+        assert_eq!(NrFormats::parse("bin, hex"), NrFormats { bin: true, hex: true, ..Default::default() });
+    }
+}
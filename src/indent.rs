@@ -0,0 +1,69 @@
+//! Indentation helpers for Quirks
+//!
+//! Measures leading whitespace with tabs expanded to tab stops, and
+//! rebuilds it with tabs or spaces for `:retab` and `expand_tab`.
+
+/// The leading whitespace of `line` and its width in columns, tabs
+/// advancing to the next multiple of `tab_width`
+pub fn leading_whitespace(line: &str, tab_width: usize) -> (&str, usize) {
+    let tab_width = tab_width.max(1);
+    let mut width = 0;
+    let mut len = 0;
+    for ch in line.chars() {
+        match ch {
+            ' ' => width += 1,
+            '\t' => width += tab_width - width % tab_width,
+            _ => break,
+        }
+        len += ch.len_utf8();
+    }
+    (&line[..len], width)
+}
+
+/// Screen columns `text` takes up, tabs advancing to the next multiple
+/// of `tab_width`
+pub fn display_width(text: &str, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    text.chars().fold(0, |width, ch| match ch {
+        '\t' => width + tab_width - width % tab_width,
+        _ => width + 1,
+    })
+}
+
+/// Whitespace `width` columns wide: all spaces with `expand_tab`, else as
+/// many tabs as fit and spaces for the rest
+pub fn make_indent(width: usize, tab_width: usize, expand_tab: bool) -> String {
+    let tab_width = tab_width.max(1);
+    if expand_tab {
+        " ".repeat(width)
+    } else {
+        format!("{}{}", "\t".repeat(width / tab_width), " ".repeat(width % tab_width))
+    }
+}
+
+/// `line` with its indentation rewritten for `:retab`: tabs measured with
+/// `old_tab_width`, rebuilt for `new_tab_width`. None if it is unchanged.
+pub fn retab_line(line: &str, old_tab_width: usize, new_tab_width: usize, expand_tab: bool) -> Option<String> {
+    let (indent, width) = leading_whitespace(line, old_tab_width);
+    let new_indent = make_indent(width, new_tab_width, expand_tab);
+    if new_indent == indent {
+        return None;
+    }
+    Some(format!("{}{}", new_indent, &line[indent.len()..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retab_line() {
+        assert_eq!(leading_whitespace("  \tx", 4), ("  \t", 4));
+        assert_eq!(display_width("\tab\t", 4), 8);
+        assert_eq!(retab_line("\t\tfoo", 4, 4, true).as_deref(), Some("        foo"));
+        assert_eq!(retab_line("      foo", 4, 4, false).as_deref(), Some("\t  foo"));
+        assert_eq!(retab_line("\tfoo", 8, 4, false).as_deref(), Some("\t\tfoo"));
+        assert_eq!(retab_line("    foo", 4, 4, true), None);
+        assert_eq!(retab_line("", 4, 4, false), None);
+    }
+}
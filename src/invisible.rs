@@ -0,0 +1,88 @@
+//! Invisible and confusable characters for Quirks
+//!
+//! Zero-width characters, byte order marks, bidi controls and unusual
+//! spaces look like nothing (or like a plain space) on screen but change
+//! what code means. The view draws them in a warning style so they can be
+//! spotted in review, along with trailing whitespace and indents that mix
+//! tabs and spaces.
+
+use std::ops::Range;
+
+/// Whether `c` is invisible or easily mistaken for a plain space
+pub fn is_suspicious(c: char) -> bool {
+    is_zero_width(c) || is_odd_space(c)
+}
+
+/// Characters that take no space on screen: zero-width spaces and joiners,
+/// the byte order mark, soft hyphens and bidi controls
+pub fn is_zero_width(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}'                  // soft hyphen
+        | '\u{061C}'                // Arabic letter mark
+        | '\u{180E}'                // Mongolian vowel separator
+        | '\u{200B}'..='\u{200F}'   // zero-width space/joiners, LRM, RLM
+        | '\u{202A}'..='\u{202E}'   // bidi embeddings and overrides
+        | '\u{2060}'..='\u{2064}'   // word joiner, invisible operators
+        | '\u{2066}'..='\u{2069}'   // bidi isolates
+        | '\u{FEFF}'                // byte order mark
+    )
+}
+
+/// Spaces other than U+0020 that look like one
+fn is_odd_space(c: char) -> bool {
+    matches!(c, '\u{00A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}')
+}
+
+/// What to draw for a suspicious character: a visible mark for zero-width
+/// ones, which would otherwise not show up at all
+pub fn replacement(c: char) -> char {
+    if is_zero_width(c) { '¤' } else { '·' }
+}
+
+/// Number of suspicious characters in some text
+pub fn count(chars: impl Iterator<Item = char>) -> usize {
+    chars.filter(|&c| is_suspicious(c)).count()
+}
+
+/// Character columns of the whitespace at the end of `line`, if any
+pub fn trailing_whitespace(line: &str) -> Option<Range<usize>> {
+    let len = line.chars().count();
+    let trailing = line.chars().rev().take_while(|c| *c == ' ' || *c == '\t').count();
+    (trailing > 0).then(|| len - trailing..len)
+}
+
+/// Character columns of the indent of `line` if it has both tabs and
+/// spaces in it
+pub fn mixed_indent(line: &str) -> Option<Range<usize>> {
+    let indent: Vec<char> = line.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+    (indent.contains(&' ') && indent.contains(&'\t')).then_some(0..indent.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suspicious_characters() {
+        assert!(is_suspicious('\u{200B}'));
+        assert!(is_suspicious('\u{FEFF}'));
+        assert!(is_suspicious('\u{202E}'));
+        assert!(is_suspicious('\u{00A0}'));
+        assert!(!is_suspicious(' '));
+        assert!(!is_suspicious('ä'));
+        assert!(!is_suspicious('\t'));
+        assert_eq!(replacement('\u{200B}'), '¤');
+        assert_eq!(replacement('\u{00A0}'), '·');
+        assert_eq!(count("let\u{00A0}x = \"a\u{200B}b\";".chars()), 2);
+    }
+
+    #[test]
+    fn test_whitespace_warnings() {
+        assert_eq!(trailing_whitespace("fn main() { \t"), Some(11..13));
+        assert_eq!(trailing_whitespace("   "), Some(0..3));
+        assert_eq!(trailing_whitespace("x"), None);
+        assert_eq!(mixed_indent("\t  x"), Some(0..3));
+        assert_eq!(mixed_indent("    x \t"), None);
+        assert_eq!(mixed_indent("\t\tx"), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Background jobs for Quirks
+//!
+//! Runs a shell command without blocking the editor, collecting its
+//! stdout and stderr line by line for the editor to pick up on each tick.
+
+use crate::events::Waker;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A running (or just finished) shell command
+#[derive(Debug)]
+pub struct Job {
+    command: String,
+    child: Child,
+    lines: Receiver<String>,
+    started: Instant,
+}
+
+/// What a job produced since the last poll
+#[derive(Debug, Default)]
+pub struct JobUpdate {
+    /// New output lines, stdout and stderr interleaved
+    pub lines: Vec<String>,
+    /// Set once the job has exited and all its output was read
+    pub finished: Option<ExitStatus>,
+}
+
+impl Job {
+    /// Start `command` with `sh -c` in `dir`; each line of output wakes
+    /// the main loop with `waker`
+    pub fn spawn(command: &str, dir: &Path, waker: Waker) -> io::Result<Self> {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(command)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // A process group of its own, so cancelling reaches everything the
+        // shell started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        let mut child = cmd.spawn()?;
+        let (tx, rx) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward_lines(stdout, tx.clone(), waker.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward_lines(stderr, tx, waker);
+        }
+        Ok(Self {
+            command: command.to_string(),
+            child,
+            lines: rx,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Time since the job started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Collect output produced so far and check whether the job is done
+    pub fn poll(&mut self) -> JobUpdate {
+        let mut update = JobUpdate::default();
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => update.lines.push(line),
+                Err(TryRecvError::Empty) => return update,
+                // Both pipes are closed, so only the exit status is left
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        update.finished = self.child.try_wait().ok().flatten();
+        update
+    }
+
+    /// Kill the job and any processes it started
+    pub fn cancel(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: killpg only sends a signal; the group id is the child's
+        // pid, as set up in `spawn`
+        unsafe {
+            libc::killpg(self.child.id() as libc::pid_t, libc::SIGKILL);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Send each line read from `pipe` down `tx` on a background thread
+fn forward_lines(pipe: impl Read + Send + 'static, tx: Sender<String>, waker: Waker) {
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+            waker.wake();
+        }
+        drop(tx);
+        waker.wake();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Poll until the job finishes, collecting all output
+    fn run_to_end(job: &mut Job) -> (Vec<String>, ExitStatus) {
+        let mut lines = Vec::new();
+        let start = Instant::now();
+        loop {
+            let update = job.poll();
+            lines.extend(update.lines);
+            if let Some(status) = update.finished {
+                return (lines, status);
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "job did not finish");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_job_collects_output() {
+        let mut job = Job::spawn("echo one; echo two >&2; exit 3", Path::new("."), Waker::default()).unwrap();
+        let (mut lines, status) = run_to_end(&mut job);
+        lines.sort();
+        assert_eq!(lines, vec!["one", "two"]);
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_job_cancel() {
+        let mut job = Job::spawn("sleep 10", Path::new("."), Waker::default()).unwrap();
+        job.cancel();
+        let (lines, status) = run_to_end(&mut job);
+        assert!(lines.is_empty());
+        assert!(!status.success());
+    }
+}
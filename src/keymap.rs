@@ -1,9 +1,18 @@
 //! Custom keybinding system for Quirks
 //!
 //! Allows users to remap keys and define custom commands.
+//! Bindings may be single keys or multi-key sequences ("gd", "<leader>ff"),
+//! stored per mode in a trie so prefixes can wait for further input.
+//!
+//! A key is looked up in the keymap of the current mode first. Removing a
+//! user binding brings back the default one for the same keys, and a key
+//! bound in neither goes to the editor's built-in handler for the mode.
 
-use crossterm::event::{KeyCode, KeyModifiers};
+use crate::config::Config;
+use crate::mode::Mode;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::collections::HashMap;
+use std::fmt;
 
 /// A key combination (key + modifiers)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -14,7 +23,24 @@ pub struct KeyCombo {
 
 impl KeyCombo {
     pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
-        Self { code, modifiers }
+        Self { code, modifiers }.normalized()
+    }
+
+    /// Build a combo from a terminal key event
+    pub fn from_event(key: &KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+
+    /// Fold Shift into the character itself so "Shift+g", "G" and a
+    /// terminal-reported Shift+'G' all compare equal
+    fn normalized(mut self) -> Self {
+        if let KeyCode::Char(c) = self.code {
+            if self.modifiers.contains(KeyModifiers::SHIFT) {
+                self.code = KeyCode::Char(c.to_ascii_uppercase());
+                self.modifiers.remove(KeyModifiers::SHIFT);
+            }
+        }
+        self
     }
 
     pub fn plain(code: KeyCode) -> Self {
@@ -38,40 +64,85 @@ impl KeyCombo {
         let s = s.trim();
         let parts: Vec<&str> = s.split('+').collect();
         
+        // Every part but the last is a modifier; the last is the key itself
+        // (so "Ctrl+s" is Ctrl with 's', not Ctrl+Shift with no key)
+        let (key_part, modifier_parts) = parts.split_last()?;
         let mut modifiers = KeyModifiers::NONE;
-        let mut key_part = "";
         
-        for part in &parts {
-            let part = part.trim();
-            match part.to_lowercase().as_str() {
-                "ctrl" | "control" | "c" if parts.len() > 1 => {
-                    modifiers |= KeyModifiers::CONTROL;
-                }
-                "shift" | "s" if parts.len() > 1 => {
-                    modifiers |= KeyModifiers::SHIFT;
-                }
-                "alt" | "meta" | "m" | "a" if parts.len() > 1 => {
-                    modifiers |= KeyModifiers::ALT;
-                }
-                _ => {
-                    key_part = part;
-                }
+        for part in modifier_parts {
+            match part.trim().to_lowercase().as_str() {
+                "ctrl" | "control" | "c" => modifiers |= KeyModifiers::CONTROL,
+                "shift" | "s" => modifiers |= KeyModifiers::SHIFT,
+                "alt" | "meta" | "m" | "a" => modifiers |= KeyModifiers::ALT,
+                _ => return None,
             }
         }
         
-        let code = parse_key_code(key_part)?;
+        let code = parse_key_code(key_part.trim())?;
         Some(Self::new(code, modifiers))
     }
 }
 
+impl fmt::Display for KeyCombo {
+    /// Format in Vim notation: "g", "<C-s>", "<Esc>", "<A-Left>"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::Enter => "CR".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::Backspace => "BS".to_string(),
+            KeyCode::Delete => "Del".to_string(),
+            KeyCode::Insert => "Insert".to_string(),
+            KeyCode::Home => "Home".to_string(),
+            KeyCode::End => "End".to_string(),
+            KeyCode::PageUp => "PageUp".to_string(),
+            KeyCode::PageDown => "PageDown".to_string(),
+            KeyCode::Up => "Up".to_string(),
+            KeyCode::Down => "Down".to_string(),
+            KeyCode::Left => "Left".to_string(),
+            KeyCode::Right => "Right".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            other => format!("{:?}", other),
+        };
+
+        let mut prefix = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            prefix.push_str("C-");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            prefix.push_str("S-");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            prefix.push_str("A-");
+        }
+
+        let is_plain_char = matches!(self.code, KeyCode::Char(c) if c != ' ' && c != '<');
+        if prefix.is_empty() && is_plain_char {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "<{}{}>", prefix, name)
+        }
+    }
+}
+
 /// Parse a key code from string
 fn parse_key_code(s: &str) -> Option<KeyCode> {
+    // Single characters keep their case ("G" is not "g")
+    let mut chars = s.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    
     let s = s.to_lowercase();
     match s.as_str() {
         // Special keys
         "esc" | "escape" => Some(KeyCode::Esc),
         "enter" | "return" | "cr" => Some(KeyCode::Enter),
         "tab" => Some(KeyCode::Tab),
+        "backtab" => Some(KeyCode::BackTab),
         "backspace" | "bs" => Some(KeyCode::Backspace),
         "delete" | "del" => Some(KeyCode::Delete),
         "insert" | "ins" => Some(KeyCode::Insert),
@@ -99,15 +170,26 @@ fn parse_key_code(s: &str) -> Option<KeyCode> {
         "f11" => Some(KeyCode::F(11)),
         "f12" => Some(KeyCode::F(12)),
         
-        // Single characters
-        _ if s.len() == 1 => {
-            s.chars().next().map(KeyCode::Char)
-        }
-        
         _ => None,
     }
 }
 
+/// State typed ahead of a bound key: a count (`5`) and/or a register (`"a`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActionContext {
+    /// Numeric prefix, if one was typed
+    pub count: Option<usize>,
+    /// Register selected with `"x`, if any
+    pub register: Option<char>,
+}
+
+impl ActionContext {
+    /// The count to repeat an action with, defaulting to 1
+    pub fn count(&self) -> usize {
+        self.count.unwrap_or(1).max(1)
+    }
+}
+
 /// Actions that can be bound to keys
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
@@ -118,7 +200,12 @@ pub enum Action {
     MoveDown,
     MoveWordForward,
     MoveWordBackward,
+    MoveWordEnd,
+    MoveBigWordForward,
+    MoveBigWordBackward,
+    MoveBigWordEnd,
     MoveLineStart,
+    MoveFirstNonWhitespace,
     MoveLineEnd,
     MoveBufferStart,
     MoveBufferEnd,
@@ -134,6 +221,7 @@ pub enum Action {
     EnterCommand,
     EnterVisual,
     EnterVisualLine,
+    EnterVisualBlock,
     
     // Editing
     DeleteChar,
@@ -146,6 +234,11 @@ pub enum Action {
     NewLineBelow,
     NewLineAbove,
     JoinLines,
+    /// Move the line or the selected lines down or up one line
+    MoveLinesDown,
+    MoveLinesUp,
+    /// Add a cursor at the next occurrence of the word under the cursor
+    AddCursor,
     
     // Search
     SearchForward,
@@ -154,6 +247,25 @@ pub enum Action {
     PrevMatch,
     ClearSearch,
     
+    // Panels
+    ToggleExplorer,
+    FindFiles,
+    FindBuffers,
+    FindLines,
+    FindRecent,
+    
+    // Git
+    NextHunk,
+    PrevHunk,
+    
+    // Tags
+    JumpToTag,
+    PopTag,
+    
+    // Terminal
+    /// Send the visual selection or paragraph to the terminal / REPL
+    SendToTerminal,
+    
     // File operations
     Save,
     SaveAs,
@@ -163,34 +275,384 @@ pub enum Action {
     
     // Custom command
     Command(String),
+    /// Lua function mapped with quirks.keymap, by its index in the engine
+    Lua(usize),
     
     // No operation
     Noop,
 }
 
+impl Action {
+    /// Parse an action name as used in the config file ("write",
+    /// "enter_normal", "move_down"). A leading ':' makes an ex command;
+    /// a trailing `<CR>` is accepted so Vim mappings like ":w<CR>" work.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(cmd) = name.strip_prefix(':') {
+            let lower = cmd.to_lowercase();
+            let cmd = if lower.ends_with("<cr>") {
+                &cmd[..cmd.len() - 4]
+            } else if lower.ends_with("<enter>") {
+                &cmd[..cmd.len() - 7]
+            } else {
+                cmd
+            };
+            return Some(Action::Command(cmd.to_string()));
+        }
+        let action = match name.trim().to_lowercase().replace('-', "_").as_str() {
+            "move_left" => Action::MoveLeft,
+            "move_right" => Action::MoveRight,
+            "move_up" => Action::MoveUp,
+            "move_down" => Action::MoveDown,
+            "move_word_forward" => Action::MoveWordForward,
+            "move_word_backward" => Action::MoveWordBackward,
+            "move_word_end" => Action::MoveWordEnd,
+            "move_big_word_forward" => Action::MoveBigWordForward,
+            "move_big_word_backward" => Action::MoveBigWordBackward,
+            "move_big_word_end" => Action::MoveBigWordEnd,
+            "move_line_start" => Action::MoveLineStart,
+            "move_first_non_whitespace" => Action::MoveFirstNonWhitespace,
+            "move_line_end" => Action::MoveLineEnd,
+            "move_buffer_start" => Action::MoveBufferStart,
+            "move_buffer_end" => Action::MoveBufferEnd,
+            "page_up" => Action::PageUp,
+            "page_down" => Action::PageDown,
+            "enter_insert" => Action::EnterInsert,
+            "enter_insert_after" => Action::EnterInsertAfter,
+            "enter_insert_line_start" => Action::EnterInsertLineStart,
+            "enter_insert_line_end" => Action::EnterInsertLineEnd,
+            "enter_normal" => Action::EnterNormal,
+            "enter_command" => Action::EnterCommand,
+            "enter_visual" => Action::EnterVisual,
+            "enter_visual_line" => Action::EnterVisualLine,
+            "enter_visual_block" => Action::EnterVisualBlock,
+            "delete_char" => Action::DeleteChar,
+            "delete_line" => Action::DeleteLine,
+            "yank_line" => Action::YankLine,
+            "paste" => Action::Paste,
+            "paste_before" => Action::PasteBefore,
+            "undo" => Action::Undo,
+            "redo" => Action::Redo,
+            "new_line_below" => Action::NewLineBelow,
+            "new_line_above" => Action::NewLineAbove,
+            "join_lines" => Action::JoinLines,
+            "move_lines_down" => Action::MoveLinesDown,
+            "move_lines_up" => Action::MoveLinesUp,
+            "add_cursor" => Action::AddCursor,
+            "search_forward" => Action::SearchForward,
+            "search_backward" => Action::SearchBackward,
+            "next_match" => Action::NextMatch,
+            "prev_match" => Action::PrevMatch,
+            "clear_search" => Action::ClearSearch,
+            "toggle_explorer" | "explore" => Action::ToggleExplorer,
+            "find_files" => Action::FindFiles,
+            "find_buffers" => Action::FindBuffers,
+            "find_lines" => Action::FindLines,
+            "find_recent" | "oldfiles" => Action::FindRecent,
+            "next_hunk" => Action::NextHunk,
+            "prev_hunk" => Action::PrevHunk,
+            "jump_to_tag" | "tag" => Action::JumpToTag,
+            "pop_tag" => Action::PopTag,
+            "send_to_terminal" => Action::SendToTerminal,
+            "save" | "write" => Action::Save,
+            "save_as" => Action::SaveAs,
+            "quit" => Action::Quit,
+            "force_quit" => Action::ForceQuit,
+            "save_and_quit" | "write_quit" => Action::SaveAndQuit,
+            "noop" | "nop" => Action::Noop,
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+impl fmt::Display for Action {
+    /// Short human-readable description, used by the key hint popup
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::MoveWordForward => "Next word",
+            Action::MoveWordBackward => "Previous word",
+            Action::MoveWordEnd => "End of word",
+            Action::MoveBigWordForward => "Next WORD",
+            Action::MoveBigWordBackward => "Previous WORD",
+            Action::MoveBigWordEnd => "End of WORD",
+            Action::MoveLineStart => "Line start",
+            Action::MoveFirstNonWhitespace => "First non-blank",
+            Action::MoveLineEnd => "Line end",
+            Action::MoveBufferStart => "Buffer start",
+            Action::MoveBufferEnd => "Buffer end",
+            Action::PageUp => "Half page up",
+            Action::PageDown => "Half page down",
+            Action::EnterInsert => "Insert",
+            Action::EnterInsertAfter => "Append",
+            Action::EnterInsertLineStart => "Insert at line start",
+            Action::EnterInsertLineEnd => "Append at line end",
+            Action::EnterNormal => "Normal mode",
+            Action::EnterCommand => "Command line",
+            Action::EnterVisual => "Visual",
+            Action::EnterVisualLine => "Visual line",
+            Action::EnterVisualBlock => "Visual block",
+            Action::DeleteChar => "Delete character",
+            Action::DeleteLine => "Delete line",
+            Action::YankLine => "Yank line",
+            Action::Paste => "Paste after",
+            Action::PasteBefore => "Paste before",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::NewLineBelow => "Open line below",
+            Action::NewLineAbove => "Open line above",
+            Action::JoinLines => "Join lines",
+            Action::MoveLinesDown => "Move line down",
+            Action::MoveLinesUp => "Move line up",
+            Action::AddCursor => "Add cursor at next match",
+            Action::SearchForward => "Search forward",
+            Action::SearchBackward => "Search backward",
+            Action::NextMatch => "Next match",
+            Action::PrevMatch => "Previous match",
+            Action::ClearSearch => "Clear search",
+            Action::ToggleExplorer => "File explorer",
+            Action::FindFiles => "Find files",
+            Action::FindBuffers => "Find buffers",
+            Action::FindLines => "Find lines",
+            Action::FindRecent => "Recent files",
+            Action::NextHunk => "Next hunk",
+            Action::PrevHunk => "Previous hunk",
+            Action::JumpToTag => "Jump to tag",
+            Action::PopTag => "Back from tag",
+            Action::SendToTerminal => "Send to terminal",
+            Action::Save => "Save",
+            Action::SaveAs => "Save as",
+            Action::Quit => "Quit",
+            Action::ForceQuit => "Force quit",
+            Action::SaveAndQuit => "Save and quit",
+            Action::Command(cmd) => return write!(f, ":{}", cmd.trim_start_matches(':')),
+            Action::Lua(_) => "Lua function",
+            Action::Noop => "Nothing",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Parse a Vim-style key sequence like "gd", "<C-s>", "<leader>ff" or "jk"
+///
+/// `<leader>` expands to the given leader key.
+pub fn parse_key_sequence(s: &str, leader: KeyCombo) -> Option<Vec<KeyCombo>> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if let Some(key) = parse_angle_key(name, leader) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(KeyCombo::plain(KeyCode::Char(c)));
+        rest = &rest[c.len_utf8()..];
+    }
+    
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+/// Parse the inside of a `<...>` key notation (e.g. "C-s", "CR", "leader")
+fn parse_angle_key(name: &str, leader: KeyCombo) -> Option<KeyCombo> {
+    match name.to_lowercase().as_str() {
+        "leader" => return Some(leader),
+        "lt" => return Some(KeyCombo::plain(KeyCode::Char('<'))),
+        "bar" => return Some(KeyCombo::plain(KeyCode::Char('|'))),
+        "bslash" => return Some(KeyCombo::plain(KeyCode::Char('\\'))),
+        _ => {}
+    }
+    
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+        match rest.as_bytes()[0].to_ascii_lowercase() {
+            b'c' => modifiers |= KeyModifiers::CONTROL,
+            b's' => modifiers |= KeyModifiers::SHIFT,
+            b'a' | b'm' => modifiers |= KeyModifiers::ALT,
+            _ => return None,
+        }
+        rest = &rest[2..];
+    }
+    
+    let code = parse_key_code(rest)?;
+    // <C-S> and <C-s> are the same key in Vim
+    let code = match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+            KeyCode::Char(c.to_ascii_lowercase())
+        }
+        other => other,
+    };
+    Some(KeyCombo::new(code, modifiers))
+}
+
+/// Result of looking up a (possibly partial) key sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMatch<'a> {
+    /// No binding starts with these keys
+    None,
+    /// Longer bindings start with these keys; the action (if any) fires
+    /// when no further key arrives before the timeout
+    Pending(Option<&'a Action>),
+    /// The keys form a complete binding with no longer alternatives
+    Complete(&'a Action),
+}
+
+/// A node in the key sequence trie
+#[derive(Debug, Clone, Default)]
+struct KeyTrie {
+    action: Option<Action>,
+    children: HashMap<KeyCombo, KeyTrie>,
+}
+
+impl KeyTrie {
+    fn node(&self, keys: &[KeyCombo]) -> Option<&KeyTrie> {
+        keys.iter().try_fold(self, |node, key| node.children.get(key))
+    }
+
+    /// Number of bindings at or below this node
+    fn binding_count(&self) -> usize {
+        usize::from(self.action.is_some())
+            + self.children.values().map(KeyTrie::binding_count).sum::<usize>()
+    }
+
+    /// Remove the binding at `keys`, returning true if this node is now empty
+    fn remove(&mut self, keys: &[KeyCombo]) -> bool {
+        match keys.split_first() {
+            None => self.action = None,
+            Some((first, rest)) => {
+                if let Some(child) = self.children.get_mut(first) {
+                    if child.remove(rest) {
+                        self.children.remove(first);
+                    }
+                }
+            }
+        }
+        self.action.is_none() && self.children.is_empty()
+    }
+}
+
 /// Keymap for a specific mode
 #[derive(Debug, Clone)]
 pub struct ModeKeymap {
-    bindings: HashMap<KeyCombo, Action>,
+    root: KeyTrie,
 }
 
 impl ModeKeymap {
     pub fn new() -> Self {
         Self {
-            bindings: HashMap::new(),
+            root: KeyTrie::default(),
         }
     }
 
     pub fn bind(&mut self, key: KeyCombo, action: Action) {
-        self.bindings.insert(key, action);
+        self.bind_sequence(&[key], action);
     }
 
     pub fn unbind(&mut self, key: &KeyCombo) {
-        self.bindings.remove(key);
+        self.unbind_sequence(std::slice::from_ref(key));
     }
 
     pub fn get(&self, key: &KeyCombo) -> Option<&Action> {
-        self.bindings.get(key)
+        self.get_sequence(std::slice::from_ref(key))
+    }
+
+    /// Bind a sequence of keys to an action
+    pub fn bind_sequence(&mut self, keys: &[KeyCombo], action: Action) {
+        if keys.is_empty() {
+            return;
+        }
+        let node = keys.iter().fold(&mut self.root, |node, key| {
+            node.children.entry(*key).or_default()
+        });
+        node.action = Some(action);
+    }
+
+    /// Remove the binding for a key sequence (longer bindings are kept)
+    pub fn unbind_sequence(&mut self, keys: &[KeyCombo]) {
+        if !keys.is_empty() {
+            self.root.remove(keys);
+        }
+    }
+
+    /// Get the action bound to exactly this key sequence
+    pub fn get_sequence(&self, keys: &[KeyCombo]) -> Option<&Action> {
+        self.root.node(keys)?.action.as_ref()
+    }
+
+    /// Look up keys typed so far, distinguishing complete and partial matches
+    pub fn lookup_sequence(&self, keys: &[KeyCombo]) -> KeyMatch<'_> {
+        match self.root.node(keys) {
+            Some(node) if !node.children.is_empty() => KeyMatch::Pending(node.action.as_ref()),
+            Some(node) => node.action.as_ref().map_or(KeyMatch::None, KeyMatch::Complete),
+            None => KeyMatch::None,
+        }
+    }
+
+    /// List the keys that can follow `prefix`, with a description of each.
+    ///
+    /// Keys that only lead to longer sequences are described as "+N more".
+    pub fn continuations(&self, prefix: &[KeyCombo]) -> Vec<(KeyCombo, String)> {
+        let Some(node) = self.root.node(prefix) else { return Vec::new() };
+        let mut hints: Vec<(KeyCombo, String)> = node.children.iter()
+            .map(|(key, child)| {
+                let desc = match &child.action {
+                    Some(action) => action.to_string(),
+                    None => format!("+{} more", child.binding_count()),
+                };
+                (*key, desc)
+            })
+            .collect();
+        hints.sort_by_key(|(key, _)| key.to_string());
+        hints
+    }
+
+    /// Every binding starting with `prefix`, sorted by keys
+    pub fn bindings(&self, prefix: &[KeyCombo]) -> Vec<(Vec<KeyCombo>, &Action)> {
+        fn collect<'a>(node: &'a KeyTrie, keys: &mut Vec<KeyCombo>, out: &mut Vec<(Vec<KeyCombo>, &'a Action)>) {
+            if let Some(action) = &node.action {
+                out.push((keys.clone(), action));
+            }
+            for (key, child) in &node.children {
+                keys.push(*key);
+                collect(child, keys, out);
+                keys.pop();
+            }
+        }
+        let mut out = Vec::new();
+        if let Some(node) = self.root.node(prefix) {
+            collect(node, &mut prefix.to_vec(), &mut out);
+        }
+        out.sort_by_cached_key(|(keys, _)| keys.iter().map(KeyCombo::to_string).collect::<String>());
+        out
+    }
+
+    /// Find the longest prefix of `keys` that is bound to an action
+    pub fn longest_match(&self, keys: &[KeyCombo]) -> Option<(usize, &Action)> {
+        let mut node = &self.root;
+        let mut best = None;
+        for (i, key) in keys.iter().enumerate() {
+            match node.children.get(key) {
+                Some(child) => node = child,
+                None => break,
+            }
+            if let Some(action) = &node.action {
+                best = Some((i + 1, action));
+            }
+        }
+        best
     }
 
     pub fn with_defaults_normal() -> Self {
@@ -207,9 +669,13 @@ impl ModeKeymap {
         km.bind(KeyCombo::plain(KeyCode::Right), Action::MoveRight);
         km.bind(KeyCombo::plain(KeyCode::Char('w')), Action::MoveWordForward);
         km.bind(KeyCombo::plain(KeyCode::Char('b')), Action::MoveWordBackward);
+        km.bind(KeyCombo::plain(KeyCode::Char('e')), Action::MoveWordEnd);
+        km.bind(KeyCombo::shift(KeyCode::Char('W')), Action::MoveBigWordForward);
+        km.bind(KeyCombo::shift(KeyCode::Char('B')), Action::MoveBigWordBackward);
+        km.bind(KeyCombo::shift(KeyCode::Char('E')), Action::MoveBigWordEnd);
         km.bind(KeyCombo::plain(KeyCode::Char('0')), Action::MoveLineStart);
+        km.bind(KeyCombo::plain(KeyCode::Char('^')), Action::MoveFirstNonWhitespace);
         km.bind(KeyCombo::plain(KeyCode::Char('$')), Action::MoveLineEnd);
-        km.bind(KeyCombo::plain(KeyCode::Char('g')), Action::MoveBufferStart);
         km.bind(KeyCombo::shift(KeyCode::Char('G')), Action::MoveBufferEnd);
         km.bind(KeyCombo::ctrl(KeyCode::Char('u')), Action::PageUp);
         km.bind(KeyCombo::ctrl(KeyCode::Char('d')), Action::PageDown);
@@ -222,6 +688,7 @@ impl ModeKeymap {
         km.bind(KeyCombo::plain(KeyCode::Char(':')), Action::EnterCommand);
         km.bind(KeyCombo::plain(KeyCode::Char('v')), Action::EnterVisual);
         km.bind(KeyCombo::shift(KeyCode::Char('V')), Action::EnterVisualLine);
+        km.bind(KeyCombo::ctrl(KeyCode::Char('v')), Action::EnterVisualBlock);
         
         // Editing
         km.bind(KeyCombo::plain(KeyCode::Char('x')), Action::DeleteChar);
@@ -232,6 +699,9 @@ impl ModeKeymap {
         km.bind(KeyCombo::plain(KeyCode::Char('o')), Action::NewLineBelow);
         km.bind(KeyCombo::shift(KeyCode::Char('O')), Action::NewLineAbove);
         km.bind(KeyCombo::shift(KeyCode::Char('J')), Action::JoinLines);
+        km.bind(KeyCombo::alt(KeyCode::Char('j')), Action::MoveLinesDown);
+        km.bind(KeyCombo::alt(KeyCode::Char('k')), Action::MoveLinesUp);
+        km.bind(KeyCombo::ctrl(KeyCode::Char('n')), Action::AddCursor);
         
         // Search
         km.bind(KeyCombo::plain(KeyCode::Char('/')), Action::SearchForward);
@@ -239,6 +709,52 @@ impl ModeKeymap {
         km.bind(KeyCombo::plain(KeyCode::Char('n')), Action::NextMatch);
         km.bind(KeyCombo::shift(KeyCode::Char('N')), Action::PrevMatch);
         
+        // Tags
+        km.bind(KeyCombo::ctrl(KeyCode::Char(']')), Action::JumpToTag);
+        // Legacy terminals send Ctrl-] as 0x1D, which crossterm reports as Ctrl-5
+        km.bind(KeyCombo::ctrl(KeyCode::Char('5')), Action::JumpToTag);
+        km.bind(KeyCombo::ctrl(KeyCode::Char('t')), Action::PopTag);
+        
+        // Git hunks
+        km.bind_sequence(&[KeyCombo::plain(KeyCode::Char(']')), KeyCombo::plain(KeyCode::Char('c'))], Action::NextHunk);
+        km.bind_sequence(&[KeyCombo::plain(KeyCode::Char('[')), KeyCombo::plain(KeyCode::Char('c'))], Action::PrevHunk);
+        
+        km
+    }
+
+    pub fn with_defaults_visual() -> Self {
+        let mut km = Self::new();
+        
+        // Movement extends the selection
+        km.bind(KeyCombo::plain(KeyCode::Char('h')), Action::MoveLeft);
+        km.bind(KeyCombo::plain(KeyCode::Char('j')), Action::MoveDown);
+        km.bind(KeyCombo::plain(KeyCode::Char('k')), Action::MoveUp);
+        km.bind(KeyCombo::plain(KeyCode::Char('l')), Action::MoveRight);
+        km.bind(KeyCombo::plain(KeyCode::Left), Action::MoveLeft);
+        km.bind(KeyCombo::plain(KeyCode::Down), Action::MoveDown);
+        km.bind(KeyCombo::plain(KeyCode::Up), Action::MoveUp);
+        km.bind(KeyCombo::plain(KeyCode::Right), Action::MoveRight);
+        km.bind(KeyCombo::plain(KeyCode::Char('w')), Action::MoveWordForward);
+        km.bind(KeyCombo::plain(KeyCode::Char('b')), Action::MoveWordBackward);
+        km.bind(KeyCombo::plain(KeyCode::Char('e')), Action::MoveWordEnd);
+        km.bind(KeyCombo::shift(KeyCode::Char('W')), Action::MoveBigWordForward);
+        km.bind(KeyCombo::shift(KeyCode::Char('B')), Action::MoveBigWordBackward);
+        km.bind(KeyCombo::shift(KeyCode::Char('E')), Action::MoveBigWordEnd);
+        km.bind(KeyCombo::plain(KeyCode::Char('0')), Action::MoveLineStart);
+        km.bind(KeyCombo::plain(KeyCode::Char('^')), Action::MoveFirstNonWhitespace);
+        km.bind(KeyCombo::plain(KeyCode::Char('$')), Action::MoveLineEnd);
+        km.bind(KeyCombo::shift(KeyCode::Char('G')), Action::MoveBufferEnd);
+        km.bind(KeyCombo::alt(KeyCode::Char('j')), Action::MoveLinesDown);
+        km.bind(KeyCombo::alt(KeyCode::Char('k')), Action::MoveLinesUp);
+
+        // ':' starts a command on the selected lines, '/' and '?' a
+        // search inside the selection
+        km.bind(KeyCombo::plain(KeyCode::Char(':')), Action::EnterCommand);
+        km.bind(KeyCombo::plain(KeyCode::Char('/')), Action::SearchForward);
+        km.bind(KeyCombo::plain(KeyCode::Char('?')), Action::SearchBackward);
+        
+        km.bind(KeyCombo::plain(KeyCode::Esc), Action::EnterNormal);
+        
         km
     }
 
@@ -258,9 +774,14 @@ impl Default for ModeKeymap {
     }
 }
 
+/// Default leader key (Vim's backslash)
+pub const DEFAULT_LEADER: char = '\\';
+
 /// Complete keymap with all modes
 #[derive(Debug, Clone)]
 pub struct Keymap {
+    /// Key that `<leader>` expands to in sequences
+    pub leader: KeyCombo,
     pub normal: ModeKeymap,
     pub insert: ModeKeymap,
     pub visual: ModeKeymap,
@@ -269,12 +790,145 @@ pub struct Keymap {
 
 impl Default for Keymap {
     fn default() -> Self {
-        Self {
+        let mut keymap = Self {
+            leader: KeyCombo::plain(KeyCode::Char(DEFAULT_LEADER)),
             normal: ModeKeymap::with_defaults_normal(),
             insert: ModeKeymap::with_defaults_insert(),
-            visual: ModeKeymap::with_defaults_normal(), // Visual uses similar bindings
+            visual: ModeKeymap::with_defaults_visual(),
             command: ModeKeymap::new(),
+        };
+        for (mode, keys, action) in leader_defaults(keymap.leader) {
+            if let Some(km) = keymap.for_mode_mut(mode) {
+                km.bind_sequence(&keys, action);
+            }
         }
+        keymap
+    }
+}
+
+/// Default bindings that start with the leader key
+fn leader_defaults(leader: KeyCombo) -> Vec<(Mode, Vec<KeyCombo>, Action)> {
+    [
+        (Mode::Normal, "<leader>e", Action::ToggleExplorer),
+        (Mode::Normal, "<leader>ff", Action::FindFiles),
+        (Mode::Normal, "<leader>fb", Action::FindBuffers),
+        (Mode::Normal, "<leader>/", Action::FindLines),
+        (Mode::Normal, "<leader>fr", Action::FindRecent),
+        (Mode::Normal, "<leader>s", Action::SendToTerminal),
+        (Mode::Visual, "<leader>s", Action::SendToTerminal),
+    ]
+    .into_iter()
+    .filter_map(|(mode, keys, action)| Some((mode, parse_key_sequence(keys, leader)?, action)))
+    .collect()
+}
+
+impl Keymap {
+    /// Get the keymap used for a given editing mode
+    pub fn for_mode(&self, mode: Mode) -> Option<&ModeKeymap> {
+        match mode {
+            Mode::Normal => Some(&self.normal),
+            Mode::Insert => Some(&self.insert),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => Some(&self.visual),
+            Mode::Command => Some(&self.command),
+            Mode::Search | Mode::Explorer | Mode::Picker | Mode::Terminal => None,
+        }
+    }
+
+    /// Get the mutable keymap used for a given editing mode
+    pub fn for_mode_mut(&mut self, mode: Mode) -> Option<&mut ModeKeymap> {
+        match mode {
+            Mode::Normal => Some(&mut self.normal),
+            Mode::Insert => Some(&mut self.insert),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => Some(&mut self.visual),
+            Mode::Command => Some(&mut self.command),
+            Mode::Search | Mode::Explorer | Mode::Picker | Mode::Terminal => None,
+        }
+    }
+
+    /// Look up the action bound to a key event in the given mode
+    pub fn lookup(&self, mode: Mode, key: &KeyEvent) -> Option<&Action> {
+        self.for_mode(mode)?.get(&KeyCombo::from_event(key))
+    }
+
+    /// Bind a Vim-style key sequence (e.g. "<leader>w") in the given mode
+    pub fn map(&mut self, mode: Mode, keys: &str, action: Action) -> Result<(), String> {
+        let sequence = parse_key_sequence(keys, self.leader)
+            .ok_or_else(|| format!("Invalid key sequence: {}", keys))?;
+        let keymap = self.for_mode_mut(mode)
+            .ok_or_else(|| format!("Mode {} has no keymap", mode.display()))?;
+        keymap.bind_sequence(&sequence, action);
+        Ok(())
+    }
+
+    /// Remove the binding of a key sequence in the given mode. A default
+    /// binding for the same keys takes its place again; returns it if so.
+    pub fn unmap(&mut self, mode: Mode, keys: &str) -> Result<Option<Action>, String> {
+        let sequence = parse_key_sequence(keys, self.leader)
+            .ok_or_else(|| format!("Invalid key sequence: {}", keys))?;
+        let mut defaults = Keymap::default();
+        defaults.set_leader(self.leader);
+        let default = defaults.for_mode(mode).and_then(|km| km.get_sequence(&sequence)).cloned();
+        let keymap = self.for_mode_mut(mode)
+            .ok_or_else(|| format!("Mode {} has no keymap", mode.display()))?;
+        match keymap.get_sequence(&sequence) {
+            None => return Err(format!("No mapping for {}", keys)),
+            Some(action) if Some(action) == default.as_ref() => return Err(format!("{} is a default binding", keys)),
+            Some(_) => {}
+        }
+        keymap.unbind_sequence(&sequence);
+        if let Some(action) = &default {
+            keymap.bind_sequence(&sequence, action.clone());
+        }
+        Ok(default)
+    }
+
+    /// Change the leader key, moving the default leader bindings with it
+    pub fn set_leader(&mut self, leader: KeyCombo) {
+        let old = leader_defaults(self.leader);
+        let new = leader_defaults(leader);
+        for ((mode, old_keys, action), (_, new_keys, _)) in old.into_iter().zip(new) {
+            let Some(km) = self.for_mode_mut(mode) else { continue };
+            if km.get_sequence(&old_keys) == Some(&action) {
+                km.unbind_sequence(&old_keys);
+                km.bind_sequence(&new_keys, action);
+            }
+        }
+        self.leader = leader;
+    }
+
+    /// Apply the leader and `[keymaps.<mode>]` bindings from the config.
+    ///
+    /// Invalid entries are skipped; a description of each is returned.
+    pub fn apply_config(&mut self, config: &Config) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(leader) = &config.leader {
+            match parse_key_sequence(leader, self.leader).as_deref() {
+                Some([key]) => self.set_leader(*key),
+                _ => errors.push(format!("invalid leader '{}'", leader)),
+            }
+        }
+
+        for binding in &config.keymaps {
+            let mode = match binding.mode.to_lowercase().as_str() {
+                "normal" => Mode::Normal,
+                "insert" => Mode::Insert,
+                "visual" => Mode::Visual,
+                _ => {
+                    errors.push(format!("line {}: unknown mode '{}'", binding.line, binding.mode));
+                    continue;
+                }
+            };
+            let Some(action) = Action::from_name(&binding.action) else {
+                errors.push(format!("line {}: unknown action '{}'", binding.line, binding.action));
+                continue;
+            };
+            if let Err(e) = self.map(mode, &binding.keys, action) {
+                errors.push(format!("line {}: {}", binding.line, e));
+            }
+        }
+
+        errors
     }
 }
 
@@ -316,6 +970,60 @@ mod tests {
         assert_eq!(action, Some(&Action::MoveDown));
     }
 
+    #[test]
+    fn test_key_combo_shift_normalized() {
+        assert_eq!(KeyCombo::shift(KeyCode::Char('G')), KeyCombo::plain(KeyCode::Char('G')));
+        assert_eq!(KeyCombo::parse("Shift+g").unwrap(), KeyCombo::plain(KeyCode::Char('G')));
+    }
+
+    #[test]
+    fn test_keymap_lookup_by_mode() {
+        let keymap = Keymap::default();
+        let key = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(keymap.lookup(Mode::Normal, &key), Some(&Action::DeleteChar));
+        assert_eq!(keymap.lookup(Mode::Visual, &key), None);
+        assert_eq!(keymap.lookup(Mode::Search, &key), None);
+    }
+
+    #[test]
+    fn test_parse_key_sequence() {
+        let leader = KeyCombo::plain(KeyCode::Char(' '));
+        let seq = parse_key_sequence("<leader>ff", leader).unwrap();
+        assert_eq!(seq, vec![
+            leader,
+            KeyCombo::plain(KeyCode::Char('f')),
+            KeyCombo::plain(KeyCode::Char('f')),
+        ]);
+        
+        let seq = parse_key_sequence("<C-s><CR>", leader).unwrap();
+        assert_eq!(seq, vec![
+            KeyCombo::ctrl(KeyCode::Char('s')),
+            KeyCombo::plain(KeyCode::Enter),
+        ]);
+        
+        assert!(parse_key_sequence("", leader).is_none());
+    }
+
+    #[test]
+    fn test_sequence_lookup() {
+        let mut km = ModeKeymap::new();
+        let g = KeyCombo::plain(KeyCode::Char('g'));
+        let d = KeyCombo::plain(KeyCode::Char('d'));
+        km.bind_sequence(&[g, d], Action::Save);
+        
+        assert_eq!(km.lookup_sequence(&[g]), KeyMatch::Pending(None));
+        assert_eq!(km.lookup_sequence(&[g, d]), KeyMatch::Complete(&Action::Save));
+        assert_eq!(km.lookup_sequence(&[d]), KeyMatch::None);
+        
+        // A bound prefix of a longer binding waits for the timeout
+        km.bind(g, Action::MoveBufferStart);
+        assert_eq!(km.lookup_sequence(&[g]), KeyMatch::Pending(Some(&Action::MoveBufferStart)));
+        assert_eq!(km.longest_match(&[g, g]), Some((1, &Action::MoveBufferStart)));
+        
+        km.unbind_sequence(&[g, d]);
+        assert_eq!(km.lookup_sequence(&[g]), KeyMatch::Complete(&Action::MoveBufferStart));
+    }
+
     #[test]
     fn test_mode_keymap_custom() {
         let mut km = ModeKeymap::new();
@@ -324,4 +1032,110 @@ mod tests {
         let action = km.get(&KeyCombo::ctrl(KeyCode::Char('s')));
         assert_eq!(action, Some(&Action::Save));
     }
+
+    #[test]
+    fn test_action_context_count() {
+        assert_eq!(ActionContext::default().count(), 1);
+        let ctx = ActionContext { count: Some(5), register: Some('a') };
+        assert_eq!(ctx.count(), 5);
+    }
+
+    #[test]
+    fn test_key_combo_display() {
+        assert_eq!(KeyCombo::plain(KeyCode::Char('g')).to_string(), "g");
+        assert_eq!(KeyCombo::ctrl(KeyCode::Char('s')).to_string(), "<C-s>");
+        assert_eq!(KeyCombo::plain(KeyCode::Char(' ')).to_string(), "<Space>");
+        assert_eq!(KeyCombo::plain(KeyCode::Esc).to_string(), "<Esc>");
+    }
+
+    #[test]
+    fn test_key_notation_round_trip() {
+        // Recorded macros are stored this way and parsed back to replay
+        let keys = vec![
+            KeyCombo::plain(KeyCode::Char('<')),
+            KeyCombo::plain(KeyCode::Char('>')),
+            KeyCombo::ctrl(KeyCode::Char('r')),
+            KeyCombo::alt(KeyCode::Left),
+            KeyCombo::shift(KeyCode::BackTab),
+            KeyCombo::plain(KeyCode::Enter),
+            KeyCombo::plain(KeyCode::F(5)),
+            KeyCombo::plain(KeyCode::Char('Q')),
+        ];
+        let text: String = keys.iter().map(KeyCombo::to_string).collect();
+        assert_eq!(text, "<lt>><C-r><A-Left><S-BackTab><CR><F5>Q");
+        assert_eq!(parse_key_sequence(&text, KeyCombo::plain(KeyCode::Char('\\'))), Some(keys));
+    }
+
+    #[test]
+    fn test_continuations() {
+        let leader = KeyCombo::plain(KeyCode::Char(' '));
+        let mut km = ModeKeymap::new();
+        let seq = |s| parse_key_sequence(s, leader).unwrap();
+        km.bind_sequence(&seq("<leader>w"), Action::Save);
+        km.bind_sequence(&seq("<leader>ff"), Action::Command("find".to_string()));
+        km.bind_sequence(&seq("<leader>fg"), Action::Command("grep".to_string()));
+
+        let hints = km.continuations(&seq("<leader>"));
+        assert_eq!(hints.len(), 2);
+        assert_eq!(hints[0], (KeyCombo::plain(KeyCode::Char('f')), "+2 more".to_string()));
+        assert_eq!(hints[1], (KeyCombo::plain(KeyCode::Char('w')), "Save".to_string()));
+        assert!(km.continuations(&seq("x")).is_empty());
+    }
+
+    #[test]
+    fn test_unmap_restores_default() {
+        let mut keymap = Keymap::default();
+        keymap.map(Mode::Normal, "x", Action::Undo).unwrap();
+        keymap.map(Mode::Normal, "Q", Action::DeleteLine).unwrap();
+        assert_eq!(keymap.unmap(Mode::Normal, "x"), Ok(Some(Action::DeleteChar)));
+        assert_eq!(keymap.normal.get(&KeyCombo::plain(KeyCode::Char('x'))), Some(&Action::DeleteChar));
+        assert_eq!(keymap.unmap(Mode::Normal, "Q"), Ok(None));
+        assert!(keymap.unmap(Mode::Normal, "Q").is_err());
+        assert!(keymap.unmap(Mode::Normal, "x").is_err());
+
+        let seq = |s| parse_key_sequence(s, keymap.leader).unwrap();
+        let leader = keymap.normal.bindings(&seq("<leader>f"));
+        let keys: Vec<String> = leader.iter().map(|(keys, _)| keys.iter().map(KeyCombo::to_string).collect()).collect();
+        assert_eq!(keys, ["\\fb", "\\ff", "\\fr"]);
+    }
+
+    #[test]
+    fn test_apply_config() {
+        use crate::config::KeyBinding;
+        let binding = |mode: &str, keys: &str, action: &str, line| KeyBinding {
+            mode: mode.to_string(),
+            keys: keys.to_string(),
+            action: action.to_string(),
+            line,
+        };
+        let config = Config {
+            leader: Some("<Space>".to_string()),
+            keymaps: vec![
+                binding("normal", "<C-s>", "write", 1),
+                binding("insert", "jk", "enter_normal", 2),
+                binding("normal", "<leader>q", ":q!", 3),
+                binding("normal", "x", "explode", 4),
+                binding("replace", "x", "undo", 5),
+            ],
+            ..Config::default()
+        };
+
+        let mut keymap = Keymap::default();
+        let errors = keymap.apply_config(&config);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("line 4"));
+        assert!(errors[1].contains("unknown mode"));
+        assert_eq!(Action::from_name(":w<CR>"), Some(Action::Command("w".to_string())));
+
+        let seq = |s| parse_key_sequence(s, keymap.leader).unwrap();
+        // Default leader bindings follow the new leader
+        assert_eq!(keymap.normal.get_sequence(&seq("<Space>e")), Some(&Action::ToggleExplorer));
+        assert_eq!(keymap.normal.get_sequence(&seq("\\e")), None);
+        assert_eq!(keymap.normal.get_sequence(&seq("<C-s>")), Some(&Action::Save));
+        assert_eq!(keymap.insert.get_sequence(&seq("jk")), Some(&Action::EnterNormal));
+        assert_eq!(
+            keymap.normal.get_sequence(&seq("<Space>q")),
+            Some(&Action::Command("q!".to_string()))
+        );
+    }
 }
@@ -0,0 +1,70 @@
+//! Quirks - A modern text editor
+//!
+//! The editor core as a library: buffers, modes, search, registers and ex
+//! commands, driven through `Editor::handle_key` and
+//! `Editor::execute_command` without a terminal. `Snapshot` describes the
+//! screen for frontends and tests; `view` draws it with ratatui.
+
+// Several modules expose API ahead of the editor wiring it up.
+#![allow(dead_code)]
+
+pub mod bookmark;
+pub mod buffer;
+pub mod buffer_manager;
+pub mod charinfo;
+pub mod clipboard;
+pub mod complete;
+pub mod config;
+pub mod cursor;
+pub mod decoration;
+pub mod diff;
+pub mod editor;
+pub mod error;
+pub mod events;
+pub mod explorer;
+pub mod format;
+pub mod git;
+pub mod gpu_info;
+pub mod help;
+pub mod history;
+pub mod increment;
+pub mod indent;
+pub mod invisible;
+pub mod jobs;
+pub mod keymap;
+pub mod log;
+pub mod lua;
+pub mod mark;
+pub mod matchpair;
+pub mod mode;
+pub mod operator;
+pub mod perf;
+pub mod picker;
+pub mod project;
+pub mod prompt;
+pub mod quickfix;
+pub mod recent;
+pub mod register;
+pub mod remote;
+pub mod script;
+pub mod search;
+pub mod selection;
+pub mod shada;
+pub mod substitute;
+pub mod snapshot;
+pub mod syntax;
+pub mod table;
+pub mod tags;
+pub mod template;
+pub mod terminal;
+pub mod textobject;
+pub mod theme;
+pub mod trust;
+pub mod view;
+pub mod wrap;
+
+pub use config::Config;
+pub use editor::Editor;
+pub use error::QuirksError;
+pub use mode::Mode;
+pub use snapshot::Snapshot;
@@ -0,0 +1,155 @@
+//! Logging for Quirks
+//!
+//! Anything printed to stderr would end up in the middle of the TUI, so
+//! diagnostics go here instead: they are kept in memory for `:messages`
+//! and appended to ~/.local/state/quirks/quirks.log. Info entries, such as
+//! what a command showed, only stay in memory.
+
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries kept in memory for :messages
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+        }
+    }
+}
+
+/// One logged message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub level: Level,
+    pub message: String,
+}
+
+impl Entry {
+    /// "2026-10-17 12:34:56 WARN message", times in UTC
+    pub fn format(&self) -> String {
+        let (days, secs) = (self.time / 86400, self.time % 86400);
+        let (year, month, day) = civil_date(days as i64);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {} {}",
+            year, month, day, secs / 3600, secs / 60 % 60, secs % 60,
+            self.level.name(), self.message,
+        )
+    }
+}
+
+/// Recent entries and the file they are appended to
+#[derive(Debug, Default)]
+struct Log {
+    entries: VecDeque<Entry>,
+    file: Option<PathBuf>,
+}
+
+impl Log {
+    fn push(&mut self, entry: Entry) {
+        // A log that cannot be written is not worth interrupting anyone for
+        if let Some(path) = self.file.as_ref().filter(|_| entry.level <= Level::Warn) {
+            let _ = path.parent().map(fs::create_dir_all);
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", entry.format());
+            }
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+fn global() -> &'static Mutex<Log> {
+    static LOG: OnceLock<Mutex<Log>> = OnceLock::new();
+    LOG.get_or_init(|| {
+        let dir = dirs::state_dir().or_else(dirs::data_local_dir);
+        // Tests only keep entries in memory
+        let file = dir.filter(|_| !cfg!(test)).map(|dir| dir.join("quirks").join("quirks.log"));
+        Mutex::new(Log { entries: VecDeque::new(), file })
+    })
+}
+
+/// Add a message to the log
+pub fn log(level: Level, message: impl Into<String>) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let entry = Entry { time, level, message: message.into() };
+    global().lock().unwrap_or_else(|e| e.into_inner()).push(entry);
+}
+
+pub fn error(message: impl Into<String>) {
+    log(Level::Error, message);
+}
+
+pub fn warn(message: impl Into<String>) {
+    log(Level::Warn, message);
+}
+
+pub fn info(message: impl Into<String>) {
+    log(Level::Info, message);
+}
+
+/// Entries logged this session, oldest first
+pub fn entries() -> Vec<Entry> {
+    global().lock().unwrap_or_else(|e| e.into_inner()).entries.iter().cloned().collect()
+}
+
+/// Path of the log file, if there is one
+pub fn file() -> Option<PathBuf> {
+    global().lock().unwrap_or_else(|e| e.into_inner()).file.clone()
+}
+
+/// Year, month and day of a day count since 1970-01-01
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_format() {
+        let entry = Entry { time: 1_792_240_496, level: Level::Warn, message: "slow".to_string() };
+        assert_eq!(entry.format(), "2026-10-17 12:34:56 WARN slow");
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn test_log_keeps_entries() {
+        error("first");
+        info("second");
+        let entries = entries();
+        let messages: Vec<&str> = entries.iter().map(|e| e.message.as_str()).collect();
+        assert!(messages.ends_with(&["first", "second"]));
+        assert!(file().is_none());
+    }
+}
@@ -1,12 +1,17 @@
 //! Language Server Protocol client foundation for Quirks
 //!
-//! Provides basic LSP communication infrastructure.
-//! Full implementation will require async runtime and JSON-RPC.
+//! Provides basic LSP communication infrastructure. Messages from the
+//! server are read on a thread of their own and wake the main loop, so a
+//! slow server never holds up the editor; `poll` collects them.
 
+use crate::error::{QuirksError, Result};
+use crate::events::Waker;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::{Child, Command, Stdio};
-use std::io::{BufReader, BufWriter, Write, BufRead};
+use std::io::{self, BufReader, Write, BufRead, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
 /// LSP server configuration
 #[derive(Debug, Clone)]
@@ -185,6 +190,8 @@ pub struct LspClient {
     process: Option<Child>,
     request_id: i64,
     initialized: bool,
+    /// Messages the reader thread took from the server's output
+    messages: Option<Receiver<String>>,
 }
 
 impl LspClient {
@@ -194,19 +201,33 @@ impl LspClient {
             process: None,
             request_id: 0,
             initialized: false,
+            messages: None,
         }
     }
 
-    /// Start the LSP server process
-    pub fn start(&mut self) -> Result<(), String> {
-        let child = Command::new(&self.config.command)
+    /// Start the LSP server process; `waker` is woken for each message
+    pub fn start(&mut self, waker: Waker) -> Result<()> {
+        let mut child = Command::new(&self.config.command)
             .args(&self.config.args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .map_err(|e| format!("Failed to start LSP server: {}", e))?;
-        
+            .map_err(QuirksError::lsp("start"))?;
+
+        if let Some(stdout) = child.stdout.take() {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stdout);
+                while let Ok(Some(message)) = read_message(&mut reader) {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                    waker.wake();
+                }
+            });
+            self.messages = Some(rx);
+        }
         self.process = Some(child);
         Ok(())
     }
@@ -216,9 +237,16 @@ impl LspClient {
         if let Some(mut process) = self.process.take() {
             let _ = process.kill();
         }
+        self.messages = None;
         self.initialized = false;
     }
 
+    /// Messages (JSON-RPC responses and notifications) received since the
+    /// last call
+    pub fn poll(&mut self) -> Vec<String> {
+        self.messages.as_ref().map_or_else(Vec::new, |rx| rx.try_iter().collect())
+    }
+
     /// Check if the server is running
     pub fn is_running(&self) -> bool {
         self.process.is_some()
@@ -247,12 +275,10 @@ impl LspClient {
     }
 
     /// Send a message to the server (with Content-Length header)
-    fn send_message(&mut self, content: &str) -> Result<(), String> {
-        let process = self.process.as_mut()
-            .ok_or("LSP server not running")?;
-        
-        let stdin = process.stdin.as_mut()
-            .ok_or("Failed to get stdin")?;
+    fn send_message(&mut self, content: &str) -> Result<()> {
+        let stdin = self.process.as_mut()
+            .and_then(|process| process.stdin.as_mut())
+            .ok_or(QuirksError::LspNotRunning)?;
         
         let message = format!(
             "Content-Length: {}\r\n\r\n{}",
@@ -261,15 +287,15 @@ impl LspClient {
         );
         
         stdin.write_all(message.as_bytes())
-            .map_err(|e| format!("Failed to write to LSP: {}", e))?;
+            .map_err(QuirksError::lsp("write"))?;
         stdin.flush()
-            .map_err(|e| format!("Failed to flush LSP stdin: {}", e))?;
+            .map_err(QuirksError::lsp("flush"))?;
         
         Ok(())
     }
 
     /// Initialize the server (must be called first)
-    pub fn initialize(&mut self, root_uri: &str) -> Result<(), String> {
+    pub fn initialize(&mut self, root_uri: &str) -> Result<()> {
         let _id = self.next_id();
         
         let params = format!(
@@ -293,7 +319,7 @@ impl LspClient {
     }
 
     /// Notify the server that a document was opened
-    pub fn did_open(&mut self, uri: &str, language_id: &str, version: i32, text: &str) -> Result<(), String> {
+    pub fn did_open(&mut self, uri: &str, language_id: &str, version: i32, text: &str) -> Result<()> {
         let params = format!(
             r#"{{"textDocument":{{"uri":"{}","languageId":"{}","version":{},"text":"{}"}}}}"#,
             uri,
@@ -307,7 +333,7 @@ impl LspClient {
     }
 
     /// Notify the server that a document was changed
-    pub fn did_change(&mut self, uri: &str, version: i32, text: &str) -> Result<(), String> {
+    pub fn did_change(&mut self, uri: &str, version: i32, text: &str) -> Result<()> {
         let params = format!(
             r#"{{"textDocument":{{"uri":"{}","version":{}}},"contentChanges":[{{"text":"{}"}}]}}"#,
             uri,
@@ -320,7 +346,7 @@ impl LspClient {
     }
 
     /// Notify the server that a document was closed
-    pub fn did_close(&mut self, uri: &str) -> Result<(), String> {
+    pub fn did_close(&mut self, uri: &str) -> Result<()> {
         let params = format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, uri);
         
         let notification = self.format_notification("textDocument/didClose", &params);
@@ -328,7 +354,7 @@ impl LspClient {
     }
 
     /// Request completions at a position
-    pub fn completion(&mut self, uri: &str, line: u32, character: u32) -> Result<(), String> {
+    pub fn completion(&mut self, uri: &str, line: u32, character: u32) -> Result<()> {
         let _id = self.next_id();
         
         let params = format!(
@@ -341,7 +367,7 @@ impl LspClient {
     }
 
     /// Request hover information at a position
-    pub fn hover(&mut self, uri: &str, line: u32, character: u32) -> Result<(), String> {
+    pub fn hover(&mut self, uri: &str, line: u32, character: u32) -> Result<()> {
         let _id = self.next_id();
         
         let params = format!(
@@ -354,7 +380,7 @@ impl LspClient {
     }
 
     /// Request go-to-definition
-    pub fn goto_definition(&mut self, uri: &str, line: u32, character: u32) -> Result<(), String> {
+    pub fn goto_definition(&mut self, uri: &str, line: u32, character: u32) -> Result<()> {
         let _id = self.next_id();
         
         let params = format!(
@@ -367,7 +393,7 @@ impl LspClient {
     }
 
     /// Shutdown the server gracefully
-    pub fn shutdown(&mut self) -> Result<(), String> {
+    pub fn shutdown(&mut self) -> Result<()> {
         let _id = self.next_id();
         let request = self.format_request("shutdown", "null");
         self.send_message(&request)?;
@@ -386,6 +412,31 @@ impl Drop for LspClient {
     }
 }
 
+/// Read one message framed by a Content-Length header; None at the end of
+/// the stream
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
 /// Find the appropriate LSP server for a file
 pub fn server_for_file(path: &str, servers: &HashMap<String, LspServerConfig>) -> Option<&LspServerConfig> {
     let path = PathBuf::from(path);
@@ -431,7 +482,19 @@ mod tests {
     #[test]
     fn test_lsp_client_creation() {
         let config = LspServerConfig::new("test-server", "test");
-        let client = LspClient::new(config);
+        let mut client = LspClient::new(config);
         assert!(!client.is_running());
+        assert!(matches!(client.did_close("file:///a.rs"), Err(QuirksError::LspNotRunning)));
+        assert!(client.poll().is_empty());
+    }
+
+    #[test]
+    fn test_read_message() {
+        let stream = "Content-Length: 2\r\n\r\n{}Content-Type: x\r\nContent-Length: 4\r\n\r\nnull";
+        let mut reader = stream.as_bytes();
+        assert_eq!(read_message(&mut reader).unwrap().as_deref(), Some("{}"));
+        assert_eq!(read_message(&mut reader).unwrap().as_deref(), Some("null"));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+        assert!(read_message(&mut "\r\n".as_bytes()).is_err());
     }
 }
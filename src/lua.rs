@@ -0,0 +1,1104 @@
+//! Lua scripting for Quirks
+//!
+//! ~/.config/quirks/init.lua runs at startup with a `quirks` table to hook
+//! into the editor. Functions that register something (`quirks.autocmd`,
+//! `quirks.command`, `quirks.statusline`) keep it here; functions that
+//! reach into the editor (`quirks.buf_name`, `print`) only work while the
+//! editor has handed itself over for a call, which is whenever Lua runs:
+//! init.lua, an autocommand and so on. Calls nest when Lua runs an ex
+//! command that runs Lua again.
+
+use crate::error::{QuirksError, Result};
+use crate::keymap::Action;
+use crate::mode::Mode;
+use mlua::{Function, Lua, MultiValue, RegistryKey, Table, Value};
+use ratatui::style::{Color, Modifier, Style};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Lua side of the `quirks` table: wrappers that reach the editor through
+/// the functions handed over for the current call. Returns the function
+/// that hands them over.
+const PRELUDE: &str = r##"
+local host
+local function editor()
+    return host or error("the editor is not available here", 3)
+end
+
+function print(...)
+    local parts = {}
+    for i = 1, select("#", ...) do
+        parts[i] = tostring((select(i, ...)))
+    end
+    editor().echo(table.concat(parts, "\t"))
+end
+
+for _, name in ipairs({ "buf_name", "mode", "cursor", "line_count", "get_line", "get_option", "set_option" }) do
+    quirks[name] = function(...)
+        return editor()[name](...)
+    end
+end
+
+local Popup = {}
+Popup.__index = Popup
+
+function Popup:update(lines)
+    editor().update_popup(self.id, lines)
+end
+
+function Popup:close()
+    editor().close_popup(self.id)
+end
+
+function quirks.popup(lines, opts)
+    return setmetatable({ id = editor().open_popup(lines, opts) }, Popup)
+end
+
+function quirks.keymap(modes, lhs, rhs)
+    for _, mode in ipairs(type(modes) == "table" and modes or { modes }) do
+        editor().map_key(mode, lhs, rhs)
+    end
+end
+
+function quirks.cmd(commands)
+    for line in commands:gmatch("[^\n]+") do
+        -- Vim script style: an optional colon, comments start with "
+        local command = line:match("^[%s:]*(.-)%s*$")
+        if command ~= "" and not command:match('^"') then
+            editor().execute(command)
+        end
+    end
+end
+
+-- Enough of Neovim's vim table for copied configuration snippets:
+-- vim.cmd("set number") and vim.cmd.colorscheme("nord")
+vim = {
+    cmd = setmetatable({}, {
+        __call = function(_, commands)
+            quirks.cmd(commands)
+        end,
+        __index = function(_, name)
+            return function(...)
+                quirks.cmd(table.concat({ name, ... }, " "))
+            end
+        end,
+    }),
+}
+
+-- Hands over the editor's functions for a call, returning the ones of the
+-- call it is nested in
+return function(functions)
+    local outer = host
+    host = functions
+    return outer
+end
+"##;
+
+/// Most calls nesting in each other, so a command that runs itself stops
+const MAX_DEPTH: usize = 20;
+
+/// Directory of init.lua; `require` finds modules in its lua/ directory
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config").join("quirks"))
+}
+
+/// What Lua can read from the editor
+pub trait View {
+    /// Path of the current buffer's file
+    fn file_name(&self) -> Option<String>;
+    /// Mode as shown in the status line ("NORMAL")
+    fn mode(&self) -> String;
+    /// Cursor line and column, 1-based
+    fn cursor(&self) -> (usize, usize);
+    fn line_count(&self) -> usize;
+    /// Text of line `line`, 1-based
+    fn line(&self, line: usize) -> Option<String>;
+    /// Value of an option by Vim name or config key, the current buffer's
+    /// own where it has one
+    fn option(&self, name: &str) -> Option<String>;
+}
+
+/// What Lua can do to the editor
+pub trait Host: View {
+    /// Show what Lua printed during a call on the message line
+    fn echo(&mut self, text: String);
+    /// Run an ex command line, which may call into Lua again
+    fn execute(&mut self, command: &str);
+    /// Show a popup, returning its id for the handle
+    fn open_popup(&mut self, popup: Popup) -> usize;
+    /// Give a popup new lines; false if it was closed
+    fn update_popup(&mut self, id: usize, lines: Vec<String>) -> bool;
+    fn close_popup(&mut self, id: usize);
+    /// Set an option as :set does, or for the current buffer only as
+    /// :setlocal does when `local`. Boolean options take `None` to enable.
+    fn set_option(&mut self, name: &str, value: Option<&str>, local: bool) -> Result<(), String>;
+    /// Bind a key sequence ("<leader>w") in `mode`
+    fn map_key(&mut self, mode: Mode, keys: &str, action: Action) -> Result<(), String>;
+}
+
+/// Editor events autocommands run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A file was opened into a buffer
+    BufRead,
+    /// A buffer is about to be written
+    BufWritePre,
+    /// A buffer was written
+    BufWritePost,
+    /// Another buffer is about to be shown
+    BufLeave,
+    /// A buffer is about to be closed
+    BufDelete,
+    /// The mode changed; the pattern matches "old:new" ("normal:insert")
+    ModeChanged,
+    /// A key moved the cursor
+    CursorMoved,
+}
+
+impl Event {
+    const ALL: [Event; 7] = [
+        Event::BufRead,
+        Event::BufWritePre,
+        Event::BufWritePost,
+        Event::BufLeave,
+        Event::BufDelete,
+        Event::ModeChanged,
+        Event::CursorMoved,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::BufRead => "BufRead",
+            Event::BufWritePre => "BufWritePre",
+            Event::BufWritePost => "BufWritePost",
+            Event::BufLeave => "BufLeave",
+            Event::BufDelete => "BufDelete",
+            Event::ModeChanged => "ModeChanged",
+            Event::CursorMoved => "CursorMoved",
+        }
+    }
+
+    /// Event by name, ignoring case; BufReadPost is BufRead as in Vim
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("BufReadPost") {
+            return Some(Event::BufRead);
+        }
+        Self::ALL.into_iter().find(|event| event.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A callback registered with quirks.autocmd
+struct Autocmd {
+    event: Event,
+    /// Comma-separated file patterns ("*.rs,*.toml"); None matches all
+    pattern: Option<String>,
+    callback: RegistryKey,
+}
+
+/// An ex command defined with quirks.command
+enum Command {
+    /// Lua function called with {name, args, fargs, bang}
+    Function(RegistryKey),
+    /// Ex command line run in its place, with `<args>` replaced by the
+    /// arguments
+    Ex(String),
+}
+
+/// What init.lua and plugins registered
+#[derive(Default)]
+struct Hooks {
+    autocmds: Vec<Autocmd>,
+    commands: HashMap<String, Command>,
+    /// Function giving the status line's extra segments
+    statusline: Option<RegistryKey>,
+    /// Functions mapped to keys, which `Action::Lua` calls by index. They
+    /// stay when a key is mapped again, as another key may still call them.
+    keymaps: Vec<RegistryKey>,
+}
+
+/// Text the statusline function added to the status line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Where a popup is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// Below the cursor, or above it when there is no room
+    Cursor,
+    Center,
+}
+
+/// A floating window opened with quirks.popup
+#[derive(Debug, Clone, PartialEq)]
+pub struct Popup {
+    pub lines: Vec<String>,
+    pub anchor: Anchor,
+    pub title: Option<String>,
+    /// Width of the text; the longest line when None
+    pub width: Option<u16>,
+    pub border: bool,
+    pub style: Style,
+}
+
+/// How a call reaches the editor
+enum Access<'a> {
+    /// While drawing: the editor can be looked at but not changed, and
+    /// what is printed goes nowhere
+    Read(&'a dyn View),
+    Write(&'a mut dyn Host),
+}
+
+impl Access<'_> {
+    fn view(&self) -> &dyn View {
+        match self {
+            Access::Read(view) => *view,
+            Access::Write(host) => &**host,
+        }
+    }
+
+    fn host(&mut self) -> mlua::Result<&mut dyn Host> {
+        match self {
+            Access::Read(_) => Err(mlua::Error::runtime("the editor can't be changed while drawing")),
+            Access::Write(host) => Ok(&mut **host),
+        }
+    }
+}
+
+/// The Lua state with what scripts registered in it
+pub struct LuaEngine {
+    lua: Lua,
+    hooks: Rc<RefCell<Hooks>>,
+    /// Prelude function that hands the editor's functions to the wrappers
+    set_host: RegistryKey,
+    /// Calls running, nested in each other
+    depth: Cell<usize>,
+}
+
+impl LuaEngine {
+    pub fn new() -> mlua::Result<Self> {
+        let lua = Lua::new();
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let quirks = lua.create_table()?;
+
+        let registered = hooks.clone();
+        // quirks.autocmd(events, [{pattern = ..., callback = ...}], [callback])
+        quirks.set("autocmd", lua.create_function(move |lua, (events, opts, callback): (Value, Value, Option<Function>)| {
+            let events = match events {
+                Value::Table(list) => list.sequence_values::<String>().collect::<mlua::Result<Vec<_>>>()?,
+                other => vec![lua.unpack::<String>(other)?],
+            };
+            let (opts, callback) = match opts {
+                Value::Function(callback) => (None, Some(callback)),
+                Value::Nil => (None, callback),
+                other => (Some(lua.unpack::<Table>(other)?), callback),
+            };
+            let pattern = match &opts {
+                Some(opts) => opts.get::<_, Option<String>>("pattern")?,
+                None => None,
+            };
+            let callback = match (callback, &opts) {
+                (Some(callback), _) => callback,
+                (None, Some(opts)) => opts.get::<_, Function>("callback")?,
+                (None, None) => return Err(mlua::Error::runtime("quirks.autocmd needs a callback")),
+            };
+            for name in events {
+                let event = Event::from_name(&name)
+                    .ok_or_else(|| mlua::Error::runtime(format!("unknown event: {}", name)))?;
+                let callback = lua.create_registry_value(callback.clone())?;
+                registered.borrow_mut().autocmds.push(Autocmd { event, pattern: pattern.clone(), callback });
+            }
+            Ok(())
+        })?)?;
+
+        let registered = hooks.clone();
+        // quirks.command(name, function | "ex command")
+        quirks.set("command", lua.create_function(move |lua, (name, action): (String, Value)| {
+            let valid = name.chars().next().is_some_and(char::is_alphabetic)
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if !valid {
+                return Err(mlua::Error::runtime(format!("invalid command name: {}", name)));
+            }
+            let command = match action {
+                Value::Function(callback) => Command::Function(lua.create_registry_value(callback)?),
+                other => Command::Ex(lua.unpack::<String>(other)?),
+            };
+            if let Some(Command::Function(old)) = registered.borrow_mut().commands.insert(name, command) {
+                lua.remove_registry_value(old)?;
+            }
+            Ok(())
+        })?)?;
+
+        let registered = hooks.clone();
+        // quirks.statusline(function | nil)
+        quirks.set("statusline", lua.create_function(move |lua, function: Option<Function>| {
+            let function = function.map(|function| lua.create_registry_value(function)).transpose()?;
+            if let Some(old) = std::mem::replace(&mut registered.borrow_mut().statusline, function) {
+                lua.remove_registry_value(old)?;
+            }
+            Ok(())
+        })?)?;
+        lua.globals().set("quirks", quirks)?;
+
+        let set_host: Function = lua.load(PRELUDE).set_name("=quirks").call(())?;
+        let set_host = lua.create_registry_value(set_host)?;
+        let engine = Self { lua, hooks, set_host, depth: Cell::new(0) };
+        if let Some(dir) = config_dir() {
+            engine.add_module_dir(&dir.join("lua"))?;
+        }
+        Ok(engine)
+    }
+
+    /// Let `require("name")` find name.lua and name/init.lua in `dir`,
+    /// before the standard places
+    pub fn add_module_dir(&self, dir: &Path) -> mlua::Result<()> {
+        let package: Table = self.lua.globals().get("package")?;
+        let path: String = package.get("path")?;
+        let dir = dir.display();
+        package.set("path", format!("{dir}/?.lua;{dir}/?/init.lua;{path}"))
+    }
+
+    /// Run the Lua file at `path`
+    pub fn run_file(&self, host: &mut dyn Host, path: &Path) -> Result<()> {
+        let code = std::fs::read_to_string(path).map_err(QuirksError::file(path))?;
+        let chunk = format!("@{}", path.display());
+        Ok(self.call(Access::Write(host), |lua| lua.load(&code).set_name(chunk).exec())?)
+    }
+
+    /// Run Lua typed at `:lua`; `=expr` prints the values of `expr`
+    pub fn run_line(&self, host: &mut dyn Host, code: &str) -> Result<()> {
+        let result = self.call(Access::Write(host), |lua| match code.strip_prefix('=') {
+            Some(expr) => {
+                let values: MultiValue = lua.load(format!("return {}", expr)).set_name("=:lua").eval()?;
+                if values.is_empty() {
+                    return Ok(());
+                }
+                let text = values.iter().map(|value| inspect(value, 0)).collect::<Vec<_>>().join(", ");
+                lua.globals().get::<_, Function>("print")?.call(text)
+            }
+            None => lua.load(code).set_name("=:lua").exec(),
+        });
+        Ok(result?)
+    }
+
+    /// Whether any autocommand waits for `event`, so the editor can skip
+    /// handing itself over when none does
+    pub fn has_autocmd(&self, event: Event) -> bool {
+        self.hooks.borrow().autocmds.iter().any(|autocmd| autocmd.event == event)
+    }
+
+    /// Run the autocommands for `event` whose pattern matches `target` (the
+    /// file, or "old:new" for ModeChanged). Returns the errors they raised.
+    pub fn fire(&self, host: &mut dyn Host, event: Event, target: &str) -> Vec<QuirksError> {
+        let file = host.file_name().unwrap_or_default();
+        let mut errors = Vec::new();
+        let result = self.call(Access::Write(host), |lua| {
+            // Callbacks may register autocommands, so let go of the hooks first
+            let callbacks = self.hooks.borrow().autocmds.iter()
+                .filter(|autocmd| autocmd.event == event)
+                .filter(|autocmd| autocmd.pattern.as_deref().is_none_or(|pattern| pattern_matches(pattern, target)))
+                .map(|autocmd| lua.registry_value::<Function>(&autocmd.callback))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            for callback in callbacks {
+                let args = lua.create_table()?;
+                args.set("event", event.name())?;
+                args.set("file", file.as_str())?;
+                args.set("match", target)?;
+                if let Err(e) = callback.call::<_, ()>(args) {
+                    errors.push(e.into());
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            errors.push(e.into());
+        }
+        errors
+    }
+
+    /// Whether `name` is a command defined with quirks.command
+    pub fn has_command(&self, name: &str) -> bool {
+        self.hooks.borrow().commands.contains_key(name)
+    }
+
+    /// Run the command `name` defined with quirks.command
+    pub fn run_command(&self, host: &mut dyn Host, name: &str, args: &str, bang: bool) -> Result<()> {
+        let callback = match self.hooks.borrow().commands.get(name) {
+            Some(Command::Function(callback)) => self.lua.registry_value::<Function>(callback)?,
+            Some(Command::Ex(line)) => {
+                let line = line.replace("<args>", args);
+                return Ok(self.nested(|| host.execute(&line))?);
+            }
+            None => return Err(QuirksError::Lua(format!("Unknown command: {}", name))),
+        };
+        let result = self.call(Access::Write(host), |lua| {
+            let opts = lua.create_table()?;
+            opts.set("name", name)?;
+            opts.set("args", args)?;
+            opts.set("fargs", args.split_whitespace().collect::<Vec<_>>())?;
+            opts.set("bang", bang)?;
+            callback.call::<_, ()>(opts)
+        });
+        Ok(result?)
+    }
+
+    /// Call the function `Action::Lua(id)` was mapped to
+    pub fn run_keymap(&self, host: &mut dyn Host, id: usize) -> Result<()> {
+        let callback = match self.hooks.borrow().keymaps.get(id) {
+            Some(callback) => self.lua.registry_value::<Function>(callback)?,
+            None => return Ok(()),
+        };
+        Ok(self.call(Access::Write(host), |_| callback.call::<_, ()>(()))?)
+    }
+
+    /// Whether a statusline function is set, whose segments can change
+    /// whenever it is called
+    pub fn has_statusline(&self) -> bool {
+        self.hooks.borrow().statusline.is_some()
+    }
+
+    /// Call the statusline function for the segments it adds to the status
+    /// line; None without one
+    pub fn statusline(&self, view: &dyn View) -> Option<Result<Vec<Segment>>> {
+        // The function may set another one, so let go of the hooks first
+        let function = self.hooks.borrow().statusline.as_ref().map(|key| self.lua.registry_value::<Function>(key))?;
+        let result = self.call(Access::Read(view), |lua| segments(lua, function?.call(())?));
+        Some(result.map_err(QuirksError::from))
+    }
+
+    /// Run `f` with the editor's functions handed to the `quirks` wrappers,
+    /// then show what it printed
+    fn call<R>(&self, access: Access, f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+        self.nested(|| self.call_unchecked(access, f))?
+    }
+
+    /// Count `f` as a call nested in the running ones
+    fn nested<R>(&self, f: impl FnOnce() -> R) -> mlua::Result<R> {
+        let depth = self.depth.get();
+        if depth >= MAX_DEPTH {
+            return Err(mlua::Error::runtime("commands nest too deeply"));
+        }
+        self.depth.set(depth + 1);
+        let result = f();
+        self.depth.set(depth);
+        Ok(result)
+    }
+
+    fn call_unchecked<R>(&self, access: Access, f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+        let access = RefCell::new(access);
+        let output = RefCell::new(Vec::new());
+        let result = self.lua.scope(|scope| {
+            let functions = self.lua.create_table()?;
+            functions.set("echo", scope.create_function(|_, text: String| {
+                output.borrow_mut().push(text);
+                Ok(())
+            })?)?;
+            functions.set("buf_name", scope.create_function(|_, ()| Ok(access.borrow().view().file_name()))?)?;
+            functions.set("mode", scope.create_function(|_, ()| Ok(access.borrow().view().mode()))?)?;
+            functions.set("cursor", scope.create_function(|_, ()| Ok(access.borrow().view().cursor()))?)?;
+            functions.set("line_count", scope.create_function(|_, ()| Ok(access.borrow().view().line_count()))?)?;
+            functions.set("get_line", scope.create_function(|_, line: usize| Ok(access.borrow().view().line(line)))?)?;
+            functions.set("get_option", scope.create_function(|lua, name: String| {
+                let value = access.borrow().view().option(&name)
+                    .ok_or_else(|| mlua::Error::runtime(format!("unknown option: {}", name)))?;
+                option_value(lua, value)
+            })?)?;
+            functions.set("set_option", scope.create_function(|_, (name, value, opts): (String, Value, Option<Table>)| {
+                let value = match value {
+                    Value::Nil => None,
+                    Value::Boolean(value) => Some(value.to_string()),
+                    Value::Integer(value) => Some(value.to_string()),
+                    Value::Number(value) => Some(value.to_string()),
+                    Value::String(value) => Some(value.to_str()?.to_string()),
+                    other => return Err(mlua::Error::runtime(format!("invalid option value: {}", other.type_name()))),
+                };
+                let local = match opts.map(|opts| opts.get::<_, Option<String>>("scope")).transpose()?.flatten().as_deref() {
+                    None | Some("global") => false,
+                    Some("local") => true,
+                    Some(other) => return Err(mlua::Error::runtime(format!("unknown scope: {} (global or local)", other))),
+                };
+                access.borrow_mut().host()?.set_option(&name, value.as_deref(), local).map_err(mlua::Error::runtime)
+            })?)?;
+            functions.set("execute", scope.create_function(|_, command: String| {
+                access.borrow_mut().host()?.execute(&command);
+                Ok(())
+            })?)?;
+            functions.set("open_popup", scope.create_function(|lua, (lines, opts): (Value, Option<Table>)| {
+                let popup = popup(lua, lines, opts)?;
+                Ok(access.borrow_mut().host()?.open_popup(popup))
+            })?)?;
+            functions.set("update_popup", scope.create_function(|lua, (id, lines): (usize, Value)| {
+                let lines = popup_lines(lua, lines)?;
+                if !access.borrow_mut().host()?.update_popup(id, lines) {
+                    return Err(mlua::Error::runtime("the popup was closed"));
+                }
+                Ok(())
+            })?)?;
+            functions.set("close_popup", scope.create_function(|_, id: usize| {
+                access.borrow_mut().host()?.close_popup(id);
+                Ok(())
+            })?)?;
+            functions.set("map_key", scope.create_function(|lua, (mode, keys, rhs): (String, String, Value)| {
+                let mode = map_mode(&mode)?;
+                let mut access = access.borrow_mut();
+                let host = access.host()?;
+                let action = match rhs {
+                    Value::Function(callback) => {
+                        let mut hooks = self.hooks.borrow_mut();
+                        hooks.keymaps.push(lua.create_registry_value(callback)?);
+                        Action::Lua(hooks.keymaps.len() - 1)
+                    }
+                    other => {
+                        let name = lua.unpack::<String>(other)?;
+                        Action::from_name(&name)
+                            .ok_or_else(|| mlua::Error::runtime(format!("unknown action: {}", name)))?
+                    }
+                };
+                host.map_key(mode, &keys, action).map_err(mlua::Error::runtime)
+            })?)?;
+
+            let set_host: Function = self.lua.registry_value(&self.set_host)?;
+            let outer: Value = set_host.call(functions)?;
+            let result = f(&self.lua);
+            set_host.call::<_, ()>(outer)?;
+            result
+        });
+        let output = output.into_inner();
+        if let (Access::Write(host), false) = (access.into_inner(), output.is_empty()) {
+            host.echo(output.join("\n"));
+        }
+        result
+    }
+}
+
+impl From<mlua::Error> for QuirksError {
+    fn from(error: mlua::Error) -> Self {
+        QuirksError::Lua(error_message(&error))
+    }
+}
+
+/// An error without Lua's stack traceback, for the message line
+pub fn error_message(error: &mlua::Error) -> String {
+    let text = match error {
+        mlua::Error::CallbackError { cause, .. } => return error_message(cause),
+        other => other.to_string(),
+    };
+    let text = text.split("\nstack traceback:").next().unwrap_or_default();
+    text.strip_prefix("runtime error: ").unwrap_or(text).trim_end().to_string()
+}
+
+/// An option's value for Lua: a boolean, a number or a string
+fn option_value(lua: &Lua, value: String) -> mlua::Result<Value<'_>> {
+    match value.as_str() {
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ => match value.parse::<i64>() {
+            Ok(number) => Ok(Value::Integer(number)),
+            Err(_) => lua.create_string(&value).map(Value::String),
+        },
+    }
+}
+
+/// Mode of a quirks.keymap mapping by its Vim letter
+fn map_mode(name: &str) -> mlua::Result<Mode> {
+    match name {
+        "n" => Ok(Mode::Normal),
+        "i" => Ok(Mode::Insert),
+        "v" | "x" => Ok(Mode::Visual),
+        "c" => Ok(Mode::Command),
+        _ => Err(mlua::Error::runtime(format!("unknown mode: {} (n, i, v or c)", name))),
+    }
+}
+
+/// Segments from what the statusline function returned: a string, a
+/// segment table ({text = ..., fg = ..., bold = true}) or a list of them
+fn segments(lua: &Lua, value: Value) -> mlua::Result<Vec<Segment>> {
+    match value {
+        Value::Nil => Ok(Vec::new()),
+        Value::Table(table) if !table.contains_key("text")? => {
+            table.sequence_values::<Value>().map(|value| segment(lua, value?)).collect()
+        }
+        other => Ok(vec![segment(lua, other)?]),
+    }
+}
+
+fn segment(lua: &Lua, value: Value) -> mlua::Result<Segment> {
+    match value {
+        Value::Table(table) => Ok(Segment { text: table.get("text")?, style: style(&table)? }),
+        other => Ok(Segment { text: lua.unpack(other)?, style: Style::default() }),
+    }
+}
+
+/// The style set by `fg`, `bg`, `bold`, `italic`, `underline` and `reverse`
+/// in `table`; colors are names ("blue") or "#rrggbb"
+fn style(table: &Table) -> mlua::Result<Style> {
+    let color = |key: &str| -> mlua::Result<Option<Color>> {
+        let Some(name) = table.get::<_, Option<String>>(key)? else {
+            return Ok(None);
+        };
+        Color::from_str(&name).map(Some).map_err(|_| mlua::Error::runtime(format!("unknown color: {}", name)))
+    };
+    let mut style = Style::default();
+    if let Some(fg) = color("fg")? {
+        style = style.fg(fg);
+    }
+    if let Some(bg) = color("bg")? {
+        style = style.bg(bg);
+    }
+    for (key, modifier) in [
+        ("bold", Modifier::BOLD),
+        ("italic", Modifier::ITALIC),
+        ("underline", Modifier::UNDERLINED),
+        ("reverse", Modifier::REVERSED),
+    ] {
+        if table.get::<_, Option<bool>>(key)?.unwrap_or(false) {
+            style = style.add_modifier(modifier);
+        }
+    }
+    Ok(style)
+}
+
+/// The popup quirks.popup(lines, opts) asks for
+fn popup(lua: &Lua, lines: Value, opts: Option<Table>) -> mlua::Result<Popup> {
+    let lines = popup_lines(lua, lines)?;
+    let Some(opts) = opts else {
+        return Ok(Popup { lines, anchor: Anchor::Center, title: None, width: None, border: true, style: Style::default() });
+    };
+    let anchor = match opts.get::<_, Option<String>>("anchor")?.as_deref() {
+        None | Some("center") => Anchor::Center,
+        Some("cursor") => Anchor::Cursor,
+        Some(other) => return Err(mlua::Error::runtime(format!("unknown anchor: {} (cursor or center)", other))),
+    };
+    Ok(Popup {
+        lines,
+        anchor,
+        title: opts.get("title")?,
+        width: opts.get("width")?,
+        border: opts.get::<_, Option<bool>>("border")?.unwrap_or(true),
+        style: style(&opts)?,
+    })
+}
+
+/// Popup lines from a list of strings, or a string split at newlines
+fn popup_lines(lua: &Lua, lines: Value) -> mlua::Result<Vec<String>> {
+    match lines {
+        Value::Table(list) => list.sequence_values::<String>().collect(),
+        other => Ok(lua.unpack::<String>(other)?.lines().map(str::to_string).collect()),
+    }
+}
+
+/// A value as Lua code, for `:lua =expr`: strings quoted, tables spelled
+/// out a few levels deep
+pub fn inspect(value: &Value, depth: usize) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(n) => n.to_string(),
+        Value::Number(n) => format!("{:?}", n),
+        Value::String(s) => format!("{:?}", s.to_string_lossy()),
+        Value::Table(_) if depth >= 3 => "{...}".to_string(),
+        Value::Table(table) => {
+            let len = table.raw_len();
+            let mut items: Vec<String> = (1..=len)
+                .map(|i| table.raw_get::<_, Value>(i).map_or_else(|_| "?".to_string(), |v| inspect(&v, depth + 1)))
+                .collect();
+            let mut fields: Vec<String> = table
+                .clone()
+                .pairs::<Value, Value>()
+                .filter_map(Result::ok)
+                .filter(|(key, _)| !matches!(key, Value::Integer(i) if (1..=len as i64).contains(i)))
+                .map(|(key, value)| {
+                    let key = match &key {
+                        Value::String(s) if is_identifier(&s.to_string_lossy()) => s.to_string_lossy().into_owned(),
+                        other => format!("[{}]", inspect(other, depth + 1)),
+                    };
+                    format!("{} = {}", key, inspect(&value, depth + 1))
+                })
+                .collect();
+            fields.sort();
+            items.append(&mut fields);
+            if items.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", items.join(", "))
+            }
+        }
+        other => other.type_name().to_string(),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split an ex command line into the command name, whether it has a `!`
+/// and its arguments: "Grep! foo bar" gives ("Grep", true, "foo bar")
+pub fn split_command(line: &str) -> (&str, bool, &str) {
+    let end = line.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    match rest.strip_prefix('!') {
+        Some(rest) => (name, true, rest.trim()),
+        None => (name, false, rest.trim()),
+    }
+}
+
+/// Whether `target` matches one of the comma-separated `patterns`, where
+/// `*` is any text and `?` one character. A pattern without a `/` only
+/// looks at the file name, as in Vim's autocommands.
+pub fn pattern_matches(patterns: &str, target: &str) -> bool {
+    patterns.split(',').map(str::trim).any(|pattern| {
+        let target = if pattern.contains('/') {
+            target
+        } else {
+            target.rsplit('/').next().unwrap_or(target)
+        };
+        glob(&pattern.chars().collect::<Vec<_>>(), &target.chars().collect::<Vec<_>>())
+    })
+}
+
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| glob(rest, &text[skip..])),
+        Some((&c, rest)) => match text.split_first() {
+            Some((&t, text)) if c == '?' || c == t => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An editor holding one file and what was shown of it
+    #[derive(Default)]
+    struct FakeHost {
+        lines: Vec<String>,
+        echoed: Vec<String>,
+        executed: Vec<String>,
+        popups: Vec<Option<Popup>>,
+        mapped: Vec<(Mode, String, Action)>,
+        /// Options by name, the buffer's own under "local:name"
+        options: HashMap<String, String>,
+    }
+
+    impl View for FakeHost {
+        fn file_name(&self) -> Option<String> {
+            Some("src/main.rs".to_string())
+        }
+        fn mode(&self) -> String {
+            "NORMAL".to_string()
+        }
+        fn cursor(&self) -> (usize, usize) {
+            (2, 1)
+        }
+        fn line_count(&self) -> usize {
+            self.lines.len()
+        }
+        fn line(&self, line: usize) -> Option<String> {
+            self.lines.get(line.checked_sub(1)?).cloned()
+        }
+        fn option(&self, name: &str) -> Option<String> {
+            self.options.get(&format!("local:{}", name)).or(self.options.get(name)).cloned()
+        }
+    }
+
+    impl Host for FakeHost {
+        fn echo(&mut self, text: String) {
+            self.echoed.push(text);
+        }
+        fn execute(&mut self, command: &str) {
+            self.executed.push(command.to_string());
+        }
+        fn open_popup(&mut self, popup: Popup) -> usize {
+            self.popups.push(Some(popup));
+            self.popups.len() - 1
+        }
+        fn update_popup(&mut self, id: usize, lines: Vec<String>) -> bool {
+            match self.popups.get_mut(id).and_then(Option::as_mut) {
+                Some(popup) => {
+                    popup.lines = lines;
+                    true
+                }
+                None => false,
+            }
+        }
+        fn close_popup(&mut self, id: usize) {
+            self.popups[id] = None;
+        }
+        fn set_option(&mut self, name: &str, value: Option<&str>, local: bool) -> Result<(), String> {
+            if !self.options.contains_key(name) {
+                return Err(format!("Unknown option: {}", name));
+            }
+            let key = if local { format!("local:{}", name) } else { name.to_string() };
+            self.options.insert(key, value.unwrap_or("true").to_string());
+            Ok(())
+        }
+        fn map_key(&mut self, mode: Mode, keys: &str, action: Action) -> Result<(), String> {
+            if keys.is_empty() {
+                return Err("no keys".to_string());
+            }
+            self.mapped.push((mode, keys.to_string(), action));
+            Ok(())
+        }
+    }
+
+    fn run(engine: &LuaEngine, host: &mut FakeHost, code: &str) -> mlua::Result<()> {
+        engine.call(Access::Write(host), |lua| lua.load(code).exec())
+    }
+
+    #[test]
+    fn test_autocmd() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost { lines: vec!["fn main() {".to_string(), "}".to_string()], ..Default::default() };
+        run(&engine, &mut host, r#"
+            quirks.autocmd("BufWritePre", { pattern = "*.rs" }, function(args)
+                print(args.event, args.file, quirks.get_line(1), quirks.line_count())
+            end)
+            quirks.autocmd({ "BufRead", "ModeChanged" }, { pattern = "*:insert", callback = function(args)
+                print(args.match)
+            end })
+        "#).unwrap();
+        assert!(engine.has_autocmd(Event::BufWritePre));
+        assert!(!engine.has_autocmd(Event::CursorMoved));
+
+        assert!(engine.fire(&mut host, Event::BufWritePre, "src/main.rs").is_empty());
+        assert!(engine.fire(&mut host, Event::BufWritePre, "Cargo.toml").is_empty());
+        assert!(engine.fire(&mut host, Event::ModeChanged, "normal:insert").is_empty());
+        assert!(engine.fire(&mut host, Event::ModeChanged, "insert:normal").is_empty());
+        assert_eq!(host.echoed, ["BufWritePre\tsrc/main.rs\tfn main() {\t2", "normal:insert"]);
+
+        let error = run(&engine, &mut host, r#"quirks.autocmd("BufSave", print)"#).unwrap_err();
+        assert!(error_message(&error).ends_with("unknown event: BufSave"), "{}", error_message(&error));
+        run(&engine, &mut host, r#"quirks.autocmd("CursorMoved", function() error("boom") end)"#).unwrap();
+        let errors = engine.fire(&mut host, Event::CursorMoved, "src/main.rs");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().ends_with("boom"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_editor_functions_outside_a_call() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost::default();
+        run(&engine, &mut host, "saved = quirks.buf_name; print(saved())").unwrap();
+        assert_eq!(host.echoed, ["src/main.rs"]);
+        // The wrappers stay valid from one call to the next
+        run(&engine, &mut host, "print(saved(), quirks.mode(), quirks.cursor())").unwrap();
+        assert_eq!(host.echoed[1], "src/main.rs\tNORMAL\t2\t1");
+    }
+
+    #[test]
+    fn test_command() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost::default();
+        run(&engine, &mut host, r#"
+            quirks.command("Hello", function(opts)
+                print(opts.name, opts.args, #opts.fargs, opts.bang)
+            end)
+            quirks.command("W", "w <args>")
+        "#).unwrap();
+        assert!(engine.has_command("Hello"));
+        assert!(!engine.has_command("hello"));
+
+        engine.run_command(&mut host, "Hello", "big  world", true).unwrap();
+        assert_eq!(host.echoed, ["Hello\tbig  world\t2\ttrue"]);
+        engine.run_command(&mut host, "W", "notes.txt", false).unwrap();
+        assert_eq!(host.executed, ["w notes.txt"]);
+
+        // Defining a command again replaces it
+        run(&engine, &mut host, r#"quirks.command("Hello", function() error("no") end)"#).unwrap();
+        assert!(engine.run_command(&mut host, "Hello", "", false).unwrap_err().to_string().ends_with("no"));
+        let error = run(&engine, &mut host, r#"quirks.command("1st", "w")"#).unwrap_err();
+        assert!(error_message(&error).ends_with("invalid command name: 1st"));
+    }
+
+    #[test]
+    fn test_keymap() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost::default();
+        run(&engine, &mut host, r#"
+            quirks.keymap("n", "<leader>w", ":w<CR>")
+            quirks.keymap({ "n", "x" }, "H", "move_line_start")
+            quirks.keymap("i", "<C-l>", function() print("mapped") end)
+        "#).unwrap();
+        assert_eq!(host.mapped, [
+            (Mode::Normal, "<leader>w".to_string(), Action::Command("w".to_string())),
+            (Mode::Normal, "H".to_string(), Action::MoveLineStart),
+            (Mode::Visual, "H".to_string(), Action::MoveLineStart),
+            (Mode::Insert, "<C-l>".to_string(), Action::Lua(0)),
+        ]);
+        engine.run_keymap(&mut host, 0).unwrap();
+        assert_eq!(host.echoed, ["mapped"]);
+
+        let error = run(&engine, &mut host, r#"quirks.keymap("n", "x", "explode")"#).unwrap_err();
+        assert!(error_message(&error).ends_with("unknown action: explode"));
+        let error = run(&engine, &mut host, r#"quirks.keymap("o", "x", "undo")"#).unwrap_err();
+        assert!(error_message(&error).ends_with("unknown mode: o (n, i, v or c)"));
+        let error = run(&engine, &mut host, r#"quirks.keymap("n", "", "undo")"#).unwrap_err();
+        assert!(error_message(&error).ends_with("no keys"));
+    }
+
+    #[test]
+    fn test_options() {
+        let engine = LuaEngine::new().unwrap();
+        let options = [("number", "false"), ("tabstop", "4"), ("showbreak", "")];
+        let options = options.into_iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let mut host = FakeHost { options, ..Default::default() };
+        run(&engine, &mut host, r#"
+            quirks.set_option("number")
+            quirks.set_option("tabstop", 8)
+            quirks.set_option("showbreak", "> ", { scope = "local" })
+            print(quirks.get_option("number"), quirks.get_option("tabstop"), quirks.get_option("showbreak"))
+        "#).unwrap();
+        assert_eq!(host.echoed, ["true\t8\t> "]);
+        assert_eq!(host.options["showbreak"], "");
+
+        let error = run(&engine, &mut host, r#"quirks.get_option("nothing")"#).unwrap_err();
+        assert!(error_message(&error).ends_with("unknown option: nothing"));
+        let error = run(&engine, &mut host, r#"quirks.set_option("nothing", true)"#).unwrap_err();
+        assert!(error_message(&error).ends_with("Unknown option: nothing"));
+        let error = run(&engine, &mut host, r#"quirks.set_option("number", true, { scope = "window" })"#).unwrap_err();
+        assert!(error_message(&error).ends_with("unknown scope: window (global or local)"));
+    }
+
+    #[test]
+    fn test_run_line() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost { lines: vec!["one".to_string()], ..Default::default() };
+        engine.run_line(&mut host, "answer = 6 * 7").unwrap();
+        engine.run_line(&mut host, "=answer, quirks.get_line(1), nil").unwrap();
+        engine.run_line(&mut host, "={ 1, 'two', { x = 1.5 }, name = 'q', [true] = print }").unwrap();
+        engine.run_line(&mut host, "=print('side effect')").unwrap();
+        assert_eq!(host.echoed, [
+            "42, \"one\", nil",
+            "{ 1, \"two\", { x = 1.5 }, [true] = function, name = \"q\" }",
+            "side effect",
+        ]);
+        let error = engine.run_line(&mut host, "=1 +").unwrap_err();
+        assert!(error.to_string().starts_with("syntax error: :lua:1:"), "{}", error);
+        assert!(engine.run_line(&mut host, "error('boom')").unwrap_err().to_string().ends_with(":lua:1: boom"));
+    }
+
+    #[test]
+    fn test_statusline() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost { lines: vec!["a b".to_string(), "c".to_string()], ..Default::default() };
+        assert!(engine.statusline(&host).is_none());
+        run(&engine, &mut host, r#"quirks.statusline(function() return quirks.line_count() .. " lines" end)"#).unwrap();
+        assert!(engine.has_statusline());
+        assert_eq!(engine.statusline(&host).unwrap().unwrap(), [Segment { text: "2 lines".to_string(), style: Style::default() }]);
+
+        run(&engine, &mut host, r##"quirks.statusline(function()
+            print("not shown")
+            return { "main", { text = "2 words", fg = "#ff8800", bg = "blue", bold = true } }
+        end)"##).unwrap();
+        let segments = engine.statusline(&host).unwrap().unwrap();
+        assert_eq!(segments[0].text, "main");
+        assert_eq!(segments[1].style, Style::default().fg(Color::Rgb(255, 136, 0)).bg(Color::Blue).add_modifier(Modifier::BOLD));
+        assert!(host.echoed.is_empty());
+
+        run(&engine, &mut host, r#"quirks.statusline(function() return { text = "x", fg = "mauve" } end)"#).unwrap();
+        assert!(engine.statusline(&host).unwrap().unwrap_err().to_string().ends_with("unknown color: mauve"));
+        run(&engine, &mut host, "quirks.statusline(nil)").unwrap();
+        assert!(!engine.has_statusline());
+    }
+
+    #[test]
+    fn test_popup() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost::default();
+        run(&engine, &mut host, r#"
+            hint = quirks.popup("fn main()\nmain entry", { anchor = "cursor", title = "Docs", border = false, bg = "blue" })
+            menu = quirks.popup({ "one", "two" })
+        "#).unwrap();
+        assert_eq!(host.popups[0], Some(Popup {
+            lines: vec!["fn main()".to_string(), "main entry".to_string()],
+            anchor: Anchor::Cursor,
+            title: Some("Docs".to_string()),
+            width: None,
+            border: false,
+            style: Style::default().bg(Color::Blue),
+        }));
+        assert_eq!(host.popups[1].as_ref().map(|popup| (popup.anchor, popup.border)), Some((Anchor::Center, true)));
+
+        // The handles outlive the call that made them
+        run(&engine, &mut host, "menu:update({ 'three' }); hint:close()").unwrap();
+        assert_eq!(host.popups[0], None);
+        assert_eq!(host.popups[1].as_ref().unwrap().lines, ["three"]);
+        let error = run(&engine, &mut host, "hint:update('again')").unwrap_err();
+        assert!(error_message(&error).ends_with("the popup was closed"));
+        let error = run(&engine, &mut host, "quirks.popup('x', { anchor = 'top' })").unwrap_err();
+        assert!(error_message(&error).ends_with("unknown anchor: top (cursor or center)"));
+
+        // Not while drawing
+        run(&engine, &mut host, "quirks.statusline(function() quirks.popup('x') end)").unwrap();
+        assert!(engine.statusline(&host).unwrap().unwrap_err().to_string().ends_with("the editor can't be changed while drawing"));
+    }
+
+    #[test]
+    fn test_cmd() {
+        let engine = LuaEngine::new().unwrap();
+        let mut host = FakeHost::default();
+        run(&engine, &mut host, r#"
+            quirks.cmd(":w")
+            vim.cmd([[
+                " Copied from a vimrc
+                set number
+                  :set tw=72
+            ]])
+            vim.cmd.colorscheme("nord")
+            vim.cmd.set("nowrap", "list")
+        "#).unwrap();
+        assert_eq!(host.executed, ["w", "set number", "set tw=72", "colorscheme nord", "set nowrap list"]);
+    }
+
+    #[test]
+    fn test_require() {
+        let dir = std::env::temp_dir().join(format!("quirks-lua-require-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tools")).unwrap();
+        std::fs::write(dir.join("greet.lua"), "return { hello = function() return 'hi' end }").unwrap();
+        std::fs::write(dir.join("tools").join("init.lua"), "return { name = 'tools' }").unwrap();
+
+        let engine = LuaEngine::new().unwrap();
+        engine.add_module_dir(&dir).unwrap();
+        let mut host = FakeHost::default();
+        run(&engine, &mut host, "print(require('greet').hello(), require('tools').name)").unwrap();
+        assert_eq!(host.echoed, ["hi\ttools"]);
+        let error = run(&engine, &mut host, "require('missing')").unwrap_err();
+        assert!(error_message(&error).contains(&format!("{}/missing.lua", dir.display())));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("Grep! foo bar"), ("Grep", true, "foo bar"));
+        assert_eq!(split_command("hello"), ("hello", false, ""));
+        assert_eq!(split_command("Fmt  x "), ("Fmt", false, "x"));
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("*.rs", "/home/me/src/main.rs"));
+        assert!(pattern_matches("*.toml,*.rs", "main.rs"));
+        assert!(!pattern_matches("*.rs", "main.rsx"));
+        assert!(pattern_matches("src/*.rs", "src/main.rs"));
+        assert!(!pattern_matches("src/*.rs", "tests/keys.rs"));
+        assert!(pattern_matches("?ain.rs", "main.rs"));
+        assert!(pattern_matches("*:insert", "normal:insert"));
+        assert!(pattern_matches("*", ""));
+    }
+
+    #[test]
+    fn test_event_names() {
+        assert_eq!(Event::from_name("bufwritepre"), Some(Event::BufWritePre));
+        assert_eq!(Event::from_name("BufReadPost"), Some(Event::BufRead));
+        assert_eq!(Event::from_name("BufSave"), None);
+    }
+}
@@ -3,34 +3,40 @@
 //! Born from the union of Vim's modal efficiency and Emacs' extensibility.
 //! Created by Egon and Aibotix.
 
-mod buffer;
-mod config;
-mod cursor;
-mod buffer_manager;
-mod editor;
-mod gpu_info;
-mod history;
-mod mode;
-mod perf;
-mod register;
-mod search;
-mod selection;
-mod syntax;
-mod view;
-
 use anyhow::Result;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyModifiers},
+    event::Event,
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use quirks::events::{self, EventLoop};
+use quirks::{editor, perf, script, view, Config};
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::{env, io};
 
 fn main() -> Result<()> {
-    // Get file argument if provided
+    // Get file arguments if provided; `-d a b` opens a side-by-side diff
     let args: Vec<String> = env::args().collect();
-    let file_path = args.get(1).map(|s| s.as_str());
+    let (file_path, diff_path) = match args.get(1).map(|s| s.as_str()) {
+        Some("-d") => match (args.get(2), args.get(3)) {
+            (Some(a), Some(b)) => (Some(a.as_str()), Some(b.as_str())),
+            _ => anyhow::bail!("usage: quirks -d <file> <file>"),
+        },
+        // Scripts run with the default options unless --config asks for
+        // the user's, so they pass or fail the same on every machine
+        Some("--script") => {
+            let user_config = args.get(2).is_some_and(|arg| arg == "--config");
+            let rest = &args[if user_config { 3 } else { 2 }..];
+            match rest.first() {
+                Some(script) => {
+                    let config = if user_config { Config::load() } else { Config::default() };
+                    return run_script(script, rest.get(1).map(|s| s.as_str()), config);
+                }
+                None => anyhow::bail!("usage: quirks --script [--config] <script> [file]"),
+            }
+        }
+        path => (path, None),
+    };
 
     // Setup terminal
     enable_raw_mode()?;
@@ -39,14 +45,22 @@ fn main() -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Create editor
+    // Create editor; input and background work arrive through `events`
+    let events = EventLoop::new();
     let mut editor = editor::Editor::new();
+    editor.set_waker(events.waker());
     if let Some(path) = file_path {
-        editor.open_file(path)?;
+        editor.load_file(path)?;
+    }
+    if let Some(path) = diff_path {
+        editor.diff_split(path);
     }
 
     // Main loop
-    let result = run_editor(&mut terminal, &mut editor);
+    events.read_input();
+    let result = run_editor(&mut terminal, &mut editor, &events);
+    // Losing saved registers and marks is not worth failing the exit for
+    let _ = editor.save_state();
 
     // Restore terminal
     disable_raw_mode()?;
@@ -56,23 +70,63 @@ fn main() -> Result<()> {
     result
 }
 
+/// Run a key-sequence script without a terminal, failing on the first
+/// expectation that does not hold
+fn run_script(script_path: &str, file_path: Option<&str>, config: Config) -> Result<()> {
+    let script = std::fs::read_to_string(script_path)?;
+    let mut editor = editor::Editor::headless(config);
+    if let Some(path) = file_path {
+        editor.open_file(path)?;
+    }
+    match script::run(&mut editor, &script) {
+        Ok(checked) => {
+            println!("{}: {} expectations passed", script_path, checked);
+            Ok(())
+        }
+        Err(e) => anyhow::bail!("{}: {}", script_path, e),
+    }
+}
+
 fn run_editor(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     editor: &mut editor::Editor,
+    events: &EventLoop,
 ) -> Result<()> {
+    let mut drawn = None;
     loop {
-        // Draw
-        terminal.draw(|frame| {
-            view::render(frame, editor);
-        })?;
+        // Draw, unless nothing visible changed since the last frame
+        let state = editor.render_state();
+        if state.needs_redraw(drawn.as_ref()) {
+            let timer = editor.profiling().then(|| perf::PerfTimer::new("render"));
+            terminal.draw(|frame| {
+                view::render(frame, editor);
+            })?;
+            if let Some(timer) = timer {
+                editor.profile(perf::Phase::Render, timer.elapsed());
+                editor.end_frame();
+            }
+            drawn = Some(state);
+        }
 
-        // Handle input
-        if let Event::Key(key) = event::read()? {
-            // Ctrl+Q to quit (always)
-            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('q') {
-                break;
+        // Sleep until input comes or background work has results, waking
+        // up for key sequence timeouts, the key hint popup and idle work
+        // such as refreshing git signs
+        let event = match events.next(editor.next_wakeup()) {
+            Some(events::Event::Input(event)) => event,
+            Some(events::Event::InputError(e)) => return Err(e.into()),
+            Some(events::Event::Wake) | None => {
+                if editor.tick() {
+                    break;
+                }
+                continue;
             }
+        };
 
+        // Handle input
+        if let Event::Resize(..) = event {
+            drawn = None;
+        }
+        if let Event::Key(key) = event {
             // Pass to editor
             if editor.handle_key(key) {
                 break;
@@ -0,0 +1,107 @@
+//! Marks for Quirks
+//!
+//! `m{a-z}` marks a position in the current file and `m{A-Z}` a position
+//! together with its file, so `'A` can jump across files. The editor sets
+//! `^` itself where insert mode was last left in each file and `"` where
+//! the cursor was when the file was last left. Marks stay on their line
+//! and column while the text around them is edited.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A marked position; `file` is None for a buffer that was never saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark {
+    pub file: Option<PathBuf>,
+    /// 0-based line
+    pub line: usize,
+    /// 0-based column
+    pub col: usize,
+}
+
+/// Mark the editor keeps where insert mode was last left (`'^`, `gi`)
+pub const LAST_INSERT: char = '^';
+
+/// Mark the editor keeps where the cursor was when the file was last left
+/// (`'"`), to return there when it is opened again
+pub const LAST_POSITION: char = '"';
+
+/// Lowercase marks per file and uppercase file marks
+#[derive(Debug, Default)]
+pub struct Marks {
+    local: BTreeMap<(Option<PathBuf>, char), Mark>,
+    global: BTreeMap<char, Mark>,
+}
+
+impl Marks {
+    /// Set mark `name` in `file`; false if `name` is not a letter,
+    /// `LAST_INSERT` or `LAST_POSITION`
+    pub fn set(&mut self, name: char, file: Option<&Path>, line: usize, col: usize) -> bool {
+        let mark = Mark { file: file.map(Path::to_path_buf), line, col };
+        if name.is_ascii_lowercase() || name == LAST_INSERT || name == LAST_POSITION {
+            self.local.insert((mark.file.clone(), name), mark);
+        } else if name.is_ascii_uppercase() {
+            self.global.insert(name, mark);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Mark `name` as seen from `file`
+    pub fn get(&self, name: char, file: Option<&Path>) -> Option<&Mark> {
+        if name.is_ascii_uppercase() {
+            self.global.get(&name)
+        } else {
+            self.local.get(&(file.map(Path::to_path_buf), name))
+        }
+    }
+
+    /// Marks usable from `file`: its lowercase marks, then the file marks
+    pub fn list(&self, file: Option<&Path>) -> Vec<(char, &Mark)> {
+        self.local.iter()
+            .filter(|((mark_file, _), _)| mark_file.as_deref() == file)
+            .map(|((_, name), mark)| (*name, mark))
+            .chain(self.global.iter().map(|(name, mark)| (*name, mark)))
+            .collect()
+    }
+
+    /// Every mark that belongs to a file on disk, for saving
+    pub fn all(&self) -> Vec<(char, &Mark)> {
+        self.local.iter()
+            .map(|((_, name), mark)| (*name, mark))
+            .chain(self.global.iter().map(|(name, mark)| (*name, mark)))
+            .filter(|(_, mark)| mark.file.is_some())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_and_file_marks() {
+        let mut marks = Marks::default();
+        let (a, b) = (Path::new("/tmp/a.rs"), Path::new("/tmp/b.rs"));
+        assert!(marks.set('x', Some(a), 3, 1));
+        assert!(marks.set('x', Some(b), 7, 0));
+        assert!(marks.set('A', Some(a), 10, 2));
+        assert!(!marks.set('1', Some(a), 0, 0));
+
+        assert_eq!(marks.get('x', Some(a)).map(|m| m.line), Some(3));
+        assert_eq!(marks.get('x', Some(b)).map(|m| m.line), Some(7));
+        assert_eq!(marks.get('x', None), None);
+        assert_eq!(marks.get('A', Some(b)).and_then(|m| m.file.as_deref()), Some(a));
+
+        let names: Vec<char> = marks.list(Some(b)).iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!['x', 'A']);
+        assert_eq!(marks.all().len(), 3);
+
+        assert!(marks.set(LAST_INSERT, Some(a), 2, 5));
+        assert_eq!(marks.get(LAST_INSERT, Some(a)).map(|m| (m.line, m.col)), Some((2, 5)));
+        assert_eq!(marks.get(LAST_INSERT, Some(b)), None);
+        assert!(marks.set(LAST_POSITION, Some(b), 8, 0));
+        assert_eq!(marks.get(LAST_POSITION, Some(b)).map(|m| m.line), Some(8));
+    }
+}
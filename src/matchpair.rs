@@ -0,0 +1,364 @@
+//! Matching pairs for `%` beyond brackets
+//!
+//! Like Vim's matchit plugin, `%` on a word that opens, continues or closes
+//! a construct jumps to the next word of the same construct: `#if`, `#elif`,
+//! `#else` and `#endif` in C, `if`, `elif`, `else` and `fi` in shell
+//! scripts, an HTML tag to its closing tag and back, a Rust `match` through
+//! its arms to the closing brace. Which constructs a language has comes
+//! from its SyntaxDef. Columns are in characters.
+
+use crate::syntax::SyntaxDef;
+
+/// Line and column
+type Pos = (usize, usize);
+
+/// HTML elements that never have a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta",
+    "source", "track", "wbr",
+];
+
+/// Where `%` goes from `pos` in `text`, or None if the cursor is not on a
+/// word or tag of one of the syntax's constructs
+pub fn find(syntax: &SyntaxDef, text: &str, pos: Pos) -> Option<Pos> {
+    let lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+    syntax.match_words.iter()
+        .find_map(|group| match_words(group, syntax.comment_single, &lines, pos))
+        .or_else(|| {
+            syntax.match_arms.iter()
+                .find_map(|&(keyword, marker)| match_arms(syntax, keyword, marker, &lines, pos))
+        })
+        .or_else(|| if syntax.match_tags { match_tags(&lines, pos) } else { None })
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether `word` is in `line` at `col` and not part of a longer word
+fn word_at(line: &[char], col: usize, word: &str) -> bool {
+    let len = word.chars().count();
+    let matches = col + len <= line.len() && line[col..col + len].iter().copied().eq(word.chars());
+    let first = word.chars().next().is_some_and(is_word);
+    let last = word.chars().last().is_some_and(is_word);
+    matches
+        && !(first && col > 0 && is_word(line[col - 1]))
+        && !(last && line.get(col + len).copied().is_some_and(is_word))
+}
+
+/// Whether `line` starts with `prefix` at `col`
+fn starts_at(line: &[char], col: usize, prefix: &str) -> bool {
+    line.get(col..).is_some_and(|rest| rest.iter().copied().take(prefix.chars().count()).eq(prefix.chars()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Open,
+    Middle,
+    Close,
+}
+
+/// `%` between the words of a matchit-style group such as
+/// `if:elif|else:fi`: from an opening or middle word to the next word of
+/// the same construct, from a closing word back to the opening one
+fn match_words(group: &str, comment: Option<&str>, lines: &[Vec<char>], (line, col): Pos) -> Option<Pos> {
+    let parts: Vec<Vec<&str>> = group.split(':').map(|part| part.split('|').collect()).collect();
+    if parts.len() < 2 {
+        return None;
+    }
+    let mut tokens: Vec<(Pos, usize, Part)> = Vec::new();
+    for (n, chars) in lines.iter().enumerate() {
+        for c in 0..chars.len() {
+            if comment.is_some_and(|comment| starts_at(chars, c, comment)) {
+                break;
+            }
+            for (i, words) in parts.iter().enumerate() {
+                let part = match i {
+                    0 => Part::Open,
+                    _ if i == parts.len() - 1 => Part::Close,
+                    _ => Part::Middle,
+                };
+                if let Some(word) = words.iter().find(|word| word_at(chars, c, word)) {
+                    tokens.push(((n, c), word.chars().count(), part));
+                }
+            }
+        }
+    }
+    let idx = tokens.iter().position(|&((l, c), len, _)| l == line && (c..c + len).contains(&col))?;
+    let mut depth = 0;
+    if tokens[idx].2 == Part::Close {
+        for &(pos, _, part) in tokens[..idx].iter().rev() {
+            match part {
+                Part::Close => depth += 1,
+                Part::Open if depth == 0 => return Some(pos),
+                Part::Open => depth -= 1,
+                Part::Middle => {}
+            }
+        }
+    } else {
+        for &(pos, _, part) in &tokens[idx + 1..] {
+            match part {
+                Part::Open => depth += 1,
+                Part::Middle | Part::Close if depth == 0 => return Some(pos),
+                Part::Close => depth -= 1,
+                Part::Middle => {}
+            }
+        }
+    }
+    None
+}
+
+/// A place `%` stops at: where it is and the columns after it on the same
+/// line that count as being on it
+#[derive(Debug, Clone, Copy)]
+struct Stop {
+    pos: Pos,
+    end: usize,
+}
+
+/// `%` through a block of arms: from `keyword` to the first arm, from
+/// each arm to the next and from the last one to the closing brace. The
+/// cursor may be anywhere from the start of an arm to its `marker` on the
+/// same line.
+fn match_arms(syntax: &SyntaxDef, keyword: &str, marker: &str, lines: &[Vec<char>], (line, col): Pos) -> Option<Pos> {
+    // The nearest block containing the cursor is the innermost one
+    let len = keyword.chars().count();
+    for (n, chars) in lines.iter().enumerate().take(line + 1).rev() {
+        let last = if n == line { (col + 1).min(chars.len()) } else { chars.len() };
+        for c in (0..last).rev() {
+            if !word_at(chars, c, keyword) {
+                continue;
+            }
+            let Some(stops) = arm_stops(syntax, marker, lines, (n, c + len)) else { continue };
+            let stops: Vec<Stop> = std::iter::once(Stop { pos: (n, c), end: c + len }).chain(stops).collect();
+            let on = stops[..stops.len() - 1].iter().position(|stop| {
+                stop.pos.0 == line && (stop.pos.1..stop.end).contains(&col)
+            });
+            if let Some(i) = on {
+                return Some(stops[i + 1].pos);
+            }
+        }
+    }
+    None
+}
+
+/// The arms of the block that starts after `from`, then its closing
+/// brace. None if no block follows.
+fn arm_stops(syntax: &SyntaxDef, marker: &str, lines: &[Vec<char>], from: Pos) -> Option<Vec<Stop>> {
+    let markers: Vec<&str> = marker.split('|').collect();
+    // A marker that is a word (case) starts the arm itself; one that is
+    // not (=>) follows the pattern the arm starts with
+    let word_marker = markers.iter().all(|marker| marker.chars().all(is_word));
+    let mut stops = Vec::new();
+    let mut depth = 0usize;
+    let mut arm_start: Option<Pos> = None;
+    let mut expecting = false;
+    let mut string: Option<char> = None;
+    for (n, chars) in lines.iter().enumerate().skip(from.0) {
+        let mut c = if n == from.0 { from.1 } else { 0 };
+        while c < chars.len() {
+            let ch = chars[c];
+            if let Some(delimiter) = string {
+                if ch == '\\' {
+                    c += 1;
+                } else if ch == delimiter {
+                    string = None;
+                }
+                c += 1;
+                continue;
+            }
+            if syntax.comment_single.is_some_and(|comment| starts_at(chars, c, comment)) {
+                break;
+            }
+            if ch.is_whitespace() {
+                c += 1;
+                continue;
+            }
+            if depth == 1 && ch == ',' {
+                expecting = true;
+                c += 1;
+                continue;
+            }
+            if depth == 1 && expecting {
+                arm_start = Some((n, c));
+                expecting = false;
+            }
+            if syntax.string_delimiters.contains(&ch) {
+                string = Some(ch);
+                c += 1;
+                continue;
+            }
+            if depth == 1 {
+                if let Some(found) = markers.iter().find(|marker| word_at(chars, c, marker)) {
+                    let len = found.chars().count();
+                    let pos = if word_marker { (n, c) } else { arm_start.take().unwrap_or((n, c)) };
+                    let end = if pos.0 == n { c + len } else { lines[pos.0].len() };
+                    stops.push(Stop { pos, end });
+                    c += len;
+                    continue;
+                }
+            }
+            match ch {
+                '{' | '(' | '[' => {
+                    // Whatever comes before the block's brace is not part of it
+                    if depth == 0 && ch == '{' {
+                        expecting = true;
+                    }
+                    if depth > 0 || ch == '{' {
+                        depth += 1;
+                    }
+                }
+                '}' | ')' | ']' if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        stops.push(Stop { pos: (n, c), end: c + 1 });
+                        return Some(stops);
+                    }
+                    if depth == 1 && ch == '}' {
+                        expecting = true;
+                    }
+                }
+                // The statement ended without a block
+                ';' | '}' if depth == 0 => return None,
+                _ => {}
+            }
+            c += 1;
+        }
+    }
+    None
+}
+
+/// An opening or closing tag: where its `<` is, up to the end of its name
+#[derive(Debug)]
+struct Tag {
+    pos: Pos,
+    end: usize,
+    name: String,
+    close: bool,
+}
+
+/// The tags of an HTML or XML document, leaving out self-closing and void
+/// elements that have no closing tag
+fn tags(lines: &[Vec<char>]) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for (n, chars) in lines.iter().enumerate() {
+        for c in 0..chars.len() {
+            if chars[c] != '<' {
+                continue;
+            }
+            let close = chars.get(c + 1) == Some(&'/');
+            let start = c + 1 + usize::from(close);
+            if !chars.get(start).is_some_and(|ch| ch.is_alphabetic()) {
+                continue;
+            }
+            let end = start + chars[start..].iter()
+                .take_while(|&&ch| is_word(ch) || matches!(ch, '-' | ':' | '.'))
+                .count();
+            let name: String = chars[start..end].iter().collect::<String>().to_lowercase();
+            if !close && (VOID_ELEMENTS.contains(&name.as_str()) || self_closing(lines, (n, end))) {
+                continue;
+            }
+            tags.push(Tag { pos: (n, c), end, name, close });
+        }
+    }
+    tags
+}
+
+/// Whether the tag whose name ends at `from` ends with `/>`
+fn self_closing(lines: &[Vec<char>], from: Pos) -> bool {
+    let mut previous = ' ';
+    for (n, chars) in lines.iter().enumerate().skip(from.0) {
+        let start = if n == from.0 { from.1 } else { 0 };
+        for &ch in &chars[start.min(chars.len())..] {
+            if ch == '>' {
+                return previous == '/';
+            }
+            previous = ch;
+        }
+    }
+    false
+}
+
+/// `%` from an opening tag to its closing tag and back
+fn match_tags(lines: &[Vec<char>], (line, col): Pos) -> Option<Pos> {
+    let tags = tags(lines);
+    let idx = tags.iter().position(|tag| tag.pos.0 == line && (tag.pos.1..tag.end).contains(&col))?;
+    let tag = &tags[idx];
+    let same = |other: &&Tag| other.name == tag.name;
+    let mut depth = 0;
+    if tag.close {
+        for other in tags[..idx].iter().rev().filter(same) {
+            match other.close {
+                true => depth += 1,
+                false if depth == 0 => return Some(other.pos),
+                false => depth -= 1,
+            }
+        }
+    } else {
+        for other in tags[idx + 1..].iter().filter(same) {
+            match other.close {
+                false => depth += 1,
+                true if depth == 0 => return Some(other.pos),
+                true => depth -= 1,
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::Highlighter;
+
+    fn jump(syntax: &str, text: &str, pos: Pos) -> Option<Pos> {
+        let mut highlighter = Highlighter::new();
+        highlighter.set_syntax_by_name(syntax);
+        find(highlighter.current_syntax()?, text, pos)
+    }
+
+    #[test]
+    fn test_match_words() {
+        let c = "#if A\n#ifdef B\n#endif\n#elif C\n#else\n#endif";
+        assert_eq!(jump("c", c, (0, 1)), Some((3, 0)));
+        assert_eq!(jump("c", c, (3, 0)), Some((4, 0)));
+        assert_eq!(jump("c", c, (4, 0)), Some((5, 0)));
+        assert_eq!(jump("c", c, (5, 3)), Some((0, 0)));
+        assert_eq!(jump("c", c, (1, 0)), Some((2, 0)));
+        assert_eq!(jump("c", c, (0, 4)), None);
+
+        let sh = "if a; then\n  echo # fi\nelif b; then\n  :\nfi";
+        assert_eq!(jump("sh", sh, (0, 0)), Some((2, 0)));
+        assert_eq!(jump("sh", sh, (2, 1)), Some((4, 0)));
+        assert_eq!(jump("sh", sh, (4, 1)), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_match_arms() {
+        let rust = "match x {\n    Some(y) => {\n        if y { 1 } else { 2 }\n    }\n    None => \"}\",\n}";
+        assert_eq!(jump("rs", rust, (0, 2)), Some((1, 4)));
+        assert_eq!(jump("rs", rust, (1, 12)), Some((4, 4)));
+        assert_eq!(jump("rs", rust, (4, 6)), Some((5, 0)));
+        assert_eq!(jump("rs", rust, (2, 8)), None);
+
+        let one_line = "let n = match c { 'a' => 1, _ => 0 };";
+        assert_eq!(jump("rs", one_line, (0, 8)), Some((0, 18)));
+        assert_eq!(jump("rs", one_line, (0, 18)), Some((0, 28)));
+        assert_eq!(jump("rs", one_line, (0, 28)), Some((0, 35)));
+
+        let js = "switch (k) {\n  case 1:\n    break;\n  default:\n}";
+        assert_eq!(jump("js", js, (0, 0)), Some((1, 2)));
+        assert_eq!(jump("js", js, (1, 3)), Some((3, 2)));
+        assert_eq!(jump("js", js, (3, 2)), Some((4, 0)));
+    }
+
+    #[test]
+    fn test_match_tags() {
+        let html = "<div class=\"a\">\n  <div><br><img src=x />\n  </div>\n</DIV>";
+        assert_eq!(jump("html", html, (0, 2)), Some((3, 0)));
+        assert_eq!(jump("html", html, (3, 1)), Some((0, 0)));
+        assert_eq!(jump("html", html, (1, 3)), Some((2, 2)));
+        assert_eq!(jump("html", html, (1, 8)), None);
+        assert_eq!(jump("rs", html, (0, 0)), None);
+    }
+}
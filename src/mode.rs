@@ -18,8 +18,12 @@ pub enum Mode {
     VisualLine,
     /// Visual Block mode - block/column selection (Ctrl+V)
     VisualBlock,
-    /// Help mode - shows help overlay
-    Help,
+    /// Explorer mode - keys navigate the file tree sidebar
+    Explorer,
+    /// Picker mode - typing filters the fuzzy finder overlay
+    Picker,
+    /// Terminal mode - keys go to the embedded terminal
+    Terminal,
 }
 
 impl Mode {
@@ -33,7 +37,9 @@ impl Mode {
             Mode::Visual => "VISUAL",
             Mode::VisualLine => "V-LINE",
             Mode::VisualBlock => "V-BLOCK",
-            Mode::Help => "HELP",
+            Mode::Explorer => "EXPLORER",
+            Mode::Picker => "PICKER",
+            Mode::Terminal => "TERMINAL",
         }
     }
 
@@ -45,7 +51,9 @@ impl Mode {
             Mode::Command => CursorStyle::Block,
             Mode::Search => CursorStyle::Block,
             Mode::Visual | Mode::VisualLine | Mode::VisualBlock => CursorStyle::Block,
-            Mode::Help => CursorStyle::Block,
+            Mode::Explorer => CursorStyle::Block,
+            Mode::Picker => CursorStyle::Bar,
+            Mode::Terminal => CursorStyle::Block,
         }
     }
 
@@ -65,7 +73,7 @@ mod tests {
         assert_eq!(Mode::Insert.display(), "INSERT");
         assert_eq!(Mode::Command.display(), "COMMAND");
         assert_eq!(Mode::Visual.display(), "VISUAL");
-        assert_eq!(Mode::Help.display(), "HELP");
+        assert_eq!(Mode::Terminal.display(), "TERMINAL");
     }
 
     #[test]
@@ -0,0 +1,304 @@
+//! Operators for Quirks
+//!
+//! An operator (d, c, y, gu, gU, g~, >, <, gq, gc) acts on a target: the
+//! visual selection, a text object (`diw`), a motion (`d3W`, `gUe`, `>j`)
+//! or its own key typed again for whole lines (`dd`, `gUU`, `gcc`).
+//! Motions are resolved here into the same `TextObject`s that `i` and `a`
+//! give, so the editor applies each operator in one place whatever it was
+//! given.
+
+use crate::buffer::Buffer;
+use crate::cursor::Cursor;
+use crate::textobject::{self, Scope, TextObject};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// d
+    Delete,
+    /// c
+    Change,
+    /// y
+    Yank,
+    /// gu
+    Lowercase,
+    /// gU
+    Uppercase,
+    /// g~
+    ToggleCase,
+    /// >
+    Indent,
+    /// <
+    Outdent,
+    /// gq
+    Format,
+    /// gc
+    Comment,
+}
+
+impl Operator {
+    /// The operator of key `c` in normal mode, or of `g` `c` with `after_g`
+    pub fn from_key(c: char, after_g: bool) -> Option<Self> {
+        let op = match (after_g, c) {
+            (false, 'd') => Operator::Delete,
+            (false, 'c') => Operator::Change,
+            (false, 'y') => Operator::Yank,
+            (false, '>') => Operator::Indent,
+            (false, '<') => Operator::Outdent,
+            (true, 'u') => Operator::Lowercase,
+            (true, 'U') => Operator::Uppercase,
+            (true, '~') => Operator::ToggleCase,
+            (true, 'q') => Operator::Format,
+            (true, 'c') => Operator::Comment,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// The operator of key `c` in visual mode, where x deletes too and
+    /// the case operators need no g (u, U, ~)
+    pub fn from_visual_key(c: char) -> Option<Self> {
+        match c {
+            'x' => Some(Operator::Delete),
+            'u' => Some(Operator::Lowercase),
+            'U' => Some(Operator::Uppercase),
+            '~' => Some(Operator::ToggleCase),
+            _ => Self::from_key(c, false),
+        }
+    }
+
+    /// The operator's last key; typed again it takes whole lines (dd,
+    /// gUU, gcc)
+    pub fn key(self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Change => 'c',
+            Operator::Yank => 'y',
+            Operator::Lowercase => 'u',
+            Operator::Uppercase => 'U',
+            Operator::ToggleCase => '~',
+            Operator::Indent => '>',
+            Operator::Outdent => '<',
+            Operator::Format => 'q',
+            Operator::Comment => 'c',
+        }
+    }
+
+    /// Whether it starts with g (gu, gq, gc), so gugu works like guu
+    pub fn after_g(self) -> bool {
+        !matches!(self, Operator::Delete | Operator::Change | Operator::Yank | Operator::Indent | Operator::Outdent)
+    }
+}
+
+/// Lines `line` to `line + count - 1`, for an operator typed twice (3dd)
+pub fn lines(buffer: &Buffer, line: usize, count: usize) -> TextObject {
+    let last = buffer.line_count().saturating_sub(1);
+    TextObject::Lines { start: line, end: (line + count.max(1) - 1).min(last) }
+}
+
+/// What the motion `key` covers from `pos`, for an operator: h l 0 ^ $,
+/// w W b B e E, j k G, `g` for gg, and { }. None for a key that is no
+/// motion or a motion that goes nowhere.
+pub fn motion(buffer: &Buffer, (line, col): (usize, usize), key: char, count: Option<usize>) -> Option<TextObject> {
+    let n = count.unwrap_or(1).max(1);
+    let last = buffer.line_count().saturating_sub(1);
+    let len = buffer.line_len(line);
+    // `$` leaves the cursor past the end of the line
+    let col = col.min(len);
+    let here = (line, col);
+    match key {
+        'h' => exclusive(buffer, (line, col.saturating_sub(n)), here),
+        'l' => exclusive(buffer, here, (line, (col + n).min(len))),
+        '0' => exclusive(buffer, (line, 0), here),
+        '^' => {
+            let first = buffer.line(line).chars().take_while(|c| c.is_whitespace()).count();
+            exclusive(buffer, here.min((line, first)), here.max((line, first)))
+        }
+        '$' => {
+            let end = (line + n - 1).min(last);
+            (end > line || col < len).then_some(TextObject::Chars { start: here, end: (end, buffer.line_len(end)) })
+        }
+        'w' | 'W' => {
+            let mut cursor = Cursor::at(line, col);
+            for step in 1..=n {
+                let from = cursor;
+                if !cursor.word_forward(buffer, key == 'W') {
+                    // No word after: up to the end of the buffer
+                    return Some(TextObject::Chars { start: here, end: (last, buffer.line_len(last)) });
+                }
+                if step == n && cursor.line > from.line {
+                    // The last word of a line ends the text, not the line
+                    // break after it (dw on the last word keeps the line)
+                    let end = (from.line, buffer.line_len(from.line));
+                    if from.line == line && col >= end.1 {
+                        return None;
+                    }
+                    return Some(TextObject::Chars { start: here, end });
+                }
+            }
+            exclusive(buffer, here, (cursor.line, cursor.col))
+        }
+        'b' | 'B' => {
+            let mut cursor = Cursor::at(line, col);
+            for _ in 0..n {
+                cursor.word_backward(buffer, key == 'B');
+            }
+            exclusive(buffer, (cursor.line, cursor.col), here)
+        }
+        'e' | 'E' => {
+            let mut cursor = Cursor::at(line, col);
+            for _ in 0..n {
+                cursor.word_end(buffer, key == 'E');
+            }
+            ((cursor.line, cursor.col) >= here).then_some(TextObject::Chars { start: here, end: (cursor.line, cursor.col) })
+        }
+        'j' => (line < last).then_some(TextObject::Lines { start: line, end: (line + n).min(last) }),
+        'k' => (line > 0).then_some(TextObject::Lines { start: line.saturating_sub(n), end: line }),
+        'G' | 'g' => {
+            let default = if key == 'G' { last } else { 0 };
+            let target = count.map_or(default, |n| n.saturating_sub(1).min(last));
+            Some(TextObject::Lines { start: line.min(target), end: line.max(target) })
+        }
+        '}' => {
+            let blank = |idx: usize| buffer.line(idx).trim().is_empty();
+            let mut end = line;
+            for _ in 0..n {
+                while end < last && blank(end + 1) {
+                    end += 1;
+                }
+                while end < last && !blank(end + 1) {
+                    end += 1;
+                }
+            }
+            Some(TextObject::Lines { start: line, end })
+        }
+        '{' => {
+            let blank = |idx: usize| buffer.line(idx).trim().is_empty();
+            let mut start = line;
+            for _ in 0..n {
+                while start > 0 && blank(start - 1) {
+                    start -= 1;
+                }
+                while start > 0 && !blank(start - 1) {
+                    start -= 1;
+                }
+            }
+            Some(TextObject::Lines { start, end: line })
+        }
+        _ => None,
+    }
+}
+
+/// What cw and cW change: like ce, to the end of the word, but from the
+/// last character of a word only that character. On white space they
+/// are the w and W motions.
+pub fn change_word(buffer: &Buffer, pos: (usize, usize), big: bool, count: Option<usize>) -> Option<TextObject> {
+    let on_word = buffer.line(pos.0).chars().nth(pos.1).is_some_and(|c| !c.is_whitespace());
+    let kind = if big { 'W' } else { 'w' };
+    if !on_word {
+        return motion(buffer, pos, kind, count);
+    }
+    let Some(TextObject::Chars { end, .. }) = textobject::select(buffer, pos, kind, Scope::Inner) else { return None };
+    let mut cursor = Cursor::at(end.0, end.1);
+    for _ in 1..count.unwrap_or(1).max(1) {
+        cursor.word_end(buffer, big);
+    }
+    Some(TextObject::Chars { start: pos, end: (cursor.line, cursor.col) })
+}
+
+/// `lines` commented out with `leader` (gc), each after the indentation
+/// the lines share, or uncommented if every line with text is a comment
+/// already. Blank lines are left alone.
+pub fn toggle_comment(lines: &[String], leader: &str) -> Vec<String> {
+    let text = || lines.iter().filter(|line| !line.trim().is_empty());
+    let commented = text().all(|line| line.trim_start().starts_with(leader));
+    let indent = text().map(|line| line.len() - line.trim_start().len()).min().unwrap_or(0);
+    lines.iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.clone()
+            } else if commented {
+                let at = line.len() - line.trim_start().len();
+                let rest = &line[at + leader.len()..];
+                format!("{}{}", &line[..at], rest.strip_prefix(' ').unwrap_or(rest))
+            } else {
+                format!("{}{} {}", &line[..indent], leader, &line[indent..])
+            }
+        })
+        .collect()
+}
+
+/// Characters from `start` up to but not including `end`. An end at the
+/// start of a line stops at the end of the line before, so the line break
+/// is left alone; if `start` is not past the indentation either, whole
+/// lines are taken, as in Vim.
+pub fn exclusive(buffer: &Buffer, start: (usize, usize), end: (usize, usize)) -> Option<TextObject> {
+    if end <= start {
+        return None;
+    }
+    let end = if end.1 == 0 && end.0 > start.0 {
+        let indent = buffer.line(start.0).chars().take_while(|c| c.is_whitespace()).count();
+        if start.1 <= indent {
+            return Some(TextObject::Lines { start: start.0, end: end.0 - 1 });
+        }
+        (end.0 - 1, buffer.line_len(end.0 - 1))
+    } else {
+        (end.0, end.1 - 1)
+    };
+    (end >= start).then_some(TextObject::Chars { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(start: (usize, usize), end: (usize, usize)) -> Option<TextObject> {
+        Some(TextObject::Chars { start, end })
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let buffer = Buffer::from_text("foo.bar baz\nqux");
+        assert_eq!(motion(&buffer, (0, 0), 'w', None), chars((0, 0), (0, 2)));
+        assert_eq!(motion(&buffer, (0, 0), 'W', None), chars((0, 0), (0, 7)));
+        // The last word of a line stops at its end
+        assert_eq!(motion(&buffer, (0, 8), 'w', None), chars((0, 8), (0, 11)));
+        assert_eq!(motion(&buffer, (0, 0), 'W', Some(2)), chars((0, 0), (0, 11)));
+        assert_eq!(motion(&buffer, (0, 0), 'W', Some(3)), chars((0, 0), (1, 3)));
+        assert_eq!(motion(&buffer, (1, 1), 'w', None), chars((1, 1), (1, 3)));
+        assert_eq!(motion(&buffer, (0, 0), 'E', None), chars((0, 0), (0, 6)));
+        assert_eq!(motion(&buffer, (0, 10), 'B', None), chars((0, 8), (0, 9)));
+        assert_eq!(motion(&buffer, (0, 0), 'b', None), None);
+        // cw stops at the end of the word, from its last character too
+        assert_eq!(change_word(&buffer, (0, 1), false, None), chars((0, 1), (0, 2)));
+        assert_eq!(change_word(&buffer, (0, 2), false, None), chars((0, 2), (0, 2)));
+        assert_eq!(change_word(&buffer, (0, 0), true, Some(2)), chars((0, 0), (0, 10)));
+    }
+
+    #[test]
+    fn test_line_motions() {
+        let buffer = Buffer::from_text("  a\nb\n\nc\nd");
+        assert_eq!(motion(&buffer, (0, 3), '^', None), chars((0, 2), (0, 2)));
+        assert_eq!(motion(&buffer, (0, 1), '$', None), chars((0, 1), (0, 3)));
+        assert_eq!(motion(&buffer, (0, 3), '$', None), None);
+        assert_eq!(motion(&buffer, (1, 0), 'j', Some(2)), Some(TextObject::Lines { start: 1, end: 3 }));
+        assert_eq!(motion(&buffer, (4, 0), 'j', None), None);
+        assert_eq!(motion(&buffer, (1, 0), 'G', None), Some(TextObject::Lines { start: 1, end: 4 }));
+        assert_eq!(motion(&buffer, (3, 0), 'g', Some(2)), Some(TextObject::Lines { start: 1, end: 3 }));
+        assert_eq!(motion(&buffer, (0, 0), '}', None), Some(TextObject::Lines { start: 0, end: 1 }));
+        assert_eq!(motion(&buffer, (4, 0), '{', None), Some(TextObject::Lines { start: 3, end: 4 }));
+        assert_eq!(lines(&buffer, 3, 5), TextObject::Lines { start: 3, end: 4 });
+        // To the start of a line from the indentation takes whole lines
+        assert_eq!(exclusive(&buffer, (0, 1), (2, 0)), Some(TextObject::Lines { start: 0, end: 1 }));
+        assert_eq!(exclusive(&buffer, (0, 3), (1, 0)), chars((0, 3), (0, 3)));
+    }
+
+    #[test]
+    fn test_toggle_comment() {
+        let lines: Vec<String> = ["    a", "", "  b"].iter().map(|line| line.to_string()).collect();
+        let commented = toggle_comment(&lines, "//");
+        assert_eq!(commented, vec!["  //   a", "", "  // b"]);
+        assert_eq!(toggle_comment(&commented, "//"), lines);
+        let mixed: Vec<String> = ["# a", "b"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(toggle_comment(&mixed, "#"), vec!["# # a", "# b"]);
+    }
+}
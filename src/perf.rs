@@ -1,8 +1,9 @@
-/// Performance monitoring utilities
+//! Performance monitoring utilities
 
+use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
-/// Time a block of code and warn if it exceeds threshold
+/// Time a block of code and log a warning if it exceeds threshold
 pub struct PerfTimer {
     name: &'static str,
     start: Instant,
@@ -43,16 +44,96 @@ impl Drop for PerfTimer {
     fn drop(&mut self) {
         let elapsed = self.elapsed();
         if elapsed.as_millis() > self.threshold_ms as u128 {
-            eprintln!(
-                "PERF WARNING: {} took {}ms (threshold: {}ms)",
+            crate::log::warn(format!(
+                "{} took {}ms (threshold: {}ms)",
                 self.name,
                 elapsed.as_millis(),
                 self.threshold_ms
-            );
+            ));
         }
     }
 }
 
+/// Frames the profiler keeps statistics over
+const FRAME_SAMPLES: usize = 120;
+
+/// Parts of a frame timed by the profiler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Handling the key that led to the frame
+    Input,
+    /// Drawing the frame, including highlighting
+    Render,
+    /// Syntax highlighting of the visible lines
+    Highlight,
+}
+
+impl Phase {
+    const ALL: [Phase; 3] = [Phase::Input, Phase::Render, Phase::Highlight];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Input => "input",
+            Phase::Render => "render",
+            Phase::Highlight => "highlight",
+        }
+    }
+}
+
+/// Per-frame timings for :profile
+#[derive(Debug, Default)]
+pub struct FrameProfiler {
+    /// Time spent in each phase of the frame being built
+    current: [Duration; 3],
+    /// Finished frames, oldest first
+    frames: VecDeque<[Duration; 3]>,
+}
+
+/// Summary of the recent frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub frames: usize,
+    /// Average frame time (input + render)
+    pub average: Duration,
+    /// 95th percentile frame time
+    pub p95: Duration,
+    /// Phase with the highest average, and that average
+    pub slowest: (Phase, Duration),
+}
+
+impl FrameProfiler {
+    /// Add time spent in a phase to the current frame
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        self.current[phase as usize] += elapsed;
+    }
+
+    /// Finish the current frame
+    pub fn end_frame(&mut self) {
+        if self.frames.len() == FRAME_SAMPLES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(std::mem::take(&mut self.current));
+    }
+
+    pub fn stats(&self) -> Option<FrameStats> {
+        let count = self.frames.len() as u32;
+        if count == 0 {
+            return None;
+        }
+        let mut totals: Vec<Duration> = self.frames.iter()
+            .map(|frame| frame[Phase::Input as usize] + frame[Phase::Render as usize])
+            .collect();
+        totals.sort();
+        let p95 = totals[(totals.len() * 95).div_ceil(100) - 1];
+        let average = totals.iter().sum::<Duration>() / count;
+        let slowest = Phase::ALL.into_iter()
+            .map(|phase| (phase, self.frames.iter().map(|frame| frame[phase as usize]).sum::<Duration>() / count))
+            .max_by_key(|(_, average)| *average)
+            .unwrap_or((Phase::Render, Duration::ZERO));
+        Some(FrameStats { frames: totals.len(), average, p95, slowest })
+    }
+}
+
 /// Macro for easy timing
 #[macro_export]
 macro_rules! time_it {
@@ -61,3 +142,25 @@ macro_rules! time_it {
         $block
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_stats() {
+        let mut profiler = FrameProfiler::default();
+        assert_eq!(profiler.stats(), None);
+        for ms in 1..=20 {
+            profiler.add(Phase::Input, Duration::from_millis(1));
+            profiler.add(Phase::Render, Duration::from_millis(ms));
+            profiler.add(Phase::Highlight, Duration::from_millis(ms / 2));
+            profiler.end_frame();
+        }
+        let stats = profiler.stats().unwrap();
+        assert_eq!(stats.frames, 20);
+        assert_eq!(stats.average, Duration::from_micros(11_500));
+        assert_eq!(stats.p95, Duration::from_millis(20));
+        assert_eq!(stats.slowest, (Phase::Render, Duration::from_micros(10_500)));
+    }
+}
@@ -0,0 +1,286 @@
+//! Fuzzy picker for Quirks
+//!
+//! An overlay that filters a list of candidates (files, buffers, lines)
+//! as the user types, fzf-style.
+
+use std::path::{Path, PathBuf};
+
+/// Maximum number of files collected for the file picker
+const MAX_FILES: usize = 10_000;
+
+/// What a picker is choosing between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerKind {
+    Files,
+    Buffers,
+    Lines,
+    /// Recently opened files
+    Recent,
+    /// Entries of the quickfix list
+    Quickfix,
+    /// Bookmarks of the current project
+    Bookmarks,
+}
+
+impl PickerKind {
+    pub fn title(&self) -> &'static str {
+        match self {
+            PickerKind::Files => "Files",
+            PickerKind::Buffers => "Buffers",
+            PickerKind::Lines => "Lines",
+            PickerKind::Recent => "Recent files",
+            PickerKind::Quickfix => "Quickfix",
+            PickerKind::Bookmarks => "Bookmarks",
+        }
+    }
+}
+
+/// Where accepting a candidate takes the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PickerTarget {
+    /// Open a file
+    File(PathBuf),
+    /// Switch to an open buffer by index
+    Buffer(usize),
+    /// Jump to a line in the current buffer (0-based)
+    Line(usize),
+    /// Jump to a quickfix entry by index
+    Quickfix(usize),
+    /// Jump to a bookmark by index
+    Bookmark(usize),
+}
+
+/// A candidate in the picker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickerItem {
+    /// Text shown and matched against
+    pub label: String,
+    pub target: PickerTarget,
+}
+
+/// A candidate that matches the current query
+#[derive(Debug, Clone)]
+pub struct PickerMatch {
+    /// Index into the picker's items
+    pub index: usize,
+    pub score: i64,
+    /// Char positions in the label that matched, for highlighting
+    pub positions: Vec<usize>,
+}
+
+/// Fuzzy picker state
+#[derive(Debug)]
+pub struct Picker {
+    kind: PickerKind,
+    items: Vec<PickerItem>,
+    query: String,
+    matches: Vec<PickerMatch>,
+    selected: usize,
+    /// Lines previewing the selected candidate, filled in by the editor
+    pub preview: Vec<String>,
+    /// Line in `preview` to highlight, if any
+    pub preview_line: Option<usize>,
+}
+
+impl Picker {
+    pub fn new(kind: PickerKind, items: Vec<PickerItem>) -> Self {
+        let mut picker = Self {
+            kind,
+            items,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            preview: Vec::new(),
+            preview_line: None,
+        };
+        picker.update_matches();
+        picker
+    }
+
+    pub fn kind(&self) -> PickerKind {
+        self.kind
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn items(&self) -> &[PickerItem] {
+        &self.items
+    }
+
+    /// Candidates matching the query, best first
+    pub fn matches(&self) -> &[PickerMatch] {
+        &self.matches
+    }
+
+    /// Index into `matches` of the highlighted candidate
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// The highlighted candidate
+    pub fn selected_item(&self) -> Option<&PickerItem> {
+        self.matches.get(self.selected).map(|m| &self.items[m.index])
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.query.push(c);
+        self.update_matches();
+    }
+
+    /// Delete the last query character, returns false if the query was empty
+    pub fn backspace(&mut self) -> bool {
+        let had_char = self.query.pop().is_some();
+        self.update_matches();
+        had_char
+    }
+
+    pub fn move_up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    fn update_matches(&mut self) {
+        let mut matches: Vec<PickerMatch> = self.items.iter().enumerate()
+            .filter_map(|(index, item)| {
+                fuzzy_match(&self.query, &item.label).map(|(score, positions)| PickerMatch {
+                    index,
+                    score,
+                    positions,
+                })
+            })
+            .collect();
+        // Stable sort keeps the original order among equal scores
+        matches.sort_by_key(|m| std::cmp::Reverse(m.score));
+        self.matches = matches;
+        self.selected = 0;
+    }
+}
+
+/// Score `candidate` against `pattern` as a subsequence match.
+///
+/// Matching is case-insensitive unless the pattern has an uppercase letter.
+/// Consecutive matches and matches at word starts score higher. Returns the
+/// score and the matched char positions, or None if it does not match.
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let case_sensitive = pattern.chars().any(|c| c.is_uppercase());
+    let normalize = |c: char| if case_sensitive { c } else { c.to_ascii_lowercase() };
+
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut positions: Vec<usize> = Vec::new();
+    let mut score = 0i64;
+    let mut pos = 0;
+
+    for p in pattern.chars().map(normalize) {
+        let found = (pos..chars.len()).find(|&i| normalize(chars[i]) == p)?;
+        score += 10;
+        if found == 0 {
+            score += 15;
+        } else {
+            let prev = chars[found - 1];
+            if matches!(prev, '/' | '_' | '-' | '.' | ' ') || (prev.is_lowercase() && chars[found].is_uppercase()) {
+                score += 12;
+            }
+        }
+        match positions.last() {
+            Some(&last) if last + 1 == found => score += 8,
+            Some(&last) => score -= (found - last - 1).min(10) as i64,
+            None => score -= found.min(10) as i64,
+        }
+        positions.push(found);
+        pos = found + 1;
+    }
+
+    // Prefer shorter candidates among otherwise equal matches
+    score -= (chars.len() / 8) as i64;
+    Some((score, positions))
+}
+
+/// Collect files below `root` for the file picker, skipping hidden
+/// directories and build output
+pub fn collect_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let path = entry.path();
+            if path.is_dir() {
+                if !name.starts_with('.') && name != "target" && name != "node_modules" {
+                    dirs.push(path);
+                }
+            } else {
+                files.push(path);
+                if files.len() >= MAX_FILES {
+                    return files;
+                }
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(label: &str) -> PickerItem {
+        PickerItem {
+            label: label.to_string(),
+            target: PickerTarget::Line(0),
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_match_subsequence() {
+        let (_, positions) = fuzzy_match("edr", "src/editor.rs").unwrap();
+        assert_eq!(positions, vec![4, 5, 9]);
+        assert!(fuzzy_match("xyz", "src/editor.rs").is_none());
+        assert!(fuzzy_match("", "anything").is_some());
+    }
+
+    #[test]
+    fn test_fuzzy_match_smart_case() {
+        assert!(fuzzy_match("readme", "README.md").is_some());
+        assert!(fuzzy_match("README", "readme.md").is_none());
+    }
+
+    #[test]
+    fn test_fuzzy_match_prefers_word_starts() {
+        let (boundary, _) = fuzzy_match("ed", "src/editor.rs").unwrap();
+        let (inner, _) = fuzzy_match("ed", "src/selected.rs").unwrap();
+        assert!(boundary > inner);
+    }
+
+    #[test]
+    fn test_picker_filtering() {
+        let mut picker = Picker::new(
+            PickerKind::Files,
+            vec![item("src/main.rs"), item("src/editor.rs"), item("README.md")],
+        );
+        assert_eq!(picker.matches().len(), 3);
+
+        picker.insert_char('e');
+        picker.insert_char('d');
+        assert_eq!(picker.selected_item().unwrap().label, "src/editor.rs");
+
+        assert!(picker.backspace());
+        assert!(picker.backspace());
+        assert!(!picker.backspace());
+        assert_eq!(picker.matches().len(), 3);
+    }
+}
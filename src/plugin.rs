@@ -2,6 +2,7 @@
 //!
 //! Provides a simple plugin system with hooks and events.
 
+use crate::error::QuirksError;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
@@ -73,14 +74,14 @@ impl PluginInfo {
 }
 
 /// Result of a plugin action
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum PluginResult {
     /// Action completed successfully
     Ok,
     /// Action completed with a message
     Message(String),
     /// Action failed with an error
-    Error(String),
+    Error(QuirksError),
     /// Request the editor to execute a command
     Command(String),
     /// Request to modify the buffer
@@ -141,7 +142,7 @@ impl PluginManager {
         
         // Check if already loaded
         if self.load_order.contains(&name) {
-            return PluginResult::Error(format!("Plugin '{}' already loaded", name));
+            return PluginResult::Error(QuirksError::PluginLoaded(name));
         }
         
         self.plugins.push(plugin);
@@ -165,7 +166,7 @@ impl PluginManager {
             self.load_order.remove(idx);
             result
         } else {
-            PluginResult::Error(format!("Plugin '{}' not found", name))
+            PluginResult::Error(QuirksError::PluginNotFound(name.to_string()))
         }
     }
 
@@ -399,6 +400,10 @@ mod tests {
         let result = manager.register(plugin);
         assert!(matches!(result, PluginResult::Ok));
         assert_eq!(manager.count(), 1);
+
+        let result = manager.register(Box::new(TestPlugin::new("test")));
+        assert!(matches!(result, PluginResult::Error(QuirksError::PluginLoaded(name)) if name == "test"));
+        assert_eq!(manager.count(), 1);
     }
 
     #[test]
@@ -0,0 +1,68 @@
+//! Project root detection for Quirks
+//!
+//! Finds the directory a file belongs to by walking up to the nearest
+//! directory containing a project marker such as `.git` or `Cargo.toml`.
+
+use std::path::{Path, PathBuf};
+
+/// Files or directories that mark the root of a project
+pub const ROOT_MARKERS: &[&str] = &[
+    ".git",
+    ".hg",
+    "Cargo.toml",
+    "package.json",
+    "go.mod",
+    "pyproject.toml",
+    "Makefile",
+];
+
+/// Find the nearest ancestor of `start` (inclusive) containing a root marker
+pub fn find_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .map(Path::to_path_buf)
+}
+
+/// Resolve a `:cd` argument against a base directory, expanding `~`
+pub fn resolve_dir(base: &Path, arg: &str) -> PathBuf {
+    let arg = arg.trim();
+    if arg.is_empty() || arg == "~" {
+        return dirs::home_dir().unwrap_or_else(|| base.to_path_buf());
+    }
+    if let Some(rest) = arg.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    base.join(arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_find_root() {
+        let root = std::env::temp_dir().join(format!("quirks-project-{}", std::process::id()));
+        let nested = root.join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join("Cargo.toml"), "").unwrap();
+
+        assert_eq!(find_root(&nested), Some(root.clone()));
+        assert_eq!(find_root(&root), Some(root.clone()));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_dir() {
+        let base = Path::new("/work");
+        assert_eq!(resolve_dir(base, "src"), PathBuf::from("/work/src"));
+        assert_eq!(resolve_dir(base, "/etc"), PathBuf::from("/etc"));
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(resolve_dir(base, ""), home);
+            assert_eq!(resolve_dir(base, "~/x"), home.join("x"));
+        }
+    }
+}
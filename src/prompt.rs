@@ -0,0 +1,133 @@
+//! Modal prompts for Quirks
+//!
+//! A prompt takes every key until it is answered: either one of a few
+//! choices, each picked by its first letter (`[y]es [n]o`), or a line of
+//! text. Actions that cannot be undone, such as quitting with unsaved
+//! changes or replacing a file, ask through one first. A prompt with body
+//! lines is drawn as a popup, a bare question in the command line.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Yes or no
+pub const YES_NO: &[&str] = &["yes", "no"];
+
+/// Save, throw away the changes, or stay
+pub const SAVE_DISCARD_CANCEL: &[&str] = &["save", "discard", "cancel"];
+
+/// How a prompt was answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    /// The first letter of the choice picked
+    Choice(char),
+    /// The text typed, on Enter
+    Text(String),
+    /// Esc
+    Cancel,
+}
+
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    question: String,
+    lines: Vec<String>,
+    choices: &'static [&'static str],
+    /// Text typed so far, for a prompt that asks for text
+    input: Option<String>,
+}
+
+impl Prompt {
+    /// Ask `question`, answered by one of `choices`; `lines` (such as the
+    /// files concerned) are shown with it
+    pub fn choose(question: impl Into<String>, lines: Vec<String>, choices: &'static [&'static str]) -> Self {
+        Self { question: question.into(), lines, choices, input: None }
+    }
+
+    /// Ask for a line of text, starting from `text`
+    pub fn input(question: impl Into<String>, text: &str) -> Self {
+        Self { question: question.into(), lines: Vec::new(), choices: &[], input: Some(text.to_string()) }
+    }
+
+    pub fn question(&self) -> &str {
+        &self.question
+    }
+
+    /// Body lines, shown in a popup; none for a command line prompt
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The text typed so far, for a text prompt
+    pub fn input_text(&self) -> Option<&str> {
+        self.input.as_deref()
+    }
+
+    /// The choices with their keys marked: `[y]es  [n]o`
+    pub fn footer(&self) -> String {
+        let choices: Vec<String> = self.choices.iter()
+            .map(|choice| {
+                let key = choice.chars().next().map_or(0, char::len_utf8);
+                format!("[{}]{}", &choice[..key], &choice[key..])
+            })
+            .collect();
+        choices.join("  ")
+    }
+
+    /// The prompt on one line, for the command line
+    pub fn line(&self) -> String {
+        match &self.input {
+            Some(text) => format!("{} {}", self.question, text),
+            None => format!("{} {}", self.question, self.footer()),
+        }
+    }
+
+    /// Take a key: the answer once it settles the prompt, None while it
+    /// stays open. Keys that are no choice are ignored.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Answer> {
+        if key.code == KeyCode::Esc || (key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c')) {
+            return Some(Answer::Cancel);
+        }
+        if let Some(text) = &mut self.input {
+            match key.code {
+                KeyCode::Enter => return Some(Answer::Text(std::mem::take(text))),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => text.clear(),
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => text.push(c),
+                _ => {}
+            }
+            return None;
+        }
+        let KeyCode::Char(c) = key.code else { return None };
+        let c = c.to_ascii_lowercase();
+        self.choices.iter().any(|choice| choice.starts_with(c)).then_some(Answer::Choice(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_choose() {
+        let mut prompt = Prompt::choose("Replace?", Vec::new(), &["yes", "no", "all", "quit", "last"]);
+        assert_eq!(prompt.line(), "Replace? [y]es  [n]o  [a]ll  [q]uit  [l]ast");
+        assert_eq!(prompt.handle_key(key(KeyCode::Char('x'))), None);
+        assert_eq!(prompt.handle_key(key(KeyCode::Enter)), None);
+        assert_eq!(prompt.handle_key(key(KeyCode::Char('A'))), Some(Answer::Choice('a')));
+        assert_eq!(prompt.handle_key(key(KeyCode::Esc)), Some(Answer::Cancel));
+    }
+
+    #[test]
+    fn test_input() {
+        let mut prompt = Prompt::input("Rename to:", "a.txt");
+        for code in [KeyCode::Backspace, KeyCode::Backspace, KeyCode::Backspace, KeyCode::Char('m'), KeyCode::Char('d')] {
+            assert_eq!(prompt.handle_key(key(code)), None);
+        }
+        assert_eq!(prompt.line(), "Rename to: a.md");
+        assert_eq!(prompt.handle_key(key(KeyCode::Enter)), Some(Answer::Text("a.md".to_string())));
+    }
+}
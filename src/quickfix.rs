@@ -0,0 +1,238 @@
+//! Quickfix list for Quirks
+//!
+//! Parses compiler output into file/line/column locations using Vim-style
+//! errorformat patterns, and tracks the current entry for :cnext/:cprev.
+
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// Default errorformat: `file:line:col: message` or `file:line: message`
+pub const DEFAULT_ERRORFORMAT: &str = "%f:%l:%c: %m,%f:%l: %m";
+
+/// A location from compiler output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuickfixEntry {
+    pub file: PathBuf,
+    /// 0-based line
+    pub line: usize,
+    /// 0-based column
+    pub col: usize,
+    /// 'e' for errors, 'w' for warnings, ' ' if unknown
+    pub kind: char,
+    pub message: String,
+}
+
+impl QuickfixEntry {
+    /// One-line summary for lists and messages
+    pub fn label(&self) -> String {
+        let kind = match self.kind {
+            'e' => " error:",
+            'w' => " warning:",
+            _ => "",
+        };
+        format!("{}:{}:{}:{} {}", self.file.display(), self.line + 1, self.col + 1, kind, self.message)
+    }
+}
+
+/// A compiled errorformat: comma-separated patterns tried in order
+#[derive(Debug)]
+pub struct ErrorFormat {
+    patterns: Vec<Regex>,
+}
+
+impl ErrorFormat {
+    /// Compile an errorformat string. Supported items are `%f` file, `%l`
+    /// line, `%c` column, `%m` message, `%t` type character and `%%`; `\,`
+    /// is a literal comma.
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let patterns = split_unescaped(format)
+            .iter()
+            .filter(|p| !p.is_empty())
+            .map(|p| compile_pattern(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Parse one line of output, if it matches any pattern
+    pub fn parse_line(&self, line: &str) -> Option<QuickfixEntry> {
+        self.patterns.iter().find_map(|re| {
+            let caps = re.captures(line)?;
+            let number = |name: &str| caps.name(name).and_then(|m| m.as_str().parse::<usize>().ok());
+            let message = caps.name("m").map_or("", |m| m.as_str()).trim().to_string();
+            let kind = match caps.name("t").and_then(|m| m.as_str().chars().next()) {
+                Some(c) => c.to_ascii_lowercase(),
+                None if message.starts_with("error") => 'e',
+                None if message.starts_with("warning") => 'w',
+                None => ' ',
+            };
+            Some(QuickfixEntry {
+                file: PathBuf::from(caps.name("f")?.as_str()),
+                line: number("l")?.saturating_sub(1),
+                col: number("c").unwrap_or(1).saturating_sub(1),
+                kind,
+                message,
+            })
+        })
+    }
+
+    /// Parse all matching lines of compiler output
+    pub fn parse_output(&self, output: &str) -> Vec<QuickfixEntry> {
+        output.lines().filter_map(|line| self.parse_line(line)).collect()
+    }
+}
+
+/// Split an errorformat on commas not preceded by a backslash
+fn split_unescaped(format: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                parts.last_mut().unwrap().push(',');
+                chars.next();
+            }
+            ',' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+/// Turn one errorformat pattern into an anchored regex
+fn compile_pattern(pattern: &str) -> Result<Regex, String> {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            re.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+        match chars.next() {
+            Some('f') => re.push_str(r"(?P<f>[^:]+?)"),
+            Some('l') => re.push_str(r"(?P<l>\d+)"),
+            Some('c') => re.push_str(r"(?P<c>\d+)"),
+            Some('m') => re.push_str(r"(?P<m>.*)"),
+            Some('t') => re.push_str(r"(?P<t>\w)"),
+            Some('%') => re.push('%'),
+            Some(other) => return Err(format!("Unsupported errorformat item: %{}", other)),
+            None => return Err("Trailing % in errorformat".to_string()),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|e| e.to_string())
+}
+
+/// Built-in :make program for a filetype (the syntax name, lowercased)
+pub fn default_makeprg(filetype: Option<&str>) -> &'static str {
+    match filetype {
+        Some("rust") => "cargo build --message-format=short",
+        Some("python") => "python3 -m py_compile %",
+        Some("javascript") => "node --check %",
+        _ => "make",
+    }
+}
+
+/// Entries from the last :make, with the one :cc last jumped to
+#[derive(Debug, Default)]
+pub struct QuickfixList {
+    entries: Vec<QuickfixEntry>,
+    current: usize,
+}
+
+impl QuickfixList {
+    /// Replace the list, resolving relative paths against `dir`
+    pub fn set(&mut self, entries: Vec<QuickfixEntry>, dir: &Path) {
+        self.entries = entries
+            .into_iter()
+            .map(|mut entry| {
+                entry.file = dir.join(&entry.file);
+                entry
+            })
+            .collect();
+        self.current = 0;
+    }
+
+    /// Add an entry, resolving a relative path against `dir`
+    pub fn push(&mut self, mut entry: QuickfixEntry, dir: &Path) {
+        entry.file = dir.join(&entry.file);
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[QuickfixEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Index of the current entry
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Select entry `idx` (0-based), clamped to the list
+    pub fn select(&mut self, idx: usize) -> Option<&QuickfixEntry> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.current = idx.min(self.entries.len() - 1);
+        self.entries.get(self.current)
+    }
+
+    /// Move `count` entries forward (or back), None past either end
+    pub fn step(&mut self, count: usize, forward: bool) -> Option<&QuickfixEntry> {
+        let idx = if forward {
+            self.current.checked_add(count).filter(|&i| i < self.entries.len())?
+        } else {
+            self.current.checked_sub(count)?
+        };
+        self.select(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_errorformat() {
+        let efm = ErrorFormat::parse(DEFAULT_ERRORFORMAT).unwrap();
+        let output = "   Compiling quirks v0.3.1\n\
+            src/main.rs:3:5: error[E0425]: cannot find value `x` in this scope\n\
+            src/lib.rs:10: warning: unused import\n\
+            error: could not compile `quirks`\n";
+        let entries = efm.parse_output(output);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].file, PathBuf::from("src/main.rs"));
+        assert_eq!((entries[0].line, entries[0].col), (2, 4));
+        assert_eq!(entries[0].kind, 'e');
+        assert_eq!(entries[0].message, "error[E0425]: cannot find value `x` in this scope");
+        assert_eq!((entries[1].line, entries[1].col, entries[1].kind), (9, 0, 'w'));
+    }
+
+    #[test]
+    fn test_custom_errorformat() {
+        assert!(ErrorFormat::parse("%f:%l:%v: %m").is_err());
+
+        let efm = ErrorFormat::parse("%f(%l\\,%c): %t: %m").unwrap();
+        let entry = efm.parse_line("app.cs(12,8): E: missing semicolon").unwrap();
+        assert_eq!((entry.line, entry.col, entry.kind), (11, 7, 'e'));
+        assert_eq!(entry.message, "missing semicolon");
+    }
+
+    #[test]
+    fn test_quickfix_navigation() {
+        let efm = ErrorFormat::parse(DEFAULT_ERRORFORMAT).unwrap();
+        let mut list = QuickfixList::default();
+        list.set(efm.parse_output("a.rs:1:1: x\nb.rs:2:1: y\nc.rs:3:1: z\n"), Path::new("/src"));
+        assert_eq!(list.entries()[0].file, PathBuf::from("/src/a.rs"));
+
+        assert_eq!(list.step(1, true).unwrap().line, 1);
+        assert_eq!(list.step(5, true), None);
+        assert_eq!(list.current(), 1);
+        assert_eq!(list.step(1, false).unwrap().line, 0);
+        assert_eq!(list.step(1, false), None);
+        assert_eq!(list.select(10).unwrap().line, 2);
+    }
+}
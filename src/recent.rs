@@ -0,0 +1,100 @@
+//! Recently opened files for Quirks
+//!
+//! Keeps a most-recent-first list of opened files in a small state file
+//! (~/.local/share/quirks/oldfiles on Linux) so it survives restarts.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Maximum number of files remembered
+const MAX_RECENT: usize = 100;
+
+/// Most-recent-first list of opened files
+#[derive(Debug, Default)]
+pub struct RecentFiles {
+    files: Vec<PathBuf>,
+    /// State file the list is persisted to, if any
+    state_path: Option<PathBuf>,
+}
+
+impl RecentFiles {
+    /// Load the list from the default state file
+    pub fn load() -> Self {
+        match dirs::data_local_dir() {
+            Some(dir) => Self::from_file(dir.join("quirks").join("oldfiles")),
+            None => Self::default(),
+        }
+    }
+
+    /// Load the list from a specific state file (missing file = empty list)
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let files = fs::read_to_string(&path)
+            .map(|content| {
+                content.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .take(MAX_RECENT)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            files,
+            state_path: Some(path),
+        }
+    }
+
+    /// Recently opened files, most recent first
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Record that a file was opened, moving it to the front
+    pub fn add(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.files.retain(|p| *p != path);
+        self.files.insert(0, path);
+        self.files.truncate(MAX_RECENT);
+    }
+
+    /// Write the list to the state file
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.state_path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut content = String::new();
+        for file in &self.files {
+            content.push_str(&file.to_string_lossy());
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_files_order_and_persistence() {
+        let dir = std::env::temp_dir().join(format!("quirks-recent-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let state = dir.join("state").join("oldfiles");
+
+        let mut recent = RecentFiles::from_file(&state);
+        assert!(recent.files().is_empty());
+
+        recent.add(Path::new("/tmp/a.rs"));
+        recent.add(Path::new("/tmp/b.rs"));
+        recent.add(Path::new("/tmp/a.rs"));
+        assert_eq!(recent.files(), &[PathBuf::from("/tmp/a.rs"), PathBuf::from("/tmp/b.rs")]);
+
+        recent.save().unwrap();
+        let reloaded = RecentFiles::from_file(&state);
+        assert_eq!(reloaded.files(), recent.files());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -16,6 +16,24 @@ pub enum RegisterContent {
 }
 
 impl RegisterContent {
+    /// Content for text from outside the editor: line-wise if it ends
+    /// with a line break
+    pub fn from_text(text: String) -> Self {
+        if text.ends_with('\n') {
+            RegisterContent::Lines(text)
+        } else {
+            RegisterContent::Chars(text)
+        }
+    }
+
+    /// The whole text, a block's lines joined by line breaks
+    pub fn full_text(&self) -> String {
+        match self {
+            RegisterContent::Block(lines) => lines.join("\n"),
+            other => other.text().to_string(),
+        }
+    }
+
     /// Get the text content
     pub fn text(&self) -> &str {
         match self {
@@ -55,6 +73,10 @@ pub struct Registers {
     /// Numbered registers (0-9)
     /// 0 = last yank, 1-9 = last deletes (1 is most recent)
     numbered: [Option<RegisterContent>; 10],
+    /// The system clipboard (+) and primary selection (*) as last read or
+    /// written; the editor keeps them in sync with the system
+    clipboard: Option<RegisterContent>,
+    primary: Option<RegisterContent>,
     // Note: The black hole register (_) discards content - we don't store anything
     // Read-only registers (%, #, etc.) would be computed on access
 }
@@ -66,6 +88,8 @@ impl Registers {
             unnamed: None,
             small_delete: None,
             numbered: Default::default(),
+            clipboard: None,
+            primary: None,
         }
     }
 
@@ -150,6 +174,8 @@ impl Registers {
             }
             '-' => self.small_delete.as_ref(),
             'a'..='z' | 'A'..='Z' => self.get_named(register),
+            '+' => self.clipboard.as_ref(),
+            '*' => self.primary.as_ref(),
             '_' => None, // Black hole - always empty
             _ => None,
         }
@@ -160,6 +186,8 @@ impl Registers {
         match register {
             '"' => self.unnamed = Some(content),
             'a'..='z' | 'A'..='Z' => self.set_named(register, content),
+            '+' => self.clipboard = Some(content),
+            '*' => self.primary = Some(content),
             '_' => {} // Black hole - discard
             _ => {
                 // For other registers, just set unnamed
@@ -172,12 +200,45 @@ impl Registers {
         }
     }
 
+    /// Every register that holds something, with its name: ", 0-9, - and
+    /// a-z. The clipboard registers belong to the system and are left out.
+    pub fn entries(&self) -> Vec<(char, &RegisterContent)> {
+        let mut entries: Vec<(char, &RegisterContent)> = Vec::new();
+        entries.extend(self.unnamed.as_ref().map(|content| ('"', content)));
+        for (i, content) in self.numbered.iter().enumerate() {
+            if let Some(content) = content {
+                entries.push((char::from(b'0' + i as u8), content));
+            }
+        }
+        entries.extend(self.small_delete.as_ref().map(|content| ('-', content)));
+        let mut named: Vec<_> = self.named.iter().map(|(&name, content)| (name, content)).collect();
+        named.sort_by_key(|(name, _)| *name);
+        entries.extend(named);
+        entries
+    }
+
+    /// Put content back into a register exactly as it was, e.g. when
+    /// loading saved state; unlike `set`, numbered registers do not shift
+    pub fn restore(&mut self, register: char, content: RegisterContent) {
+        match register {
+            '"' => self.unnamed = Some(content),
+            '0'..='9' => self.numbered[register as usize - '0' as usize] = Some(content),
+            '-' => self.small_delete = Some(content),
+            'a'..='z' => {
+                self.named.insert(register, content);
+            }
+            _ => {}
+        }
+    }
+
     /// Clear all registers
     pub fn clear(&mut self) {
         self.named.clear();
         self.unnamed = None;
         self.small_delete = None;
         self.numbered = Default::default();
+        self.clipboard = None;
+        self.primary = None;
     }
 }
 
@@ -255,6 +316,18 @@ mod tests {
         assert!(regs.get('_').is_none());
     }
 
+    #[test]
+    fn test_clipboard_registers() {
+        let mut regs = Registers::new();
+        regs.set('+', RegisterContent::from_text("copied\n".to_string()), false);
+        regs.set('*', RegisterContent::from_text("selected".to_string()), false);
+
+        assert_eq!(regs.get('+'), Some(&RegisterContent::Lines("copied\n".to_string())));
+        assert_eq!(regs.get('*'), Some(&RegisterContent::Chars("selected".to_string())));
+        assert!(regs.get_unnamed().is_none());
+        assert!(regs.entries().is_empty());
+    }
+
     #[test]
     fn test_linewise_detection() {
         let chars = RegisterContent::Chars("hello".to_string());
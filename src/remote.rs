@@ -0,0 +1,271 @@
+//! Remote files for Quirks
+//!
+//! Opens and saves `scp://[user@]host[:port]/path` files by running `ssh`
+//! in the background, so the editor keeps drawing and can show how far
+//! the transfer got. Like Vim's netrw, `scp://host/file` is relative to
+//! the remote home directory and `scp://host//etc/file` is absolute.
+//! `ssh` runs with BatchMode, so a host that needs a password fails
+//! instead of prompting over the editor; use keys or an agent.
+
+use crate::events::Waker;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+/// A file on another machine, reached over ssh
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemotePath {
+    /// `host` or `user@host`
+    pub host: String,
+    pub port: Option<u16>,
+    /// Path on the host; relative paths start in the remote home
+    pub path: String,
+}
+
+impl RemotePath {
+    /// Parse an `scp://` URL; None for anything else. A host starting with
+    /// `-` would be read by ssh as an option, so it is refused.
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("scp://")?;
+        let (authority, path) = rest.split_once('/')?;
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse().ok()?)),
+            None => (authority, None),
+        };
+        if host.is_empty() || host.starts_with('-') || path.is_empty() || path.ends_with('/') {
+            return None;
+        }
+        Some(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// `ssh` running `command` on the host
+    fn ssh(&self, command: &str) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        // Nothing after -- is taken for an option
+        cmd.arg("--").arg(&self.host).arg(command);
+        cmd
+    }
+}
+
+impl fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "scp://{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write!(f, "/{}", self.path)
+    }
+}
+
+/// Quote `text` as a single word for the remote shell
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// A download or upload running in the background
+pub struct Transfer {
+    remote: RemotePath,
+    upload: bool,
+    done: Arc<AtomicU64>,
+    /// Size of the file, 0 until it is known
+    total: Arc<AtomicU64>,
+    result: Receiver<Result<String, String>>,
+}
+
+impl Transfer {
+    /// Start fetching the file's text, waking the main loop with `waker`
+    /// when it is done
+    pub fn download(remote: RemotePath, waker: Waker) -> io::Result<Self> {
+        let path = shell_quote(&remote.path);
+        let mut child = remote
+            .ssh(&format!("wc -c < {} && cat {}", path, path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (done, total) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+        let (tx, rx) = mpsc::channel();
+        let (done_in, total_in) = (done.clone(), total.clone());
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        thread::spawn(move || {
+            let received = stdout.ok_or_else(|| "no output".to_string()).and_then(|stdout| {
+                let mut reader = BufReader::new(stdout);
+                let mut size = String::new();
+                reader.read_line(&mut size).map_err(|e| e.to_string())?;
+                total_in.store(size.trim().parse().unwrap_or(0), Ordering::Relaxed);
+                let mut bytes = Vec::new();
+                let mut chunk = [0; 16 * 1024];
+                loop {
+                    let n = reader.read(&mut chunk).map_err(|e| e.to_string())?;
+                    if n == 0 {
+                        break;
+                    }
+                    bytes.extend_from_slice(&chunk[..n]);
+                    done_in.store(bytes.len() as u64, Ordering::Relaxed);
+                }
+                Ok(bytes)
+            });
+            let result = finish(child, stderr, received)
+                .and_then(|bytes| String::from_utf8(bytes).map_err(|_| "not a UTF-8 text file".to_string()));
+            let _ = tx.send(result);
+            waker.wake();
+        });
+        Ok(Self { remote, upload: false, done, total, result: rx })
+    }
+
+    /// Start writing `text` to the file, waking the main loop with `waker`
+    /// when it is done
+    pub fn upload(remote: RemotePath, text: String, waker: Waker) -> io::Result<Self> {
+        let mut child = remote
+            .ssh(&format!("cat > {}", shell_quote(&remote.path)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let done = Arc::new(AtomicU64::new(0));
+        let total = Arc::new(AtomicU64::new(text.len() as u64));
+        let (tx, rx) = mpsc::channel();
+        let done_in = done.clone();
+        let stdin = child.stdin.take();
+        let stderr = child.stderr.take();
+        thread::spawn(move || {
+            let sent = stdin.ok_or_else(|| "no input".to_string()).and_then(|mut stdin| {
+                for chunk in text.as_bytes().chunks(16 * 1024) {
+                    stdin.write_all(chunk).map_err(|e| e.to_string())?;
+                    done_in.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+                Ok(Vec::new())
+            });
+            let _ = tx.send(finish(child, stderr, sent).map(|_| String::new()));
+            waker.wake();
+        });
+        Ok(Self { remote, upload: true, done, total, result: rx })
+    }
+
+    pub fn remote(&self) -> &RemotePath {
+        &self.remote
+    }
+
+    pub fn is_upload(&self) -> bool {
+        self.upload
+    }
+
+    /// The file's text once a download is done (empty for an upload), or
+    /// why it failed; None while it is still running
+    pub fn poll(&self) -> Option<Result<String, String>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("transfer stopped".to_string())),
+        }
+    }
+
+    /// How far the transfer got, for the message line
+    pub fn progress(&self) -> String {
+        let verb = if self.upload { "Writing" } else { "Reading" };
+        progress_text(verb, &self.remote, self.done.load(Ordering::Relaxed), self.total.load(Ordering::Relaxed))
+    }
+}
+
+/// Wait for ssh and turn a failure into its error output
+fn finish(
+    mut child: std::process::Child,
+    stderr: Option<std::process::ChildStderr>,
+    result: Result<Vec<u8>, String>,
+) -> Result<Vec<u8>, String> {
+    let mut errors = String::new();
+    if let Some(mut stderr) = stderr {
+        let _ = stderr.read_to_string(&mut errors);
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        let error = errors.lines().last().unwrap_or("").trim();
+        return Err(if error.is_empty() { format!("ssh failed ({})", status) } else { error.to_string() });
+    }
+    result
+}
+
+fn progress_text(verb: &str, remote: &RemotePath, done: u64, total: u64) -> String {
+    if total == 0 {
+        format!("{} {}: {}", verb, remote, format_size(done))
+    } else {
+        format!(
+            "{} {}: {} of {} ({}%)",
+            verb,
+            remote,
+            format_size(done),
+            format_size(total),
+            done.min(total) * 100 / total
+        )
+    }
+}
+
+/// Byte count in B, KiB or MiB
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_path() {
+        let remote = RemotePath::parse("scp://me@box:2222//etc/hosts").unwrap();
+        assert_eq!(remote.host, "me@box");
+        assert_eq!(remote.port, Some(2222));
+        assert_eq!(remote.path, "/etc/hosts");
+        assert_eq!(remote.to_string(), "scp://me@box:2222//etc/hosts");
+
+        let remote = RemotePath::parse("scp://box/notes.md").unwrap();
+        assert_eq!((remote.host.as_str(), remote.port, remote.path.as_str()), ("box", None, "notes.md"));
+
+        assert_eq!(RemotePath::parse("notes.md"), None);
+        assert_eq!(RemotePath::parse("scp://box"), None);
+        assert_eq!(RemotePath::parse("scp://box/dir/"), None);
+        assert_eq!(RemotePath::parse("scp://box:ssh/file"), None);
+        assert_eq!(RemotePath::parse("scp://-oProxyCommand=touch%20x/f"), None);
+    }
+
+    #[test]
+    fn test_ssh_command() {
+        let remote = RemotePath::parse("scp://me@box:2222/notes.md").unwrap();
+        let cmd = remote.ssh("cat notes.md");
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(args, ["-o", "BatchMode=yes", "-p", "2222", "--", "me@box", "cat notes.md"]);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_progress_text() {
+        let remote = RemotePath::parse("scp://box/f").unwrap();
+        assert_eq!(progress_text("Reading", &remote, 0, 0), "Reading scp://box/f: 0 B");
+        assert_eq!(
+            progress_text("Writing", &remote, 1536, 4096),
+            "Writing scp://box/f: 1.5 KiB of 4.0 KiB (37%)"
+        );
+        assert_eq!(format_size(3 * 1_048_576), "3.0 MiB");
+    }
+}
@@ -0,0 +1,147 @@
+//! Key-sequence scripts for Quirks
+//!
+//! Drives an editor from a small script and checks the result, for tests
+//! and for `quirks --script`. One step per line:
+//!
+//! ```text
+//! # comment
+//! text one\ntwo          replace the buffer ("\n" is a line break, "\t" a tab)
+//! keys jdd               keys in Vim notation, e.g. "ciwnew<Esc>"
+//! :s/a/b/                an ex command
+//! expect text one        the whole buffer
+//! expect line 2 two      one line (1-based)
+//! expect cursor 1:1      cursor line:column (1-based)
+//! expect mode NORMAL     mode as shown in the status line
+//! expect message Saved   message on the command line
+//! expect prompt Delete?   question waiting for an answer, with its choices
+//! expect top 1            first line shown in the window (1-based)
+//! ```
+
+use crate::editor::Editor;
+use crate::prompt::Prompt;
+use std::fmt;
+
+/// A step that did not parse or an expectation that did not hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    /// 1-based line of the script
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Run `script` against `editor`, stopping at the first failure. Returns
+/// the number of expectations checked.
+pub fn run(editor: &mut Editor, script: &str) -> Result<usize, ScriptError> {
+    let mut checked = 0;
+    for (idx, line) in script.lines().enumerate() {
+        let fail = |message: String| ScriptError { line: idx + 1, message };
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(cmd) = line.strip_prefix(':') {
+            editor.execute_command(cmd);
+            continue;
+        }
+        let (step, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match step {
+            "text" => editor.set_text(&unescape(arg)),
+            "keys" => {
+                editor.feed_keys(arg).ok_or_else(|| fail("no keys".to_string()))?;
+            }
+            "expect" => {
+                expect(editor, arg).map_err(fail)?;
+                checked += 1;
+            }
+            _ => return Err(fail(format!("unknown step: {}", step))),
+        }
+    }
+    Ok(checked)
+}
+
+/// Check one `expect` step, describing the mismatch if it fails
+fn expect(editor: &Editor, arg: &str) -> Result<(), String> {
+    let (what, value) = arg.split_once(' ').unwrap_or((arg, ""));
+    let buffer = editor.buffer();
+    let actual = match what {
+        "text" => {
+            let expected = unescape(value);
+            let actual = buffer.content();
+            // The buffer's final newline is optional in the script
+            if actual == expected || actual.strip_suffix('\n') == Some(expected.as_str()) {
+                return Ok(());
+            }
+            return Err(format!("expected text {:?}, got {:?}", expected, actual));
+        }
+        "line" => {
+            let (number, text) = value.split_once(' ').unwrap_or((value, ""));
+            let number: usize = number.parse().map_err(|_| format!("bad line number: {}", number))?;
+            let actual = buffer.line(number.saturating_sub(1));
+            if actual == text {
+                return Ok(());
+            }
+            return Err(format!("expected line {} {:?}, got {:?}", number, text, actual));
+        }
+        "cursor" => format!("{}:{}", editor.cursor().line + 1, editor.cursor().col + 1),
+        "mode" => editor.mode().display().to_string(),
+        "message" => editor.message().cloned().unwrap_or_default(),
+        "prompt" => editor.prompt().map(Prompt::line).unwrap_or_default(),
+        "top" => (editor.scroll_offset() + 1).to_string(),
+        _ => return Err(format!("unknown expectation: {}", what)),
+    };
+    if actual == value {
+        Ok(())
+    } else {
+        Err(format!("expected {} {:?}, got {:?}", what, value, actual))
+    }
+}
+
+/// Turn "\n" into line breaks and "\\" into a backslash
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('t')) => {
+                out.push('\t');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_script_reports_failures() {
+        let mut editor = Editor::headless(Config::default());
+        let script = "text one\\ntwo\nkeys jdd\nexpect text one\nexpect cursor 1:1\n";
+        assert_eq!(run(&mut editor, script), Ok(2));
+
+        let err = run(&mut editor, "# comment\nexpect mode INSERT\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "expected mode \"INSERT\", got \"NORMAL\"");
+        assert!(run(&mut editor, "frobnicate").is_err());
+    }
+}
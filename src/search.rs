@@ -1,8 +1,28 @@
 //! Search functionality for Quirks
 //!
-//! Provides vi-style search with regex support.
-
+//! Provides vi-style search with regex support. In large buffers matches
+//! are found on a worker thread while the pattern is typed. Like Vim, the
+//! text after an unescaped closing `/` (or `?`) is an offset saying where
+//! to land: `/foo/e` on the last character of the match, `/foo/+1` on the
+//! line below it. `\%V` in a pattern only matches inside the last visual
+//! selection.
+
+use crate::events::Waker;
+use crate::selection::Selection;
 use regex::Regex;
+use ropey::Rope;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+/// Lines a search worker scans between delivering results and checking
+/// whether it was cancelled
+const SEARCH_BATCH: usize = 2000;
+
+/// Pattern atom limiting matches to the last visual selection
+pub const VISUAL_AREA: &str = r"\%V";
 
 /// Search direction
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,11 +42,88 @@ pub struct SearchMatch {
     pub end_col: usize,
 }
 
+/// Where a search lands relative to its match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOffset {
+    /// `s[+-n]` / `b[+-n]` (or no offset): columns from the match start
+    Start(isize),
+    /// `e[+-n]`: columns from the last character of the match
+    End(isize),
+    /// `[+-]n`: lines below or above the match, at its first column. A
+    /// search with a line offset is linewise when used after an operator.
+    Lines(isize),
+}
+
+impl Default for SearchOffset {
+    fn default() -> Self {
+        SearchOffset::Start(0)
+    }
+}
+
+impl SearchOffset {
+    /// Parse the offset after the closing delimiter; None if invalid
+    pub fn parse(text: &str) -> Option<Self> {
+        let number = |text: &str| -> Option<isize> {
+            match text {
+                "" => Some(0),
+                "+" => Some(1),
+                "-" => Some(-1),
+                _ => text.strip_prefix('+').unwrap_or(text).parse().ok(),
+            }
+        };
+        match text.chars().next() {
+            None => Some(SearchOffset::Start(0)),
+            Some('s' | 'b') => number(&text[1..]).map(SearchOffset::Start),
+            Some('e') => number(&text[1..]).map(SearchOffset::End),
+            _ => number(text).map(SearchOffset::Lines),
+        }
+    }
+
+    /// Line and column to land on for a match, in a buffer of
+    /// `line_count` lines. The column may be past the end of the line.
+    pub fn apply(self, m: SearchMatch, line_count: usize) -> (usize, usize) {
+        match self {
+            SearchOffset::Start(n) => (m.line, m.start_col.saturating_add_signed(n)),
+            SearchOffset::End(n) => (m.line, m.end_col.saturating_sub(1).max(m.start_col).saturating_add_signed(n)),
+            SearchOffset::Lines(n) => (m.line.saturating_add_signed(n).min(line_count.saturating_sub(1)), 0),
+        }
+    }
+
+    /// Whether an operator with this search acts on whole lines
+    pub fn is_linewise(self) -> bool {
+        matches!(self, SearchOffset::Lines(_))
+    }
+}
+
+/// Split typed search text at the first `delimiter` not escaped with a
+/// backslash into the pattern and its offset
+pub fn split_offset(text: &str, delimiter: char) -> (&str, Option<&str>) {
+    let mut escaped = false;
+    for (idx, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            return (&text[..idx], Some(&text[idx + c.len_utf8()..]));
+        }
+    }
+    (text, None)
+}
+
 /// Search state
 #[derive(Debug)]
 pub struct Search {
     /// Current search pattern (as entered by user)
     pattern: String,
+    /// Length of the regex part of `pattern`, before any offset
+    regex_len: usize,
+    /// Where to land relative to a match
+    offset: SearchOffset,
+    /// The last visual selection, for `\%V`
+    visual_area: Option<Selection>,
+    /// The pattern contains `\%V`
+    in_visual_area: bool,
     /// Compiled regex (if valid)
     regex: Option<Regex>,
     /// Search direction
@@ -37,6 +134,69 @@ pub struct Search {
     current_match: Option<usize>,
     /// Whether search highlighting is active
     pub highlight_active: bool,
+    /// Background search still delivering matches
+    worker: Option<SearchWorker>,
+}
+
+/// Match-finding for one pattern on a worker thread. Dropping it cancels
+/// the search.
+#[derive(Debug)]
+struct SearchWorker {
+    cancelled: Arc<AtomicBool>,
+    results: Receiver<Vec<SearchMatch>>,
+}
+
+impl SearchWorker {
+    fn spawn(regex: Regex, area: Option<Selection>, text: Rope, waker: Waker) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let flag = Arc::clone(&cancelled);
+        thread::spawn(move || {
+            let mut batch = Vec::new();
+            for (line_idx, line) in text.lines().enumerate() {
+                let line = Cow::from(line);
+                find_matches(&regex, area.as_ref(), line_idx, line.strip_suffix('\n').unwrap_or(&line), &mut batch);
+                if (line_idx + 1) % SEARCH_BATCH == 0 {
+                    if flag.load(Ordering::Relaxed) || tx.send(std::mem::take(&mut batch)).is_err() {
+                        return;
+                    }
+                    waker.wake();
+                }
+            }
+            let _ = tx.send(batch);
+            drop(tx);
+            waker.wake();
+        });
+        Self { cancelled, results: rx }
+    }
+}
+
+impl Drop for SearchWorker {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Append the matches of `regex` in one line to `matches`, keeping only
+/// those wholly inside `area` if there is one
+fn find_matches(regex: &Regex, area: Option<&Selection>, line_idx: usize, line: &str, matches: &mut Vec<SearchMatch>) {
+    let cols = match area {
+        Some(area) => match area.cols_on_line(line_idx) {
+            Some(cols) => cols,
+            None => return,
+        },
+        None => 0..usize::MAX,
+    };
+    for mat in regex.find_iter(line) {
+        let m = SearchMatch {
+            line: line_idx,
+            start_col: line[..mat.start()].chars().count(),
+            end_col: line[..mat.end()].chars().count(),
+        };
+        if m.start_col >= cols.start && m.end_col <= cols.end {
+            matches.push(m);
+        }
+    }
 }
 
 impl Default for Search {
@@ -49,11 +209,16 @@ impl Search {
     pub fn new() -> Self {
         Self {
             pattern: String::new(),
+            regex_len: 0,
+            offset: SearchOffset::default(),
+            visual_area: None,
+            in_visual_area: false,
             regex: None,
             direction: SearchDirection::Forward,
             matches: Vec::new(),
             current_match: None,
             highlight_active: false,
+            worker: None,
         }
     }
 
@@ -64,6 +229,7 @@ impl Search {
         self.regex = None;
         self.matches.clear();
         self.current_match = None;
+        self.worker = None;
     }
 
     /// Get the current pattern
@@ -71,43 +237,74 @@ impl Search {
         &self.pattern
     }
 
+    /// The regex part of the pattern, without any offset
+    pub fn regex_pattern(&self) -> &str {
+        &self.pattern[..self.regex_len]
+    }
+
+    /// Where to land relative to a match
+    pub fn offset(&self) -> SearchOffset {
+        self.offset
+    }
+
     /// Set the pattern and compile regex
     pub fn set_pattern(&mut self, pattern: &str) {
         self.pattern = pattern.to_string();
-        // Try to compile as regex, fall back to literal if invalid
-        self.regex = Regex::new(pattern).ok().or_else(|| {
-            Regex::new(&regex::escape(pattern)).ok()
-        });
+        self.compile();
     }
 
     /// Add a character to the pattern
     pub fn push_char(&mut self, c: char) {
         self.pattern.push(c);
-        self.regex = Regex::new(&self.pattern).ok().or_else(|| {
-            Regex::new(&regex::escape(&self.pattern)).ok()
-        });
+        self.compile();
     }
 
     /// Remove last character from pattern
     pub fn pop_char(&mut self) -> bool {
         if self.pattern.pop().is_some() {
-            self.regex = if self.pattern.is_empty() {
-                None
-            } else {
-                Regex::new(&self.pattern).ok().or_else(|| {
-                    Regex::new(&regex::escape(&self.pattern)).ok()
-                })
-            };
+            self.compile();
             true
         } else {
             false
         }
     }
 
+    /// Set the area `\%V` matches in
+    pub fn set_visual_area(&mut self, area: Option<Selection>) {
+        self.visual_area = area;
+        self.compile();
+    }
+
+    /// Area matches are limited to, if the pattern has `\%V`
+    fn area(&self) -> Option<Selection> {
+        self.visual_area.filter(|_| self.in_visual_area)
+    }
+
+    /// Split off the offset and compile the rest, as a regex or falling
+    /// back to a literal if it is not a valid one. With `\%V` but no
+    /// visual selection nothing matches.
+    fn compile(&mut self) {
+        let delimiter = match self.direction {
+            SearchDirection::Forward => '/',
+            SearchDirection::Backward => '?',
+        };
+        let (regex, offset) = split_offset(&self.pattern, delimiter);
+        self.regex_len = regex.len();
+        self.offset = offset.and_then(SearchOffset::parse).unwrap_or_default();
+        self.in_visual_area = regex.contains(VISUAL_AREA);
+        let regex = regex.replace(VISUAL_AREA, "");
+        self.regex = if regex.is_empty() || (self.in_visual_area && self.visual_area.is_none()) {
+            None
+        } else {
+            Regex::new(&regex).ok().or_else(|| Regex::new(&regex::escape(&regex)).ok())
+        };
+    }
+
     /// Execute search on buffer content
     pub fn execute(&mut self, lines: &[String], cursor_line: usize, cursor_col: usize) {
         self.matches.clear();
         self.current_match = None;
+        self.worker = None;
         
         let regex = match &self.regex {
             Some(r) => r,
@@ -115,14 +312,9 @@ impl Search {
         };
 
         // Find all matches
+        let area = self.area();
         for (line_idx, line) in lines.iter().enumerate() {
-            for mat in regex.find_iter(line) {
-                self.matches.push(SearchMatch {
-                    line: line_idx,
-                    start_col: line[..mat.start()].chars().count(),
-                    end_col: line[..mat.end()].chars().count(),
-                });
-            }
+            find_matches(regex, area.as_ref(), line_idx, line, &mut self.matches);
         }
 
         if self.matches.is_empty() {
@@ -135,6 +327,59 @@ impl Search {
         self.current_match = Some(self.find_nearest_match(cursor_line, cursor_col));
     }
 
+    /// Start finding matches in `text` on a worker thread, cancelling any
+    /// search still running. Matches arrive through `poll`, each batch
+    /// waking the main loop with `waker`.
+    pub fn execute_in_background(&mut self, text: Rope, waker: Waker) {
+        self.matches.clear();
+        self.current_match = None;
+        let area = self.area();
+        self.worker = self.regex.clone().map(|regex| SearchWorker::spawn(regex, area, text, waker));
+        self.highlight_active = self.worker.is_some();
+    }
+
+    /// Whether a background search is still running
+    pub fn is_running(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    /// Collect matches found by the background search since the last poll.
+    /// Returns true if there were any or the search finished.
+    pub fn poll(&mut self) -> bool {
+        let Some(worker) = &self.worker else { return false };
+        let mut changed = false;
+        loop {
+            match worker.results.try_recv() {
+                Ok(batch) => {
+                    changed |= !batch.is_empty();
+                    self.matches.extend(batch);
+                }
+                Err(TryRecvError::Empty) => return changed,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        self.worker = None;
+        true
+    }
+
+    /// Wait for the background search to finish, then select the match
+    /// nearest the cursor like `execute`
+    pub fn wait(&mut self, cursor_line: usize, cursor_col: usize) {
+        if let Some(worker) = self.worker.take() {
+            self.matches.extend(worker.results.iter().flatten());
+        }
+        self.current_match = if self.matches.is_empty() {
+            None
+        } else {
+            Some(self.find_nearest_match(cursor_line, cursor_col))
+        };
+    }
+
+    /// Stop the background search, keeping the matches found so far
+    pub fn cancel(&mut self) {
+        self.worker = None;
+    }
+
     /// Find the nearest match from cursor position
     fn find_nearest_match(&self, cursor_line: usize, cursor_col: usize) -> usize {
         if self.matches.is_empty() {
@@ -176,6 +421,7 @@ impl Search {
             None => 0,
         };
         self.current_match = Some(next);
+        self.highlight_active = true;
         self.matches.get(next).copied()
     }
 
@@ -196,9 +442,18 @@ impl Search {
             None => self.matches.len() - 1,
         };
         self.current_match = Some(prev);
+        self.highlight_active = true;
         self.matches.get(prev).copied()
     }
 
+    /// Make the match under `line`/`col` the current one, if there is one
+    pub fn select_match_at(&mut self, line: usize, col: usize) -> Option<SearchMatch> {
+        let start = self.matches.partition_point(|m| m.line < line);
+        let idx = start + self.matches_on_line(line).iter().position(|m| m.start_col <= col && col < m.end_col)?;
+        self.current_match = Some(idx);
+        self.matches.get(idx).copied()
+    }
+
     /// Get current match
     pub fn current(&self) -> Option<SearchMatch> {
         self.current_match.and_then(|i| self.matches.get(i).copied())
@@ -209,6 +464,13 @@ impl Search {
         &self.matches
     }
 
+    /// Matches on one line; matches are kept in line order
+    pub fn matches_on_line(&self, line: usize) -> &[SearchMatch] {
+        let start = self.matches.partition_point(|m| m.line < line);
+        let end = self.matches.partition_point(|m| m.line <= line);
+        &self.matches[start..end]
+    }
+
     /// Get match count info string
     pub fn match_info(&self) -> String {
         if self.matches.is_empty() {
@@ -243,6 +505,25 @@ impl Search {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_background_search() {
+        let text: String = (0..5000).map(|i| format!("line {}\n", i)).collect();
+        let mut search = Search::new();
+        search.set_pattern("e 4.9");
+        search.execute_in_background(Rope::from_str(&text), Waker::default());
+        assert!(search.is_running());
+        search.wait(0, 0);
+        assert!(!search.is_running());
+        // "line 4x9" and "line 4x9y" for any digits x and y
+        assert_eq!(search.matches().len(), 110);
+        assert_eq!(search.current(), Some(SearchMatch { line: 409, start_col: 3, end_col: 8 }));
+
+        search.set_pattern("line");
+        search.execute_in_background(Rope::from_str(&text), Waker::default());
+        search.cancel();
+        assert!(!search.is_running());
+    }
+
     #[test]
     fn test_search_new() {
         let search = Search::new();
@@ -287,4 +568,70 @@ mod tests {
         
         assert_eq!(search.matches().len(), 2);
     }
+
+    #[test]
+    fn test_select_match_at() {
+        let mut search = Search::new();
+        search.set_pattern("ab");
+        search.execute(&["ab ab".to_string(), "x ab".to_string()], 0, 0);
+        assert_eq!(search.select_match_at(1, 3), Some(SearchMatch { line: 1, start_col: 2, end_col: 4 }));
+        assert_eq!(search.match_info(), "3/3");
+        assert_eq!(search.select_match_at(0, 2), None);
+        assert_eq!(search.current(), Some(SearchMatch { line: 1, start_col: 2, end_col: 4 }));
+
+        search.clear_highlight();
+        search.next_match();
+        assert!(search.highlight_active);
+    }
+
+    #[test]
+    fn test_visual_area() {
+        let lines = vec!["ab ab ab".to_string(), "ab".to_string()];
+        let mut search = Search::new();
+        search.set_pattern(r"\%Vab");
+        search.execute(&lines, 0, 0);
+        assert!(search.matches().is_empty());
+
+        let mut area = Selection::new(crate::selection::VisualMode::Char, 0, 2);
+        area.update_cursor(0, 6);
+        search.set_visual_area(Some(area));
+        search.execute(&lines, 0, 0);
+        assert_eq!(search.matches(), [SearchMatch { line: 0, start_col: 3, end_col: 5 }]);
+
+        search.set_pattern("ab");
+        search.execute(&lines, 0, 0);
+        assert_eq!(search.matches().len(), 4);
+    }
+
+    #[test]
+    fn test_search_offset() {
+        assert_eq!(split_offset("foo/e", '/'), ("foo", Some("e")));
+        assert_eq!(split_offset("a\\/b/+1", '/'), ("a\\/b", Some("+1")));
+        assert_eq!(split_offset("foo", '/'), ("foo", None));
+        assert_eq!(split_offset("a/b?", '?'), ("a/b", Some("")));
+
+        assert_eq!(SearchOffset::parse(""), Some(SearchOffset::Start(0)));
+        assert_eq!(SearchOffset::parse("e"), Some(SearchOffset::End(0)));
+        assert_eq!(SearchOffset::parse("e-1"), Some(SearchOffset::End(-1)));
+        assert_eq!(SearchOffset::parse("s+2"), Some(SearchOffset::Start(2)));
+        assert_eq!(SearchOffset::parse("b-"), Some(SearchOffset::Start(-1)));
+        assert_eq!(SearchOffset::parse("+1"), Some(SearchOffset::Lines(1)));
+        assert_eq!(SearchOffset::parse("-"), Some(SearchOffset::Lines(-1)));
+        assert_eq!(SearchOffset::parse("3"), Some(SearchOffset::Lines(3)));
+        assert_eq!(SearchOffset::parse("x"), None);
+
+        let m = SearchMatch { line: 2, start_col: 4, end_col: 7 };
+        assert_eq!(SearchOffset::End(0).apply(m, 10), (2, 6));
+        assert_eq!(SearchOffset::Start(-1).apply(m, 10), (2, 3));
+        assert_eq!(SearchOffset::Lines(-3).apply(m, 10), (0, 0));
+        assert_eq!(SearchOffset::Lines(9).apply(m, 10), (9, 0));
+
+        let mut search = Search::new();
+        search.start(SearchDirection::Forward);
+        search.set_pattern("world/e");
+        assert_eq!(search.regex_pattern(), "world");
+        assert_eq!(search.offset(), SearchOffset::End(0));
+        search.execute(&["hello world".to_string()], 0, 0);
+        assert_eq!(search.current(), Some(SearchMatch { line: 0, start_col: 6, end_col: 11 }));
+    }
 }
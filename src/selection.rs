@@ -14,7 +14,7 @@ pub enum VisualMode {
 }
 
 /// A text selection
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Selection {
     /// Visual mode type
     pub mode: VisualMode,
@@ -125,6 +125,27 @@ impl Selection {
             (self.cursor_col, self.anchor_col)
         }
     }
+
+    /// Columns of `line` inside the selection (the end is exclusive and
+    /// usize::MAX for the rest of the line), or None if it is outside
+    pub fn cols_on_line(&self, line: usize) -> Option<std::ops::Range<usize>> {
+        let (start_line, start_col, end_line, end_col) = self.normalized();
+        if line < start_line || line > end_line {
+            return None;
+        }
+        match self.mode {
+            VisualMode::Line => Some(0..usize::MAX),
+            VisualMode::Block => {
+                let (left, right) = self.col_range();
+                Some(left..right + 1)
+            }
+            VisualMode::Char => {
+                let from = if line == start_line { start_col } else { 0 };
+                let to = if line == end_line { end_col + 1 } else { usize::MAX };
+                Some(from..to)
+            }
+        }
+    }
 }
 
 /// Clipboard/register for yanked text
@@ -187,6 +208,20 @@ mod tests {
         assert_eq!(end_col, 10);
     }
 
+    #[test]
+    fn test_cols_on_line() {
+        let mut sel = Selection::new(VisualMode::Char, 1, 4);
+        sel.update_cursor(3, 2);
+        assert_eq!(sel.cols_on_line(0), None);
+        assert_eq!(sel.cols_on_line(1), Some(4..usize::MAX));
+        assert_eq!(sel.cols_on_line(2), Some(0..usize::MAX));
+        assert_eq!(sel.cols_on_line(3), Some(0..3));
+        sel.mode = VisualMode::Block;
+        assert_eq!(sel.cols_on_line(2), Some(2..5));
+        sel.mode = VisualMode::Line;
+        assert_eq!(sel.cols_on_line(3), Some(0..usize::MAX));
+    }
+
     #[test]
     fn test_visual_mode_variants() {
         let char_mode = VisualMode::Char;
@@ -0,0 +1,179 @@
+//! Saved editor state for Quirks
+//!
+//! Registers, marks and the command and search histories are written to a
+//! state file (~/.local/share/quirks/shada on Linux) on exit and read back
+//! on startup, like Vim's viminfo and Neovim's shada file. Each line is
+//! one item; text has backslashes and newlines escaped.
+
+use crate::mark::Mark;
+use crate::register::RegisterContent;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Entries kept of each history
+pub const MAX_HISTORY: usize = 100;
+
+/// State carried from one session to the next
+#[derive(Debug, Default)]
+pub struct ShaDa {
+    pub registers: Vec<(char, RegisterContent)>,
+    pub marks: Vec<(char, Mark)>,
+    /// Ex commands, oldest first
+    pub command_history: Vec<String>,
+    /// Search patterns, oldest first
+    pub search_history: Vec<String>,
+    /// State file this was read from and is written to, if any
+    state_path: Option<PathBuf>,
+}
+
+impl ShaDa {
+    /// Load the state from the default state file
+    pub fn load() -> Self {
+        match dirs::data_local_dir() {
+            Some(dir) => Self::from_file(dir.join("quirks").join("shada")),
+            None => Self::default(),
+        }
+    }
+
+    /// Load the state from a specific file (missing file = empty state).
+    /// Lines that do not parse are skipped.
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut shada = Self::default();
+        for line in fs::read_to_string(&path).unwrap_or_default().lines() {
+            shada.parse_line(line);
+        }
+        shada.state_path = Some(path);
+        shada
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<()> {
+        let (kind, rest) = line.split_once(' ')?;
+        match kind {
+            "register" => {
+                let mut fields = rest.splitn(3, ' ');
+                let name = fields.next()?.chars().next()?;
+                let kind = fields.next()?;
+                let text = unescape(fields.next()?);
+                let content = match kind {
+                    "chars" => RegisterContent::Chars(text),
+                    "lines" => RegisterContent::Lines(text),
+                    "block" => RegisterContent::Block(text.split('\n').map(String::from).collect()),
+                    _ => return None,
+                };
+                self.registers.push((name, content));
+            }
+            "mark" => {
+                let mut fields = rest.splitn(4, ' ');
+                let name = fields.next()?.chars().next()?;
+                let line = fields.next()?.parse().ok()?;
+                let col = fields.next()?.parse().ok()?;
+                let file = Some(PathBuf::from(unescape(fields.next()?)));
+                self.marks.push((name, Mark { file, line, col }));
+            }
+            "command" => self.command_history.push(unescape(rest)),
+            "search" => self.search_history.push(unescape(rest)),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Write the state to the state file
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.state_path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut content = String::from("# Quirks state, rewritten on exit\n");
+        for (name, register) in &self.registers {
+            let (kind, text) = match register {
+                RegisterContent::Chars(text) => ("chars", escape(text)),
+                RegisterContent::Lines(text) => ("lines", escape(text)),
+                RegisterContent::Block(lines) => ("block", escape(&lines.join("\n"))),
+            };
+            content.push_str(&format!("register {} {} {}\n", name, kind, text));
+        }
+        for (name, mark) in &self.marks {
+            if let Some(file) = &mark.file {
+                let file = escape(&file.to_string_lossy());
+                content.push_str(&format!("mark {} {} {} {}\n", name, mark.line, mark.col, file));
+            }
+        }
+        let recent = |history: &[String]| history[history.len().saturating_sub(MAX_HISTORY)..].to_vec();
+        for command in recent(&self.command_history) {
+            content.push_str(&format!("command {}\n", escape(&command)));
+        }
+        for pattern in recent(&self.search_history) {
+            content.push_str(&format!("search {}\n", escape(&pattern)));
+        }
+        fs::write(path, content)
+    }
+}
+
+/// Add `entry` as the newest history item, dropping an older copy of it
+pub fn add_history(history: &mut Vec<String>, entry: &str) {
+    if entry.is_empty() {
+        return;
+    }
+    history.retain(|item| item != entry);
+    history.push(entry.to_string());
+    if history.len() > MAX_HISTORY {
+        history.remove(0);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shada_round_trip() {
+        let dir = std::env::temp_dir().join(format!("quirks-shada-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let state = dir.join("state").join("shada");
+
+        let mut shada = ShaDa::from_file(&state);
+        assert!(shada.registers.is_empty());
+        shada.registers.push(('a', RegisterContent::Lines("fn main() {\n    \\n\n".to_string())));
+        shada.registers.push(('"', RegisterContent::Chars("word".to_string())));
+        shada.marks.push(('A', Mark { file: Some(PathBuf::from("/tmp/a b.rs")), line: 4, col: 2 }));
+        shada.marks.push(('x', Mark { file: None, line: 1, col: 0 }));
+        add_history(&mut shada.command_history, "set nu");
+        add_history(&mut shada.command_history, "w");
+        add_history(&mut shada.command_history, "set nu");
+        add_history(&mut shada.search_history, "foo\\|bar");
+        shada.save().unwrap();
+
+        let loaded = ShaDa::from_file(&state);
+        assert_eq!(loaded.registers, shada.registers);
+        // Marks of unsaved buffers are not kept
+        assert_eq!(loaded.marks, shada.marks[..1]);
+        assert_eq!(loaded.command_history, vec!["w", "set nu"]);
+        assert_eq!(loaded.search_history, vec!["foo\\|bar"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
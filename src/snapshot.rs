@@ -0,0 +1,42 @@
+//! Screen state without a terminal for Quirks
+//!
+//! A plain-data picture of what the editor shows, for frontends other than
+//! the terminal view and for tests.
+
+use crate::editor::Editor;
+use crate::mode::Mode;
+
+/// What the editor shows, independent of how it is drawn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Buffer line shown in the first row
+    pub first_line: usize,
+    /// Text of the visible lines
+    pub lines: Vec<String>,
+    /// Cursor line and column in the buffer
+    pub cursor: (usize, usize),
+    pub mode: Mode,
+    /// The bottom line: command being typed or last message
+    pub command_line: String,
+    /// File name, None for an unnamed buffer
+    pub file_name: Option<String>,
+    pub modified: bool,
+}
+
+impl Snapshot {
+    /// What `editor` shows in a window `height` lines tall
+    pub fn of(editor: &Editor, height: usize) -> Self {
+        let buffer = editor.buffer();
+        let first_line = editor.scroll_offset();
+        let end = (first_line + height).min(buffer.line_count());
+        Self {
+            first_line,
+            lines: (first_line..end).map(|line| buffer.line(line)).collect(),
+            cursor: (editor.cursor().line, editor.cursor().col),
+            mode: editor.mode(),
+            command_line: editor.command_line(),
+            file_name: buffer.file_name().map(String::from),
+            modified: buffer.is_modified(),
+        }
+    }
+}
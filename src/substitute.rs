@@ -16,13 +16,13 @@ pub struct SubstituteResult {
 }
 
 /// Flags for substitution
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct SubstituteFlags {
     /// Replace all occurrences on each line (g flag)
     pub global: bool,
     /// Case insensitive matching (i flag)
     pub ignore_case: bool,
-    /// Confirm each replacement (c flag) - not yet implemented
+    /// Confirm each replacement (c flag)
     pub confirm: bool,
     /// Print matching lines (p flag) - not yet implemented  
     pub print: bool,
@@ -48,24 +48,17 @@ impl SubstituteFlags {
 /// Parse a substitute command
 /// Format: s/pattern/replacement/flags or %s/pattern/replacement/flags
 pub fn parse_substitute_command(cmd: &str) -> Option<(Option<Range>, String, String, SubstituteFlags)> {
-    let cmd = cmd.trim();
-    
-    // Check for range prefix
-    let (range, rest) = if cmd.starts_with('%') {
-        (Some(Range::All), &cmd[1..])
-    } else if cmd.starts_with('.') {
-        (Some(Range::Current), &cmd[1..])
-    } else if let Some(stripped) = cmd.strip_prefix("s") {
-        (Some(Range::Current), cmd)
-    } else {
-        (None, cmd)
-    };
+    let (range, rest) = parse_range(cmd.trim());
     
     // Must start with 's'
     let rest = rest.strip_prefix('s')?;
     
-    // Get delimiter (usually /)
+    // Get delimiter (usually /); like Vim, letters and digits can't be one,
+    // which keeps commands like :stats from parsing as substitutions
     let delim = rest.chars().next()?;
+    if delim.is_alphanumeric() || matches!(delim, '\\' | '"' | '|' | ' ') {
+        return None;
+    }
     let rest = &rest[delim.len_utf8()..];
     
     // Split by delimiter
@@ -86,6 +79,98 @@ pub fn parse_substitute_command(cmd: &str) -> Option<(Option<Range>, String, Str
     Some((range, pattern, replacement, flags))
 }
 
+/// Split a line range off the front of an ex command: `%`, `.`, `$`,
+/// `'<,'>`, a line number or `first,last` (1-based, `last` may be `$`).
+/// No range means the current line.
+pub fn parse_range(cmd: &str) -> (Option<Range>, &str) {
+    if let Some(rest) = cmd.strip_prefix('%') {
+        return (Some(Range::All), rest);
+    }
+    if let Some(rest) = cmd.strip_prefix('.') {
+        return (Some(Range::Current), rest);
+    }
+    if let Some(rest) = cmd.strip_prefix('$') {
+        return (Some(Range::Last), rest);
+    }
+    if let Some(rest) = cmd.strip_prefix("'<,'>") {
+        return (Some(Range::Visual), rest);
+    }
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let end = digits(cmd);
+    let Ok(first) = cmd[..end].parse::<usize>() else {
+        return (None, cmd);
+    };
+    let first = first.saturating_sub(1);
+    let rest = &cmd[end..];
+    if let Some(after) = rest.strip_prefix(',') {
+        if let Some(after) = after.strip_prefix('$') {
+            return (Some(Range::Lines(first, usize::MAX)), after);
+        }
+        let end = digits(after);
+        if let Ok(last) = after[..end].parse::<usize>() {
+            return (Some(Range::Lines(first, last.saturating_sub(1))), &after[end..]);
+        }
+    }
+    (Some(Range::Line(first)), rest)
+}
+
+/// Line an ex address like `:m`'s names: `N`, `.` or `$`, then any
+/// `+N`/`-N` offsets (a bare `+` or `-` is one line; an address that is
+/// only offsets counts from `.`). Lines are 1-based and 0 is above the
+/// first; `current` and `last` are 0-based.
+pub fn parse_address(text: &str, current: usize, last: usize) -> Option<usize> {
+    let text = text.trim();
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (mut line, mut rest) = match text.chars().next()? {
+        '.' => (current as isize + 1, &text[1..]),
+        '$' => (last as isize + 1, &text[1..]),
+        '+' | '-' => (current as isize + 1, text),
+        _ => {
+            let end = digits(text);
+            (text[..end].parse().ok()?, &text[end..])
+        }
+    };
+    while let Some(sign) = rest.chars().next() {
+        let sign = match sign {
+            '+' => 1,
+            '-' => -1,
+            _ => return None,
+        };
+        let end = digits(&rest[1..]) + 1;
+        let offset = if end == 1 { 1 } else { rest[1..end].parse::<isize>().ok()? };
+        line += sign * offset;
+        rest = &rest[end..];
+    }
+    (0..=last as isize + 1).contains(&line).then_some(line as usize)
+}
+
+/// Turn a Vim replacement into the regex crate's syntax: `&` and `\0` are
+/// the whole match, `\1`-`\9` groups, and `\&` a literal `&`
+pub fn vim_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => {
+                    converted.push_str("${");
+                    converted.push(d);
+                    converted.push('}');
+                }
+                Some('n') => converted.push('\n'),
+                Some('t') => converted.push('\t'),
+                Some('$') => converted.push_str("$$"),
+                Some(other) => converted.push(other),
+                None => converted.push('\\'),
+            },
+            _ => converted.push(ch),
+        }
+    }
+    converted
+}
+
 /// Split string by delimiter, respecting escapes
 fn split_by_delimiter(s: &str, delim: char) -> Vec<&str> {
     let mut parts = Vec::new();
@@ -122,15 +207,48 @@ pub enum Range {
     Current,
     /// All lines (%)
     All,
+    /// Last line ($)
+    Last,
     /// Specific line number
     Line(usize),
     /// Range of lines (start, end)
     Lines(usize, usize),
+    /// Lines of the last visual selection ('<,'>)
+    Visual,
+}
+
+/// Compile a :s pattern with its flags
+pub fn build_regex(pattern: &str, flags: &SubstituteFlags) -> Result<Regex, String> {
+    if flags.ignore_case {
+        Regex::new(&format!("(?i){}", pattern))
+    } else {
+        Regex::new(pattern)
+    }
+    .map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+/// `line` with the matches of `regex` replaced by `replacement` (in the
+/// regex crate's syntax), and the character ranges the replacements take
+/// up in it, for previewing a :s while it is typed
+pub fn replace_with_spans(line: &str, regex: &Regex, replacement: &str, global: bool) -> (String, Vec<std::ops::Range<usize>>) {
+    let mut result = String::new();
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for caps in regex.captures_iter(line).take(if global { usize::MAX } else { 1 }) {
+        let whole = caps.get(0).unwrap();
+        result.push_str(&line[last..whole.start()]);
+        let start = result.chars().count();
+        caps.expand(replacement, &mut result);
+        spans.push(start..result.chars().count());
+        last = whole.end();
+    }
+    result.push_str(&line[last..]);
+    (result, spans)
 }
 
 /// Perform substitution on text
 pub fn substitute(
-    lines: &mut Vec<String>,
+    lines: &mut [String],
     range: Range,
     pattern: &str,
     replacement: &str,
@@ -138,17 +256,13 @@ pub fn substitute(
     current_line: usize,
 ) -> SubstituteResult {
     // Build regex
-    let regex = match if flags.ignore_case {
-        Regex::new(&format!("(?i){}", pattern))
-    } else {
-        Regex::new(pattern)
-    } {
+    let regex = match build_regex(pattern, flags) {
         Ok(r) => r,
         Err(e) => {
             return SubstituteResult {
                 count: 0,
                 lines: 0,
-                error: Some(format!("Invalid pattern: {}", e)),
+                error: Some(e),
             };
         }
     };
@@ -157,10 +271,15 @@ pub fn substitute(
     let (start, end) = match range {
         Range::Current => (current_line, current_line),
         Range::All => (0, lines.len().saturating_sub(1)),
+        Range::Last => (lines.len().saturating_sub(1), lines.len().saturating_sub(1)),
         Range::Line(n) => (n, n),
         Range::Lines(s, e) => (s, e),
+        // Only the editor knows the selection; it resolves this itself
+        Range::Visual => (current_line, current_line),
     };
     
+    let replacement = vim_replacement(replacement);
+    let replacement = replacement.as_str();
     let mut total_count = 0;
     let mut affected_lines = 0;
     
@@ -199,7 +318,7 @@ mod tests {
     fn test_parse_substitute_basic() {
         let result = parse_substitute_command("s/foo/bar/");
         assert!(result.is_some());
-        let (range, pattern, replacement, flags) = result.unwrap();
+        let (_, pattern, replacement, flags) = result.unwrap();
         assert_eq!(pattern, "foo");
         assert_eq!(replacement, "bar");
         assert!(!flags.global);
@@ -259,11 +378,11 @@ mod tests {
     #[test]
     fn test_substitute_global_flag() {
         let mut lines = vec!["aaa".to_string()];
-        let mut flags = SubstituteFlags::default();
-        flags.global = true;
+        let flags = SubstituteFlags { global: true, ..Default::default() };
         
         let result = substitute(&mut lines, Range::Current, "a", "b", &flags, 0);
         
+        assert_eq!(result.count, 3);
         assert_eq!(lines[0], "bbb");
     }
 
@@ -274,9 +393,55 @@ mod tests {
         
         let result = substitute(&mut lines, Range::Current, r"\d+", "###", &flags, 0);
         
+        assert_eq!(result.count, 1);
         assert_eq!(lines[0], "hello###world");
     }
 
+    #[test]
+    fn test_vim_replacement() {
+        let mut lines = vec!["key = value".to_string()];
+        let flags = SubstituteFlags::default();
+        substitute(&mut lines, Range::Current, r"(\w+) = (\w+)", r"\2 = \1 [&] \& $1", &flags, 0);
+        assert_eq!(lines[0], "value = key [key = value] & $1");
+    }
+
+    #[test]
+    fn test_replace_with_spans() {
+        let regex = build_regex("o+", &SubstituteFlags::default()).unwrap();
+        let replacement = vim_replacement("<&>");
+        let (line, spans) = replace_with_spans("föo boo", &regex, &replacement, false);
+        assert_eq!(line, "fö<o> boo");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0], 2..5);
+        assert_eq!(replace_with_spans("foo boo", &regex, &replacement, true).1, vec![1..5, 7..11]);
+        assert_eq!(replace_with_spans("x", &regex, &replacement, true), ("x".to_string(), vec![]));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert!(matches!(parse_range("3,5s/a/b/"), (Some(Range::Lines(2, 4)), "s/a/b/")));
+        assert!(matches!(parse_range("7&&"), (Some(Range::Line(6)), "&&")));
+        assert!(matches!(parse_range("&&"), (None, "&&")));
+        assert!(matches!(parse_range("2,$d"), (Some(Range::Lines(1, usize::MAX)), "d")));
+        assert!(matches!(parse_range("'<,'>s/a/b/"), (Some(Range::Visual), "s/a/b/")));
+        assert!(parse_substitute_command("stats").is_none());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("0", 4, 9), Some(0));
+        assert_eq!(parse_address("7", 4, 9), Some(7));
+        assert_eq!(parse_address("$", 4, 9), Some(10));
+        assert_eq!(parse_address("+1", 4, 9), Some(6));
+        assert_eq!(parse_address("-2", 4, 9), Some(3));
+        assert_eq!(parse_address(".+", 4, 9), Some(6));
+        assert_eq!(parse_address("$-3+1", 4, 9), Some(8));
+        assert_eq!(parse_address("-6", 4, 9), None);
+        assert_eq!(parse_address("11", 4, 9), None);
+        assert_eq!(parse_address("x", 4, 9), None);
+        assert_eq!(parse_address("", 4, 9), None);
+    }
+
     #[test]
     fn test_split_by_delimiter() {
         let parts = split_by_delimiter("foo/bar/baz", '/');
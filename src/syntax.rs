@@ -1,9 +1,41 @@
 //! Syntax highlighting for Quirks
 //!
 //! Simple regex-based syntax highlighting. Tree-sitter integration planned for v0.2.
+//!
+//! The syntax for a file comes from its name (Makefile, Dockerfile), its
+//! extension or, for scripts without one, the interpreter on its `#!` line.
 
 use ratatui::style::{Color, Style};
 use std::collections::HashMap;
+use std::path::Path;
+
+/// Syntaxes of files known by their whole name
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("Makefile", "make"),
+    ("makefile", "make"),
+    ("GNUmakefile", "make"),
+    ("Dockerfile", "dockerfile"),
+    ("Containerfile", "dockerfile"),
+    ("Cargo.lock", "toml"),
+    ("PKGBUILD", "sh"),
+    (".bashrc", "sh"),
+    (".bash_profile", "sh"),
+    (".profile", "sh"),
+    (".zshrc", "sh"),
+];
+
+/// Syntaxes of scripts by the interpreter on their `#!` line
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "py"),
+    ("node", "js"),
+    ("deno", "js"),
+    ("sh", "sh"),
+    ("bash", "sh"),
+    ("zsh", "sh"),
+    ("dash", "sh"),
+    ("ksh", "sh"),
+    ("make", "make"),
+];
 
 /// A syntax highlighting rule
 #[derive(Debug, Clone)]
@@ -31,6 +63,14 @@ pub struct SyntaxDef {
     pub comment_multi: Option<(&'static str, &'static str)>,
     /// String delimiters
     pub string_delimiters: &'static [char],
+    /// Words `%` jumps between, matchit-style: the opening, middle and
+    /// closing words separated by `:`, alternatives by `|` ("if:elif|else:fi")
+    pub match_words: &'static [&'static str],
+    /// Keywords opening a `{}` block of arms, with what marks an arm
+    /// (`match` and `=>`), for `%` to step through the arms
+    pub match_arms: &'static [(&'static str, &'static str)],
+    /// Whether `%` jumps between HTML opening and closing tags
+    pub match_tags: bool,
 }
 
 /// Highlighted span within a line
@@ -78,6 +118,9 @@ impl Highlighter {
             comment_single: Some("//"),
             comment_multi: Some(("/*", "*/")),
             string_delimiters: &['"'],
+            match_words: &[],
+            match_arms: &[("match", "=>")],
+            match_tags: false,
         });
 
         // Python syntax
@@ -95,6 +138,9 @@ impl Highlighter {
             comment_single: Some("#"),
             comment_multi: None,
             string_delimiters: &['"', '\''],
+            match_words: &[],
+            match_arms: &[],
+            match_tags: false,
         });
 
         // JavaScript/TypeScript
@@ -113,6 +159,46 @@ impl Highlighter {
             comment_single: Some("//"),
             comment_multi: Some(("/*", "*/")),
             string_delimiters: &['"', '\'', '`'],
+            match_words: &[],
+            match_arms: &[("switch", "case|default")],
+            match_tags: false,
+        });
+
+        // C and C++
+        syntaxes.insert("c", SyntaxDef {
+            extensions: &["c", "h", "cc", "cpp", "cxx", "hpp"],
+            name: "C",
+            keywords: &[
+                "break", "case", "const", "continue", "default", "do", "else", "enum",
+                "extern", "for", "goto", "if", "inline", "return", "sizeof", "static",
+                "struct", "switch", "typedef", "union", "volatile", "while",
+                "class", "namespace", "template", "public", "private", "protected",
+                "new", "delete", "true", "false", "NULL", "nullptr",
+            ],
+            types: &[
+                "void", "char", "short", "int", "long", "float", "double", "signed",
+                "unsigned", "bool", "size_t", "auto",
+            ],
+            comment_single: Some("//"),
+            comment_multi: Some(("/*", "*/")),
+            string_delimiters: &['"'],
+            match_words: &["#if|#ifdef|#ifndef:#elif|#else:#endif"],
+            match_arms: &[("switch", "case|default")],
+            match_tags: false,
+        });
+
+        // HTML and XML
+        syntaxes.insert("html", SyntaxDef {
+            extensions: &["html", "htm", "xhtml", "xml", "svg"],
+            name: "HTML",
+            keywords: &[],
+            types: &[],
+            comment_single: None,
+            comment_multi: Some(("<!--", "-->")),
+            string_delimiters: &['"', '\''],
+            match_words: &[],
+            match_arms: &[],
+            match_tags: true,
         });
 
         // Markdown
@@ -124,6 +210,62 @@ impl Highlighter {
             comment_single: None,
             comment_multi: None,
             string_delimiters: &[],
+            match_words: &[],
+            match_arms: &[],
+            match_tags: true,
+        });
+
+        // Shell scripts
+        syntaxes.insert("sh", SyntaxDef {
+            extensions: &["sh", "bash", "zsh"],
+            name: "Shell",
+            keywords: &[
+                "if", "then", "else", "elif", "fi", "case", "esac", "for", "while",
+                "until", "do", "done", "in", "function", "return", "local", "export",
+                "readonly", "shift", "exit", "source",
+            ],
+            types: &[],
+            comment_single: Some("#"),
+            comment_multi: None,
+            string_delimiters: &['"', '\''],
+            match_words: &["if:elif|else:fi", "case:esac", "do:done"],
+            match_arms: &[],
+            match_tags: false,
+        });
+
+        // Makefiles
+        syntaxes.insert("make", SyntaxDef {
+            extensions: &["mk", "mak"],
+            name: "Makefile",
+            keywords: &[
+                "ifeq", "ifneq", "ifdef", "ifndef", "else", "endif", "include",
+                "define", "endef", "export", "override", "PHONY",
+            ],
+            types: &[],
+            comment_single: Some("#"),
+            comment_multi: None,
+            string_delimiters: &['"', '\''],
+            match_words: &["ifeq|ifneq|ifdef|ifndef:else:endif", "define:endef"],
+            match_arms: &[],
+            match_tags: false,
+        });
+
+        // Dockerfiles
+        syntaxes.insert("dockerfile", SyntaxDef {
+            extensions: &["dockerfile", "containerfile"],
+            name: "Dockerfile",
+            keywords: &[
+                "FROM", "AS", "RUN", "CMD", "LABEL", "EXPOSE", "ENV", "ADD", "COPY",
+                "ENTRYPOINT", "VOLUME", "USER", "WORKDIR", "ARG", "ONBUILD",
+                "STOPSIGNAL", "HEALTHCHECK", "SHELL",
+            ],
+            types: &[],
+            comment_single: Some("#"),
+            comment_multi: None,
+            string_delimiters: &['"'],
+            match_words: &[],
+            match_arms: &[],
+            match_tags: false,
         });
 
         // TOML
@@ -135,6 +277,9 @@ impl Highlighter {
             comment_single: Some("#"),
             comment_multi: None,
             string_delimiters: &['"', '\''],
+            match_words: &[],
+            match_arms: &[],
+            match_tags: false,
         });
 
         Self {
@@ -147,43 +292,106 @@ impl Highlighter {
     pub fn set_syntax_for_extension(&mut self, ext: &str) {
         let ext = ext.trim_start_matches('.');
         self.current = self.syntaxes.keys().find(|&&k| {
-            self.syntaxes.get(k).map_or(false, |s| s.extensions.contains(&ext))
+            self.syntaxes.get(k).is_some_and(|s| s.extensions.contains(&ext))
         }).copied();
     }
 
+    /// Set the current syntax by name (`python`), key or extension (`py`);
+    /// `off` turns highlighting off. False if the name is not known.
+    pub fn set_syntax_by_name(&mut self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        if matches!(name.as_str(), "off" | "none" | "plain" | "") {
+            self.current = None;
+            return true;
+        }
+        let found = self.syntaxes.iter().find(|(key, syntax)| {
+            **key == name || syntax.name.to_lowercase() == name || syntax.extensions.contains(&name.as_str())
+        });
+        match found {
+            Some((key, _)) => {
+                self.current = Some(key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names of the known syntaxes in lowercase, sorted
+    pub fn syntax_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.syntaxes.values().map(|syntax| syntax.name.to_lowercase()).collect();
+        names.sort();
+        names
+    }
+
+    /// Set the current syntax for a file from its name, its extension or
+    /// the `#!` line that starts `first_line`, or turn it off if none of
+    /// them is known
+    pub fn set_syntax_for_file(&mut self, path: &Path, first_line: &str) {
+        self.current = self.detect(path, first_line);
+    }
+
+    fn detect(&self, path: &Path, first_line: &str) -> Option<&'static str> {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let by_name = FILE_NAMES.iter()
+            .find(|(known, _)| name == *known)
+            .or_else(|| FILE_NAMES.iter().find(|(known, _)| name.strip_prefix(known).is_some_and(|rest| rest.starts_with('.'))));
+        if let Some((_, key)) = by_name {
+            return Some(key);
+        }
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let by_ext = self.syntaxes.iter().find(|(_, syntax)| syntax.extensions.contains(&ext));
+        if let Some((key, _)) = by_ext {
+            return Some(key);
+        }
+        let program = interpreter(first_line)?;
+        INTERPRETERS.iter().find(|(known, _)| program == *known).map(|(_, key)| *key)
+    }
+
     /// Get the current syntax name
     pub fn current_syntax_name(&self) -> Option<&'static str> {
         self.current.and_then(|ext| self.syntaxes.get(ext).map(|s| s.name))
     }
 
+    /// Get the current syntax definition
+    pub fn current_syntax(&self) -> Option<&SyntaxDef> {
+        self.current.and_then(|ext| self.syntaxes.get(ext))
+    }
+
     /// Highlight a line of text
     pub fn highlight_line(&self, line: &str) -> Vec<HighlightSpan> {
         let mut spans = Vec::new();
-        
+        self.highlight_line_into(line, &mut spans);
+        spans
+    }
+
+    /// Highlight a line of text into `spans`, which is cleared first, so
+    /// the renderer can reuse one buffer for every line. Spans are in
+    /// character columns; the line is scanned by byte without copying it.
+    pub fn highlight_line_into(&self, line: &str, spans: &mut Vec<HighlightSpan>) {
+        spans.clear();
         let syntax = match self.current.and_then(|ext| self.syntaxes.get(ext)) {
             Some(s) => s,
-            None => return spans, // No highlighting
+            None => return, // No highlighting
         };
 
-        let chars: Vec<char> = line.chars().collect();
-        let mut i = 0;
-
-        // Helper: check if chars starting at index match a prefix string
-        let chars_start_with = |chars: &[char], idx: usize, prefix: &str| -> bool {
-            let prefix_chars: Vec<char> = prefix.chars().collect();
-            if idx + prefix_chars.len() > chars.len() {
-                return false;
+        // `b` is the byte offset of character `i`
+        let (mut b, mut i) = (0, 0);
+        let next = |b: usize| line[b..].chars().next();
+        // Consume characters while `pred` holds
+        let take_while = |b: &mut usize, i: &mut usize, pred: &dyn Fn(char) -> bool| {
+            while let Some(c) = next(*b).filter(|&c| pred(c)) {
+                *b += c.len_utf8();
+                *i += 1;
             }
-            chars[idx..idx + prefix_chars.len()] == prefix_chars[..]
         };
 
-        while i < chars.len() {
+        while let Some(c) = next(b) {
             // Check for comments
             if let Some(comment_prefix) = syntax.comment_single {
-                if chars_start_with(&chars, i, comment_prefix) {
+                if line[b..].starts_with(comment_prefix) {
                     spans.push(HighlightSpan {
                         start: i,
-                        end: chars.len(),
+                        end: i + line[b..].chars().count(),
                         style: Style::default().fg(Color::DarkGray),
                     });
                     break;
@@ -191,18 +399,23 @@ impl Highlighter {
             }
 
             // Check for strings
-            if syntax.string_delimiters.contains(&chars[i]) {
-                let delim = chars[i];
+            if syntax.string_delimiters.contains(&c) {
                 let start = i;
+                b += c.len_utf8();
                 i += 1;
-                while i < chars.len() && chars[i] != delim {
-                    if chars[i] == '\\' && i + 1 < chars.len() {
-                        i += 1; // Skip escaped char
-                    }
+                while let Some(d) = next(b) {
+                    b += d.len_utf8();
                     i += 1;
-                }
-                if i < chars.len() {
-                    i += 1; // Include closing delimiter
+                    if d == c {
+                        break; // Include closing delimiter
+                    }
+                    if d == '\\' {
+                        // Skip escaped char
+                        if let Some(escaped) = next(b) {
+                            b += escaped.len_utf8();
+                            i += 1;
+                        }
+                    }
                 }
                 spans.push(HighlightSpan {
                     start,
@@ -213,11 +426,9 @@ impl Highlighter {
             }
 
             // Check for numbers
-            if chars[i].is_ascii_digit() {
+            if c.is_ascii_digit() {
                 let start = i;
-                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.' || chars[i] == '_') {
-                    i += 1;
-                }
+                take_while(&mut b, &mut i, &|c| c.is_ascii_alphanumeric() || c == '.' || c == '_');
                 spans.push(HighlightSpan {
                     start,
                     end: i,
@@ -227,20 +438,18 @@ impl Highlighter {
             }
 
             // Check for identifiers (keywords, types)
-            if chars[i].is_alphabetic() || chars[i] == '_' {
-                let start = i;
-                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
-                    i += 1;
-                }
-                let word: String = chars[start..i].iter().collect();
-                
-                if syntax.keywords.contains(&word.as_str()) {
+            if c.is_alphabetic() || c == '_' {
+                let (start, start_byte) = (i, b);
+                take_while(&mut b, &mut i, &|c| c.is_alphanumeric() || c == '_');
+                let word = &line[start_byte..b];
+
+                if syntax.keywords.contains(&word) {
                     spans.push(HighlightSpan {
                         start,
                         end: i,
                         style: Style::default().fg(Color::Yellow),
                     });
-                } else if syntax.types.contains(&word.as_str()) {
+                } else if syntax.types.contains(&word) {
                     spans.push(HighlightSpan {
                         start,
                         end: i,
@@ -250,11 +459,22 @@ impl Highlighter {
                 continue;
             }
 
+            b += c.len_utf8();
             i += 1;
         }
+    }
+}
 
-        spans
+/// Program a `#!` line runs, without its directory or version:
+/// `python` for `#!/usr/bin/env python3.11`
+pub fn interpreter(first_line: &str) -> Option<&str> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
     }
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    (!program.is_empty()).then_some(program)
 }
 
 // Color scheme constants
@@ -301,6 +521,37 @@ mod tests {
         assert!(highlighter.current_syntax_name().is_none());
     }
 
+    #[test]
+    fn test_set_syntax_by_name() {
+        let mut highlighter = Highlighter::new();
+        assert!(highlighter.set_syntax_by_name("python"));
+        assert_eq!(highlighter.current_syntax_name(), Some("Python"));
+        assert!(highlighter.set_syntax_by_name("bash"));
+        assert_eq!(highlighter.current_syntax_name(), Some("Shell"));
+        assert!(!highlighter.set_syntax_by_name("cobol"));
+        assert_eq!(highlighter.current_syntax_name(), Some("Shell"));
+        assert!(highlighter.set_syntax_by_name("off"));
+        assert_eq!(highlighter.current_syntax_name(), None);
+        assert!(highlighter.syntax_names().contains(&"makefile".to_string()));
+    }
+
+    #[test]
+    fn test_detect_syntax() {
+        let mut highlighter = Highlighter::new();
+        let mut detect = |path: &str, first_line: &str| {
+            highlighter.set_syntax_for_file(Path::new(path), first_line);
+            highlighter.current_syntax_name()
+        };
+        assert_eq!(detect("src/Makefile", ""), Some("Makefile"));
+        assert_eq!(detect("Dockerfile.dev", "FROM rust"), Some("Dockerfile"));
+        assert_eq!(detect("main.rs", "#!/bin/sh"), Some("Rust"));
+        assert_eq!(detect("bin/deploy", "#!/usr/bin/env -S bash -e"), Some("Shell"));
+        assert_eq!(detect("tool", "#!/usr/bin/python3.11"), Some("Python"));
+        assert_eq!(detect("notes", "hello"), None);
+        assert_eq!(interpreter("#! /usr/bin/env node"), Some("node"));
+        assert_eq!(interpreter("# not a shebang"), None);
+    }
+
     #[test]
     fn test_highlight_rust_keyword() {
         let mut highlighter = Highlighter::new();
@@ -309,6 +560,15 @@ mod tests {
         assert!(!spans.is_empty());
     }
 
+    #[test]
+    fn test_highlight_columns_are_chars() {
+        let mut highlighter = Highlighter::new();
+        highlighter.set_syntax_for_extension("rs");
+        let spans = highlighter.highlight_line("let ä = \"ö\\\"\"; // ü");
+        let ranges: Vec<(usize, usize)> = spans.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(ranges, vec![(0, 3), (8, 13), (15, 19)]);
+    }
+
     #[test]
     fn test_highlight_comment() {
         let mut highlighter = Highlighter::new();
@@ -0,0 +1,227 @@
+//! Table formatting for Quirks
+//!
+//! Lines up the columns of pipe-delimited tables as written in Markdown
+//! (`| a | b |` with a `| --- | :-: |` separator) and Org mode
+//! (`|---+---|` separators).
+
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Column alignment from a Markdown separator row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    /// No colons: left aligned, written back without colons
+    Default,
+    Left,
+    Center,
+    Right,
+}
+
+enum Row {
+    Cells(Vec<String>),
+    /// A separator row; `org` if it used `+` between columns
+    Separator { org: bool },
+}
+
+/// Whether a line is part of a table
+pub fn is_table_line(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// Lines of the table containing `line`, if it is in one
+pub fn table_range<S: AsRef<str>>(lines: &[S], line: usize) -> Option<Range<usize>> {
+    if !is_table_line(lines.get(line)?.as_ref()) {
+        return None;
+    }
+    let start = line - lines[..line].iter().rev().take_while(|l| is_table_line(l.as_ref())).count();
+    let end = line + lines[line..].iter().take_while(|l| is_table_line(l.as_ref())).count();
+    Some(start..end)
+}
+
+/// Reformat table lines so every column has the same width. The first
+/// line's indentation is used for the whole table.
+pub fn format_table<S: AsRef<str>>(lines: &[S]) -> Vec<String> {
+    let Some(first) = lines.first() else { return Vec::new() };
+    let first = first.as_ref();
+    let indent = &first[..first.len() - first.trim_start().len()];
+    let rows: Vec<Row> = lines.iter().map(|line| parse_row(line.as_ref())).collect();
+
+    let columns = rows.iter()
+        .map(|row| match row {
+            Row::Cells(cells) => cells.len(),
+            Row::Separator { .. } => 0,
+        })
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    // Alignment comes from the first Markdown separator
+    let mut aligns = vec![Align::Default; columns];
+    if let Some(line) = lines.iter().map(AsRef::as_ref).find(|l| is_separator(l) && !l.contains('+')) {
+        for (align, cell) in aligns.iter_mut().zip(split_cells(line)) {
+            *align = match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (true, false) => Align::Left,
+                (false, true) => Align::Right,
+                (false, false) => Align::Default,
+            };
+        }
+    }
+
+    let mut widths = vec![1; columns];
+    for row in &rows {
+        if let Row::Cells(cells) = row {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(cell.graphemes(true).count());
+            }
+        }
+    }
+    // Markdown needs at least three dashes, plus room for colons
+    if rows.iter().any(|row| matches!(row, Row::Separator { org: false })) {
+        for width in &mut widths {
+            *width = (*width).max(3);
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            let body = match row {
+                Row::Cells(cells) => format_cells(cells, &widths, &aligns),
+                Row::Separator { org: true } => format_org_separator(&widths),
+                Row::Separator { org: false } => format_separator(&widths, &aligns),
+            };
+            format!("{}{}", indent, body)
+        })
+        .collect()
+}
+
+/// Column just after the `n`th pipe of a line, skipping the padding space,
+/// so the cursor can stay in its cell after reformatting
+pub fn cell_start(line: &str, n: usize) -> Option<usize> {
+    let graphemes: Vec<&str> = line.graphemes(true).collect();
+    let pipe = pipe_columns(&graphemes).nth(n.checked_sub(1)?)?;
+    let col = pipe + 1;
+    Some(if graphemes.get(col) == Some(&" ") && col + 1 < graphemes.len() { col + 1 } else { col })
+}
+
+/// Number of unescaped pipes before column `col`
+pub fn pipes_before(line: &str, col: usize) -> usize {
+    let graphemes: Vec<&str> = line.graphemes(true).collect();
+    pipe_columns(&graphemes).take_while(|&c| c < col).count()
+}
+
+fn pipe_columns<'a>(graphemes: &'a [&str]) -> impl Iterator<Item = usize> + 'a {
+    (0..graphemes.len()).filter(|&i| graphemes[i] == "|" && (i == 0 || graphemes[i - 1] != "\\"))
+}
+
+fn parse_row(line: &str) -> Row {
+    if is_separator(line) {
+        Row::Separator { org: line.contains('+') }
+    } else {
+        Row::Cells(split_cells(line))
+    }
+}
+
+/// A row made only of dashes, colons, `+` and pipes
+fn is_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.contains('-') && line.chars().all(|c| matches!(c, '|' | '-' | ':' | '+' | ' '))
+}
+
+/// Trimmed cells of a row; `\|` does not split a cell
+fn split_cells(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = if line.ends_with('|') && !line.ends_with("\\|") { &line[..line.len() - 1] } else { line };
+    let mut cells = vec![String::new()];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cells.last_mut().unwrap().push_str("\\|");
+                chars.next();
+            }
+            '|' => cells.push(String::new()),
+            _ => cells.last_mut().unwrap().push(c),
+        }
+    }
+    cells.iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+fn format_cells(cells: &[String], widths: &[usize], aligns: &[Align]) -> String {
+    let mut out = String::from("|");
+    for (i, (&width, &align)) in widths.iter().zip(aligns).enumerate() {
+        let cell = cells.get(i).map_or("", String::as_str);
+        let pad = width - cell.graphemes(true).count();
+        let (left, right) = match align {
+            Align::Default | Align::Left => (0, pad),
+            Align::Right => (pad, 0),
+            Align::Center => (pad / 2, pad - pad / 2),
+        };
+        out.push_str(&format!(" {}{}{} |", " ".repeat(left), cell, " ".repeat(right)));
+    }
+    out
+}
+
+fn format_separator(widths: &[usize], aligns: &[Align]) -> String {
+    let mut out = String::from("|");
+    for (&width, &align) in widths.iter().zip(aligns) {
+        let (left, right) = match align {
+            Align::Default => ("-", "-"),
+            Align::Left => (":", "-"),
+            Align::Right => ("-", ":"),
+            Align::Center => (":", ":"),
+        };
+        out.push_str(&format!(" {}{}{} |", left, "-".repeat(width - 2), right));
+    }
+    out
+}
+
+fn format_org_separator(widths: &[usize]) -> String {
+    let dashes: Vec<String> = widths.iter().map(|w| "-".repeat(w + 2)).collect();
+    format!("|{}|", dashes.join("+"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_markdown_table() {
+        let lines = [
+            "  | Name | Count | Notes |",
+            "|:-|--:|:-:|",
+            "| quirks | 3 | a \\| b |",
+            "| x | 12345",
+        ];
+        assert_eq!(format_table(&lines), vec![
+            "  | Name   | Count | Notes  |",
+            "  | :----- | ----: | :----: |",
+            "  | quirks |     3 | a \\| b |",
+            "  | x      | 12345 |        |",
+        ]);
+    }
+
+    #[test]
+    fn test_format_org_table() {
+        let lines = ["| a | bb |", "|---+--|", "|ccc|d|"];
+        assert_eq!(format_table(&lines), vec![
+            "| a   | bb |",
+            "|-----+----|",
+            "| ccc | d  |",
+        ]);
+    }
+
+    #[test]
+    fn test_table_range_and_cells() {
+        let lines = ["text", "| a |", "| b |", "", "| c |"];
+        assert_eq!(table_range(&lines, 2), Some(1..3));
+        assert_eq!(table_range(&lines, 4), Some(4..5));
+        assert_eq!(table_range(&lines, 0), None);
+
+        assert_eq!(pipes_before("| a | b |", 5), 2);
+        assert_eq!(cell_start("| a   | b |", 2), Some(8));
+        assert_eq!(cell_start("| a |", 2), Some(5));
+        assert_eq!(cell_start("| a |", 0), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! ctags support for Quirks
+//!
+//! Reads a `tags` file in the format written by ctags (Exuberant or
+//! Universal) so Ctrl-] can jump to definitions without a language server.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where a tag's definition is within its file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagAddress {
+    /// 0-based line number
+    Line(usize),
+    /// Text of the defining line (from a `/^...$/` search pattern)
+    Pattern(String),
+}
+
+/// A single tag definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    /// File containing the definition, relative to the tags file
+    pub file: PathBuf,
+    pub address: TagAddress,
+}
+
+impl Tag {
+    /// Line of the definition within `lines`, if it can be found
+    pub fn find_line<S: AsRef<str>>(&self, lines: &[S]) -> Option<usize> {
+        match &self.address {
+            TagAddress::Line(line) => Some(*line),
+            TagAddress::Pattern(text) => lines.iter().position(|line| line.as_ref() == text),
+        }
+    }
+}
+
+/// Tags loaded from a tags file, sorted by name
+#[derive(Debug, Default)]
+pub struct TagFile {
+    tags: Vec<Tag>,
+}
+
+impl TagFile {
+    /// Load a tags file; paths in it are made relative to its directory
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut tags = Self::parse(&content);
+        for tag in &mut tags.tags {
+            tag.file = base.join(&tag.file);
+        }
+        Ok(tags)
+    }
+
+    /// Parse the contents of a tags file, skipping `!_TAG_` metadata
+    pub fn parse(content: &str) -> Self {
+        let mut tags: Vec<Tag> = content
+            .lines()
+            .filter(|line| !line.starts_with("!_TAG_"))
+            .filter_map(parse_line)
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { tags }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// All definitions of a tag
+    pub fn find(&self, name: &str) -> &[Tag] {
+        let start = self.tags.partition_point(|tag| tag.name.as_str() < name);
+        let end = start + self.tags[start..].iter().take_while(|tag| tag.name == name).count();
+        &self.tags[start..end]
+    }
+
+    /// Distinct tag names starting with `prefix`, for completion
+    pub fn names_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        let start = self.tags.partition_point(|tag| tag.name.as_str() < prefix);
+        let mut names: Vec<&str> = self.tags[start..]
+            .iter()
+            .take_while(|tag| tag.name.starts_with(prefix))
+            .map(|tag| tag.name.as_str())
+            .collect();
+        names.dedup();
+        names
+    }
+}
+
+/// Parse one `name<TAB>file<TAB>address[;"<TAB>fields]` line
+fn parse_line(line: &str) -> Option<Tag> {
+    let mut parts = line.splitn(3, '\t');
+    let name = parts.next()?;
+    let file = parts.next()?;
+    let rest = parts.next()?;
+    // Extended fields follow `;"`; patterns may contain tabs, so cut there
+    let address = rest.rsplit_once(";\"").map_or(rest, |(address, _)| address);
+    Some(Tag {
+        name: name.to_string(),
+        file: PathBuf::from(file),
+        address: parse_address(address.trim_end())?,
+    })
+}
+
+fn parse_address(address: &str) -> Option<TagAddress> {
+    if let Ok(line) = address.parse::<usize>() {
+        return Some(TagAddress::Line(line.saturating_sub(1)));
+    }
+    let pattern = address
+        .strip_prefix('/')
+        .and_then(|p| p.strip_suffix('/'))
+        .or_else(|| address.strip_prefix('?').and_then(|p| p.strip_suffix('?')))?;
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = pattern.strip_suffix('$').unwrap_or(pattern);
+    Some(TagAddress::Pattern(pattern.replace("\\/", "/").replace("\\\\", "\\")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: &str = "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+        Editor\tsrc/editor.rs\t/^pub struct Editor {$/;\"\ts\n\
+        main\tsrc/main.rs\t/^fn main() -> Result<()> {$/;\"\tf\n\
+        new\tsrc/buffer.rs\t12;\"\tf\n\
+        new\tsrc/editor.rs\t/^    pub fn new() -> Self {$/;\"\tf\n";
+
+    #[test]
+    fn test_parse_tags() {
+        let tags = TagFile::parse(TAGS);
+        let editor = tags.find("Editor");
+        assert_eq!(editor.len(), 1);
+        assert_eq!(editor[0].file, PathBuf::from("src/editor.rs"));
+        assert_eq!(editor[0].address, TagAddress::Pattern("pub struct Editor {".to_string()));
+
+        let new = tags.find("new");
+        assert_eq!(new.len(), 2);
+        assert_eq!(new[0].address, TagAddress::Line(11));
+        assert!(tags.find("missing").is_empty());
+    }
+
+    #[test]
+    fn test_find_line_and_completion() {
+        let tags = TagFile::parse(TAGS);
+        let lines = ["use x;", "fn main() -> Result<()> {", "}"];
+        assert_eq!(tags.find("main")[0].find_line(&lines), Some(1));
+        assert_eq!(tags.names_with_prefix("n"), vec!["new"]);
+        assert_eq!(tags.names_with_prefix(""), vec!["Editor", "main", "new"]);
+    }
+}
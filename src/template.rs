@@ -0,0 +1,89 @@
+//! File templates for Quirks
+//!
+//! A file opened under a name that does not exist yet can start from a
+//! skeleton in ~/.config/quirks/templates/: one named like the whole file
+//! (`Makefile`, `Cargo.toml`) or `skeleton.<ext>` for its extension.
+//! `{{filename}}`, `{{name}}`, `{{date}}`, `{{year}}` and `{{author}}` in
+//! the skeleton are replaced; other `{{...}}` are left alone.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Values substituted into a template
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars {
+    /// File name with extension
+    pub filename: String,
+    /// File name without extension
+    pub name: String,
+    /// Today as `YYYY-MM-DD`
+    pub date: String,
+    pub author: String,
+}
+
+/// Directory templates are looked up in
+pub fn template_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config").join("quirks").join("templates"))
+}
+
+/// Template in `dir` for a new file at `path`, if there is one
+pub fn find(dir: &Path, path: &Path) -> Option<PathBuf> {
+    let by_name = dir.join(path.file_name()?);
+    if by_name.is_file() {
+        return Some(by_name);
+    }
+    let ext = path.extension()?.to_str()?;
+    let by_ext = dir.join(format!("skeleton.{}", ext));
+    by_ext.is_file().then_some(by_ext)
+}
+
+/// `template` with its variables replaced
+pub fn expand(template: &str, vars: &TemplateVars) -> String {
+    let year = vars.date.split('-').next().unwrap_or_default();
+    template
+        .replace("{{filename}}", &vars.filename)
+        .replace("{{name}}", &vars.name)
+        .replace("{{date}}", &vars.date)
+        .replace("{{year}}", year)
+        .replace("{{author}}", &vars.author)
+}
+
+/// Load and expand the template for a new file at `path`
+pub fn load(dir: &Path, path: &Path, vars: &TemplateVars) -> Option<(PathBuf, String)> {
+    let template = find(dir, path)?;
+    let text = fs::read_to_string(&template).ok()?;
+    Some((template, expand(&text, vars)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let vars = TemplateVars {
+            filename: "main.rs".to_string(),
+            name: "main".to_string(),
+            date: "2024-05-06".to_string(),
+            author: "Ada".to_string(),
+        };
+        assert_eq!(
+            expand("// {{filename}} ({{name}}) by {{author}}, {{date}}\n// (c) {{year}} {{other}}", &vars),
+            "// main.rs (main) by Ada, 2024-05-06\n// (c) 2024 {{other}}"
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = std::env::temp_dir().join(format!("quirks-template-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("skeleton.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("Makefile"), "all:\n").unwrap();
+
+        assert_eq!(find(&dir, Path::new("src/new.rs")), Some(dir.join("skeleton.rs")));
+        assert_eq!(find(&dir, Path::new("Makefile")), Some(dir.join("Makefile")));
+        assert_eq!(find(&dir, Path::new("new.py")), None);
+        assert_eq!(find(&dir, Path::new("README")), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,241 @@
+//! Embedded terminal for Quirks
+//!
+//! Runs a shell on a pseudo-terminal and keeps its screen in a vt100
+//! parser, which the view draws as a pane below the editor.
+
+use crate::events::Waker;
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// Lines of scrollback kept by the terminal
+const SCROLLBACK: usize = 1000;
+
+/// A shell running in a pseudo-terminal
+pub struct Terminal {
+    parser: Arc<Mutex<vt100::Parser>>,
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    size: Mutex<(u16, u16)>,
+}
+
+impl Terminal {
+    /// Start `shell` in `dir` on a new pseudo-terminal; its output wakes
+    /// the main loop with `waker`
+    pub fn spawn(shell: &str, dir: &Path, rows: u16, cols: u16, waker: Waker) -> Result<Self> {
+        let pair = native_pty_system().openpty(pty_size(rows, cols))?;
+        let mut command = CommandBuilder::new(shell);
+        command.cwd(dir);
+        command.env("TERM", "xterm-256color");
+        let child = pair.slave.spawn_command(command)?;
+        // The child holds its own handle; keeping ours would stop EOF on exit
+        drop(pair.slave);
+
+        let parser = Arc::new(Mutex::new(vt100::Parser::new(rows, cols, SCROLLBACK)));
+        let mut reader = pair.master.try_clone_reader()?;
+        let writer = pair.master.take_writer()?;
+
+        let thread_parser = Arc::clone(&parser);
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                if let Ok(mut parser) = thread_parser.lock() {
+                    parser.process(&buf[..n]);
+                }
+                waker.wake();
+            }
+            // The shell exited
+            waker.wake();
+        });
+
+        Ok(Self {
+            parser,
+            master: pair.master,
+            writer,
+            child,
+            size: Mutex::new((rows, cols)),
+        })
+    }
+
+    /// The terminal screen, locked for reading
+    pub fn screen(&self) -> MutexGuard<'_, vt100::Parser> {
+        self.parser.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the shell is still running
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Resize the pseudo-terminal to match the pane, if it changed
+    pub fn resize(&self, rows: u16, cols: u16) {
+        let mut size = self.size.lock().unwrap_or_else(|e| e.into_inner());
+        if *size == (rows, cols) || rows == 0 || cols == 0 {
+            return;
+        }
+        *size = (rows, cols);
+        let _ = self.master.resize(pty_size(rows, cols));
+        self.screen().screen_mut().set_size(rows, cols);
+    }
+
+    /// Send raw bytes to the shell
+    pub fn write(&mut self, bytes: &[u8]) {
+        let _ = self.writer.write_all(bytes);
+        let _ = self.writer.flush();
+    }
+
+    /// Send a key press to the shell
+    pub fn send_key(&mut self, key: &KeyEvent) {
+        let application_cursor = self.screen().screen().application_cursor();
+        let bytes = key_bytes(key, application_cursor);
+        if !bytes.is_empty() {
+            self.write(&bytes);
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// Run a shell command with `text` on its stdin, e.g. to paste into a REPL
+/// in another tmux pane
+pub fn pipe_to_command(command: &str, text: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+fn pty_size(rows: u16, cols: u16) -> PtySize {
+    PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+/// Bytes a terminal sends for a key press
+pub fn key_bytes(key: &KeyEvent, application_cursor: bool) -> Vec<u8> {
+    let cursor = |c: u8| {
+        if application_cursor {
+            vec![0x1b, b'O', c]
+        } else {
+            vec![0x1b, b'[', c]
+        }
+    };
+    let mut bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c {
+            'a'..='z' | 'A'..='Z' => vec![(c.to_ascii_lowercase() as u8) & 0x1f],
+            '@' | ' ' | '2' => vec![0],
+            '[' | '3' => vec![0x1b],
+            '\\' | '4' => vec![0x1c],
+            ']' | '5' => vec![0x1d],
+            '^' | '6' => vec![0x1e],
+            '_' | '7' => vec![0x1f],
+            _ => c.to_string().into_bytes(),
+        },
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => cursor(b'A'),
+        KeyCode::Down => cursor(b'B'),
+        KeyCode::Right => cursor(b'C'),
+        KeyCode::Left => cursor(b'D'),
+        KeyCode::Home => cursor(b'H'),
+        KeyCode::End => cursor(b'F'),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + n - 1],
+        KeyCode::F(n) => {
+            let code = match n {
+                5 => 15,
+                6..=10 => n + 11,
+                11..=12 => n + 12,
+                _ => return Vec::new(),
+            };
+            format!("\x1b[{}~", code).into_bytes()
+        }
+        _ => Vec::new(),
+    };
+    // Alt sends an escape prefix
+    if key.modifiers.contains(KeyModifiers::ALT) && !bytes.is_empty() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_key_bytes() {
+        assert_eq!(key_bytes(&key(KeyCode::Char('a'), KeyModifiers::NONE), false), b"a");
+        assert_eq!(key_bytes(&key(KeyCode::Char('c'), KeyModifiers::CONTROL), false), vec![3]);
+        assert_eq!(key_bytes(&key(KeyCode::Char('b'), KeyModifiers::ALT), false), b"\x1bb");
+        assert_eq!(key_bytes(&key(KeyCode::Enter, KeyModifiers::NONE), false), b"\r");
+        assert_eq!(key_bytes(&key(KeyCode::Up, KeyModifiers::NONE), false), b"\x1b[A");
+        assert_eq!(key_bytes(&key(KeyCode::Up, KeyModifiers::NONE), true), b"\x1bOA");
+        assert_eq!(key_bytes(&key(KeyCode::F(5), KeyModifiers::NONE), false), b"\x1b[15~");
+    }
+
+    #[test]
+    fn test_pipe_to_command() {
+        let path = std::env::temp_dir().join(format!("quirks-repl-{}.txt", std::process::id()));
+        pipe_to_command(&format!("cat > {}", path.display()), "print(1)\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "print(1)\n");
+        std::fs::remove_file(&path).unwrap();
+        assert!(pipe_to_command("exit 3", "").is_err());
+    }
+
+    #[test]
+    fn test_spawn_shell() {
+        let mut term = Terminal::spawn("/bin/sh", Path::new("."), 10, 40, Waker::default()).unwrap();
+        term.write(b"echo quirks-$((40 + 2))\r");
+        let start = std::time::Instant::now();
+        while !term.screen().screen().contents().contains("quirks-42") {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5), "no output from shell");
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(term.is_alive());
+        term.write(b"exit\r");
+        let start = std::time::Instant::now();
+        while term.is_alive() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5), "shell did not exit");
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
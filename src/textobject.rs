@@ -0,0 +1,292 @@
+//! Text objects for Quirks
+//!
+//! What an `i` or `a` object covers around the cursor: a word, a quoted
+//! string, a bracket pair or a paragraph. Operators act on it (`diw`,
+//! `ci"`, `ya(`) and visual mode selects it (`vi{`).
+
+use crate::buffer::Buffer;
+
+/// How much of an object is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// The word, or what is between the quotes or brackets (i)
+    Inner,
+    /// With the white space after the word or quotes, the brackets
+    /// themselves, or the blank lines after a paragraph (a)
+    Around,
+}
+
+impl Scope {
+    /// The scope of `i` or `a`
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'i' => Some(Scope::Inner),
+            'a' => Some(Scope::Around),
+            _ => None,
+        }
+    }
+}
+
+/// The text an object covers, as (line, column) positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    /// Characters from `start` to `end`, both included
+    Chars { start: (usize, usize), end: (usize, usize) },
+    /// Nothing, at `at`: the inside of `()` or `""`
+    Empty { at: (usize, usize) },
+    /// Lines `start` to `end`, both included
+    Lines { start: usize, end: usize },
+}
+
+/// The object `kind` (w, W, a quote, a bracket, b, B or p) around `pos`
+pub fn select(buffer: &Buffer, pos: (usize, usize), kind: char, scope: Scope) -> Option<TextObject> {
+    match kind {
+        'w' => word(buffer, pos, scope, false),
+        'W' => word(buffer, pos, scope, true),
+        '"' | '\'' | '`' => quoted(buffer, pos, kind, scope),
+        '(' | ')' | 'b' => bracketed(buffer, pos, '(', ')', scope),
+        '[' | ']' => bracketed(buffer, pos, '[', ']', scope),
+        '{' | '}' | 'B' => bracketed(buffer, pos, '{', '}', scope),
+        '<' | '>' => bracketed(buffer, pos, '<', '>', scope),
+        'p' => Some(paragraph(buffer, pos.0, scope)),
+        _ => None,
+    }
+}
+
+/// Class of a character for word objects: white space, word characters,
+/// other characters. A WORD (`big`) is anything but white space.
+fn char_class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+/// Columns `start..end` of the run of characters of the same class as the
+/// one at `col`
+fn run_at(chars: &[char], col: usize, big: bool) -> (usize, usize) {
+    let class = char_class(chars[col], big);
+    let mut start = col;
+    while start > 0 && char_class(chars[start - 1], big) == class {
+        start -= 1;
+    }
+    let mut end = col + 1;
+    while end < chars.len() && char_class(chars[end], big) == class {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// iw, aw, iW and aW: the run of word characters, other characters or
+/// white space at the cursor. `a` adds the white space after a word, or
+/// before it when there is none after, and the word after white space.
+fn word(buffer: &Buffer, (line, col): (usize, usize), scope: Scope, big: bool) -> Option<TextObject> {
+    let chars: Vec<char> = buffer.line(line).chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+    let col = col.min(chars.len() - 1);
+    let (mut start, mut end) = run_at(&chars, col, big);
+    if scope == Scope::Around {
+        if chars[col].is_whitespace() {
+            if end < chars.len() {
+                end = run_at(&chars, end, big).1;
+            }
+        } else if end < chars.len() && chars[end].is_whitespace() {
+            end = run_at(&chars, end, big).1;
+        } else if start > 0 && chars[start - 1].is_whitespace() {
+            start = run_at(&chars, start - 1, big).0;
+        }
+    }
+    Some(TextObject::Chars { start: (line, start), end: (line, end - 1) })
+}
+
+/// i" and a": the quoted string on the line around the cursor, or else
+/// the first one after it. Quotes after a backslash do not count.
+fn quoted(buffer: &Buffer, (line, col): (usize, usize), quote: char, scope: Scope) -> Option<TextObject> {
+    let chars: Vec<char> = buffer.line(line).chars().collect();
+    let quotes: Vec<usize> = (0..chars.len())
+        .filter(|&idx| chars[idx] == quote && (idx == 0 || chars[idx - 1] != '\\'))
+        .collect();
+    let (open, close) = quotes.chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| col <= close)?;
+    if scope == Scope::Inner {
+        if close == open + 1 {
+            return Some(TextObject::Empty { at: (line, close) });
+        }
+        return Some(TextObject::Chars { start: (line, open + 1), end: (line, close - 1) });
+    }
+    let (mut start, mut end) = (open, close + 1);
+    if end < chars.len() && chars[end].is_whitespace() {
+        end = run_at(&chars, end, false).1;
+    } else if start > 0 && chars[start - 1].is_whitespace() {
+        start = run_at(&chars, start - 1, false).0;
+    }
+    Some(TextObject::Chars { start: (line, start), end: (line, end - 1) })
+}
+
+/// i( and a(: the innermost pair of brackets around the cursor. Inside a
+/// block whose brackets end and start lines of their own, i takes the
+/// lines between them.
+fn bracketed(buffer: &Buffer, pos: (usize, usize), open: char, close: char, scope: Scope) -> Option<TextObject> {
+    let start = match buffer.char_at(pos.0, pos.1) {
+        Some(c) if c == open => pos,
+        Some(c) if c == close => matching_bracket(buffer, pos)?,
+        _ => scan(buffer, pos, open, close, false)?,
+    };
+    let end = matching_bracket(buffer, start)?;
+    if scope == Scope::Around {
+        return Some(TextObject::Chars { start, end });
+    }
+    let block = start.1 + 1 == buffer.line_len(start.0)
+        && buffer.line(end.0).chars().take(end.1).all(char::is_whitespace);
+    if block && start.0 < end.0 {
+        if start.0 + 1 == end.0 {
+            return Some(TextObject::Empty { at: end });
+        }
+        return Some(TextObject::Lines { start: start.0 + 1, end: end.0 - 1 });
+    }
+    if start.0 == end.0 && start.1 + 1 == end.1 {
+        return Some(TextObject::Empty { at: end });
+    }
+    let first = if start.1 + 1 < buffer.line_len(start.0) { (start.0, start.1 + 1) } else { (start.0 + 1, 0) };
+    let last = if end.1 > 0 { (end.0, end.1 - 1) } else { (end.0 - 1, buffer.line_len(end.0 - 1).saturating_sub(1)) };
+    Some(TextObject::Chars { start: first, end: last })
+}
+
+/// ip and ap: the lines around `line` that are all blank or all not.
+/// `a` adds the blank lines after a paragraph, or before it when there
+/// are none after, and the paragraph after blank lines.
+fn paragraph(buffer: &Buffer, line: usize, scope: Scope) -> TextObject {
+    let blank = |idx: usize| buffer.line(idx).trim().is_empty();
+    let last = buffer.line_count().saturating_sub(1);
+    let run = |idx: usize| {
+        let (mut start, mut end) = (idx, idx);
+        while start > 0 && blank(start - 1) == blank(idx) {
+            start -= 1;
+        }
+        while end < last && blank(end + 1) == blank(idx) {
+            end += 1;
+        }
+        (start, end)
+    };
+    let (mut start, mut end) = run(line);
+    if scope == Scope::Around {
+        if end < last {
+            end = run(end + 1).1;
+        } else if start > 0 && !blank(line) {
+            start = run(start - 1).0;
+        }
+    }
+    TextObject::Lines { start, end }
+}
+
+/// Where the bracket at `pos` is closed or opened: `(` `)`, `[` `]`,
+/// `{` `}` and `<` `>` pair up, counting the ones nested in between
+pub fn matching_bracket(buffer: &Buffer, pos: (usize, usize)) -> Option<(usize, usize)> {
+    let current = buffer.char_at(pos.0, pos.1)?;
+    let (target, forward) = match current {
+        '(' => (')', true),
+        ')' => ('(', false),
+        '[' => (']', true),
+        ']' => ('[', false),
+        '{' => ('}', true),
+        '}' => ('{', false),
+        '<' => ('>', true),
+        '>' => ('<', false),
+        _ => return None,
+    };
+    scan(buffer, pos, target, current, forward)
+}
+
+/// The first `target` from `pos` on (not counting `pos` itself) that is
+/// not paired with a `nested` seen on the way
+fn scan(buffer: &Buffer, pos: (usize, usize), target: char, nested: char, forward: bool) -> Option<(usize, usize)> {
+    let (mut line, mut col) = pos;
+    let mut depth = 1;
+    loop {
+        if forward {
+            col += 1;
+            if col >= buffer.line_len(line) {
+                line += 1;
+                if line >= buffer.line_count() {
+                    return None;
+                }
+                col = 0;
+            }
+        } else if col == 0 {
+            if line == 0 {
+                return None;
+            }
+            line -= 1;
+            col = buffer.line_len(line).saturating_sub(1);
+        } else {
+            col -= 1;
+        }
+
+        match buffer.char_at(line, col) {
+            Some(c) if c == nested => depth += 1,
+            Some(c) if c == target => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((line, col));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(start: (usize, usize), end: (usize, usize)) -> Option<TextObject> {
+        Some(TextObject::Chars { start, end })
+    }
+
+    #[test]
+    fn test_words() {
+        let buffer = Buffer::from_text("let foo_bar = x.y;");
+        assert_eq!(select(&buffer, (0, 5), 'w', Scope::Inner), chars((0, 4), (0, 10)));
+        assert_eq!(select(&buffer, (0, 5), 'w', Scope::Around), chars((0, 4), (0, 11)));
+        assert_eq!(select(&buffer, (0, 3), 'w', Scope::Around), chars((0, 3), (0, 10)));
+        assert_eq!(select(&buffer, (0, 15), 'w', Scope::Inner), chars((0, 15), (0, 15)));
+        assert_eq!(select(&buffer, (0, 15), 'W', Scope::Around), chars((0, 13), (0, 17)));
+    }
+
+    #[test]
+    fn test_quotes() {
+        let buffer = Buffer::from_text(r#"say("a \"b\"", "") done"#);
+        assert_eq!(select(&buffer, (0, 6), '"', Scope::Inner), chars((0, 5), (0, 11)));
+        assert_eq!(select(&buffer, (0, 0), '"', Scope::Around), chars((0, 4), (0, 12)));
+        assert_eq!(select(&buffer, (0, 15), '"', Scope::Inner), Some(TextObject::Empty { at: (0, 16) }));
+        assert_eq!(select(&buffer, (0, 20), '"', Scope::Inner), None);
+    }
+
+    #[test]
+    fn test_brackets() {
+        let buffer = Buffer::from_text("f(a, (b)) + g()\nfn x() {\n    body\n}");
+        assert_eq!(select(&buffer, (0, 3), '(', Scope::Inner), chars((0, 2), (0, 7)));
+        assert_eq!(select(&buffer, (0, 6), 'b', Scope::Around), chars((0, 5), (0, 7)));
+        assert_eq!(select(&buffer, (0, 8), ')', Scope::Around), chars((0, 1), (0, 8)));
+        assert_eq!(select(&buffer, (0, 14), '(', Scope::Inner), Some(TextObject::Empty { at: (0, 14) }));
+        assert_eq!(select(&buffer, (2, 6), '{', Scope::Inner), Some(TextObject::Lines { start: 2, end: 2 }));
+        assert_eq!(select(&buffer, (2, 6), 'B', Scope::Around), chars((1, 7), (3, 0)));
+        assert_eq!(matching_bracket(&buffer, (0, 5)), Some((0, 7)));
+        assert_eq!(matching_bracket(&buffer, (3, 0)), Some((1, 7)));
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let buffer = Buffer::from_text("a\nb\n\n\nc\nd");
+        assert_eq!(select(&buffer, (1, 0), 'p', Scope::Inner), Some(TextObject::Lines { start: 0, end: 1 }));
+        assert_eq!(select(&buffer, (1, 0), 'p', Scope::Around), Some(TextObject::Lines { start: 0, end: 3 }));
+        assert_eq!(select(&buffer, (2, 0), 'p', Scope::Around), Some(TextObject::Lines { start: 2, end: 5 }));
+        assert_eq!(select(&buffer, (5, 0), 'p', Scope::Around), Some(TextObject::Lines { start: 2, end: 5 }));
+    }
+}
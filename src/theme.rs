@@ -2,8 +2,8 @@
 //!
 //! Supports built-in themes and loading from files.
 
+use crate::mode::Mode;
 use ratatui::style::{Color, Modifier, Style};
-use std::collections::HashMap;
 
 /// A color theme for the editor
 #[derive(Debug, Clone)]
@@ -24,6 +24,12 @@ pub struct Theme {
     pub status_line_insert: Style,
     /// Status line (visual mode)
     pub status_line_visual: Style,
+    /// Status line (command-line and search modes)
+    pub status_line_command: Style,
+    /// Status line while a macro is being recorded
+    pub status_line_recording: Style,
+    /// Status line for a read-only buffer
+    pub status_line_readonly: Style,
     /// Command line
     pub command_line: Style,
     /// Cursor line highlight
@@ -77,6 +83,9 @@ impl Theme {
             status_line: Style::default().fg(Color::Black).bg(Color::White),
             status_line_insert: Style::default().fg(Color::Black).bg(Color::Green),
             status_line_visual: Style::default().fg(Color::Black).bg(Color::Magenta),
+            status_line_command: Style::default().fg(Color::Black).bg(Color::Cyan),
+            status_line_recording: Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD),
+            status_line_readonly: Style::default().fg(Color::Black).bg(Color::Rgb(255, 150, 0)),
             command_line: Style::default().fg(Color::White),
             cursor_line: Style::default().bg(Color::Rgb(40, 40, 40)),
             selection: Style::default().bg(Color::Rgb(60, 60, 100)),
@@ -109,6 +118,9 @@ impl Theme {
             status_line: Style::default().fg(Color::White).bg(Color::DarkGray),
             status_line_insert: Style::default().fg(Color::White).bg(Color::Green),
             status_line_visual: Style::default().fg(Color::White).bg(Color::Magenta),
+            status_line_command: Style::default().fg(Color::White).bg(Color::Blue),
+            status_line_recording: Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD),
+            status_line_readonly: Style::default().fg(Color::White).bg(Color::Rgb(200, 120, 0)),
             command_line: Style::default().fg(Color::Black),
             cursor_line: Style::default().bg(Color::Rgb(240, 240, 240)),
             selection: Style::default().bg(Color::Rgb(180, 180, 220)),
@@ -141,6 +153,9 @@ impl Theme {
             status_line: Style::default().fg(Color::Rgb(248, 248, 242)).bg(Color::Rgb(64, 64, 64)),
             status_line_insert: Style::default().fg(Color::Black).bg(Color::Rgb(166, 226, 46)),
             status_line_visual: Style::default().fg(Color::Black).bg(Color::Rgb(174, 129, 255)),
+            status_line_command: Style::default().fg(Color::Black).bg(Color::Rgb(102, 217, 239)),
+            status_line_recording: Style::default().fg(Color::Rgb(248, 248, 242)).bg(Color::Rgb(249, 38, 114)).add_modifier(Modifier::BOLD),
+            status_line_readonly: Style::default().fg(Color::Black).bg(Color::Rgb(253, 151, 31)),
             command_line: Style::default().fg(Color::Rgb(248, 248, 242)),
             cursor_line: Style::default().bg(Color::Rgb(60, 60, 50)),
             selection: Style::default().bg(Color::Rgb(73, 72, 62)),
@@ -167,7 +182,6 @@ impl Theme {
         let base03 = Color::Rgb(0, 43, 54);
         let base02 = Color::Rgb(7, 54, 66);
         let base01 = Color::Rgb(88, 110, 117);
-        let base00 = Color::Rgb(101, 123, 131);
         let base0 = Color::Rgb(131, 148, 150);
         let base1 = Color::Rgb(147, 161, 161);
         let yellow = Color::Rgb(181, 137, 0);
@@ -189,6 +203,9 @@ impl Theme {
             status_line: Style::default().fg(base1).bg(base02),
             status_line_insert: Style::default().fg(base03).bg(green),
             status_line_visual: Style::default().fg(base03).bg(magenta),
+            status_line_command: Style::default().fg(base03).bg(cyan),
+            status_line_recording: Style::default().fg(base03).bg(red).add_modifier(Modifier::BOLD),
+            status_line_readonly: Style::default().fg(base03).bg(orange),
             command_line: Style::default().fg(base0),
             cursor_line: Style::default().bg(base02),
             selection: Style::default().bg(base02),
@@ -217,8 +234,6 @@ impl Theme {
         let nord2 = Color::Rgb(67, 76, 94);
         let nord3 = Color::Rgb(76, 86, 106);
         let nord4 = Color::Rgb(216, 222, 233); // Snow Storm
-        let nord5 = Color::Rgb(229, 233, 240);
-        let nord6 = Color::Rgb(236, 239, 244);
         let nord7 = Color::Rgb(143, 188, 187); // Frost
         let nord8 = Color::Rgb(136, 192, 208);
         let nord9 = Color::Rgb(129, 161, 193);
@@ -239,6 +254,9 @@ impl Theme {
             status_line: Style::default().fg(nord4).bg(nord1),
             status_line_insert: Style::default().fg(nord0).bg(nord14),
             status_line_visual: Style::default().fg(nord0).bg(nord15),
+            status_line_command: Style::default().fg(nord0).bg(nord8),
+            status_line_recording: Style::default().fg(nord0).bg(nord11).add_modifier(Modifier::BOLD),
+            status_line_readonly: Style::default().fg(nord0).bg(nord12),
             command_line: Style::default().fg(nord4),
             cursor_line: Style::default().bg(nord1),
             selection: Style::default().bg(nord2),
@@ -271,6 +289,16 @@ impl Theme {
         }
     }
 
+    /// Status line style for `mode`
+    pub fn status_line_for(&self, mode: Mode) -> Style {
+        match mode {
+            Mode::Insert | Mode::Terminal => self.status_line_insert,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => self.status_line_visual,
+            Mode::Command | Mode::Search => self.status_line_command,
+            Mode::Normal | Mode::Explorer | Mode::Picker => self.status_line,
+        }
+    }
+
     /// List available theme names
     pub fn available() -> Vec<&'static str> {
         vec!["dark", "light", "monokai", "solarized-dark", "nord"]
@@ -296,6 +324,21 @@ mod tests {
         assert!(Theme::by_name("nonexistent").is_none());
     }
 
+    #[test]
+    fn test_status_line_for_mode() {
+        let theme = Theme::nord();
+        assert_eq!(theme.status_line_for(Mode::Normal), theme.status_line);
+        assert_eq!(theme.status_line_for(Mode::Insert), theme.status_line_insert);
+        assert_eq!(theme.status_line_for(Mode::VisualBlock), theme.status_line_visual);
+        assert_eq!(theme.status_line_for(Mode::Search), theme.status_line_command);
+        for name in Theme::available() {
+            let theme = Theme::by_name(name).unwrap();
+            assert_ne!(theme.status_line.bg, theme.status_line_insert.bg, "{}", name);
+            assert_ne!(theme.status_line.bg, theme.status_line_recording.bg, "{}", name);
+            assert_ne!(theme.status_line.bg, theme.status_line_readonly.bg, "{}", name);
+        }
+    }
+
     #[test]
     fn test_available_themes() {
         let themes = Theme::available();
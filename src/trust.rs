@@ -0,0 +1,143 @@
+//! Trusted project files for Quirks
+//!
+//! A project's `.quirks.toml` comes with the code, and it can set the
+//! shell and the programs :make runs, so it is only read once the user
+//! agreed to. The answer is remembered per file in a small state file
+//! (~/.local/share/quirks/trust on Linux), along with a hash of the
+//! contents it was given for: a file that changed is asked about again.
+//! Each line is `allow<TAB>hash<TAB>path` or `deny<TAB>hash<TAB>path`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What the user decided about a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// Allowed with its current contents
+    Allowed,
+    /// Never to be read
+    Denied,
+    /// Not asked about yet, or changed since it was allowed
+    Unknown,
+}
+
+/// Remembered decisions about project files
+#[derive(Debug, Default)]
+pub struct TrustStore {
+    /// File path to whether it is allowed and the hash of its contents
+    entries: HashMap<PathBuf, (bool, u64)>,
+    /// State file the decisions are persisted to, if any
+    state_path: Option<PathBuf>,
+}
+
+impl TrustStore {
+    /// Load the decisions from the default state file
+    pub fn load() -> Self {
+        match dirs::data_local_dir() {
+            Some(dir) => Self::from_file(dir.join("quirks").join("trust")),
+            None => Self::default(),
+        }
+    }
+
+    /// Load the decisions from a specific state file (missing file = none)
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = fs::read_to_string(&path)
+            .map(|content| content.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+        Self {
+            entries,
+            state_path: Some(path),
+        }
+    }
+
+    /// Whether `path` with `content` may be read
+    pub fn check(&self, path: &Path, content: &str) -> Trust {
+        match self.entries.get(path) {
+            Some((false, _)) => Trust::Denied,
+            Some((true, hash)) if *hash == content_hash(content) => Trust::Allowed,
+            _ => Trust::Unknown,
+        }
+    }
+
+    /// Remember that `path` with `content` is allowed, or always denied
+    pub fn set(&mut self, path: &Path, content: &str, allow: bool) {
+        self.entries.insert(path.to_path_buf(), (allow, content_hash(content)));
+    }
+
+    /// Forget the decision about `path`; false if there was none
+    pub fn remove(&mut self, path: &Path) -> bool {
+        self.entries.remove(path).is_some()
+    }
+
+    /// Write the decisions to the state file
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.state_path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut lines: Vec<String> = self.entries
+            .iter()
+            .map(|(file, (allow, hash))| {
+                let decision = if *allow { "allow" } else { "deny" };
+                format!("{}\t{:016x}\t{}\n", decision, hash, file.display())
+            })
+            .collect();
+        lines.sort();
+        fs::write(path, lines.concat())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, (bool, u64))> {
+    let mut fields = line.splitn(3, '\t');
+    let allow = match fields.next()? {
+        "allow" => true,
+        "deny" => false,
+        _ => return None,
+    };
+    let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+    Some((PathBuf::from(fields.next()?), (allow, hash)))
+}
+
+/// FNV-1a hash of a file's contents; stable across builds, unlike std's
+/// hasher, so saved decisions keep matching
+fn content_hash(content: &str) -> u64 {
+    content.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trust_follows_content() {
+        let path = Path::new("/work/app/.quirks.toml");
+        let mut store = TrustStore::default();
+        assert_eq!(store.check(path, "tab_width = 2"), Trust::Unknown);
+        store.set(path, "tab_width = 2", true);
+        assert_eq!(store.check(path, "tab_width = 2"), Trust::Allowed);
+        assert_eq!(store.check(path, "shell = \"evil\""), Trust::Unknown);
+        store.set(path, "tab_width = 2", false);
+        assert_eq!(store.check(path, "anything"), Trust::Denied);
+        assert!(store.remove(path));
+        assert_eq!(store.check(path, "tab_width = 2"), Trust::Unknown);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let state = std::env::temp_dir().join(format!("quirks-trust-{}", std::process::id()));
+        let mut store = TrustStore::from_file(&state);
+        store.set(Path::new("/a/.quirks.toml"), "x = 1", true);
+        store.set(Path::new("/b/.quirks.toml"), "y = 2", false);
+        store.save().unwrap();
+
+        let loaded = TrustStore::from_file(&state);
+        assert_eq!(loaded.check(Path::new("/a/.quirks.toml"), "x = 1"), Trust::Allowed);
+        assert_eq!(loaded.check(Path::new("/b/.quirks.toml"), "y = 2"), Trust::Denied);
+        let _ = fs::remove_file(&state);
+    }
+}
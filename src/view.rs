@@ -2,48 +2,327 @@
 //!
 //! Handles all terminal UI rendering using ratatui.
 
+use crate::complete::Completion;
+use crate::decoration::Decoration;
+use crate::diff::{ChangeKind, DiffView};
 use crate::editor::Editor;
+use crate::explorer::Explorer;
+use crate::git;
+use crate::invisible;
+use crate::keymap::KeyCombo;
+use crate::lua;
 use crate::mode::Mode;
-use crate::search::{SearchDirection, SearchMatch};
+use crate::perf::{FrameStats, Phase};
+use crate::picker::Picker;
+use crate::search::SearchMatch;
 use crate::selection::Selection;
 use crate::syntax::HighlightSpan;
+use crate::terminal::Terminal;
+use crate::wrap;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::Paragraph,
+    widgets::{Block, Borders, Clear, Paragraph},
     Frame,
 };
+use std::borrow::Cow;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 
 /// Render the editor to the terminal
 pub fn render(frame: &mut Frame, editor: &Editor) {
+    // A message of several lines takes the rows it needs, up to half the
+    // screen, with the prompt below it
+    let command_height = editor.long_message().map_or(1, |message| {
+        (message.lines().count() as u16 + 1).min(frame.area().height / 2).max(1)
+    });
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Min(1),    // Editor area
-            Constraint::Length(1), // Status line
-            Constraint::Length(1), // Command line
+            Constraint::Length(if editor.zen() { 0 } else { 1 }), // Status line
+            Constraint::Length(command_height), // Command line
         ])
         .split(frame.area());
 
-    render_editor_area(frame, editor, chunks[0]);
-    render_status_line(frame, editor, chunks[1]);
+    // File explorer sidebar takes the left side of the editor area
+    let mut explorer_cursor = None;
+    let editor_area = match editor.explorer() {
+        Some(explorer) => {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(EXPLORER_WIDTH), Constraint::Min(1)])
+                .split(chunks[0]);
+            explorer_cursor = Some(render_explorer(frame, explorer, editor.mode() == Mode::Explorer, columns[0]));
+            columns[1]
+        }
+        None => chunks[0],
+    };
+
+    // The terminal pane takes the bottom of the editor area
+    let mut terminal_cursor = None;
+    let editor_area = match editor.terminal() {
+        Some(terminal) => {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(editor_area);
+            terminal_cursor = render_terminal(frame, terminal, editor.mode() == Mode::Terminal, rows[1]);
+            rows[0]
+        }
+        None => editor_area,
+    };
+
+    // Diff mode shows the other file to the right
+    let editor_area = match editor.diff_view() {
+        Some(diff) => {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(editor_area);
+            render_diff_pane(frame, diff, editor.scroll_offset(), columns[1]);
+            columns[0]
+        }
+        None => editor_area,
+    };
+
+    // Zen mode narrows the text to a centered column
+    let editor_area = if editor.zen() {
+        centered_column(editor_area, editor.config().zen_width as u16)
+    } else {
+        editor_area
+    };
+
+    render_editor_area(frame, editor, editor_area);
+    if !editor.zen() {
+        render_status_line(frame, editor, chunks[1]);
+    }
     render_command_line(frame, editor, chunks[2]);
 
-    // Render help overlay if in help mode
-    if editor.mode() == Mode::Help {
-        render_help_overlay(frame);
+    // Candidates of an insert mode completion
+    if let Some(completion) = editor.completion() {
+        let cursor = calculate_cursor_position(editor, editor_area);
+        render_completion(frame, completion, cursor, editor_area);
     }
 
+    // Floating windows opened from Lua
+    for popup in editor.popups() {
+        let cursor = calculate_cursor_position(editor, editor_area);
+        render_lua_popup(frame, popup, cursor, chunks[0]);
+    }
+
+    // Show what can follow a pending key sequence
+    if let Some((typed, hints)) = editor.key_hints() {
+        render_key_hints(frame, &typed, &hints, chunks[0]);
+    }
+
+    // Frame timings from :profile
+    if let Some(stats) = editor.frame_stats() {
+        render_profile(frame, &stats, editor_area);
+    }
+
+    // System metrics from :sysinfo
+    if editor.show_sysinfo() {
+        render_popup(frame, " System ", &editor.gpu_info().metrics().details(), chunks[0]);
+    }
+
+    // A prompt listing what it is about, such as the unsaved files when
+    // quitting; a bare question is asked in the command line
+    if let Some(prompt) = editor.prompt().filter(|prompt| !prompt.lines().is_empty()) {
+        let mut lines = prompt.lines().to_vec();
+        lines.push(String::new());
+        lines.push(prompt.footer());
+        render_popup(frame, &format!(" {} ", prompt.question()), &lines, chunks[0]);
+    }
+
+    // Fuzzy finder overlay
+    let picker_cursor = editor.picker().map(|picker| render_picker(frame, picker, frame.area()));
+
     // Position cursor
-    let (cursor_x, cursor_y) = calculate_cursor_position(editor, chunks[0]);
+    let (cursor_x, cursor_y) = match (explorer_cursor, picker_cursor) {
+        // The end of the text typed for a prompt
+        _ if editor.prompt().is_some_and(|prompt| prompt.input_text().is_some()) => {
+            let width = editor.prompt().map_or(0, |prompt| prompt.line().chars().count()) as u16;
+            (chunks[2].x + width.min(chunks[2].width.saturating_sub(1)), chunks[2].y)
+        }
+        (_, Some(pos)) if editor.mode() == Mode::Picker => pos,
+        (Some(pos), _) if editor.mode() == Mode::Explorer => pos,
+        _ if editor.mode() == Mode::Terminal => match terminal_cursor {
+            Some(pos) => pos,
+            None => return, // The program in the terminal hid its cursor
+        },
+        _ => calculate_cursor_position(editor, editor_area),
+    };
     frame.set_cursor_position((cursor_x, cursor_y));
 }
 
+/// A column of at most `width` cells in the middle of `area`
+fn centered_column(area: Rect, width: u16) -> Rect {
+    let width = width.min(area.width);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        width,
+        ..area
+    }
+}
+
+/// Width of the file explorer sidebar, including its border
+const EXPLORER_WIDTH: u16 = 30;
+
+/// Shown below a message of several lines until a key dismisses it
+const MORE_PROMPT: &str = "Press ENTER or type command to continue";
+
+/// Render the file explorer sidebar, returning the selected row's position
+fn render_explorer(frame: &mut Frame, explorer: &Explorer, focused: bool, area: Rect) -> (u16, u16) {
+    let block = Block::default()
+        .borders(Borders::RIGHT)
+        .border_style(Style::default().fg(Color::DarkGray));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    // Keep the selection visible
+    let height = inner.height as usize;
+    let selected = explorer.selected();
+    let top = (selected + 1).saturating_sub(height);
+
+    let lines: Vec<Line> = explorer.entries().iter().enumerate()
+        .skip(top)
+        .take(height)
+        .map(|(idx, entry)| {
+            let marker = match (entry.is_dir, entry.expanded) {
+                (true, true) => "▾ ",
+                (true, false) => "▸ ",
+                (false, _) => "  ",
+            };
+            let suffix = if entry.is_dir { "/" } else { "" };
+            let text = format!("{}{}{}{}", "  ".repeat(entry.depth), marker, entry.name(), suffix);
+            let mut style = if entry.is_dir {
+                Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            if idx == selected {
+                style = if focused {
+                    style.add_modifier(Modifier::REVERSED)
+                } else {
+                    style.bg(Color::DarkGray)
+                };
+            }
+            Line::from(Span::styled(text, style))
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
+
+    (inner.x, inner.y + (selected - top) as u16)
+}
+
+/// Render the embedded terminal pane, returning its cursor position if the
+/// cursor is visible
+fn render_terminal(frame: &mut Frame, terminal: &Terminal, focused: bool, area: Rect) -> Option<(u16, u16)> {
+    let border = if focused { Color::LightGreen } else { Color::DarkGray };
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .border_style(Style::default().fg(border))
+        .title(Span::styled(" terminal ", Style::default().fg(border)));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    terminal.resize(inner.height, inner.width);
+
+    let parser = terminal.screen();
+    let screen = parser.screen();
+    let lines: Vec<Line> = (0..inner.height)
+        .map(|row| {
+            let spans: Vec<Span> = (0..inner.width)
+                .filter_map(|col| {
+                    let cell = screen.cell(row, col)?;
+                    if cell.is_wide_continuation() {
+                        return None;
+                    }
+                    let text = if cell.has_contents() { cell.contents().to_string() } else { " ".to_string() };
+                    Some(Span::styled(text, terminal_cell_style(cell)))
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
+
+    if screen.hide_cursor() {
+        return None;
+    }
+    let (row, col) = screen.cursor_position();
+    Some((inner.x + col.min(inner.width.saturating_sub(1)), inner.y + row.min(inner.height.saturating_sub(1))))
+}
+
+/// Style of a terminal cell
+fn terminal_cell_style(cell: &vt100::Cell) -> Style {
+    let color = |color: vt100::Color| match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(idx) => Some(Color::Indexed(idx)),
+        vt100::Color::Rgb(r, g, b) => Some(Color::Rgb(r, g, b)),
+    };
+    let mut style = Style::default();
+    if let Some(fg) = color(cell.fgcolor()) {
+        style = style.fg(fg);
+    }
+    if let Some(bg) = color(cell.bgcolor()) {
+        style = style.bg(bg);
+    }
+    for (on, modifier) in [
+        (cell.bold(), Modifier::BOLD),
+        (cell.dim(), Modifier::DIM),
+        (cell.italic(), Modifier::ITALIC),
+        (cell.underline(), Modifier::UNDERLINED),
+        (cell.inverse(), Modifier::REVERSED),
+    ] {
+        if on {
+            style = style.add_modifier(modifier);
+        }
+    }
+    style
+}
+
+/// Background for a line that differs in diff mode
+fn diff_background(kind: ChangeKind) -> Color {
+    match kind {
+        ChangeKind::Added => Color::Rgb(0x1f, 0x3d, 0x26),
+        ChangeKind::Modified => Color::Rgb(0x3d, 0x38, 0x1f),
+        ChangeKind::Removed => Color::Rgb(0x4a, 0x1f, 0x22),
+    }
+}
+
+/// Render the file compared against in diff mode, scrolled to match the
+/// buffer's scroll position
+fn render_diff_pane(frame: &mut Frame, diff: &DiffView, scroll_offset: usize, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::LEFT)
+        .border_style(Style::default().fg(Color::DarkGray))
+        .title(Span::styled(diff.path().display().to_string(), Style::default().fg(Color::DarkGray)));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let top = diff.other_line(scroll_offset);
+    let lines: Vec<Line> = (top..top + inner.height as usize)
+        .map(|idx| match diff.lines().get(idx) {
+            Some(text) => {
+                let style = match diff.other_change(idx) {
+                    Some(kind) => Style::default().bg(diff_background(kind)),
+                    None => Style::default(),
+                };
+                // Pad so the change background spans the whole row
+                let width = inner.width as usize;
+                Line::from(Span::styled(format!("{:<width$}", text, width = width), style))
+            }
+            None => Line::from(Span::styled("~", Style::default().fg(Color::DarkGray))),
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
 /// Render the main editor area with line numbers and content
 fn render_editor_area(frame: &mut Frame, editor: &Editor, area: Rect) {
     let buffer = editor.buffer();
-    let scroll_offset = editor.scroll_offset();
     let show_line_numbers = editor.config().line_numbers;
 
     // Calculate line number width (only if showing)
@@ -54,97 +333,391 @@ fn render_editor_area(frame: &mut Frame, editor: &Editor, area: Rect) {
         0
     };
 
-    // Split into line numbers and content
+    // Split into signs, line numbers, content and the scrollbar
+    let git_hunks = editor.git_hunks();
+    let sign_width = sign_column_width(editor);
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([
+            Constraint::Length(sign_width),
             Constraint::Length(if show_line_numbers { line_num_width + 1 } else { 0 }),
             Constraint::Min(1),
+            Constraint::Length(scrollbar_width(editor)),
         ])
         .split(area);
+    if editor.config().scrollbar {
+        render_scrollbar(frame, editor, chunks[3]);
+    }
+    let rows = screen_rows(editor, chunks[2].width as usize, area.height as usize);
 
-    // Render line numbers (if enabled)
+    // Render signs: decoration signs, else git changes (only for files
+    // tracked by git)
+    if sign_width > 0 {
+        let signs: Vec<Line> = rows.iter()
+            .map(|row| {
+                if row.cols.start > 0 {
+                    return Line::from("");
+                }
+                let sign = buffer.line_decorations(row.line).into_iter().find_map(|(_, mark)| match &mark.decoration {
+                    Decoration::Sign { text, style } => Some(Span::styled(text.clone(), *style)),
+                    _ => None,
+                });
+                if let Some(sign) = sign {
+                    return Line::from(sign);
+                }
+                match git_hunks.and_then(|hunks| git::sign_at(hunks, row.line)) {
+                    Some(kind) => {
+                        let color = match kind {
+                            ChangeKind::Added => Color::Green,
+                            ChangeKind::Modified => Color::Yellow,
+                            ChangeKind::Removed => Color::Red,
+                        };
+                        Line::from(Span::styled(git::sign_char(kind).to_string(), Style::default().fg(color)))
+                    }
+                    None => Line::from(""),
+                }
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(signs), chunks[0]);
+    }
+
+    // Render line numbers (if enabled); wrapped rows get none
     if show_line_numbers {
         let mut line_numbers: Vec<Line> = Vec::new();
         for i in 0..area.height as usize {
-            let line_idx = scroll_offset + i;
-            if line_idx < total_lines {
-                line_numbers.push(Line::from(Span::styled(
-                    format!("{:>width$} ", line_idx + 1, width = line_num_width as usize),
-                    Style::default().fg(Color::DarkGray),
-                )));
-            } else {
-                line_numbers.push(Line::from(Span::styled(
-                    format!("{:>width$} ", "~", width = line_num_width as usize),
-                    Style::default().fg(Color::DarkGray),
-                )));
-            }
+            let label = match rows.get(i) {
+                Some(row) if row.cols.start == 0 => (row.line + 1).to_string(),
+                Some(_) => String::new(),
+                None => "~".to_string(),
+            };
+            line_numbers.push(Line::from(Span::styled(
+                format!("{:>width$} ", label, width = line_num_width as usize),
+                Style::default().fg(Color::DarkGray),
+            )));
         }
         let line_num_widget = Paragraph::new(line_numbers);
-        frame.render_widget(line_num_widget, chunks[0]);
+        frame.render_widget(line_num_widget, chunks[1]);
     }
 
-    // Render content with syntax, search, and selection highlighting
-    let highlighter = editor.highlighter();
+    // Render content with syntax, search, and selection highlighting.
+    // Spans borrow from the line text, which is read once per line.
+    // With syntax off the highlight pass is skipped entirely, which is
+    // also the way out when highlighting a file is slow
+    let highlighter = editor.syntax_enabled().then(|| editor.highlighter());
     let search = editor.search();
+    let search_highlight = editor.search_highlight();
     let selection = editor.selection();
-    let search_matches: Vec<&SearchMatch> = if search.highlight_active {
-        search.matches().iter().collect()
-    } else {
-        Vec::new()
+    let profiling = editor.profiling();
+    let first_line = rows.first().map_or(0, |row| row.line);
+    let line_count = rows.last().map_or(0, |row| row.line + 1 - first_line);
+    let texts: Vec<String> = (first_line..first_line + line_count).map(|line| buffer.line(line)).collect();
+
+    let mut content_lines: Vec<Line> = Vec::with_capacity(rows.len());
+    let mut syntax_highlights = Vec::new();
+    let mut line_spans: Option<(usize, Vec<Span>)> = None;
+    for row in &rows {
+        let line_idx = row.line;
+        if line_spans.as_ref().is_none_or(|(line, _)| *line != line_idx) {
+            let line_content = texts[line_idx - first_line].as_str();
+            if let Some(highlighter) = highlighter {
+                let started = profiling.then(Instant::now);
+                highlighter.highlight_line_into(line_content, &mut syntax_highlights);
+                if let Some(started) = started {
+                    editor.profile(Phase::Highlight, started.elapsed());
+                }
+            }
+            let line_search_matches = if search_highlight {
+                search.matches_on_line(line_idx)
+            } else {
+                &[]
+            };
+
+            let mut spans = apply_all_highlights(line_content, line_idx, &syntax_highlights, line_search_matches, selection);
+            let decorations = buffer.line_decorations(line_idx);
+            for (col, mark) in &decorations {
+                if let Decoration::Highlight { len, style } = &mark.decoration {
+                    spans = overlay_style(&spans, *col..col + len, *style);
+                }
+            }
+            if line_content.chars().any(invisible::is_suspicious) {
+                spans = mark_invisible(&spans, line_content);
+            }
+            if editor.config().whitespace_warnings {
+                // Not while typing at the end of the cursor line
+                let typing = editor.mode() == Mode::Insert && line_idx == editor.cursor().line;
+                if let Some(cols) = invisible::trailing_whitespace(line_content).filter(|_| !typing) {
+                    spans = overlay_style(&spans, cols, Style::default().bg(Color::Red));
+                }
+                if let Some(cols) = invisible::mixed_indent(line_content) {
+                    spans = overlay_style(&spans, cols, Style::default().bg(Color::Magenta));
+                }
+            }
+            // Diff mode tints changed lines, leaving search and selection on top
+            if let Some(kind) = editor.diff_view().and_then(|diff| diff.current_change(line_idx)) {
+                let bg = diff_background(kind);
+                for span in &mut spans {
+                    if span.style.bg.is_none() {
+                        span.style = span.style.bg(bg);
+                    }
+                }
+                let width = chunks[2].width as usize;
+                let len = line_content.chars().count();
+                if len < width {
+                    spans.push(Span::styled(" ".repeat(width - len), Style::default().bg(bg)));
+                }
+            }
+            // Extra cursors, with a cell past the end of the line for one there
+            let caret = Style::default().add_modifier(Modifier::REVERSED);
+            for col in editor.extra_cursors().on_line(line_idx) {
+                let len = line_content.chars().count();
+                if col < len {
+                    spans = overlay_style(&spans, col..col + 1, caret);
+                } else {
+                    spans.push(Span::styled(" ", caret));
+                }
+            }
+            // Virtual text after the line, such as blame
+            for (_, mark) in &decorations {
+                if let Decoration::EndOfLine { text, style } = &mark.decoration {
+                    spans.push(Span::styled(text.as_str(), *style));
+                }
+            }
+            line_spans = Some((line_idx, spans));
+        }
+        if let Some((_, spans)) = &line_spans {
+            let mut row_spans = slice_spans(spans, &row.cols);
+            // Inline hints, right to left so earlier columns stay put
+            for (col, text, style) in inline_hints(buffer, row).into_iter().rev() {
+                let mut with_hint = slice_spans(&row_spans, &(0..col));
+                with_hint.push(Span::styled(text, style));
+                with_hint.extend(slice_spans(&row_spans, &(col..usize::MAX)));
+                row_spans = with_hint;
+            }
+            if !row.prefix.is_empty() {
+                row_spans.insert(0, Span::styled(row.prefix.as_str(), Style::default().fg(Color::DarkGray)));
+            }
+            content_lines.push(Line::from(row_spans));
+        }
+    }
+    let content_widget = Paragraph::new(content_lines);
+    frame.render_widget(content_widget, chunks[2]);
+}
+
+/// A row of the editor area showing columns `cols` of buffer line `line`.
+/// The last row of a line runs to `usize::MAX`, so it also holds the
+/// cursor past the end of the line and any virtual text.
+struct ScreenRow {
+    line: usize,
+    cols: Range<usize>,
+    /// Indentation and marker drawn before a wrapped row
+    prefix: String,
+}
+
+/// The buffer rows visible in an editor area `width` columns wide, top to
+/// bottom
+fn screen_rows(editor: &Editor, width: usize, height: usize) -> Vec<ScreenRow> {
+    let buffer = editor.buffer();
+    let total_lines = buffer.line_count();
+    if !editor.config().wrap {
+        return (editor.scroll_offset()..total_lines)
+            .take(height)
+            .map(|line| ScreenRow { line, cols: 0..usize::MAX, prefix: String::new() })
+            .collect();
+    }
+
+    let config = editor.config();
+    let wrapped = |line: usize| {
+        let text = buffer.line(line);
+        let prefix = wrap::continuation_prefix(&text, width, config.breakindent, &config.showbreak);
+        let mut parts = wrap::wrap_line(&text, width, prefix.chars().count());
+        if let Some(last) = parts.last_mut() {
+            last.end = usize::MAX;
+        }
+        (parts, prefix)
     };
+    // The editor scrolls by whole lines; start further down when wrapped
+    // lines above would push the cursor off the bottom
+    let cursor = editor.cursor();
+    let mut top = editor.scroll_offset().min(cursor.line);
+    let cursor_row = wrap::row_of(&wrapped(cursor.line).0, cursor.col);
+    let mut used = (top..cursor.line).map(|line| wrapped(line).0.len()).sum::<usize>() + cursor_row + 1;
+    while used > height && top < cursor.line {
+        used -= wrapped(top).0.len();
+        top += 1;
+    }
+
+    let mut rows = Vec::new();
+    for line in top..total_lines {
+        let (parts, prefix) = wrapped(line);
+        for cols in parts {
+            if rows.len() == height {
+                return rows;
+            }
+            let prefix = if cols.start == 0 { String::new() } else { prefix.clone() };
+            rows.push(ScreenRow { line, cols, prefix });
+        }
+    }
+    rows
+}
 
-    let mut content_lines: Vec<Line> = Vec::new();
-    for i in 0..area.height as usize {
-        let line_idx = scroll_offset + i;
-        if line_idx < total_lines {
-            let line_content = buffer.line(line_idx);
-            let syntax_highlights = highlighter.highlight_line(&line_content);
+/// Width of the scrollbar column, if enabled
+fn scrollbar_width(editor: &Editor) -> u16 {
+    if editor.config().scrollbar { 1 } else { 0 }
+}
 
-            // Get search matches for this line
-            let line_search_matches: Vec<&SearchMatch> = search_matches
-                .iter()
-                .filter(|m| m.line == line_idx)
-                .copied()
-                .collect();
+/// Render the scrollbar: a thumb for the visible lines, with marks for
+/// search matches and quickfix entries in the file
+fn render_scrollbar(frame: &mut Frame, editor: &Editor, area: Rect) {
+    let height = area.height as usize;
+    if height == 0 {
+        return;
+    }
+    let total = editor.buffer().line_count().max(height);
+    let row_of = |line: usize| (line * height / total).min(height - 1);
+    let thumb_len = (height * height / total).max(1);
+    let thumb_start = row_of(editor.scroll_offset()).min(height - thumb_len);
 
-            let spans = apply_all_highlights(&line_content, line_idx, &syntax_highlights, &line_search_matches, selection);
-            content_lines.push(Line::from(spans));
-        } else {
-            content_lines.push(Line::from(""));
+    let mut cells: Vec<Span> = (0..height)
+        .map(|row| {
+            if (thumb_start..thumb_start + thumb_len).contains(&row) {
+                Span::styled("┃", Style::default().fg(Color::Gray))
+            } else {
+                Span::styled("│", Style::default().fg(Color::DarkGray))
+            }
+        })
+        .collect();
+    let search = editor.search();
+    if editor.search_highlight() {
+        for m in search.matches() {
+            cells[row_of(m.line)] = Span::styled("━", Style::default().fg(Color::Yellow));
         }
     }
-    let content_widget = Paragraph::new(content_lines);
-    frame.render_widget(content_widget, chunks[1]);
+    // Errors go on top of search matches
+    for line in editor.quickfix_lines() {
+        cells[row_of(line)] = Span::styled("━", Style::default().fg(Color::Red));
+    }
+    frame.render_widget(Paragraph::new(cells.into_iter().map(Line::from).collect::<Vec<_>>()), area);
+}
+
+/// Inline decorations shown in a screen row, as (column within the row,
+/// text, style) in column order
+fn inline_hints<'a>(buffer: &'a crate::buffer::Buffer, row: &ScreenRow) -> Vec<(usize, &'a str, Style)> {
+    buffer.line_decorations(row.line)
+        .into_iter()
+        .filter(|(col, _)| row.cols.contains(col))
+        .filter_map(|(col, mark)| match &mark.decoration {
+            Decoration::Inline { text, style } => Some((col - row.cols.start, text.as_str(), *style)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Width of the sign column: room for decoration signs, or one cell for
+/// git change signs
+fn sign_column_width(editor: &Editor) -> u16 {
+    let git = if editor.git_hunks().is_some() { 1 } else { 0 };
+    (editor.buffer().sign_width() as u16).max(git)
+}
+
+/// Line spans with invisible and confusable characters made visible in a
+/// warning style
+fn mark_invisible<'a>(spans: &[Span<'a>], line: &str) -> Vec<Span<'a>> {
+    let warning = Style::default().fg(Color::White).bg(Color::Red);
+    let mut marked = Vec::new();
+    let mut done = 0;
+    for (col, c) in line.chars().enumerate().filter(|(_, c)| invisible::is_suspicious(*c)) {
+        marked.extend(slice_spans(spans, &(done..col)));
+        marked.push(Span::styled(invisible::replacement(c).to_string(), warning));
+        done = col + 1;
+    }
+    marked.extend(slice_spans(spans, &(done..usize::MAX)));
+    marked
+}
+
+/// Line spans with `style` patched over character columns `cols`
+fn overlay_style<'a>(spans: &[Span<'a>], cols: Range<usize>, style: Style) -> Vec<Span<'a>> {
+    let mut patched = slice_spans(spans, &(0..cols.start));
+    patched.extend(
+        slice_spans(spans, &cols)
+            .into_iter()
+            .map(|span| {
+                let style = span.style.patch(style);
+                span.style(style)
+            }),
+    );
+    patched.extend(slice_spans(spans, &(cols.end..usize::MAX)));
+    patched
+}
+
+/// The part of a line's spans covering character columns `cols`. Spans
+/// borrowing the line stay borrowed.
+fn slice_spans<'a>(spans: &[Span<'a>], cols: &Range<usize>) -> Vec<Span<'a>> {
+    let mut sliced = Vec::new();
+    let mut pos = 0;
+    for span in spans {
+        let len = span.content.chars().count();
+        let (start, end) = (cols.start.max(pos), cols.end.min(pos + len));
+        if start == pos && end == pos + len && len > 0 {
+            sliced.push(span.clone());
+        } else if start < end {
+            let from = byte_offset(&span.content, 0, start - pos);
+            let to = byte_offset(&span.content, from, end - start);
+            let content = match &span.content {
+                Cow::Borrowed(text) => Cow::Borrowed(&text[from..to]),
+                Cow::Owned(text) => Cow::Owned(text[from..to].to_string()),
+            };
+            sliced.push(Span::styled(content, span.style));
+        }
+        pos += len;
+    }
+    sliced
 }
 
-/// Render the status line
+/// Byte offset `chars` characters after byte offset `from` in `text`
+fn byte_offset(text: &str, from: usize, chars: usize) -> usize {
+    text[from..].char_indices().nth(chars).map_or(text.len(), |(b, _)| from + b)
+}
+
+/// Render the status line in the theme's style for the mode; it turns to
+/// the recording style while a macro is recorded
 fn render_status_line(frame: &mut Frame, editor: &Editor, area: Rect) {
     let buffer = editor.buffer();
     let cursor = editor.cursor();
     let mode = editor.mode();
+    let theme = editor.theme();
+    let style = match editor.recording() {
+        Some(_) => theme.status_line_recording,
+        None => theme.status_line_for(mode),
+    };
 
     // Mode indicator
-    let mode_style = match mode {
-        Mode::Normal => Style::default().bg(Color::Blue).fg(Color::White),
-        Mode::Insert => Style::default().bg(Color::Green).fg(Color::Black),
-        Mode::Command => Style::default().bg(Color::Yellow).fg(Color::Black),
-        Mode::Search => Style::default().bg(Color::Magenta).fg(Color::White),
-        Mode::Visual | Mode::VisualLine | Mode::VisualBlock => Style::default().bg(Color::Cyan).fg(Color::Black),
-        Mode::Help => Style::default().bg(Color::DarkGray).fg(Color::White),
-    };
+    let mode_style = style.add_modifier(Modifier::REVERSED | Modifier::BOLD);
     let mode_span = Span::styled(format!(" {} ", mode.display()), mode_style);
 
+    // Macro being recorded, shown until q stops it
+    let recording_span = editor.recording().map(|register| {
+        Span::styled(format!(" recording @{} ", register), style.add_modifier(Modifier::BOLD))
+    });
+
     // File name
     let file_name = buffer
         .file_name()
         .unwrap_or("[No Name]")
         .to_string();
-    let modified = if buffer.is_modified() { " [+]" } else { "" };
-    let file_span = Span::styled(
-        format!(" {}{} ", file_name, modified),
-        Style::default().fg(Color::White),
-    );
+    // After edits, say when the text is back to what was last written
+    let modified = if buffer.is_modified() {
+        " [+]"
+    } else if buffer.can_undo() || buffer.can_redo() {
+        " [saved]"
+    } else {
+        ""
+    };
+    let file_span = Span::raw(format!(" {}{} ", file_name, modified));
+
+    // Buffers that :w refuses to write
+    let readonly_span = buffer
+        .is_readonly()
+        .then(|| Span::styled(" [RO] ", theme.status_line_readonly.add_modifier(Modifier::BOLD)));
 
     // Position and percentage
     let line_count = buffer.line_count();
@@ -154,141 +727,367 @@ fn render_status_line(frame: &mut Frame, editor: &Editor, area: Rect) {
         let pct = ((cursor.line + 1) * 100) / line_count;
         format!("{}%", pct)
     };
-    let pos_span = Span::styled(
-        format!(" {}:{} {} Ln {} ", cursor.line + 1, cursor.col + 1, percent, line_count),
-        Style::default().fg(Color::DarkGray),
-    );
+    let pos_span = Span::raw(format!(" {}:{} {} Ln {} ", cursor.line + 1, cursor.col + 1, percent, line_count));
 
     // Syntax indicator
     let syntax_name = editor.highlighter().current_syntax_name().unwrap_or("Plain");
-    let syntax_span = Span::styled(
-        format!(" {} ", syntax_name),
-        Style::default().fg(Color::Cyan),
-    );
+    let syntax_span = Span::raw(format!(" {} ", syntax_name));
+
+    // Warn about invisible characters anywhere in the buffer
+    let invisible_span = match editor.invisible_count() {
+        0 => None,
+        n => Some(Span::styled(format!(" ⚠ {} invisible ", n), Style::default().add_modifier(Modifier::BOLD))),
+    };
+
+    // Background job indicator
+    let job_span = editor.job_status().map(|status| Span::raw(format!(" ⟳ {} ", status)));
+
+    // Undo depth and the unnamed register
+    let history_span = editor.config().status_history.then(|| {
+        Span::raw(format!(" {} ", editor.history_summary()))
+    });
+
+    // CPU, memory and GPU usage
+    let metrics_span = Some(editor.gpu_info().metrics().summary())
+        .filter(|summary| editor.config().sysinfo && !summary.is_empty())
+        .map(|summary| Span::raw(format!(" {} ", summary)));
+
+    // Segments from init.lua's quirks.statusline function
+    let lua_spans: Vec<Span> = match editor.lua_statusline() {
+        None => Vec::new(),
+        Some(Ok(segments)) => segments
+            .into_iter()
+            .filter(|segment| !segment.text.is_empty())
+            .map(|segment| Span::styled(format!(" {} ", segment.text), segment.style))
+            .collect(),
+        Some(Err(e)) => vec![Span::styled(
+            format!(" statusline: {} ", e),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        )],
+    };
 
     // Build status line
-    let left = vec![mode_span, file_span];
-    let mut right = vec![syntax_span, pos_span];
+    let mut left = vec![mode_span];
+    left.extend(recording_span);
+    left.push(file_span);
+    left.extend(readonly_span);
+    left.extend(invisible_span);
+    left.extend(job_span);
+    let mut right: Vec<Span> = lua_spans.into_iter().chain(history_span).chain(metrics_span).collect();
+    right.extend([syntax_span, pos_span]);
 
     let status = Line::from(left);
-    let status_widget = Paragraph::new(status)
-        .style(Style::default().bg(Color::DarkGray));
+    let status_widget = Paragraph::new(status).style(style);
     frame.render_widget(status_widget, area);
 
     // Right-aligned position (render separately)
     let right_status = Line::from(right);
     let right_widget = Paragraph::new(right_status)
-        .style(Style::default().bg(Color::DarkGray))
+        .style(style)
         .alignment(ratatui::layout::Alignment::Right);
     frame.render_widget(right_widget, area);
 }
 
 /// Render the command line (for : commands and messages)
 fn render_command_line(frame: &mut Frame, editor: &Editor, area: Rect) {
-    let content = if editor.mode() == Mode::Command {
-        format!(":{}", editor.command_buffer())
-    } else if editor.mode() == Mode::Search {
-        let prefix = match editor.search().direction() {
-            SearchDirection::Forward => "/",
-            SearchDirection::Backward => "?",
-        };
-        format!("{}{}", prefix, editor.search().pattern())
-    } else if let Some(msg) = editor.message() {
-        msg.to_string()
+    // The end of a long message, the whole of it is in :messages
+    if let Some(message) = editor.long_message() {
+        let lines: Vec<&str> = message.lines().collect();
+        let shown = lines.len().min(area.height.saturating_sub(1) as usize);
+        let mut text: Vec<Line> = lines[lines.len() - shown..].iter().map(|line| Line::from(*line)).collect();
+        text.push(Line::styled(MORE_PROMPT, Style::default().fg(Color::Green)));
+        frame.render_widget(Paragraph::new(text), area);
+        return;
+    }
+
+    let widget = Paragraph::new(editor.command_line());
+    frame.render_widget(widget, area);
+
+    // The command typed so far, waiting for the rest
+    if let Some(keys) = editor.pending_command() {
+        let text = format!(" {} ", keys);
+        let width = (text.chars().count() as u16).min(area.width);
+        let corner = Rect::new(area.x + area.width - width, area.y, width, area.height);
+        let widget = Paragraph::new(text).style(Style::default().fg(Color::Gray));
+        frame.render_widget(Clear, corner);
+        frame.render_widget(widget, corner);
+    }
+}
+
+/// Render the fuzzy finder overlay, returning the prompt cursor position
+fn render_picker(frame: &mut Frame, picker: &Picker, area: Rect) -> (u16, u16) {
+    let width = (area.width * 4 / 5).max(20).min(area.width);
+    let height = (area.height * 4 / 5).max(5).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", picker.kind().title()))
+        .style(Style::default().fg(Color::White).bg(Color::Black));
+    let inner = block.inner(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+    if inner.height < 2 {
+        return (inner.x, inner.y);
+    }
+
+    // Prompt line with match count
+    let prompt_area = Rect::new(inner.x, inner.y, inner.width, 1);
+    let prompt = format!("> {}", picker.query());
+    frame.render_widget(Paragraph::new(prompt.clone()), prompt_area);
+    let count = format!("{}/{} ", picker.matches().len(), picker.items().len());
+    frame.render_widget(
+        Paragraph::new(count)
+            .style(Style::default().fg(Color::DarkGray))
+            .alignment(ratatui::layout::Alignment::Right),
+        prompt_area,
+    );
+
+    let body = Rect::new(inner.x, inner.y + 1, inner.width, inner.height - 1);
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(body);
+
+    // Candidate list, scrolled to keep the selection visible
+    let list_height = columns[0].height as usize;
+    let selected = picker.selected();
+    let top = (selected + 1).saturating_sub(list_height);
+    let lines: Vec<Line> = picker.matches().iter().enumerate()
+        .skip(top)
+        .take(list_height)
+        .map(|(idx, m)| {
+            let label = &picker.items()[m.index].label;
+            let base = if idx == selected {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            let spans: Vec<Span> = label.chars().enumerate()
+                .map(|(i, c)| {
+                    let style = if m.positions.contains(&i) {
+                        base.fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                    } else {
+                        base
+                    };
+                    Span::styled(c.to_string(), style)
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), columns[0]);
+
+    // Preview of the highlighted candidate
+    let preview_block = Block::default()
+        .borders(Borders::LEFT)
+        .border_style(Style::default().fg(Color::DarkGray));
+    let preview_area = preview_block.inner(columns[1]);
+    frame.render_widget(preview_block, columns[1]);
+    let preview_height = preview_area.height as usize;
+    let preview_top = picker.preview_line
+        .map(|line| line.saturating_sub(preview_height / 2))
+        .unwrap_or(0);
+    let preview: Vec<Line> = picker.preview.iter().enumerate()
+        .skip(preview_top)
+        .take(preview_height)
+        .map(|(idx, text)| {
+            let style = if Some(idx) == picker.preview_line {
+                Style::default().bg(Color::DarkGray)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            Line::from(Span::styled(text.replace('\t', "    "), style))
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(preview), preview_area);
+
+    let cursor_x = prompt_area.x + (prompt.chars().count() as u16).min(prompt_area.width.saturating_sub(1));
+    (cursor_x, prompt_area.y)
+}
+
+/// Render the key hint popup in the bottom-right corner of the editor area
+fn render_key_hints(frame: &mut Frame, typed: &str, hints: &[(KeyCombo, String)], area: Rect) {
+    let keys: Vec<String> = hints.iter().map(|(key, _)| key.to_string()).collect();
+    let key_width = keys.iter().map(|k| k.chars().count()).max().unwrap_or(0);
+
+    let lines: Vec<Line> = keys.iter().zip(hints)
+        .map(|(key, (_, desc))| {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {:<width$} ", key, width = key_width),
+                    Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(format!("{} ", desc)),
+            ])
+        })
+        .collect();
+
+    let content_width = lines.iter().map(|l| l.width()).max().unwrap_or(0);
+    let width = (content_width.max(typed.chars().count() + 4) as u16 + 2).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + area.width - width,
+        area.y + area.height - height,
+        width,
+        height,
+    );
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", typed))
+        .style(Style::default().fg(Color::White).bg(Color::Black));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// Most completion candidates shown at once
+const COMPLETION_ROWS: usize = 10;
+
+/// Completion candidates under the completed text, or above it when
+/// there is no room below; the list scrolls to keep the selection shown
+fn render_completion(frame: &mut Frame, completion: &Completion, cursor: (u16, u16), area: Rect) {
+    let labels: Vec<&str> = completion.candidates.iter().map(|c| completion.label(c)).collect();
+    let rows = labels.len().min(COMPLETION_ROWS);
+    let first = completion.selected.map_or(0, |idx| idx.saturating_sub(rows - 1));
+    let width = (labels.iter().map(|l| l.chars().count()).max().unwrap_or(0) as u16 + 2).min(area.width);
+    let height = (rows as u16).min(area.height);
+
+    let (cursor_x, cursor_y) = cursor;
+    let label_width = completion.label(completion.text()).chars().count() as u16;
+    let x = cursor_x.saturating_sub(label_width).max(area.x).min(area.x + area.width - width);
+    let y = if cursor_y + 1 + height <= area.y + area.height {
+        cursor_y + 1
     } else {
-        String::new()
+        cursor_y.saturating_sub(height).max(area.y)
     };
+    let popup = Rect::new(x, y, width, height);
 
-    let widget = Paragraph::new(content);
-    frame.render_widget(widget, area);
+    let normal = Style::default().fg(Color::White).bg(Color::DarkGray);
+    let selected = Style::default().fg(Color::Black).bg(Color::LightBlue);
+    let lines: Vec<Line> = labels
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(rows)
+        .map(|(idx, label)| {
+            let style = if completion.selected == Some(idx) { selected } else { normal };
+            Line::from(Span::styled(format!(" {:<w$} ", label, w = (width as usize).saturating_sub(2)), style))
+        })
+        .collect();
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines), popup);
+}
+
+/// Frame time overlay in the top right corner of `area`
+fn render_profile(frame: &mut Frame, stats: &FrameStats, area: Rect) {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let (phase, slowest) = stats.slowest;
+    let text = format!(
+        " frame avg {:.1}ms p95 {:.1}ms · {} {:.1}ms ({} frames) ",
+        ms(stats.average),
+        ms(stats.p95),
+        phase.name(),
+        ms(slowest),
+        stats.frames,
+    );
+    let width = (text.chars().count() as u16).min(area.width);
+    let overlay = Rect::new(area.x + area.width - width, area.y, width, 1.min(area.height));
+    let style = Style::default().fg(Color::Black).bg(Color::LightYellow);
+    frame.render_widget(Clear, overlay);
+    frame.render_widget(Paragraph::new(text).style(style), overlay);
 }
 
-/// Render help overlay
-fn render_help_overlay(frame: &mut Frame) {
-    let help_text = vec![
-        "",
-        "  ╔══════════════════════════════════════════════════════════════╗",
-        "  ║                      QUIRKS HELP                             ║",
-        "  ╠══════════════════════════════════════════════════════════════╣",
-        "  ║  NAVIGATION                                                  ║",
-        "  ║    h/j/k/l     Move left/down/up/right                       ║",
-        "  ║    w/b/e       Word forward/backward/end                     ║",
-        "  ║    0/$         Line start/end                                ║",
-        "  ║    gg/G        Buffer start/end                              ║",
-        "  ║    gt/gT       Next/previous buffer                          ║",
-        "  ║                                                              ║",
-        "  ║  EDITING                                                     ║",
-        "  ║    i/a         Insert before/after cursor                    ║",
-        "  ║    I/A         Insert at line start/end                      ║",
-        "  ║    o/O         New line below/above                          ║",
-        "  ║    x           Delete character                              ║",
-        "  ║    dd          Delete line                                   ║",
-        "  ║    yy          Yank (copy) line                              ║",
-        "  ║    p/P         Paste after/before                            ║",
-        "  ║    u/Ctrl+R    Undo/Redo                                     ║",
-        "  ║                                                              ║",
-        "  ║  SEARCH                                                      ║",
-        "  ║    /pattern    Search forward                                ║",
-        "  ║    ?pattern    Search backward                               ║",
-        "  ║    n/N         Next/previous match                           ║",
-        "  ║                                                              ║",
-        "  ║  COMMANDS                                                    ║",
-        "  ║    :w          Save file     :e <file>   Open file           ║",
-        "  ║    :q          Quit          :ls         List buffers        ║",
-        "  ║    :wq         Save & quit   :b <N>      Switch buffer       ║",
-        "  ║    :q!         Force quit    :bd         Close buffer        ║",
-        "  ║    :set        Show config   :help       This help           ║",
-        "  ║                                                              ║",
-        "  ║  OTHER                                                       ║",
-        "  ║    Ctrl+G      File info     Ctrl+Q      Force quit          ║",
-        "  ║    >>          Indent        <<          Outdent             ║",
-        "  ║                                                              ║",
-        "  ║           Press q, Esc, or Enter to close                    ║",
-        "  ╚══════════════════════════════════════════════════════════════╝",
-        "",
-    ];
-
-    let area = frame.area();
-    let help_height = help_text.len() as u16;
-    let help_width = 68;
-    
-    let x = area.width.saturating_sub(help_width) / 2;
-    let y = area.height.saturating_sub(help_height) / 2;
-    
-    let help_area = Rect::new(x, y, help_width, help_height);
-    
-    // Clear background
-    let block = ratatui::widgets::Block::default()
-        .style(Style::default().bg(Color::Black));
-    frame.render_widget(block, help_area);
-    
-    // Render help text
-    let text: Vec<Line> = help_text.iter().map(|s| Line::from(*s)).collect();
-    let paragraph = Paragraph::new(text)
+/// Popup in the middle of `area` with some lines of text, for
+/// `Metrics::details` and the quit prompt
+fn render_popup(frame: &mut Frame, title: &str, lines: &[String], area: Rect) {
+    let width = (lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as u16 + 4).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let lines: Vec<Line> = lines.iter().map(|line| Line::from(format!(" {} ", line))).collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
         .style(Style::default().fg(Color::White).bg(Color::Black));
-    frame.render_widget(paragraph, help_area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// A popup from quirks.popup, centered in `area` or under the cursor (above
+/// it when there is no room below)
+fn render_lua_popup(frame: &mut Frame, popup: &lua::Popup, cursor: (u16, u16), area: Rect) {
+    let frame_size = if popup.border { 2 } else { 0 };
+    let text_width = popup
+        .width
+        .unwrap_or_else(|| popup.lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as u16);
+    let width = (text_width + 2 + frame_size).min(area.width);
+    let height = (popup.lines.len() as u16 + frame_size).min(area.height);
+    let (x, y) = match popup.anchor {
+        lua::Anchor::Center => (area.x + (area.width - width) / 2, area.y + (area.height - height) / 2),
+        lua::Anchor::Cursor => {
+            let (cursor_x, cursor_y) = cursor;
+            let y = if cursor_y + 1 + height <= area.y + area.height {
+                cursor_y + 1
+            } else {
+                cursor_y.saturating_sub(height).max(area.y)
+            };
+            (cursor_x.max(area.x).min(area.x + area.width - width), y)
+        }
+    };
+    let rect = Rect::new(x, y, width, height);
+    let style = Style::default().fg(Color::White).bg(Color::Black).patch(popup.style);
+    let lines: Vec<Line> = popup.lines.iter().map(|line| Line::from(format!(" {} ", line))).collect();
+    let mut paragraph = Paragraph::new(lines).style(style);
+    if popup.border {
+        let mut block = Block::default().borders(Borders::ALL);
+        if let Some(title) = &popup.title {
+            block = block.title(format!(" {} ", title));
+        }
+        paragraph = paragraph.block(block);
+    }
+    frame.render_widget(Clear, rect);
+    frame.render_widget(paragraph, rect);
 }
 
-/// Apply all highlighting (syntax, search, selection) to a line of text
-fn apply_all_highlights(
-    line: &str,
+/// Apply all highlighting (syntax, search, selection) to a line of text.
+/// Columns are in characters; the spans borrow from `line`.
+fn apply_all_highlights<'a>(
+    line: &'a str,
     line_idx: usize,
     syntax_highlights: &[HighlightSpan],
-    search_matches: &[&SearchMatch],
+    search_matches: &[SearchMatch],
     selection: Option<&Selection>,
-) -> Vec<Span<'static>> {
-    let chars: Vec<char> = line.chars().collect();
-
+) -> Vec<Span<'a>> {
     if syntax_highlights.is_empty() && search_matches.is_empty() && selection.is_none() {
-        return vec![Span::raw(line.to_string())];
+        return vec![Span::raw(line)];
     }
 
+    let len = line.chars().count();
     let mut spans = Vec::new();
     let mut i = 0;
+    // Byte offset of character `i`
+    let mut byte = 0;
+    // Text from character `i` up to character `end`, moving `i` there
+    let mut take = |i: &mut usize, end: usize| {
+        let start = byte;
+        byte = byte_offset(line, byte, end - *i);
+        *i = end;
+        &line[start..byte]
+    };
 
-    while i < chars.len() {
+    while i < len {
         // Check if we're in a selection
-        let in_selection = selection.map_or(false, |s| s.contains(line_idx, i));
+        let in_selection = selection.is_some_and(|s| s.contains(line_idx, i));
 
         // Check if we're in a search match
         let search_match = search_matches.iter().find(|m| i >= m.start_col && i < m.end_col);
@@ -299,38 +1098,34 @@ fn apply_all_highlights(
         if in_selection {
             // Selection - find extent
             let mut end = i + 1;
-            while end < chars.len() && selection.map_or(false, |s| s.contains(line_idx, end)) {
+            while end < len && selection.is_some_and(|s| s.contains(line_idx, end)) {
                 end += 1;
             }
-            let text: String = chars[i..end].iter().collect();
 
             // Apply syntax color on selection background
             let fg = syntax_hl.map(|h| h.style.fg.unwrap_or(Color::White)).unwrap_or(Color::White);
             spans.push(Span::styled(
-                text,
+                take(&mut i, end),
                 Style::default()
                     .bg(Color::DarkGray)
                     .fg(fg),
             ));
-            i = end;
         } else if let Some(sm) = search_match {
             // Search match takes priority - render with highlight background
-            let end = sm.end_col.min(chars.len());
-            let text: String = chars[i..end].iter().collect();
+            let end = sm.end_col.min(len);
             spans.push(Span::styled(
-                text,
+                take(&mut i, end),
                 Style::default()
                     .bg(Color::Yellow)
                     .fg(Color::Black)
                     .add_modifier(Modifier::BOLD),
             ));
-            i = end;
         } else if let Some(sh) = syntax_hl {
             // Regular syntax highlight
-            let end = sh.end.min(chars.len());
+            let end = sh.end.min(len);
             // Check if selection or search starts before syntax ends
             let selection_start = if selection.is_some() {
-                (i+1..end).find(|&c| selection.map_or(false, |s| s.contains(line_idx, c)))
+                (i+1..end).find(|&c| selection.is_some_and(|s| s.contains(line_idx, c)))
             } else {
                 None
             };
@@ -345,9 +1140,7 @@ fn apply_all_highlights(
                 .min()
                 .unwrap_or(end);
 
-            let text: String = chars[i..actual_end].iter().collect();
-            spans.push(Span::styled(text, sh.style));
-            i = actual_end;
+            spans.push(Span::styled(take(&mut i, actual_end), sh.style));
         } else {
             // No highlight - find where the next highlight starts
             let next_syntax = syntax_highlights
@@ -361,7 +1154,7 @@ fn apply_all_highlights(
                 .map(|m| m.start_col)
                 .min();
             let next_selection = if selection.is_some() {
-                (i+1..chars.len()).find(|&c| selection.map_or(false, |s| s.contains(line_idx, c)))
+                (i+1..len).find(|&c| selection.is_some_and(|s| s.contains(line_idx, c)))
             } else {
                 None
             };
@@ -370,20 +1163,18 @@ fn apply_all_highlights(
                 .flatten()
                 .min();
 
-            let end = next.unwrap_or(chars.len()).min(chars.len());
-            let text: String = chars[i..end].iter().collect();
-            spans.push(Span::raw(text));
-            i = end;
+            let end = next.unwrap_or(len).min(len);
+            spans.push(Span::raw(take(&mut i, end)));
         }
     }
 
     // Handle empty line with selection
-    if chars.is_empty() && selection.map_or(false, |s| s.contains(line_idx, 0)) {
+    if len == 0 && selection.is_some_and(|s| s.contains(line_idx, 0)) {
         spans.push(Span::styled(" ", Style::default().bg(Color::DarkGray)));
     }
 
     if spans.is_empty() {
-        spans.push(Span::raw(String::new()));
+        spans.push(Span::raw(""));
     }
 
     spans
@@ -404,8 +1195,27 @@ fn calculate_cursor_position(editor: &Editor, editor_area: Rect) -> (u16, u16) {
         0
     };
 
-    let screen_line = cursor.line.saturating_sub(scroll_offset) as u16;
-    let screen_col = cursor.col as u16 + line_num_width;
+    let sign_width = sign_column_width(editor);
+
+    // Find the cursor among the (possibly wrapped) rows on screen
+    let width = editor_area.width.saturating_sub(sign_width + line_num_width + scrollbar_width(editor)) as usize;
+    let rows = screen_rows(editor, width, editor_area.height as usize);
+    let (screen_line, col) = rows.iter()
+        .position(|row| row.line == cursor.line && row.cols.contains(&cursor.col))
+        .map(|i| {
+            let row = &rows[i];
+            let col = cursor.col - row.cols.start;
+            let hints: usize = inline_hints(buffer, row)
+                .iter()
+                .filter(|(hint_col, _, _)| *hint_col <= col)
+                .map(|(_, text, _)| text.chars().count())
+                .sum();
+            (i, row.prefix.chars().count() + col + hints)
+        })
+        .unwrap_or((cursor.line.saturating_sub(scroll_offset), cursor.col));
+
+    let screen_line = screen_line as u16;
+    let screen_col = col as u16 + sign_width + line_num_width;
 
     (
         editor_area.x + screen_col.min(editor_area.width - 1),
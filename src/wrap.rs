@@ -0,0 +1,86 @@
+//! Soft wrapping for Quirks
+//!
+//! Splits long lines into screen rows at word boundaries, for the `wrap`
+//! option. Rows after the first can start with a prefix: the line's
+//! indentation (`breakindent`) and a marker (`showbreak`). The buffer is
+//! never changed.
+
+use std::ops::Range;
+
+/// Character ranges of the screen rows a line wraps into. Rows break after
+/// whitespace where possible; a word longer than the row is split. Rows
+/// after the first leave room for a prefix `prefix_width` wide.
+pub fn wrap_line(line: &str, width: usize, prefix_width: usize) -> Vec<Range<usize>> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut rows = Vec::new();
+    let mut start = 0;
+    loop {
+        let width = if start == 0 { width } else { width.saturating_sub(prefix_width) }.max(1);
+        if chars.len() - start <= width {
+            break;
+        }
+        let end = (start + 1..=start + width)
+            .rev()
+            .find(|&i| chars[i - 1].is_whitespace())
+            .unwrap_or(start + width);
+        rows.push(start..end);
+        start = end;
+    }
+    rows.push(start..chars.len());
+    rows
+}
+
+/// Text shown before the wrapped rows of `line`: its indentation with
+/// `breakindent`, then `showbreak`. The indentation is dropped if the
+/// prefix would take more than half the width.
+pub fn continuation_prefix(line: &str, width: usize, breakindent: bool, showbreak: &str) -> String {
+    let indent = if breakindent {
+        &line[..line.len() - line.trim_start().len()]
+    } else {
+        ""
+    };
+    let prefix = format!("{}{}", indent, showbreak);
+    if prefix.chars().count() > width / 2 {
+        showbreak.to_string()
+    } else {
+        prefix
+    }
+}
+
+/// Row of `rows` that shows column `col`; the cursor past the end of the
+/// line belongs to the last row
+pub fn row_of(rows: &[Range<usize>], col: usize) -> usize {
+    rows.iter().position(|row| col < row.end).unwrap_or(rows.len().saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_line() {
+        assert_eq!(wrap_line("", 10, 0), vec![0..0]);
+        assert_eq!(wrap_line("short", 10, 0), vec![0..5]);
+        assert_eq!(wrap_line("the quick brown fox", 10, 0), vec![0..10, 10..19]);
+        assert_eq!(wrap_line("abcdefghij", 4, 0), vec![0..4, 4..8, 8..10]);
+        assert_eq!(wrap_line("ab cdefghij", 4, 0), vec![0..3, 3..7, 7..11]);
+        assert_eq!(wrap_line("abcdefghij", 4, 2), vec![0..4, 4..6, 6..8, 8..10]);
+    }
+
+    #[test]
+    fn test_continuation_prefix() {
+        assert_eq!(continuation_prefix("    - item", 40, true, ""), "    ");
+        assert_eq!(continuation_prefix("    - item", 40, false, "↪ "), "↪ ");
+        assert_eq!(continuation_prefix("  x", 40, true, "↪ "), "  ↪ ");
+        assert_eq!(continuation_prefix("            x", 10, true, "> "), "> ");
+    }
+
+    #[test]
+    fn test_row_of() {
+        let rows = wrap_line("the quick brown fox", 10, 0);
+        assert_eq!(row_of(&rows, 0), 0);
+        assert_eq!(row_of(&rows, 9), 0);
+        assert_eq!(row_of(&rows, 10), 1);
+        assert_eq!(row_of(&rows, 19), 1);
+    }
+}
@@ -0,0 +1,36 @@
+//! Driving the editor without a terminal
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use quirks::{Config, Editor, Mode, Snapshot};
+
+fn type_keys(editor: &mut Editor, keys: &str) {
+    for c in keys.chars() {
+        let code = match c {
+            '\n' => KeyCode::Enter,
+            '\x1b' => KeyCode::Esc,
+            c => KeyCode::Char(c),
+        };
+        editor.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+}
+
+#[test]
+fn test_edit_without_terminal() {
+    let mut editor = Editor::headless(Config::default());
+    type_keys(&mut editor, "ifirst\nsecond\x1b");
+
+    let snapshot = Snapshot::of(&editor, 10);
+    assert_eq!(snapshot.lines, vec!["first", "second"]);
+    assert_eq!(snapshot.cursor, (1, 5));
+    assert_eq!(snapshot.mode, Mode::Normal);
+    assert!(snapshot.modified);
+    assert_eq!(snapshot.file_name, None);
+
+    type_keys(&mut editor, ":");
+    assert_eq!(Snapshot::of(&editor, 10).command_line, ":");
+    type_keys(&mut editor, "\x1b");
+
+    assert!(!editor.execute_command("set nonumber"));
+    assert!(!editor.config().line_numbers);
+    assert!(editor.execute_command("q!"));
+}
@@ -0,0 +1,1755 @@
+//! Modal editing regressions, as key-sequence scripts
+
+use quirks::prompt::Prompt;
+use quirks::{script, Config, Editor};
+
+fn check(script: &str) {
+    let mut editor = Editor::headless(Config::default());
+    if let Err(err) = script::run(&mut editor, script) {
+        panic!("{}\n{}", err, script);
+    }
+}
+
+#[test]
+fn test_delete_lines() {
+    check("
+        text one\\ntwo\\nthree
+        keys dd
+        expect text two\\nthree
+        expect cursor 1:1
+        keys Gdd
+        expect text two
+        keys u
+        expect text two\\nthree
+    ");
+    check("
+        text a\\nb\\nc\\nd
+        keys j2dd
+        expect text a\\nd
+        keys p
+        expect text a\\nd\\nb\\nc
+    ");
+}
+
+#[test]
+fn test_change_line() {
+    check("
+        text   indented\\nnext
+        keys ccnew<Esc>
+        expect line 1 new
+        expect line 2 next
+        expect mode NORMAL
+    ");
+}
+
+#[test]
+fn test_visual_delete() {
+    check("
+        text hello world
+        keys wvlld
+        expect text hello ld
+        expect mode NORMAL
+    ");
+    check("
+        text one\\ntwo\\nthree
+        keys Vjd
+        expect text three
+        expect cursor 1:1
+    ");
+}
+
+#[test]
+fn test_insert() {
+    check("
+        text world
+        keys ihello <Esc>
+        expect text hello world
+        expect cursor 1:6
+        keys Aagain<Esc>
+        expect text hello worldagain
+        expect mode NORMAL
+    ");
+}
+
+#[test]
+fn test_multiple_cursors() {
+    check("
+        text let foo = 1;\\nfoo(foo);\\nbar
+        keys w<C-n><C-n><C-n>
+        expect cursor 2:5
+        expect message No more matches for foo
+        keys ibaz_<Esc>
+        expect text let baz_foo = 1;\\nbaz_foo(baz_foo);\\nbar
+        keys <Esc>x
+        expect text let baz_foo = 1;\\nbaz_foo(bazfoo);\\nbar
+    ");
+    check("
+        text a\\nb\\na
+        keys <C-n>Ax<CR>y<Esc>
+        expect text ax\\ny\\nb\\nax\\ny
+        keys <Esc>x
+        expect text ax\\ny\\nb\\nax\\n
+    ");
+}
+
+#[test]
+fn test_visual_change() {
+    check("
+        text abcd\\nefgh\\nij\\nklmn
+        keys l<C-v>jjjlc
+        keys XY<Esc>
+        expect text aXYd\\neXYh\\niXY\\nkXYn
+        expect mode NORMAL
+    ");
+    check("
+        text one\\ntwo\\nthree
+        keys Vjcnew<Esc>
+        expect text new\\nthree
+    ");
+    check("
+        text one two three
+        keys wvec2<Esc>
+        expect text one 2 three
+    ");
+}
+
+#[test]
+fn test_visual_shift() {
+    check("
+        text a\\n\\nb\\nc
+        keys Vjj>
+        expect text     a\\n\\n    b\\nc
+        expect mode V-LINE
+        expect message 3 lines >ed 1 time
+        keys 2>
+        expect text             a\\n\\n            b\\nc
+        keys <lt><Esc>u
+        expect text             a\\n\\n            b\\nc
+    ");
+}
+
+#[test]
+fn test_visual_paste() {
+    check("
+        text one two\\nthree
+        keys vey
+        keys wvep
+        expect text one one\\nthree
+        keys 0vep
+        expect text two one\\nthree
+        expect cursor 1:3
+    ");
+    check("
+        text a\\nb\\nc
+        keys yyjVp
+        expect text a\\na\\nc
+        keys G0vp
+        expect text a\\na\\n\\nb\\n
+    ");
+}
+
+#[test]
+fn test_visual_case_and_join() {
+    check("
+        text Hello World\\nfoo
+        keys veU
+        expect text HELLO World\\nfoo
+        keys wv$~
+        expect text HELLO wORLD\\nfoo
+        keys Vju
+        expect text hello world\\nfoo
+        keys u
+        expect text HELLO wORLD\\nfoo
+    ");
+    check("
+        text a\\n  b\\n  c\\nd
+        keys VjjJ
+        expect text a b c\\nd
+        expect cursor 1:4
+        keys u
+        expect text a\\n  b\\n  c\\nd
+    ");
+    check("
+        text a\\n  b\\n  c\\nd\\ne
+        keys 3gJ
+        expect text a  b  c\\nd\\ne
+        expect cursor 1:5
+        keys jVjgJ
+        expect text a  b  c\\nde
+        keys ugg3J
+        expect text a  b  c d e
+        expect cursor 1:10
+    ");
+}
+
+#[test]
+fn test_counts() {
+    check("
+        text abcdef\\nl2\\nl3\\nl4
+        keys 3x
+        expect text def\\nl2\\nl3\\nl4
+        keys j2yy
+        expect message 2 lines yanked
+        keys 3p
+        expect text def\\nl2\\nl2\\nl3\\nl2\\nl3\\nl2\\nl3\\nl3\\nl4
+        expect message 6 more lines
+    ");
+    check("
+        text ab\\ncd\\nef\\ngh
+        keys 2>>
+        expect text     ab\\n    cd\\nef\\ngh
+        keys 2<lt><lt>
+        expect text ab\\ncd\\nef\\ngh
+        keys 3~
+        expect text AB\\ncd\\nef\\ngh
+        keys j2ccx<Esc>
+        expect text AB\\nx\\ngh
+        keys p
+        expect text AB\\nx\\ncd\\nef\\ngh
+    ");
+}
+
+#[test]
+fn test_special_registers() {
+    check("
+        text abc\\ntwo
+        keys xjyy
+        keys \"-p
+        expect text bc\\ntawo
+        keys 0\"_D
+        keys P
+        expect text bc\\ntwo\\n
+        keys gg\"_dd
+        expect text two\\n
+        keys v\"_x
+        keys p
+        expect text wo\\ntwo\\n
+    ");
+}
+
+#[test]
+fn test_macros() {
+    let mut editor = Editor::headless(Config::default());
+    script::run(&mut editor, "
+        text one\\ntwo\\nthree
+        keys qa
+    ").unwrap();
+    assert_eq!(editor.recording(), Some('a'));
+    script::run(&mut editor, "
+        keys A!<Esc>jq
+        expect message Recorded @a (4 keys)
+        keys @a
+        expect text one!\\ntwo!\\nthree
+        keys @@
+        expect text one!\\ntwo!\\nthree!
+        :macros
+        expect message @a  A!<Esc>j
+        keys ggqbxq2@b
+        expect line 1 !
+        keys @c
+        expect message Register c is empty
+        :macros
+        expect text @a  A!<Esc>j\\n@b  x\\n
+    ").unwrap();
+    assert_eq!(editor.recording(), None);
+}
+
+#[test]
+fn test_macro_stops_at_error() {
+    check("
+        text x1\\nx2\\nx3\\nx4
+        keys qa/x<CR>rYq
+        expect line 1 Y1
+        keys 100@a
+        expect text Y1\\nY2\\nY3\\nY4
+        expect message Pattern not found
+        keys ggqbA!<Esc>jq3@b
+        expect text Y1!\\nY2!\\nY3!\\nY4!
+    ");
+}
+
+#[test]
+fn test_setlocal_syntax() {
+    let mut editor = Editor::headless(Config::default());
+    assert!(editor.syntax_enabled());
+    editor.execute_command("set nosyntax");
+    assert!(!editor.syntax_enabled());
+    editor.execute_command("setlocal syntax");
+    assert!(editor.syntax_enabled());
+    script::run(&mut editor, "
+        :setlocal
+        expect message syntax=true
+        :setlocal syntax&
+        expect message Syntax highlighting follows the global option (false)
+        :setlocal nosyntax
+        :set syntax
+        :setlocal
+        expect message syntax=false
+        :setlocal wrap
+        expect message Unknown local option: wrap
+    ").unwrap();
+    assert!(!editor.syntax_enabled());
+    editor.execute_command("new");
+    assert!(editor.syntax_enabled());
+}
+
+#[test]
+fn test_long_messages() {
+    let mut editor = Editor::headless(Config::default());
+    script::run(&mut editor, "
+        text abc
+        keys :s/(/x/<CR>
+    ").unwrap();
+    let message = editor.long_message().expect("regex errors span several lines").to_string();
+    assert!(message.starts_with("Invalid pattern: regex parse error:\n"));
+    // Space dismisses the message instead of moving
+    script::run(&mut editor, "
+        keys <Space>
+        expect cursor 1:1
+        expect message 
+        keys :s/b/B/<CR>
+        expect line 1 aBc
+        keys :messages<CR>
+    ").unwrap();
+    assert!(editor.long_message().is_none());
+    let log = editor.buffer().content();
+    assert!(log.contains(&message));
+    assert!(log.contains("INFO :s/b/B/\n1 substitution on 1 line"));
+}
+
+#[test]
+fn test_clipboard_registers() {
+    // Headless editors have no clipboard tool, so + and * stay in the editor
+    check("
+        text one\\ntwo
+        keys \"+yyj\"+p
+        expect text one\\ntwo\\none\\n
+        keys gg\"*x\"*P
+        expect line 1 one
+        :set clipboard
+        keys jyygg\"+p
+        expect text one\\ntwo\\ntwo\\none\\n
+        keys Gkddgg\"+P
+        expect text one\\none\\ntwo\\ntwo\\n
+    ");
+}
+
+#[test]
+fn test_substitute() {
+    check("
+        text a a\\na a\\na a\\na a
+        :s/a/b/g
+        expect text b b\\na a\\na a\\na a
+        expect message 2 substitutions on 1 line
+        keys j&
+        expect text b b\\nb a\\na a\\na a
+        :3,4&&
+        expect text b b\\nb a\\nb b\\nb b
+        :%s/(b) (\\w)/\\2-&/
+        expect text b-b b\\na-b a\\nb-b b\\nb-b b
+        :%s/b/c/g
+        expect text c-c c\\na-c a\\nc-c c\\nc-c c
+        expect message 10 substitutions on 4 lines
+        keys u
+        expect text b-b b\\na-b a\\nb-b b\\nb-b b
+        :s/zzz/y/
+        expect message Pattern not found: zzz
+    ");
+}
+
+#[test]
+fn test_retab() {
+    check("
+        text \\tone\\n  \\ttwo\\n\\t\\tthree
+        :retab
+        expect text     one\\n    two\\n        three
+        :set noexpandtab
+        :2,3retab! 2
+        expect text     one\\n\\t\\ttwo\\n\\t\\t\\t\\tthree
+        :set ts?
+        expect message ts=2
+    ");
+    check("
+        text x
+        :set noet
+        keys i<Tab><Esc>:set et<CR>A<Tab>y<Esc>
+        expect text \\tx   y
+    ");
+}
+
+#[test]
+fn test_format() {
+    check("
+        text one two three four five six\\n\\n// seven eight\\n// nine ten eleven
+        :set tw=10
+        keys gqq
+        expect text one two\\nthree four\\nfive six\\n\\n// seven eight\\n// nine ten eleven
+        expect cursor 3:1
+        keys Ggqap
+        expect text one two\\nthree four\\nfive six\\n\\n// seven\\n// eight\\n// nine\\n// ten\\n// eleven
+        :set tw=30
+        keys ggVjjgq
+        expect text one two three four five six\\n\\n// seven\\n// eight\\n// nine\\n// ten\\n// eleven
+        keys 2jgq4j
+        expect text one two three four five six\\n\\n// seven eight nine ten eleven
+        expect mode NORMAL
+    ");
+}
+
+#[test]
+fn test_help() {
+    check("
+        text one\\ntwo
+        keys j
+        :help registers
+        :help no such thing
+        expect message Sorry, no help for no such thing
+        keys /see .registers<CR>w<C-]><C-t>q
+        expect text one\\ntwo
+        expect cursor 2:1
+    ");
+}
+
+#[test]
+fn test_file_info() {
+    check("
+        text one two three four five six seven\\nline
+        keys <C-g>
+        expect message \"[No Name]\" 38 chars, line 1/2; undo 0
+        keys jdd<C-g>
+        expect message \"[No Name]\" [Modified] 33 chars, line 1/1; undo 1, \"\" line
+        keys u<C-g>
+        expect message \"[No Name]\" 38 chars, line 2/2; undo 0, redo 1, \"\" line
+        keys kyy<C-g>
+        expect message \"[No Name]\" 38 chars, line 1/2; undo 0, redo 1, \"\" one two three four f…
+    ");
+}
+
+#[test]
+fn test_move_lines() {
+    check("
+        text a\\nb\\nc\\nd
+        keys <A-j>
+        expect text b\\na\\nc\\nd
+        expect cursor 2:1
+        keys <A-j><A-j><A-j>
+        expect text b\\nc\\nd\\na
+        keys u
+        expect text b\\nc\\na\\nd
+        keys ggVj<A-k>
+        expect text b\\nc\\na\\nd
+        keys <A-j>
+        expect text a\\nb\\nc\\nd
+        expect mode V-LINE
+        keys <Esc>:1,2m$<CR>
+        expect text c\\nd\\na\\nb
+        expect cursor 4:1
+        keys :m0<CR>
+        expect text b\\nc\\nd\\na
+        keys :2,3m 2<CR>
+        expect message Cannot move a range of lines into itself
+    ");
+}
+
+#[test]
+fn test_line_commands() {
+    check("
+        text a\\nb\\nc\\nd\\ne
+        :2,3d x
+        expect text a\\nd\\ne
+        expect cursor 2:1
+        :$put x
+        expect text a\\nd\\ne\\nb\\nc
+        expect cursor 5:1
+        :0put x
+        expect text b\\nc\\na\\nd\\ne\\nb\\nc
+        expect cursor 2:1
+        :4y 2
+        :1put!
+        expect text d\\ne\\nb\\nc\\na\\nd\\ne\\nb\\nc
+        keys u
+        expect text b\\nc\\na\\nd\\ne\\nb\\nc
+        :3,$d
+        expect text b\\nc
+        :put q
+        expect message Nothing in register q
+        :d xy
+        expect message Trailing characters: xy
+    ");
+}
+
+#[test]
+fn test_path_completion() {
+    check("
+        text see Cargo.
+        keys A<C-x><C-f>
+        expect text see Cargo.lock
+        expect message -- File name completion: match 1 of 2
+        keys <C-n>
+        expect text see Cargo.toml
+        keys <C-n>
+        expect text see Cargo.
+        keys <C-p><C-y>!<Esc>
+        expect text see Cargo.toml!
+        keys o(tests/ke<C-x><C-f>)<Esc>
+        expect line 2 (tests/keys.rs)
+        keys o<C-x>x
+        expect line 3 x
+    ");
+}
+
+#[test]
+fn test_line_completion() {
+    check("
+        text [server]\\n    port = 8080\\n    host = \"a\"\\n\\n[client]
+        keys Gopo<C-x><C-l>
+        expect line 6     port = 8080
+        keys <Esc>o    h<C-x><C-l>
+        expect line 7     host = \"a\"
+        keys <Esc>o<C-x><C-l>
+        expect message -- Whole line completion: match 1 of 4
+        keys <C-e>x<Esc>
+        expect line 8 x
+        keys oqq<C-x><C-l>
+        expect message -- Whole line completion: Pattern not found
+    ");
+}
+
+#[test]
+fn test_percent_matching() {
+    check("
+        text fn f() {\\n    match x {\\n        1 => a(),\\n        _ => {}\\n    }\\n}
+        :set ft=rust
+        keys j^%
+        expect cursor 3:9
+        keys %
+        expect cursor 4:9
+        keys %
+        expect cursor 5:5
+        keys %
+        expect cursor 2:13
+        keys ggf{%
+        expect cursor 6:1
+    ");
+    check("
+        text <ul>\\n  <li>one</li>\\n</ul>
+        :set ft=html
+        keys l%
+        expect cursor 3:1
+        keys %
+        expect cursor 1:1
+    ");
+}
+
+#[test]
+fn test_map_commands() {
+    check("
+        text one\\ntwo\\nthree
+        :nmap Q delete_line
+        expect message Mapped Q to Delete line
+        keys Q
+        expect text two\\nthree
+        :nmap x undo
+        keys x
+        expect text one\\ntwo\\nthree
+        :nunmap x
+        expect message x is back to Delete character
+        keys x
+        expect line 1 ne
+        :nmap Q
+        expect message n  Q            Delete line
+        :nunmap Q
+        expect message Unmapped Q
+        :nunmap Q
+        expect message No mapping for Q
+        :imap jk enter_normal
+        keys Ajk
+        expect mode NORMAL
+        expect line 1 ne
+        :nmap <leader>w :s/ne/one/
+        keys \\w
+        expect line 1 one
+        :nmap Z explode
+        expect message Unknown action: explode
+    ");
+}
+
+#[test]
+fn test_lua_remap() {
+    // A Lua mapping replaces a default binding until it is unmapped
+    check("
+        text one\\ntwo\\nthree
+        :lua quirks.keymap({ 'n', 'v' }, 'x', 'delete_line')
+        keys x
+        expect text two\\nthree
+        :lua quirks.keymap('n', 'dd', function() quirks.cmd('s/^/- /') end)
+        keys dd
+        expect text - two\\nthree
+        keys vx<Esc>
+        expect text three
+        :nunmap x
+        expect message x is back to Delete character
+        keys x
+        expect text hree
+    ");
+}
+
+#[test]
+fn test_insert_editing_keys() {
+    check("
+        text foo.bar(baz  
+        keys A<C-w>
+        expect text foo.bar(
+        keys <C-w><C-w>
+        expect text foo.
+        keys <C-w>x<Esc>
+        expect text foox
+        :set tabstop=4
+        keys A<C-t>
+        expect text     foox
+        expect cursor 1:9
+        keys <C-t><C-d>y
+        expect text     fooxy
+        keys <C-u>
+        expect text     
+        keys <C-u>bar<Esc>
+        expect text bar
+        keys o  baz<C-d>
+        expect text bar\\nbaz
+        keys <C-u><C-u>
+        expect text bar
+    ");
+}
+
+#[test]
+fn test_counted_insert() {
+    check("
+        text ab
+        keys 5i-<Esc>
+        expect text -----ab
+        expect cursor 1:5
+        keys 2Ax<Esc>
+        expect text -----abxx
+        expect cursor 1:9
+        keys 3oline<Esc>
+        expect text -----abxx\\nline\\nline\\nline
+        expect cursor 4:4
+        keys u
+        expect text -----abxx\\nline
+        keys 2Oa<CR>b<Esc>
+        expect text -----abxx\\na\\nb\\na\\nb\\nline
+        expect cursor 5:1
+    ");
+}
+
+#[test]
+fn test_resume_insert() {
+    check("
+        text one\\ntwo\\nthree
+        keys jAx<Esc>
+        expect cursor 2:4
+        keys gg0gi!<Esc>
+        expect text one\\ntwox!\\nthree
+        keys G'^
+        expect cursor 2:1
+        keys G`^
+        expect cursor 2:5
+        keys m^
+        expect message Invalid mark: ^
+    ");
+}
+
+#[test]
+fn test_search_offsets() {
+    check("
+        text one two three\\nfour five\\nsix
+        keys /two/e<CR>
+        expect cursor 1:7
+        keys n
+        expect cursor 1:7
+        keys gg/fi/+1<CR>
+        expect cursor 3:1
+        keys gg/thr/s-1<CR>
+        expect cursor 1:8
+    ");
+    check("
+        text one two three\\nfour five
+        keys d/thr<CR>
+        expect text three\\nfour five
+        keys u
+        expect text one two three\\nfour five
+        keys wd/fi/e<CR>
+        expect text one ve
+        keys p
+        expect text one vtwo three\\nfour fie
+    ");
+    check("
+        text alpha beta gamma
+        keys ww\"ay?bet<CR>
+        expect cursor 1:7
+        keys \"ap
+        expect text alpha bbeta eta gamma
+        keys 0c/gam<CR>new <Esc>
+        expect text new gamma
+    ");
+    check("
+        text a\\nb\\nc\\nd
+        keys d/c/0<CR>
+        expect text d
+        keys uy/b/+1<CR>
+        expect message 3 lines yanked
+    ");
+}
+
+#[test]
+fn test_star_search() {
+    check("
+        text say foo\\nfoo bar foo
+        keys wl*
+        expect cursor 1:6
+        expect message Found: foo
+        keys n
+        expect cursor 2:1
+        keys :noh<CR>n
+        expect cursor 2:9
+        keys #
+        expect cursor 2:9
+    ");
+}
+
+#[test]
+fn test_visual_area() {
+    check("
+        text a\\nb\\nc\\nd
+        keys :'<,'>d<CR>
+        expect message Invalid range
+        keys jVj:d<CR>
+        expect text a\\nd
+    ");
+    check("
+        text foo foo foo\\nfoo foo
+        keys wve:s/\\%Vfoo/bar/<CR>
+        expect text foo bar foo\\nfoo foo
+        keys 0<C-v>jl<Esc>:%s/\\%Vo/0/g<CR>
+        expect text f0o bar foo\\nf0o foo
+    ");
+    check("
+        text x one\\none x\\nx
+        keys jjVk/x<CR>
+        expect cursor 2:5
+        keys n
+        expect cursor 3:1
+        keys n
+        expect cursor 2:5
+    ");
+}
+
+#[test]
+fn test_scroll_margin() {
+    // The headless window is 24 lines high
+    let text: String = (1..=100).map(|n| format!("{}\\n", n)).collect();
+    check(&format!("
+        text {}
+        keys 21j
+        expect top 2
+        :set so=0
+        keys G23k
+        expect top 78
+        :set so=999
+        keys j
+        expect top 68
+        keys gg
+        expect top 1
+    ", text));
+}
+
+#[test]
+fn test_char_info() {
+    check("
+        text a\u{a0}é
+        keys lga
+        expect message <\u{a0}> 160, Hex a0, Oct 240, UTF-8 c2 a0, NO-BREAK SPACE
+        keys l:ascii<CR>
+        expect message <é> 233, Hex e9, Oct 351, UTF-8 c3 a9, LATIN SMALL LETTER E WITH ACUTE
+    ");
+    check("
+        text
+        keys ga
+        expect message NUL
+    ");
+}
+
+#[test]
+fn test_count_stats() {
+    check("
+        text one two\\nthree four five
+        keys jwlg<C-g>
+        expect message Col 8 of 15; Line 2 of 2; Word 4 of 5; Char 16 of 23; Byte 16 of 23
+        keys ggg<C-g>
+        expect message Col 1 of 7; Line 1 of 2; Word 1 of 5; Char 1 of 23; Byte 1 of 23
+        keys wvjg<C-g>
+        expect message Selected 2 of 2 Lines; 2 of 5 Words; 9 of 23 Chars; 9 of 23 Bytes
+        expect mode VISUAL
+        keys <Esc>:count<CR>
+        expect message 2 lines, 5 words, 23 chars, 23 bytes
+        keys :'<,'>count<CR>
+        expect message 2 lines, 2 words, 9 chars, 9 bytes
+        keys :2count<CR>
+        expect message 1 lines, 3 words, 16 chars, 16 bytes
+    ");
+}
+
+#[test]
+fn test_substitute_preview() {
+    check("
+        text one one\\none
+        keys :%s/one/two
+        expect text two one\\ntwo
+        expect mode COMMAND
+        keys /g
+        expect text two two\\ntwo
+        keys <Esc>
+        expect text one one\\none
+        keys :s/one/three/<CR>
+        expect text three one\\none
+        keys u
+        expect text one one\\none
+        :set noinccommand
+        keys :s/one/two
+        expect text one one\\none
+    ");
+}
+
+#[test]
+fn test_restore_cursor() {
+    check("
+        :e Cargo.toml
+        keys 5Gw
+        :e README.md
+        expect cursor 1:1
+        keys 3G
+        :e Cargo.toml
+        expect cursor 5:9
+        keys `\"
+        expect cursor 5:9
+        :e README.md
+        expect cursor 3:1
+    ");
+    check("
+        :set restore_cursor=false
+        :e Cargo.toml
+        keys 5G
+        :e README.md
+        :e Cargo.toml
+        expect cursor 1:1
+    ");
+}
+
+#[test]
+fn test_write_all() {
+    let dir = std::env::temp_dir().join(format!("quirks-write-all-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    std::fs::write(&a, "one\n").unwrap();
+    std::fs::write(&b, "two\n").unwrap();
+    let missing = dir.join("gone/c.txt");
+
+    let mut editor = Editor::headless(Config::default());
+    let script = format!(
+        "
+        :e {a}
+        keys A!<Esc>
+        :e {b}
+        keys A?<Esc>
+        :wa
+        expect message 2 files written
+        :wa
+        expect message No changes to write
+        :e {missing}
+        keys ithree<Esc>
+        :e {a}
+        keys A!<Esc>
+        ",
+        a = a.display(),
+        b = b.display(),
+        missing = missing.display(),
+    );
+    if let Err(err) = script::run(&mut editor, &script) {
+        panic!("{}\n{}", err, script);
+    }
+    assert!(!editor.execute_command("wqa"));
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "one!!\n");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "two?\n");
+    assert!(editor.message().unwrap().starts_with(&format!("Not written: {}: ", missing.display())));
+
+    std::fs::create_dir_all(missing.parent().unwrap()).unwrap();
+    assert!(editor.execute_command("xa"));
+    assert_eq!(std::fs::read_to_string(&missing).unwrap(), "three");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_quit_prompt() {
+    let dir = std::env::temp_dir().join(format!("quirks-quit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    std::fs::write(&a, "one\n").unwrap();
+    std::fs::write(&b, "two\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    assert!(editor.execute_command("q"));
+    editor.execute_command(&format!("e {}", a.display()));
+    assert_eq!(editor.feed_keys("A!<Esc>"), Some(false));
+    editor.execute_command(&format!("e {}", b.display()));
+    assert!(!editor.execute_command("q"));
+    assert_eq!(editor.prompt().map(Prompt::lines), Some(&[a.display().to_string()][..]));
+    assert_eq!(editor.feed_keys("x<Esc>"), Some(false));
+    assert!(editor.prompt().is_none());
+    assert_eq!(editor.buffer().content(), "two\n");
+
+    assert_eq!(editor.feed_keys("A?<Esc><C-q>"), Some(false));
+    assert_eq!(editor.prompt().map(|prompt| prompt.lines().len()), Some(2));
+    assert_eq!(editor.feed_keys("s"), Some(true));
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "one!\n");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "two?\n");
+
+    assert_eq!(editor.feed_keys("A.<Esc>"), Some(false));
+    assert!(editor.execute_command("wq"));
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "two?.\n");
+    assert_eq!(editor.feed_keys("x<C-q>"), Some(false));
+    assert_eq!(editor.feed_keys("d"), Some(true));
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "two?.\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Home directory of a stand-in for ssh that runs the command here, put
+/// first on PATH for the whole test run. Commands on files named slow*
+/// wait a moment first.
+fn fake_ssh_home() -> &'static std::path::Path {
+    static HOME: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+    HOME.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("quirks-remote-{}", std::process::id()));
+        let (bin, home) = (dir.join("bin"), dir.join("home"));
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        let ssh = bin.join("ssh");
+        let script = format!(
+            "#!/bin/sh\ncd '{}' || exit 1\nfor last; do :; done\ncase \"$last\" in *slow*) sleep 0.3;; esac\nexec sh -c \"$last\"\n",
+            home.display()
+        );
+        std::fs::write(&ssh, script).unwrap();
+        std::fs::set_permissions(&ssh, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", bin.display(), path));
+        home
+    })
+}
+
+/// Run the editor's background work until the message starts with `done`
+fn wait_for_message(editor: &mut Editor, done: &str) {
+    for _ in 0..500 {
+        editor.tick();
+        if editor.message().is_some_and(|message| message.starts_with(done)) {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("no {:?}: {:?}", done, editor.message());
+}
+
+#[test]
+fn test_remote_write() {
+    let home = fake_ssh_home();
+    std::fs::write(home.join("notes.txt"), "one\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command("e scp://box/notes.txt");
+    wait_for_message(&mut editor, "Opened:");
+    assert_eq!(editor.buffer().content(), "one\n");
+
+    for write in ["w", "w!", "write!"] {
+        editor.feed_keys("Atwo<Esc>");
+        editor.execute_command(write);
+        assert!(editor.prompt().is_none(), ":{} asked {:?}", write, editor.prompt().map(Prompt::line));
+        wait_for_message(&mut editor, "Written: scp://box/notes.txt");
+        assert_eq!(std::fs::read_to_string(home.join("notes.txt")).unwrap(), editor.buffer().content());
+        assert!(!editor.buffer().is_modified());
+    }
+    assert!(!std::path::Path::new("scp:").exists());
+}
+
+#[test]
+fn test_substitute_preview_while_opening() {
+    let home = fake_ssh_home();
+    std::fs::write(home.join("slow.txt"), "two\n").unwrap();
+    let dir = std::env::temp_dir().join(format!("quirks-preview-open-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let local = dir.join("local.txt");
+    std::fs::write(&local, "one\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", local.display()));
+    editor.execute_command("e scp://box/slow.txt");
+    // The file comes in while :s is previewed on the local one
+    editor.feed_keys(":s/one/ONE/");
+    assert_eq!(editor.buffer().line(0), "ONE");
+    wait_for_message(&mut editor, "Opened:");
+    assert_eq!(editor.buffer().line(0), "two");
+    editor.feed_keys("<Esc>");
+    assert_eq!(editor.buffer().content(), "two\n");
+
+    editor.execute_command("b 0");
+    assert_eq!(editor.buffer().file_path(), Some(&local));
+    assert_eq!(editor.buffer().content(), "one\n");
+    assert!(!editor.buffer().is_modified());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_rename_delete_file() {
+    let dir = std::env::temp_dir().join(format!("quirks-rename-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b, c) = (dir.join("a.txt"), dir.join("b.rs"), dir.join("c.txt"));
+    std::fs::write(&a, "one\n").unwrap();
+    std::fs::write(&c, "three\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", a.display()));
+    editor.execute_command("Rename b.rs");
+    assert!(!a.exists());
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "one\n");
+    assert_eq!(editor.buffer().file_path(), Some(&b));
+    assert_eq!(editor.message().unwrap(), &format!("Renamed to {} [Rust]", b.display()));
+
+    // Only :Rename itself, not commands that start the same
+    editor.execute_command("RenameAll x.txt");
+    assert_eq!(editor.message().map(String::as_str), Some("Unknown command: RenameAll x.txt"));
+    editor.execute_command("lua quirks.command('RenameAll', function(opts) print('all ' .. opts.args) end)");
+    editor.execute_command("RenameAll x.txt");
+    assert_eq!(editor.message().map(String::as_str), Some("all x.txt"));
+    assert!(b.exists());
+
+    editor.execute_command("Rename c.txt");
+    assert_eq!(editor.prompt().map(Prompt::line), Some(format!("{} exists, replace it? [y]es  [n]o", c.display())));
+    editor.feed_keys("n");
+    assert_eq!(editor.message().map(String::as_str), Some("Cancelled"));
+    assert!(b.exists());
+
+    editor.execute_command("Delete");
+    assert_eq!(editor.prompt().map(Prompt::line), Some(format!("Delete {}? [y]es  [n]o", b.display())));
+    editor.feed_keys("y");
+    assert!(!b.exists());
+    assert_eq!(editor.buffer().file_path(), None);
+    assert_eq!(std::fs::read_to_string(&c).unwrap(), "three\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_confirm_prompts() {
+    let dir = std::env::temp_dir().join(format!("quirks-confirm-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    std::fs::write(&a, "one\n").unwrap();
+    std::fs::write(&b, "two\n").unwrap();
+
+    // Writing over another file asks first
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", a.display()));
+    editor.execute_command(&format!("w {}", b.display()));
+    assert_eq!(editor.prompt().map(Prompt::line), Some(format!("{} exists, overwrite it? [y]es  [n]o", b.display())));
+    editor.feed_keys("<Esc>");
+    assert_eq!(editor.message().map(String::as_str), Some("Cancelled"));
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "two\n");
+    editor.execute_command(&format!("w {}", b.display()));
+    editor.feed_keys("y");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "one\n");
+
+    // :bd on a modified buffer offers to save it
+    editor.feed_keys("A!<Esc>");
+    editor.execute_command("bd");
+    assert_eq!(editor.prompt().map(Prompt::footer), Some("[s]ave  [d]iscard  [c]ancel".to_string()));
+    editor.feed_keys("c");
+    assert_eq!(editor.buffer().file_path(), Some(&b));
+    editor.execute_command("bd");
+    editor.feed_keys("s");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "one!\n");
+    assert_eq!(editor.message().map(String::as_str), Some("Buffer closed"));
+
+    // :Rename without a name asks for one
+    editor.execute_command(&format!("e {}", a.display()));
+    editor.execute_command("Rename");
+    assert_eq!(editor.prompt().map(Prompt::line), Some("Rename to: a.txt".to_string()));
+    editor.feed_keys("<BS><BS><BS>md<CR>");
+    assert!(dir.join("a.md").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_substitute_confirm() {
+    check("
+        text a a\\nb\\na a
+        :%s/a/x/gc
+        expect prompt Replace this match? [y]es  [n]o  [a]ll  [q]uit  [l]ast
+        expect cursor 1:1
+        keys y
+        expect cursor 1:3
+        keys n
+        expect cursor 3:1
+        keys a
+        expect text x a\\nb\\nx x
+        expect message 3 substitutions on 2 lines
+        keys u
+        expect text a a\\nb\\na a
+        :%s/a/x/c
+        keys nl
+        expect text a a\\nb\\nx a
+        expect message 1 substitution on 1 line
+        :%s/a/x/c
+        keys <Esc>
+        expect text a a\\nb\\nx a
+        expect message 0 substitutions on 0 lines
+        :%s/z/x/c
+        expect message Pattern not found: z
+    ");
+}
+
+#[test]
+fn test_text_objects() {
+    check(r#"
+        text let foo_bar = call(a, "x y");
+        keys wdiw
+        expect text let  = call(a, "x y");
+        keys u0f"ci"z<Esc>
+        expect text let foo_bar = call(a, "z");
+        keys ya(P
+        expect text let foo_bar = call(a, "z")(a, "z");
+        expect cursor 1:19
+        keys bdiw
+        expect text let foo_bar = (a, "z")(a, "z");
+        keys 0fzci"<Esc>
+        expect text let foo_bar = (a, "")(a, "z");
+        keys ci"q<Esc>
+        expect text let foo_bar = (a, "q")(a, "z");
+        keys 0daw
+        expect text foo_bar = (a, "q")(a, "z");
+    "#);
+    check("
+        text fn f() {\\n    one\\n    two\\n}\\n\\nrest
+        keys jdi{
+        expect text fn f() {\\n}\\n\\nrest
+        keys u
+        keys jvipd
+        expect text \\nrest
+        keys u
+        keys Gdap
+        expect text fn f() {\\n    one\\n    two\\n}
+    ");
+}
+
+#[test]
+fn test_operator_targets() {
+    check("
+        text one two.three four\\nfive
+        keys dw
+        expect text two.three four\\nfive
+        keys dW
+        expect text four\\nfive
+        keys u
+        keys cwxx<Esc>
+        expect text xx.three four\\nfive
+        keys wgUe
+        expect text xx.THREE four\\nfive
+        keys 0g~W
+        expect text XX.three four\\nfive
+        keys gugu
+        expect text xx.three four\\nfive
+        keys gU/fo<CR>
+        expect text XX.THREE four\\nfive
+        keys 2d2w
+        expect text \\nfive
+    ");
+    check("
+        text a\\nb\\nc\\nd
+        keys >j
+        expect text     a\\n    b\\nc\\nd
+        keys <G
+        expect text a\\nb\\nc\\nd
+        keys 2yyGp
+        expect text a\\nb\\nc\\nd\\na\\nb
+        keys ggd2<Esc>
+        expect mode NORMAL
+        expect text a\\nb\\nc\\nd\\na\\nb
+    ");
+    check("
+        text fn a() {\\n    b();\\n}
+        :set filetype=rust
+        keys jgcc
+        expect text fn a() {\\n    // b();\\n}
+        keys gcc
+        expect text fn a() {\\n    b();\\n}
+        keys gcj
+        expect text fn a() {\\n//     b();\\n// }
+        keys ggVGgc
+        expect text // fn a() {\\n// //     b();\\n// // }
+        expect mode NORMAL
+    ");
+    check("
+        text Hello World
+        keys vegu
+        expect text hello World
+        expect mode NORMAL
+        keys wviWU
+        expect text hello WORLD
+        keys Vgc
+        expect message No line comment for this file type
+    ");
+}
+
+#[test]
+fn test_open_directory() {
+    check("
+        :e tests
+        expect mode EXPLORER
+        expect message Directory: tests
+        keys j<CR>
+        expect mode NORMAL
+        expect line 1 //! Modal editing regressions, as key-sequence scripts
+    ");
+}
+
+#[test]
+fn test_write_creates_directories() {
+    let dir = std::env::temp_dir().join(format!("quirks-mkdir-{}", std::process::id()));
+    let path = dir.join("new/module/mod.rs");
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", path.display()));
+    assert_eq!(editor.message().unwrap(), &format!("New file: {} [Rust]", path.display()));
+    editor.feed_keys("ipub fn f() {}<Esc>");
+
+    editor.execute_command("w");
+    let question = format!("{} does not exist, create it? [y]es  [n]o", path.parent().unwrap().display());
+    assert_eq!(editor.prompt().map(Prompt::line), Some(question));
+    editor.feed_keys("<Esc>");
+    assert!(!dir.exists());
+
+    editor.execute_command("w");
+    editor.feed_keys("y");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "pub fn f() {}");
+    assert!(!editor.buffer().is_modified());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_set_filetype() {
+    check("
+        :e Cargo.toml
+        :set ft?
+        expect message filetype=toml
+        :set filetype=python
+        :set ft?
+        expect message filetype=python
+        :e README.md
+        :set ft?
+        expect message filetype=markdown
+        keys gT
+        :set ft?
+        expect message filetype=python
+        :filetype
+        expect message filetype=python (set with :set filetype)
+        :filetype detect
+        expect message Filetype detected [TOML]
+        :filetype
+        expect message filetype=toml (detected)
+        :set ft=cobol
+        expect message Unknown filetype: cobol (use c, dockerfile, html, javascript, makefile, markdown, python, rust, shell, toml or off)
+        :syntax off
+        expect message Syntax highlighting off
+        :new
+        :filetype
+        expect message No filetype detected
+    ");
+}
+
+#[test]
+fn test_increment_numbers() {
+    check("
+        text width: 9px, height: 0x0f
+        keys <C-a>
+        expect line 1 width: 10px, height: 0x0f
+        expect cursor 1:9
+        keys b5<C-x>
+        expect line 1 width: 5px, height: 0x0f
+        keys $<C-a>
+        expect line 1 width: 5px, height: 0x10
+    ");
+    check("
+        text item 0\\nitem 0\\nitem 0\\nitem 0
+        keys VGg<C-a>
+        expect text item 1\\nitem 2\\nitem 3\\nitem 4
+        expect mode NORMAL
+        keys jVj10<C-a>
+        expect text item 1\\nitem 12\\nitem 13\\nitem 4
+        keys gg<C-v>G$2g<C-x>
+        expect text item -1\\nitem 8\\nitem 7\\nitem -4
+    ");
+    check("
+        :set nrformats=alpha
+        text a) first\\na) second
+        keys j<C-a>
+        expect line 2 b) second
+    ");
+}
+
+#[test]
+fn test_pending_command() {
+    let mut editor = Editor::headless(Config::default());
+    editor.set_text("one\ntwo\nthree");
+    editor.feed_keys("\"a2d");
+    assert_eq!(editor.pending_command(), Some("\"a2d"));
+    editor.feed_keys("d");
+    assert_eq!(editor.pending_command(), None);
+    assert_eq!(editor.buffer().line(0), "three");
+    editor.feed_keys("d2");
+    assert_eq!(editor.pending_command(), Some("d2"));
+    editor.feed_keys("<Esc>g");
+    assert_eq!(editor.pending_command(), Some("g"));
+    editor.feed_keys("g");
+    assert_eq!(editor.pending_command(), None);
+    editor.feed_keys("123456789012");
+    assert_eq!(editor.pending_command(), Some("3456789012"));
+}
+
+#[test]
+fn test_bookmarks() {
+    let dir = std::env::temp_dir().join(format!("quirks-bookmark-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join(".git")).unwrap();
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    std::fs::write(&a, "one\ntwo\nthree\n").unwrap();
+    std::fs::write(&b, "four\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", a.display()));
+    editor.feed_keys("j");
+    editor.execute_command("bookmark add \"review this\"");
+    assert_eq!(editor.message().map(String::as_str), Some("Bookmark added: review this"));
+    assert_eq!(editor.buffer().line_decorations(1).len(), 1);
+    // The bookmark follows its line as lines are added above it
+    editor.feed_keys("ggOzero<Esc>");
+    editor.execute_command(&format!("e {}", b.display()));
+    editor.execute_command("bm add");
+    editor.execute_command("bookmarks");
+    let labels: Vec<&str> = editor.picker().unwrap().items().iter().map(|item| item.label.as_str()).collect();
+    assert_eq!(labels, ["a.txt:3  review this", "b.txt:1"]);
+    editor.feed_keys("<CR>");
+    assert_eq!(editor.buffer().file_path(), Some(&a));
+    assert_eq!(editor.cursor().line, 2);
+
+    editor.execute_command("bookmark remove");
+    assert_eq!(editor.message().map(String::as_str), Some("Bookmark removed"));
+    assert!(editor.buffer().line_decorations(2).is_empty());
+    editor.execute_command("bookmark remove");
+    assert_eq!(editor.message().map(String::as_str), Some("No bookmark on this line"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_project_config() {
+    let dir = std::env::temp_dir().join(format!("quirks-project-{}", std::process::id()));
+    let (project, other) = (dir.join("app"), dir.join("other"));
+    std::fs::create_dir_all(project.join(".git")).unwrap();
+    std::fs::create_dir_all(other.join(".git")).unwrap();
+    let config = project.join(".quirks.toml");
+    std::fs::write(&config, "tab_width = 2\n").unwrap();
+    std::fs::write(project.join("main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(other.join("notes.txt"), "notes\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", project.join("main.rs").display()));
+    assert_eq!(
+        editor.prompt().map(Prompt::line),
+        Some(format!("Trust {}? It can set the shell and build commands. [y]es  [n]o", config.display()))
+    );
+    assert_eq!(editor.config().tab_width, 4);
+    editor.feed_keys("y");
+    assert_eq!(editor.config().tab_width, 2);
+
+    // Options set by hand outlive the project's
+    editor.execute_command("set wrap");
+    editor.execute_command(&format!("e {}", other.join("notes.txt").display()));
+    assert_eq!(editor.config().tab_width, 4);
+    assert!(editor.config().wrap);
+    editor.execute_command(&format!("e {}", project.join("main.rs").display()));
+    assert_eq!(editor.config().tab_width, 2);
+
+    // A changed file is asked about again
+    std::fs::write(&config, "tab_width = 3\n").unwrap();
+    editor.execute_command(&format!("e {}", other.join("notes.txt").display()));
+    editor.execute_command(&format!("e {}", project.join("main.rs").display()));
+    assert_eq!(editor.config().tab_width, 4);
+    editor.feed_keys("n");
+    assert_eq!(editor.config().tab_width, 4);
+    editor.execute_command("trust ++deny");
+    assert_eq!(editor.message().unwrap(), &format!("Denied {}", config.display()));
+    editor.execute_command("trust");
+    assert_eq!(editor.config().tab_width, 3);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_lua_autocmd() {
+    let dir = std::env::temp_dir().join(format!("quirks-lua-autocmd-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let init = dir.join("init.lua");
+    std::fs::write(
+        &init,
+        r#"
+        quirks.autocmd("BufRead", { pattern = "*.rs", callback = function(ev)
+            print("read " .. ev.match .. " with " .. quirks.line_count() .. " lines")
+        end })
+        quirks.autocmd({ "BufWritePre", "BufWritePost" }, function(ev) print(ev.event) end)
+        quirks.autocmd("ModeChanged", { pattern = "normal:insert", callback = function()
+            print("inserting at " .. table.concat({ quirks.cursor() }, ":"))
+        end })
+        "#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(dir.join("notes.txt"), "notes\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.source_lua(&init);
+    editor.execute_command(&format!("e {}", dir.join("main.rs").display()));
+    assert_eq!(editor.message().unwrap(), &format!("read {} with 2 lines", dir.join("main.rs").display()));
+    editor.execute_command(&format!("e {}", dir.join("notes.txt").display()));
+    assert!(editor.message().unwrap().starts_with("Opened:"));
+    editor.execute_command("w");
+    assert_eq!(editor.message().map(String::as_str), Some("BufWritePost"));
+    editor.feed_keys("A");
+    assert_eq!(editor.message().map(String::as_str), Some("inserting at 1:6"));
+
+    // Errors name the event and leave the editor usable
+    std::fs::write(&init, "quirks.autocmd('CursorMoved', function() error('boom') end)").unwrap();
+    editor.source_lua(&init);
+    editor.feed_keys("<Esc>0");
+    assert!(editor.message().unwrap().starts_with("CursorMoved autocommand:"));
+    assert!(editor.message().unwrap().ends_with("boom"));
+    std::fs::write(&init, "quirks.autocmd('BufSave', print)").unwrap();
+    editor.source_lua(&init);
+    assert!(editor.message().unwrap().ends_with("unknown event: BufSave"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_lua_command() {
+    let dir = std::env::temp_dir().join(format!("quirks-lua-command-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let init = dir.join("init.lua");
+    std::fs::write(
+        &init,
+        r#"
+        quirks.command("hello", function(opts)
+            print("hello " .. (opts.bang and "loudly " or "") .. opts.args)
+        end)
+        quirks.command("Quote", "s/^/<args> /")
+        quirks.command("Q", "q!")
+        "#,
+    )
+    .unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command("hello");
+    assert_eq!(editor.message().map(String::as_str), Some("Unknown command: hello"));
+    editor.source_lua(&init);
+    editor.execute_command("hello! world");
+    assert_eq!(editor.message().map(String::as_str), Some("hello loudly world"));
+    editor.set_text("one\ntwo");
+    editor.feed_keys("j:Quote ><CR>");
+    assert_eq!(editor.buffer().content(), "one\n> two");
+    assert!(editor.execute_command("Q"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_lua_keymap() {
+    let dir = std::env::temp_dir().join(format!("quirks-lua-keymap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("keys.lua");
+    std::fs::write(
+        &script,
+        r#"
+        quirks.keymap("n", "<leader>l", function() print("on line " .. quirks.cursor()) end)
+        quirks.keymap("n", "<leader>q", ":q!<CR>")
+        quirks.keymap("n", "<leader>x", function() error("boom") end)
+        "#,
+    )
+    .unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.set_text("one\ntwo");
+    editor.execute_command(&format!("luafile {}", script.display()));
+    assert_eq!(editor.feed_keys("j<leader>l"), Some(false));
+    assert_eq!(editor.message().map(String::as_str), Some("on line 2"));
+    editor.execute_command("nmap <leader>l");
+    assert!(editor.message().unwrap().ends_with("Lua function"));
+    editor.feed_keys("<leader>x");
+    assert!(editor.message().unwrap().ends_with("boom"));
+    assert_eq!(editor.feed_keys("<leader>q"), Some(true));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_lua_options() {
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command("lua quirks.set_option('tabstop', 8); quirks.set_option('number', false)");
+    assert_eq!(editor.config().tab_width, 8);
+    assert!(!editor.config().line_numbers);
+    editor.execute_command("lua =quirks.get_option('ts'), quirks.get_option('nu')");
+    assert_eq!(editor.message().map(String::as_str), Some("8, false"));
+    editor.execute_command("set tw=60");
+    editor.execute_command("lua =quirks.get_option('textwidth')");
+    assert_eq!(editor.message().map(String::as_str), Some("60"));
+
+    // Buffer-local values win over the global ones, as with :setlocal
+    editor.execute_command("lua quirks.set_option('syntax', false, { scope = 'local' })");
+    assert!(editor.config().syntax_highlighting);
+    assert!(!editor.syntax_enabled());
+    editor.execute_command("lua quirks.set_option('readonly', true)");
+    assert!(editor.buffer().is_readonly());
+    editor.execute_command("lua =quirks.get_option('syntax'), quirks.get_option('ro')");
+    assert_eq!(editor.message().map(String::as_str), Some("false, true"));
+    editor.execute_command("lua quirks.set_option('tabstop', 2, { scope = 'local' })");
+    assert!(editor.message().unwrap().ends_with("Unknown local option: tabstop"));
+    editor.execute_command("lua quirks.set_option('number', 'maybe')");
+    assert!(editor.message().unwrap().ends_with("Invalid boolean: maybe"));
+}
+
+#[test]
+fn test_lua_line() {
+    check("
+        text one\\ntwo
+        keys j:lua print(quirks.get_line(quirks.cursor()) .. '!')<CR>
+        expect message two!
+        keys :lua =quirks.line_count(), quirks.mode()<CR>
+        expect message 2, \"COMMAND\"
+        keys :lua x =<CR>
+        expect message Lua: syntax error: :lua:1: unexpected symbol near <eof>
+        keys :lua<CR>
+        expect message Argument required
+    ");
+}
+
+#[test]
+fn test_lua_statusline() {
+    let mut editor = Editor::headless(Config::default());
+    editor.set_text("one two\nthree");
+    assert!(editor.lua_statusline().is_none());
+    let state = editor.render_state();
+    assert!(!state.needs_redraw(Some(&state)));
+
+    editor.execute_command("lua quirks.statusline(function() return { 'words', { text = #quirks.get_line(1), bold = true } } end)");
+    let segments = editor.lua_statusline().unwrap().unwrap();
+    assert_eq!(segments.iter().map(|segment| segment.text.as_str()).collect::<Vec<_>>(), ["words", "7"]);
+    // The function is called again for every frame
+    let state = editor.render_state();
+    assert!(state.needs_redraw(Some(&state)));
+
+    editor.execute_command("lua quirks.statusline(function() return quirks.nothing() end)");
+    assert!(editor.lua_statusline().unwrap().unwrap_err().to_string().contains("attempt to call a nil value"));
+}
+
+#[test]
+fn test_lua_popup() {
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command("lua docs = quirks.popup({ 'fn main()' }, { anchor = 'cursor', title = 'Docs' })");
+    editor.execute_command("lua quirks.command('Count', function() docs:update({ 'lines: ' .. quirks.line_count() }) end)");
+    let popups: Vec<_> = editor.popups().collect();
+    assert_eq!(popups.len(), 1);
+    assert_eq!((popups[0].anchor, popups[0].title.as_deref()), (quirks::lua::Anchor::Cursor, Some("Docs")));
+
+    let state = editor.render_state();
+    editor.set_text("one\ntwo");
+    editor.execute_command("Count");
+    assert_eq!(editor.popups().next().unwrap().lines, ["lines: 2"]);
+    assert!(editor.render_state().needs_redraw(Some(&state)));
+
+    // Esc closes them, after that the handle is stale
+    editor.execute_command("lua quirks.popup('second')");
+    editor.feed_keys("<Esc>");
+    assert_eq!(editor.popups().count(), 0);
+    editor.execute_command("Count");
+    assert!(editor.message().unwrap().ends_with("the popup was closed"));
+}
+
+#[test]
+fn test_lua_cmd() {
+    let mut editor = Editor::headless(Config::default());
+    editor.set_text("one two");
+    editor.execute_command(r#"lua vim.cmd("set tw=40") vim.cmd.set("nowrap")"#);
+    assert_eq!(editor.config().text_width, 40);
+    assert!(!editor.config().wrap);
+
+    // Commands Lua runs can run Lua again
+    editor.execute_command("lua quirks.command('Shout', function(opts) print(opts.args:upper()) end)");
+    editor.execute_command("lua quirks.cmd('Shout ' .. quirks.get_line(1))");
+    assert_eq!(editor.message().map(String::as_str), Some("ONE TWO"));
+    editor.execute_command("lua quirks.command('Again', 'Again')");
+    editor.execute_command("Again");
+    assert!(editor.message().unwrap().ends_with("commands nest too deeply"), "{:?}", editor.message());
+    assert!(editor.execute_command("lua vim.cmd('q')"));
+}
+
+#[test]
+fn test_luafile() {
+    let dir = std::env::temp_dir().join(format!("quirks-luafile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("plugin.lua");
+    std::fs::write(&script, "print('loaded ' .. (quirks.buf_name() or 'nothing'))").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("luafile {}", script.display()));
+    assert_eq!(editor.message().map(String::as_str), Some("loaded nothing"));
+    editor.execute_command("luaf %");
+    assert_eq!(editor.message().map(String::as_str), Some("The buffer has no file name"));
+    editor.execute_command(&format!("e {}", script.display()));
+    editor.execute_command("luafile %");
+    assert_eq!(editor.message().unwrap(), &format!("loaded {}", script.display()));
+    editor.execute_command(&format!("luafile {}", dir.join("missing.lua").display()));
+    assert!(editor.message().unwrap().starts_with("Lua: "));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_undo_to_saved_state() {
+    let dir = std::env::temp_dir().join(format!("quirks-saved-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "abc\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", path.display()));
+    editor.feed_keys("x");
+    editor.execute_command("w");
+    editor.feed_keys("x");
+    assert!(editor.buffer().is_modified());
+    editor.feed_keys("u");
+    assert!(!editor.buffer().is_modified());
+    editor.feed_keys("u");
+    assert!(editor.buffer().is_modified());
+    assert_eq!(editor.buffer().saved_steps(), Some(1));
+    assert!(editor.history_summary().starts_with("undo 0, redo 2, saved 1 ahead"));
+
+    editor.execute_command("w diff");
+    assert_eq!(editor.message().unwrap(), &format!("1 hunks differ from {}", path.display()));
+    assert!(editor.diff_view().is_some());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_write_line_range() {
+    let dir = std::env::temp_dir().join(format!("quirks-range-write-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let part = dir.join("part.txt");
+
+    let mut editor = Editor::headless(Config::default());
+    editor.set_text("one\ntwo\nthree\nfour\n");
+    editor.execute_command(&format!("2,3w {}", part.display()));
+    assert_eq!(std::fs::read_to_string(&part).unwrap(), "two\nthree\n");
+    assert_eq!(editor.message().unwrap(), &format!("2 lines written to {}", part.display()));
+    assert_eq!(editor.buffer().file_path(), None);
+
+    editor.execute_command(&format!("1w {}", part.display()));
+    assert_eq!(editor.message().unwrap(), &format!("{} exists (add ! to replace it)", part.display()));
+    editor.execute_command(&format!("3w >> {}", part.display()));
+    assert_eq!(editor.message().unwrap(), &format!("1 line appended to {}", part.display()));
+    editor.feed_keys("ggVj<Esc>");
+    editor.execute_command(&format!("'<,'>w>>{}", part.display()));
+    assert_eq!(std::fs::read_to_string(&part).unwrap(), "two\nthree\nthree\none\ntwo\n");
+    editor.execute_command(&format!("4w! {}", part.display()));
+    assert_eq!(std::fs::read_to_string(&part).unwrap(), "four\n");
+    editor.execute_command(&format!("w >> {}", part.display()));
+    assert_eq!(std::fs::read_to_string(&part).unwrap(), "four\none\ntwo\nthree\nfour\n");
+    assert!(editor.message().unwrap().ends_with(&format!("lines appended to {}", part.display())));
+    editor.execute_command(&format!("write>>{}", part.display()));
+    assert_eq!(std::fs::read_to_string(&part).unwrap().lines().count(), 9);
+    assert_eq!(editor.buffer().file_path(), None);
+    assert!(!dir.join(format!(">> {}", part.display())).exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_readonly_buffer() {
+    let dir = std::env::temp_dir().join(format!("quirks-readonly-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let locked = dir.join("locked.txt");
+    std::fs::write(&locked, "abc\n").unwrap();
+    let mut permissions = std::fs::metadata(&locked).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&locked, permissions).unwrap();
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "abc\n").unwrap();
+
+    let mut editor = Editor::headless(Config::default());
+    editor.execute_command(&format!("e {}", locked.display()));
+    assert!(editor.buffer().is_readonly());
+    editor.execute_command(&format!("e {}", path.display()));
+    assert!(!editor.buffer().is_readonly());
+    script::run(&mut editor, "
+        :setlocal readonly
+        expect message Read-only
+        :setlocal
+        expect message syntax=true (global) readonly
+        keys x
+        :w
+        expect message 'readonly' is set (add ! to override)
+    ").unwrap();
+    editor.execute_command("wa");
+    assert_eq!(editor.message().unwrap(), &format!("Not written: {}: 'readonly' is set", path.display()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "abc\n");
+    editor.execute_command("w!");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "bc\n");
+    editor.execute_command("ro");
+    assert!(!editor.buffer().is_readonly());
+    assert_eq!(editor.message().unwrap(), "Writable");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_colorscheme() {
+    let mut editor = Editor::headless(Config::default());
+    assert_eq!(editor.theme().name, "dark");
+    script::run(&mut editor, "
+        :colorscheme nord
+        :colorscheme
+        expect message nord (available: dark, light, monokai, solarized-dark, nord)
+        :colo vim
+        expect message Unknown color scheme: vim (use dark, light, monokai, solarized-dark, nord)
+    ").unwrap();
+    assert_eq!(editor.theme().name, "nord");
+    editor.execute_command("set colorscheme=solarized");
+    assert_eq!(editor.theme().name, "solarized-dark");
+}
+
+#[test]
+fn test_render_state_redraws() {
+    let mut editor = Editor::headless(Config::default());
+    editor.set_text(&(1..=40).map(|n| format!("line {}\n", n)).collect::<String>());
+    editor.set_viewport_height(10);
+    let mut drawn = editor.render_state();
+    assert!(drawn.needs_redraw(None));
+    assert!(!editor.render_state().needs_redraw(Some(&drawn)));
+    // A key that changes nothing visible draws nothing
+    editor.feed_keys("k");
+    assert!(!editor.render_state().needs_redraw(Some(&drawn)));
+
+    // Each of these changes something on screen: the cursor, scrolling
+    // with it, messages, pending keys, recording, the selection, the text
+    // and the command line
+    for keys in ["5j", "20j", "<C-g>", "d", "<Esc>", "2", "\"a", "<Esc>", "g", "<Esc>", "qa", "q", "v", "<Esc>", "x", ":"] {
+        editor.feed_keys(keys);
+        let state = editor.render_state();
+        assert!(state.needs_redraw(Some(&drawn)), "no redraw after {}", keys);
+        assert!(!editor.render_state().needs_redraw(Some(&state)), "redraw again after {}", keys);
+        drawn = state;
+    }
+    assert!(editor.scroll_offset() > 0);
+}